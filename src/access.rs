@@ -0,0 +1,340 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Chainable accessors that report what went wrong instead of panicking.
+//!
+//! Indexing a `Val` panics on a missing field and `get`/`as_*` need a match
+//! (or a chain of `and_then`s) per step. `Val::path` instead returns an
+//! `Accessor`, which follows a dot-separated path one step at a time and
+//! converts the value it finds to a Rust type. Each step may name a field
+//! (or list index), as in `Val::lookup`, or a protocol layer further down
+//! the stack, as in `Val::layer`. Failures are `PathError`s that name the
+//! whole path that was attempted and where and why it failed.
+//!
+//! For the common case of a single path and conversion, `Val` has
+//! shorthands such as `get_unsigned` and `get_address`.
+//!
+//! ```
+//! # fn main() { example().unwrap() }
+//! # fn example() -> Result<(), rshark::access::PathError> {
+//! let packet = [
+//!     0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+//!     0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00,
+//!     192, 168, 0, 1, 192, 168, 0, 2,
+//!     0x01, 0xbb, 0xfc, 0xeb, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x0b,
+//!     0x50, 0x12, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00];
+//! let val = rshark::ethernet::dissect(&packet).unwrap();
+//!
+//! assert!(val.path("IPv4.TCP.Flags").bit("SYN")?);
+//! assert_eq!(val.path("IPv4").path("TCP.Window").unsigned()?, 65535);
+//!
+//! let err = val.path("IPv4.UDP.Length").unsigned().unwrap_err();
+//! assert_eq!(err.to_string(), "IPv4.UDP.Length: no field or layer 'UDP' in IPv4");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+
+use DissectError;
+use Val;
+use mac::MacAddr;
+use time::Time;
+
+/// Why a path couldn't be followed or its value converted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathErrorKind {
+    /// Nothing named `step` in the value at `parent`.
+    NotFound { step: String, parent: String },
+
+    /// The path went through a payload that failed to dissect.
+    Dissect(DissectError),
+
+    /// The value isn't of the type that was asked for.
+    WrongType { expected: &'static str, found: &'static str },
+
+    /// The flags have no bit with the given name.
+    NoSuchBit(String),
+}
+
+/// A failure to follow a path, naming the full path that was attempted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathError {
+    pub path: String,
+    pub kind: PathErrorKind,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}: ", self.path]?;
+        match self.kind {
+            PathErrorKind::NotFound { ref step, ref parent } =>
+                write![f, "no field or layer '{}' in {}", step, parent],
+            PathErrorKind::Dissect(ref e) => write![f, "payload failed to dissect: {}", e],
+            PathErrorKind::WrongType { expected, found } =>
+                write![f, "expected {} but found {}", expected, found],
+            PathErrorKind::NoSuchBit(ref name) => write![f, "no flag bit named '{}'", name],
+        }
+    }
+}
+
+impl Error for PathError {
+    fn description(&self) -> &str {
+        "error following a path through a dissection"
+    }
+}
+
+/// A step (or several) along a path through a dissection; see `Val::path`.
+#[derive(Clone, Debug)]
+pub struct Accessor<'val, 'data: 'val> {
+    path: String,
+    result: Result<&'val Val<'data>, PathErrorKind>,
+}
+
+impl<'data> Val<'data> {
+    /// Start following a path of fields and layers from this value.
+    pub fn path<'val>(&'val self, path: &str) -> Accessor<'val, 'data> {
+        Accessor { path: String::new(), result: Ok(self) }.path(path)
+    }
+
+    /// The Signed at a path (see `path`).
+    pub fn get_signed(&self, path: &str) -> Result<i64, PathError> {
+        self.path(path).signed()
+    }
+
+    /// The Unsigned at a path (see `path`).
+    pub fn get_unsigned(&self, path: &str) -> Result<u64, PathError> {
+        self.path(path).unsigned()
+    }
+
+    /// The String or Symbol at a path (see `path`).
+    pub fn get_string<'val>(&'val self, path: &str) -> Result<&'val str, PathError> {
+        self.path(path).string()
+    }
+
+    /// The Bytes at a path (see `path`).
+    pub fn get_bytes(&self, path: &str) -> Result<&'data [u8], PathError> {
+        self.path(path).bytes()
+    }
+
+    /// The encoded form of the Address at a path (see `path`).
+    pub fn get_address<'val>(&'val self, path: &str) -> Result<&'val str, PathError> {
+        self.path(path).address()
+    }
+
+    /// The IP address at a path (see `path`).
+    pub fn get_ip(&self, path: &str) -> Result<IpAddr, PathError> {
+        self.path(path).ip()
+    }
+
+    /// The MAC address at a path (see `path`).
+    pub fn get_mac(&self, path: &str) -> Result<MacAddr, PathError> {
+        self.path(path).mac()
+    }
+
+    /// The number of the Enum at a path (see `path`).
+    pub fn get_enum(&self, path: &str) -> Result<u64, PathError> {
+        self.path(path).enum_value()
+    }
+
+    /// The elements of the List at a path (see `path`).
+    pub fn get_list<'val>(&'val self, path: &str) -> Result<&'val [Val<'data>], PathError> {
+        self.path(path).list()
+    }
+
+    /// The Timestamp at a path (see `path`).
+    pub fn get_timestamp(&self, path: &str) -> Result<Time, PathError> {
+        self.path(path).timestamp()
+    }
+
+    /// The Duration at a path (see `path`).
+    pub fn get_duration(&self, path: &str) -> Result<Time, PathError> {
+        self.path(path).duration()
+    }
+
+    /// The name of the value's variant, e.g., "Unsigned", for messages.
+    pub fn variant_name(&self) -> &'static str {
+        match *self {
+            Val::Signed(_) => "Signed",
+            Val::Unsigned(_) => "Unsigned",
+            Val::String(_) => "String",
+            Val::Symbol(_) => "Symbol",
+            Val::Address { .. } => "Address",
+            Val::BitFlags8(..) => "BitFlags8",
+            Val::BitFlags(..) => "BitFlags",
+            Val::Object(..) => "Object",
+            Val::List(_) => "List",
+            Val::Payload(..) => "Payload",
+            Val::Bytes(_) => "Bytes",
+            Val::Undissected(..) => "Undissected",
+            Val::Enum { .. } => "Enum",
+            Val::Timestamp(_) => "Timestamp",
+            Val::Duration(_) => "Duration",
+        }
+    }
+}
+
+impl<'val, 'data> Accessor<'val, 'data> {
+    /// Follow more of the path.
+    pub fn path(mut self, path: &str) -> Accessor<'val, 'data> {
+        for step in path.split('.') {
+            let parent = if self.path.is_empty() { "the dissection".to_string() }
+                         else { self.path.clone() };
+            if !self.path.is_empty() {
+                self.path.push('.');
+            }
+            self.path.push_str(step);
+
+            self.result = self.result.and_then(|val| match val.get(step) {
+                Ok(found) => Ok(found),
+                Err(_) => match *val {
+                    Val::Payload(_, Err(ref e)) => Err(PathErrorKind::Dissect(e.clone())),
+                    _ => val.layer(step).ok_or(PathErrorKind::NotFound {
+                        step: step.to_string(),
+                        parent: parent,
+                    }),
+                },
+            });
+        }
+        self
+    }
+
+    /// The value at the end of the path.
+    pub fn val(self) -> Result<&'val Val<'data>, PathError> {
+        let path = self.path;
+        self.result.map_err(|kind| PathError { path: path, kind: kind })
+    }
+
+    pub fn signed(self) -> Result<i64, PathError> {
+        self.convert("Signed", Val::as_signed)
+    }
+
+    pub fn unsigned(self) -> Result<u64, PathError> {
+        self.convert("Unsigned", Val::as_unsigned)
+    }
+
+    /// The string of a String or Symbol.
+    pub fn string(self) -> Result<&'val str, PathError> {
+        self.convert("String or Symbol", |v| v.as_string().or(v.as_symbol()))
+    }
+
+    pub fn bytes(self) -> Result<&'data [u8], PathError> {
+        self.convert("Bytes", Val::as_bytes)
+    }
+
+    /// The encoded (printable) form of an Address.
+    pub fn address(self) -> Result<&'val str, PathError> {
+        self.convert("Address", Val::as_address_encoded)
+    }
+
+    pub fn ip(self) -> Result<IpAddr, PathError> {
+        self.convert("IP address", Val::as_ip)
+    }
+
+    pub fn mac(self) -> Result<MacAddr, PathError> {
+        self.convert("MAC address", Val::as_mac)
+    }
+
+    /// The number of an Enum.
+    pub fn enum_value(self) -> Result<u64, PathError> {
+        self.convert("Enum", |v| v.as_enum().map(|(value, _)| value))
+    }
+
+    pub fn list(self) -> Result<&'val [Val<'data>], PathError> {
+        self.convert("List", Val::as_list)
+    }
+
+    pub fn timestamp(self) -> Result<Time, PathError> {
+        self.convert("Timestamp", Val::as_timestamp)
+    }
+
+    pub fn duration(self) -> Result<Time, PathError> {
+        self.convert("Duration", Val::as_duration)
+    }
+
+    /// Whether the named bit of a BitFlags8 or BitFlags is set.
+    pub fn bit(self, name: &str) -> Result<bool, PathError> {
+        let path = self.path.clone();
+        let val = self.convert("BitFlags", |v| if v.is_bitflags() { Some(v) } else { None })?;
+        val.as_bitflags_bit_name(name)
+            .ok_or(PathError { path: path, kind: PathErrorKind::NoSuchBit(name.to_string()) })
+    }
+
+    fn convert<T, F>(self, expected: &'static str, f: F) -> Result<T, PathError>
+        where F: FnOnce(&'val Val<'data>) -> Option<T> {
+
+        let path = self.path;
+        let val = self.result.map_err(|kind| PathError { path: path.clone(), kind: kind })?;
+        f(val).ok_or(PathError {
+            path: path,
+            kind: PathErrorKind::WrongType { expected: expected, found: val.variant_name() },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ErrorCode;
+
+    #[test]
+    fn follow_paths() {
+        let error = DissectError::malformed(ErrorCode::BadMagic, "no");
+        let val = Val::Object("Outer", vec![
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(4)])),
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", vec![
+                ("Name", Val::Symbol("x")),
+                ("Flags", Val::BitFlags8(1, [Some("A"), None, None, None, None, None, None, None])),
+                ("Payload", Val::Payload(&[], Err(error))),
+            ]))))),
+        ]);
+
+        assert_eq!(val.path("Hops.1").unsigned(), Ok(4));
+        assert_eq!(val.path("Inner.Name").string(), Ok("x"));
+        assert_eq!(val.path("Payload").path("Flags").bit("A"), Ok(true));
+        assert_eq!(val.path("Outer.Hops").val(), Ok(&val["Hops"]));
+
+        let errors = vec![
+            val.path("Hops.2").unsigned().unwrap_err(),
+            val.path("Inner.Name").unsigned().unwrap_err(),
+            val.path("Inner.Flags").bit("B").unwrap_err(),
+            val.path("Inner.Payload.Source").val().unwrap_err(),
+            val.path("Nothing.Name").val().unwrap_err(),
+        ];
+        assert_eq!(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(), vec![
+            "Hops.2: no field or layer '2' in Hops",
+            "Inner.Name: expected Unsigned but found Symbol",
+            "Inner.Flags: no flag bit named 'B'",
+            "Inner.Payload.Source: payload failed to dissect: invalid data (bad_magic): no",
+            "Nothing.Name: no field or layer 'Nothing' in the dissection",
+        ]);
+        assert_eq!(errors[4].path, "Nothing.Name");
+    }
+
+    #[test]
+    fn typed_getters() {
+        let data = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+            10, 0, 0, 1, 10, 0, 0, 2,
+            0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+        let val = ::ethernet::dissect(&data).unwrap();
+
+        assert_eq!(val.get_mac("Source").unwrap().to_string(), "66:77:88:99:aa:bb");
+        assert_eq!(val.get_address("Payload.Source"), Ok("10.0.0.1"));
+        assert_eq!(val.get_enum("IPv4.Protocol"), Ok(17));
+        assert_eq!(val.get_unsigned("UDP.Length"), Ok(8));
+        assert_eq!(val.get_bytes("UDP.Checksum"), Ok(&[0, 0][..]));
+
+        assert_eq!(val.get_unsigned("Payload.Source").unwrap_err().to_string(),
+                   "Payload.Source: expected Unsigned but found Address");
+    }
+}
@@ -0,0 +1,478 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the Advanced Message Queuing Protocol (AMQP), versions 0-9-1
+//! and 1.0, on TCP port 5672.
+//!
+//! The two versions are unrelated protocols that share a port and a protocol
+//! header. Each segment is dissected as whichever version's framing it
+//! matches: 0-9-1 frames end with a 0xCE octet, whereas 1.0 frames start with
+//! their size and a data offset. A segment holding several frames is
+//! represented as a chain of "Next Frame" payloads.
+//!
+//! See the [AMQP 0-9-1](https://www.rabbitmq.com/resources/specs/amqp0-9-1.pdf)
+//! and [AMQP 1.0](http://docs.oasis-open.org/amqp/core/v1.0/amqp-core-complete-v1.0.pdf)
+//! specifications.
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use Val;
+use NamedValues;
+
+/// The 0-9-1 end-of-frame marker.
+const FRAME_END: u8 = 0xce;
+
+/// Dissect an AMQP protocol header or frame(s) of either version.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() >= 8 && &data[0..4] == b"AMQP" {
+        return protocol_header(data);
+    }
+
+    if is_frame_0_9_1(data) {
+        dissect_0_9_1(data)
+    } else {
+        dissect_1_0(data)
+    }
+}
+
+/// Dissect AMQP 0-9-1 frames.
+pub fn dissect_0_9_1(data : &[u8]) -> DissectResult {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "An AMQP 0-9-1 frame must be at least 8 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let frame_type = data[0];
+    values.push(("Type", Val::Unsigned(frame_type as u64)));
+    values.push(("Type Name", Val::Symbol(match frame_type {
+        1 => "Method",
+        2 => "Content Header",
+        3 => "Content Body",
+        8 => "Heartbeat",
+        _ => "Unknown",
+    })));
+
+    values.push(("Channel", Val::Unsigned(be(data, 1, 2)?)));
+
+    let size = be(data, 3, 4)? as usize;
+    values.push(("Length", Val::Unsigned(size as u64)));
+
+    let payload = slice(data, 7, size)?;
+    if data.get(7 + size) != Some(&FRAME_END) {
+        return Err(DissectError::malformed(ErrorCode::BadMagic, "AMQP frame does not end with 0xCE"));
+    }
+
+    match frame_type {
+        1 => method(payload, &mut values)?,
+        2 => {
+            values.push(("Class", Val::Unsigned(be(payload, 0, 2)?)));
+            values.push(("Body Size", Val::Unsigned(be(payload, 4, 8)?)));
+            values.push(("Property Flags", Val::Unsigned(be(payload, 12, 2)?)));
+        },
+        3 => values.push(("Body", Val::Bytes(payload))),
+        _ => {},
+    }
+
+    let rest = &data[8 + size..];
+    if !rest.is_empty() {
+        values.push(("Next Frame", Val::Payload(rest, dissect(rest))));
+    }
+
+    Ok(Box::new(Val::Object("AMQP", values)))
+}
+
+/// Dissect AMQP 1.0 frames.
+pub fn dissect_1_0(data : &[u8]) -> DissectResult {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "An AMQP 1.0 frame must be at least 8 B".to_string() })
+    }
+
+    let size = be(data, 0, 4)? as usize;
+    let offset = data[4] as usize * 4;
+    if size < 8 || offset < 8 || offset > size {
+        return Err(DissectError::malformed(ErrorCode::InvalidLength,
+            format!["AMQP 1.0 frame size {} or data offset {} is invalid", size, offset]));
+    }
+
+    let frame = slice(data, 0, size)?;
+    let mut values = NamedValues::new();
+    values.push(("Length", Val::Unsigned(size as u64)));
+    values.push(("Data Offset", Val::Unsigned(data[4] as u64)));
+
+    let frame_type = data[5];
+    values.push(("Type", Val::Unsigned(frame_type as u64)));
+    values.push(("Type Name", Val::Symbol(match frame_type {
+        0 => "AMQP",
+        1 => "SASL",
+        _ => "Unknown",
+    })));
+
+    if frame_type == 0 {
+        values.push(("Channel", Val::Unsigned(be(data, 6, 2)?)));
+    }
+
+    // A frame with no body is a heartbeat.
+    let body = &frame[offset..];
+    if !body.is_empty() {
+        performative(body, &mut values)?;
+    }
+
+    let rest = &data[size..];
+    if !rest.is_empty() {
+        values.push(("Next Frame", Val::Payload(rest, dissect(rest))));
+    }
+
+    Ok(Box::new(Val::Object("AMQP", values)))
+}
+
+/// The protocol header that opens a connection (or a SASL/TLS layer).
+fn protocol_header(data: &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+
+    // 0-9-1 sends (0, 0, 9, 1); 1.0 sends (protocol ID, 1, 0, 0).
+    let (version, protocol) = match (data[4], data[5], data[6], data[7]) {
+        (0, 0, 9, 1) => ("0-9-1", None),
+        (id, 1, 0, 0) => ("1.0", Some(id)),
+        (_, major, minor, revision) =>
+            return Err(DissectError::malformed(ErrorCode::UnsupportedVersion,
+                format!["unsupported AMQP version {}.{}.{}", major, minor, revision])),
+    };
+
+    values.push(("Protocol Header", Val::Symbol(version)));
+    if let Some(id) = protocol {
+        values.push(("Protocol ID", Val::Symbol(match id {
+            0 => "AMQP",
+            2 => "TLS",
+            3 => "SASL",
+            _ => "Unknown",
+        })));
+    }
+
+    if data.len() > 8 {
+        values.push(("Next Frame", Val::Payload(&data[8..], dissect(&data[8..]))));
+    }
+
+    Ok(Box::new(Val::Object("AMQP", values)))
+}
+
+fn is_frame_0_9_1(data: &[u8]) -> bool {
+    match (data.first(), be(data, 3, 4)) {
+        (Some(&t), Ok(size)) if t == 1 || t == 2 || t == 3 || t == 8 =>
+            data.get(7 + size as usize) == Some(&FRAME_END),
+        _ => false,
+    }
+}
+
+/// A 0-9-1 method frame: class and method IDs, then arguments.
+fn method<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    let class = be(data, 0, 2)? as u16;
+    let method = be(data, 2, 2)? as u16;
+
+    values.push(("Class", Val::Unsigned(class as u64)));
+    values.push(("Method", Val::Unsigned(method as u64)));
+    if let Some((class_name, method_name)) = method_name(class, method) {
+        values.push(("Class Name", Val::Symbol(class_name)));
+        values.push(("Method Name", Val::Symbol(method_name)));
+    }
+
+    // Decode the arguments most useful for auditing; the rest are left raw.
+    let arguments = &data[4..];
+    match (class, method) {
+        (10, 30) | (10, 31) => {
+            values.push(("Channel Max", Val::Unsigned(be(arguments, 0, 2)?)));
+            values.push(("Frame Max", Val::Unsigned(be(arguments, 2, 4)?)));
+            values.push(("Heartbeat", Val::Unsigned(be(arguments, 6, 2)?)));
+        },
+        (10, 40) => {
+            values.push(("Virtual Host", Val::String(short_string(arguments, 0)?.0)));
+        },
+        (40, 10) => {
+            let (exchange, next) = short_string(arguments, 2)?;
+            values.push(("Exchange", Val::String(exchange)));
+            values.push(("Exchange Type", Val::String(short_string(arguments, next)?.0)));
+        },
+        (50, 10) | (50, 20) | (60, 20) => {
+            values.push(("Queue", Val::String(short_string(arguments, 2)?.0)));
+        },
+        (60, 40) => {
+            let (exchange, next) = short_string(arguments, 2)?;
+            values.push(("Exchange", Val::String(exchange)));
+            values.push(("Routing Key", Val::String(short_string(arguments, next)?.0)));
+        },
+        (60, 60) => {
+            let (consumer, next) = short_string(arguments, 0)?;
+            values.push(("Consumer Tag", Val::String(consumer)));
+            values.push(("Delivery Tag", Val::Unsigned(be(arguments, next, 8)?)));
+            values.push(("Redelivered", Val::Unsigned(be(arguments, next + 8, 1)? & 1)));
+
+            let (exchange, next) = short_string(arguments, next + 9)?;
+            values.push(("Exchange", Val::String(exchange)));
+            values.push(("Routing Key", Val::String(short_string(arguments, next)?.0)));
+        },
+        (60, 80) | (60, 90) | (60, 120) => {
+            values.push(("Delivery Tag", Val::Unsigned(be(arguments, 0, 8)?)));
+        },
+        _ => {
+            if !arguments.is_empty() {
+                values.push(("Arguments", Val::Bytes(arguments)));
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Names of an AMQP 0-9-1 class and method.
+pub fn method_name(class: u16, method: u16) -> Option<(&'static str, &'static str)> {
+    let class_name = match class {
+        10 => "Connection",
+        20 => "Channel",
+        40 => "Exchange",
+        50 => "Queue",
+        60 => "Basic",
+        85 => "Confirm",
+        90 => "Tx",
+        _ => return None,
+    };
+
+    let method_name = match (class, method) {
+        (10, 10) => "Start",
+        (10, 11) => "Start-Ok",
+        (10, 20) => "Secure",
+        (10, 21) => "Secure-Ok",
+        (10, 30) => "Tune",
+        (10, 31) => "Tune-Ok",
+        (10, 40) | (20, 10) => "Open",
+        (10, 41) | (20, 11) => "Open-Ok",
+        (10, 50) | (20, 40) => "Close",
+        (10, 51) | (20, 41) => "Close-Ok",
+        (20, 20) => "Flow",
+        (20, 21) => "Flow-Ok",
+        (40, 10) | (50, 10) => "Declare",
+        (40, 11) | (50, 11) => "Declare-Ok",
+        (40, 20) | (50, 40) => "Delete",
+        (40, 21) | (50, 41) => "Delete-Ok",
+        (50, 20) => "Bind",
+        (50, 21) => "Bind-Ok",
+        (50, 30) => "Purge",
+        (50, 31) => "Purge-Ok",
+        (50, 50) => "Unbind",
+        (50, 51) => "Unbind-Ok",
+        (60, 10) => "Qos",
+        (60, 11) => "Qos-Ok",
+        (60, 20) => "Consume",
+        (60, 21) => "Consume-Ok",
+        (60, 30) => "Cancel",
+        (60, 31) => "Cancel-Ok",
+        (60, 40) => "Publish",
+        (60, 50) => "Return",
+        (60, 60) => "Deliver",
+        (60, 70) => "Get",
+        (60, 71) => "Get-Ok",
+        (60, 72) => "Get-Empty",
+        (60, 80) => "Ack",
+        (60, 90) => "Reject",
+        (60, 100) => "Recover-Async",
+        (60, 110) => "Recover",
+        (60, 111) => "Recover-Ok",
+        (60, 120) => "Nack",
+        (85, 10) | (90, 10) => "Select",
+        (85, 11) | (90, 11) => "Select-Ok",
+        (90, 20) => "Commit",
+        (90, 21) => "Commit-Ok",
+        (90, 30) => "Rollback",
+        (90, 31) => "Rollback-Ok",
+        _ => "Unknown",
+    };
+
+    Some((class_name, method_name))
+}
+
+/// An AMQP 1.0 performative: a described list, whose descriptor identifies
+/// the performative and whose elements are its fields.
+fn performative<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    if data.len() < 2 || data[0] != 0x00 {
+        return Err(DissectError::malformed(ErrorCode::InvalidData,
+            "AMQP 1.0 frame body does not start with a described type"));
+    }
+
+    let (descriptor, used) = match value(&data[1..])? {
+        (Some(Val::Unsigned(code)), used) => (code, used),
+        _ => return Err(DissectError::InvalidData("AMQP 1.0 descriptor is not a ulong".to_string())),
+    };
+
+    let (name, fields) = performative_fields(descriptor);
+    values.push(("Descriptor", Val::Unsigned(descriptor)));
+    values.push(("Performative", Val::Symbol(name)));
+
+    // list0, list8 or list32: (constructor, [size, count])
+    let list = &data[1 + used..];
+    let (count, mut rest) = match list.first() {
+        Some(&0x45) => (0, &list[1..]),
+        Some(&0xc0) => (be(list, 2, 1)? as usize, &list[3..]),
+        Some(&0xd0) => (be(list, 5, 4)? as usize, &list[9..]),
+        _ => return Err(DissectError::InvalidData("AMQP 1.0 performative is not a list".to_string())),
+    };
+
+    for i in 0..count {
+        let (val, used) = value(rest)?;
+        rest = &rest[used..];
+
+        // Omitted (null) fields take their default values.
+        match (fields.get(i), val) {
+            (_, None) => {},
+            (Some(&field), Some(val)) => values.push((field, val)),
+            (None, Some(val)) => values.push(("Field", val)),
+        }
+    }
+
+    // A transfer's payload (the message) follows its performative.
+    if descriptor == 0x14 && !rest.is_empty() {
+        values.push(("Message", Val::Bytes(rest)));
+    }
+
+    Ok(())
+}
+
+fn performative_fields(descriptor: u64) -> (&'static str, &'static [&'static str]) {
+    match descriptor {
+        0x10 => ("open", &["Container ID", "Hostname", "Max Frame Size", "Channel Max", "Idle Timeout"]),
+        0x11 => ("begin", &["Remote Channel", "Next Outgoing ID", "Incoming Window", "Outgoing Window",
+                            "Handle Max"]),
+        0x12 => ("attach", &["Name", "Handle", "Role", "Sender Settle Mode", "Receiver Settle Mode",
+                             "Source", "Target"]),
+        0x13 => ("flow", &["Next Incoming ID", "Incoming Window", "Next Outgoing ID", "Outgoing Window",
+                           "Handle", "Delivery Count", "Link Credit"]),
+        0x14 => ("transfer", &["Handle", "Delivery ID", "Delivery Tag", "Message Format", "Settled",
+                               "More"]),
+        0x15 => ("disposition", &["Role", "First", "Last", "Settled", "State"]),
+        0x16 => ("detach", &["Handle", "Closed", "Error"]),
+        0x17 => ("end", &["Error"]),
+        0x18 => ("close", &["Error"]),
+        0x40 => ("sasl-mechanisms", &["Mechanisms"]),
+        0x41 => ("sasl-init", &["Mechanism", "Initial Response", "Hostname"]),
+        0x42 => ("sasl-challenge", &["Challenge"]),
+        0x43 => ("sasl-response", &["Response"]),
+        0x44 => ("sasl-outcome", &["Code", "Additional Data"]),
+        _ => ("unknown", &[]),
+    }
+}
+
+/// Decode an AMQP 1.0 encoded value, returning it (or `None` for null) and
+/// the number of bytes it used.
+///
+/// Compound values (lists, maps, arrays and described types) are returned
+/// as raw bytes.
+fn value<'data>(data: &'data [u8]) -> Result<(Option<Val<'data>>, usize), DissectError> {
+    let constructor = be(data, 0, 1)? as u8;
+
+    // The high nibble of a constructor gives the encoding's width.
+    let (header, length) = match constructor >> 4 {
+        0x0 => {
+            // Described type: a descriptor, then the value.
+            let (_, descriptor) = value(&data[1..])?;
+            let (_, described) = value(&data[1 + descriptor..])?;
+            return Ok((Some(Val::Bytes(&data[..1 + descriptor + described])), 1 + descriptor + described));
+        },
+        0x4 => (1, 0),
+        0x5 => (1, 1),
+        0x6 => (1, 2),
+        0x7 => (1, 4),
+        0x8 => (1, 8),
+        0x9 => (1, 16),
+        0xa | 0xc | 0xe => (2, be(data, 1, 1)? as usize),
+        0xb | 0xd | 0xf => (5, be(data, 1, 4)? as usize),
+        _ => return Err(DissectError::InvalidData(format!["invalid AMQP 1.0 constructor {:#04x}", constructor])),
+    };
+
+    let bytes = slice(data, header, length)?;
+    let used = header + length;
+
+    let val = match constructor {
+        0x40 => return Ok((None, used)),
+        0x41 => Val::Unsigned(1),
+        0x42 | 0x43 | 0x44 => Val::Unsigned(0),
+        0x56 => Val::Unsigned(bytes[0] as u64),
+        0x50 | 0x52 | 0x53 | 0x60 | 0x70 | 0x80 => Val::Unsigned(be(bytes, 0, length)?),
+        0x51 => Val::Signed(bytes[0] as i8 as i64),
+        0x54 => Val::Signed(bytes[0] as i8 as i64),
+        0x61 => Val::Signed(be(bytes, 0, 2)? as i16 as i64),
+        0x71 => Val::Signed(be(bytes, 0, 4)? as i32 as i64),
+        0x81 => Val::Signed(be(bytes, 0, 8)? as i64),
+        0xa1 | 0xa3 | 0xb1 | 0xb3 => Val::String(String::from_utf8_lossy(bytes).into_owned()),
+        _ => Val::Bytes(&data[..used]),
+    };
+
+    Ok((Some(val), used))
+}
+
+/// A 0-9-1 short string (one length octet, then the string) and the offset
+/// of what follows it.
+fn short_string(data: &[u8], offset: usize) -> Result<(String, usize), DissectError> {
+    let length = be(data, offset, 1)? as usize;
+    let bytes = slice(data, offset + 1, length)?;
+    Ok((String::from_utf8_lossy(bytes).into_owned(), offset + 1 + length))
+}
+
+fn be(data: &[u8], offset: usize, length: usize) -> Result<u64, DissectError> {
+    Ok(slice(data, offset, length)?.iter().fold(0, |value, &b| value << 8 | b as u64))
+}
+
+fn slice(data: &[u8], offset: usize, length: usize) -> Result<&[u8], DissectError> {
+    data.get(offset..offset + length).ok_or(DissectError::Underflow {
+        expected: Some(offset + length), have: data.len(),
+        message: "AMQP field extends past the end of the data".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_amqp_0_9_1() {
+        // Basic.Publish to exchange "ex" with routing key "rk", then a heartbeat
+        let data = [0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x0d,
+                    0x00, 0x3c, 0x00, 0x28, 0x00, 0x00, 0x02, b'e', b'x', 0x02, b'r', b'k', 0x00,
+                    0xce,
+                    0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xce];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Channel"].as_unsigned().unwrap(), 1);
+        assert_eq!(val["Class Name"].as_symbol().unwrap(), "Basic");
+        assert_eq!(val["Method Name"].as_symbol().unwrap(), "Publish");
+        assert_eq!(val["Exchange"].as_string().unwrap(), "ex");
+        assert_eq!(val["Routing Key"].as_string().unwrap(), "rk");
+        assert_eq!(val["Next Frame"]["Type Name"].as_symbol().unwrap(), "Heartbeat");
+
+        let header = *dissect(b"AMQP\x00\x00\x09\x01").unwrap();
+        assert_eq!(header["Protocol Header"].as_symbol().unwrap(), "0-9-1");
+    }
+
+    #[test]
+    fn dissect_amqp_1_0() {
+        // open(container-id: "c1", hostname: null, max-frame-size: 512)
+        let data = [0x00, 0x00, 0x00, 0x18, 0x02, 0x00, 0x00, 0x00,
+                    0x00, 0x53, 0x10, 0xc0, 0x0b, 0x03, 0xa1, 0x02, b'c', b'1', 0x40,
+                    0x70, 0x00, 0x00, 0x02, 0x00];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Type Name"].as_symbol().unwrap(), "AMQP");
+        assert_eq!(val["Performative"].as_symbol().unwrap(), "open");
+        assert_eq!(val["Container ID"].as_string().unwrap(), "c1");
+        assert_eq!(val["Max Frame Size"].as_unsigned().unwrap(), 512);
+        assert!(val.get("Hostname").is_err());
+
+        assert!(dissect_1_0(&data[..20]).is_err());
+    }
+}
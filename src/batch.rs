@@ -0,0 +1,203 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of many packets at once.
+//!
+//! Each packet is dissected in isolation: an error (or even a panic) while
+//! dissecting one packet is recorded as that packet's result and never stops
+//! the rest of the batch from being processed.
+//!
+//! ```
+//! let packets: Vec<&[u8]> = vec![&[0; 14], &[0; 3]];
+//! let batch = rshark::batch::dissect_all(&packets, rshark::ethernet::dissect);
+//!
+//! assert!(batch.results[0].is_ok());
+//! assert!(batch.results[1].is_err());
+//! assert_eq!(batch.stats.failed, 1);
+//! ```
+
+use std::panic;
+use std::thread;
+
+use DissectError;
+use DissectResult;
+use Dissector;
+use ErrorCode;
+use Val;
+
+/// A successfully dissected packet.
+pub type Dissection<'data> = Box<Val<'data>>;
+
+/// Aggregate statistics about the errors in a batch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchStats {
+    pub packets: u64,
+
+    /// Packets whose outermost layer was dissected.
+    pub dissected: u64,
+
+    /// Packets that couldn't be dissected at all.
+    pub failed: u64,
+    pub underflows: u64,
+    pub invalid: u64,
+
+    /// Packets whose dissector panicked (these are also counted as invalid).
+    pub panics: u64,
+
+    /// Errors within the payloads of dissected packets.
+    pub payload_errors: u64,
+}
+
+impl BatchStats {
+    /// Account for one packet's result.
+    pub fn record(&mut self, result: &DissectResult) {
+        self.packets += 1;
+
+        match result {
+            &Ok(ref val) => {
+                self.dissected += 1;
+                self.payload_errors += payload_errors(val);
+            },
+            &Err(ref e) => {
+                self.failed += 1;
+                match e {
+                    &DissectError::Underflow { .. } => self.underflows += 1,
+                    &DissectError::InvalidData(_) | &DissectError::Malformed { .. } =>
+                        self.invalid += 1,
+                }
+            },
+        }
+    }
+
+    /// Add the statistics of another batch to these.
+    pub fn merge(&mut self, other: &BatchStats) {
+        self.packets += other.packets;
+        self.dissected += other.dissected;
+        self.failed += other.failed;
+        self.underflows += other.underflows;
+        self.invalid += other.invalid;
+        self.panics += other.panics;
+        self.payload_errors += other.payload_errors;
+    }
+}
+
+/// The results of dissecting a batch of packets, in packet order.
+pub struct Batch<'data> {
+    pub results: Vec<Result<Dissection<'data>, DissectError>>,
+    pub stats: BatchStats,
+}
+
+/// Dissect each packet in turn.
+pub fn dissect_all<'data>(packets: &[&'data [u8]], dissector: Dissector<'data>) -> Batch<'data> {
+    let mut stats = BatchStats::default();
+    let results = packets.iter()
+        .map(|&packet| {
+            let (result, panicked) = guarded(dissector, packet);
+            stats.record(&result);
+            stats.panics += panicked as u64;
+            result
+        })
+        .collect();
+
+    Batch { results: results, stats: stats }
+}
+
+/// Dissect packets on up to `threads` threads. Results are returned in the
+/// same order as the packets.
+pub fn dissect_all_parallel<'data>(packets: &[&'data [u8]], dissector: Dissector<'data>,
+                                   threads: usize) -> Batch<'data> {
+
+    if threads <= 1 || packets.len() <= 1 {
+        return dissect_all(packets, dissector);
+    }
+
+    let chunk = (packets.len() + threads - 1) / threads;
+
+    thread::scope(|scope| {
+        let workers = packets.chunks(chunk)
+            .map(|packets| scope.spawn(move || dissect_all(packets, dissector)))
+            .collect::<Vec<_>>();
+
+        let mut batch = Batch { results: Vec::with_capacity(packets.len()), stats: BatchStats::default() };
+        for worker in workers {
+            // Dissector panics are caught within dissect_all.
+            let part = worker.join().unwrap();
+            batch.results.extend(part.results);
+            batch.stats.merge(&part.stats);
+        }
+
+        batch
+    })
+}
+
+/// Run a dissector, converting a panic into an error.
+fn guarded<'data>(dissector: Dissector<'data>, packet: &'data [u8]) -> (DissectResult<'data>, bool) {
+    match panic::catch_unwind(move || dissector(packet)) {
+        Ok(result) => (result, false),
+        Err(cause) => {
+            let message = cause.downcast_ref::<&str>().map(|s| s.to_string())
+                .or(cause.downcast_ref::<String>().cloned())
+                .unwrap_or("unknown cause".to_string());
+
+            (Err(DissectError::malformed(ErrorCode::DissectorPanic,
+                                         format!["dissector panicked: {}", message])), true)
+        },
+    }
+}
+
+fn payload_errors(val: &Val) -> u64 {
+    match val {
+        &Val::Object(_, ref values) => values.iter().map(|&(_, ref v)| payload_errors(v)).sum(),
+        &Val::List(ref values) => values.iter().map(payload_errors).sum(),
+        &Val::Payload(_, Ok(ref inner)) => payload_errors(inner),
+        &Val::Payload(_, Err(_)) => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use DissectError;
+    use DissectResult;
+    use ethernet;
+
+    fn fragile(data: &[u8]) -> DissectResult {
+        match data.first() {
+            Some(&0) => panic!("zero"),
+            Some(_) => ethernet::dissect(data),
+            None => Err(DissectError::InvalidData("empty".to_string())),
+        }
+    }
+
+    #[test]
+    fn batch_errors() {
+        let ok = [1; 14];
+        let packets: Vec<&[u8]> = vec![&ok, &[0], &[], &ok[..5], &ok];
+
+        for batch in vec![dissect_all(&packets, fragile), dissect_all_parallel(&packets, fragile, 3)] {
+            assert_eq!(batch.results.len(), 5);
+            assert!(batch.results[0].is_ok());
+            assert!(batch.results[1].is_err());
+            assert!(batch.results[4].is_ok());
+
+            assert_eq!(batch.stats, BatchStats {
+                packets: 5,
+                dissected: 2,
+                failed: 3,
+                underflows: 1,
+                invalid: 2,
+                panics: 1,
+                payload_errors: 0,
+            });
+            assert_eq!(batch.results[1].as_ref().unwrap_err().code(), ErrorCode::DissectorPanic);
+            assert_eq!(batch.results[3].as_ref().unwrap_err().code(), ErrorCode::Truncated);
+        }
+    }
+}
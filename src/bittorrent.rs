@@ -0,0 +1,511 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of BitTorrent: the peer wire protocol over TCP, the DHT's
+//! KRPC messages and the UDP tracker protocol.
+//!
+//! Peers listen on arbitrary ports, so peer connections are recognized by
+//! their handshake ("\x13BitTorrent protocol") rather than by port; the
+//! length-prefixed messages that follow are only dissected on the
+//! conventional ports (6881-6889) or after a handshake in the same segment.
+//! KRPC messages are bencoded dictionaries, whose well-known keys (the
+//! transaction ID, query name, node IDs, info hashes and compact node and
+//! peer lists) are surfaced as fields. `dissect_bencode` dissects any other
+//! bencoded data, e.g., an HTTP tracker's response.
+//!
+//! See [BEP 3](http://bittorrent.org/beps/bep_0003.html),
+//! [BEP 5](http://bittorrent.org/beps/bep_0005.html) and
+//! [BEP 15](http://bittorrent.org/beps/bep_0015.html).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use {read_be_u16, read_be_u32, read_be_u64};
+
+enum_map!(pub MESSAGE_TYPES {
+    0 => "Choke",
+    1 => "Unchoke",
+    2 => "Interested",
+    3 => "Not Interested",
+    4 => "Have",
+    5 => "Bitfield",
+    6 => "Request",
+    7 => "Piece",
+    8 => "Cancel",
+    9 => "Port",
+    20 => "Extended",
+});
+
+enum_map!(pub TRACKER_ACTIONS {
+    0 => "Connect",
+    1 => "Announce",
+    2 => "Scrape",
+    3 => "Error",
+});
+
+enum_map!(pub ANNOUNCE_EVENTS {
+    0 => "None",
+    1 => "Completed",
+    2 => "Started",
+    3 => "Stopped",
+});
+
+/// The first of the conventional peer (and DHT) ports, and the last.
+pub const PORT: u16 = 6881;
+pub const LAST_PORT: u16 = 6889;
+
+/// A common port for UDP trackers.
+pub const TRACKER_PORT: u16 = 6969;
+
+const PROTOCOL: &'static [u8] = b"\x13BitTorrent protocol";
+
+/// The connection ID of a UDP tracker Connect request.
+const TRACKER_MAGIC: u64 = 0x417_2710_1980;
+
+/// Bencoded data is rarely nested deeply; deeper data is rejected rather
+/// than risking the stack.
+const MAX_DEPTH: usize = 32;
+
+/// Whether a TCP payload starts with a peer wire handshake.
+pub fn looks_like_handshake(data: &[u8]) -> bool {
+    data.starts_with(PROTOCOL)
+}
+
+/// Whether a UDP payload looks like a KRPC message: a bencoded dictionary
+/// whose first key is one of the usual few.
+pub fn looks_like_krpc(data: &[u8]) -> bool {
+    ["d1:a", "d1:e", "d1:r", "d1:t", "d1:v", "d2:ip"].iter().any(|p| data.starts_with(p.as_bytes()))
+        && data.last() == Some(&b'e')
+}
+
+/// Whether a UDP payload is a tracker Connect request.
+pub fn looks_like_tracker_connect(data: &[u8]) -> bool {
+    data.len() == 16 && read_be_u64(data, 0).ok() == Some(TRACKER_MAGIC)
+}
+
+/// Dissect peer wire protocol data: an optional handshake followed by
+/// length-prefixed messages.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+    let mut offset = 0;
+
+    if looks_like_handshake(data) {
+        if data.len() < 68 {
+            return Err(DissectError::Underflow { expected: Some(68), have: data.len(),
+                message: "A BitTorrent handshake must be 68 B".to_string() });
+        }
+
+        values.push(("Protocol", Val::String("BitTorrent protocol".to_string())));
+        values.push(("Reserved", Val::Bytes(&data[20..28])));
+        values.push(("Extension Protocol", Val::Unsigned((data[25] & 0x10 != 0) as u64)));
+        values.push(("DHT", Val::Unsigned((data[27] & 0x01 != 0) as u64)));
+        values.push(("Info Hash", Val::Bytes(&data[28..48])));
+        values.push(("Peer ID", Val::Bytes(&data[48..68])));
+        offset = 68;
+    }
+
+    while offset + 4 <= data.len() {
+        let length = read_be_u32(data, offset)? as usize;
+        let end = offset.saturating_add(4).saturating_add(length);
+        if length > 0x20_0000 {
+            return Err(DissectError::InvalidData(
+                format!["BitTorrent message length {} is implausible", length]));
+        }
+
+        // The last message may continue in the next segment.
+        let body = &data[offset + 4..end.min(data.len())];
+        values.push(("Message", message(length, body)?));
+        offset = end;
+    }
+
+    if offset < data.len() {
+        values.push(("Remainder", Val::Undissected("BitTorrent", &data[offset..])));
+    }
+
+    Ok(Box::new(Val::Object("BitTorrent", values)))
+}
+
+fn message(length: usize, body: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+    values.push(("Length", Val::Unsigned(length as u64)));
+
+    if length == 0 {
+        values.push(("Message Type Name", Val::Symbol("Keep-Alive")));
+        return Ok(Val::Object("BitTorrent Message", values));
+    }
+
+    // The message may continue in the next segment.
+    let id = match body.first() {
+        Some(&id) => id,
+        None => return Ok(Val::Object("BitTorrent Message", values)),
+    };
+    let payload = &body[1..];
+    values.push(("Message Type", Val::Unsigned(id as u64)));
+    values.push(("Message Type Name", MESSAGE_TYPES.val(id)));
+
+    match id {
+        4 => values.push(("Piece Index", Val::Unsigned(read_be_u32(payload, 0)? as u64))),
+        5 => values.push(("Bitfield", Val::Bytes(payload))),
+        6 | 8 => {
+            values.push(("Piece Index", Val::Unsigned(read_be_u32(payload, 0)? as u64)));
+            values.push(("Begin", Val::Unsigned(read_be_u32(payload, 4)? as u64)));
+            values.push(("Block Length", Val::Unsigned(read_be_u32(payload, 8)? as u64)));
+        },
+        7 => {
+            values.push(("Piece Index", Val::Unsigned(read_be_u32(payload, 0)? as u64)));
+            values.push(("Begin", Val::Unsigned(read_be_u32(payload, 4)? as u64)));
+            values.push(("Block", Val::Bytes(&payload[8..])));
+        },
+        9 => values.push(("DHT Port", Val::Unsigned(read_be_u16(payload, 0)? as u64))),
+        20 => {
+            if let Some(&extended) = payload.first() {
+                values.push(("Extended Message ID", Val::Unsigned(extended as u64)));
+                if extended == 0 {
+                    values.push(("Extended Handshake", dissect_bencode(&payload[1..])
+                        .map(|v| *v).unwrap_or(Val::Bytes(&payload[1..]))));
+                }
+            }
+        },
+        _ if !payload.is_empty() => values.push(("Payload", Val::Bytes(payload))),
+        _ => {},
+    }
+
+    Ok(Val::Object("BitTorrent Message", values))
+}
+
+/// A decoded bencoded value.
+enum Bencode<'data> {
+    Integer(i64),
+    String(&'data [u8]),
+    List(Vec<Bencode<'data>>),
+    Dictionary(Vec<(&'data [u8], Bencode<'data>)>),
+}
+
+impl<'data> Bencode<'data> {
+    /// Parse the value at the start of `data`, returning it and its length.
+    fn parse(data: &'data [u8], depth: usize) -> Result<(Bencode<'data>, usize), DissectError> {
+        if depth > MAX_DEPTH {
+            return Err(DissectError::InvalidData("Bencoded data is nested too deeply".to_string()));
+        }
+
+        let truncated = || DissectError::Underflow { expected: None, have: data.len(),
+            message: "Bencoded data is truncated".to_string() };
+
+        match data.first() {
+            Some(&b'i') => {
+                let end = data.iter().position(|&b| b == b'e').ok_or_else(truncated)?;
+                let n = String::from_utf8_lossy(&data[1..end]).parse::<i64>().map_err(|_|
+                    DissectError::InvalidData("Bencoded integer is invalid".to_string()))?;
+                Ok((Bencode::Integer(n), end + 1))
+            },
+            Some(&b'l') | Some(&b'd') => {
+                let dictionary = data[0] == b'd';
+                let mut items = Vec::new();
+                let mut entries = Vec::new();
+                let mut offset = 1;
+
+                loop {
+                    match data.get(offset) {
+                        Some(&b'e') => break,
+                        None => return Err(truncated()),
+                        _ => {},
+                    }
+
+                    let (value, length) = Bencode::parse(&data[offset..], depth + 1)?;
+                    offset += length;
+
+                    if !dictionary {
+                        items.push(value);
+                        continue;
+                    }
+
+                    let key = match value {
+                        Bencode::String(key) => key,
+                        _ => return Err(DissectError::InvalidData(
+                            "Bencoded dictionary key is not a string".to_string())),
+                    };
+                    let (value, length) = Bencode::parse(&data[offset..], depth + 1)?;
+                    entries.push((key, value));
+                    offset += length;
+                }
+
+                let value = if dictionary { Bencode::Dictionary(entries) } else { Bencode::List(items) };
+                Ok((value, offset + 1))
+            },
+            Some(&b) if b.is_ascii_digit() => {
+                let colon = data.iter().position(|&b| b == b':').ok_or_else(truncated)?;
+                let length = String::from_utf8_lossy(&data[..colon]).parse::<usize>().map_err(|_|
+                    DissectError::InvalidData("Bencoded string length is invalid".to_string()))?;
+                let end = colon.checked_add(1 + length).filter(|&e| e <= data.len()).ok_or_else(truncated)?;
+                Ok((Bencode::String(&data[colon + 1..end]), end))
+            },
+            _ => Err(DissectError::InvalidData("Data is not bencoded".to_string())),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Bencode<'data>> {
+        match *self {
+            Bencode::Dictionary(ref entries) =>
+                entries.iter().find(|&&(k, _)| k == key.as_bytes()).map(|&(_, ref v)| v),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Option<&'data [u8]> {
+        match *self {
+            Bencode::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn val(&self) -> Val<'data> {
+        match *self {
+            Bencode::Integer(n) => Val::Signed(n),
+            Bencode::String(s) => string(s),
+            Bencode::List(ref items) =>
+                Val::Object("Bencode List", items.iter().map(|i| ("Item", i.val())).collect()),
+            Bencode::Dictionary(ref entries) => {
+                let mut values = NamedValues::new();
+                for &(key, ref value) in entries {
+                    values.push(("Key", string(key)));
+                    values.push(("Value", value.val()));
+                }
+                Val::Object("Bencode Dictionary", values)
+            },
+        }
+    }
+}
+
+/// A bencoded string, which may be text or binary.
+fn string(s: &[u8]) -> Val {
+    match ::std::str::from_utf8(s) {
+        Ok(text) if text.chars().all(|c| !c.is_control()) => Val::String(text.to_string()),
+        _ => Val::Bytes(s),
+    }
+}
+
+/// Dissect any bencoded value, e.g., a .torrent file or an HTTP tracker
+/// response. Dictionaries are dissected as alternating "Key" and "Value"
+/// fields, since their keys are arbitrary.
+pub fn dissect_bencode(data : &[u8]) -> DissectResult {
+    let (value, _) = Bencode::parse(data, 0)?;
+    Ok(Box::new(value.val()))
+}
+
+/// Dissect a DHT (KRPC) message.
+pub fn dissect_krpc(data : &[u8]) -> DissectResult {
+    let (message, _) = Bencode::parse(data, 0)?;
+    match message {
+        Bencode::Dictionary(_) => {},
+        _ => return Err(DissectError::InvalidData("A KRPC message must be a dictionary".to_string())),
+    }
+
+    let mut values = NamedValues::new();
+    if let Some(t) = message.get("t").and_then(|t| t.bytes()) {
+        values.push(("Transaction ID", Val::Bytes(t)));
+    }
+
+    values.push(("Message Type", Val::Symbol(match message.get("y").and_then(|y| y.bytes()) {
+        Some(b"q") => "Query",
+        Some(b"r") => "Response",
+        Some(b"e") => "Error",
+        _ => return Err(DissectError::InvalidData("KRPC message type is missing".to_string())),
+    })));
+
+    if let Some(q) = message.get("q").and_then(|q| q.bytes()) {
+        values.push(("Query", string(q)));
+    }
+    if let Some(v) = message.get("v").and_then(|v| v.bytes()) {
+        values.push(("Client Version", Val::Bytes(v)));
+    }
+
+    if let Some(arguments) = message.get("a").or_else(|| message.get("r")) {
+        for &(key, name) in &[("id", "Node ID"), ("target", "Target"), ("info_hash", "Info Hash"),
+                              ("token", "Token")] {
+            if let Some(b) = arguments.get(key).and_then(|b| b.bytes()) {
+                values.push((name, Val::Bytes(b)));
+            }
+        }
+        if let Some(&Bencode::Integer(port)) = arguments.get("port") {
+            values.push(("Port", Val::Signed(port)));
+        }
+
+        // Compact node info: a 20 B ID, an IPv4 address and a port each
+        if let Some(nodes) = arguments.get("nodes").and_then(|n| n.bytes()) {
+            for node in nodes.chunks(26).filter(|n| n.len() == 26) {
+                let mut fields = NamedValues::new();
+                fields.push(("Node ID", Val::Bytes(&node[..20])));
+                fields.push(("Address", ipv4(&node[20..24])));
+                fields.push(("Port", Val::Unsigned(read_be_u16(node, 24)? as u64)));
+                values.push(("Node", Val::Object("DHT Node", fields)));
+            }
+        }
+
+        // Compact peer info: an IPv4 address and a port each
+        if let Some(&Bencode::List(ref peers)) = arguments.get("values") {
+            for peer in peers.iter().filter_map(|p| p.bytes()).filter(|p| p.len() == 6) {
+                let mut fields = NamedValues::new();
+                fields.push(("Address", ipv4(&peer[..4])));
+                fields.push(("Port", Val::Unsigned(read_be_u16(peer, 4)? as u64)));
+                values.push(("Peer", Val::Object("DHT Peer", fields)));
+            }
+        }
+    }
+
+    if let Some(&Bencode::List(ref error)) = message.get("e") {
+        if let Some(&Bencode::Integer(code)) = error.get(0) {
+            values.push(("Error Code", Val::Signed(code)));
+        }
+        if let Some(text) = error.get(1).and_then(|m| m.bytes()) {
+            values.push(("Error Message", string(text)));
+        }
+    }
+
+    Ok(Box::new(Val::Object("BitTorrent DHT", values)))
+}
+
+/// Dissect a UDP tracker request, from client to tracker.
+pub fn dissect_tracker_request(data : &[u8]) -> DissectResult {
+    if data.len() < 16 {
+        return Err(DissectError::Underflow { expected: Some(16), have: data.len(),
+            message: "A UDP tracker request must be at least 16 B".to_string() });
+    }
+
+    let action = read_be_u32(data, 8)?;
+    let mut values = NamedValues::new();
+    values.push(("Connection ID", Val::Unsigned(read_be_u64(data, 0)?)));
+    values.push(("Action", TRACKER_ACTIONS.val(action)));
+    values.push(("Transaction ID", Val::Unsigned(read_be_u32(data, 12)? as u64)));
+
+    match action {
+        1 => {
+            if data.len() < 98 {
+                return Err(DissectError::Underflow { expected: Some(98), have: data.len(),
+                    message: "A UDP tracker announce must be 98 B".to_string() });
+            }
+            values.push(("Info Hash", Val::Bytes(&data[16..36])));
+            values.push(("Peer ID", Val::Bytes(&data[36..56])));
+            values.push(("Downloaded", Val::Unsigned(read_be_u64(data, 56)?)));
+            values.push(("Left", Val::Unsigned(read_be_u64(data, 64)?)));
+            values.push(("Uploaded", Val::Unsigned(read_be_u64(data, 72)?)));
+            values.push(("Event", ANNOUNCE_EVENTS.val(read_be_u32(data, 80)?)));
+            values.push(("IP Address", ipv4(&data[84..88])));
+            values.push(("Key", Val::Unsigned(read_be_u32(data, 88)? as u64)));
+            values.push(("Peers Wanted", Val::Signed(read_be_u32(data, 92)? as i32 as i64)));
+            values.push(("Port", Val::Unsigned(read_be_u16(data, 96)? as u64)));
+        },
+        2 => for hash in data[16..].chunks(20) {
+            values.push(("Info Hash", Val::Bytes(hash)));
+        },
+        _ => {},
+    }
+
+    Ok(Box::new(Val::Object("BitTorrent Tracker", values)))
+}
+
+/// Dissect a UDP tracker response, from tracker to client.
+pub fn dissect_tracker_response(data : &[u8]) -> DissectResult {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "A UDP tracker response must be at least 8 B".to_string() });
+    }
+
+    let action = read_be_u32(data, 0)?;
+    let mut values = NamedValues::new();
+    values.push(("Action", TRACKER_ACTIONS.val(action)));
+    values.push(("Transaction ID", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+
+    match action {
+        0 => values.push(("Connection ID", Val::Unsigned(read_be_u64(data, 8)?))),
+        1 => {
+            values.push(("Interval", Val::Unsigned(read_be_u32(data, 8)? as u64)));
+            values.push(("Leechers", Val::Unsigned(read_be_u32(data, 12)? as u64)));
+            values.push(("Seeders", Val::Unsigned(read_be_u32(data, 16)? as u64)));
+            for peer in data[20..].chunks(6).filter(|p| p.len() == 6) {
+                let mut fields = NamedValues::new();
+                fields.push(("Address", ipv4(&peer[..4])));
+                fields.push(("Port", Val::Unsigned(read_be_u16(peer, 4)? as u64)));
+                values.push(("Peer", Val::Object("BitTorrent Peer", fields)));
+            }
+        },
+        3 => values.push(("Error Message", Val::String(String::from_utf8_lossy(&data[8..]).into_owned()))),
+        _ => {},
+    }
+
+    Ok(Box::new(Val::Object("BitTorrent Tracker", values)))
+}
+
+fn ipv4(bytes: &[u8]) -> Val {
+    Val::Address {
+        bytes: bytes,
+        encoded: bytes.iter().map(|b| b.to_string()).collect::<Vec<_>>().join("."),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_peer_wire() {
+        let mut data = PROTOCOL.to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0x10, 0, 0x01]);
+        data.extend_from_slice(&[0xaa; 20]);
+        data.extend_from_slice(b"-TR2940-0123456789ab");
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, 13, 6, 0, 0, 0, 7, 0, 0, 0x40, 0, 0, 0, 0x40, 0]);
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Info Hash"].as_bytes().unwrap(), &[0xaa; 20][..]);
+        assert_eq!(val["DHT"].as_unsigned().unwrap(), 1);
+
+        let messages = val.as_object().unwrap().1.iter()
+            .filter(|&&(name, _)| name == "Message")
+            .map(|&(_, ref m)| m["Message Type Name"].as_symbol().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["Keep-Alive", "Request"]);
+        assert_eq!(val.as_object().unwrap().1.last().unwrap().1["Begin"].as_unsigned().unwrap(), 0x4000);
+    }
+
+    #[test]
+    fn dissect_dht() {
+        let query = b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz123456e\
+                      1:q9:get_peers1:t2:aa1:y1:qe";
+        assert!(looks_like_krpc(query));
+
+        let val = *dissect_krpc(query).unwrap();
+        assert_eq!(val["Message Type"].as_symbol().unwrap(), "Query");
+        assert_eq!(val["Query"].as_string().unwrap(), "get_peers");
+        assert_eq!(val["Info Hash"].as_bytes().unwrap(), b"mnopqrstuvwxyz123456");
+
+        let response = b"d1:rd2:id20:abcdefghij01234567895:token2:xy6:valuesl6:\x0a\x00\x00\x01\x1a\xe1ee\
+                         1:t2:aa1:y1:re";
+        let val = *dissect_krpc(response).unwrap();
+        assert_eq!(val["Peer"]["Address"].as_address_encoded().unwrap(), "10.0.0.1");
+        assert_eq!(val["Peer"]["Port"].as_unsigned().unwrap(), 6881);
+
+        assert!(dissect_bencode(b"d3:fooi42e").is_err());
+        assert!(dissect_bencode(&[b'l'; 64]).is_err());
+    }
+
+    #[test]
+    fn dissect_udp_tracker() {
+        let connect = [0, 0, 0x04, 0x17, 0x27, 0x10, 0x19, 0x80, 0, 0, 0, 0, 0, 0, 0, 7];
+        assert!(looks_like_tracker_connect(&connect));
+        let val = *dissect_tracker_request(&connect).unwrap();
+        assert_eq!(val["Action"].as_symbol().unwrap(), "Connect");
+
+        let announced = [0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0x07, 0x08, 0, 0, 0, 1, 0, 0, 0, 2,
+                         192, 168, 1, 2, 0x1a, 0xe1];
+        let val = *dissect_tracker_response(&announced).unwrap();
+        assert_eq!(val["Interval"].as_unsigned().unwrap(), 1800);
+        assert_eq!(val["Peer"]["Address"].as_address_encoded().unwrap(), "192.168.1.2");
+    }
+}
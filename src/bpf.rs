@@ -0,0 +1,607 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Capture filters in the style of the Berkeley Packet Filter (BPF).
+//!
+//! `compile` translates a tcpdump-style filter expression into a `Program`
+//! for a small virtual machine modelled on classic BPF, which can be run
+//! against raw Ethernet frames before they are dissected. This gives packet
+//! sources cheap pre-filtering without needing libpcap.
+//!
+//! Programs can't loop (all jumps go forwards) and loads past the end of a
+//! packet reject it, so running untrusted filters on untrusted packets is
+//! safe.
+//!
+//! The supported syntax is:
+//!
+//!  * protocols: `ip`, `ip6`, `arp`, `icmp`, `tcp`, `udp`
+//!  * `[src|dst] host A.B.C.D`
+//!  * `[src|dst] net A.B.C.D/LEN`
+//!  * `[tcp|udp] [src|dst] port N`
+//!  * `greater N`, `less N` (packet length)
+//!  * `not`/`!`, `and`/`&&`, `or`/`||` and parentheses
+//!
+//! ```
+//! let filter = rshark::bpf::compile("tcp and not port 22").unwrap();
+//! assert!(!filter.matches(&[0; 14]));
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// The width of a packet load.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Size {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Size {
+    fn bytes(&self) -> usize {
+        match self {
+            &Size::Byte => 1,
+            &Size::Half => 2,
+            &Size::Word => 4,
+        }
+    }
+}
+
+/// A filter VM instruction. Jump offsets are relative to the following
+/// instruction, as in classic BPF.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Insn {
+    /// A <- P[k:size]
+    LoadAbsolute(Size, u32),
+
+    /// A <- P[X + k:size]
+    LoadIndirect(Size, u32),
+
+    /// A <- length of the packet
+    LoadLength,
+
+    /// X <- 4 * (P[k] & 0xf), i.e., an IPv4 header length
+    LoadHeaderLength(u32),
+
+    /// A <- A & k
+    And(u32),
+
+    /// pc += (A == k) ? jt : jf
+    JumpEq(u32, usize, usize),
+
+    /// pc += (A > k) ? jt : jf
+    JumpGt(u32, usize, usize),
+
+    /// pc += (A >= k) ? jt : jf
+    JumpGe(u32, usize, usize),
+
+    /// pc += (A & k != 0) ? jt : jf
+    JumpSet(u32, usize, usize),
+
+    /// Accept k bytes of the packet (0 rejects it).
+    Return(u32),
+}
+
+/// An error in a filter expression or program.
+#[derive(Debug, PartialEq)]
+pub struct FilterError(pub String);
+
+impl Error for FilterError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write![f, "filter error: {}", self.0]
+    }
+}
+
+/// A validated filter program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    insns: Vec<Insn>,
+}
+
+impl Program {
+    /// Validate a program: every jump must land within it and it must end
+    /// with a `Return`.
+    pub fn new(insns: Vec<Insn>) -> Result<Program, FilterError> {
+        match insns.last() {
+            Some(&Insn::Return(_)) => {},
+            _ => return Err(FilterError("program must end with a return".to_string())),
+        }
+
+        for (pc, insn) in insns.iter().enumerate() {
+            let targets = match insn {
+                &Insn::JumpEq(_, jt, jf) | &Insn::JumpGt(_, jt, jf)
+                    | &Insn::JumpGe(_, jt, jf) | &Insn::JumpSet(_, jt, jf) => (jt, jf),
+                _ => continue,
+            };
+
+            if pc + 1 + ::std::cmp::max(targets.0, targets.1) >= insns.len() {
+                return Err(FilterError(format!["jump out of program at instruction {}", pc]));
+            }
+        }
+
+        Ok(Program { insns: insns })
+    }
+
+    pub fn instructions(&self) -> &[Insn] {
+        &self.insns
+    }
+
+    /// Run the program over a packet, returning the number of bytes to
+    /// accept (0 if the packet is rejected).
+    pub fn run(&self, packet: &[u8]) -> u32 {
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut pc = 0;
+
+        let load = |offset: usize, size: Size| -> Option<u32> {
+            packet.get(offset..offset + size.bytes())
+                .map(|bytes| bytes.iter().fold(0, |v, &b| v << 8 | b as u32))
+        };
+
+        while pc < self.insns.len() {
+            let insn = self.insns[pc];
+            pc += 1;
+
+            let (condition, jt, jf) = match insn {
+                Insn::LoadAbsolute(size, k) => {
+                    a = match load(k as usize, size) { Some(v) => v, None => return 0 };
+                    continue;
+                },
+                Insn::LoadIndirect(size, k) => {
+                    a = match load(x as usize + k as usize, size) { Some(v) => v, None => return 0 };
+                    continue;
+                },
+                Insn::LoadLength => { a = packet.len() as u32; continue },
+                Insn::LoadHeaderLength(k) => {
+                    x = match load(k as usize, Size::Byte) { Some(v) => 4 * (v & 0xf), None => return 0 };
+                    continue;
+                },
+                Insn::And(k) => { a &= k; continue },
+                Insn::JumpEq(k, jt, jf) => (a == k, jt, jf),
+                Insn::JumpGt(k, jt, jf) => (a > k, jt, jf),
+                Insn::JumpGe(k, jt, jf) => (a >= k, jt, jf),
+                Insn::JumpSet(k, jt, jf) => (a & k != 0, jt, jf),
+                Insn::Return(k) => return k,
+            };
+
+            pc += if condition { jt } else { jf };
+        }
+
+        0
+    }
+
+    /// Returns true if the program accepts the packet.
+    pub fn matches(&self, packet: &[u8]) -> bool {
+        self.run(packet) != 0
+    }
+}
+
+/// Compile a filter expression for Ethernet frames. An empty expression
+/// accepts every packet.
+pub fn compile(filter: &str) -> Result<Program, FilterError> {
+    let tokens = tokenize(filter);
+    if tokens.is_empty() {
+        return Program::new(vec![Insn::Return(ACCEPT)]);
+    }
+
+    let mut parser = Parser { tokens: tokens, position: 0 };
+    let expr = parser.expression()?;
+    if let Some(token) = parser.peek() {
+        return Err(FilterError(format!["unexpected '{}'", token]));
+    }
+
+    let mut gen = Generator { code: Vec::new(), labels: vec![None, None] };
+    gen.emit(&expr, TRUE, FALSE);
+    Program::new(gen.finish())
+}
+
+const ACCEPT: u32 = 0x40000;
+const TRUE: usize = 0;
+const FALSE: usize = 1;
+
+/// Ethernet header length: link-layer offset of the network layer.
+const LINK: u32 = 14;
+
+/// A boolean filter expression over primitive tests.
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Test(Test),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// A primitive test: load a value, mask it and compare it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Test {
+    load: Load,
+    mask: Option<u32>,
+    comparison: Comparison,
+    value: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Load {
+    Absolute(Size, u32),
+
+    /// Relative to the end of the IPv4 header
+    Transport(Size, u32),
+    Length,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Eq,
+    Gt,
+    Ge,
+    Set,
+}
+
+fn test(load: Load, mask: Option<u32>, comparison: Comparison, value: u32) -> Expr {
+    Expr::Test(Test { load: load, mask: mask, comparison: comparison, value: value })
+}
+
+fn and(a: Expr, b: Expr) -> Expr {
+    Expr::And(Box::new(a), Box::new(b))
+}
+
+fn or(a: Expr, b: Expr) -> Expr {
+    Expr::Or(Box::new(a), Box::new(b))
+}
+
+fn not(a: Expr) -> Expr {
+    Expr::Not(Box::new(a))
+}
+
+fn ethertype(value: u32) -> Expr {
+    test(Load::Absolute(Size::Half, 12), None, Comparison::Eq, value)
+}
+
+fn ip_protocol(value: u32) -> Expr {
+    and(ethertype(0x0800), test(Load::Absolute(Size::Byte, LINK + 9), None, Comparison::Eq, value))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    Source,
+    Destination,
+    Either,
+}
+
+fn directional<F>(direction: Direction, f: F) -> Expr where F: Fn(Direction) -> Expr {
+    match direction {
+        Direction::Either => or(f(Direction::Source), f(Direction::Destination)),
+        d => f(d),
+    }
+}
+
+fn tokenize(filter: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for c in filter.chars() {
+        match c {
+            '(' | ')' | '!' => {
+                if !current.is_empty() { tokens.push(current.clone()); current.clear(); }
+                tokens.push(c.to_string());
+            },
+            c if c.is_whitespace() => {
+                if !current.is_empty() { tokens.push(current.clone()); current.clear(); }
+            },
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|s| &s[..])
+    }
+
+    fn next(&mut self) -> Result<String, FilterError> {
+        match self.tokens.get(self.position) {
+            Some(t) => { self.position += 1; Ok(t.clone()) },
+            None => Err(FilterError("unexpected end of filter".to_string())),
+        }
+    }
+
+    fn accept(&mut self, options: &[&str]) -> bool {
+        match self.peek() {
+            Some(t) if options.contains(&t) => { self.position += 1; true },
+            _ => false,
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.term()?;
+        while self.accept(&["or", "||"]) {
+            expr = or(expr, self.term()?);
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.factor()?;
+        while self.accept(&["and", "&&"]) {
+            expr = and(expr, self.factor()?);
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, FilterError> {
+        if self.accept(&["not", "!"]) {
+            return Ok(not(self.factor()?));
+        }
+
+        if self.accept(&["("]) {
+            let expr = self.expression()?;
+            if !self.accept(&[")"]) {
+                return Err(FilterError("expected ')'".to_string()));
+            }
+            return Ok(expr);
+        }
+
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Expr, FilterError> {
+        let token = self.next()?;
+
+        let protocol = match &token[..] {
+            "tcp" => Some(6),
+            "udp" => Some(17),
+            _ => None,
+        };
+
+        match &token[..] {
+            "ip" => return Ok(ethertype(0x0800)),
+            "ip6" => return Ok(ethertype(0x86dd)),
+            "arp" => return Ok(ethertype(0x0806)),
+            "icmp" => return Ok(ip_protocol(1)),
+            "greater" => return Ok(test(Load::Length, None, Comparison::Ge, self.number()?)),
+            "less" => return Ok(not(test(Load::Length, None, Comparison::Gt, self.number()?))),
+            _ => {},
+        }
+
+        // Protocols may qualify a port: "tcp port 80" vs "tcp".
+        let token = match protocol {
+            Some(p) => match self.peek() {
+                Some("src") | Some("dst") | Some("port") => self.next()?,
+                _ => return Ok(ip_protocol(p)),
+            },
+            None => token,
+        };
+
+        let (direction, token) = match &token[..] {
+            "src" => (Direction::Source, self.next()?),
+            "dst" => (Direction::Destination, self.next()?),
+            _ => (Direction::Either, token),
+        };
+
+        match &token[..] {
+            "host" => {
+                let address = self.address()?;
+                Ok(and(ethertype(0x0800), directional(direction, |d| {
+                    test(Load::Absolute(Size::Word, address_offset(d)), None, Comparison::Eq, address)
+                })))
+            },
+
+            "net" => {
+                let spec = self.next()?;
+                let mut parts = spec.splitn(2, '/');
+                let address = parse_address(parts.next().unwrap_or(""))?;
+                let length = match parts.next().map(|l| l.parse::<u32>()) {
+                    Some(Ok(l)) if l <= 32 => l,
+                    None => 32,
+                    _ => return Err(FilterError(format!["invalid network '{}'", spec])),
+                };
+                let mask = if length == 0 { 0 } else { !0u32 << (32 - length) };
+
+                Ok(and(ethertype(0x0800), directional(direction, |d| {
+                    test(Load::Absolute(Size::Word, address_offset(d)), Some(mask), Comparison::Eq,
+                         address & mask)
+                })))
+            },
+
+            "port" => {
+                let port = self.number()?;
+                if port > 0xffff {
+                    return Err(FilterError(format!["invalid port {}", port]));
+                }
+
+                let protocols = match protocol {
+                    Some(p) => ip_protocol(p),
+                    None => or(ip_protocol(6), ip_protocol(17)),
+                };
+
+                // Only the first fragment carries the transport header.
+                let unfragmented = not(test(Load::Absolute(Size::Half, LINK + 6), None,
+                                            Comparison::Set, 0x1fff));
+
+                Ok(and(and(protocols, unfragmented), directional(direction, |d| {
+                    let offset = if d == Direction::Source { 0 } else { 2 };
+                    test(Load::Transport(Size::Half, offset), None, Comparison::Eq, port)
+                })))
+            },
+
+            t => Err(FilterError(format!["unknown primitive '{}'", t])),
+        }
+    }
+
+    fn number(&mut self) -> Result<u32, FilterError> {
+        let token = self.next()?;
+        token.parse().map_err(|_| FilterError(format!["expected a number, not '{}'", token]))
+    }
+
+    fn address(&mut self) -> Result<u32, FilterError> {
+        let token = self.next()?;
+        parse_address(&token)
+    }
+}
+
+fn parse_address(s: &str) -> Result<u32, FilterError> {
+    s.parse::<Ipv4Addr>()
+        .map(|a| a.octets().iter().fold(0, |v, &b| v << 8 | b as u32))
+        .map_err(|_| FilterError(format!["invalid IPv4 address '{}'", s]))
+}
+
+fn address_offset(direction: Direction) -> u32 {
+    if direction == Direction::Source { LINK + 12 } else { LINK + 16 }
+}
+
+/// Code generator: compiles expressions into jumps to "true" and "false"
+/// labels, which are resolved to instruction offsets once all code has been
+/// emitted. Labels are always placed after the code that jumps to them, so
+/// all jumps go forwards.
+struct Generator {
+    code: Vec<Pending>,
+    labels: Vec<Option<usize>>,
+}
+
+enum Pending {
+    Insn(Insn),
+
+    /// A conditional jump to (true, false) labels
+    Jump(Comparison, u32, usize, usize),
+}
+
+impl Generator {
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn emit(&mut self, expr: &Expr, t: usize, f: usize) {
+        match expr {
+            &Expr::Test(ref test) => {
+                match test.load {
+                    Load::Absolute(size, k) => self.code.push(Pending::Insn(Insn::LoadAbsolute(size, k))),
+                    Load::Transport(size, k) => {
+                        self.code.push(Pending::Insn(Insn::LoadHeaderLength(LINK)));
+                        self.code.push(Pending::Insn(Insn::LoadIndirect(size, LINK + k)));
+                    },
+                    Load::Length => self.code.push(Pending::Insn(Insn::LoadLength)),
+                }
+
+                if let Some(mask) = test.mask {
+                    self.code.push(Pending::Insn(Insn::And(mask)));
+                }
+
+                self.code.push(Pending::Jump(test.comparison, test.value, t, f));
+            },
+
+            &Expr::And(ref a, ref b) => {
+                let next = self.label();
+                self.emit(a, next, f);
+                self.place(next);
+                self.emit(b, t, f);
+            },
+
+            &Expr::Or(ref a, ref b) => {
+                let next = self.label();
+                self.emit(a, t, next);
+                self.place(next);
+                self.emit(b, t, f);
+            },
+
+            &Expr::Not(ref a) => self.emit(a, f, t),
+        }
+    }
+
+    fn finish(mut self) -> Vec<Insn> {
+        self.place(TRUE);
+        self.code.push(Pending::Insn(Insn::Return(ACCEPT)));
+        self.place(FALSE);
+        self.code.push(Pending::Insn(Insn::Return(0)));
+
+        let labels = self.labels;
+        let target = |pc: usize, label: usize| labels[label].unwrap() - pc - 1;
+
+        self.code.iter().enumerate()
+            .map(|(pc, pending)| match pending {
+                &Pending::Insn(insn) => insn,
+                &Pending::Jump(comparison, k, t, f) => {
+                    let (jt, jf) = (target(pc, t), target(pc, f));
+                    match comparison {
+                        Comparison::Eq => Insn::JumpEq(k, jt, jf),
+                        Comparison::Gt => Insn::JumpGt(k, jt, jf),
+                        Comparison::Ge => Insn::JumpGe(k, jt, jf),
+                        Comparison::Set => Insn::JumpSet(k, jt, jf),
+                    }
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 192.168.1.115:64747 -> 46.137.186.243:443 [SYN]
+    const SYN: [u8; 54] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+        0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x00, 0x00, 0x40, 0x06, 0x00, 0x00,
+        192, 168, 1, 115, 46, 137, 186, 243,
+        0xfc, 0xeb, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn filter_packets() {
+        let matches = |filter| compile(filter).unwrap().matches(&SYN);
+
+        assert!(matches(""));
+        assert!(matches("ip and tcp"));
+        assert!(!matches("udp or arp"));
+        assert!(matches("tcp port 443"));
+        assert!(matches("dst port 443 && src port 64747"));
+        assert!(!matches("src port 443"));
+        assert!(!matches("udp port 443"));
+        assert!(matches("src host 192.168.1.115 and not dst host 192.168.1.115"));
+        assert!(matches("net 46.137.0.0/16"));
+        assert!(!matches("src net 46.137.0.0/16"));
+        assert!(matches("(icmp or tcp) and greater 54 and less 54"));
+        assert!(!matches("!(tcp)"));
+
+        // Truncated packets are rejected rather than read past the end.
+        assert!(!compile("tcp port 443").unwrap().matches(&SYN[..35]));
+    }
+
+    #[test]
+    fn invalid_filters() {
+        assert!(compile("tcp port").is_err());
+        assert!(compile("host 300.1.1.1").is_err());
+        assert!(compile("(tcp").is_err());
+        assert!(compile("tcp tcp").is_err());
+        assert!(Program::new(vec![Insn::JumpEq(0, 0, 1), Insn::Return(0)]).is_err());
+        assert!(Program::new(vec![Insn::LoadLength]).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Memory accounting for stateful subsystems.
+//!
+//! Long-running live captures must not grow without bound, so stateful
+//! subsystems charge the memory they hold to a `MemoryBudget` and evict
+//! their oldest state when it is exceeded:
+//!
+//!  * `flow::FlowTable` forgets the least recently seen flows,
+//!  * `pdu::PduExtractor` drops the least recently seen TCP streams,
+//!  * `ip::sctp::Reassembler` and `can::Reassembler` drop partial messages,
+//!    and
+//!  * `ttl::TtlAnalysis` forgets the least recently seen hosts.
+//!
+//! These subsystems are taps, which see packets only after they have been
+//! dissected, so what they drop can't be reported as expert info in the
+//! packets concerned. Instead, each keeps an expert note (see `note`) of
+//! every eviction of a flow, partial stream or partial message, alongside a
+//! count of them. Clones of a budget share the same accounting, so a single
+//! limit can be applied across several subsystems.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ErrorCode;
+use expert::{Info, Severity};
+
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    /// A budget allowing `limit` bytes to be held across all subsystems.
+    pub fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget { limit: limit, used: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    pub fn unlimited() -> MemoryBudget {
+        MemoryBudget::new(::std::usize::MAX)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Bytes currently charged to this budget.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.used() > self.limit
+    }
+
+    pub fn charge(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// A warning that state about a `layer`, e.g., a TCP stream, was evicted to
+/// stay within a budget.
+pub fn note<S: Into<String>>(layer: &'static str, message: S) -> Info {
+    Info {
+        layer: layer,
+        field: None,
+        code: ErrorCode::BudgetExceeded.name(),
+        severity: Severity::Warn,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shared_accounting() {
+        let budget = MemoryBudget::new(100);
+        let other = budget.clone();
+
+        budget.charge(60);
+        other.charge(60);
+        assert_eq!(budget.used(), 120);
+        assert!(budget.is_exceeded());
+
+        other.release(60);
+        assert!(!budget.is_exceeded());
+        assert!(!MemoryBudget::unlimited().is_exceeded());
+    }
+}
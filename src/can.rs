@@ -0,0 +1,412 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Controller Area Network (CAN) frames as captured by Linux
+//! SocketCAN (LINKTYPE_CAN_SOCKETCAN), and reassembly of the ISO-TP
+//! (ISO 15765-2) messages carried over them, e.g., UDS diagnostics.
+//!
+//! CAN frames carry at most 8 B (64 B for CAN FD), so longer messages are
+//! segmented by ISO-TP into a First Frame, which gives the message length,
+//! and Consecutive Frames numbered modulo 16. Nothing in a CAN frame says
+//! whether it carries ISO-TP, so frames are only decoded as ISO-TP by
+//! `dissect_isotp` or by a `Reassembler` tap.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::can::Reassembler;
+//! use rshark::tap::Taps;
+//!
+//! // A UDS response split into a First Frame and a Consecutive Frame
+//! let first = [0, 0, 0x07, 0xe8, 8, 0, 0, 0, 0x10, 0x0a, 0x62, 0xf1, 0x90, b'W', b'0', b'L'];
+//! let consecutive = [0, 0, 0x07, 0xe8, 5, 0, 0, 0, 0x21, b'0', b'0', b'0', b'4'];
+//!
+//! let mut reassembler = Reassembler::new();
+//! {
+//!     let mut taps = Taps::new();
+//!     taps.register("CAN", &mut reassembler);
+//!     for data in &[&first[..], &consecutive[..]] {
+//!         let frame = rshark::can::dissect(data).unwrap();
+//!         taps.dispatch(Duration::new(0, 0), data.len(), &frame);
+//!     }
+//! }
+//!
+//! let messages = reassembler.messages();
+//! assert_eq!(messages[0].identifier, 0x7e8);
+//! assert_eq!(&messages[0].data[..], b"\x62\xf1\x90W0L0004");
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use read_be_u16;
+use read_be_u32;
+use budget::{self, MemoryBudget};
+use expert::Info;
+use tap::{PacketInfo, Tap};
+
+/// Flags in the top bits of the CAN identifier
+const EXTENDED: u32 = 0x8000_0000;
+const REMOTE: u32 = 0x4000_0000;
+const ERROR: u32 = 0x2000_0000;
+
+enum_map!(pub ISOTP_FRAME_TYPES {
+    0 => "Single Frame",
+    1 => "First Frame",
+    2 => "Consecutive Frame",
+    3 => "Flow Control",
+});
+
+enum_map!(pub FLOW_STATUSES {
+    0 => "Continue To Send",
+    1 => "Wait",
+    2 => "Overflow",
+});
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "A SocketCAN header must be 8 B".to_string() });
+    }
+
+    let id = read_be_u32(data, 0)?;
+    let length = data[4] as usize;
+    if 8 + length > data.len() {
+        return Err(DissectError::Underflow { expected: Some(8 + length), have: data.len(),
+            message: format!["CAN frame length {} overruns the capture", length] });
+    }
+
+    let mut values = NamedValues::new();
+    let identifier = if id & EXTENDED != 0 { id & 0x1fff_ffff } else { id & 0x7ff };
+    values.push(("Identifier", Val::Unsigned(identifier as u64)));
+    values.push(("Frame Flags", Val::BitFlags8((id >> 29) as u8, [
+                 Some("Error"), Some("Remote Transmission Request"), Some("Extended Frame Format"),
+                 None, None, None, None, None])));
+    values.push(("Length", Val::Unsigned(length as u64)));
+    if data[5] != 0 {
+        values.push(("FD Flags", Val::BitFlags8(data[5], [
+                     Some("Bit Rate Switch"), Some("Error State Indicator"), Some("FD Frame"),
+                     None, None, None, None, None])));
+    }
+
+    // Error frames give the error class in the identifier.
+    if id & ERROR != 0 {
+        values.push(("Error Class", Val::Unsigned((id & 0x1fff_ffff) as u64)));
+    }
+
+    values.push(("Data", Val::Bytes(&data[8..8 + length])));
+
+    Ok(Box::new(Val::Object("CAN", values)))
+}
+
+/// The protocol control information at the start of an ISO-TP frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pci {
+    Single { length: usize, offset: usize },
+    First { length: usize, offset: usize },
+    Consecutive { sequence: u8 },
+    FlowControl { status: u8, block_size: u8, separation_time: u8 },
+}
+
+impl Pci {
+    fn parse(data: &[u8]) -> Result<Pci, DissectError> {
+        let underflow = |expected| DissectError::Underflow { expected: Some(expected),
+            have: data.len(), message: "ISO-TP frame is truncated".to_string() };
+
+        let first = *data.first().ok_or_else(|| underflow(1))?;
+        let pci = match first >> 4 {
+            // Lengths that don't fit in a nibble (CAN FD) or 12 bits are escaped.
+            0 if first & 0x0f != 0 => Pci::Single { length: (first & 0x0f) as usize, offset: 1 },
+            0 => Pci::Single { length: *data.get(1).ok_or_else(|| underflow(2))? as usize, offset: 2 },
+            1 => match read_be_u16(data, 0)? & 0x0fff {
+                0 => Pci::First { length: read_be_u32(data, 2)? as usize, offset: 6 },
+                length => Pci::First { length: length as usize, offset: 2 },
+            },
+            2 => Pci::Consecutive { sequence: first & 0x0f },
+            3 if data.len() < 3 => return Err(underflow(3)),
+            3 => Pci::FlowControl { status: first & 0x0f, block_size: data[1],
+                                    separation_time: data[2] },
+            other => return Err(DissectError::InvalidData(
+                format!["ISO-TP frame type {} is invalid", other])),
+        };
+
+        if let Pci::Single { length, offset } = pci {
+            if offset + length > data.len() {
+                return Err(underflow(offset + length));
+            }
+        }
+
+        Ok(pci)
+    }
+}
+
+/// Dissect the data of a CAN frame as an ISO-TP frame.
+pub fn dissect_isotp(data : &[u8]) -> DissectResult {
+    let pci = Pci::parse(data)?;
+
+    let mut values = NamedValues::new();
+    values.push(("Frame Type", ISOTP_FRAME_TYPES.val(data[0] >> 4)));
+
+    match pci {
+        Pci::Single { length, offset } => {
+            values.push(("Message Length", Val::Unsigned(length as u64)));
+            values.push(("Data", Val::Bytes(&data[offset..offset + length])));
+        },
+        Pci::First { length, offset } => {
+            values.push(("Message Length", Val::Unsigned(length as u64)));
+            values.push(("Data", Val::Bytes(&data[offset..])));
+        },
+        Pci::Consecutive { sequence } => {
+            values.push(("Sequence Number", Val::Unsigned(sequence as u64)));
+            values.push(("Data", Val::Bytes(&data[1..])));
+        },
+        Pci::FlowControl { status, block_size, separation_time } => {
+            values.push(("Flow Status", FLOW_STATUSES.val(status)));
+            values.push(("Block Size", Val::Unsigned(block_size as u64)));
+            values.push(("Separation Time", Val::Unsigned(separation_time as u64)));
+        },
+    }
+
+    Ok(Box::new(Val::Object("ISO-TP", values)))
+}
+
+/// A reassembled ISO-TP message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    /// The CAN identifier the message was sent with.
+    pub identifier: u32,
+    pub data: Vec<u8>,
+
+    /// Packet number of the final frame.
+    pub number: u64,
+}
+
+/// A message whose Consecutive Frames are still arriving.
+struct Transfer {
+    length: usize,
+    next_sequence: u8,
+    data: Vec<u8>,
+
+    /// Number of the latest frame, for LRU eviction.
+    last_seen: u64,
+}
+
+/// A tap that reassembles ISO-TP messages from CAN frames, per identifier.
+///
+/// Every data frame is assumed to carry ISO-TP; frames that can't be parsed
+/// as ISO-TP are ignored. The data of unfinished transfers is charged to a
+/// `MemoryBudget`, and the least recently active transfers are abandoned
+/// when it is exceeded.
+pub struct Reassembler {
+    transfers: HashMap<u32, Transfer>,
+    messages: Vec<Message>,
+    aborted: u64,
+
+    /// Transfers by the number of their latest frame.
+    lru: BTreeMap<u64, u32>,
+    budget: MemoryBudget,
+    evictions: u64,
+    notes: Vec<Info>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::with_budget(MemoryBudget::unlimited())
+    }
+
+    pub fn with_budget(budget: MemoryBudget) -> Reassembler {
+        Reassembler {
+            transfers: HashMap::new(),
+            messages: Vec::new(),
+            aborted: 0,
+            lru: BTreeMap::new(),
+            budget: budget,
+            evictions: 0,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Messages reassembled so far, in the order they were completed.
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Transfers abandoned because of a missing Consecutive Frame or a new
+    /// First Frame on the same identifier.
+    pub fn aborted(&self) -> u64 {
+        self.aborted
+    }
+
+    /// Transfers abandoned to stay within the memory budget.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Expert notes about the transfers abandoned to stay within the memory
+    /// budget, oldest first.
+    pub fn notes(&self) -> &[Info] {
+        &self.notes
+    }
+
+    /// Take the expert notes about the transfers abandoned so far.
+    pub fn take_notes(&mut self) -> Vec<Info> {
+        self.notes.split_off(0)
+    }
+
+    /// Account for the data of one CAN frame.
+    pub fn frame(&mut self, identifier: u32, number: u64, data: &[u8]) {
+        match Pci::parse(data) {
+            Ok(Pci::Single { length, offset }) => {
+                let data = data[offset..offset + length].to_vec();
+                self.messages.push(Message { identifier: identifier, data: data, number: number });
+            },
+            Ok(Pci::First { length, offset }) => {
+                if self.remove(identifier).is_some() {
+                    self.aborted += 1;
+                }
+
+                self.budget.charge(data.len() - offset);
+                self.lru.insert(number, identifier);
+                self.transfers.insert(identifier, Transfer {
+                    length: length,
+                    next_sequence: 1,
+                    data: data[offset..].to_vec(),
+                    last_seen: number,
+                });
+            },
+            Ok(Pci::Consecutive { sequence }) => {
+                let complete = match self.transfers.get_mut(&identifier) {
+                    Some(ref mut t) if t.next_sequence == sequence => {
+                        t.data.extend_from_slice(&data[1..]);
+                        t.next_sequence = (sequence + 1) & 0x0f;
+                        self.budget.charge(data.len() - 1);
+
+                        self.lru.remove(&t.last_seen);
+                        self.lru.insert(number, identifier);
+                        t.last_seen = number;
+
+                        t.data.len() >= t.length
+                    },
+                    Some(_) => {
+                        self.remove(identifier);
+                        self.aborted += 1;
+                        false
+                    },
+                    None => false,
+                };
+
+                if complete {
+                    let mut transfer = self.remove(identifier).unwrap();
+                    transfer.data.truncate(transfer.length);
+                    self.messages.push(Message {
+                        identifier: identifier, data: transfer.data, number: number,
+                    });
+                }
+            },
+            Ok(Pci::FlowControl { .. }) | Err(_) => {},
+        }
+
+        while self.budget.is_exceeded() && !self.lru.is_empty() {
+            let oldest = *self.lru.values().next().unwrap();
+            let held = self.remove(oldest).map(|t| t.data.len()).unwrap_or(0);
+            self.evictions += 1;
+            self.notes.push(budget::note("ISO-TP",
+                format!["transfer on identifier {:#x} abandoned with {} B to stay within the \
+                         memory budget", oldest, held]));
+        }
+    }
+
+    /// Forget a transfer, releasing its data.
+    fn remove(&mut self, identifier: u32) -> Option<Transfer> {
+        let transfer = self.transfers.remove(&identifier)?;
+        self.lru.remove(&transfer.last_seen);
+        self.budget.release(transfer.data.len());
+        Some(transfer)
+    }
+}
+
+impl Drop for Reassembler {
+    fn drop(&mut self) {
+        self.budget.release(self.transfers.values().map(|t| t.data.len()).sum());
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Reassembler {
+        Reassembler::new()
+    }
+}
+
+impl Tap for Reassembler {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        let (identifier, flags, data) = match (layer.get("Identifier"), layer.get("Frame Flags"),
+                                               layer.get("Data")) {
+            (Ok(&Val::Unsigned(id)), Ok(&Val::BitFlags8(flags, _)), Ok(&Val::Bytes(data))) =>
+                (id as u32, (flags as u32) << 29, data),
+            _ => return,
+        };
+
+        if flags & (REMOTE | ERROR) == 0 {
+            self.frame(identifier, info.number, data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_frames() {
+        // An extended-format frame with a UDS request in an ISO-TP Single Frame
+        let data = [0x98, 0xda, 0x10, 0xf1, 3, 0, 0, 0, 0x02, 0x10, 0x03];
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Identifier"].as_unsigned().unwrap(), 0x18da10f1);
+        assert!(val["Frame Flags"].as_bitflags8_bit_name("Extended Frame Format").unwrap());
+        assert_eq!(val["Data"].as_bytes().unwrap(), &[0x02, 0x10, 0x03]);
+        assert!(dissect(&data[..10]).is_err());
+
+        let isotp = *dissect_isotp(&data[8..]).unwrap();
+        assert_eq!(isotp["Frame Type"].as_symbol().unwrap(), "Single Frame");
+        assert_eq!(isotp["Data"].as_bytes().unwrap(), &[0x10, 0x03]);
+
+        let flow = *dissect_isotp(&[0x30, 0, 20]).unwrap();
+        assert_eq!(flow["Flow Status"].as_symbol().unwrap(), "Continue To Send");
+        assert!(dissect_isotp(&[0x07, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn abort_out_of_sequence() {
+        let mut reassembler = Reassembler::new();
+        reassembler.frame(0x7e0, 1, &[0x10, 0x09, 1, 2, 3, 4, 5, 6]);
+        reassembler.frame(0x7e0, 2, &[0x22, 7, 8, 9]);
+        reassembler.frame(0x7e0, 3, &[0x21, 7, 8, 9]);
+        reassembler.frame(0x7df, 4, &[0x02, 0x3e, 0x00]);
+
+        assert_eq!(reassembler.aborted(), 1);
+        assert_eq!(reassembler.messages(), &[Message { identifier: 0x7df, data: vec![0x3e, 0], number: 4 }]);
+
+        // Only one unfinished transfer fits in the budget.
+        let budget = MemoryBudget::new(10);
+        let mut reassembler = Reassembler::with_budget(budget.clone());
+        reassembler.frame(0x7e0, 1, &[0x10, 0x09, 1, 2, 3, 4, 5, 6]);
+        reassembler.frame(0x7e8, 2, &[0x10, 0x09, 1, 2, 3, 4, 5, 6]);
+        reassembler.frame(0x7e0, 3, &[0x21, 7, 8, 9]);
+
+        assert_eq!((reassembler.evictions(), budget.used()), (1, 6));
+        assert_eq!(reassembler.notes()[0].to_string(),
+                   "Warn: ISO-TP: transfer on identifier 0x7e0 abandoned with 6 B to stay within \
+                    the memory budget");
+        assert!(reassembler.messages().is_empty());
+        drop(reassembler);
+        assert_eq!(budget.used(), 0);
+    }
+}
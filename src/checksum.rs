@@ -0,0 +1,223 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Verification and recalculation of Internet checksums (IPv4, TCP, UDP).
+//!
+//! Packets sent by the capturing host are often captured before the NIC has
+//! filled in their checksums ("checksum offload"), leaving a zero or a partial
+//! pseudo-header sum in the checksum field. Such checksums are reported as
+//! `Status::Offloaded` rather than `Status::Bad` so that locally-captured
+//! traffic isn't mistaken for corruption.
+//!
+//! See [RFC 1071](https://tools.ietf.org/html/rfc1071).
+
+/// The outcome of verifying a checksum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Good,
+    Bad,
+    /// No checksum was sent (permitted for UDP over IPv4).
+    Absent,
+    /// The checksum is wrong in a way that suggests it was never filled in
+    /// because the sender offloads checksum computation to its NIC.
+    Offloaded,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Status::Good => "Good",
+            &Status::Bad => "Bad",
+            &Status::Absent => "Absent",
+            &Status::Offloaded => "Likely offloaded",
+        }
+    }
+}
+
+/// The (unfolded) ones' complement sum of `data` as 16b big-endian words.
+pub fn sum(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|w| (w[0] as u32) << 8 | *w.get(1).unwrap_or(&0) as u32)
+        .fold(0, |acc, w| acc + w)
+}
+
+/// Fold a 32b sum into 16b with end-around carry.
+pub fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    sum as u16
+}
+
+/// The Internet checksum of `data`.
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(sum(data))
+}
+
+/// Verify an IPv4 header checksum.
+pub fn verify_ipv4_header(header: &[u8]) -> Status {
+    if header.len() >= 12 && header[10] == 0 && header[11] == 0 {
+        Status::Offloaded
+    } else if fold(sum(header)) == 0xffff {
+        Status::Good
+    } else {
+        Status::Bad
+    }
+}
+
+/// Verify the checksum of a complete TCP or UDP segment carried over IPv4.
+pub fn verify_transport(protocol: u8, source: &[u8], destination: &[u8], segment: &[u8])
+    -> Status {
+
+    let offset = match protocol {
+        6 => 16,
+        17 => 6,
+        _ => return Status::Bad,
+    };
+
+    if segment.len() < offset + 2 {
+        return Status::Bad;
+    }
+
+    let stored = (segment[offset] as u16) << 8 | segment[offset + 1] as u16;
+    let pseudo = pseudo_header_sum(protocol, source, destination, segment.len());
+
+    if protocol == 17 && stored == 0 {
+        Status::Absent
+    } else if fold(pseudo + sum(segment)) == 0xffff {
+        Status::Good
+    } else if stored == 0 || stored == fold(pseudo) {
+        Status::Offloaded
+    } else {
+        Status::Bad
+    }
+}
+
+/// Verify the checksum of a DCCP or UDP-Lite segment, which covers the
+/// first `coverage` bytes of the segment (but the whole segment's length in
+/// the pseudo-header). Neither protocol allows the checksum to be omitted.
+pub fn verify_partial(protocol: u8, source: &[u8], destination: &[u8], segment: &[u8],
+                      coverage: usize) -> Status {
+
+    let pseudo = pseudo_header_sum(protocol, source, destination, segment.len());
+    if fold(pseudo + sum(&segment[..coverage.min(segment.len())])) == 0xffff {
+        Status::Good
+    } else {
+        Status::Bad
+    }
+}
+
+/// The checksum to store in a TCP or UDP segment whose checksum field is
+/// zero, e.g., one rebuilt from a compressed form.
+pub fn transport(protocol: u8, source: &[u8], destination: &[u8], segment: &[u8]) -> u16 {
+    !fold(pseudo_header_sum(protocol, source, destination, segment.len()) + sum(segment))
+}
+
+/// Recompute the lengths and checksums of an IPv4 packet and (unless it is a
+/// fragment) the TCP or UDP segment that it carries, in place.
+///
+/// The IPv4 total length and UDP length are set to match the data, which
+/// should therefore not include any link-layer padding.
+pub fn fix_ipv4(packet: &mut [u8]) {
+    if packet.len() < 20 {
+        return;
+    }
+
+    let header_length = 4 * (packet[0] & 0x0f) as usize;
+    if header_length < 20 || header_length > packet.len() {
+        return;
+    }
+
+    let length = packet.len();
+    packet[2] = (length >> 8) as u8;
+    packet[3] = length as u8;
+
+    packet[10] = 0;
+    packet[11] = 0;
+    let header_checksum = checksum(&packet[..header_length]);
+    packet[10] = (header_checksum >> 8) as u8;
+    packet[11] = header_checksum as u8;
+
+    let fragmented = packet[6] & 0x3f != 0 || packet[7] != 0;
+    let protocol = packet[9];
+    let (header, segment) = packet.split_at_mut(header_length);
+    let segment_length = segment.len();
+
+    let offset = match protocol {
+        6 if !fragmented && segment_length >= 20 => 16,
+        17 if !fragmented && segment_length >= 8 => {
+            segment[4] = (segment_length >> 8) as u8;
+            segment[5] = segment_length as u8;
+            6
+        },
+        _ => return,
+    };
+
+    segment[offset] = 0;
+    segment[offset + 1] = 0;
+
+    let pseudo = pseudo_header_sum(protocol, &header[12..16], &header[16..20], segment_length);
+    let mut transport_checksum = !fold(pseudo + sum(segment));
+    if protocol == 17 && transport_checksum == 0 {
+        transport_checksum = 0xffff;
+    }
+
+    segment[offset] = (transport_checksum >> 8) as u8;
+    segment[offset + 1] = transport_checksum as u8;
+}
+
+fn pseudo_header_sum(protocol: u8, source: &[u8], destination: &[u8], length: usize) -> u32 {
+    sum(source) + sum(destination) + protocol as u32 + (length as u32 & 0xffff) + (length as u32 >> 16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UDP: [u8; 32] = [
+        0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        10, 0, 0, 1, 10, 0, 0, 2,
+        0xd4, 0x31, 0x00, 0x35, 0x00, 0x0c, 0x00, 0x00,
+        0xde, 0xad, 0xbe, 0xef];
+
+    #[test]
+    fn rfc1071_example() {
+        assert_eq!(fold(sum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7])), 0xddf2);
+        assert_eq!(checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]), 0x220d);
+    }
+
+    #[test]
+    fn fix_and_verify() {
+        let mut packet = UDP.to_vec();
+        assert_eq!(verify_ipv4_header(&packet[..20]), Status::Offloaded);
+        assert_eq!(verify_transport(17, &packet[12..16], &packet[16..20], &packet[20..]),
+                   Status::Absent);
+
+        fix_ipv4(&mut packet);
+        assert_eq!(verify_ipv4_header(&packet[..20]), Status::Good);
+        assert_eq!(verify_transport(17, &packet[12..16], &packet[16..20], &packet[20..]),
+                   Status::Good);
+
+        packet[31] ^= 0xff;
+        assert_eq!(verify_transport(17, &packet[12..16], &packet[16..20], &packet[20..]),
+                   Status::Bad);
+    }
+
+    #[test]
+    fn partial_checksum_is_offloaded() {
+        let mut packet = UDP.to_vec();
+        let partial = fold(pseudo_header_sum(17, &packet[12..16], &packet[16..20], 12));
+        packet[26] = (partial >> 8) as u8;
+        packet[27] = partial as u8;
+
+        assert_eq!(verify_transport(17, &packet[12..16], &packet[16..20], &packet[20..]),
+                   Status::Offloaded);
+    }
+}
@@ -0,0 +1,360 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Matching of IPv4 and IPv6 addresses against CIDR prefixes, ranges and
+//! lists thereof.
+//!
+//! An `AddressSet` stores prefixes in a binary trie (ranges are split into
+//! the prefixes that cover them), so membership tests take time
+//! proportional to the address length rather than the size of the set.
+//! An `AddressFilter` applies a set to an address field of dissected packets:
+//!
+//! ```
+//! use rshark::cidr::AddressFilter;
+//!
+//! let filter = AddressFilter::parse("ip.Source in 10.0.0.0/8, 192.168.0.0/16").unwrap();
+//! let data = [0; 14];
+//! assert!(!filter.matches(&rshark::ethernet::dissect(&data).unwrap()));
+//! ```
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use DissectError;
+use Val;
+
+/// An address prefix such as `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    pub address: IpAddr,
+    pub length: u8,
+}
+
+impl Cidr {
+    /// The prefix of length `length` that contains `address`.
+    pub fn network(address: &IpAddr, length: u8) -> Cidr {
+        let (a, width) = bits(address);
+        let length = ::std::cmp::min(length as u32, width);
+        let network = a & !mask(width - length);
+
+        Cidr {
+            address: match address {
+                &IpAddr::V4(_) => IpAddr::from((network as u32).to_be_bytes()),
+                &IpAddr::V6(_) => IpAddr::from(network.to_be_bytes()),
+            },
+            length: length as u8,
+        }
+    }
+
+    /// Returns true if `address` is within this prefix.
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (bits(&self.address), bits(address)) {
+            ((a, width), (b, w)) if width == w => {
+                let shift = width - self.length as u32;
+                shift >= 128 || a >> shift == b >> shift
+            },
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}/{}", self.address, self.length]
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = DissectError;
+
+    /// Parse `ADDRESS/LENGTH` or a bare address (a full-length prefix).
+    fn from_str(s: &str) -> Result<Cidr, DissectError> {
+        let mut parts = s.trim().splitn(2, '/');
+        let address = parse_address(parts.next().unwrap_or(""))?;
+        let width = bits(&address).1;
+
+        let length = match parts.next() {
+            Some(l) => match l.parse::<u32>() {
+                Ok(l) if l <= width => l,
+                _ => return Err(DissectError::InvalidData(format!["invalid prefix length in '{}'", s])),
+            },
+            None => width,
+        };
+
+        Ok(Cidr { address: address, length: length as u8 })
+    }
+}
+
+/// A set of addresses, built from prefixes, ranges and individual addresses.
+#[derive(Clone, Debug, Default)]
+pub struct AddressSet {
+    v4: Trie,
+    v6: Trie,
+}
+
+impl AddressSet {
+    pub fn new() -> AddressSet {
+        AddressSet::default()
+    }
+
+    pub fn insert(&mut self, cidr: Cidr) {
+        let (value, width) = bits(&cidr.address);
+        let trie = if width == 32 { &mut self.v4 } else { &mut self.v6 };
+        trie.insert(value, width, cidr.length as u32);
+    }
+
+    /// Insert an inclusive range of addresses of the same family.
+    pub fn insert_range(&mut self, first: IpAddr, last: IpAddr) -> Result<(), DissectError> {
+        let ((mut start, width), (end, w)) = (bits(&first), bits(&last));
+        if width != w || start > end {
+            return Err(DissectError::InvalidData(
+                format!["invalid address range {}-{}", first, last]));
+        }
+
+        // Split the range into the largest aligned prefixes that fit in it.
+        loop {
+            let mut size = if start == 0 { width } else { start.trailing_zeros().min(width) };
+            while size > 0 && end - start < mask(size) {
+                size -= 1;
+            }
+
+            let trie = if width == 32 { &mut self.v4 } else { &mut self.v6 };
+            trie.insert(start, width, width - size);
+
+            let last = start | mask(size);
+            if last >= end {
+                return Ok(());
+            }
+
+            start = last + 1;
+        }
+    }
+
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let (value, width) = bits(address);
+        let trie = if width == 32 { &self.v4 } else { &self.v6 };
+        trie.contains(value, width)
+    }
+}
+
+impl FromStr for AddressSet {
+    type Err = DissectError;
+
+    /// Parse a comma-separated list of prefixes (`10.0.0.0/8`), ranges
+    /// (`10.0.0.1-10.0.0.9`) and addresses.
+    fn from_str(s: &str) -> Result<AddressSet, DissectError> {
+        let mut set = AddressSet::new();
+
+        for item in s.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+            let mut range = item.splitn(2, '-');
+            match (range.next(), range.next()) {
+                (Some(first), Some(last)) =>
+                    set.insert_range(parse_address(first)?, parse_address(last)?)?,
+                _ => set.insert(item.parse()?),
+            }
+        }
+
+        Ok(set)
+    }
+}
+
+/// A test of whether an address field is in a set: `LAYER.FIELD in SET`.
+#[derive(Clone, Debug)]
+pub struct AddressFilter {
+    layer: String,
+    field: String,
+    set: AddressSet,
+}
+
+impl AddressFilter {
+    /// Parse a filter such as `IPv4.Source in 10.0.0.0/8, 192.168.0.0/16`.
+    /// The layer may also be given by the lower-case aliases `ip`, `tcp`
+    /// and `udp`.
+    pub fn parse(filter: &str) -> Result<AddressFilter, DissectError> {
+        let mut parts = filter.splitn(2, " in ");
+        let (path, set) = match (parts.next(), parts.next()) {
+            (Some(path), Some(set)) => (path.trim(), set),
+            _ => return Err(DissectError::InvalidData(
+                format!["expected 'FIELD in ADDRESSES', not '{}'", filter])),
+        };
+
+        let mut path = path.splitn(2, '.');
+        let (layer, field) = match (path.next(), path.next()) {
+            (Some(layer), Some(field)) => (layer, field),
+            _ => return Err(DissectError::InvalidData(
+                format!["expected a LAYER.FIELD path in '{}'", filter])),
+        };
+
+        let layer = match layer {
+            "ip" => "IPv4",
+            "tcp" => "TCP",
+            "udp" => "UDP",
+            l => l,
+        };
+
+        Ok(AddressFilter {
+            layer: layer.to_string(),
+            field: field.to_string(),
+            set: set.parse()?,
+        })
+    }
+
+    /// Returns true if the packet has the field and its address is in the set.
+    pub fn matches(&self, packet: &Val) -> bool {
+        packet.layer(&self.layer)
+            .and_then(|layer| layer.lookup(&self.field))
+            .and_then(|field| field.as_address_bytes())
+            .and_then(ip_addr)
+            .map(|address| self.set.contains(&address))
+            .unwrap_or(false)
+    }
+}
+
+/// A binary trie of prefixes.
+#[derive(Clone, Debug, Default)]
+struct Trie {
+    /// Child indices for 0 and 1 bits, and whether a prefix ends here
+    nodes: Vec<([Option<usize>; 2], bool)>,
+}
+
+impl Trie {
+    fn insert(&mut self, value: u128, width: u32, length: u32) {
+        if self.nodes.is_empty() {
+            self.nodes.push(([None, None], false));
+        }
+
+        let mut node = 0;
+        for i in 0..length {
+            if self.nodes[node].1 {
+                return;     // already covered by a shorter prefix
+            }
+
+            let bit = (value >> (width - 1 - i)) as usize & 1;
+            node = match self.nodes[node].0[bit] {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(([None, None], false));
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].0[bit] = Some(child);
+                    child
+                },
+            };
+        }
+
+        self.nodes[node].1 = true;
+    }
+
+    fn contains(&self, value: u128, width: u32) -> bool {
+        let mut node = match self.nodes.first() {
+            Some(_) => 0,
+            None => return false,
+        };
+
+        for i in 0..width {
+            if self.nodes[node].1 {
+                return true;
+            }
+
+            let bit = (value >> (width - 1 - i)) as usize & 1;
+            node = match self.nodes[node].0[bit] {
+                Some(child) => child,
+                None => return false,
+            };
+        }
+
+        self.nodes[node].1
+    }
+}
+
+fn parse_address(s: &str) -> Result<IpAddr, DissectError> {
+    s.trim().parse()
+        .map_err(|_| DissectError::InvalidData(format!["invalid IP address '{}'", s.trim()]))
+}
+
+/// An address as an integer, along with its width in bits.
+fn bits(address: &IpAddr) -> (u128, u32) {
+    match address {
+        &IpAddr::V4(a) => (u32::from(a) as u128, 32),
+        &IpAddr::V6(a) => (u128::from(a), 128),
+    }
+}
+
+/// The low `n` bits set.
+fn mask(n: u32) -> u128 {
+    if n >= 128 { !0 } else { (1 << n) - 1 }
+}
+
+fn ip_addr(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::from([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::IpAddr;
+    use ethernet;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn address_sets() {
+        let set: AddressSet = "10.0.0.0/8, 192.168.1.10-192.168.1.20, 172.16.0.1, 2001:db8::/32"
+            .parse().unwrap();
+
+        assert!(set.contains(&ip("10.200.3.4")));
+        assert!(!set.contains(&ip("11.0.0.0")));
+        assert!(set.contains(&ip("192.168.1.10")));
+        assert!(set.contains(&ip("192.168.1.15")));
+        assert!(set.contains(&ip("192.168.1.20")));
+        assert!(!set.contains(&ip("192.168.1.21")));
+        assert!(!set.contains(&ip("192.168.1.9")));
+        assert!(set.contains(&ip("172.16.0.1")));
+        assert!(!set.contains(&ip("172.16.0.2")));
+        assert!(set.contains(&ip("2001:db8::1")));
+        assert!(!set.contains(&ip("2001:db9::1")));
+
+        let everything: AddressSet = "0.0.0.0-255.255.255.255".parse().unwrap();
+        assert!(everything.contains(&ip("1.2.3.4")));
+
+        assert!("10.0.0.0/33".parse::<AddressSet>().is_err());
+        assert!("10.0.0.9-10.0.0.1".parse::<AddressSet>().is_err());
+        assert!("10.0.0.1-::1".parse::<AddressSet>().is_err());
+        assert!(Cidr { address: ip("10.0.0.0"), length: 0 }.contains(&ip("99.0.0.1")));
+        assert_eq!(Cidr::network(&ip("192.168.7.42"), 24).to_string(), "192.168.7.0/24");
+        assert_eq!(Cidr::network(&ip("2001:db8::1:2"), 64).to_string(), "2001:db8::/64");
+    }
+
+    #[test]
+    fn filter_packets() {
+        let data = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+            10, 0, 0, 1, 192, 168, 7, 2,
+            0xd4, 0x31, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+        let packet = ethernet::dissect(&data).unwrap();
+
+        let matches = |f| AddressFilter::parse(f).unwrap().matches(&packet);
+        assert!(matches("ip.Source in 10.0.0.0/8, 192.168.0.0/16"));
+        assert!(matches("IPv4.Destination in 192.168.7.0/24"));
+        assert!(!matches("ip.Destination in 10.0.0.0/8"));
+        assert!(!matches("ip.Missing in 10.0.0.0/8"));
+        assert!(AddressFilter::parse("ip.Source 10.0.0.0/8").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Protocol conformance warnings.
+//!
+//! A packet that violates its specification (e.g., a TCP header with a data
+//! offset of less than five words) is still worth dissecting: malformed
+//! packets are often the most interesting ones. Rather than failing, a
+//! dissector that finds such a violation records a "Conformance Warning"
+//! field in the layer concerned and carries on as best it can.
+//!
+//! ```
+//! use rshark::conformance;
+//!
+//! // An IPv4 header that claims to be version 5
+//! let data = [0x55, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+//! let packet = rshark::ip::dissect(&data).unwrap();
+//!
+//! let warnings = conformance::warnings(&packet);
+//! assert_eq!(warnings.len(), 1);
+//! assert_eq!(warnings[0].layer, "IPv4");
+//! assert_eq!(warnings[0].message, "IP version 5 in an IPv4 header");
+//! ```
+
+use std::fmt;
+
+use Val;
+use NamedValues;
+
+/// The key of conformance warning fields.
+pub const WARNING: &'static str = "Conformance Warning";
+
+/// A specification violation found in a dissected packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    /// The layer (object) that the violation was found in.
+    pub layer: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}: {}", self.layer, self.message]
+    }
+}
+
+/// Record a conformance warning in a layer's values.
+pub fn warn<S: Into<String>>(values: &mut NamedValues, message: S) {
+    values.push((WARNING, Val::String(message.into())));
+}
+
+/// All of the conformance warnings within a dissection, outermost first.
+pub fn warnings(val: &Val) -> Vec<Warning> {
+    let mut found = Vec::new();
+    collect(val, &mut found);
+    found
+}
+
+fn collect(val: &Val, found: &mut Vec<Warning>) {
+    match val {
+        &Val::Object(name, ref values) => {
+            for &(key, ref v) in values {
+                match v {
+                    &Val::String(ref message) if key == WARNING =>
+                        found.push(Warning { layer: name, message: message.clone() }),
+                    _ => collect(v, found),
+                }
+            }
+        },
+        &Val::List(ref values) => for v in values {
+            collect(v, found);
+        },
+        &Val::Payload(_, Ok(ref inner)) => collect(inner, found),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ip;
+
+    #[test]
+    fn tcp_and_tls_warnings() {
+        // A TCP header with a data offset of 4, carrying an oversized TLS
+        // handshake record
+        let mut data = vec![0x45, 0, 0, 48, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                            0xc0, 0x00, 0x01, 0xbb, 0, 0, 0, 1, 0, 0, 0, 0, 0x40, 0x18, 0xff, 0xff,
+                            0, 0, 0, 0];
+        data.extend_from_slice(&[22, 3, 3, 0x40, 0x01, 1, 0, 0, 0]);
+
+        let packet = ip::dissect(&data).unwrap();
+        let warnings = warnings(&packet);
+
+        assert_eq!(warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(), vec![
+            "TCP: TCP data offset 4 is less than the minimum of 5",
+            "TLS Record: TLS record length 16385 exceeds 16384 B",
+        ]);
+    }
+}
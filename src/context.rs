@@ -0,0 +1,362 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection context: state that outlives a single call to a dissector.
+//!
+//! Most dissectors are pure functions of their bytes (`Dissector`), but some
+//! protocols can't be dissected that way: NetFlow v9 data records need the
+//! templates sent earlier by the same exporter, HPACK header blocks refer
+//! to a table built up over a connection and TCP analysis compares each
+//! segment with the ones before it. A `ContextDissector` is also given a
+//! `Context`, which carries:
+//!
+//!  * metadata about the packet being dissected (`Frame`),
+//!  * session-wide state (`state`) and per-conversation state
+//!    (`conversation`), of whatever type each protocol needs,
+//!  * typed preferences (see `preferences`), starting with those of the
+//!    built-in dissectors, and
+//!  * the depth of nested dissection, so that packets that encapsulate
+//!    themselves over and over can't exhaust the stack.
+//!
+//! Link layers, IPv4, IPv6, TCP, UDP and the tunnels that carry them (e.g.,
+//! GRE, MPLS and VXLAN) are dissected with a context (see, e.g.,
+//! `ethernet::dissect_with`), which they pass on to the dissectors of their
+//! payloads, whether bound in the `registry` or called directly. Only their
+//! plain `dissect` functions, for dissecting a packet from the outside, use
+//! a fresh context for each packet.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::{DissectResult, Val};
+//! use rshark::context::Context;
+//!
+//! /// Numbers each message of a conversation, identified by its first byte.
+//! fn counted<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+//!     let count = context.conversation::<u8, u64>(data[0]);
+//!     *count += 1;
+//!     Ok(Box::new(Val::Object("Counted", vec![("Message", Val::Unsigned(*count))])))
+//! }
+//!
+//! let mut context = Context::new();
+//! for data in &[[1], [2], [1]] {
+//!     context.next_frame(Duration::new(0, 0), 0);
+//!     let val = context.dissect(counted, data).unwrap();
+//!     println!["frame {}: {}", context.frame().number, val];
+//! }
+//!
+//! assert_eq!(context.frame().number, 3);
+//! assert_eq!(*context.conversation::<u8, u64>(1), 2);
+//! ```
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use Dissector;
+use DissectError;
+use DissectResult;
+use keys::StaticKeys;
+use preferences::{Preferences, Value};
+use preset;
+
+/// How deeply dissectors may be nested by default.
+pub const MAX_DEPTH: usize = 32;
+
+/// Type of dissection functions that need a `Context`.
+pub type ContextDissector<'data> = fn(&'data [u8], &mut Context) -> DissectResult<'data>;
+
+/// Metadata about the packet being dissected.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Frame {
+    /// Sequence number of the packet, starting at 1 (0 before the first).
+    pub number: u64,
+
+    /// Capture time, since the Unix epoch.
+    pub timestamp: Duration,
+
+    /// libpcap link type (DLT) of the capture.
+    pub link_type: u32,
+}
+
+/// State threaded through dissectors, across the packets of a capture.
+pub struct Context {
+    frame: Frame,
+    preferences: Preferences,
+    state: HashMap<TypeId, Box<dyn Any>>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            frame: Frame::default(),
+            preferences: Preferences::builtin(),
+            state: HashMap::new(),
+            depth: 0,
+            max_depth: MAX_DEPTH,
+        }
+    }
+
+    /// Limit how deeply dissectors may be nested.
+    pub fn max_depth(mut self, depth: usize) -> Context {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Use (declared and perhaps loaded) preferences. These replace the
+    /// built-in ones, so they should usually start from
+    /// `Preferences::builtin()`.
+    pub fn with_preferences(mut self, preferences: Preferences) -> Context {
+        self.preferences = preferences;
+        self
+    }
+
+    /// The packet being dissected.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Move on to the next packet of the capture.
+    pub fn next_frame(&mut self, timestamp: Duration, link_type: u32) -> &Frame {
+        self.frame = Frame { number: self.frame.number + 1, timestamp: timestamp,
+                             link_type: link_type };
+        self.depth = 0;
+        &self.frame
+    }
+
+    /// How many dissectors are currently nested within one another.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Dissect (part of) a packet with a dissector that needs this context:
+    /// a `ContextDissector` or a closure that captures whatever else it needs.
+    ///
+    /// Fails without calling the dissector if dissectors are already nested
+    /// `max_depth` deep.
+    pub fn dissect<'data, D>(&mut self, dissector: D, data: &'data [u8]) -> DissectResult<'data>
+        where D: FnOnce(&'data [u8], &mut Context) -> DissectResult<'data> {
+
+        let mut nested = self.nest()?;
+        dissector(data, &mut nested)
+    }
+
+    /// Dissect (part of) a packet with a dissector that doesn't need this
+    /// context, still within the limit on nesting.
+    pub fn dissect_stateless<'data>(&mut self, dissector: Dissector<'data>, data: &'data [u8])
+        -> DissectResult<'data> {
+
+        let _nested = self.nest()?;
+        dissector(data)
+    }
+
+    /// Move on to the next packet and dissect it according to its link type
+    /// (see `preset::dissect_with`).
+    pub fn dissect_frame<'data>(&mut self, timestamp: Duration, link_type: u32,
+                                data: &'data [u8]) -> DissectResult<'data> {
+        self.next_frame(timestamp, link_type);
+        preset::dissect_with(link_type, data, self)
+    }
+
+    /// Go one level deeper, until the guard is dropped (even by a panic).
+    fn nest(&mut self) -> Result<Nested<'_>, DissectError> {
+        if self.depth >= self.max_depth {
+            return Err(DissectError::InvalidData(
+                format!["dissectors nested more than {} deep", self.max_depth]));
+        }
+
+        self.depth += 1;
+        Ok(Nested(self))
+    }
+
+    pub fn preferences(&self) -> &Preferences {
+        &self.preferences
+    }
+
+    /// The preferences, e.g., for dissectors to declare theirs.
+    pub fn preferences_mut(&mut self) -> &mut Preferences {
+        &mut self.preferences
+    }
+
+    /// The value of a declared preference (or its default).
+    pub fn preference(&self, name: &str) -> Option<&Value> {
+        self.preferences.get(name)
+    }
+
+    /// The TLS secrets in the key log named by the `tls.keylog_file`
+    /// preference (if any), read on first use and again whenever the
+    /// preference changes.
+    pub fn tls_keys(&mut self) -> Result<Option<&StaticKeys>, DissectError> {
+        let path = match self.preferences.path("tls.keylog_file") {
+            Some(path) if path != Path::new("") => path.to_path_buf(),
+            _ => return Ok(None),
+        };
+
+        let log = self.state::<KeyLog>();
+        if log.path != path {
+            log.keys = StaticKeys::open(&path)?;
+            log.path = path;
+        }
+
+        Ok(Some(&log.keys))
+    }
+
+    /// Session-wide state of some type, created on first use.
+    pub fn state<T: Any + Default>(&mut self) -> &mut T {
+        self.state.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .downcast_mut::<T>()
+            .expect("state stored under another type's TypeId")
+    }
+
+    /// The state of one conversation, created on first use.
+    ///
+    /// Conversations are identified by keys of the protocol's choosing, e.g.,
+    /// a `flow::FlowKey` (in a canonical direction) or a NetFlow exporter's
+    /// address and source ID. State of each type is kept separately.
+    pub fn conversation<K, T>(&mut self, key: K) -> &mut T
+        where K: Any + Eq + Hash, T: Any + Default {
+
+        self.state::<HashMap<K, T>>().entry(key).or_insert_with(T::default)
+    }
+
+    /// Forget all session and conversation state (but not preferences),
+    /// e.g., before dissecting a capture again from the start.
+    pub fn reset(&mut self) {
+        self.frame = Frame::default();
+        self.state.clear();
+        self.depth = 0;
+    }
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context::new()
+    }
+}
+
+/// A context one level deeper, restored when dropped.
+struct Nested<'c>(&'c mut Context);
+
+impl<'c> Deref for Nested<'c> {
+    type Target = Context;
+
+    fn deref(&self) -> &Context {
+        self.0
+    }
+}
+
+impl<'c> DerefMut for Nested<'c> {
+    fn deref_mut(&mut self) -> &mut Context {
+        self.0
+    }
+}
+
+impl<'c> Drop for Nested<'c> {
+    fn drop(&mut self) {
+        self.0.depth -= 1;
+    }
+}
+
+/// A TLS key log, as last read (see `Context::tls_keys`).
+#[derive(Default)]
+struct KeyLog {
+    path: PathBuf,
+    keys: StaticKeys,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic;
+    use Val;
+
+    /// Dissects the rest of the data with itself, one byte at a time.
+    fn nested<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+        if data.is_empty() {
+            return Ok(Box::new(Val::Unsigned(context.depth() as u64)));
+        }
+        context.dissect(nested, &data[1..])
+    }
+
+    fn panicking<'data>(_: &'data [u8], _: &mut Context) -> DissectResult<'data> {
+        panic!["dissector bug"]
+    }
+
+    #[test]
+    fn limit_depth() {
+        let mut context = Context::new().max_depth(4);
+        assert_eq!(context.dissect(nested, &[0; 3]), Ok(Box::new(Val::Unsigned(4))));
+        assert!(context.dissect(nested, &[0; 4]).is_err());
+        assert_eq!(context.depth(), 0);
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            context.dissect(panicking, &[])
+        }));
+        assert!(result.is_err());
+        assert_eq!(context.depth(), 0);
+
+        // Ethernet and IPv4, but not TCP
+        let frame = [0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 2, 0x08, 0x00,
+                     0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                     0x04, 0xd2, 0x00, 0x50, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff,
+                     0, 0, 0, 0];
+        let mut context = Context::new().max_depth(2);
+        let val = context.dissect(::ethernet::dissect_with, &frame).unwrap();
+        assert!(val.layer("IPv4").is_some());
+        assert!(val.lookup("Payload.Payload").unwrap().as_payload().unwrap().is_err());
+        assert_eq!(context.depth(), 0);
+    }
+
+    #[test]
+    fn keep_state() {
+        let mut context = Context::new();
+        *context.conversation::<&str, u32>("a") += 1;
+        *context.conversation::<&str, u32>("a") += 1;
+        *context.conversation::<&str, u32>("b") += 1;
+        context.conversation::<&str, String>("a").push_str("x");
+        context.preferences_mut().declare("tcp.analyze_sequence_numbers",
+                                          "Analyze TCP sequence numbers", Value::Bool(true));
+        context.preferences_mut().set_str("tcp.analyze_sequence_numbers", "false").unwrap();
+
+        assert_eq!(*context.conversation::<&str, u32>("a"), 2);
+        assert_eq!(context.conversation::<&str, String>("a"), "x");
+
+        context.next_frame(Duration::new(1, 0), 1);
+        context.reset();
+        assert_eq!(context.frame().number, 0);
+        assert_eq!(*context.conversation::<&str, u32>("a"), 0);
+        assert_eq!(context.preference("tcp.analyze_sequence_numbers"), Some(&Value::Bool(false)));
+    }
+
+    #[test]
+    fn read_key_log() {
+        use keys::{KeyId, KeyProvider};
+        use std::fs;
+
+        let mut context = Context::new();
+        assert!(context.tls_keys().unwrap().is_none());
+
+        let path = ::std::env::temp_dir().join(format!["rshark-keylog-{}", ::std::process::id()]);
+        fs::write(&path, "CLIENT_RANDOM 0102 a0a1\n").unwrap();
+        context.preferences_mut().set("tls.keylog_file", Value::Path(path.clone())).unwrap();
+
+        let id = KeyId::Tls { label: "CLIENT_RANDOM".to_string(), client_random: vec![1, 2] };
+        assert_eq!(context.tls_keys().unwrap().unwrap().key(&id), Some(vec![0xa0, 0xa1]));
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(context.tls_keys().unwrap().unwrap().len(), 1);
+        context.preferences_mut().set_str("tls.keylog_file", "/nonexistent/keys.log").unwrap();
+        assert!(context.tls_keys().is_err());
+    }
+}
@@ -0,0 +1,514 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Cryptographic primitives for decrypting captured traffic (only built with
+//! the `crypto` feature).
+//!
+//! These are straightforward implementations of SHA-1, HMAC, PBKDF2, AES-128
+//! and its CBC, CCM and GCM modes, sufficient to recover keys and plaintext from captures made with
+//! known credentials. They are not constant-time and must not be used to
+//! protect anything.
+
+use std::sync::OnceLock;
+
+/// The SHA-1 digest of `data`.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    message.extend_from_slice(&bits.to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1], block[4 * i + 2], block[4 * i + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for i in 0..80 {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(w[i]);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// HMAC-SHA1 (RFC 2104) of `data` under `key`.
+pub fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; 20] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..20].copy_from_slice(&sha1(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>();
+    inner.extend_from_slice(data);
+
+    let mut outer = block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>();
+    outer.extend_from_slice(&sha1(&inner));
+
+    sha1(&outer)
+}
+
+/// PBKDF2 (RFC 2898) with HMAC-SHA1, filling `output` with derived key.
+pub fn pbkdf2_hmac_sha1(password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    for (i, chunk) in output.chunks_mut(20).enumerate() {
+        let mut message = salt.to_vec();
+        message.extend_from_slice(&(i as u32 + 1).to_be_bytes());
+
+        let mut u = hmac_sha1(password, &message);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac_sha1(password, &u);
+            for (t, u) in t.iter_mut().zip(u.iter()) {
+                *t ^= *u;
+            }
+        }
+
+        let n = chunk.len();
+        chunk.copy_from_slice(&t[..n]);
+    }
+}
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+/// AES-128 (FIPS 197).
+pub struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+}
+
+impl Aes128 {
+    pub fn new(key: &[u8; 16]) -> Aes128 {
+        let mut round_keys = [[0u8; 16]; 11];
+        round_keys[0] = *key;
+
+        let mut rcon = 1u8;
+        for round in 1..11 {
+            let previous = round_keys[round - 1];
+            let mut word = [SBOX[previous[13] as usize] ^ rcon, SBOX[previous[14] as usize],
+                            SBOX[previous[15] as usize], SBOX[previous[12] as usize]];
+
+            for i in 0..4 {
+                for j in 0..4 {
+                    word[j] ^= previous[4 * i + j];
+                    round_keys[round][4 * i + j] = word[j];
+                }
+            }
+
+            rcon = xtime(rcon);
+        }
+
+        Aes128 { round_keys: round_keys }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; 16]) {
+        add_round_key(block, &self.round_keys[0]);
+
+        for round in 1..11 {
+            for b in block.iter_mut() {
+                *b = SBOX[*b as usize];
+            }
+
+            // ShiftRows: row r (bytes r, r + 4, ...) rotates left by r.
+            let state = *block;
+            for column in 0..4 {
+                for row in 0..4 {
+                    block[4 * column + row] = state[4 * ((column + row) % 4) + row];
+                }
+            }
+
+            if round < 10 {
+                for column in block.chunks_mut(4) {
+                    let (a0, a1, a2, a3) = (column[0], column[1], column[2], column[3]);
+                    let all = a0 ^ a1 ^ a2 ^ a3;
+                    column[0] ^= all ^ xtime(a0 ^ a1);
+                    column[1] ^= all ^ xtime(a1 ^ a2);
+                    column[2] ^= all ^ xtime(a2 ^ a3);
+                    column[3] ^= all ^ xtime(a3 ^ a0);
+                }
+            }
+
+            add_round_key(block, &self.round_keys[round]);
+        }
+    }
+
+    /// The inverse cipher, which only CBC mode needs.
+    pub fn decrypt_block(&self, block: &mut [u8; 16]) {
+        let inverse = inverse_sbox();
+        add_round_key(block, &self.round_keys[10]);
+
+        for round in (0..10).rev() {
+            // InvShiftRows: row r rotates right by r; then InvSubBytes.
+            let state = *block;
+            for column in 0..4 {
+                for row in 0..4 {
+                    let b = state[4 * ((column + 4 - row) % 4) + row];
+                    block[4 * column + row] = inverse[b as usize];
+                }
+            }
+
+            add_round_key(block, &self.round_keys[round]);
+
+            if round > 0 {
+                for column in block.chunks_mut(4) {
+                    let (a0, a1, a2, a3) = (column[0], column[1], column[2], column[3]);
+                    column[0] = mul(a0, 14) ^ mul(a1, 11) ^ mul(a2, 13) ^ mul(a3, 9);
+                    column[1] = mul(a0, 9) ^ mul(a1, 14) ^ mul(a2, 11) ^ mul(a3, 13);
+                    column[2] = mul(a0, 13) ^ mul(a1, 9) ^ mul(a2, 14) ^ mul(a3, 11);
+                    column[3] = mul(a0, 11) ^ mul(a1, 13) ^ mul(a2, 9) ^ mul(a3, 14);
+                }
+            }
+        }
+    }
+}
+
+fn inverse_sbox() -> &'static [u8; 256] {
+    static INVERSE: OnceLock<[u8; 256]> = OnceLock::new();
+    INVERSE.get_or_init(|| {
+        let mut inverse = [0u8; 256];
+        for (i, s) in SBOX.iter().enumerate() {
+            inverse[*s as usize] = i as u8;
+        }
+        inverse
+    })
+}
+
+fn add_round_key(block: &mut [u8; 16], key: &[u8; 16]) {
+    for (b, k) in block.iter_mut().zip(key.iter()) {
+        *b ^= *k;
+    }
+}
+
+/// Multiplication by x in GF(2^8).
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+/// Multiplication in GF(2^8).
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    product
+}
+
+/// Decrypt an AES-CBC message, which must be a whole number of blocks (any
+/// padding is left for the caller to remove).
+pub fn cbc_decrypt(key: &[u8; 16], iv: &[u8; 16], data: &[u8]) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(16) {
+        return None;
+    }
+
+    let aes = Aes128::new(key);
+    let mut previous = *iv;
+    let mut plaintext = Vec::with_capacity(data.len());
+    for chunk in data.chunks(16) {
+        let mut block = [0u8; 16];
+        block.copy_from_slice(chunk);
+        aes.decrypt_block(&mut block);
+        plaintext.extend(block.iter().zip(previous.iter()).map(|(b, p)| b ^ p));
+        previous.copy_from_slice(chunk);
+    }
+
+    Some(plaintext)
+}
+
+/// Decrypt and authenticate an AES-GCM (NIST SP 800-38D) message with a
+/// 12 B nonce. `tag` may be truncated, e.g., to the 8 B allowed by ESP.
+pub fn gcm_decrypt(key: &[u8; 16], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8], tag: &[u8])
+    -> Option<Vec<u8>> {
+
+    let aes = Aes128::new(key);
+    let expected = gcm_tag(&aes, nonce, aad, ciphertext);
+    if tag.is_empty() || tag.len() > 16 || tag != &expected[..tag.len()] {
+        return None;
+    }
+
+    Some(gcm_ctr(&aes, nonce, ciphertext))
+}
+
+/// Encrypt and authenticate a message with AES-GCM, returning the ciphertext
+/// followed by the 16 B tag.
+pub fn gcm_encrypt(key: &[u8; 16], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let aes = Aes128::new(key);
+    let mut output = gcm_ctr(&aes, nonce, plaintext);
+    let tag = gcm_tag(&aes, nonce, aad, &output);
+    output.extend_from_slice(&tag);
+    output
+}
+
+/// The block for a 32b counter following a 12 B nonce, encrypted.
+fn gcm_counter_block(aes: &Aes128, nonce: &[u8; 12], counter: u32) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..12].copy_from_slice(nonce);
+    block[12..].copy_from_slice(&counter.to_be_bytes());
+    aes.encrypt_block(&mut block);
+    block
+}
+
+fn gcm_ctr(aes: &Aes128, nonce: &[u8; 12], data: &[u8]) -> Vec<u8> {
+    data.chunks(16)
+        .enumerate()
+        .flat_map(|(i, chunk)| {
+            let stream = gcm_counter_block(aes, nonce, i as u32 + 2);
+            chunk.iter().zip(stream.iter()).map(|(d, s)| d ^ s).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn gcm_tag(aes: &Aes128, nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let mut h = [0u8; 16];
+    aes.encrypt_block(&mut h);
+    let h = u128::from_be_bytes(h);
+
+    let mut y = 0u128;
+    for data in &[aad, ciphertext] {
+        for chunk in data.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            y = gf128_mul(y ^ u128::from_be_bytes(block), h);
+        }
+    }
+    let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+    y = gf128_mul(y ^ lengths, h);
+
+    let s0 = u128::from_be_bytes(gcm_counter_block(aes, nonce, 1));
+    (y ^ s0).to_be_bytes()
+}
+
+/// Multiplication in GCM's GF(2^128), whose bits are numbered from the most
+/// significant.
+fn gf128_mul(x: u128, y: u128) -> u128 {
+    let mut product = 0;
+    let mut v = y;
+    for i in 0..128 {
+        if x & (1 << (127 - i)) != 0 {
+            product ^= v;
+        }
+        v = (v >> 1) ^ if v & 1 != 0 { 0xe1 << 120 } else { 0 };
+    }
+    product
+}
+
+/// Decrypt and authenticate an AES-CCM (RFC 3610) message with a 13 B nonce
+/// (so a 2 B length field), as used by CCMP. `data` is the ciphertext
+/// followed by a MIC of `mic_length` bytes.
+pub fn ccm_decrypt(key: &[u8; 16], nonce: &[u8; 13], aad: &[u8], data: &[u8], mic_length: usize)
+    -> Option<Vec<u8>> {
+
+    if data.len() < mic_length || data.len() - mic_length > 0xffff {
+        return None;
+    }
+
+    let aes = Aes128::new(key);
+    let (ciphertext, mic) = data.split_at(data.len() - mic_length);
+    let plaintext = ctr(&aes, nonce, ciphertext);
+
+    let expected = cbc_mac(&aes, nonce, aad, &plaintext, mic_length);
+    let s0 = counter_block(&aes, nonce, 0);
+    let matches = mic.iter().zip(expected.iter().zip(s0.iter())).all(|(m, (e, s))| *m == e ^ s);
+
+    if matches { Some(plaintext) } else { None }
+}
+
+/// Encrypt and authenticate a message with AES-CCM, returning the
+/// ciphertext followed by the MIC.
+pub fn ccm_encrypt(key: &[u8; 16], nonce: &[u8; 13], aad: &[u8], plaintext: &[u8], mic_length: usize)
+    -> Vec<u8> {
+
+    let aes = Aes128::new(key);
+    let mut output = ctr(&aes, nonce, plaintext);
+
+    let mac = cbc_mac(&aes, nonce, aad, plaintext, mic_length);
+    let s0 = counter_block(&aes, nonce, 0);
+    output.extend(mac.iter().zip(s0.iter()).take(mic_length).map(|(m, s)| m ^ s));
+    output
+}
+
+fn counter_block(aes: &Aes128, nonce: &[u8; 13], counter: u16) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[0] = 0x01;
+    block[1..14].copy_from_slice(nonce);
+    block[14..].copy_from_slice(&counter.to_be_bytes());
+    aes.encrypt_block(&mut block);
+    block
+}
+
+fn ctr(aes: &Aes128, nonce: &[u8; 13], data: &[u8]) -> Vec<u8> {
+    data.chunks(16)
+        .enumerate()
+        .flat_map(|(i, chunk)| {
+            let stream = counter_block(aes, nonce, i as u16 + 1);
+            chunk.iter().zip(stream.iter()).map(|(d, s)| d ^ s).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn cbc_mac(aes: &Aes128, nonce: &[u8; 13], aad: &[u8], message: &[u8], mic_length: usize) -> [u8; 16] {
+    let mut b0 = [0u8; 16];
+    b0[0] = if aad.is_empty() { 0 } else { 0x40 } | (((mic_length as u8 - 2) / 2) << 3) | 0x01;
+    b0[1..14].copy_from_slice(nonce);
+    b0[14..].copy_from_slice(&(message.len() as u16).to_be_bytes());
+
+    let mut input = b0.to_vec();
+    if !aad.is_empty() {
+        input.extend_from_slice(&(aad.len() as u16).to_be_bytes());
+        input.extend_from_slice(aad);
+        while input.len() % 16 != 0 {
+            input.push(0);
+        }
+    }
+    input.extend_from_slice(message);
+    while input.len() % 16 != 0 {
+        input.push(0);
+    }
+
+    let mut mac = [0u8; 16];
+    for block in input.chunks(16) {
+        for (m, b) in mac.iter_mut().zip(block.iter()) {
+            *m ^= *b;
+        }
+        aes.encrypt_block(&mut mac);
+    }
+    mac
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(sha1(b"abc").to_vec(), hex("a9993e364706816aba3e25717850c26c9cd0d89d"));
+        assert_eq!(hmac_sha1(&[0x0b; 20], b"Hi There").to_vec(),
+                   hex("b617318655057264e28bc0b6fb378c8ef146be00"));
+
+        // The 802.11i passphrase-to-PSK test vector
+        let mut psk = [0u8; 32];
+        pbkdf2_hmac_sha1(b"password", b"IEEE", 4096, &mut psk);
+        assert_eq!(psk.to_vec(), hex("f42c6fc52df0ebef9ebb4b90b38a5f902e83fe1b135a70e23aed762e9710a12e"));
+    }
+
+    #[test]
+    fn aes_ccm() {
+        let mut key = [0u8; 16];
+        key.copy_from_slice(&hex("000102030405060708090a0b0c0d0e0f"));
+        let mut block = [0u8; 16];
+        block.copy_from_slice(&hex("00112233445566778899aabbccddeeff"));
+        Aes128::new(&key).encrypt_block(&mut block);
+        assert_eq!(block.to_vec(), hex("69c4e0d86a7b0430d8cdb78070b4c55a"));
+
+        // RFC 3610, packet vector #1
+        key.copy_from_slice(&hex("c0c1c2c3c4c5c6c7c8c9cacbcccdcecf"));
+        let mut nonce = [0u8; 13];
+        nonce.copy_from_slice(&hex("00000003020100a0a1a2a3a4a5"));
+        let aad = hex("0001020304050607");
+        let plaintext = hex("08090a0b0c0d0e0f101112131415161718191a1b1c1d1e");
+        let expected = hex("588c979a61c663d2f066d0c2c0f989806d5f6b61dac38417e8d12cfdf926e0");
+
+        assert_eq!(ccm_encrypt(&key, &nonce, &aad, &plaintext, 8), expected);
+        assert_eq!(ccm_decrypt(&key, &nonce, &aad, &expected, 8), Some(plaintext));
+
+        let mut forged = expected.clone();
+        forged[0] ^= 1;
+        assert_eq!(ccm_decrypt(&key, &nonce, &aad, &forged, 8), None);
+    }
+
+    #[test]
+    fn aes_cbc_gcm() {
+        let mut key = [0u8; 16];
+        key.copy_from_slice(&hex("000102030405060708090a0b0c0d0e0f"));
+        let mut block = [0u8; 16];
+        block.copy_from_slice(&hex("69c4e0d86a7b0430d8cdb78070b4c55a"));
+        Aes128::new(&key).decrypt_block(&mut block);
+        assert_eq!(block.to_vec(), hex("00112233445566778899aabbccddeeff"));
+
+        // RFC 3602, case #1
+        key.copy_from_slice(&hex("06a9214036b8a15b512e03d534120006"));
+        let mut iv = [0u8; 16];
+        iv.copy_from_slice(&hex("3dafba429d9eb430b422da802c9fac41"));
+        assert_eq!(cbc_decrypt(&key, &iv, &hex("e353779c1079aeb82708942dbe77181a")),
+                   Some(b"Single block msg".to_vec()));
+        assert_eq!(cbc_decrypt(&key, &iv, &[0; 15]), None);
+
+        // The GCM specification's test cases #2 and #3
+        let zero = [0u8; 12];
+        assert_eq!(gcm_encrypt(&[0; 16], &zero, &[], &[0; 16]),
+                   hex("0388dace60b6a392f328c2b971b2fe78ab6e47d42cec13bdf53a67b21257bddf"));
+
+        key.copy_from_slice(&hex("feffe9928665731c6d6a8f9467308308"));
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&hex("cafebabefacedbaddecaf888"));
+        let plaintext = hex("d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255");
+        let ciphertext = hex("42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e\
+                              21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091473f5985");
+        let tag = hex("4d5c2af327cd64a62cf35abd2ba6fab4");
+        assert_eq!(gcm_encrypt(&key, &nonce, &[], &plaintext), [&ciphertext[..], &tag].concat());
+        assert_eq!(gcm_decrypt(&key, &nonce, &[], &ciphertext, &tag[..8]), Some(plaintext));
+        assert_eq!(gcm_decrypt(&key, &nonce, b"aad", &ciphertext, &tag), None);
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! "Decode As": forcing the dissector of a port or a conversation.
+//!
+//! Malware rarely sticks to well-known ports, so the `registry` and the
+//! heuristics can't always tell what a TCP or UDP payload is. A Decode-As
+//! rule names the dissector to use for the payloads sent to or from a port
+//! or within a single conversation (5-tuple), overriding both. Rules for a
+//! conversation take precedence over rules for a port.
+//!
+//! Rules are held in a global `DecodeAs` table; after changing it, dissect
+//! any loaded packets again (see `session::Change::DecodeAs`).
+//!
+//! ```
+//! use rshark::{DissectResult, Val};
+//! use rshark::decode_as::{self, Selector};
+//!
+//! fn beacon(data: &[u8]) -> DissectResult {
+//!     Ok(Box::new(Val::Object("Beacon", vec![("Bytes", Val::Unsigned(data.len() as u64))])))
+//! }
+//!
+//! decode_as::add("tcp.port 4444".parse().unwrap(), "Beacon", beacon);
+//!
+//! let segment = [0x11, 0x5c, 0xd4, 0x31, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0x10, 0x00,
+//!                0, 0, 0, 0, 0xca, 0xfe];
+//! let tcp = rshark::ip::tcp::dissect(&segment).unwrap();
+//! assert_eq!(tcp.lookup("Payload.Bytes"), Some(&Val::Unsigned(2)));
+//!
+//! assert!(decode_as::remove(&Selector::TcpPort(4444)));
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{OnceLock, RwLock};
+
+use DissectError;
+use flow::FlowKey;
+use registry::{Binding, Handler, Table};
+
+/// What a Decode-As rule applies to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Selector {
+    /// TCP payloads sent to or from a port.
+    TcpPort(u16),
+
+    /// UDP payloads sent to or from a port.
+    UdpPort(u16),
+
+    /// Transport-layer payloads of one conversation, in either direction.
+    Flow(FlowKey),
+}
+
+impl FromStr for Selector {
+    type Err = DissectError;
+
+    /// Parse a port binding such as "tcp.port 4444" (see `registry::Binding`).
+    fn from_str(s: &str) -> Result<Selector, DissectError> {
+        let binding = s.parse::<Binding>()?;
+        if binding.value > 0xffff {
+            return Err(DissectError::InvalidData(format!["'{}' isn't a valid port", s]));
+        }
+
+        match binding.table {
+            Table::TcpPort => Ok(Selector::TcpPort(binding.value as u16)),
+            Table::UdpPort => Ok(Selector::UdpPort(binding.value as u16)),
+            table => Err(DissectError::InvalidData(
+                format!["can't decode {} as another protocol", table.name()])),
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Selector::TcpPort(port) => write![f, "tcp.port {}", port],
+            Selector::UdpPort(port) => write![f, "udp.port {}", port],
+            Selector::Flow(ref key) => write![f, "flow {} {}:{} <-> {}:{}", key.protocol,
+                                              key.source, key.source_port,
+                                              key.destination, key.destination_port],
+        }
+    }
+}
+
+/// A table of Decode-As rules.
+#[derive(Clone, Default)]
+pub struct DecodeAs {
+    rules: Vec<(Selector, &'static str, Handler)>,
+}
+
+impl DecodeAs {
+    pub fn new() -> DecodeAs {
+        DecodeAs::default()
+    }
+
+    /// Dissect what a selector matches with a dissector, replacing any rule
+    /// for the same selector.
+    pub fn add(&mut self, selector: Selector, name: &'static str, handler: Handler) {
+        self.remove(&selector);
+        self.rules.push((selector, name, handler));
+    }
+
+    /// Remove the rule for a selector. Returns false if there wasn't one.
+    pub fn remove(&mut self, selector: &Selector) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|&(ref s, _, _)| s != selector);
+        self.rules.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The rules, by selector and dissector name, in the order they were added.
+    pub fn rules(&self) -> Vec<(Selector, &'static str)> {
+        self.rules.iter().map(|&(selector, name, _)| (selector, name)).collect()
+    }
+
+    /// The dissector for the payloads sent between two ports, if a rule
+    /// names one for either port.
+    pub fn lookup_ports(&self, table: Table, source: u16, destination: u16)
+        -> Option<(&'static str, Handler)> {
+
+        self.find(|selector| match (table, *selector) {
+            (Table::TcpPort, Selector::TcpPort(port))
+                | (Table::UdpPort, Selector::UdpPort(port)) =>
+                port == source || port == destination,
+            _ => false,
+        })
+    }
+
+    /// The dissector for the payloads of a conversation, in either direction.
+    pub fn lookup_flow(&self, key: &FlowKey) -> Option<(&'static str, Handler)> {
+        let reversed = key.reversed();
+        self.find(|selector| *selector == Selector::Flow(*key)
+                             || *selector == Selector::Flow(reversed))
+    }
+
+    /// The most recently added rule that matches.
+    fn find<F>(&self, matches: F) -> Option<(&'static str, Handler)>
+        where F: Fn(&Selector) -> bool {
+
+        self.rules.iter().rev()
+            .find(|&&(ref selector, _, _)| matches(selector))
+            .map(|&(_, name, handler)| (name, handler))
+    }
+}
+
+/// The rules applied by rshark's own dissectors.
+pub fn global() -> &'static RwLock<DecodeAs> {
+    static RULES: OnceLock<RwLock<DecodeAs>> = OnceLock::new();
+    RULES.get_or_init(|| RwLock::new(DecodeAs::new()))
+}
+
+/// Add a rule to the global table (see `DecodeAs::add`).
+pub fn add(selector: Selector, name: &'static str, handler: Handler) {
+    global().write().unwrap().add(selector, name, handler)
+}
+
+/// Remove a rule from the global table (see `DecodeAs::remove`).
+pub fn remove(selector: &Selector) -> bool {
+    global().write().unwrap().remove(selector)
+}
+
+/// Remove all rules from the global table.
+pub fn clear() {
+    global().write().unwrap().clear()
+}
+
+/// The dissector that a global rule names for the payloads between two ports.
+pub fn lookup_ports(table: Table, source: u16, destination: u16) -> Option<Handler> {
+    global().read().unwrap().lookup_ports(table, source, destination).map(|(_, handler)| handler)
+}
+
+/// The dissector that a global rule names for the payloads of a conversation.
+pub fn lookup_flow(key: &FlowKey) -> Option<Handler> {
+    global().read().unwrap().lookup_flow(key).map(|(_, handler)| handler)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use {DissectResult, Val};
+
+    fn forced(data: &[u8]) -> DissectResult {
+        Ok(Box::new(Val::Object("Forced", vec![("Length", Val::Unsigned(data.len() as u64))])))
+    }
+
+    fn other(_: &[u8]) -> DissectResult {
+        Ok(Box::new(Val::Object("Other", vec![])))
+    }
+
+    #[test]
+    fn match_rules() {
+        let key = FlowKey {
+            protocol: 17,
+            source: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            source_port: 1234,
+            destination: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            destination_port: 53,
+        };
+
+        let mut rules = DecodeAs::new();
+        rules.add("udp.port 53".parse().unwrap(), "Other", other);
+        rules.add(Selector::UdpPort(53), "Forced", forced);
+        rules.add(Selector::Flow(key), "Other", other);
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules.lookup_ports(Table::UdpPort, 53, 1234).map(|(n, _)| n), Some("Forced"));
+        assert_eq!(rules.lookup_ports(Table::TcpPort, 53, 1234).map(|(n, _)| n), None);
+        assert_eq!(rules.lookup_flow(&key.reversed()).map(|(n, _)| n), Some("Other"));
+        assert_eq!(rules.rules().iter().map(|&(s, n)| format!["{}: {}", s, n])
+                       .collect::<Vec<_>>(),
+                   vec!["udp.port 53: Forced", "flow 17 10.0.0.1:1234 <-> 10.0.0.2:53: Other"]);
+
+        assert!("ethertype 0x800".parse::<Selector>().is_err());
+        assert!("tcp.port 65536".parse::<Selector>().is_err());
+        assert!(rules.remove(&Selector::Flow(key)));
+        assert!(!rules.remove(&Selector::Flow(key)));
+    }
+
+    #[test]
+    fn decode_flow_as() {
+        let packet = [0x45, 0x00, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+                      10, 9, 9, 1, 10, 9, 9, 2,
+                      0x04, 0x57, 0x08, 0xae, 0x00, 0x0a, 0x00, 0x00,
+                      b'h', b'i'];
+        let key = FlowKey {
+            protocol: 17,
+            source: IpAddr::V4(Ipv4Addr::new(10, 9, 9, 2)),
+            source_port: 2222,
+            destination: IpAddr::V4(Ipv4Addr::new(10, 9, 9, 1)),
+            destination_port: 1111,
+        };
+
+        add(Selector::Flow(key), "Forced", forced);
+        let val = ::ip::dissect(&packet).unwrap();
+        assert!(remove(&Selector::Flow(key)));
+
+        assert_eq!(val.lookup("Payload.Payload.Length"), Some(&Val::Unsigned(2)));
+        assert!(::ip::dissect(&packet).unwrap().layer("Forced").is_none());
+    }
+}
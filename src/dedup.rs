@@ -0,0 +1,223 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Detection and removal of duplicate packets.
+//!
+//! A misconfigured SPAN port (e.g., one mirroring both the ingress and the
+//! egress of a switch) captures many packets twice, which doubles counts and
+//! looks like retransmissions. As with `editcap -d`, a packet is considered a
+//! duplicate if it is byte-for-byte identical to one of the few packets just
+//! before it; additionally, it must have been captured within a short time of
+//! that packet. Wrapping a source in a `DedupSource` removes duplicates before
+//! statistics are gathered or packets are exported.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::dedup::DedupSource;
+//! use rshark::source::{Packet, PacketSource, VecSource};
+//!
+//! let packet = |us: u32, byte| Packet { timestamp: Duration::new(0, us * 1000), original_length: 4, data: vec![byte; 4] };
+//! let source = VecSource::new(vec![packet(0, 1), packet(5, 1), packet(10, 2)], 1);
+//!
+//! let mut dedup = DedupSource::new(source);
+//! let mut kept = 0;
+//! while let Some(_) = dedup.next_packet() {
+//!     kept += 1;
+//! }
+//! assert_eq!(kept, 2);
+//! assert_eq!(dedup.report().duplicates, 1);
+//! ```
+
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::Hasher;
+use std::io;
+use std::time::Duration;
+
+use source::{Packet, PacketSource, SourceStats};
+
+/// How many packets were seen and how many of them were duplicates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DedupReport {
+    pub packets: u64,
+    pub duplicates: u64,
+
+    /// Captured bytes in duplicate packets.
+    pub duplicate_bytes: u64,
+}
+
+impl DedupReport {
+    /// The fraction of packets that were duplicates.
+    pub fn rate(&self) -> f64 {
+        if self.packets == 0 { 0.0 } else { self.duplicates as f64 / self.packets as f64 }
+    }
+}
+
+impl fmt::Display for DedupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{} of {} packets ({:.1}%) were duplicates, {} B",
+               self.duplicates, self.packets, 100.0 * self.rate(), self.duplicate_bytes]
+    }
+}
+
+/// Remembers recent packets in order to recognize duplicates of them.
+pub struct Deduplicator {
+    depth: usize,
+    window: Duration,
+    recent: VecDeque<(Duration, u64, Vec<u8>)>,
+    report: DedupReport,
+}
+
+impl Deduplicator {
+    /// Compare each packet to the previous 5 (as `editcap -d` does), if they
+    /// were captured within 1 ms.
+    pub fn new() -> Deduplicator {
+        Deduplicator {
+            depth: 5,
+            window: Duration::from_millis(1),
+            recent: VecDeque::new(),
+            report: DedupReport::default(),
+        }
+    }
+
+    /// Compare each packet to this many of the packets before it.
+    pub fn depth(mut self, depth: usize) -> Deduplicator {
+        self.depth = depth;
+        self
+    }
+
+    /// Only consider packets captured within `window` of each other.
+    pub fn window(mut self, window: Duration) -> Deduplicator {
+        self.window = window;
+        self
+    }
+
+    /// Check whether a packet duplicates a recent one, remembering it if not.
+    pub fn is_duplicate(&mut self, packet: &Packet) -> bool {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&packet.data);
+        let hash = hasher.finish();
+
+        let window = self.window;
+        let duplicate = self.recent.iter().any(|&(timestamp, h, ref data)| {
+            h == hash && *data == packet.data
+                && packet.timestamp >= timestamp && packet.timestamp - timestamp <= window
+        });
+
+        self.report.packets += 1;
+        if duplicate {
+            self.report.duplicates += 1;
+            self.report.duplicate_bytes += packet.data.len() as u64;
+            return true;
+        }
+
+        if self.depth > 0 {
+            if self.recent.len() == self.depth {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((packet.timestamp, hash, packet.data.clone()));
+        }
+
+        false
+    }
+
+    pub fn report(&self) -> &DedupReport {
+        &self.report
+    }
+}
+
+/// A source that passes on packets from another, less duplicates.
+pub struct DedupSource<S> {
+    source: S,
+    dedup: Deduplicator,
+    stats: SourceStats,
+}
+
+impl<S: PacketSource> DedupSource<S> {
+    pub fn new(source: S) -> DedupSource<S> {
+        DedupSource::with(source, Deduplicator::new())
+    }
+
+    /// Remove duplicates as recognized by a configured `Deduplicator`.
+    pub fn with(source: S, dedup: Deduplicator) -> DedupSource<S> {
+        DedupSource { source: source, dedup: dedup, stats: SourceStats::default() }
+    }
+
+    pub fn report(&self) -> &DedupReport {
+        self.dedup.report()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: PacketSource> PacketSource for DedupSource<S> {
+    fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+        loop {
+            match self.source.next_packet() {
+                Some(Ok(packet)) => {
+                    if !self.dedup.is_duplicate(&packet) {
+                        self.stats.count(&packet);
+                        return Some(Ok(packet));
+                    }
+                },
+                other => return other,
+            }
+        }
+    }
+
+    fn link_type(&self) -> u32 {
+        self.source.link_type()
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats { dropped: self.source.stats().dropped, ..self.stats.clone() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use source::{Packet, PacketSource, VecSource};
+
+    fn packet(ms: u64, byte: u8) -> Packet {
+        Packet { timestamp: Duration::from_millis(ms), original_length: 8, data: vec![byte; 8] }
+    }
+
+    fn kept<S: PacketSource>(source: &mut S) -> Vec<u8> {
+        let mut kept = Vec::new();
+        while let Some(packet) = source.next_packet() {
+            kept.push(packet.unwrap().data[0]);
+        }
+        kept
+    }
+
+    #[test]
+    fn remove_duplicates() {
+        let packets = vec![packet(0, 1), packet(0, 2), packet(0, 1), packet(5, 1), packet(5, 3), packet(5, 3)];
+        let mut source = DedupSource::new(VecSource::new(packets.clone(), 1));
+
+        assert_eq!(kept(&mut source), vec![1, 2, 1, 3]);
+        assert_eq!(source.stats().packets, 4);
+        assert_eq!(source.report().duplicates, 2);
+        assert_eq!(source.report().to_string(), "2 of 6 packets (33.3%) were duplicates, 16 B");
+
+        // With a longer window, the repeat 5 ms later is a duplicate too.
+        let dedup = Deduplicator::new().window(Duration::from_millis(10));
+        let mut source = DedupSource::with(VecSource::new(packets.clone(), 1), dedup);
+        assert_eq!(kept(&mut source).len(), 3);
+
+        // Without memory, nothing is a duplicate.
+        let mut source = DedupSource::with(VecSource::new(packets, 1), Deduplicator::new().depth(0));
+        assert_eq!(kept(&mut source).len(), 6);
+    }
+}
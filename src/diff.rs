@@ -0,0 +1,166 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Differences between dissections.
+//!
+//! `diff` compares two dissections field by field, e.g., the output of a
+//! dissector against a golden copy or a packet against its retransmission,
+//! and reports the fields that were added, removed or changed along with
+//! their paths (in `Val::lookup` form). Fields that occur more than once in
+//! an object are paired up in order. Objects with different names (e.g.,
+//! different protocol layers) aren't compared field by field: the whole
+//! object has changed.
+//!
+//! ```
+//! use rshark::Val;
+//!
+//! let a = Val::Object("UDP", vec![("Source Port", Val::Unsigned(53))]);
+//! let b = Val::Object("UDP", vec![("Source Port", Val::Unsigned(5353)),
+//!                                 ("Length", Val::Unsigned(8))]);
+//!
+//! let changes = rshark::diff(&a, &b).iter().map(|c| c.to_string()).collect::<Vec<_>>();
+//! assert_eq!(changes, vec!["~ Source Port: 53 -> 5353", "+ Length: 8"]);
+//! ```
+
+use std::fmt;
+
+use Val;
+use visit::Step;
+
+/// A difference between two dissections.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    /// A field present only in the second dissection.
+    Added { path: String, value: String },
+
+    /// A field present only in the first dissection.
+    Removed { path: String, value: String },
+
+    /// A field whose value differs between the dissections.
+    Changed { path: String, old: String, new: String },
+}
+
+impl Change {
+    pub fn path(&self) -> &str {
+        match *self {
+            Change::Added { ref path, .. } | Change::Removed { ref path, .. }
+                | Change::Changed { ref path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Added { ref path, ref value } => write![f, "+ {}: {}", path, value],
+            Change::Removed { ref path, ref value } => write![f, "- {}: {}", path, value],
+            Change::Changed { ref path, ref old, ref new } =>
+                write![f, "~ {}: {} -> {}", path, old, new],
+        }
+    }
+}
+
+/// The changes that turn dissection `a` into dissection `b`, in order.
+pub fn diff(a: &Val, b: &Val) -> Vec<Change> {
+    let mut changes = Vec::new();
+    compare(a, b, &mut Vec::new(), &mut changes);
+    changes
+}
+
+fn compare(a: &Val, b: &Val, path: &mut Vec<Step>, changes: &mut Vec<Change>) {
+    match (a, b) {
+        (&Val::Object(a_name, ref a_values), &Val::Object(b_name, ref b_values))
+            if a_name == b_name => {
+
+            // Pair up the nth occurrences of each field name.
+            let mut matched = vec![false; b_values.len()];
+            for (i, &(key, ref v)) in a_values.iter().enumerate() {
+                let nth = a_values[..i].iter().filter(|&&(k, _)| k == key).count();
+                let other = b_values.iter().enumerate()
+                    .filter(|&(_, &(k, _))| k == key)
+                    .nth(nth);
+
+                path.push(Step::Field(key));
+                match other {
+                    Some((j, &(_, ref w))) => {
+                        matched[j] = true;
+                        compare(v, w, path, changes);
+                    },
+                    None => changes.push(Change::Removed { path: Step::join(path),
+                                                           value: v.to_string() }),
+                }
+                path.pop();
+            }
+
+            for (&(key, ref w), _) in b_values.iter().zip(matched).filter(|&(_, m)| !m) {
+                path.push(Step::Field(key));
+                changes.push(Change::Added { path: Step::join(path), value: w.to_string() });
+                path.pop();
+            }
+        },
+
+        (&Val::List(ref a_values), &Val::List(ref b_values)) => {
+            for i in 0..a_values.len().max(b_values.len()) {
+                path.push(Step::Index(i));
+                match (a_values.get(i), b_values.get(i)) {
+                    (Some(v), Some(w)) => compare(v, w, path, changes),
+                    (Some(v), None) => changes.push(Change::Removed { path: Step::join(path),
+                                                                      value: v.to_string() }),
+                    (None, Some(w)) => changes.push(Change::Added { path: Step::join(path),
+                                                                    value: w.to_string() }),
+                    (None, None) => {},
+                }
+                path.pop();
+            }
+        },
+
+        (&Val::Payload(_, Ok(ref v)), &Val::Payload(_, Ok(ref w))) => compare(v, w, path, changes),
+
+        _ if a != b => changes.push(Change::Changed {
+            path: Step::join(path),
+            old: a.to_string(),
+            new: b.to_string(),
+        }),
+
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use DissectError;
+    use ErrorCode;
+
+    #[test]
+    fn diff_trees() {
+        let a = Val::Object("Outer", vec![
+            ("Option", Val::Unsigned(1)),
+            ("Option", Val::Unsigned(2)),
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(4)])),
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", vec![
+                ("Name", Val::String("x".to_string())),
+            ]))))),
+        ]);
+        let b = Val::Object("Outer", vec![
+            ("Option", Val::Unsigned(1)),
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(5), Val::Unsigned(6)])),
+            ("Payload", Val::Payload(&[], Err(DissectError::malformed(ErrorCode::BadMagic, "no")))),
+        ]);
+
+        assert_eq!(diff(&a, &a), vec![]);
+        assert_eq!(diff(&a, &b).iter().map(|c| c.to_string()).collect::<Vec<_>>(), vec![
+            "- Option: 2",
+            "~ Hops.1: 4 -> 5",
+            "+ Hops.2: 6",
+            "~ Payload: (Inner -> { Name: \"x\" }) -> <<invalid data (bad_magic): no>>",
+        ]);
+        assert_eq!(diff(&a, &b)[0].path(), "Option");
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of EAP over LAN (EAPOL, IEEE 802.1X, Ethertype 0x888e) frames,
+//! in particular the EAPOL-Key frames of the WPA 4-way handshake.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use {read_be_u16, read_be_u64};
+
+enum_map!(pub PACKET_TYPES {
+    0 => "EAP Packet",
+    1 => "Start",
+    2 => "Logoff",
+    3 => "Key",
+    4 => "Encapsulated ASF Alert",
+});
+
+/// The fixed part of an EAPOL-Key frame, including the EAPOL header.
+const KEY_LENGTH: usize = 99;
+
+/// Offset of the Key MIC within an EAPOL-Key frame.
+pub const MIC_OFFSET: usize = 81;
+
+/// The fields of an EAPOL-Key frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Key<'data> {
+    pub descriptor_type: u8,
+    pub information: u16,
+    pub replay_counter: u64,
+    pub nonce: &'data [u8],
+    pub mic: &'data [u8],
+    pub data: &'data [u8],
+
+    /// The whole EAPOL frame (without any padding), over which the MIC is
+    /// computed.
+    pub frame: &'data [u8],
+}
+
+impl<'data> Key<'data> {
+    /// Parse an EAPOL frame, which must be an EAPOL-Key frame.
+    pub fn parse(data: &'data [u8]) -> Result<Key<'data>, DissectError> {
+        if data.len() < KEY_LENGTH {
+            return Err(DissectError::Underflow { expected: Some(KEY_LENGTH), have: data.len(),
+                message: format!["An EAPOL-Key frame must be at least {} B", KEY_LENGTH] });
+        }
+        if data[1] != 3 {
+            return Err(DissectError::InvalidData(format!["EAPOL packet type {} is not Key", data[1]]));
+        }
+
+        let end = (4 + read_be_u16(data, 2)? as usize).min(data.len());
+        let data_length = read_be_u16(data, 97)? as usize;
+        if KEY_LENGTH + data_length > end {
+            return Err(DissectError::Underflow { expected: Some(KEY_LENGTH + data_length), have: end,
+                message: "EAPOL-Key data overruns its frame".to_string() });
+        }
+
+        Ok(Key {
+            descriptor_type: data[4],
+            information: read_be_u16(data, 5)?,
+            replay_counter: read_be_u64(data, 9)?,
+            nonce: &data[17..49],
+            mic: &data[MIC_OFFSET..MIC_OFFSET + 16],
+            data: &data[KEY_LENGTH..KEY_LENGTH + data_length],
+            frame: &data[..end],
+        })
+    }
+
+    /// The key descriptor version: 1 for HMAC-MD5 and RC4 (WPA/TKIP), 2 for
+    /// HMAC-SHA1 and AES (WPA2/CCMP).
+    pub fn descriptor_version(&self) -> u16 {
+        self.information & 0x07
+    }
+
+    pub fn pairwise(&self) -> bool { self.information & 0x0008 != 0 }
+    pub fn install(&self) -> bool { self.information & 0x0040 != 0 }
+    pub fn ack(&self) -> bool { self.information & 0x0080 != 0 }
+    pub fn has_mic(&self) -> bool { self.information & 0x0100 != 0 }
+    pub fn secure(&self) -> bool { self.information & 0x0200 != 0 }
+
+    /// Which message of the 4-way handshake this is, if it is one.
+    pub fn message(&self) -> Option<u8> {
+        match (self.pairwise(), self.ack(), self.has_mic()) {
+            (true, true, false) => Some(1),
+            (true, true, true) => Some(3),
+            (true, false, true) if self.secure() || self.data.is_empty() => Some(4),
+            (true, false, true) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "An EAPOL header must be 4 B".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Version", Val::Unsigned(data[0] as u64)));
+    values.push(("Type", PACKET_TYPES.val(data[1])));
+    values.push(("Length", Val::Unsigned(read_be_u16(data, 2)? as u64)));
+
+    if data[1] != 3 {
+        values.push(("Payload", Val::Undissected("EAPOL body", &data[4..])));
+        return Ok(Box::new(Val::Object("EAPOL", values)));
+    }
+
+    let key = Key::parse(data)?;
+    values.push(("Descriptor Type", Val::Unsigned(key.descriptor_type as u64)));
+    values.push(("Key Information", Val::Unsigned(key.information as u64)));
+    values.push(("Descriptor Version", Val::Unsigned(key.descriptor_version() as u64)));
+    values.push(("Key Flags", Val::BitFlags8((key.information >> 3) as u8, [
+                 Some("Pairwise"), None, None, Some("Install"),
+                 Some("Ack"), Some("MIC"), Some("Secure"), Some("Error")])));
+    if let Some(message) = key.message() {
+        values.push(("Handshake Message", Val::Unsigned(message as u64)));
+    }
+    values.push(("Key Length", Val::Unsigned(read_be_u16(data, 7)? as u64)));
+    values.push(("Replay Counter", Val::Unsigned(key.replay_counter)));
+    values.push(("Key Nonce", Val::Bytes(key.nonce)));
+    values.push(("Key MIC", Val::Bytes(key.mic)));
+    values.push(("Key Data", Val::Bytes(key.data)));
+
+    Ok(Box::new(Val::Object("EAPOL", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_handshake_message() {
+        // Message 1: pairwise, ack, HMAC-SHA1/AES
+        let mut data = vec![0x02, 0x03, 0x00, 0x5f, 0x02, 0x00, 0x8a, 0x00, 0x10,
+                            0, 0, 0, 0, 0, 0, 0, 1];
+        data.extend_from_slice(&[0xaa; 32]);
+        data.extend_from_slice(&[0; 48]);
+        data.extend_from_slice(&[0, 0]);
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Type"].as_symbol().unwrap(), "Key");
+        assert_eq!(val["Descriptor Version"].as_unsigned().unwrap(), 2);
+        assert_eq!(val["Handshake Message"].as_unsigned().unwrap(), 1);
+        assert_eq!(val["Key Nonce"].as_bytes().unwrap(), &[0xaa; 32][..]);
+
+        assert!(dissect(&data[..50]).is_err());
+    }
+}
@@ -7,19 +7,34 @@
  * copied, modified, or distributed except according to those terms.
  */
 
-//! Dissection of Ethernet (IEEE 802.3) frames.
+//! Dissection of Ethernet (IEEE 802.3) frames, including 802.1Q VLAN tags
+//! and stacked 802.1ad (QinQ) tags.
 
 use DissectError;
 use DissectResult;
+use ErrorCode;
 use IntoDissectResult;
 use Val;
 use NamedValues;
-use ip;
+use context::Context;
+use iana;
+use llc;
+use mac::MacAddr;
 use nom::{be_u16, rest};
+use read_be_u16;
+use registry::{self, Table};
+
+/// Ethertypes of 802.1Q, 802.1ad and (pre-standard) QinQ tags.
+const VLAN_TAGS: [u16; 3] = [0x8100, 0x88a8, 0x9100];
 
 pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a frame, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
 
-    //TODO: beter parsing: 802.1Q tag, minimum payload size, CRC
+    //TODO: beter parsing: minimum payload size, CRC
     chain!(data,
            dest: take!(6) ~
            src: take!(6) ~
@@ -27,26 +42,64 @@ pub fn dissect(data : &[u8]) -> DissectResult {
            remainder: rest,
            || {
                let mut values = NamedValues::new();
+               let mut tlen = tlen;
+               let mut remainder = remainder;
+
+               address(&mut values, "Destination", "Destination Vendor", dest);
+               address(&mut values, "Source", "Source Vendor", src);
+
+               // Each tag is followed by another type/length field.
+               while VLAN_TAGS.contains(&tlen) && remainder.len() >= 4 {
+                   let tci = read_be_u16(remainder, 0).unwrap();
+                   let mut tag = NamedValues::new();
+                   tag.push(("TPID", Val::Unsigned(tlen as u64)));
+                   tag.push(("Priority", Val::Unsigned((tci >> 13) as u64)));
+                   tag.push(("DEI", Val::Unsigned(((tci >> 12) & 0x01) as u64)));
+                   tag.push(("ID", Val::Unsigned((tci & 0x0fff) as u64)));
+                   values.push(("VLAN Tag", Val::Object("802.1Q Tag", tag)));
 
-               values.push(("Destination", Val::Bytes(dest)));
-               values.push(("Source", Val::Bytes(src)));
+                   tlen = read_be_u16(remainder, 2).unwrap();
+                   remainder = &remainder[4..];
+               }
 
                if tlen <= 1500 {
+                   // An 802.3 length: the payload (less any padding) is LLC.
                    values.push(("Length", Val::Unsigned(tlen as u64)));
+                   let payload = &remainder[..(tlen as usize).min(remainder.len())];
+                   if !payload.is_empty() {
+                       values.push(("Payload", Val::Payload(payload,
+                           context.dissect(llc::dissect_with, payload))));
+                   }
                } else {
-                   match tlen {
-                       0x800 => values.push(("Payload", Val::Payload(ip::dissect(remainder)))),
-                       0x806 => values.push(("Payload", Val::Undissected("ARP", remainder))),
-                       0x8138 => values.push(("Payload", Val::Undissected("IPX", remainder))),
-                       0x86dd => values.push(("Payload", Val::Undissected("IPv6", remainder))),
-                       _ => values.push(("Payload", Val::Payload(Err(DissectError::InvalidData(format!["unknown protocol: {:x}", tlen]))))),
-                   };
+                   values.push(("Type", iana::ETHERTYPES.enum_val(tlen)));
+                   values.push(("Payload", dissect_ethertype_with(tlen, remainder, context)));
                };
 
                values
            }).into_dissect_result("Ethernet frame", data)
 }
 
+/// Add an address and, if it's known, its vendor.
+fn address<'data>(values: &mut NamedValues<'data>, key: &'static str, vendor_key: &'static str,
+                  bytes: &'data [u8]) {
+    values.push((key, Val::mac(bytes)));
+    if let Some(vendor) = MacAddr::from_bytes(bytes).and_then(|m| m.vendor()) {
+        values.push((vendor_key, Val::Symbol(vendor)));
+    }
+}
+
+/// Dissect the payload of a frame according to its Ethertype (which may
+/// also come from an LLC SNAP header), within a context.
+pub fn dissect_ethertype_with<'data>(ethertype: u16, data: &'data [u8], context: &mut Context)
+    -> Val<'data> {
+
+    match registry::lookup(Table::Ethertype, ethertype as u64) {
+        Some(dissector) => registry::payload(data, dissector.dissect(data, context)),
+        None => Val::Payload(data, Err(DissectError::malformed(
+            ErrorCode::UnknownProtocol, format!["unknown protocol: {:x}", ethertype]))),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -58,11 +111,29 @@ mod test {
         let val = *dissect(&data).unwrap();
         println!("{}", &val.pretty_print(0));
 
-        assert_eq!(val["Destination"].as_bytes().unwrap(), &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
-        assert_eq!(val["Source"].as_bytes().unwrap(), &[0xa0, 0x0b, 0xba, 0x84, 0x2d, 0x0e]);
+        assert_eq!(val["Destination"].as_address_bytes().unwrap(), &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(val["Source"].as_address_encoded().unwrap(), "a0:0b:ba:84:2d:0e");
+        assert!(val["Destination"].as_mac().unwrap().is_broadcast());
         assert!(val["Payload"].is_undissected());
     }
 
+    #[test]
+    fn dissect_vlan_tags() {
+        // Outer 802.1ad tag (VLAN 100), inner 802.1Q tag (priority 5, VLAN 42), IPv4
+        let data = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0, 0, 0, 0, 1,
+                    0x88, 0xa8, 0x00, 0x64, 0x81, 0x00, 0xa0, 0x2a, 0x08, 0x00,
+                    0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+
+        let val = *dissect(&data).unwrap();
+        let tags = val.as_object().unwrap().1.iter()
+            .filter(|&&(k, _)| k == "VLAN Tag")
+            .map(|&(_, ref v)| (v["ID"].as_unsigned().unwrap(), v["Priority"].as_unsigned().unwrap()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(tags, vec![(100, 0), (42, 5)]);
+        assert_eq!(val["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+    }
+
     #[test]
     #[should_panic(expected = "Underflow { expected: Some(12), have: 10, message: \"Need 12 B of data to dissect Ethernet frame, have 10 B\" }")]
     fn dissect_ethernet_underflow() {
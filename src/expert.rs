@@ -0,0 +1,197 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Expert information: notes about individual fields, tagged by severity.
+//!
+//! A field can be dissected perfectly well and still deserve attention: a
+//! checksum that doesn't match, a deprecated protocol version or an unusual
+//! TTL. Dissectors record such observations as "Expert Info" objects in the
+//! layer concerned, naming the field they refer to and the kind of note (a
+//! code such as "checksum.bad", for programs to filter or count), and the
+//! dissection carries on. `infos` collects them from a whole packet, along
+//! with any conformance warnings (see `conformance`), which are reported as
+//! `Warn`s about the layer as a whole with the code `CONFORMANCE`.
+//!
+//! ```
+//! use rshark::expert::{self, Severity};
+//!
+//! // An IPv4 header with a bad checksum
+//! let data = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0xde, 0xad, 10, 0, 0, 1, 10, 0, 0, 2];
+//! let packet = rshark::ip::dissect(&data).unwrap();
+//!
+//! let infos = expert::infos(&packet);
+//! assert_eq!(infos[0].severity, Severity::Error);
+//! assert_eq!(infos[0].code, "checksum.bad");
+//! assert_eq!(infos[0].to_string(), "Error: IPv4 Checksum: checksum doesn't match");
+//! ```
+
+use std::fmt;
+
+use NamedValues;
+use Val;
+use conformance;
+
+/// The key (and object name) of expert info fields.
+pub const EXPERT_INFO: &'static str = "Expert Info";
+
+/// The code of conformance warnings.
+pub const CONFORMANCE: &'static str = "conformance";
+
+/// How much attention a note deserves, in increasing order.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Normal protocol behaviour worth pointing out.
+    Chat,
+    /// Unusual but valid.
+    Note,
+    /// Likely a problem.
+    Warn,
+    /// Definitely a problem, e.g., a corrupt field.
+    Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Severity::Chat => "Chat",
+            Severity::Note => "Note",
+            Severity::Warn => "Warn",
+            Severity::Error => "Error",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Severity> {
+        [Severity::Chat, Severity::Note, Severity::Warn, Severity::Error].iter()
+            .find(|s| s.name() == name)
+            .cloned()
+    }
+}
+
+/// A note found in a dissected packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Info {
+    /// The layer (object) that the note was found in.
+    pub layer: &'static str,
+
+    /// The field the note is about, if not the layer as a whole.
+    pub field: Option<&'static str>,
+
+    /// The kind of note, e.g., "checksum.bad" (or empty if not given).
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.field {
+            Some(field) => write![f, "{}: {} {}: {}", self.severity.name(), self.layer, field,
+                                  self.message],
+            None => write![f, "{}: {}: {}", self.severity.name(), self.layer, self.message],
+        }
+    }
+}
+
+/// Record a note of some kind (`code`) about the field `field` in a layer's
+/// values.
+pub fn add<S: Into<String>>(values: &mut NamedValues, field: &'static str, code: &'static str,
+                            severity: Severity, message: S) {
+    values.push((EXPERT_INFO, Val::Object(EXPERT_INFO, vec![
+        ("Field", Val::Symbol(field)),
+        ("Code", Val::Symbol(code)),
+        ("Severity", Val::Symbol(severity.name())),
+        ("Message", Val::String(message.into())),
+    ])));
+}
+
+/// All of the notes within a dissection, outermost first.
+pub fn infos(val: &Val) -> Vec<Info> {
+    let mut found = Vec::new();
+    collect(val, &mut found);
+    found
+}
+
+/// The most severe note within a dissection, if it has any.
+pub fn max_severity(val: &Val) -> Option<Severity> {
+    infos(val).iter().map(|i| i.severity).max()
+}
+
+fn collect(val: &Val, found: &mut Vec<Info>) {
+    match val {
+        &Val::Object(name, ref values) => {
+            for &(key, ref v) in values {
+                match (key, v) {
+                    (EXPERT_INFO, &Val::Object(_, _)) => {
+                        let field = v.get("Field").ok().and_then(|f| f.as_symbol());
+                        let code = v.get("Code").ok().and_then(|c| c.as_symbol()).unwrap_or("");
+                        let severity = v.get("Severity").ok().and_then(|s| s.as_symbol())
+                            .and_then(Severity::from_name)
+                            .unwrap_or(Severity::Note);
+                        let message = v.get("Message").ok().and_then(|m| m.as_string())
+                            .unwrap_or("");
+
+                        found.push(Info { layer: name, field: field, code: code,
+                                          severity: severity, message: message.to_string() });
+                    },
+                    (conformance::WARNING, &Val::String(ref message)) =>
+                        found.push(Info { layer: name, field: None, code: CONFORMANCE,
+                                          severity: Severity::Warn, message: message.clone() }),
+                    _ => collect(v, found),
+                }
+            }
+        },
+        &Val::List(ref values) => for v in values {
+            collect(v, found);
+        },
+        &Val::Payload(_, Ok(ref inner)) => collect(inner, found),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use heuristic::{self, Content};
+
+    #[test]
+    fn collect_infos() {
+        let mut values = NamedValues::new();
+        values.push(("TTL", Val::Unsigned(0)));
+        add(&mut values, "TTL", "ip.ttl_zero", Severity::Warn, "TTL of 0");
+        conformance::warn(&mut values, "bad header");
+        values.push(("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", vec![
+            (EXPERT_INFO, Val::Object(EXPERT_INFO, vec![
+                ("Field", Val::Symbol("Flags")),
+                ("Severity", Val::Symbol("Chat")),
+                ("Message", Val::String("hello".to_string())),
+            ])),
+        ]))))));
+        let val = Val::Object("Outer", values);
+
+        assert_eq!(infos(&val).iter().map(|i| i.to_string()).collect::<Vec<_>>(), vec![
+            "Warn: Outer TTL: TTL of 0",
+            "Warn: Outer: bad header",
+            "Chat: Inner Flags: hello",
+        ]);
+        assert_eq!(infos(&val).iter().map(|i| i.code).collect::<Vec<_>>(),
+                   vec!["ip.ttl_zero", CONFORMANCE, ""]);
+        assert_eq!(max_severity(&val), Some(Severity::Warn));
+        assert_eq!(max_severity(&Val::Unsigned(1)), None);
+    }
+
+    #[test]
+    fn deprecated_tls() {
+        // SSL 3.0 application data
+        let val = heuristic::dissect(&[23, 3, 0, 0, 2, 0xab, 0xcd], Content::Tls).unwrap();
+        let infos = infos(&val);
+        assert_eq!(infos[0].field, Some("Version"));
+        assert_eq!(infos[0].code, "tls.deprecated_version");
+        assert_eq!(infos[0].severity, Severity::Warn);
+    }
+}
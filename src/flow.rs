@@ -0,0 +1,513 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Tracking of transport-layer conversations (flows) across packets.
+//!
+//! A `FlowTable` is fed dissected packets along with their capture timestamps
+//! and aggregates them by 5-tuple (protocol, addresses and ports).
+//! The first packet seen for a flow determines which side is the originator.
+//! When the table's `MemoryBudget` is exceeded, the least-recently-seen flows
+//! are evicted.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use Val;
+use budget::{self, MemoryBudget};
+use expert::Info;
+use tap::{PacketInfo, Tap};
+
+/// The 5-tuple identifying a flow, as seen from the originator.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub source: IpAddr,
+    pub source_port: u16,
+    pub destination: IpAddr,
+    pub destination_port: u16,
+}
+
+impl FlowKey {
+    /// Extract a flow key from a dissected packet, if it carries TCP, UDP or
+    /// SCTP over IP. The transport headers quoted by ICMP errors don't count.
+    pub fn from_val(packet: &Val) -> Option<FlowKey> {
+        if packet.layer("ICMP").is_some() {
+            return None;
+        }
+
+        let ip = match packet.layer("IPv4").or(packet.layer("IPv6")) {
+            Some(ip) => ip,
+            None => return None,
+        };
+
+        let (protocol, transport) = match (packet.layer("TCP"), packet.layer("UDP"), packet.layer("SCTP")) {
+            (Some(tcp), _, _) => (6, tcp),
+            (None, Some(udp), _) => (17, udp),
+            (None, None, Some(sctp)) => (132, sctp),
+            _ => return None,
+        };
+
+        let source = ip.get("Source").ok().and_then(|a| a.as_address_bytes()).and_then(ip_addr);
+        let destination = ip.get("Destination").ok().and_then(|a| a.as_address_bytes()).and_then(ip_addr);
+        let source_port = transport.get("Source Port").ok().and_then(|p| p.as_unsigned());
+        let destination_port = transport.get("Destination Port").ok().and_then(|p| p.as_unsigned());
+
+        match (source, source_port, destination, destination_port) {
+            (Some(source), Some(source_port), Some(destination), Some(destination_port)) =>
+                Some(FlowKey {
+                    protocol: protocol,
+                    source: source,
+                    source_port: source_port as u16,
+                    destination: destination,
+                    destination_port: destination_port as u16,
+                }),
+            _ => None,
+        }
+    }
+
+    /// The flow of the datagram quoted by an ICMP error message, i.e., the
+    /// flow that triggered the error.
+    ///
+    /// A quoted TCP header is too short to dissect, so ports are read from
+    /// the start of the quoted transport header.
+    pub fn quoted(packet: &Val) -> Option<FlowKey> {
+        let ip = match packet.layer("ICMP").and_then(|icmp| icmp.get("Original Datagram").ok()) {
+            Some(quoted) => quoted,
+            None => return None,
+        };
+
+        let address = |key| ip.get(key).ok().and_then(|a| a.as_address_bytes()).and_then(ip_addr);
+        let protocol = ip.get("Protocol").ok().and_then(|p| p.as_unsigned());
+        let ports = ip.get("Payload").ok().and_then(|p| p.as_payload_bytes()).filter(|p| p.len() >= 4);
+
+        match (address("Source"), address("Destination"), protocol, ports) {
+            (Some(source), Some(destination), Some(protocol), Some(ports))
+                    if protocol == 6 || protocol == 17 || protocol == 132 =>
+                Some(FlowKey {
+                    protocol: protocol as u8,
+                    source: source,
+                    source_port: (ports[0] as u16) << 8 | ports[1] as u16,
+                    destination: destination,
+                    destination_port: (ports[2] as u16) << 8 | ports[3] as u16,
+                }),
+            _ => None,
+        }
+    }
+
+    /// The same flow, seen from the other end.
+    pub fn reversed(&self) -> FlowKey {
+        FlowKey {
+            protocol: self.protocol,
+            source: self.destination,
+            source_port: self.destination_port,
+            destination: self.source,
+            destination_port: self.source_port,
+        }
+    }
+
+    /// The name of the transport-layer protocol.
+    pub fn transport(&self) -> &'static str {
+        match self.protocol {
+            6 => "TCP",
+            17 => "UDP",
+            132 => "SCTP",
+            _ => "IP",
+        }
+    }
+}
+
+/// E.g., "10.0.0.1:5004 -> 10.0.0.2:5006".
+impl fmt::Display for FlowKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{} -> {}", SocketAddr::new(self.source, self.source_port),
+               SocketAddr::new(self.destination, self.destination_port)]
+    }
+}
+
+/// Aggregate information about a single flow.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Flow {
+    /// Sequential identifier, unique within a `FlowTable`.
+    pub id: u64,
+
+    pub key: FlowKey,
+
+    /// Timestamps of the first and most recent packets.
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+
+    pub orig_packets: u64,
+    pub resp_packets: u64,
+
+    /// Transport payload bytes sent by each side.
+    pub orig_bytes: u64,
+    pub resp_bytes: u64,
+
+    /// Union of all TCP flags sent by each side.
+    pub orig_flags: u8,
+    pub resp_flags: u8,
+
+    /// The application-layer protocol, if one was dissected.
+    pub service: Option<&'static str>,
+}
+
+impl Flow {
+    pub fn duration(&self) -> Duration {
+        self.last_seen - self.first_seen
+    }
+}
+
+/// Memory charged to the budget for each tracked flow.
+const FLOW_SIZE: usize = 2 * mem::size_of::<FlowKey>() + mem::size_of::<Flow>()
+    + mem::size_of::<(Duration, u64)>();
+
+/// A table of flows, keyed by originator-to-responder 5-tuple.
+pub struct FlowTable {
+    flows: HashMap<FlowKey, Flow>,
+    next_id: u64,
+
+    /// Flows ordered by when they were last seen, for LRU eviction.
+    lru: BTreeMap<(Duration, u64), FlowKey>,
+    budget: MemoryBudget,
+    evictions: u64,
+    notes: Vec<Info>,
+}
+
+impl FlowTable {
+    pub fn new() -> FlowTable {
+        FlowTable::with_budget(MemoryBudget::unlimited())
+    }
+
+    pub fn with_budget(budget: MemoryBudget) -> FlowTable {
+        FlowTable {
+            flows: HashMap::new(),
+            next_id: 0,
+            lru: BTreeMap::new(),
+            budget: budget,
+            evictions: 0,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Account for a dissected packet captured at `timestamp`, returning the
+    /// flow it belongs to (or `None` if it isn't TCP or UDP over IP).
+    pub fn track(&mut self, timestamp: Duration, packet: &Val) -> Option<&Flow> {
+        let key = match FlowKey::from_val(packet) {
+            Some(key) => key,
+            None => return None,
+        };
+
+        let (key, from_orig) =
+            if self.flows.contains_key(&key) { (key, true) }
+            else if self.flows.contains_key(&key.reversed()) { (key.reversed(), false) }
+            else {
+                self.budget.charge(FLOW_SIZE);
+                while self.budget.is_exceeded() && !self.lru.is_empty() {
+                    self.evict_oldest();
+                }
+
+                let id = self.next_id;
+                self.next_id += 1;
+                self.lru.insert((timestamp, id), key);
+                self.flows.insert(key, Flow {
+                    id: id,
+                    key: key,
+                    first_seen: timestamp,
+                    last_seen: timestamp,
+                    orig_packets: 0,
+                    resp_packets: 0,
+                    orig_bytes: 0,
+                    resp_bytes: 0,
+                    orig_flags: 0,
+                    resp_flags: 0,
+                    service: None,
+                });
+                (key, true)
+            };
+
+        let bytes = payload_length(packet);
+        let flags = packet.layer("TCP")
+            .and_then(|tcp| tcp.get("Flags").ok())
+            .and_then(|f| f.as_bitflags())
+            .map(|(flags, _)| flags as u8)
+            .unwrap_or(0);
+
+        let flow = self.flows.get_mut(&key).unwrap();
+        if timestamp > flow.last_seen {
+            self.lru.remove(&(flow.last_seen, flow.id));
+            self.lru.insert((timestamp, flow.id), key);
+            flow.last_seen = timestamp;
+        }
+
+        if from_orig {
+            flow.orig_packets += 1;
+            flow.orig_bytes += bytes;
+            flow.orig_flags |= flags;
+        } else {
+            flow.resp_packets += 1;
+            flow.resp_bytes += bytes;
+            flow.resp_flags |= flags;
+        }
+
+        if flow.service.is_none() {
+            flow.service = service(packet);
+        }
+
+        Some(flow)
+    }
+
+    /// All flows, in the order they were first seen.
+    pub fn flows(&self) -> Vec<&Flow> {
+        let mut flows = self.flows.values().collect::<Vec<_>>();
+        flows.sort_by_key(|f| f.id);
+        flows
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Remove and return flows that have been idle for at least `timeout` as of
+    /// `now`, which should come from a `replay::Clock`.
+    pub fn expire(&mut self, now: Duration, timeout: Duration) -> Vec<Flow> {
+        let mut expired = Vec::new();
+
+        loop {
+            let oldest = match self.lru.keys().next() {
+                Some(&(last_seen, id)) if last_seen + timeout <= now => (last_seen, id),
+                _ => break,
+            };
+
+            let key = self.lru.remove(&oldest).unwrap();
+            expired.extend(self.flows.remove(&key));
+            self.budget.release(FLOW_SIZE);
+        }
+
+        expired
+    }
+
+    /// Number of flows evicted to stay within the memory budget.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Expert notes about the flows evicted, oldest first.
+    pub fn notes(&self) -> &[Info] {
+        &self.notes
+    }
+
+    /// Take the expert notes about the flows evicted so far.
+    pub fn take_notes(&mut self) -> Vec<Info> {
+        self.notes.split_off(0)
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = match self.lru.keys().next() {
+            Some(&oldest) => oldest,
+            None => return,
+        };
+
+        let key = self.lru.remove(&oldest).unwrap();
+        self.flows.remove(&key);
+        self.budget.release(FLOW_SIZE);
+        self.evictions += 1;
+        self.notes.push(budget::note(key.transport(),
+            format!["flow {} evicted to stay within the memory budget", key]));
+    }
+}
+
+impl Drop for FlowTable {
+    fn drop(&mut self) {
+        self.budget.release(self.flows.len() * FLOW_SIZE);
+    }
+}
+
+/// A flow table can be registered as a tap on the `"IPv4"` layer.
+impl Tap for FlowTable {
+    fn tap(&mut self, info: &PacketInfo, _: &Val) {
+        self.track(info.timestamp, info.packet);
+    }
+}
+
+/// Convert the bytes of an IPv4 or IPv6 address to an `IpAddr`.
+pub fn ip_addr(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))),
+        16 => {
+            let mut words = [0u16; 8];
+            for (i, w) in bytes.chunks(2).enumerate() {
+                words[i] = (w[0] as u16) << 8 | w[1] as u16;
+            }
+            Some(IpAddr::V6(Ipv6Addr::new(words[0], words[1], words[2], words[3],
+                                          words[4], words[5], words[6], words[7])))
+        },
+        _ => None,
+    }
+}
+
+/// Length of the transport-layer payload, derived from header length fields.
+fn payload_length(packet: &Val) -> u64 {
+    let field = |layer: &str, name: &str| packet.layer(layer)
+        .and_then(|l| l.get(name).ok())
+        .and_then(|v| v.as_unsigned())
+        .unwrap_or(0);
+
+    if packet.layer("TCP").is_none() {
+        return field("UDP", "Length").saturating_sub(8);
+    }
+
+    let tcp_header = 4 * field("TCP", "Offset");
+    match packet.layer("IPv6") {
+        // The payload length includes any extension headers, so with them
+        // the segment is whatever was left after them.
+        Some(ipv6) if ipv6.get("Extension Header").is_ok() => match ipv6.get("Payload") {
+            Ok(&Val::Payload(segment, _)) => (segment.len() as u64).saturating_sub(tcp_header),
+            _ => 0,
+        },
+        Some(_) => field("IPv6", "Payload Length").saturating_sub(tcp_header),
+        None => field("IPv4", "Length").saturating_sub(4 * field("IPv4", "IHL") + tcp_header),
+    }
+}
+
+/// The name of the first layer above TCP or UDP, if it was dissected.
+fn service(packet: &Val) -> Option<&'static str> {
+    let transport = match packet.layer("TCP").or(packet.layer("UDP")) {
+        Some(t) => t,
+        None => return None,
+    };
+
+    match transport.get("Payload") {
+        Ok(&Val::Payload(_, Ok(ref app))) => match **app {
+            Val::Object("Data", _) => None,
+            Val::Object(name, _) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ethernet;
+    use ip;
+    use replay::{Clock, Replay};
+
+    // 192.168.1.115:64747 -> 46.137.186.243:443 [SYN] and the [SYN, ACK] reply
+    const SYN: [u8; 54] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+        0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x00, 0x00, 0x40, 0x06, 0x00, 0x00,
+        192, 168, 1, 115, 46, 137, 186, 243,
+        0xfc, 0xeb, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00];
+
+    const SYN_ACK: [u8; 58] = [
+        0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x08, 0x00,
+        0x45, 0x00, 0x00, 0x2c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x06, 0x00, 0x00,
+        46, 137, 186, 243, 192, 168, 1, 115,
+        0x01, 0xbb, 0xfc, 0xeb, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x02,
+        0x50, 0x12, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xde, 0xad, 0xbe, 0xef];
+
+    #[test]
+    fn track_tcp() {
+        let mut table = FlowTable::new();
+
+        table.track(Duration::new(10, 0), &ethernet::dissect(&SYN).unwrap());
+        table.track(Duration::new(10, 500), &ethernet::dissect(&SYN_ACK).unwrap());
+
+        assert_eq!(table.len(), 1);
+
+        let flow = table.flows()[0];
+        assert_eq!(flow.key.source, "192.168.1.115".parse::<IpAddr>().unwrap());
+        assert_eq!(flow.key.source_port, 64747);
+        assert_eq!(flow.key.destination_port, 443);
+        assert_eq!(flow.orig_packets, 1);
+        assert_eq!(flow.resp_packets, 1);
+        assert_eq!(flow.orig_bytes, 0);
+        assert_eq!(flow.resp_bytes, 4);
+        assert_eq!(flow.orig_flags, 0x02);
+        assert_eq!(flow.resp_flags, 0x12);
+        assert_eq!(flow.duration(), Duration::new(0, 500));
+    }
+
+    #[test]
+    fn quoted_flow() {
+        // 10.0.0.254 -> 192.168.1.115: Host Unreachable, quoting the SYN
+        let mut packet = vec![0x45, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00,
+                              10, 0, 0, 254, 192, 168, 1, 115,
+                              3, 1, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&SYN[14..42]);
+        let val = ip::dissect(&packet).unwrap();
+
+        assert_eq!(FlowKey::from_val(&val), None);
+        assert_eq!(FlowKey::quoted(&val),
+                   FlowKey::from_val(&ethernet::dissect(&SYN).unwrap()));
+    }
+
+    #[test]
+    fn evict_least_recently_seen() {
+        let budget = MemoryBudget::new(2 * FLOW_SIZE);
+        let mut table = FlowTable::with_budget(budget.clone());
+
+        let mut syn = SYN;
+        for port in 0..3 {
+            syn[35] = port;
+            table.track(Duration::new(port as u64, 0), &ethernet::dissect(&syn).unwrap());
+        }
+
+        // Refresh the second flow, making the third the least recently seen
+        syn[35] = 1;
+        table.track(Duration::new(4, 0), &ethernet::dissect(&syn).unwrap());
+
+        syn[35] = 3;
+        table.track(Duration::new(5, 0), &ethernet::dissect(&syn).unwrap());
+
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.evictions(), 2);
+        assert_eq!(table.notes()[0].code, "budget_exceeded");
+        assert_eq!(table.notes()[0].to_string(),
+                   "Warn: TCP: flow 192.168.1.115:64512 -> 46.137.186.243:443 evicted to stay \
+                    within the memory budget");
+        assert_eq!(table.flows().iter().map(|f| f.key.source_port & 0xff).collect::<Vec<_>>(),
+                   vec![1, 3]);
+        assert_eq!(budget.used(), 2 * FLOW_SIZE);
+
+        drop(table);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn expire_idle_flows() {
+        let mut replay = Replay::new(vec![(Duration::new(10, 0), &SYN[..]),
+                                          (Duration::new(70, 0), &SYN_ACK[..])].into_iter());
+        let clock = replay.clock();
+        let mut table = FlowTable::new();
+
+        let (timestamp, data) = replay.next().unwrap();
+        table.track(timestamp, &ethernet::dissect(data).unwrap());
+        assert!(table.expire(clock.now(), Duration::new(30, 0)).is_empty());
+
+        replay.next();
+        let expired = table.expire(clock.now(), Duration::new(30, 0));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].key.destination_port, 443);
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn track_non_ip() {
+        let mut table = FlowTable::new();
+        assert!(table.track(Duration::new(0, 0), &Val::Unsigned(4)).is_none());
+        assert_eq!(table.len(), 0);
+    }
+}
@@ -0,0 +1,460 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Heuristic dissection of TCP and UDP payloads that no port-based dissector
+//! has claimed.
+//!
+//! The classifier looks for a few common shapes (TLS records, HTTP messages,
+//! DNS headers and printable line-based protocols) and dissects just enough
+//! of the payload to make it legible, including HTTP bodies of a content
+//! type with a dissector in `payload::Contents`. Every object produced here
+//! begins with a `Heuristic` field naming the pattern that matched, since
+//! the guess may be wrong.
+//!
+//! When a payload has more than one plausible shape, each candidate is
+//! scored by how well the payload fits it (scaled by tunable `Weights`) and
+//! the best one wins, with ties going to the candidate declared first in
+//! `Content`. The losers are listed in `Rejected Heuristic` fields, so that a
+//! misclassification can be seen (and the weights adjusted) rather than
+//! passing silently. Plain text is only a fallback, for payloads that fit
+//! nothing more specific.
+
+use Endianness;
+use DissectError;
+use DissectResult;
+use Val;
+use NamedValues;
+use conformance;
+use expert::{self, Severity};
+use payload::Contents;
+use unsigned;
+
+/// A kind of content recognized by the classifier, in order of priority.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Content {
+    Tls,
+    Http,
+    Dns,
+    Text,
+}
+
+impl Content {
+    /// A description of the pattern that identifies this content.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            &Content::Tls => "TLS record pattern",
+            &Content::Http => "HTTP method prefix",
+            &Content::Dns => "DNS header shape",
+            &Content::Text => "printable ASCII lines",
+        }
+    }
+}
+
+/// A plausible classification of a payload and how well the payload fits it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    pub content: Content,
+    pub score: u32,
+}
+
+/// Percentages by which candidates' scores are scaled (100 by default).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weights {
+    weights: [u32; 4],
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights { weights: [100; 4] }
+    }
+}
+
+impl Weights {
+    pub fn set(mut self, content: Content, percent: u32) -> Weights {
+        self.weights[content as usize] = percent;
+        self
+    }
+
+    pub fn get(&self, content: Content) -> u32 {
+        self.weights[content as usize]
+    }
+}
+
+const HTTP_PREFIXES: [&'static [u8]; 10] = [
+    b"GET ", b"POST ", b"HEAD ", b"PUT ", b"DELETE ", b"OPTIONS ", b"CONNECT ",
+    b"PATCH ", b"TRACE ", b"HTTP/1.",
+];
+
+/// Guess what a payload contains. DNS is only considered for datagrams,
+/// since DNS over TCP has an additional length prefix.
+pub fn classify(data: &[u8], datagram: bool) -> Option<Content> {
+    candidates(data, datagram, &Weights::default()).first().map(|c| c.content)
+}
+
+/// Every plausible classification of a payload, best first.
+pub fn candidates(data: &[u8], datagram: bool, weights: &Weights) -> Vec<Candidate> {
+    let mut scores = Vec::new();
+    if looks_like_tls(data) {
+        scores.push((Content::Tls, if tls_records_end(data) == data.len() { 90 } else { 60 }));
+    }
+
+    if HTTP_PREFIXES.iter().any(|p| data.starts_with(p)) {
+        scores.push((Content::Http, if data.windows(2).any(|w| w == b"\r\n") { 90 } else { 70 }));
+    }
+
+    if datagram && looks_like_dns(data) {
+        scores.push((Content::Dns, if dns_question_end(data) == Some(data.len()) { 70 } else { 50 }));
+    }
+
+    if scores.is_empty() && looks_like_text(data) {
+        scores.push((Content::Text, 10));
+    }
+
+    let mut candidates = scores.into_iter()
+        .map(|(content, score)| Candidate { content: content, score: score * weights.get(content) / 100 })
+        .filter(|c| c.score > 0)
+        .collect::<Vec<_>>();
+
+    // Sorting is stable, so ties keep the order of `Content`.
+    candidates.sort_by(|a, b| b.score.cmp(&a.score));
+    candidates
+}
+
+/// Dissect a payload as its best candidate, if it has any, noting the
+/// candidates that lost.
+pub fn dissect_best<'data>(data: &'data [u8], datagram: bool, weights: &Weights)
+    -> Option<DissectResult<'data>> {
+
+    let candidates = candidates(data, datagram, weights);
+    let best = match candidates.first() {
+        Some(best) => best.content,
+        None => return None,
+    };
+
+    Some(dissect(data, best).map(|mut val| {
+        if let Val::Object(_, ref mut values) = *val {
+            for (i, loser) in candidates[1..].iter().enumerate() {
+                values.insert(1 + i, ("Rejected Heuristic", Val::String(format!["{} (score {} < {})",
+                    loser.content.pattern(), loser.score, candidates[0].score])));
+            }
+        }
+        val
+    }))
+}
+
+/// Dissect a payload according to its (guessed) content.
+pub fn dissect(data: &[u8], content: Content) -> DissectResult {
+    let mut values = NamedValues::new();
+    values.push(("Heuristic", Val::Symbol(content.pattern())));
+
+    let name = match content {
+        Content::Tls => { tls(data, &mut values)?; "TLS" },
+        Content::Http => { http(data, &mut values); "HTTP" },
+        Content::Dns => { dns(data, &mut values)?; "DNS" },
+        Content::Text => { text(data, &mut values); "Text" },
+    };
+
+    Ok(Box::new(Val::Object(name, values)))
+}
+
+fn looks_like_tls(data: &[u8]) -> bool {
+    data.len() >= 5
+        && data[0] >= 20 && data[0] <= 23
+        && data[1] == 3 && data[2] <= 4
+        && unsigned(&data[3..5], Endianness::BigEndian).unwrap() <= 16384 + 2048
+}
+
+/// The end of the last whole TLS record at the start of `data`.
+fn tls_records_end(data: &[u8]) -> usize {
+    let mut offset = 0;
+    while looks_like_tls(&data[offset..]) {
+        let length = unsigned(&data[offset + 3..offset + 5], Endianness::BigEndian).unwrap() as usize;
+        if offset + 5 + length > data.len() {
+            break;
+        }
+        offset += 5 + length;
+    }
+
+    offset
+}
+
+/// The end of a DNS message's first question.
+fn dns_question_end(data: &[u8]) -> Option<usize> {
+    let mut offset = 12;
+    while offset < data.len() {
+        match data[offset] as usize {
+            0 => return Some(offset + 5),
+            len if len < 64 => offset += 1 + len,
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+fn looks_like_dns(data: &[u8]) -> bool {
+    if data.len() < 12 {
+        return false;
+    }
+
+    let count = |i: usize| unsigned(&data[i..i + 2], Endianness::BigEndian).unwrap();
+    let opcode = (data[2] >> 3) & 0x0f;
+    let z = data[3] & 0x40;
+
+    if z != 0 || ![0, 1, 2, 4, 5].contains(&opcode) || count(4) == 0 || count(4) > 8
+        || count(6) > 256 || count(8) > 256 || count(10) > 256 {
+        return false;
+    }
+
+    // The first question's name should be a sequence of short labels.
+    match dns_question_end(data) {
+        Some(end) => end <= data.len(),
+        None => false,
+    }
+}
+
+fn looks_like_text(data: &[u8]) -> bool {
+    data.len() >= 4
+        && data.contains(&b'\n')
+        && data.iter().all(|&b| (b >= 0x20 && b < 0x7f) || b == b'\r' || b == b'\n' || b == b'\t')
+}
+
+fn tls<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    let mut offset = 0;
+
+    while offset + 5 <= data.len() {
+        let record = &data[offset..];
+        let length = unsigned(&record[3..5], Endianness::BigEndian).unwrap() as usize;
+
+        let mut fields = NamedValues::new();
+        fields.push(("Content Type", Val::Unsigned(record[0] as u64)));
+        fields.push(("Content Type Name", Val::Symbol(match record[0] {
+            20 => "ChangeCipherSpec",
+            21 => "Alert",
+            22 => "Handshake",
+            23 => "Application Data",
+            _ => "Unknown",
+        })));
+        let version = unsigned(&record[1..3], Endianness::BigEndian).unwrap();
+        fields.push(("Version", Val::Unsigned(version)));
+
+        // Hellos may carry 0x0301 for compatibility, but other records carry
+        // the negotiated version.
+        if version < 0x0301 || (version < 0x0303 && record[0] != 22) {
+            expert::add(&mut fields, "Version", "tls.deprecated_version", Severity::Warn,
+                        format!["deprecated protocol version 0x{:04x}", version]);
+        }
+        fields.push(("Length", Val::Unsigned(length as u64)));
+
+        // Encryption may expand application data beyond the plaintext limit.
+        let limit = if record[0] == 23 { 16384 + 2048 } else { 16384 };
+        if length > limit {
+            conformance::warn(&mut fields, format!["TLS record length {} exceeds {} B", length, limit]);
+        }
+
+        // Records may continue in the next segment.
+        let end = if 5 + length <= record.len() { 5 + length } else { record.len() };
+        if record[0] == 22 && end > 5 {
+            fields.push(("Handshake Type", Val::Unsigned(record[5] as u64)));
+        }
+        fields.push(("Fragment", Val::Undissected("TLS", &record[5..end])));
+
+        values.push(("Record", Val::Object("TLS Record", fields)));
+        offset += end;
+    }
+
+    if offset == 0 {
+        return Err(DissectError::Underflow { expected: Some(5), have: data.len(),
+            message: "A TLS record must be at least 5 B".to_string() });
+    }
+
+    if offset < data.len() {
+        values.push(("Trailing Data", Val::Bytes(&data[offset..])));
+    }
+
+    Ok(())
+}
+
+fn http<'data>(data: &'data [u8], values: &mut NamedValues<'data>) {
+    let mut lines = Lines { data: data, offset: 0 };
+
+    if let Some(start) = lines.next() {
+        let start = String::from_utf8_lossy(start);
+        let mut parts = start.splitn(3, ' ');
+        let mut next = || parts.next().unwrap_or("").to_string();
+
+        if start.starts_with("HTTP/") {
+            values.push(("Version", Val::String(next())));
+            values.push(("Status Code", Val::String(next())));
+            values.push(("Reason", Val::String(next())));
+        } else {
+            values.push(("Method", Val::String(next())));
+            values.push(("URI", Val::String(next())));
+            values.push(("Version", Val::String(next())));
+        }
+    }
+
+    let mut content_type = None;
+    while let Some(line) = lines.next() {
+        if line.is_empty() {
+            break;
+        }
+
+        let header = String::from_utf8_lossy(line).to_string();
+        let mut parts = header.splitn(2, ':');
+        if parts.next().unwrap_or("").trim().eq_ignore_ascii_case("Content-Type") {
+            content_type = parts.next().map(|t| t.trim().to_string());
+        }
+        values.push(("Header", Val::String(header)));
+    }
+
+    if lines.offset < data.len() {
+        let body = &data[lines.offset..];
+        values.push(("Body", match content_type.and_then(|t| Contents::new().dissect(&t, body)) {
+            Some(result) => Val::Payload(body, result),
+            None => Val::Bytes(body),
+        }));
+    }
+}
+
+fn dns<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    if data.len() < 12 {
+        return Err(DissectError::Underflow { expected: Some(12), have: data.len(),
+            message: "A DNS header must be 12 B".to_string() });
+    }
+
+    let field = |i: usize| unsigned(&data[i..i + 2], Endianness::BigEndian).unwrap();
+
+    values.push(("Transaction ID", Val::Unsigned(field(0))));
+    values.push(("Response", Val::Unsigned((data[2] >> 7) as u64)));
+    values.push(("Opcode", Val::Unsigned(((data[2] >> 3) & 0x0f) as u64)));
+    values.push(("Response Code", Val::Unsigned((data[3] & 0x0f) as u64)));
+    values.push(("Questions", Val::Unsigned(field(4))));
+    values.push(("Answers", Val::Unsigned(field(6))));
+    values.push(("Authority Records", Val::Unsigned(field(8))));
+    values.push(("Additional Records", Val::Unsigned(field(10))));
+
+    // The first question name, as dotted labels
+    let mut labels = Vec::new();
+    let mut offset = 12;
+    while offset < data.len() && data[offset] != 0 {
+        let len = data[offset] as usize;
+
+        // Two high bits set indicate a compression pointer, not a label.
+        if len & 0xc0 == 0xc0 {
+            break;
+        } else if len > 63 {
+            conformance::warn(values, format!["DNS label length {} exceeds 63 B", len]);
+            break;
+        }
+
+        if offset + 1 + len > data.len() {
+            break;
+        }
+
+        labels.push(String::from_utf8_lossy(&data[offset + 1..offset + 1 + len]).to_string());
+        offset += 1 + len;
+    }
+    values.push(("Query Name", Val::String(labels.join("."))));
+    values.push(("Records", Val::Undissected("DNS records", &data[12..])));
+
+    Ok(())
+}
+
+fn text<'data>(data: &'data [u8], values: &mut NamedValues<'data>) {
+    for line in (Lines { data: data, offset: 0 }) {
+        values.push(("Line", Val::String(String::from_utf8_lossy(line).to_string())));
+    }
+}
+
+/// Iterator over CRLF- or LF-terminated lines.
+struct Lines<'data> {
+    data: &'data [u8],
+    offset: usize,
+}
+
+impl<'data> Iterator for Lines<'data> {
+    type Item = &'data [u8];
+
+    fn next(&mut self) -> Option<&'data [u8]> {
+        let rest = &self.data[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (line, consumed) = match rest.iter().position(|&b| b == b'\n') {
+            Some(i) if i > 0 && rest[i - 1] == b'\r' => (&rest[..i - 1], i + 1),
+            Some(i) => (&rest[..i], i + 1),
+            None => (rest, rest.len()),
+        };
+
+        self.offset += consumed;
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classify_payloads() {
+        assert_eq!(classify(&[0x16, 0x03, 0x01, 0x00, 0x02, 0x01, 0x00], false), Some(Content::Tls));
+        assert_eq!(classify(b"GET / HTTP/1.1\r\n\r\n", false), Some(Content::Http));
+        assert_eq!(classify(b"EHLO example.com\r\n", false), Some(Content::Text));
+        assert_eq!(classify(&[0xde, 0xad, 0xbe, 0xef], true), None);
+
+        let dns = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0,
+                   3, b'w', b'w', b'w', 0, 0, 1, 0, 1];
+        assert_eq!(classify(&dns, true), Some(Content::Dns));
+        assert_eq!(classify(&dns, false), None);
+
+        let mut long_label = dns.to_vec();
+        long_label[12] = 64;
+        let val = *dissect(&long_label, Content::Dns).unwrap();
+        assert_eq!(val["Conformance Warning"].as_string().unwrap(), "DNS label length 64 exceeds 63 B");
+    }
+
+    #[test]
+    fn score_conflicts() {
+        // A zero-length TLS record, or a DNS query for "www"
+        let ambiguous = [0x16, 0x03, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0,
+                         3, b'w', b'w', b'w', 0, 0, 1, 0, 1];
+        let scores = candidates(&ambiguous, true, &Weights::default());
+        assert_eq!(scores, vec![Candidate { content: Content::Dns, score: 70 },
+                                Candidate { content: Content::Tls, score: 60 }]);
+
+        let val = *dissect_best(&ambiguous, true, &Weights::default()).unwrap().unwrap();
+        assert_eq!(val.as_object().unwrap().0, "DNS");
+        assert_eq!(val["Rejected Heuristic"].as_string().unwrap(), "TLS record pattern (score 60 < 70)");
+
+        // Weighting TLS up changes the winner.
+        let weights = Weights::default().set(Content::Tls, 150);
+        assert_eq!(classify(&ambiguous, true), Some(Content::Dns));
+        assert_eq!(candidates(&ambiguous, true, &weights)[0].content, Content::Tls);
+        assert!(dissect_best(&[0xde, 0xad], false, &weights).is_none());
+    }
+
+    #[test]
+    fn dissect_http() {
+        let data = b"HTTP/1.1 404 Not Found\r\nContent-Length: 2\r\n\r\nno";
+        let val = *dissect(data, Content::Http).unwrap();
+        println!("{}", &val.pretty_print(0));
+
+        assert_eq!(val["Heuristic"].as_symbol().unwrap(), "HTTP method prefix");
+        assert_eq!(val["Status Code"].as_string().unwrap(), "404");
+        assert_eq!(val["Reason"].as_string().unwrap(), "Not Found");
+        assert_eq!(val["Header"].as_string().unwrap(), "Content-Length: 2");
+        assert_eq!(val["Body"].as_bytes().unwrap(), b"no");
+
+        let data = b"POST /api HTTP/1.1\r\ncontent-type: application/json\r\n\r\n[true]";
+        let val = *dissect(data, Content::Http).unwrap();
+        assert_eq!(val["Body"]["Value"]["Element"].as_symbol().unwrap(), "true");
+    }
+}
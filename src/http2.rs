@@ -0,0 +1,199 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of HTTP/2 framing, as used in cleartext ("h2c") by gRPC.
+//!
+//! A client connection starts with a fixed preface, after which both sides
+//! send frames: a 9 B header (length, type, flags and stream identifier)
+//! and a payload. Header blocks are HPACK-compressed and are not
+//! decompressed, but a `content-type` of `application/grpc` sent as a
+//! literal is recognized. DATA frames carry gRPC messages (see
+//! `payload::grpc`) when a HEADERS frame in the same segment says so, or when
+//! their contents have the shape of length-prefixed gRPC messages.
+//!
+//! See [RFC 7540](https://tools.ietf.org/html/rfc7540).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use payload::grpc;
+use read_be_u32;
+
+enum_map!(pub FRAME_TYPES {
+    0 => "DATA",
+    1 => "HEADERS",
+    2 => "PRIORITY",
+    3 => "RST_STREAM",
+    4 => "SETTINGS",
+    5 => "PUSH_PROMISE",
+    6 => "PING",
+    7 => "GOAWAY",
+    8 => "WINDOW_UPDATE",
+    9 => "CONTINUATION",
+});
+
+enum_map!(pub SETTINGS {
+    1 => "HEADER_TABLE_SIZE",
+    2 => "ENABLE_PUSH",
+    3 => "MAX_CONCURRENT_STREAMS",
+    4 => "INITIAL_WINDOW_SIZE",
+    5 => "MAX_FRAME_SIZE",
+    6 => "MAX_HEADER_LIST_SIZE",
+});
+
+enum_map!(pub ERROR_CODES {
+    0 => "NO_ERROR",
+    1 => "PROTOCOL_ERROR",
+    2 => "INTERNAL_ERROR",
+    3 => "FLOW_CONTROL_ERROR",
+    4 => "SETTINGS_TIMEOUT",
+    5 => "STREAM_CLOSED",
+    6 => "FRAME_SIZE_ERROR",
+    7 => "REFUSED_STREAM",
+    8 => "CANCEL",
+    9 => "COMPRESSION_ERROR",
+    10 => "CONNECT_ERROR",
+    11 => "ENHANCE_YOUR_CALM",
+    12 => "INADEQUATE_SECURITY",
+    13 => "HTTP_1_1_REQUIRED",
+});
+
+pub const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0;
+const HEADERS: u8 = 1;
+const PADDED: u8 = 0x08;
+const PRIORITY: u8 = 0x20;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+    let mut offset = 0;
+
+    if data.starts_with(PREFACE) {
+        values.push(("Preface", Val::Bytes(PREFACE)));
+        offset = PREFACE.len();
+    }
+
+    let grpc_headers = data.windows(16).any(|w| w == b"application/grpc");
+
+    while offset < data.len() {
+        let (length, frame) = frame(&data[offset..], grpc_headers)?;
+        values.push(("Frame", frame));
+        offset += length;
+    }
+
+    Ok(Box::new(Val::Object("HTTP/2", values)))
+}
+
+/// Dissect the frame at the start of `data`, returning its length.
+fn frame(data: &[u8], grpc_headers: bool) -> Result<(usize, Val), DissectError> {
+    if data.len() < 9 {
+        return Err(DissectError::Underflow { expected: Some(9), have: data.len(),
+            message: "An HTTP/2 frame header must be 9 B".to_string() });
+    }
+
+    let length = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    let frame_type = data[3];
+    let flags = data[4];
+    let stream = read_be_u32(data, 5)? & 0x7fff_ffff;
+
+    let mut values = NamedValues::new();
+    values.push(("Length", Val::Unsigned(length as u64)));
+    values.push(("Type", Val::Unsigned(frame_type as u64)));
+    values.push(("Type Name", FRAME_TYPES.val(frame_type)));
+    values.push(("Flags", Val::BitFlags8(flags, match frame_type {
+        DATA => [Some("END_STREAM"), None, None, Some("PADDED"), None, None, None, None],
+        HEADERS => [Some("END_STREAM"), None, Some("END_HEADERS"), Some("PADDED"),
+                    None, Some("PRIORITY"), None, None],
+        4 | 6 => [Some("ACK"), None, None, None, None, None, None, None],
+        5 | 9 => [None, None, Some("END_HEADERS"), Some("PADDED"), None, None, None, None],
+        _ => [None; 8],
+    })));
+    values.push(("Stream Identifier", Val::Unsigned(stream as u64)));
+
+    // The last frame may continue in the next segment.
+    let end = (9 + length).min(data.len());
+    let mut payload = &data[9..end];
+
+    // Padding (and, for HEADERS, priority) precede the content.
+    if (frame_type == DATA || frame_type == HEADERS || frame_type == 5) && flags & PADDED != 0 {
+        let padding = payload.first().cloned().unwrap_or(0) as usize;
+        values.push(("Pad Length", Val::Unsigned(padding as u64)));
+        let content = payload.get(1..).unwrap_or(&[]);
+        payload = &content[..content.len().saturating_sub(padding)];
+    }
+    if frame_type == HEADERS && flags & PRIORITY != 0 && payload.len() >= 5 {
+        let dependency = read_be_u32(payload, 0)? & 0x7fff_ffff;
+        values.push(("Stream Dependency", Val::Unsigned(dependency as u64)));
+        values.push(("Weight", Val::Unsigned(payload[4] as u64 + 1)));
+        payload = &payload[5..];
+    }
+
+    match frame_type {
+        DATA if !payload.is_empty() => values.push(("Data",
+            if grpc_headers || grpc::looks_like_grpc(payload) {
+                Val::Payload(payload, grpc::dissect(payload))
+            } else {
+                Val::Bytes(payload)
+            })),
+        HEADERS | 9 => values.push(("Header Block Fragment", Val::Bytes(payload))),
+        3 => values.push(("Error Code", ERROR_CODES.val(read_be_u32(payload, 0)?))),
+        4 => for setting in payload.chunks(6).filter(|s| s.len() == 6) {
+            let mut fields = NamedValues::new();
+            let id = (setting[0] as u16) << 8 | setting[1] as u16;
+            fields.push(("Identifier", SETTINGS.val(id)));
+            fields.push(("Value", Val::Unsigned(read_be_u32(setting, 2)? as u64)));
+            values.push(("Setting", Val::Object("HTTP/2 Setting", fields)));
+        },
+        6 => values.push(("Opaque Data", Val::Bytes(payload))),
+        7 => {
+            let last = read_be_u32(payload, 0)? & 0x7fff_ffff;
+            values.push(("Last Stream ID", Val::Unsigned(last as u64)));
+            values.push(("Error Code", ERROR_CODES.val(read_be_u32(payload, 4)?)));
+            if payload.len() > 8 {
+                values.push(("Debug Data", Val::Bytes(&payload[8..])));
+            }
+        },
+        8 => values.push(("Window Size Increment",
+                          Val::Unsigned((read_be_u32(payload, 0)? & 0x7fff_ffff) as u64))),
+        _ if !payload.is_empty() => values.push(("Payload", Val::Bytes(payload))),
+        _ => {},
+    }
+
+    Ok((end, Val::Object("HTTP/2 Frame", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_grpc_call() {
+        let mut data = PREFACE.to_vec();
+
+        // SETTINGS: MAX_CONCURRENT_STREAMS = 100
+        data.extend_from_slice(&[0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 100]);
+
+        // DATA on stream 1, ending it: one uncompressed 3 B gRPC message
+        data.extend_from_slice(&[0, 0, 8, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 3, 0x08, 0x96, 0x01]);
+
+        let val = *dissect(&data).unwrap();
+        assert!(val["Preface"].is_bytes());
+        assert_eq!(val["Frame"]["Setting"]["Identifier"].as_symbol().unwrap(),
+                   "MAX_CONCURRENT_STREAMS");
+
+        let data_frame = &val.as_object().unwrap().1[2].1;
+        assert_eq!(data_frame["Type Name"].as_symbol().unwrap(), "DATA");
+        assert_eq!(data_frame["Flags"].as_bitflags8_bit_name("END_STREAM"), Some(true));
+        assert_eq!(data_frame["Data"]["Message"]["Length"].as_unsigned().unwrap(), 3);
+
+        assert!(dissect(&[0, 0, 4]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Names for numbers assigned by the IANA (and, for EtherTypes, the IEEE):
+//! IP protocol numbers, EtherTypes and well-known transport ports.
+//!
+//! Dissectors use these tables to make `Val::Enum`s, which keep the number
+//! and render as, e.g., "TCP (6)". They cover the common assignments, not
+//! the full registries.
+//!
+//! ```
+//! use rshark::iana;
+//!
+//! assert_eq!(iana::PROTOCOLS.name(6u8), Some("TCP"));
+//! assert_eq!(iana::PROTOCOLS.enum_val(6u8).to_string(), "TCP (6)");
+//! assert_eq!(iana::PORTS.name(443u16), Some("https"));
+//! ```
+//!
+//! See the [protocol numbers](https://www.iana.org/assignments/protocol-numbers),
+//! [EtherTypes](https://www.iana.org/assignments/ieee-802-numbers) and
+//! [service names and ports](https://www.iana.org/assignments/service-names-port-numbers)
+//! registries.
+
+enum_map!(pub PROTOCOLS {
+    0 => "HOPOPT",
+    1 => "ICMP",
+    2 => "IGMP",
+    4 => "IPv4",
+    6 => "TCP",
+    8 => "EGP",
+    9 => "IGP",
+    17 => "UDP",
+    27 => "RDP",
+    33 => "DCCP",
+    41 => "IPv6",
+    43 => "IPv6-Route",
+    44 => "IPv6-Frag",
+    46 => "RSVP",
+    47 => "GRE",
+    50 => "ESP",
+    51 => "AH",
+    58 => "IPv6-ICMP",
+    59 => "IPv6-NoNxt",
+    60 => "IPv6-Opts",
+    88 => "EIGRP",
+    89 => "OSPF",
+    94 => "IPIP",
+    97 => "ETHERIP",
+    103 => "PIM",
+    112 => "VRRP",
+    115 => "L2TP",
+    132 => "SCTP",
+    135 => "Mobility Header",
+    136 => "UDPLite",
+    137 => "MPLS-in-IP",
+    143 => "Ethernet",
+});
+
+enum_map!(pub ETHERTYPES {
+    0x0800 => "IPv4",
+    0x0806 => "ARP",
+    0x0842 => "Wake-on-LAN",
+    0x22f0 => "AVTP",
+    0x6558 => "Transparent Ethernet Bridging",
+    0x8035 => "RARP",
+    0x809b => "AppleTalk",
+    0x8100 => "802.1Q",
+    0x8137 => "IPX",
+    0x8138 => "IPX",
+    0x86dd => "IPv6",
+    0x8808 => "Ethernet Flow Control",
+    0x8809 => "Slow Protocols",
+    0x8847 => "MPLS",
+    0x8848 => "MPLS Multicast",
+    0x8863 => "PPPoE Discovery",
+    0x8864 => "PPPoE Session",
+    0x888e => "EAPOL",
+    0x88a8 => "802.1ad",
+    0x88b5 => "Local Experimental",
+    0x88cc => "LLDP",
+    0x88e5 => "MACsec",
+    0x88f7 => "PTP",
+    0x8906 => "FCoE",
+    0x9100 => "VLAN Double Tagging",
+});
+
+enum_map!(pub PORTS {
+    7 => "echo",
+    20 => "ftp-data",
+    21 => "ftp",
+    22 => "ssh",
+    23 => "telnet",
+    25 => "smtp",
+    53 => "domain",
+    67 => "bootps",
+    68 => "bootpc",
+    69 => "tftp",
+    80 => "http",
+    88 => "kerberos",
+    102 => "iso-tsap",
+    110 => "pop3",
+    111 => "sunrpc",
+    123 => "ntp",
+    137 => "netbios-ns",
+    138 => "netbios-dgm",
+    139 => "netbios-ssn",
+    143 => "imap",
+    161 => "snmp",
+    162 => "snmptrap",
+    179 => "bgp",
+    389 => "ldap",
+    443 => "https",
+    445 => "microsoft-ds",
+    500 => "isakmp",
+    514 => "syslog",
+    554 => "rtsp",
+    587 => "submission",
+    636 => "ldaps",
+    853 => "domain-s",
+    993 => "imaps",
+    995 => "pop3s",
+    1080 => "socks",
+    1194 => "openvpn",
+    1433 => "ms-sql-s",
+    1812 => "radius",
+    1900 => "ssdp",
+    1935 => "rtmp",
+    2049 => "nfs",
+    3306 => "mysql",
+    3389 => "ms-wbt-server",
+    4222 => "nats",
+    4500 => "ipsec-nat-t",
+    4789 => "vxlan",
+    5060 => "sip",
+    5353 => "mdns",
+    5432 => "postgresql",
+    5672 => "amqp",
+    6081 => "geneve",
+    6379 => "redis",
+    8080 => "http-alt",
+});
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Val;
+
+    #[test]
+    fn enum_vals() {
+        assert_eq!(PROTOCOLS.enum_val(17u8), Val::Enum { value: 17, name: Some("UDP") });
+        assert_eq!(ETHERTYPES.enum_val(0x86ddu16).to_string(), "IPv6 (34525)");
+        assert_eq!(PORTS.enum_val(40000u16).to_string(), "40000");
+    }
+}
@@ -0,0 +1,514 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of IEEE 802.11 (Wi-Fi) MAC frames.
+//!
+//! Management frames are dissected down to their information elements
+//! (SSID, rates, channel, RSN, ...), control frames to their addresses and
+//! unprotected data frames through LLC into the IP stack. Frames captured in
+//! monitor mode usually arrive with a `radiotap` header in front.
+
+use DissectError;
+use DissectResult;
+use EnumMap;
+use NamedValues;
+use Val;
+use conformance;
+use llc;
+use time::Time;
+use {read_le_u16, read_le_u64};
+
+pub mod radiotap;
+#[cfg(feature = "crypto")]
+pub mod wpa;
+
+enum_map!(pub FRAME_TYPES {
+    0 => "Management",
+    1 => "Control",
+    2 => "Data",
+    3 => "Extension",
+});
+
+enum_map!(pub MANAGEMENT_SUBTYPES {
+    0 => "Association Request",
+    1 => "Association Response",
+    2 => "Reassociation Request",
+    3 => "Reassociation Response",
+    4 => "Probe Request",
+    5 => "Probe Response",
+    6 => "Timing Advertisement",
+    8 => "Beacon",
+    9 => "ATIM",
+    10 => "Disassociation",
+    11 => "Authentication",
+    12 => "Deauthentication",
+    13 => "Action",
+    14 => "Action No Ack",
+});
+
+enum_map!(pub CONTROL_SUBTYPES {
+    4 => "Beamforming Report Poll",
+    5 => "VHT NDP Announcement",
+    7 => "Control Wrapper",
+    8 => "Block Ack Request",
+    9 => "Block Ack",
+    10 => "PS-Poll",
+    11 => "RTS",
+    12 => "CTS",
+    13 => "Ack",
+    14 => "CF-End",
+    15 => "CF-End + CF-Ack",
+});
+
+enum_map!(pub DATA_SUBTYPES {
+    0 => "Data",
+    1 => "Data + CF-Ack",
+    2 => "Data + CF-Poll",
+    3 => "Data + CF-Ack + CF-Poll",
+    4 => "Null",
+    5 => "CF-Ack",
+    6 => "CF-Poll",
+    7 => "CF-Ack + CF-Poll",
+    8 => "QoS Data",
+    9 => "QoS Data + CF-Ack",
+    10 => "QoS Data + CF-Poll",
+    11 => "QoS Data + CF-Ack + CF-Poll",
+    12 => "QoS Null",
+    14 => "QoS CF-Poll",
+    15 => "QoS CF-Ack + CF-Poll",
+});
+
+enum_map!(pub ELEMENTS {
+    0 => "SSID",
+    1 => "Supported Rates",
+    3 => "DS Parameter Set",
+    5 => "Traffic Indication Map",
+    7 => "Country",
+    42 => "ERP Information",
+    45 => "HT Capabilities",
+    48 => "RSN",
+    50 => "Extended Supported Rates",
+    61 => "HT Operation",
+    127 => "Extended Capabilities",
+    191 => "VHT Capabilities",
+    192 => "VHT Operation",
+    221 => "Vendor Specific",
+});
+
+// Cipher suites with the IEEE 802.11 OUI (00-0f-ac)
+enum_map!(pub CIPHER_SUITES {
+    1 => "WEP-40",
+    2 => "TKIP",
+    4 => "CCMP-128",
+    5 => "WEP-104",
+    6 => "BIP-CMAC-128",
+    8 => "GCMP-128",
+    9 => "GCMP-256",
+    10 => "CCMP-256",
+});
+
+// Authentication and key management (AKM) suites with the IEEE 802.11 OUI
+enum_map!(pub AKM_SUITES {
+    1 => "802.1X",
+    2 => "PSK",
+    3 => "FT over 802.1X",
+    4 => "FT PSK",
+    5 => "802.1X SHA-256",
+    6 => "PSK SHA-256",
+    8 => "SAE",
+    18 => "OWE",
+});
+
+const FLAGS: [Option<&'static str>; 8] = [
+    Some("To DS"), Some("From DS"), Some("More Fragments"), Some("Retry"),
+    Some("Power Management"), Some("More Data"), Some("Protected"), Some("Order"),
+];
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    expect(data, 10, "An 802.11 frame")?;
+
+    let mut values = NamedValues::new();
+
+    let frame_control = read_le_u16(data, 0)?;
+    let frame_type = (frame_control >> 2) & 0x03;
+    let subtype = (frame_control >> 4) & 0x0f;
+    let flags = (frame_control >> 8) as u8;
+
+    values.push(("Version", Val::Unsigned((frame_control & 0x03) as u64)));
+    values.push(("Type", FRAME_TYPES.val(frame_type)));
+    values.push(("Subtype", Val::Unsigned(subtype as u64)));
+    values.push(("Subtype Name", match frame_type {
+        0 => MANAGEMENT_SUBTYPES.val(subtype),
+        1 => CONTROL_SUBTYPES.val(subtype),
+        2 => DATA_SUBTYPES.val(subtype),
+        _ => Val::String(format!["Unknown ({})", subtype]),
+    }));
+    values.push(("Flags", Val::BitFlags8(flags, FLAGS)));
+    values.push(("Duration", Val::Unsigned(read_le_u16(data, 2)? as u64)));
+
+    match frame_type {
+        0 => management(data, subtype, flags, &mut values)?,
+        1 => control(data, subtype, &mut values)?,
+        2 => data_frame(data, subtype, flags, &mut values)?,
+        _ => values.push(("Payload", Val::Undissected("802.11 extension frame", &data[10..]))),
+    }
+
+    Ok(Box::new(Val::Object("IEEE 802.11", values)))
+}
+
+fn management<'data>(data: &'data [u8], subtype: u16, flags: u8, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    expect(data, 24, "An 802.11 management frame")?;
+
+    values.push(("Destination", Val::mac(&data[4..10])));
+    values.push(("Source", Val::mac(&data[10..16])));
+    values.push(("BSSID", Val::mac(&data[16..22])));
+    sequence_control(data, values)?;
+
+    // An HT Control field follows if the Order flag is set.
+    let start = if flags & 0x80 != 0 { 28 } else { 24 };
+    expect(data, start, "An 802.11 management frame with HT Control")?;
+    let body = &data[start..];
+
+    if flags & 0x40 != 0 {
+        values.push(("Payload", Val::Undissected("Encrypted 802.11 management frame", body)));
+        return Ok(());
+    }
+
+    let fixed = |length| expect(body, length, "802.11 management frame fixed parameters");
+    let u16_at = |offset| read_le_u16(body, offset).map(|v| Val::Unsigned(v as u64));
+
+    let elements_start = match subtype {
+        0 => {
+            fixed(4)?;
+            capabilities(body, values)?;
+            values.push(("Listen Interval", u16_at(2)?));
+            4
+        },
+        1 | 3 => {
+            fixed(6)?;
+            capabilities(body, values)?;
+            values.push(("Status Code", u16_at(2)?));
+            values.push(("Association ID", Val::Unsigned((read_le_u16(body, 4)? & 0x3fff) as u64)));
+            6
+        },
+        2 => {
+            fixed(10)?;
+            capabilities(body, values)?;
+            values.push(("Listen Interval", u16_at(2)?));
+            values.push(("Current AP", Val::mac(&body[4..10])));
+            10
+        },
+        4 => 0,
+        5 | 8 => {
+            fixed(12)?;
+            // The sender's timer synchronization function (TSF): microseconds
+            // since the BSS started
+            values.push(("Timestamp", Val::Duration(Time::from_micros(read_le_u64(body, 0)?))));
+            values.push(("Beacon Interval", u16_at(8)?));
+            capabilities(&body[10..], values)?;
+            12
+        },
+        10 | 12 => {
+            fixed(2)?;
+            values.push(("Reason Code", u16_at(0)?));
+            return Ok(());
+        },
+        11 => {
+            fixed(6)?;
+            values.push(("Authentication Algorithm", u16_at(0)?));
+            values.push(("Authentication Sequence", u16_at(2)?));
+            values.push(("Status Code", u16_at(4)?));
+            6
+        },
+        13 | 14 => {
+            fixed(1)?;
+            values.push(("Category", Val::Unsigned(body[0] as u64)));
+            values.push(("Action", Val::Bytes(&body[1..])));
+            return Ok(());
+        },
+        _ => {
+            values.push(("Payload", Val::Undissected("802.11 management frame", body)));
+            return Ok(());
+        },
+    };
+
+    elements(&body[elements_start..], values);
+    Ok(())
+}
+
+fn control<'data>(data: &'data [u8], subtype: u16, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    values.push(("Receiver", Val::mac(&data[4..10])));
+
+    // CTS and Ack frames have no transmitter address.
+    let mut end = 10;
+    if [4, 5, 8, 9, 10, 11, 14, 15].contains(&subtype) {
+        expect(data, 16, "An 802.11 control frame")?;
+        values.push(("Transmitter", Val::mac(&data[10..16])));
+        end = 16;
+    }
+
+    if end < data.len() {
+        values.push(("Payload", Val::Undissected("802.11 control frame", &data[end..])));
+    }
+
+    Ok(())
+}
+
+fn data_frame<'data>(data: &'data [u8], subtype: u16, flags: u8, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    expect(data, 24, "An 802.11 data frame")?;
+
+    // The meaning of the addresses depends on the direction of the frame.
+    let names = match flags & 0x03 {
+        0 => ["Destination", "Source", "BSSID"],
+        1 => ["BSSID", "Source", "Destination"],
+        2 => ["Destination", "BSSID", "Source"],
+        _ => ["Receiver", "Transmitter", "Destination"],
+    };
+    values.push((names[0], Val::mac(&data[4..10])));
+    values.push((names[1], Val::mac(&data[10..16])));
+    values.push((names[2], Val::mac(&data[16..22])));
+    sequence_control(data, values)?;
+
+    let mut offset = 24;
+    if flags & 0x03 == 0x03 {
+        expect(data, 30, "A four-address 802.11 data frame")?;
+        values.push(("Source", Val::mac(&data[24..30])));
+        offset = 30;
+    }
+
+    let mut amsdu = false;
+    if subtype & 0x08 != 0 {
+        let qos = read_le_u16(data, offset)?;
+        values.push(("TID", Val::Unsigned((qos & 0x0f) as u64)));
+        amsdu = qos & 0x80 != 0;
+        offset += 2;
+
+        if flags & 0x80 != 0 {
+            expect(data, offset + 4, "An 802.11 data frame with HT Control")?;
+            values.push(("HT Control", Val::Bytes(&data[offset..offset + 4])));
+            offset += 4;
+        }
+    }
+
+    // Null and CF-only subtypes carry no data.
+    if subtype & 0x04 != 0 {
+        return Ok(());
+    }
+
+    let body = &data[offset..];
+    if flags & 0x40 != 0 {
+        security_header(body, values);
+        values.push(("Payload", Val::Undissected("Encrypted 802.11 data", body)));
+    } else if amsdu {
+        values.push(("Payload", Val::Undissected("A-MSDU", body)));
+    } else {
+        values.push(("Payload", Val::Payload(body, llc::dissect(body))));
+    }
+
+    Ok(())
+}
+
+/// The key ID and, for TKIP and CCMP (which use an extended IV), the packet
+/// number of a protected frame.
+fn security_header(body: &[u8], values: &mut NamedValues) {
+    if body.len() < 4 {
+        return;
+    }
+
+    values.push(("Key ID", Val::Unsigned((body[3] >> 6) as u64)));
+
+    if body[3] & 0x20 != 0 && body.len() >= 8 {
+        let pn = [body[7], body[6], body[5], body[4], body[1], body[0]].iter()
+            .fold(0u64, |pn, &b| pn << 8 | b as u64);
+        values.push(("Packet Number", Val::Unsigned(pn)));
+    }
+}
+
+fn sequence_control(data: &[u8], values: &mut NamedValues) -> Result<(), DissectError> {
+    let control = read_le_u16(data, 22)?;
+    values.push(("Fragment Number", Val::Unsigned((control & 0x0f) as u64)));
+    values.push(("Sequence Number", Val::Unsigned((control >> 4) as u64)));
+    Ok(())
+}
+
+fn capabilities(data: &[u8], values: &mut NamedValues) -> Result<(), DissectError> {
+    let capabilities = read_le_u16(data, 0)?;
+    values.push(("Capabilities", Val::BitFlags8(capabilities as u8, [
+                 Some("ESS"), Some("IBSS"), Some("CF-Pollable"), Some("CF-Poll Request"),
+                 Some("Privacy"), Some("Short Preamble"), None, None])));
+    Ok(())
+}
+
+/// Information elements: type-length-value fields in management frames.
+fn elements<'data>(data: &'data [u8], values: &mut NamedValues<'data>) {
+    let mut offset = 0;
+
+    while offset + 2 <= data.len() {
+        let id = data[offset];
+        let length = data[offset + 1] as usize;
+        let start = offset + 2;
+
+        if start + length > data.len() {
+            conformance::warn(values, format!["802.11 element {} overruns its frame", id]);
+            break;
+        }
+
+        let body = &data[start..start + length];
+        let mut element = NamedValues::new();
+        element.push(("ID", Val::Unsigned(id as u64)));
+        element.push(("Name", ELEMENTS.val(id)));
+        element.push(("Length", Val::Unsigned(length as u64)));
+
+        match id {
+            0 => element.push(("SSID", Val::String(String::from_utf8_lossy(body).to_string()))),
+            1 | 50 => element.push(("Rates", Val::String(rates(body)))),
+            3 if length >= 1 => element.push(("Channel", Val::Unsigned(body[0] as u64))),
+            5 if length >= 2 => {
+                element.push(("DTIM Count", Val::Unsigned(body[0] as u64)));
+                element.push(("DTIM Period", Val::Unsigned(body[1] as u64)));
+            },
+            7 if length >= 2 =>
+                element.push(("Country Code", Val::String(String::from_utf8_lossy(&body[..2]).to_string()))),
+            48 => if let Err(e) = rsn(body, &mut element) {
+                conformance::warn(&mut element, format!["malformed RSN element: {}", e]);
+            },
+            221 if length >= 3 => {
+                let oui = (body[0] as u64) << 16 | (body[1] as u64) << 8 | body[2] as u64;
+                element.push(("OUI", Val::Unsigned(oui)));
+                element.push(("Data", Val::Bytes(&body[3..])));
+            },
+            _ => element.push(("Data", Val::Bytes(body))),
+        }
+
+        values.push(("Element", Val::Object("Information Element", element)));
+        offset = start + length;
+    }
+}
+
+/// Data rates in Mb/s, basic rates marked with a '*'.
+fn rates(data: &[u8]) -> String {
+    data.iter()
+        .map(|&r| {
+            let mbps = (r & 0x7f) as f64 / 2.0;
+            let basic = if r & 0x80 != 0 { "*" } else { "" };
+            format!["{}{}", mbps, basic]
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The Robust Security Network (WPA2/WPA3) element.
+fn rsn(data: &[u8], values: &mut NamedValues) -> Result<(), DissectError> {
+    values.push(("Version", Val::Unsigned(read_le_u16(data, 0)? as u64)));
+
+    expect(data, 6, "An RSN group cipher suite")?;
+    values.push(("Group Cipher", suite(&data[2..6], &CIPHER_SUITES)));
+
+    let mut offset = 6;
+    for &(key, names) in &[("Pairwise Ciphers", &CIPHER_SUITES), ("AKM Suites", &AKM_SUITES)] {
+        if offset == data.len() {
+            return Ok(());
+        }
+
+        let count = read_le_u16(data, offset)? as usize;
+        offset += 2;
+        expect(data, offset + 4 * count, "RSN suites")?;
+
+        values.push((key, Val::List(data[offset..offset + 4 * count].chunks(4)
+                                    .map(|s| suite(s, names))
+                                    .collect())));
+        offset += 4 * count;
+    }
+
+    if offset + 2 <= data.len() {
+        values.push(("RSN Capabilities", Val::Unsigned(read_le_u16(data, offset)? as u64)));
+    }
+
+    Ok(())
+}
+
+fn suite<'data>(data: &[u8], names: &EnumMap) -> Val<'data> {
+    if data[..3] == [0x00, 0x0f, 0xac] {
+        names.val(data[3])
+    } else {
+        Val::String(format!["{:02x}-{:02x}-{:02x}:{}", data[0], data[1], data[2], data[3]])
+    }
+}
+
+fn expect(data: &[u8], length: usize, what: &str) -> Result<(), DissectError> {
+    if data.len() < length {
+        Err(DissectError::Underflow { expected: Some(length), have: data.len(),
+            message: format!["{} must be at least {} B", what, length] })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_beacon() {
+        let data = [0x80, 0x00, 0x00, 0x00,
+                    0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                    0x02, 0x11, 0x22, 0x33, 0x44, 0x55,
+                    0x02, 0x11, 0x22, 0x33, 0x44, 0x55,
+                    0x30, 0x01,
+                    1, 0, 0, 0, 0, 0, 0, 0, 0x64, 0x00, 0x11, 0x04,
+                    0, 4, b'r', b'u', b's', b't',
+                    1, 2, 0x82, 0x0c,
+                    3, 1, 6,
+                    48, 20, 1, 0, 0x00, 0x0f, 0xac, 4, 1, 0, 0x00, 0x0f, 0xac, 4, 1, 0, 0x00, 0x0f, 0xac, 2, 0, 0];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Subtype Name"].as_symbol().unwrap(), "Beacon");
+        assert_eq!(val["BSSID"].as_address_encoded().unwrap(), "02:11:22:33:44:55");
+        assert_eq!(val["Sequence Number"].as_unsigned().unwrap(), 0x13);
+        assert_eq!(val["Beacon Interval"].as_unsigned().unwrap(), 100);
+        assert_eq!(val["Capabilities"].as_bitflags8_bit_name("Privacy"), Some(true));
+
+        let elements = val.as_object().unwrap().1.iter()
+            .filter(|&&(k, _)| k == "Element")
+            .map(|&(_, ref e)| e)
+            .collect::<Vec<_>>();
+        assert_eq!(elements.len(), 4);
+        assert_eq!(elements[0]["SSID"].as_string().unwrap(), "rust");
+        assert_eq!(elements[1]["Rates"].as_string().unwrap(), "1* 6");
+        assert_eq!(elements[2]["Channel"].as_unsigned().unwrap(), 6);
+        assert_eq!(elements[3]["Pairwise Ciphers"]["0"].as_symbol().unwrap(), "CCMP-128");
+        assert_eq!(elements[3]["AKM Suites"].as_list().unwrap().len(), 1);
+        assert_eq!(elements[3].lookup("AKM Suites.0").unwrap().as_symbol().unwrap(), "PSK");
+    }
+
+    #[test]
+    fn dissect_data() {
+        // To DS, carrying SNAP-encapsulated IPv4
+        let data = [0x08, 0x01, 0x00, 0x00,
+                    0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 3, 0x00, 0x00,
+                    0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00,
+                    0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["BSSID"].as_address_encoded().unwrap(), "02:00:00:00:00:01");
+        assert_eq!(val["Destination"].as_address_encoded().unwrap(), "02:00:00:00:00:03");
+        assert_eq!(val["Payload"]["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        // An Ack has only a receiver address.
+        let ack = *dissect(&[0xd4, 0x00, 0x00, 0x00, 0x02, 0, 0, 0, 0, 1]).unwrap();
+        assert_eq!(ack["Subtype Name"].as_symbol().unwrap(), "Ack");
+        assert!(ack.get("Transmitter").is_err());
+    }
+}
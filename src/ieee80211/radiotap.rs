@@ -0,0 +1,133 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Radiotap headers, which carry per-frame radio information
+//! (rate, channel, signal strength, ...) in front of captured 802.11 frames.
+//!
+//! The fields present are given by a bitmask; each field is aligned to its
+//! own size, so a field can only be found if the sizes of all those before
+//! it are known. Dissection of the header stops at the first unknown field,
+//! but the 802.11 frame, whose offset is given by the header length, is
+//! always dissected.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use super::dissect as dissect_frame;
+use {read_le_u16, read_le_u32, read_le_u64};
+
+/// The alignment and size of each field in the default namespace, by bit.
+const FIELDS: [(usize, usize); 28] = [
+    (8, 8), (1, 1), (1, 1), (2, 4), (2, 2), (1, 1), (1, 1), (2, 2),
+    (2, 2), (2, 2), (1, 1), (1, 1), (1, 1), (1, 1), (2, 2), (2, 2),
+    (1, 1), (1, 1), (4, 8), (1, 3), (4, 8), (2, 12), (8, 12), (2, 12),
+    (2, 12), (2, 6), (1, 1), (2, 4),
+];
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "A Radiotap header must be at least 8 B".to_string() });
+    }
+
+    let length = read_le_u16(data, 2)? as usize;
+    if length < 8 || length > data.len() {
+        return Err(DissectError::Underflow { expected: Some(length.max(8)), have: data.len(),
+            message: format!["Radiotap header length {} is invalid", length] });
+    }
+    let header = &data[..length];
+
+    let mut values = NamedValues::new();
+    values.push(("Version", Val::Unsigned(data[0] as u64)));
+    values.push(("Length", Val::Unsigned(length as u64)));
+
+    // Bit 31 of each presence word says that another word follows.
+    let present = read_le_u32(header, 4)?;
+    values.push(("Present", Val::Unsigned(present as u64)));
+
+    let mut offset = 8;
+    let mut word = present;
+    while word & 0x8000_0000 != 0 {
+        word = read_le_u32(header, offset)?;
+        offset += 4;
+    }
+
+    // Fields in other namespaces, and those we can't size, are skipped.
+    let mut flags = 0;
+    for bit in 0..28 {
+        if present & (1 << bit) == 0 {
+            continue;
+        }
+
+        let (align, size) = FIELDS[bit];
+        offset = (offset + align - 1) / align * align;
+        if offset + size > length {
+            break;
+        }
+        let field = &header[offset..offset + size];
+
+        match bit {
+            0 => values.push(("TSFT", Val::Unsigned(read_le_u64(field, 0)?))),
+            1 => {
+                flags = field[0];
+                values.push(("Flags", Val::BitFlags8(flags, [
+                             Some("CFP"), Some("Short Preamble"), Some("WEP"), Some("Fragmentation"),
+                             Some("FCS at End"), Some("Data Pad"), Some("Bad FCS"), Some("Short GI")])));
+            },
+            // In units of 500 kb/s
+            2 => values.push(("Rate", Val::Unsigned(field[0] as u64))),
+            3 => {
+                values.push(("Channel Frequency", Val::Unsigned(read_le_u16(field, 0)? as u64)));
+                values.push(("Channel Flags", Val::Unsigned(read_le_u16(field, 2)? as u64)));
+            },
+            5 => values.push(("Antenna Signal", Val::Signed(field[0] as i8 as i64))),
+            6 => values.push(("Antenna Noise", Val::Signed(field[0] as i8 as i64))),
+            11 => values.push(("Antenna", Val::Unsigned(field[0] as u64))),
+            19 => values.push(("MCS Index", Val::Unsigned(field[2] as u64))),
+            _ => {},
+        }
+
+        offset += size;
+    }
+
+    let mut frame = &data[length..];
+    if flags & 0x10 != 0 && frame.len() >= 4 {
+        let fcs = frame.len() - 4;
+        values.push(("FCS", Val::Bytes(&frame[fcs..])));
+        frame = &frame[..fcs];
+    }
+
+    values.push(("Payload", Val::Payload(frame, dissect_frame(frame))));
+
+    Ok(Box::new(Val::Object("Radiotap", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_radiotap() {
+        // Flags (FCS at end), rate, channel and signal, then an Ack and its FCS
+        let data = [0x00, 0x00, 0x10, 0x00, 0x2e, 0x00, 0x00, 0x00,
+                    0x10, 0x0c, 0x85, 0x09, 0xa0, 0x00, 0xc4, 0x00,
+                    0xd4, 0x00, 0x00, 0x00, 0x02, 0, 0, 0, 0, 1,
+                    0xde, 0xad, 0xbe, 0xef];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Rate"].as_unsigned().unwrap(), 12);
+        assert_eq!(val["Channel Frequency"].as_unsigned().unwrap(), 2437);
+        assert_eq!(val["Antenna Signal"].as_signed().unwrap(), -60);
+        assert_eq!(val["FCS"].as_bytes().unwrap(), &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(val["Payload"]["Subtype Name"].as_symbol().unwrap(), "Ack");
+
+        assert!(dissect(&data[..12]).is_err());
+    }
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Decryption of WPA2 (CCMP) data frames with a known passphrase or PMK
+//! (only built with the `crypto` feature).
+//!
+//! As in Wireshark, a station's traffic can only be decrypted if its 4-way
+//! handshake was captured: a `Wpa2` watches 802.11 frames for handshakes,
+//! derives the station's pairwise transient key (PTK) from the nonces in
+//! messages 1 and 2 and keeps it if it reproduces message 2's MIC. The PMK
+//! comes from a passphrase and SSID, from a raw PMK, or from a `KeyProvider`
+//! (asked for `KeyId::Wpa` with the SSID seen in the network's beacons).
+//! Group (broadcast) and TKIP traffic is not decrypted.
+//!
+//! ```
+//! use rshark::ieee80211::wpa::Wpa2;
+//!
+//! let mut wpa = Wpa2::new().passphrase("password", "IEEE");
+//! let captured: Vec<Vec<u8>> = vec![];
+//!
+//! for frame in &captured {
+//!     wpa.observe(frame);
+//!     if let Ok(decrypted) = wpa.decrypt(frame) {
+//!         println!["{}", decrypted.dissect().unwrap()];
+//!     }
+//! }
+//! assert_eq!(wpa.stations(), 0);
+//! ```
+
+use std::collections::HashMap;
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use crypto::{ccm_decrypt, hmac_sha1, pbkdf2_hmac_sha1};
+use eapol;
+use keys::{KeyId, KeyProvider};
+use llc;
+
+type Mac = [u8; 6];
+
+/// The PMK of a network, derived from its passphrase and SSID.
+pub fn pmk(passphrase: &str, ssid: &[u8]) -> [u8; 32] {
+    let mut pmk = [0; 32];
+    pbkdf2_hmac_sha1(passphrase.as_bytes(), ssid, 4096, &mut pmk);
+    pmk
+}
+
+/// A decrypted 802.11 data frame body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decrypted {
+    pub packet_number: u64,
+    pub data: Vec<u8>,
+}
+
+impl Decrypted {
+    /// Dissect the inner (LLC) payload.
+    pub fn dissect(&self) -> DissectResult {
+        let mut values = NamedValues::new();
+        values.push(("Packet Number", Val::Unsigned(self.packet_number)));
+        values.push(("Payload", Val::Payload(&self.data, llc::dissect(&self.data))));
+
+        Ok(Box::new(Val::Object("Decrypted 802.11 data", values)))
+    }
+}
+
+/// Keys learned from 4-way handshakes, by (AP, station).
+#[derive(Default)]
+pub struct Wpa2 {
+    pmks: Vec<[u8; 32]>,
+    keys: Option<Box<dyn KeyProvider>>,
+    ssids: HashMap<Mac, Vec<u8>>,
+    anonces: HashMap<(Mac, Mac), [u8; 32]>,
+    temporal_keys: HashMap<(Mac, Mac), [u8; 16]>,
+}
+
+impl Wpa2 {
+    pub fn new() -> Wpa2 {
+        Wpa2::default()
+    }
+
+    pub fn passphrase(self, passphrase: &str, ssid: &str) -> Wpa2 {
+        self.pmk(pmk(passphrase, ssid.as_bytes()))
+    }
+
+    pub fn pmk(mut self, pmk: [u8; 32]) -> Wpa2 {
+        self.pmks.push(pmk);
+        self
+    }
+
+    /// Where to find the PMKs of networks, by SSID.
+    pub fn keys<P: KeyProvider + 'static>(mut self, provider: P) -> Wpa2 {
+        self.keys = Some(Box::new(provider));
+        self
+    }
+
+    /// The number of stations whose traffic can be decrypted.
+    pub fn stations(&self) -> usize {
+        self.temporal_keys.len()
+    }
+
+    /// Learn from a captured 802.11 frame: the SSID of a beacon or probe
+    /// response, or a handshake message. Returns true if the frame completed
+    /// a handshake and a station's key was recovered.
+    pub fn observe(&mut self, frame: &[u8]) -> bool {
+        if frame.len() < 24 || frame[1] & 0x40 != 0 {
+            return false;
+        }
+
+        if frame[0] & 0x0c == 0 && (frame[0] >> 4 == 8 || frame[0] >> 4 == 5) {
+            self.learn_ssid(frame);
+            return false;
+        }
+
+        let header = match Header::parse(frame) {
+            Some(header) => header,
+            None => return false,
+        };
+
+        let body = &frame[header.length..];
+        if !body.starts_with(&[0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x88, 0x8e]) {
+            return false;
+        }
+
+        let key = match eapol::Key::parse(&body[8..]) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+
+        let pair = (header.authenticator, header.station);
+        match key.message() {
+            Some(1) | Some(3) => {
+                let mut anonce = [0; 32];
+                anonce.copy_from_slice(key.nonce);
+                self.anonces.insert(pair, anonce);
+                false
+            },
+            Some(2) => match self.anonces.get(&pair).cloned() {
+                Some(anonce) => self.install(pair, &anonce, &key),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Decrypt a CCMP-protected data frame.
+    pub fn decrypt(&self, frame: &[u8]) -> Result<Decrypted, DissectError> {
+        let header = Header::parse(frame).ok_or_else(||
+            DissectError::InvalidData("not an 802.11 data frame to or from an AP".to_string()))?;
+
+        if frame[1] & 0x40 == 0 {
+            return Err(DissectError::InvalidData("802.11 frame is not protected".to_string()));
+        }
+
+        let pair = (header.authenticator, header.station);
+        let tk = self.temporal_keys.get(&pair).ok_or_else(||
+            DissectError::InvalidData(format!["no key for station {}", hex(&header.station)]))?;
+
+        let body = &frame[header.length..];
+        if body.len() < 16 {
+            return Err(DissectError::Underflow { expected: Some(16), have: body.len(),
+                message: "A CCMP frame body must be at least 16 B".to_string() });
+        }
+        if body[3] & 0x20 == 0 {
+            return Err(DissectError::InvalidData("802.11 frame has no extended IV (not CCMP)".to_string()));
+        }
+
+        let (nonce, aad) = ccmp_nonce_aad(frame, &header);
+        let data = ccm_decrypt(tk, &nonce, &aad, &body[8..], 8).ok_or_else(||
+            DissectError::InvalidData("CCMP MIC check failed (wrong key?)".to_string()))?;
+
+        let packet_number = nonce[7..].iter().fold(0u64, |pn, &b| pn << 8 | b as u64);
+        Ok(Decrypted { packet_number: packet_number, data: data })
+    }
+
+    fn learn_ssid(&mut self, frame: &[u8]) {
+        let mut offset = 36;
+        while offset + 2 <= frame.len() {
+            let (id, length) = (frame[offset], frame[offset + 1] as usize);
+            if offset + 2 + length > frame.len() {
+                return;
+            }
+            if id == 0 {
+                let ssid = frame[offset + 2..offset + 2 + length].to_vec();
+                self.ssids.insert(mac(&frame[16..22]), ssid);
+                return;
+            }
+            offset += 2 + length;
+        }
+    }
+
+    /// Try each candidate PMK against message 2 of a handshake.
+    fn install(&mut self, pair: (Mac, Mac), anonce: &[u8; 32], key: &eapol::Key) -> bool {
+        if key.descriptor_version() != 2 {
+            return false;
+        }
+
+        let mut candidates = self.pmks.clone();
+        let provided = match (self.ssids.get(&pair.0), &self.keys) {
+            (Some(ssid), &Some(ref keys)) => keys.key(&KeyId::Wpa { ssid: ssid.clone() }),
+            _ => None,
+        };
+        if let Some(provided) = provided.filter(|k| k.len() == 32) {
+            let mut pmk = [0; 32];
+            pmk.copy_from_slice(&provided);
+            candidates.push(pmk);
+        }
+
+        for pmk in candidates {
+            let ptk = ptk(&pmk, &pair.0, &pair.1, anonce, key.nonce);
+            if mic(&ptk[..16], key.frame)[..] == *key.mic {
+                let mut tk = [0; 16];
+                tk.copy_from_slice(&ptk[32..48]);
+                self.temporal_keys.insert(pair, tk);
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// The parts of a data frame's MAC header that keys and nonces depend on.
+struct Header {
+    length: usize,
+    qos: Option<u16>,
+    authenticator: Mac,
+    station: Mac,
+}
+
+impl Header {
+    /// Parse the header of a data frame sent to or from an AP.
+    fn parse(frame: &[u8]) -> Option<Header> {
+        if frame.len() < 24 || (frame[0] >> 2) & 0x03 != 2 {
+            return None;
+        }
+
+        let (authenticator, station) = match frame[1] & 0x03 {
+            1 => (mac(&frame[4..10]), mac(&frame[10..16])),
+            2 => (mac(&frame[10..16]), mac(&frame[4..10])),
+            _ => return None,
+        };
+
+        let mut length = 24;
+        let mut qos = None;
+        if frame[0] & 0x80 != 0 {
+            if frame.len() < 26 {
+                return None;
+            }
+            qos = Some(frame[24] as u16 | (frame[25] as u16) << 8);
+            length = if frame[1] & 0x80 != 0 { 30 } else { 26 };
+        }
+
+        if frame.len() < length {
+            return None;
+        }
+
+        Some(Header { length: length, qos: qos, authenticator: authenticator, station: station })
+    }
+}
+
+/// The CCM nonce and additional authenticated data of a protected frame.
+fn ccmp_nonce_aad(frame: &[u8], header: &Header) -> ([u8; 13], Vec<u8>) {
+    let body = &frame[header.length..];
+    let priority = header.qos.map(|q| (q & 0x0f) as u8).unwrap_or(0);
+
+    let mut nonce = [0; 13];
+    nonce[0] = priority;
+    nonce[1..7].copy_from_slice(&frame[10..16]);
+    nonce[7..].copy_from_slice(&[body[7], body[6], body[5], body[4], body[1], body[0]]);
+
+    // Subtype bits, Retry, Power Management and More Data are masked, as is
+    // Order in QoS frames; the sequence number is masked, but not the
+    // fragment number.
+    let mut flags = (frame[1] & 0xc7) | 0x40;
+    if header.qos.is_some() {
+        flags &= 0x7f;
+    }
+
+    let mut aad = vec![frame[0] & 0x8f, flags];
+    aad.extend_from_slice(&frame[4..22]);
+    aad.extend_from_slice(&[frame[22] & 0x0f, 0]);
+    if header.qos.is_some() {
+        aad.extend_from_slice(&[priority, 0]);
+    }
+
+    (nonce, aad)
+}
+
+/// The 384-bit PTK (KCK, KEK and TK) for CCMP.
+fn ptk(pmk: &[u8; 32], authenticator: &Mac, station: &Mac, anonce: &[u8], snonce: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(authenticator.min(station));
+    data.extend_from_slice(authenticator.max(station));
+    data.extend_from_slice(anonce.min(snonce));
+    data.extend_from_slice(anonce.max(snonce));
+
+    let mut ptk = Vec::new();
+    for i in 0..3 {
+        let mut input = b"Pairwise key expansion\0".to_vec();
+        input.extend_from_slice(&data);
+        input.push(i);
+        ptk.extend_from_slice(&hmac_sha1(pmk, &input));
+    }
+
+    ptk.truncate(48);
+    ptk
+}
+
+/// The MIC of an EAPOL-Key frame (descriptor version 2).
+fn mic(kck: &[u8], frame: &[u8]) -> [u8; 16] {
+    let mut frame = frame.to_vec();
+    for b in &mut frame[eapol::MIC_OFFSET..eapol::MIC_OFFSET + 16] {
+        *b = 0;
+    }
+
+    let mut mic = [0; 16];
+    mic.copy_from_slice(&hmac_sha1(kck, &frame)[..16]);
+    mic
+}
+
+fn mac(bytes: &[u8]) -> Mac {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&bytes[..6]);
+    mac
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!["{:02x}", b]).collect::<Vec<_>>().join(":")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::ccm_encrypt;
+
+    const AP: Mac = [0x02, 0, 0, 0, 0, 1];
+    const STATION: Mac = [0x02, 0, 0, 0, 0, 2];
+
+    /// A data frame to (or from) the AP, carrying `body`.
+    fn frame(to_ap: bool, protected: bool, body: &[u8]) -> Vec<u8> {
+        let flags = if to_ap { 0x01 } else { 0x02 } | if protected { 0x40 } else { 0 };
+        let (a1, a2) = if to_ap { (AP, STATION) } else { (STATION, AP) };
+
+        let mut data = vec![0x08, flags, 0, 0];
+        data.extend_from_slice(&a1);
+        data.extend_from_slice(&a2);
+        data.extend_from_slice(&AP);
+        data.extend_from_slice(&[0x10, 0x00]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn eapol_key(information: u16, nonce: u8, key_data: &[u8]) -> Vec<u8> {
+        let length = 95 + key_data.len() as u16;
+        let mut data = vec![0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x88, 0x8e,
+                            0x02, 0x03, (length >> 8) as u8, length as u8, 0x02,
+                            (information >> 8) as u8, information as u8, 0x00, 0x10,
+                            0, 0, 0, 0, 0, 0, 0, 1];
+        data.extend_from_slice(&[nonce; 32]);
+        data.extend_from_slice(&[0; 48]);
+        data.extend_from_slice(&[0, key_data.len() as u8]);
+        data.extend_from_slice(key_data);
+        data
+    }
+
+    #[test]
+    fn decrypt_after_handshake() {
+        let pmk = pmk("password", b"IEEE");
+        let ptk = ptk(&pmk, &AP, &STATION, &[0x11; 32], &[0x22; 32]);
+
+        let message1 = frame(false, false, &eapol_key(0x008a, 0x11, &[]));
+        let mut message2 = frame(true, false, &eapol_key(0x010a, 0x22, &[0xdd, 0x00]));
+        let mic = mic(&ptk[..16], &message2[32..]);
+        message2[32 + eapol::MIC_OFFSET..32 + eapol::MIC_OFFSET + 16].copy_from_slice(&mic);
+
+        // A CCMP-protected frame with packet number 1 carrying SNAP and IPv4
+        let plaintext = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00,
+                         0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        let mut protected = frame(true, true, &[0x01, 0x00, 0x00, 0x20, 0, 0, 0, 0]);
+        let (nonce, aad) = ccmp_nonce_aad(&protected, &Header::parse(&protected).unwrap());
+        let mut tk = [0; 16];
+        tk.copy_from_slice(&ptk[32..48]);
+        protected.extend(ccm_encrypt(&tk, &nonce, &aad, &plaintext, 8));
+
+        let mut wpa = Wpa2::new().passphrase("password", "IEEE");
+        assert!(wpa.decrypt(&protected).is_err());
+        assert!(!wpa.observe(&message1));
+        assert!(wpa.observe(&message2));
+        assert_eq!(wpa.stations(), 1);
+
+        let decrypted = wpa.decrypt(&protected).unwrap();
+        assert_eq!(decrypted.packet_number, 1);
+        let val = decrypted.dissect().unwrap();
+        assert_eq!(val["Payload"]["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        // The wrong PMK doesn't reproduce the MIC.
+        let mut wrong = Wpa2::new().pmk([0; 32]);
+        wrong.observe(&message1);
+        assert!(!wrong.observe(&message2));
+    }
+}
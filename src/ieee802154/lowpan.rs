@@ -0,0 +1,469 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of 6LoWPAN (IPv6 over 802.15.4) headers, including IPHC header
+//! compression and UDP next-header compression, and their re-inflation into
+//! IPv6 packets.
+//!
+//! IPHC elides whatever can be derived from elsewhere: addresses are often
+//! built from the link-layer addresses of the 802.15.4 frame, and the
+//! lengths from the frame's length. A dissected IPHC header therefore can't
+//! be handed to the IPv6 dissector directly; `inflate` rebuilds the IPv6
+//! packet, which can.
+//!
+//! Context-based (stateful) address compression depends on prefixes
+//! distributed out of band, and isn't supported.
+//!
+//! See [RFC 4944](https://tools.ietf.org/html/rfc4944) and
+//! [RFC 6282](https://tools.ietf.org/html/rfc6282).
+
+use std::net::Ipv6Addr;
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use checksum;
+use context::Context;
+use ip::ipv6;
+use read_be_u16;
+
+/// The link-layer (802.15.4) source and destination addresses of a packet,
+/// in big-endian order: 2 B short or 8 B extended addresses.
+pub type LinkAddresses<'a> = (&'a [u8], &'a [u8]);
+
+/// Dissect a 6LoWPAN packet without knowing its link-layer addresses.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with_link(data, None, &mut Context::new())
+}
+
+/// Dissect a 6LoWPAN packet, deriving elided addresses from the link layer,
+/// and any uncompressed IPv6 packet within a context.
+pub fn dissect_with_link<'data>(data: &'data [u8], link: Option<LinkAddresses>,
+                                context: &mut Context) -> DissectResult<'data> {
+
+    let mut values = NamedValues::new();
+    let mut data = data;
+
+    // Mesh and fragmentation headers come before the compressed IPv6 header.
+    if data.first().map_or(false, |d| d >> 6 == 2) {
+        values.push(("Dispatch", Val::Symbol("Mesh")));
+        values.push(("Payload", Val::Undissected("6LoWPAN mesh", data)));
+        return Ok(Box::new(Val::Object("6LoWPAN", values)));
+    }
+
+    if data.first().map_or(false, |d| d >> 3 == 0x18 || d >> 3 == 0x1c) {
+        let first = data[0] >> 3 == 0x18;
+        let length = if first { 4 } else { 5 };
+        if data.len() < length {
+            return Err(DissectError::Underflow { expected: Some(length), have: data.len(),
+                message: "6LoWPAN fragmentation header is truncated".to_string() });
+        }
+
+        values.push(("Dispatch", Val::Symbol(if first { "FRAG1" } else { "FRAGN" })));
+        values.push(("Datagram Size", Val::Unsigned((read_be_u16(data, 0)? & 0x7ff) as u64)));
+        values.push(("Datagram Tag", Val::Unsigned(read_be_u16(data, 2)? as u64)));
+        if !first {
+            values.push(("Datagram Offset", Val::Unsigned(data[4] as u64 * 8)));
+            values.push(("Payload", Val::Undissected("6LoWPAN fragment", &data[5..])));
+            return Ok(Box::new(Val::Object("6LoWPAN", values)));
+        }
+
+        data = &data[4..];
+    }
+
+    match data.first() {
+        Some(&0x41) => {
+            values.push(("Dispatch", Val::Symbol("IPv6")));
+            let packet = &data[1..];
+            values.push(("Payload",
+                         Val::Payload(packet, context.dissect(ipv6::dissect_with, packet))));
+        },
+        Some(d) if d >> 5 == 3 => {
+            values.push(("Dispatch", Val::Symbol("IPHC")));
+            iphc(data, link, &mut values)?;
+        },
+        Some(d) => return Err(DissectError::InvalidData(
+            format!["6LoWPAN dispatch 0x{:02x} is not supported", d])),
+        None => return Err(DissectError::Underflow { expected: Some(1), have: 0,
+            message: "A 6LoWPAN packet must not be empty".to_string() }),
+    }
+
+    Ok(Box::new(Val::Object("6LoWPAN", values)))
+}
+
+fn iphc<'data>(data: &'data [u8], link: Option<LinkAddresses>, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    let header = Header::parse(data, link)?;
+
+    values.push(("Traffic Class", Val::Unsigned(header.traffic_class as u64)));
+    values.push(("Flow Label", Val::Unsigned(header.flow_label as u64)));
+    values.push(("Next Header", Val::Unsigned(header.next_header as u64)));
+    values.push(("Hop Limit", Val::Unsigned(header.hop_limit as u64)));
+    let (start, end) = header.source_inline;
+    values.push(("Source", address(header.source, &data[start..end])));
+    let (start, end) = header.destination_inline;
+    values.push(("Destination", address(header.destination, &data[start..end])));
+
+    if let Some(ref udp) = header.udp {
+        values.push(("UDP Source Port", Val::Unsigned(udp.source as u64)));
+        values.push(("UDP Destination Port", Val::Unsigned(udp.destination as u64)));
+        if let Some(checksum) = udp.checksum {
+            values.push(("UDP Checksum", Val::Unsigned(checksum as u64)));
+        }
+    }
+
+    let payload = &data[header.length..];
+    values.push(("Payload", match header.next_header {
+        6 => Val::Undissected("TCP", payload),
+        17 => Val::Undissected(if header.udp.is_some() { "UDP payload" } else { "UDP" }, payload),
+        58 => Val::Undissected("ICMPv6", payload),
+        _ => Val::Undissected("Unknown", payload),
+    }));
+
+    Ok(())
+}
+
+/// An address shows as much of itself as could be reconstructed.
+fn address(address: Option<[u8; 16]>, inline: &[u8]) -> Val {
+    Val::Address {
+        bytes: inline,
+        encoded: match address {
+            Some(a) => Ipv6Addr::from(a).to_string(),
+            None => "(derived from link layer or context)".to_string(),
+        },
+    }
+}
+
+/// Rebuild the IPv6 packet from a 6LoWPAN packet (without mesh or
+/// fragmentation headers), so that it can be dissected with
+/// `ip::ipv6::dissect`.
+pub fn inflate(data: &[u8], link: Option<LinkAddresses>) -> Result<Vec<u8>, DissectError> {
+    match data.first() {
+        Some(&0x41) => return Ok(data[1..].to_vec()),
+        Some(d) if d >> 5 == 3 => {},
+        _ => return Err(DissectError::InvalidData(
+            "only IPHC and uncompressed IPv6 packets can be inflated".to_string())),
+    }
+
+    let header = Header::parse(data, link)?;
+    let (source, destination) = match (header.source, header.destination) {
+        (Some(s), Some(d)) => (s, d),
+        _ => return Err(DissectError::InvalidData(
+            "IPHC addresses can't be derived without link-layer addresses or contexts".to_string())),
+    };
+
+    let mut payload = Vec::new();
+    if let Some(ref udp) = header.udp {
+        let length = 8 + data.len() - header.length;
+        let checksum = udp.checksum.unwrap_or(0);
+        payload.extend_from_slice(&[(udp.source >> 8) as u8, udp.source as u8,
+                                    (udp.destination >> 8) as u8, udp.destination as u8,
+                                    (length >> 8) as u8, length as u8,
+                                    (checksum >> 8) as u8, checksum as u8]);
+        payload.extend_from_slice(&data[header.length..]);
+
+        // An elided checksum must be recomputed (and 0 means "no checksum").
+        if udp.checksum.is_none() {
+            let checksum = match checksum::transport(17, &source, &destination, &payload) {
+                0 => 0xffff,
+                c => c,
+            };
+            payload[6] = (checksum >> 8) as u8;
+            payload[7] = checksum as u8;
+        }
+    } else {
+        payload.extend_from_slice(&data[header.length..]);
+    }
+
+    let mut packet = vec![
+        0x60 | header.traffic_class >> 4,
+        header.traffic_class << 4 | (header.flow_label >> 16) as u8 & 0x0f,
+        (header.flow_label >> 8) as u8,
+        header.flow_label as u8,
+        (payload.len() >> 8) as u8,
+        payload.len() as u8,
+        header.next_header,
+        header.hop_limit,
+    ];
+    packet.extend_from_slice(&source);
+    packet.extend_from_slice(&destination);
+    packet.extend(payload);
+
+    Ok(packet)
+}
+
+/// A compressed UDP header.
+struct Udp {
+    source: u16,
+    destination: u16,
+    checksum: Option<u16>,
+}
+
+/// A parsed IPHC header, with the addresses that could be reconstructed.
+struct Header {
+    traffic_class: u8,
+    flow_label: u32,
+    next_header: u8,
+    hop_limit: u8,
+    source: Option<[u8; 16]>,
+    destination: Option<[u8; 16]>,
+
+    /// Where the inline parts of the addresses are.
+    source_inline: (usize, usize),
+    destination_inline: (usize, usize),
+
+    udp: Option<Udp>,
+
+    /// The length of the compressed header(s).
+    length: usize,
+}
+
+/// Reads the inline fields of a compressed header in order.
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], DissectError> {
+        if self.offset + length > self.data.len() {
+            return Err(DissectError::Underflow { expected: Some(self.offset + length),
+                have: self.data.len(), message: "IPHC header is truncated".to_string() });
+        }
+
+        self.offset += length;
+        Ok(&self.data[self.offset - length..self.offset])
+    }
+
+    fn byte(&mut self) -> Result<u8, DissectError> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn word(&mut self) -> Result<u16, DissectError> {
+        self.take(2).map(|w| (w[0] as u16) << 8 | w[1] as u16)
+    }
+}
+
+impl Header {
+    fn parse(data: &[u8], link: Option<LinkAddresses>) -> Result<Header, DissectError> {
+        let mut cursor = Cursor { data: data, offset: 0 };
+        let iphc = cursor.word()?;
+
+        let context = iphc & 0x0080 != 0;
+        if context {
+            cursor.byte()?;
+        }
+
+        // Traffic class (ECN then DSCP on the wire) and flow label
+        let (ecn_dscp, flow_label) = match (iphc >> 11) & 0x03 {
+            0 => {
+                let f = cursor.take(4)?;
+                (f[0], ((f[1] & 0x0f) as u32) << 16 | (f[2] as u32) << 8 | f[3] as u32)
+            },
+            1 => {
+                let f = cursor.take(3)?;
+                (f[0] & 0xc0, ((f[0] & 0x0f) as u32) << 16 | (f[1] as u32) << 8 | f[2] as u32)
+            },
+            2 => (cursor.byte()?, 0),
+            _ => (0, 0),
+        };
+        let traffic_class = (ecn_dscp & 0x3f) << 2 | ecn_dscp >> 6;
+
+        let compressed_next_header = iphc & 0x0400 != 0;
+        let mut next_header = if compressed_next_header { 0 } else { cursor.byte()? };
+
+        let hop_limit = match (iphc >> 8) & 0x03 {
+            0 => cursor.byte()?,
+            1 => 1,
+            2 => 64,
+            _ => 255,
+        };
+
+        let link_source = link.map(|l| l.0);
+        let link_destination = link.map(|l| l.1);
+
+        let start = cursor.offset;
+        let source = unicast(&mut cursor, iphc & 0x0040 != 0, (iphc >> 4) & 0x03, link_source)?;
+        let source_inline = (start, cursor.offset);
+
+        let start = cursor.offset;
+        let destination = if iphc & 0x0008 != 0 {
+            multicast(&mut cursor, iphc & 0x0004 != 0, iphc & 0x03)?
+        } else {
+            unicast(&mut cursor, iphc & 0x0004 != 0, iphc & 0x03, link_destination)?
+        };
+        let destination_inline = (start, cursor.offset);
+
+        let mut udp = None;
+        if compressed_next_header {
+            let nhc = cursor.byte()?;
+            if nhc & 0xf8 != 0xf0 {
+                return Err(DissectError::InvalidData(
+                    format!["6LoWPAN next header compression 0x{:02x} is not supported", nhc]));
+            }
+
+            let (source, destination) = match nhc & 0x03 {
+                0 => (cursor.word()?, cursor.word()?),
+                1 => (cursor.word()?, 0xf000 | cursor.byte()? as u16),
+                2 => (0xf000 | cursor.byte()? as u16, cursor.word()?),
+                _ => {
+                    let ports = cursor.byte()?;
+                    (0xf0b0 | (ports >> 4) as u16, 0xf0b0 | (ports & 0x0f) as u16)
+                },
+            };
+            let checksum = if nhc & 0x04 == 0 { Some(cursor.word()?) } else { None };
+
+            next_header = 17;
+            udp = Some(Udp { source: source, destination: destination, checksum: checksum });
+        }
+
+        Ok(Header {
+            traffic_class: traffic_class,
+            flow_label: flow_label,
+            next_header: next_header,
+            hop_limit: hop_limit,
+            source: source,
+            destination: destination,
+            source_inline: source_inline,
+            destination_inline: destination_inline,
+            udp: udp,
+            length: cursor.offset,
+        })
+    }
+}
+
+/// A unicast address, which is link-local unless compressed with a context.
+fn unicast(cursor: &mut Cursor, context: bool, mode: u16, link: Option<&[u8]>)
+    -> Result<Option<[u8; 16]>, DissectError> {
+
+    let mut address = [0; 16];
+
+    if context {
+        // The unspecified address (::) is the only stateless case.
+        return match mode {
+            0 => Ok(Some(address)),
+            1 => cursor.take(8).map(|_| None),
+            2 => cursor.take(2).map(|_| None),
+            _ => Ok(None),
+        };
+    }
+
+    match mode {
+        0 => {
+            address.copy_from_slice(cursor.take(16)?);
+            return Ok(Some(address));
+        },
+        1 => address[8..].copy_from_slice(cursor.take(8)?),
+        2 => {
+            address[11..13].copy_from_slice(&[0xff, 0xfe]);
+            address[14..].copy_from_slice(cursor.take(2)?);
+        },
+        _ => match link.map(interface_identifier) {
+            Some(Some(iid)) => address[8..].copy_from_slice(&iid),
+            _ => return Ok(None),
+        },
+    }
+
+    address[0..2].copy_from_slice(&[0xfe, 0x80]);
+    Ok(Some(address))
+}
+
+fn multicast(cursor: &mut Cursor, context: bool, mode: u16)
+    -> Result<Option<[u8; 16]>, DissectError> {
+
+    let mut address = [0; 16];
+
+    match (context, mode) {
+        (false, 0) => address.copy_from_slice(cursor.take(16)?),
+        (false, 1) => {
+            let inline = cursor.take(6)?;
+            address[0] = 0xff;
+            address[1] = inline[0];
+            address[11..].copy_from_slice(&inline[1..]);
+        },
+        (false, 2) => {
+            let inline = cursor.take(4)?;
+            address[0] = 0xff;
+            address[1] = inline[0];
+            address[13..].copy_from_slice(&inline[1..]);
+        },
+        (false, 3) => {
+            address[0..2].copy_from_slice(&[0xff, 0x02]);
+            address[15] = cursor.byte()?;
+        },
+        (true, 0) => return cursor.take(6).map(|_| None),
+        _ => return Err(DissectError::InvalidData(
+            format!["IPHC multicast address mode {} with a context is reserved", mode])),
+    }
+
+    Ok(Some(address))
+}
+
+/// The interface identifier derived from a short or extended link-layer
+/// address (RFC 4944, section 6).
+fn interface_identifier(link: &[u8]) -> Option<[u8; 8]> {
+    let mut iid = [0; 8];
+
+    match link.len() {
+        2 => {
+            iid[3..5].copy_from_slice(&[0xff, 0xfe]);
+            iid[6..].copy_from_slice(link);
+        },
+        8 => {
+            iid.copy_from_slice(link);
+            iid[0] ^= 0x02;
+        },
+        _ => return None,
+    }
+
+    Some(iid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn inflate_iphc_udp() {
+        // Link-local addresses from the link layer, hop limit 64, and UDP
+        // ports 0xf0b1 -> 0xf0b2 with an elided checksum
+        let data = [0x7e, 0x33, 0xf7, 0x12, b'h', b'i'];
+        let link = (&[0x00, 0x01][..], &[0x02, 0x12, 0x4b, 0, 0, 0, 0, 0x02][..]);
+
+        let val = *dissect_with_link(&data, Some(link), &mut Context::new()).unwrap();
+        assert_eq!(val["Dispatch"].as_symbol().unwrap(), "IPHC");
+        assert_eq!(val["Hop Limit"].as_unsigned().unwrap(), 64);
+        assert_eq!(val["Source"].as_address_encoded().unwrap(), "fe80::ff:fe00:1");
+        assert_eq!(val["Destination"].as_address_encoded().unwrap(), "fe80::12:4b00:0:2");
+        assert_eq!(val["UDP Destination Port"].as_unsigned().unwrap(), 0xf0b2);
+
+        let packet = inflate(&data, Some(link)).unwrap();
+        let val = *ipv6::dissect(&packet).unwrap();
+        assert_eq!(val["Payload Length"].as_unsigned().unwrap(), 10);
+        assert_eq!(val["Payload"]["Checksum Status"].as_symbol().unwrap(), "Good");
+
+        assert!(inflate(&data, None).is_err());
+    }
+
+    #[test]
+    fn dissect_fragments() {
+        let frag1 = [0xc0, 0x50, 0x12, 0x34, 0x7b, 0x3b, 0x3a, 0x01];
+        let val = *dissect(&frag1).unwrap();
+        assert_eq!(val["Datagram Size"].as_unsigned().unwrap(), 80);
+        assert_eq!(val["Next Header"].as_unsigned().unwrap(), 58);
+        assert_eq!(val["Destination"].as_address_encoded().unwrap(), "ff02::1");
+
+        let fragn = [0xe0, 0x50, 0x12, 0x34, 0x06, 0xaa];
+        let val = *dissect(&fragn).unwrap();
+        assert_eq!(val["Datagram Offset"].as_unsigned().unwrap(), 48);
+        assert!(dissect(&fragn[..4]).is_err());
+    }
+}
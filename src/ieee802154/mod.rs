@@ -0,0 +1,255 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of IEEE 802.15.4 MAC frames, the link layer of Zigbee, Thread
+//! and other low-power wireless networks.
+//!
+//! The frame control field gives the frame type and which addresses (none,
+//! 16 b short or 64 b extended) and PAN identifiers are present; all fields
+//! are little-endian. Secured frames carry an auxiliary security header, and
+//! their payloads are encrypted. Data frame payloads are dissected as
+//! `lowpan` (6LoWPAN) packets.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use context::Context;
+use {read_le_u16, read_le_u32};
+
+pub mod lowpan;
+
+enum_map!(pub FRAME_TYPES {
+    0 => "Beacon",
+    1 => "Data",
+    2 => "Acknowledgement",
+    3 => "MAC Command",
+    5 => "Multipurpose",
+});
+
+enum_map!(pub ADDRESSING_MODES {
+    0 => "None",
+    1 => "Reserved",
+    2 => "Short",
+    3 => "Extended",
+});
+
+enum_map!(pub SECURITY_LEVELS {
+    0 => "None",
+    1 => "MIC-32",
+    2 => "MIC-64",
+    3 => "MIC-128",
+    4 => "ENC",
+    5 => "ENC-MIC-32",
+    6 => "ENC-MIC-64",
+    7 => "ENC-MIC-128",
+});
+
+enum_map!(pub COMMANDS {
+    1 => "Association Request",
+    2 => "Association Response",
+    3 => "Disassociation Notification",
+    4 => "Data Request",
+    5 => "PAN ID Conflict Notification",
+    6 => "Orphan Notification",
+    7 => "Beacon Request",
+    8 => "Coordinator Realignment",
+    9 => "GTS Request",
+});
+
+/// Frame control flags
+const SECURITY: u16 = 0x0008;
+const PAN_ID_COMPRESSION: u16 = 0x0040;
+const SEQUENCE_SUPPRESSION: u16 = 0x0100;
+
+/// Dissect a frame that ends with its 2 B FCS (LINKTYPE_IEEE802_15_4_WITHFCS).
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a frame that ends with its FCS, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 5 {
+        return Err(DissectError::Underflow { expected: Some(5), have: data.len(),
+            message: "An 802.15.4 frame with an FCS must be at least 5 B".to_string() });
+    }
+
+    let fcs = data.len() - 2;
+    let mut val = dissect_nofcs_with(&data[..fcs], context)?;
+    if let Val::Object(_, ref mut values) = *val {
+        values.push(("FCS", Val::Bytes(&data[fcs..])));
+    }
+
+    Ok(val)
+}
+
+/// Dissect a frame without an FCS (LINKTYPE_IEEE802_15_4_NOFCS).
+pub fn dissect_nofcs(data : &[u8]) -> DissectResult {
+    dissect_nofcs_with(data, &mut Context::new())
+}
+
+/// Dissect a frame without an FCS, and its payload within a context.
+pub fn dissect_nofcs_with<'data>(data: &'data [u8], context: &mut Context)
+    -> DissectResult<'data> {
+
+    if data.len() < 2 {
+        return Err(DissectError::Underflow { expected: Some(2), have: data.len(),
+            message: "An 802.15.4 frame must be at least 2 B".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+    let control = read_le_u16(data, 0)?;
+    let frame_type = control & 0x07;
+    let destination_mode = (control >> 10) & 0x03;
+    let source_mode = (control >> 14) & 0x03;
+
+    values.push(("Frame Type", FRAME_TYPES.val(frame_type)));
+    values.push(("Flags", Val::BitFlags8((control >> 3) as u8, [
+                 Some("Security Enabled"), Some("Frame Pending"), Some("Ack Request"),
+                 Some("PAN ID Compression"), None, Some("Sequence Number Suppression"),
+                 Some("IE Present"), None])));
+    values.push(("Frame Version", Val::Unsigned(((control >> 12) & 0x03) as u64)));
+    values.push(("Destination Addressing Mode", ADDRESSING_MODES.val(destination_mode)));
+    values.push(("Source Addressing Mode", ADDRESSING_MODES.val(source_mode)));
+
+    let mut offset = 2;
+    if control & SEQUENCE_SUPPRESSION == 0 {
+        values.push(("Sequence Number", Val::Unsigned(field(data, offset, 1)?[0] as u64)));
+        offset += 1;
+    }
+
+    let mut destination = None;
+    if destination_mode >= 2 {
+        values.push(("Destination PAN", Val::Unsigned(read_le_u16(data, offset)? as u64)));
+        offset += 2;
+
+        let bytes = field(data, offset, if destination_mode == 2 { 2 } else { 8 })?;
+        values.push(("Destination", address(bytes)));
+        destination = Some(bytes);
+        offset += bytes.len();
+    }
+
+    let mut source = None;
+    if source_mode >= 2 {
+        if control & PAN_ID_COMPRESSION == 0 {
+            values.push(("Source PAN", Val::Unsigned(read_le_u16(data, offset)? as u64)));
+            offset += 2;
+        }
+
+        let bytes = field(data, offset, if source_mode == 2 { 2 } else { 8 })?;
+        values.push(("Source", address(bytes)));
+        source = Some(bytes);
+        offset += bytes.len();
+    }
+
+    if control & SECURITY != 0 {
+        let security_control = field(data, offset, 1)?[0];
+        let key_mode = (security_control >> 3) & 0x03;
+        values.push(("Security Level", SECURITY_LEVELS.val(security_control & 0x07)));
+        values.push(("Key Identifier Mode", Val::Unsigned(key_mode as u64)));
+        values.push(("Frame Counter", Val::Unsigned(read_le_u32(data, offset + 1)? as u64)));
+        offset += 5;
+
+        // The key source (if any) comes before the key index.
+        if key_mode != 0 {
+            let source_length = [0, 0, 4, 8][key_mode as usize];
+            let key = field(data, offset, source_length + 1)?;
+            if source_length > 0 {
+                values.push(("Key Source", Val::Bytes(&key[..source_length])));
+            }
+            values.push(("Key Index", Val::Unsigned(key[source_length] as u64)));
+            offset += key.len();
+        }
+
+        values.push(("Payload", Val::Undissected("Encrypted 802.15.4 payload", &data[offset..])));
+        return Ok(Box::new(Val::Object("IEEE 802.15.4", values)));
+    }
+
+    let payload = &data[offset..];
+    match frame_type {
+        1 if !payload.is_empty() => {
+            // 6LoWPAN derives addresses from big-endian link-layer addresses.
+            let reversed = |a: &[u8]| a.iter().rev().cloned().collect::<Vec<_>>();
+            let link = match (source.map(reversed), destination.map(reversed)) {
+                (Some(s), Some(d)) => Some((s, d)),
+                _ => None,
+            };
+            let link = link.as_ref().map(|&(ref s, ref d)| (&s[..], &d[..]));
+            values.push(("Payload", Val::Payload(payload,
+                         context.dissect(|data, context| {
+                             lowpan::dissect_with_link(data, link, context)
+                         }, payload))));
+        },
+        3 if !payload.is_empty() => {
+            values.push(("Command", COMMANDS.val(payload[0])));
+            values.push(("Command Payload", Val::Bytes(&payload[1..])));
+        },
+        0 => values.push(("Payload", Val::Undissected("802.15.4 beacon", payload))),
+        _ if !payload.is_empty() => values.push(("Payload", Val::Undissected("Unknown", payload))),
+        _ => {},
+    }
+
+    Ok(Box::new(Val::Object("IEEE 802.15.4", values)))
+}
+
+fn field(data: &[u8], offset: usize, length: usize) -> Result<&[u8], DissectError> {
+    data.get(offset..offset + length).ok_or_else(|| DissectError::Underflow {
+        expected: Some(offset + length), have: data.len(),
+        message: "802.15.4 header is truncated".to_string() })
+}
+
+/// Short addresses are shown as numbers, extended ones like MAC addresses,
+/// most significant byte first.
+fn address(bytes: &[u8]) -> Val {
+    Val::Address {
+        bytes: bytes,
+        encoded: match bytes.len() {
+            2 => format!["0x{:04x}", (bytes[1] as u16) << 8 | bytes[0] as u16],
+            _ => bytes.iter().rev().map(|b| format!["{:02x}", b]).collect::<Vec<_>>().join(":"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_data_frame() {
+        // Short addresses, compressed PAN ID, then IPHC with addresses from
+        // the link layer and an inline ICMPv6 next header
+        let data = [0x41, 0x88, 0x07, 0xcd, 0xab, 0x02, 0x00, 0x01, 0x00,
+                    0x7b, 0x33, 0x3a, 0x80, 0x00, 0xbe, 0xef];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Frame Type"].as_symbol().unwrap(), "Data");
+        assert_eq!(val["Sequence Number"].as_unsigned().unwrap(), 7);
+        assert_eq!(val["Destination PAN"].as_unsigned().unwrap(), 0xabcd);
+        assert_eq!(val["Source"].as_address_encoded().unwrap(), "0x0001");
+        assert_eq!(val["Payload"]["Source"].as_address_encoded().unwrap(), "fe80::ff:fe00:1");
+        assert_eq!(val["Payload"]["Destination"].as_address_encoded().unwrap(), "fe80::ff:fe00:2");
+        assert_eq!(val["FCS"].as_bytes().unwrap(), &[0xbe, 0xef]);
+
+        assert!(dissect_nofcs(&data[..6]).is_err());
+    }
+
+    #[test]
+    fn dissect_secured_frame() {
+        // Extended source, ENC-MIC-32 with key identifier mode 1
+        let data = [0x49, 0xc8, 0x01, 0xcd, 0xab, 0xff, 0xff,
+                    8, 7, 6, 5, 4, 3, 2, 1,
+                    0x0d, 0x2a, 0, 0, 0, 0x01, 0xde, 0xad];
+
+        let val = *dissect_nofcs(&data).unwrap();
+        assert_eq!(val["Source"].as_address_encoded().unwrap(), "01:02:03:04:05:06:07:08");
+        assert_eq!(val["Security Level"].as_symbol().unwrap(), "ENC-MIC-32");
+        assert_eq!(val["Frame Counter"].as_unsigned().unwrap(), 42);
+        assert_eq!(val["Key Index"].as_unsigned().unwrap(), 1);
+    }
+}
@@ -0,0 +1,226 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Interning of strings.
+//!
+//! Dissectors name their fields, layers and symbols with `&'static str`s, so
+//! a freshly-dissected packet costs nothing for them. Dissections that are
+//! loaded back from an export (or built from names known only at run time)
+//! must get their `&'static str`s from somewhere: an `Interner` allocates
+//! each distinct string once, however many values refer to it, and numbers
+//! the strings so that they can be written once to an export's dictionary.
+//!
+//! Interned strings live for the rest of the program, so they are shared
+//! by all interners (a string is only ever allocated once per process) and
+//! no more than `MAX_INTERNED` bytes of them are ever allocated. An export
+//! or descriptor full of distinct names therefore can't exhaust memory: once
+//! the limit is reached, interning a new string fails. An interner can be
+//! given a lower limit of its own with `with_limit`.
+//!
+//! ```
+//! use rshark::intern::Interner;
+//!
+//! let mut names = Interner::new();
+//! let a = names.intern(&String::from("Source Port")).unwrap();
+//! let b = Interner::new().intern("Source Port").unwrap();
+//!
+//! assert!(a.as_ptr() == b.as_ptr());
+//! assert_eq!(names.id("Source Port").unwrap(), 0);
+//! assert_eq!(names.get(0), Some("Source Port"));
+//!
+//! let mut short = Interner::with_limit(16);
+//! assert!(short.intern("Destination Port").is_ok());
+//! assert!(short.intern("Protocol").is_err());
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::{Mutex, OnceLock};
+
+use DissectError;
+
+/// How many bytes of strings and bit name lists may be interned by the
+/// whole process.
+pub const MAX_INTERNED: usize = 16 << 20;
+
+#[derive(Clone, Debug)]
+pub struct Interner {
+    ids: HashMap<&'static str, u32>,
+    strings: Vec<&'static str>,
+    bytes: usize,
+    limit: usize,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::with_limit(MAX_INTERNED)
+    }
+
+    /// An interner whose strings may total no more than `limit` bytes.
+    pub fn with_limit(limit: usize) -> Interner {
+        Interner {
+            ids: HashMap::new(),
+            strings: Vec::new(),
+            bytes: 0,
+            limit: limit,
+        }
+    }
+
+    /// The interned copy of a string (only for sets of strings that stop
+    /// growing: names and symbols, not arbitrary packet contents).
+    pub fn intern(&mut self, s: &str) -> Result<&'static str, DissectError> {
+        let id = self.id(s)?;
+        Ok(self.strings[id as usize])
+    }
+
+    /// The number of a string, interning it if it's new. Strings are
+    /// numbered from zero in the order they were first interned.
+    pub fn id(&mut self, s: &str) -> Result<u32, DissectError> {
+        if let Some(&id) = self.ids.get(s) {
+            return Ok(id);
+        }
+
+        self.charge(s.len())?;
+        let interned = pool().lock().unwrap().string(s)?;
+        Ok(self.insert_static(interned))
+    }
+
+    /// Add a string that is already static, without copying it.
+    pub fn insert_static(&mut self, s: &'static str) -> u32 {
+        if let Some(&id) = self.ids.get(s) {
+            return id;
+        }
+
+        let id = self.strings.len() as u32;
+        self.ids.insert(s, id);
+        self.strings.push(s);
+        id
+    }
+
+    /// The interned copy of a list of (interned) bit names, as used by
+    /// `Val::BitFlags`.
+    pub fn intern_bit_names(&mut self, names: Vec<Option<&'static str>>)
+        -> Result<&'static [Option<&'static str>], DissectError> {
+
+        let mut pool = pool().lock().unwrap();
+        if let Some(&interned) = pool.bit_names.get(&names) {
+            return Ok(interned);
+        }
+
+        self.charge(names.len() * mem::size_of::<Option<&str>>())?;
+        pool.bit_names(names)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&'static str> {
+        self.strings.get(id as usize).cloned()
+    }
+
+    /// All interned strings, in the order of their numbers.
+    pub fn strings(&self) -> &[&'static str] {
+        &self.strings
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    fn charge(&mut self, bytes: usize) -> Result<(), DissectError> {
+        if self.bytes + bytes > self.limit {
+            return Err(DissectError::InvalidData(
+                format!["interned names would exceed the limit of {} B", self.limit]));
+        }
+
+        self.bytes += bytes;
+        Ok(())
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Interner {
+        Interner::new()
+    }
+}
+
+/// The strings and bit name lists allocated for all interners.
+#[derive(Default)]
+struct Pool {
+    strings: HashSet<&'static str>,
+    bit_names: HashMap<Vec<Option<&'static str>>, &'static [Option<&'static str>]>,
+    bytes: usize,
+}
+
+impl Pool {
+    fn string(&mut self, s: &str) -> Result<&'static str, DissectError> {
+        if let Some(&interned) = self.strings.get(s) {
+            return Ok(interned);
+        }
+
+        self.allocate(s.len())?;
+        let interned: &'static str = Box::leak(s.to_string().into_boxed_str());
+        self.strings.insert(interned);
+        Ok(interned)
+    }
+
+    fn bit_names(&mut self, names: Vec<Option<&'static str>>)
+        -> Result<&'static [Option<&'static str>], DissectError> {
+
+        self.allocate(names.len() * mem::size_of::<Option<&str>>())?;
+        let interned: &'static [Option<&'static str>] = Box::leak(names.clone().into_boxed_slice());
+        self.bit_names.insert(names, interned);
+        Ok(interned)
+    }
+
+    fn allocate(&mut self, bytes: usize) -> Result<(), DissectError> {
+        if self.bytes + bytes > MAX_INTERNED {
+            return Err(DissectError::InvalidData(
+                format!["interned names would exceed the limit of {} B", MAX_INTERNED]));
+        }
+
+        self.bytes += bytes;
+        Ok(())
+    }
+}
+
+fn pool() -> &'static Mutex<Pool> {
+    static POOL: OnceLock<Mutex<Pool>> = OnceLock::new();
+    POOL.get_or_init(|| Mutex::new(Pool::default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intern_strings() {
+        let mut names = Interner::new();
+        assert_eq!(names.insert_static("TCP"), 0);
+        assert_eq!(names.id("UDP").unwrap(), 1);
+        assert_eq!(names.id("TCP").unwrap(), 0);
+        assert_eq!(names.intern("UDP").unwrap(), "UDP");
+
+        assert_eq!(names.strings(), &["TCP", "UDP"]);
+        assert_eq!(names.get(2), None);
+
+        let flags = names.intern_bit_names(vec![Some("TCP"), None]).unwrap();
+        let again = names.intern_bit_names(vec![Some("TCP"), None]).unwrap();
+        assert!(flags.as_ptr() == again.as_ptr());
+
+        // Strings already interned elsewhere still count against the limit.
+        let mut limited = Interner::with_limit(6);
+        assert!(limited.intern("UDP").unwrap().as_ptr() == names.intern("UDP").unwrap().as_ptr());
+        assert_eq!(limited.id("UDP").unwrap(), 0);
+        assert!(limited.intern("SCTP").is_err());
+        assert_eq!(limited.intern("TCP").unwrap(), "TCP");
+        assert!(limited.intern_bit_names(vec![None]).is_err());
+    }
+}
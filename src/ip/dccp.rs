@@ -0,0 +1,175 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Datagram Congestion Control Protocol (DCCP) packets.
+//!
+//! Sequence numbers are 48 b, or 24 b in packets that don't set the X
+//! (extended sequence numbers) bit; the checksum may cover only part of the
+//! application data.
+//!
+//! See [RFC 4340](https://tools.ietf.org/html/rfc4340).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use raw;
+use {read_be_u16, read_be_u32};
+
+enum_map!(pub PACKET_TYPES {
+    0 => "Request",
+    1 => "Response",
+    2 => "Data",
+    3 => "Ack",
+    4 => "DataAck",
+    5 => "CloseReq",
+    6 => "Close",
+    7 => "Reset",
+    8 => "Sync",
+    9 => "SyncAck",
+});
+
+enum_map!(pub RESET_CODES {
+    0 => "Unspecified",
+    1 => "Closed",
+    2 => "Aborted",
+    3 => "No Connection",
+    4 => "Packet Error",
+    5 => "Option Error",
+    6 => "Mandatory Error",
+    7 => "Connection Refused",
+    8 => "Bad Service Code",
+    9 => "Too Busy",
+    10 => "Bad Init Cookie",
+    11 => "Aggression Penalty",
+});
+
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+const DATA: u8 = 2;
+const RESET: u8 = 7;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 12 {
+        return Err(DissectError::Underflow { expected: Some(12), have: data.len(),
+            message: "A DCCP packet must be at least 12 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Source Port", Val::Unsigned(read_be_u16(data, 0)? as u64)));
+    values.push(("Destination Port", Val::Unsigned(read_be_u16(data, 2)? as u64)));
+
+    let data_offset = data[4] as usize * 4;
+    values.push(("Data Offset", Val::Unsigned(data_offset as u64)));
+    values.push(("CCVal", Val::Unsigned((data[5] >> 4) as u64)));
+    values.push(("Checksum Coverage", Val::Unsigned((data[5] & 0x0f) as u64)));
+    values.push(("Checksum", Val::Bytes(&data[6..8])));
+
+    let packet_type = (data[8] >> 1) & 0x0f;
+    let extended = data[8] & 0x01 != 0;
+    values.push(("Type", Val::Unsigned(packet_type as u64)));
+    values.push(("Type Name", PACKET_TYPES.val(packet_type)));
+    values.push(("Extended Sequence Numbers", Val::Unsigned(extended as u64)));
+
+    // Request and Data packets must use extended sequence numbers.
+    let (sequence, mut offset) = if extended {
+        (number(data, 10, 6)?, 16)
+    } else {
+        (number(data, 9, 3)?, 12)
+    };
+    values.push(("Sequence Number", Val::Unsigned(sequence)));
+
+    if packet_type != REQUEST && packet_type != DATA {
+        let (acknowledgement, length) = if extended {
+            (number(data, offset + 2, 6)?, 8)
+        } else {
+            (number(data, offset + 1, 3)?, 4)
+        };
+        values.push(("Acknowledgement Number", Val::Unsigned(acknowledgement)));
+        offset += length;
+    }
+
+    match packet_type {
+        REQUEST | RESPONSE => {
+            values.push(("Service Code", Val::Unsigned(read_be_u32(data, offset)? as u64)));
+            offset += 4;
+        },
+        RESET => {
+            let reset = field(data, offset, 4)?;
+            values.push(("Reset Code", RESET_CODES.val(reset[0])));
+            values.push(("Reset Data", Val::Bytes(&reset[1..])));
+            offset += 4;
+        },
+        _ => {},
+    }
+
+    if data_offset < offset || data_offset > data.len() {
+        return Err(DissectError::Underflow { expected: Some(data_offset.max(offset)), have: data.len(),
+            message: format!["DCCP data offset {} is invalid", data_offset] });
+    }
+
+    if data_offset > offset {
+        values.push(("Options", Val::Bytes(&data[offset..data_offset])));
+    }
+
+    let payload = &data[data_offset..];
+    if !payload.is_empty() {
+        values.push(("Payload", Val::Payload(payload, raw("Data", payload))));
+    }
+
+    Ok(Box::new(Val::Object("DCCP", values)))
+}
+
+/// The number of bytes covered by the checksum: the header and the first
+/// (CsCov - 1) words of application data, or everything if CsCov is zero.
+pub fn checksum_coverage(segment: &[u8]) -> usize {
+    match (segment.get(4), segment.get(5).map(|b| b & 0x0f)) {
+        (Some(&offset), Some(coverage)) if coverage > 0 =>
+            (offset as usize + coverage as usize - 1) * 4,
+        _ => segment.len(),
+    }
+}
+
+fn field(data: &[u8], offset: usize, length: usize) -> Result<&[u8], DissectError> {
+    data.get(offset..offset + length).ok_or_else(|| DissectError::Underflow {
+        expected: Some(offset + length), have: data.len(),
+        message: "DCCP header is truncated".to_string() })
+}
+
+/// A big-endian number of `length` bytes.
+fn number(data: &[u8], offset: usize, length: usize) -> Result<u64, DissectError> {
+    Ok(field(data, offset, length)?.iter().fold(0, |n, &b| n << 8 | b as u64))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_dccp() {
+        // A Request with a 48 b sequence number and service code "perf"
+        let data = [0x04, 0xd2, 0x13, 0x88, 5, 0x00, 0, 0, 0x01, 0x00,
+                    0, 0, 0, 0, 0x12, 0x34, b'p', b'e', b'r', b'f'];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Destination Port"].as_unsigned().unwrap(), 5000);
+        assert_eq!(val["Type Name"].as_symbol().unwrap(), "Request");
+        assert_eq!(val["Sequence Number"].as_unsigned().unwrap(), 0x1234);
+        assert_eq!(val["Service Code"].as_unsigned().unwrap(), 0x70657266);
+
+        // A Reset with short sequence numbers is 5 words long
+        let reset = [0x04, 0xd2, 0x13, 0x88, 5, 0x00, 0, 0, 0x0e, 0, 0, 7,
+                     0, 0, 0, 9, 8, 0, 0, 0];
+        let val = *dissect(&reset).unwrap();
+        assert_eq!(val["Acknowledgement Number"].as_unsigned().unwrap(), 9);
+        assert_eq!(val["Reset Code"].as_symbol().unwrap(), "Bad Service Code");
+
+        assert!(dissect(&reset[..16]).is_err());
+    }
+}
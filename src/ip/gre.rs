@@ -0,0 +1,241 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Generic Routing Encapsulation (GRE) packets (IP protocol
+//! 47), including the enhanced GRE of PPTP and ERSPAN mirrored traffic.
+//!
+//! The encapsulated payload is dissected according to the GRE protocol
+//! type, so a tunnelled packet is dissected as deeply as a bare one.
+//!
+//! See [RFC 2784](https://tools.ietf.org/html/rfc2784),
+//! [RFC 2890](https://tools.ietf.org/html/rfc2890),
+//! [RFC 2637](https://tools.ietf.org/html/rfc2637) (PPTP) and
+//! [draft-foschiano-erspan](https://tools.ietf.org/html/draft-foschiano-erspan-03).
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use NamedValues;
+use Val;
+use checksum;
+use context::Context;
+use ethernet;
+use ip;
+use mpls;
+use ppp;
+use {read_be_u16, read_be_u32};
+
+pub const TRANSPARENT_ETHERNET: u16 = 0x6558;
+pub const ERSPAN_II: u16 = 0x88be;
+pub const ERSPAN_III: u16 = 0x22eb;
+
+const CHECKSUM_PRESENT: u8 = 0x80;
+const ROUTING_PRESENT: u8 = 0x40;
+const KEY_PRESENT: u8 = 0x20;
+const SEQUENCE_PRESENT: u8 = 0x10;
+const ACK_PRESENT: u8 = 0x80;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a GRE packet, and the packet it encapsulates within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "A GRE header must be at least 4 B".to_string() });
+    }
+
+    let flags = data[0];
+    let version = data[1] & 0x07;
+    let protocol = read_be_u16(data, 2)?;
+
+    let mut values = NamedValues::new();
+    values.push(("Flags", Val::BitFlags8(flags, [
+                 None, None, None, Some("Strict Source Route"),
+                 Some("Sequence Number Present"), Some("Key Present"),
+                 Some("Routing Present"), Some("Checksum Present")])));
+    values.push(("Recursion Control", Val::Unsigned((flags & 0x07) as u64)));
+    values.push(("Version", Val::Unsigned(version as u64)));
+    values.push(("Protocol Type", Val::Unsigned(protocol as u64)));
+
+    if version > 1 {
+        return Err(DissectError::malformed(ErrorCode::UnsupportedVersion,
+            format!["unsupported GRE version {}", version]));
+    }
+
+    let length = 4
+        + if flags & (CHECKSUM_PRESENT | ROUTING_PRESENT) != 0 { 4 } else { 0 }
+        + if flags & KEY_PRESENT != 0 { 4 } else { 0 }
+        + if flags & SEQUENCE_PRESENT != 0 { 4 } else { 0 }
+        + if version == 1 && data[1] & ACK_PRESENT != 0 { 4 } else { 0 };
+
+    if data.len() < length {
+        return Err(DissectError::Underflow { expected: Some(length), have: data.len(),
+            message: "GRE header greater than available data".to_string() });
+    }
+
+    let mut offset = 4;
+    if flags & (CHECKSUM_PRESENT | ROUTING_PRESENT) != 0 {
+        values.push(("Checksum", Val::Bytes(&data[4..6])));
+        values.push(("Offset", Val::Unsigned(read_be_u16(data, 6)? as u64)));
+        if flags & CHECKSUM_PRESENT != 0 {
+            let status = if checksum::fold(checksum::sum(data)) == 0xffff { checksum::Status::Good }
+                         else { checksum::Status::Bad };
+            values.push(("Checksum Status", Val::Symbol(status.name())));
+        }
+        offset += 4;
+    }
+
+    if flags & KEY_PRESENT != 0 {
+        // Enhanced GRE (PPTP) splits the key into a length and a call ID.
+        if version == 1 {
+            values.push(("Payload Length", Val::Unsigned(read_be_u16(data, offset)? as u64)));
+            values.push(("Call ID", Val::Unsigned(read_be_u16(data, offset + 2)? as u64)));
+        } else {
+            values.push(("Key", Val::Unsigned(read_be_u32(data, offset)? as u64)));
+        }
+        offset += 4;
+    }
+
+    if flags & SEQUENCE_PRESENT != 0 {
+        values.push(("Sequence Number", Val::Unsigned(read_be_u32(data, offset)? as u64)));
+        offset += 4;
+    }
+
+    if version == 1 && data[1] & ACK_PRESENT != 0 {
+        values.push(("Acknowledgment Number", Val::Unsigned(read_be_u32(data, offset)? as u64)));
+        offset += 4;
+    }
+
+    let payload = &data[offset..];
+    let dissected = match protocol {
+        0x0800 => context.dissect(ip::dissect_with, payload),
+        0x86dd => context.dissect(ip::ipv6::dissect_with, payload),
+        0x880b => context.dissect(ppp::dissect_with, payload),
+        TRANSPARENT_ETHERNET => context.dissect(ethernet::dissect_with, payload),
+        ERSPAN_II if flags & SEQUENCE_PRESENT != 0 => erspan(payload, 2, context),
+        // ERSPAN type I has no header (and no sequence number).
+        ERSPAN_II => context.dissect(ethernet::dissect_with, payload),
+        ERSPAN_III => erspan(payload, 3, context),
+        0x8847 | 0x8848 => context.dissect(mpls::dissect_with, payload),
+        _ => {
+            values.push(("Payload", Val::Undissected("Unknown", payload)));
+            return Ok(Box::new(Val::Object("GRE", values)));
+        },
+    };
+    values.push(("Payload", Val::Payload(payload, dissected)));
+
+    Ok(Box::new(Val::Object("GRE", values)))
+}
+
+/// Dissect an ERSPAN type II or III header and the mirrored frame.
+fn erspan<'data>(data: &'data [u8], erspan_type: u8, context: &mut Context)
+    -> DissectResult<'data> {
+
+    let minimum = if erspan_type == 2 { 8 } else { 12 };
+    if data.len() < minimum {
+        return Err(DissectError::Underflow { expected: Some(minimum), have: data.len(),
+            message: format!["An ERSPAN type {} header must be at least {} B", erspan_type, minimum] });
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Version", Val::Unsigned((data[0] >> 4) as u64)));
+    values.push(("VLAN", Val::Unsigned((read_be_u16(data, 0)? & 0x0fff) as u64)));
+    values.push(("COS", Val::Unsigned((data[2] >> 5) as u64)));
+    values.push(("Truncated", Val::Unsigned(((data[2] >> 2) & 0x01) as u64)));
+    values.push(("Session ID", Val::Unsigned((read_be_u16(data, 2)? & 0x03ff) as u64)));
+
+    let mut length = minimum;
+    if erspan_type == 2 {
+        values.push(("Encapsulation", Val::Unsigned(((data[2] >> 3) & 0x03) as u64)));
+        values.push(("Index", Val::Unsigned((read_be_u32(data, 4)? & 0x000f_ffff) as u64)));
+    } else {
+        values.push(("Bad/Short/Oversized", Val::Unsigned(((data[2] >> 3) & 0x03) as u64)));
+        values.push(("Timestamp", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+        values.push(("Security Group Tag", Val::Unsigned(read_be_u16(data, 8)? as u64)));
+        values.push(("Frame Type", Val::Unsigned(((data[10] >> 2) & 0x1f) as u64)));
+        values.push(("Hardware ID", Val::Unsigned(((read_be_u16(data, 10)? >> 4) & 0x3f) as u64)));
+        values.push(("Direction", Val::Symbol(if data[11] & 0x08 != 0 { "Egress" } else { "Ingress" })));
+        values.push(("Timestamp Granularity", Val::Unsigned(((data[11] >> 1) & 0x03) as u64)));
+
+        // An optional platform-specific subheader follows.
+        if data[11] & 0x01 != 0 {
+            length += 8;
+            if data.len() < length {
+                return Err(DissectError::Underflow { expected: Some(length), have: data.len(),
+                    message: "ERSPAN platform subheader greater than available data".to_string() });
+            }
+            values.push(("Platform Subheader", Val::Bytes(&data[12..20])));
+        }
+    }
+
+    let frame = &data[length..];
+    values.push(("Payload", Val::Payload(frame, context.dissect(ethernet::dissect_with, frame))));
+    Ok(Box::new(Val::Object("ERSPAN", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use context::MAX_DEPTH;
+
+    const INNER: [u8; 20] = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+
+    #[test]
+    fn dissect_gre() {
+        // Key and sequence number, carrying IPv4
+        let mut data = vec![0x30, 0x00, 0x08, 0x00, 0, 0, 0, 42, 0, 0, 0, 7];
+        data.extend_from_slice(&INNER);
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Key"].as_unsigned().unwrap(), 42);
+        assert_eq!(val["Sequence Number"].as_unsigned().unwrap(), 7);
+        assert_eq!(val["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        // ERSPAN type II: session 5, mirroring an Ethernet frame
+        let mut data = vec![0x10, 0x00, 0x88, 0xbe, 0, 0, 0, 1, 0x10, 0x0a, 0x00, 0x05, 0, 0, 0, 0];
+        data.extend_from_slice(&[0xff; 12]);
+        data.extend_from_slice(&[0x08, 0x00]);
+        data.extend_from_slice(&INNER);
+        let val = *dissect(&data).unwrap();
+        let erspan = &val["Payload"];
+        assert_eq!(erspan["Session ID"].as_unsigned().unwrap(), 5);
+        assert_eq!(erspan["VLAN"].as_unsigned().unwrap(), 10);
+        assert_eq!(erspan["Payload"]["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        assert!(dissect(&[0x30, 0x00, 0x08, 0x00, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn nested_too_deep() {
+        // IPv4 carrying GRE carrying IPv4... far deeper than dissectors may nest
+        let mut data = INNER.to_vec();
+        for _ in 0..1000 {
+            let mut outer = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 47, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                                 0x00, 0x00, 0x08, 0x00];
+            outer.extend_from_slice(&data);
+            data = outer;
+        }
+
+        let val = ip::dissect(&data).unwrap();
+        let mut layer = &*val;
+        let mut depth = 0;
+        let error = loop {
+            match layer["Payload"] {
+                Val::Payload(_, Ok(ref inner)) => layer = inner,
+                Val::Payload(_, Err(ref e)) => break e.to_string(),
+                ref other => panic!["unexpected payload: {:?}", other],
+            }
+            depth += 1;
+        };
+
+        assert!(depth <= MAX_DEPTH, "dissected {} layers deep", depth);
+        assert!(error.contains("nested more than"));
+    }
+}
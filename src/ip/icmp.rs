@@ -0,0 +1,167 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Internet Control Message Protocol (ICMP) messages.
+//!
+//! Error messages quote the IP header and the first 8 B of the datagram that
+//! caused them, which is re-dissected as an `Original Datagram` payload so
+//! that the error can be traced to the offending flow (see
+//! `flow::FlowKey::quoted`). 8 B is a whole UDP header but only the ports
+//! and sequence number of a TCP header, so a quoted TCP layer is an error.
+//!
+//! See [RFC 792](https://tools.ietf.org/html/rfc792).
+
+use DissectError;
+use DissectResult;
+use Val;
+use NamedValues;
+use context::Context;
+use raw;
+use read_be_u16;
+
+pub const ECHO_REPLY: u8 = 0;
+pub const DESTINATION_UNREACHABLE: u8 = 3;
+pub const REDIRECT: u8 = 5;
+pub const ECHO_REQUEST: u8 = 8;
+pub const TIME_EXCEEDED: u8 = 11;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect an ICMP message, and any datagram it quotes within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "An ICMP message must be at least 8 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let message_type = data[0];
+    values.push(("Type", Val::Unsigned(message_type as u64)));
+    if let Some(name) = type_name(message_type) {
+        values.push(("Type Name", Val::Symbol(name)));
+    }
+
+    let code = data[1];
+    values.push(("Code", Val::Unsigned(code as u64)));
+    if let Some(name) = code_name(message_type, code) {
+        values.push(("Code Name", Val::Symbol(name)));
+    }
+
+    values.push(("Checksum", Val::Bytes(&data[2..4])));
+
+    match message_type {
+        ECHO_REPLY | ECHO_REQUEST => {
+            values.push(("Identifier", Val::Unsigned(read_be_u16(data, 4)? as u64)));
+            values.push(("Sequence Number", Val::Unsigned(read_be_u16(data, 6)? as u64)));
+            values.push(("Payload", Val::Payload(&data[8..], raw("Data", &data[8..]))));
+        },
+
+        // Errors quote the header (and some data) of the offending datagram.
+        DESTINATION_UNREACHABLE | REDIRECT | TIME_EXCEEDED => {
+            if message_type == REDIRECT {
+                values.push(("Gateway", Val::ipv4(&data[4..8])));
+            } else if message_type == DESTINATION_UNREACHABLE && code == 4 {
+                values.push(("Next-Hop MTU", Val::Unsigned(read_be_u16(data, 6)? as u64)));
+            }
+
+            let quoted = &data[8..];
+            values.push(("Original Datagram",
+                         Val::Payload(quoted, context.dissect(super::dissect_with, quoted))));
+        },
+
+        _ => {
+            values.push(("Rest of Header", Val::Bytes(&data[4..8])));
+            values.push(("Payload", Val::Payload(&data[8..], raw("Data", &data[8..]))));
+        },
+    }
+
+    Ok(Box::new(Val::Object("ICMP", values)))
+}
+
+/// The name of an ICMP message type.
+pub fn type_name(message_type: u8) -> Option<&'static str> {
+    Some(match message_type {
+        ECHO_REPLY => "Echo Reply",
+        DESTINATION_UNREACHABLE => "Destination Unreachable",
+        4 => "Source Quench",
+        REDIRECT => "Redirect",
+        ECHO_REQUEST => "Echo Request",
+        9 => "Router Advertisement",
+        10 => "Router Solicitation",
+        TIME_EXCEEDED => "Time Exceeded",
+        12 => "Parameter Problem",
+        13 => "Timestamp",
+        14 => "Timestamp Reply",
+        _ => return None,
+    })
+}
+
+/// The name of a code within an ICMP message type (e.g., the cause of a
+/// Destination Unreachable message).
+pub fn code_name(message_type: u8, code: u8) -> Option<&'static str> {
+    Some(match (message_type, code) {
+        (DESTINATION_UNREACHABLE, 0) => "Network Unreachable",
+        (DESTINATION_UNREACHABLE, 1) => "Host Unreachable",
+        (DESTINATION_UNREACHABLE, 2) => "Protocol Unreachable",
+        (DESTINATION_UNREACHABLE, 3) => "Port Unreachable",
+        (DESTINATION_UNREACHABLE, 4) => "Fragmentation Needed",
+        (DESTINATION_UNREACHABLE, 5) => "Source Route Failed",
+        (DESTINATION_UNREACHABLE, 6) => "Destination Network Unknown",
+        (DESTINATION_UNREACHABLE, 7) => "Destination Host Unknown",
+        (DESTINATION_UNREACHABLE, 8) => "Source Host Isolated",
+        (DESTINATION_UNREACHABLE, 9) => "Network Administratively Prohibited",
+        (DESTINATION_UNREACHABLE, 10) => "Host Administratively Prohibited",
+        (DESTINATION_UNREACHABLE, 11) => "Network Unreachable for TOS",
+        (DESTINATION_UNREACHABLE, 12) => "Host Unreachable for TOS",
+        (DESTINATION_UNREACHABLE, 13) => "Communication Administratively Prohibited",
+        (DESTINATION_UNREACHABLE, 14) => "Host Precedence Violation",
+        (DESTINATION_UNREACHABLE, 15) => "Precedence Cutoff in Effect",
+
+        (REDIRECT, 0) => "Redirect for Network",
+        (REDIRECT, 1) => "Redirect for Host",
+        (REDIRECT, 2) => "Redirect for TOS and Network",
+        (REDIRECT, 3) => "Redirect for TOS and Host",
+
+        (TIME_EXCEEDED, 0) => "TTL Exceeded in Transit",
+        (TIME_EXCEEDED, 1) => "Fragment Reassembly Time Exceeded",
+
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_icmp() {
+        let echo = [8, 0, 0xf7, 0xfd, 0x00, 0x01, 0x00, 0x02, 0xde, 0xad];
+        let val = *dissect(&echo).unwrap();
+        assert_eq!(val["Type Name"].as_symbol().unwrap(), "Echo Request");
+        assert_eq!(val["Sequence Number"].as_unsigned().unwrap(), 2);
+        assert_eq!(val["Payload"]["raw data"].as_bytes().unwrap(), &[0xde, 0xad]);
+
+        let unreachable = [3, 4, 0, 0, 0, 0, 0x05, 0xdc,
+                           0x45, 0x00, 0x05, 0xdc, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+                           10, 0, 0, 1, 10, 0, 0, 2,
+                           0x13, 0x88, 0x00, 0x35, 0x05, 0xc8, 0x00, 0x00];
+        let val = *dissect(&unreachable).unwrap();
+        assert_eq!(val["Code Name"].as_symbol().unwrap(), "Fragmentation Needed");
+        assert_eq!(val["Next-Hop MTU"].as_unsigned().unwrap(), 1500);
+
+        let quoted = &val["Original Datagram"];
+        assert_eq!(quoted["Source"].as_address_encoded().unwrap(), "10.0.0.1");
+        assert_eq!(quoted["Payload"]["Destination Port"].as_unsigned().unwrap(), 53);
+
+        assert!(dissect(&echo[..7]).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Internet Group Management Protocol (IGMP) messages,
+//! versions 1 to 3.
+//!
+//! See [RFC 2236](https://tools.ietf.org/html/rfc2236) and
+//! [RFC 3376](https://tools.ietf.org/html/rfc3376).
+
+use DissectError;
+use DissectResult;
+use Val;
+use NamedValues;
+use read_be_u16;
+
+pub const MEMBERSHIP_QUERY: u8 = 0x11;
+pub const V1_MEMBERSHIP_REPORT: u8 = 0x12;
+pub const V2_MEMBERSHIP_REPORT: u8 = 0x16;
+pub const LEAVE_GROUP: u8 = 0x17;
+pub const V3_MEMBERSHIP_REPORT: u8 = 0x22;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "An IGMP message must be at least 8 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let message_type = data[0];
+    values.push(("Type", Val::Unsigned(message_type as u64)));
+    values.push(("Type Name", TYPES.val(message_type)));
+    values.push(("Max Response Time", Val::Unsigned(data[1] as u64)));
+    values.push(("Checksum", Val::Bytes(&data[2..4])));
+
+    if message_type != V3_MEMBERSHIP_REPORT {
+        values.push(("Group Address", Val::ipv4(&data[4..8])));
+
+        // A version 3 query has at least 12 B, with a list of sources.
+        if message_type == MEMBERSHIP_QUERY && data.len() >= 12 {
+            let count = read_be_u16(data, 10)? as usize;
+            values.push(("Number of Sources", Val::Unsigned(count as u64)));
+            sources(&data[12..], count, &mut values)?;
+        }
+
+        return Ok(Box::new(Val::Object("IGMP", values)));
+    }
+
+    let count = read_be_u16(data, 6)? as usize;
+    values.push(("Number of Group Records", Val::Unsigned(count as u64)));
+
+    let mut offset = 8;
+    for _ in 0..count {
+        if data.len() < offset + 8 {
+            return Err(DissectError::Underflow { expected: Some(offset + 8), have: data.len(),
+                message: "IGMPv3 group record greater than available data".to_string() });
+        }
+
+        let record = &data[offset..];
+        let record_type = record[0];
+        let auxiliary = record[1] as usize * 4;
+        let sources_count = read_be_u16(record, 2)? as usize;
+
+        let mut fields = NamedValues::new();
+        fields.push(("Record Type", Val::Unsigned(record_type as u64)));
+        fields.push(("Record Type Name", RECORD_TYPES.val(record_type)));
+        fields.push(("Number of Sources", Val::Unsigned(sources_count as u64)));
+        fields.push(("Multicast Address", Val::ipv4(&record[4..8])));
+        sources(&record[8..], sources_count, &mut fields)?;
+
+        values.push(("Group Record", Val::Object("IGMP Group Record", fields)));
+        offset += 8 + 4 * sources_count + auxiliary;
+    }
+
+    Ok(Box::new(Val::Object("IGMP", values)))
+}
+
+enum_map!(pub TYPES {
+    MEMBERSHIP_QUERY => "Membership Query",
+    V1_MEMBERSHIP_REPORT => "Version 1 Membership Report",
+    V2_MEMBERSHIP_REPORT => "Version 2 Membership Report",
+    LEAVE_GROUP => "Leave Group",
+    V3_MEMBERSHIP_REPORT => "Version 3 Membership Report",
+});
+
+enum_map!(pub RECORD_TYPES {
+    1 => "MODE_IS_INCLUDE",
+    2 => "MODE_IS_EXCLUDE",
+    3 => "CHANGE_TO_INCLUDE_MODE",
+    4 => "CHANGE_TO_EXCLUDE_MODE",
+    5 => "ALLOW_NEW_SOURCES",
+    6 => "BLOCK_OLD_SOURCES",
+});
+
+fn sources<'data>(data: &'data [u8], count: usize, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    if data.len() < 4 * count {
+        return Err(DissectError::Underflow { expected: Some(4 * count), have: data.len(),
+            message: "IGMP source list greater than available data".to_string() });
+    }
+
+    for source in data[..4 * count].chunks(4) {
+        values.push(("Source", Val::ipv4(source)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_igmp() {
+        let v2 = [0x16, 0x00, 0xfa, 0x04, 239, 1, 2, 3];
+        let val = *dissect(&v2).unwrap();
+        assert_eq!(val["Type Name"].as_symbol().unwrap(), "Version 2 Membership Report");
+        assert_eq!(val["Group Address"].as_address_encoded().unwrap(), "239.1.2.3");
+
+        let v3 = [0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+                  0x04, 0x00, 0x00, 0x00, 239, 1, 2, 3,
+                  0x01, 0x00, 0x00, 0x01, 232, 0, 0, 1, 10, 0, 0, 9];
+        let val = *dissect(&v3).unwrap();
+        assert_eq!(val["Number of Group Records"].as_unsigned().unwrap(), 2);
+        assert_eq!(val["Group Record"]["Record Type Name"].as_symbol().unwrap(), "CHANGE_TO_EXCLUDE_MODE");
+
+        assert!(dissect(&v3[..20]).is_err());
+        assert_eq!(dissect(&[0x99, 0, 0, 0, 0, 0, 0, 0]).unwrap()["Type Name"],
+                   Val::String("Unknown (153)".to_string()));
+    }
+}
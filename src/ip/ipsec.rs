@@ -0,0 +1,578 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of IPsec headers: the Authentication Header (AH, IP protocol
+//! 51), the Encapsulating Security Payload (ESP, IP protocol 50) and ESP in
+//! UDP for NAT traversal (port 4500).
+//!
+//! Without keys, all that can be seen of ESP is its SPI, its sequence
+//! number and the length of its encrypted payload. Given the security
+//! associations (SAs) in use, as in Wireshark's ESP SA table, an `SaTable`
+//! can decrypt ESP payloads so that the inner packet can be dissected:
+//!
+//! ```
+//! use rshark::ip::ipsec::{Null, SaTable, SecurityAssociation};
+//!
+//! let packet = [
+//!     0x45, 0, 0, 0x28, 0, 0, 0, 0, 64, 50, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+//!     0x00, 0x00, 0x10, 0x01, 0, 0, 0, 1,
+//!     0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00,   // UDP
+//!     0x01, 0x02, 0x02, 17];                            // padding, next header
+//! let val = rshark::ip::dissect(&packet).unwrap();
+//!
+//! let sas = SaTable::new().add(SecurityAssociation::new(0x1001, Null).icv_length(0));
+//! let decrypted = sas.decrypt_packet(&val).unwrap();
+//! assert_eq!(decrypted.dissect().unwrap()["Payload"]["Destination Port"].as_unsigned(), Some(53));
+//! ```
+//!
+//! NULL encryption is always available. With the `crypto` feature, so are
+//! AES-128-CBC with HMAC-SHA1-96 (`AesCbcHmacSha1`) and AES-128-GCM
+//! (`AesGcm`), which check the ICV before decrypting; any other algorithm
+//! can be supplied by the embedding application as a `Cipher`.
+//!
+//! See [RFC 4302](https://tools.ietf.org/html/rfc4302),
+//! [RFC 4303](https://tools.ietf.org/html/rfc4303) and
+//! [RFC 3948](https://tools.ietf.org/html/rfc3948).
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use context::Context;
+#[cfg(feature = "crypto")]
+use crypto;
+use keys::{KeyId, KeyProvider};
+use read_be_u32;
+use super::{ipv6, tcp, udp};
+
+pub const NAT_T_PORT: u16 = 4500;
+
+pub fn dissect_esp(data : &[u8]) -> DissectResult {
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "An ESP header must be at least 8 B".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("SPI", Val::Unsigned(read_be_u32(data, 0)? as u64)));
+    values.push(("Sequence Number", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+
+    // The payload, padding, next header and ICV are all encrypted or opaque.
+    values.push(("Encrypted Payload Length", Val::Unsigned((data.len() - 8) as u64)));
+    values.push(("Encrypted Payload", Val::Bytes(&data[8..])));
+
+    Ok(Box::new(Val::Object("ESP", values)))
+}
+
+pub fn dissect_ah(data : &[u8]) -> DissectResult {
+    dissect_ah_with(data, &mut Context::new())
+}
+
+/// Dissect an AH header, and the packet it authenticates within a context.
+pub fn dissect_ah_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 12 {
+        return Err(DissectError::Underflow { expected: Some(12), have: data.len(),
+            message: "An AH header must be at least 12 B".to_string() });
+    }
+
+    // The payload length is in 32b words, less two.
+    let next_header = data[0];
+    let length = (data[1] as usize + 2) * 4;
+    if length < 12 || length > data.len() {
+        return Err(DissectError::Underflow { expected: Some(length), have: data.len(),
+            message: "AH length greater than available data".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Next Header", Val::Unsigned(next_header as u64)));
+    values.push(("Length", Val::Unsigned(length as u64)));
+    values.push(("SPI", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+    values.push(("Sequence Number", Val::Unsigned(read_be_u32(data, 8)? as u64)));
+    values.push(("ICV", Val::Bytes(&data[12..length])));
+
+    if let Some(payload) = payload(next_header, &data[length..], context) {
+        values.push(("Payload", payload));
+    }
+
+    Ok(Box::new(Val::Object("AH", values)))
+}
+
+/// Dissect what follows AH or decrypted ESP, according to its next header
+/// (or nothing, for "No Next Header").
+fn payload<'data>(next_header: u8, data: &'data [u8], context: &mut Context)
+    -> Option<Val<'data>> {
+
+    match next_header {
+        4 => Some(Val::Payload(data, context.dissect(super::dissect_with, data))),
+        6 => Some(Val::Payload(data, context.dissect(tcp::dissect_with, data))),
+        17 => Some(Val::Payload(data, context.dissect(udp::dissect_with, data))),
+        41 => Some(Val::Payload(data, context.dissect(ipv6::dissect_with, data))),
+        50 => Some(Val::Payload(data, context.dissect_stateless(dissect_esp, data))),
+        59 => None,
+        _ => Some(Val::Undissected("Unknown", data)),
+    }
+}
+
+/// Dissect a UDP-encapsulated packet on the NAT traversal port, which may
+/// be ESP, IKE (after a zero "non-ESP marker") or a NAT keepalive.
+pub fn dissect_nat_t(data : &[u8]) -> DissectResult {
+    if data == [0xff] {
+        return Ok(Box::new(Val::Object("NAT-T Keepalive", NamedValues::new())));
+    }
+
+    if data.len() >= 4 && data[..4] == [0, 0, 0, 0] {
+        let mut values = NamedValues::new();
+        values.push(("Non-ESP Marker", Val::Bytes(&data[..4])));
+        values.push(("Payload", Val::Undissected("IKE", &data[4..])));
+        return Ok(Box::new(Val::Object("NAT-T", values)));
+    }
+
+    dissect_esp(data)
+}
+
+/// An ESP encryption (and perhaps integrity) algorithm.
+pub trait Cipher {
+    fn name(&self) -> &'static str;
+
+    /// The length of the IV at the start of the encrypted payload.
+    fn iv_length(&self) -> usize;
+
+    /// The usual length of the ICV at the end of the packet (that of
+    /// HMAC-SHA1-96 unless the algorithm says otherwise).
+    fn icv_length(&self) -> usize { 12 }
+
+    /// Decrypt `ciphertext`, returning None if it can't be decrypted.
+    fn decrypt(&self, key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>>;
+
+    /// Check the ICV of an ESP packet whose `header` is its SPI and sequence
+    /// number, then decrypt it. By default, the ICV isn't checked.
+    fn open(&self, key: &[u8], header: &[u8], iv: &[u8], ciphertext: &[u8], icv: &[u8])
+        -> Option<Vec<u8>> {
+
+        let _ = (header, icv);
+        self.decrypt(key, iv, ciphertext)
+    }
+}
+
+/// NULL encryption, used for integrity-only ESP
+/// (see [RFC 2410](https://tools.ietf.org/html/rfc2410)).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Null;
+
+impl Cipher for Null {
+    fn name(&self) -> &'static str { "NULL" }
+    fn iv_length(&self) -> usize { 0 }
+
+    fn decrypt(&self, _key: &[u8], _iv: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        Some(ciphertext.to_vec())
+    }
+}
+
+/// AES-128-CBC encryption with HMAC-SHA1-96 integrity
+/// (see [RFC 3602](https://tools.ietf.org/html/rfc3602) and
+/// [RFC 2404](https://tools.ietf.org/html/rfc2404)). Its key is the 16 B
+/// encryption key followed by the 20 B authentication key.
+#[cfg(feature = "crypto")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AesCbcHmacSha1;
+
+#[cfg(feature = "crypto")]
+impl Cipher for AesCbcHmacSha1 {
+    fn name(&self) -> &'static str { "AES-CBC with HMAC-SHA1-96" }
+    fn iv_length(&self) -> usize { 16 }
+
+    fn decrypt(&self, key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let mut aes_key = [0u8; 16];
+        let mut aes_iv = [0u8; 16];
+        if key.len() < 16 || iv.len() != 16 {
+            return None;
+        }
+        aes_key.copy_from_slice(&key[..16]);
+        aes_iv.copy_from_slice(iv);
+
+        crypto::cbc_decrypt(&aes_key, &aes_iv, ciphertext)
+    }
+
+    fn open(&self, key: &[u8], header: &[u8], iv: &[u8], ciphertext: &[u8], icv: &[u8])
+        -> Option<Vec<u8>> {
+
+        if key.len() != 36 {
+            return None;
+        }
+
+        let mac = crypto::hmac_sha1(&key[16..], &[header, iv, ciphertext].concat());
+        if icv.len() > mac.len() || icv != &mac[..icv.len()] {
+            return None;
+        }
+
+        self.decrypt(key, iv, ciphertext)
+    }
+}
+
+/// AES-128-GCM, with an ICV of 16 B
+/// (see [RFC 4106](https://tools.ietf.org/html/rfc4106)). Its key is the
+/// 16 B AES key followed by a 4 B salt.
+#[cfg(feature = "crypto")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AesGcm;
+
+#[cfg(feature = "crypto")]
+impl AesGcm {
+    fn split(key: &[u8], iv: &[u8]) -> Option<([u8; 16], [u8; 12])> {
+        if key.len() != 20 || iv.len() != 8 {
+            return None;
+        }
+
+        let mut aes_key = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        aes_key.copy_from_slice(&key[..16]);
+        nonce[..4].copy_from_slice(&key[16..]);
+        nonce[4..].copy_from_slice(iv);
+        Some((aes_key, nonce))
+    }
+}
+
+#[cfg(feature = "crypto")]
+impl Cipher for AesGcm {
+    fn name(&self) -> &'static str { "AES-GCM" }
+    fn iv_length(&self) -> usize { 8 }
+    fn icv_length(&self) -> usize { 16 }
+
+    /// GCM can't decrypt without checking the ICV, which this isn't given.
+    fn decrypt(&self, _key: &[u8], _iv: &[u8], _ciphertext: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn open(&self, key: &[u8], header: &[u8], iv: &[u8], ciphertext: &[u8], icv: &[u8])
+        -> Option<Vec<u8>> {
+
+        let (aes_key, nonce) = AesGcm::split(key, iv)?;
+        crypto::gcm_decrypt(&aes_key, &nonce, header, ciphertext, icv)
+    }
+}
+
+/// An ESP security association, identified by its SPI and (optionally)
+/// its destination address.
+pub struct SecurityAssociation {
+    spi: u32,
+    destination: Option<IpAddr>,
+    cipher: Box<dyn Cipher>,
+    key: Option<Vec<u8>>,
+    icv_length: usize,
+}
+
+impl SecurityAssociation {
+    /// An SA with its cipher's usual ICV, whose key is found by the
+    /// `SaTable`'s key provider unless given with `key`.
+    pub fn new<C: Cipher + 'static>(spi: u32, cipher: C) -> SecurityAssociation {
+        SecurityAssociation {
+            spi: spi,
+            destination: None,
+            icv_length: cipher.icv_length(),
+            cipher: Box::new(cipher),
+            key: None,
+        }
+    }
+
+    /// Only match packets sent to `destination`.
+    pub fn destination(mut self, destination: IpAddr) -> SecurityAssociation {
+        self.destination = Some(destination);
+        self
+    }
+
+    pub fn key(mut self, key: Vec<u8>) -> SecurityAssociation {
+        self.key = Some(key);
+        self
+    }
+
+    /// The length of the integrity check value at the end of the packet.
+    pub fn icv_length(mut self, length: usize) -> SecurityAssociation {
+        self.icv_length = length;
+        self
+    }
+
+    fn matches(&self, spi: u32, destination: Option<IpAddr>) -> bool {
+        self.spi == spi && (self.destination.is_none() || self.destination == destination)
+    }
+}
+
+impl fmt::Debug for SecurityAssociation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "SA {:08x} ({})", self.spi, self.cipher.name()]
+    }
+}
+
+/// The security associations to decrypt ESP with.
+#[derive(Default)]
+pub struct SaTable {
+    sas: Vec<SecurityAssociation>,
+    keys: Option<Box<dyn KeyProvider>>,
+}
+
+impl SaTable {
+    pub fn new() -> SaTable {
+        SaTable::default()
+    }
+
+    pub fn add(mut self, sa: SecurityAssociation) -> SaTable {
+        self.sas.push(sa);
+        self
+    }
+
+    /// Where to find the keys of SAs that weren't given one.
+    pub fn keys<P: KeyProvider + 'static>(mut self, provider: P) -> SaTable {
+        self.keys = Some(Box::new(provider));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.sas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sas.is_empty()
+    }
+
+    pub fn lookup(&self, spi: u32, destination: Option<IpAddr>) -> Option<&SecurityAssociation> {
+        self.sas.iter().find(|sa| sa.matches(spi, destination))
+    }
+
+    /// Decrypt an ESP packet (starting at its SPI) sent to `destination`.
+    pub fn decrypt(&self, esp: &[u8], destination: Option<IpAddr>) -> Result<Decrypted, DissectError> {
+        let spi = read_be_u32(esp, 0)?;
+        let sa = self.lookup(spi, destination).ok_or_else(||
+            DissectError::InvalidData(format!["no SA for SPI {:08x}", spi]))?;
+
+        let key = match (&sa.key, &self.keys, destination) {
+            (&Some(ref key), _, _) => key.clone(),
+            (&None, &Some(ref keys), Some(destination)) =>
+                keys.key(&KeyId::Esp { spi: spi, destination: destination }).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        let iv_end = 8 + sa.cipher.iv_length();
+        if esp.len() < iv_end + sa.icv_length + 2 {
+            return Err(DissectError::Underflow { expected: Some(iv_end + sa.icv_length + 2),
+                have: esp.len(), message: "ESP packet too short for its SA".to_string() });
+        }
+
+        let (ciphertext, icv) = esp[iv_end..].split_at(esp.len() - iv_end - sa.icv_length);
+        let mut plaintext = sa.cipher.open(&key, &esp[..8], &esp[8..iv_end], ciphertext, icv)
+            .ok_or_else(|| DissectError::InvalidData(
+                format!["unable to authenticate or decrypt ESP with SPI {:08x}", spi]))?;
+
+        // The plaintext ends with padding, the padding length and the next header.
+        let next_header = plaintext.pop().unwrap_or(0);
+        let pad_length = plaintext.pop().unwrap_or(0) as usize;
+        if pad_length > plaintext.len() {
+            return Err(DissectError::InvalidData(
+                format!["ESP padding of {} B is longer than the payload (wrong key?)", pad_length]));
+        }
+
+        let length = plaintext.len() - pad_length;
+        plaintext.truncate(length);
+
+        Ok(Decrypted { spi: spi, next_header: next_header, data: plaintext })
+    }
+
+    /// Find the outermost ESP layer of a dissected packet and decrypt it.
+    pub fn decrypt_packet(&self, packet: &Val) -> Result<Decrypted, DissectError> {
+        match find_esp(packet, None) {
+            Some((esp, destination)) => self.decrypt(esp, destination),
+            None => Err(DissectError::InvalidData("no ESP payload to decrypt".to_string())),
+        }
+    }
+}
+
+/// A decrypted ESP payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decrypted {
+    pub spi: u32,
+    pub next_header: u8,
+    pub data: Vec<u8>,
+}
+
+impl Decrypted {
+    /// Dissect the inner packet.
+    pub fn dissect(&self) -> DissectResult {
+        let mut values = NamedValues::new();
+        values.push(("SPI", Val::Unsigned(self.spi as u64)));
+        values.push(("Next Header", Val::Unsigned(self.next_header as u64)));
+        if let Some(payload) = payload(self.next_header, &self.data, &mut Context::new()) {
+            values.push(("Payload", payload));
+        }
+
+        Ok(Box::new(Val::Object("Decrypted ESP", values)))
+    }
+}
+
+/// The raw bytes of the outermost ESP header and payload, along with the
+/// destination address of the IP header carrying them.
+fn find_esp<'a>(val: &'a Val<'a>, destination: Option<IpAddr>) -> Option<(&'a [u8], Option<IpAddr>)> {
+    let (name, values) = match val.as_object() {
+        Some(obj) => obj,
+        None => return None,
+    };
+
+    let destination = match name {
+        "IPv4" | "IPv6" => values.iter()
+            .find(|&&(k, _)| k == "Destination")
+            .and_then(|&(_, ref v)| v.as_address_bytes())
+            .and_then(address)
+            .or(destination),
+        _ => destination,
+    };
+
+    values.iter()
+        .filter_map(|&(_, ref v)| match v {
+            &Val::Payload(data, Ok(ref inner)) => match inner.as_object() {
+                Some(("ESP", _)) => Some((data, destination)),
+                _ => find_esp(inner, destination),
+            },
+            _ => None,
+        })
+        .next()
+}
+
+fn address(bytes: &[u8]) -> Option<IpAddr> {
+    if bytes.len() == 4 {
+        Some(IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])))
+    } else if bytes.len() == 16 {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(bytes);
+        Some(IpAddr::V6(Ipv6Addr::from(octets)))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_ipsec() {
+        let esp = [0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x07, 0xaa, 0xbb, 0xcc, 0xdd];
+        let val = *dissect_esp(&esp).unwrap();
+        assert_eq!(val["SPI"].as_unsigned().unwrap(), 0x1001);
+        assert_eq!(val["Sequence Number"].as_unsigned().unwrap(), 7);
+        assert_eq!(val["Encrypted Payload Length"].as_unsigned().unwrap(), 4);
+
+        // AH with a 12 B ICV, protecting UDP
+        let mut ah = vec![17, 4, 0, 0, 0x00, 0x00, 0x20, 0x02, 0x00, 0x00, 0x00, 0x01];
+        ah.extend_from_slice(&[0x11; 12]);
+        ah.extend_from_slice(&[0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+        let val = *dissect_ah(&ah).unwrap();
+        assert_eq!(val["SPI"].as_unsigned().unwrap(), 0x2002);
+        assert_eq!(val["ICV"].as_bytes().unwrap().len(), 12);
+        assert_eq!(val["Payload"]["Destination Port"].as_unsigned().unwrap(), 53);
+
+        assert_eq!(dissect_nat_t(&esp).unwrap().as_object().unwrap().0, "ESP");
+        assert_eq!(dissect_nat_t(&[0xff]).unwrap().as_object().unwrap().0, "NAT-T Keepalive");
+        assert!(dissect_ah(&ah[..20]).is_err());
+    }
+
+    /// A toy cipher that XORs with the key, with a 4 B IV.
+    struct Xor;
+
+    impl Cipher for Xor {
+        fn name(&self) -> &'static str { "XOR" }
+        fn iv_length(&self) -> usize { 4 }
+
+        fn decrypt(&self, key: &[u8], _iv: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+            if key.is_empty() { return None }
+            Some(ciphertext.iter().zip(key.iter().cycle()).map(|(c, k)| c ^ k).collect())
+        }
+    }
+
+    #[test]
+    fn decrypt_esp() {
+        let plaintext = [0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, 0x01, 0x02, 0x02, 17];
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 50, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                              0x00, 0x00, 0x20, 0x02, 0, 0, 0, 1, 0xaa, 0xbb, 0xcc, 0xdd];
+        packet.extend(plaintext.iter().map(|b| b ^ 0x5a));
+        packet.extend_from_slice(&[0x11; 12]);
+        let length = packet.len() as u8;
+        packet[3] = length;
+        let val = ::ip::dissect(&packet).unwrap();
+
+        // The key comes from a key provider, for the SA's destination only.
+        let destination: IpAddr = "10.0.0.2".parse().unwrap();
+        let sas = SaTable::new()
+            .add(SecurityAssociation::new(0x2002, Xor).destination(destination))
+            .keys(|id: &KeyId| match id {
+                &KeyId::Esp { spi: 0x2002, .. } => Some(vec![0x5a]),
+                _ => None,
+            });
+
+        let decrypted = sas.decrypt_packet(&val).unwrap();
+        assert_eq!(decrypted.next_header, 17);
+        assert_eq!(decrypted.data, &plaintext[..8]);
+        assert_eq!(decrypted.dissect().unwrap()["Payload"]["Source Port"].as_unsigned(), Some(1234));
+
+        let elsewhere = SaTable::new().add(SecurityAssociation::new(0x2002, Xor)
+                                           .destination("10.0.0.9".parse().unwrap()));
+        assert!(elsewhere.decrypt_packet(&val).is_err());
+        assert!(SaTable::new().add(SecurityAssociation::new(0x2002, Xor)).decrypt_packet(&val).is_err());
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn decrypt_aes() {
+        let plaintext = [0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, 0x01, 0x02, 0x02, 17];
+        let header = [0x00, 0x00, 0x30, 0x03, 0, 0, 0, 9];
+        let key = (0..20).collect::<Vec<u8>>();
+        let iv = [0xa5; 8];
+
+        let mut nonce = [0u8; 12];
+        nonce[..4].copy_from_slice(&key[16..]);
+        nonce[4..].copy_from_slice(&iv);
+        let mut aes_key = [0u8; 16];
+        aes_key.copy_from_slice(&key[..16]);
+        let sealed = crypto::gcm_encrypt(&aes_key, &nonce, &header, &plaintext);
+
+        let mut esp = header.to_vec();
+        esp.extend_from_slice(&iv);
+        esp.extend_from_slice(&sealed);
+
+        let sas = SaTable::new().add(SecurityAssociation::new(0x3003, AesGcm).key(key));
+        let decrypted = sas.decrypt(&esp, None).unwrap();
+        assert_eq!(decrypted.next_header, 17);
+        assert_eq!(decrypted.data, &plaintext[..8]);
+
+        let last = esp.len() - 1;
+        esp[last] ^= 1;
+        assert!(sas.decrypt(&esp, None).is_err());
+
+        // One CBC block: UDP, 6 B of padding, the padding length and next header
+        let key = (0..36).collect::<Vec<u8>>();
+        let iv = [0x3c; 16];
+        let mut block = [0u8; 16];
+        block[..8].copy_from_slice(&plaintext[..8]);
+        block[8..].copy_from_slice(&[1, 2, 3, 4, 5, 6, 6, 17]);
+        for (b, i) in block.iter_mut().zip(iv.iter()) {
+            *b ^= *i;
+        }
+        aes_key.copy_from_slice(&key[..16]);
+        crypto::Aes128::new(&aes_key).encrypt_block(&mut block);
+
+        let mut esp = [&header[..], &iv, &block].concat();
+        let icv = crypto::hmac_sha1(&key[16..], &esp);
+        esp.extend_from_slice(&icv[..12]);
+
+        let cbc = SaTable::new().add(SecurityAssociation::new(0x3003, AesCbcHmacSha1).key(key));
+        assert_eq!(cbc.decrypt(&esp, None).unwrap().data, &plaintext[..8]);
+
+        esp[30] ^= 1;
+        assert!(cbc.decrypt(&esp, None).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Internet Protocol version 6 (IPv6) packets.
+//!
+//! Extension headers are skipped (their types are recorded as "Extension
+//! Header" values) to find the upper-layer protocol.
+//!
+//! See [RFC 8200](https://tools.ietf.org/html/rfc8200).
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use Val;
+use NamedValues;
+use context::Context;
+use iana;
+use {read_be_u16, read_be_u32};
+use registry::Table;
+use super::{payload, transport};
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect an IPv6 packet, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 40 {
+        return Err(DissectError::Underflow { expected: Some(40), have: data.len(),
+            message: "An IPv6 packet must be at least 40 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let version = data[0] >> 4;
+    if version != 6 {
+        return Err(DissectError::malformed(ErrorCode::UnsupportedVersion,
+            format!["IP version {} is not IPv6", version]));
+    }
+    values.push(("Version", Val::Unsigned(version as u64)));
+
+    let traffic_class = (data[0] & 0x0f) << 4 | data[1] >> 4;
+    values.push(("DSCP", Val::Unsigned((traffic_class >> 2) as u64)));
+    values.push(("ECN", Val::Unsigned((traffic_class & 0x03) as u64)));
+
+    let flow_label = read_be_u32(data, 0)? & 0xfffff;
+    values.push(("Flow Label", Val::Unsigned(flow_label as u64)));
+
+    // Length of everything after this header
+    let length = read_be_u16(data, 4)? as usize;
+    values.push(("Payload Length", Val::Unsigned(length as u64)));
+
+    let mut next_header = data[6];
+    values.push(("Next Header", iana::PROTOCOLS.enum_val(next_header)));
+    values.push(("Hop Limit", Val::Unsigned(data[7] as u64)));
+
+    let source = &data[8..24];
+    values.push(("Source", Val::ipv6(source)));
+
+    let dest = &data[24..40];
+    values.push(("Destination", Val::ipv6(dest)));
+
+    let complete = 40 + length <= data.len();
+    let mut remainder = &data[40..if complete { 40 + length } else { data.len() }];
+    let mut fragment = false;
+
+    loop {
+        let header_length = match next_header {
+            // Hop-by-hop options, routing and destination options
+            0 | 43 | 60 if remainder.len() >= 2 => (remainder[1] as usize + 1) * 8,
+
+            // Fragment: only the first fragment has an upper-layer header.
+            44 if remainder.len() >= 8 => {
+                let offset = read_be_u16(remainder, 2)? >> 3;
+                fragment = true;
+                if offset != 0 {
+                    values.push(("Extension Header", Val::Unsigned(next_header as u64)));
+                    values.push(("Payload", Val::Undissected("Fragment", &remainder[8..])));
+                    return Ok(Box::new(Val::Object("IPv6", values)));
+                }
+                8
+            },
+
+            // Authentication header
+            51 if remainder.len() >= 2 => (remainder[1] as usize + 2) * 4,
+
+            _ => break,
+        };
+
+        if header_length > remainder.len() {
+            return Err(DissectError::Underflow { expected: Some(header_length), have: remainder.len(),
+                message: "IPv6 extension header greater than available data".to_string() });
+        }
+
+        values.push(("Extension Header", Val::Unsigned(next_header as u64)));
+        next_header = remainder[0];
+        remainder = &remainder[header_length..];
+    }
+
+    match next_header {
+        6 | 17 | 33 | 136 => values.push(("Payload", Val::Payload(remainder, transport(next_header, source, dest, remainder,
+                                                                 complete && !fragment, context)))),
+        59 => {},
+        _ => values.push(("Payload", payload(Table::Ipv6NextHeader, next_header, remainder,
+                                             context))),
+    }
+
+    Ok(Box::new(Val::Object("IPv6", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use ip::dissect_raw;
+
+    #[test]
+    fn dissect_ipv6() {
+        let mut data = vec![0x60, 0x00, 0x00, 0x01, 0x00, 0x14, 0x00, 0x40];
+        data.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        data.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+        // Hop-by-hop options (PadN), then UDP
+        data.extend_from_slice(&[17, 0, 1, 4, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0xd4, 0x31, 0x00, 0x35, 0x00, 0x0c, 0x12, 0x34, 0xde, 0xad, 0xbe, 0xef]);
+
+        let val = *dissect_raw(&data).unwrap();
+        assert_eq!(val["Flow Label"].as_unsigned().unwrap(), 1);
+        assert_eq!(val["Hop Limit"].as_unsigned().unwrap(), 64);
+        assert_eq!(val["Source"].as_address_encoded().unwrap(), "2001:db8::1");
+        assert_eq!(val["Extension Header"].as_unsigned().unwrap(), 0);
+        assert_eq!(val["Payload"]["Destination Port"].as_unsigned().unwrap(), 53);
+        assert_eq!(val["Payload"]["Checksum Status"].as_symbol().unwrap(), "Bad");
+
+        assert!(dissect_raw(&[0x45, 0x00]).is_err());
+        assert!(dissect_raw(&[0x50]).is_err());
+    }
+}
@@ -15,14 +15,30 @@
 //!
 //! See [RFC 791](https://tools.ietf.org/html/rfc791).
 
-use Endianness;
 use DissectError;
 use DissectResult;
+use ErrorCode;
 use Val;
 use NamedValues;
-use unsigned;
+use checksum;
+use conformance;
+use context::Context;
+use decode_as;
+use expert::{self, Severity};
+use flow::{self, FlowKey};
+use iana;
+use read_be_u16;
+use registry::{self, Table};
+
+static FLAGS: [Option<&'static str>; 3] = [Some("More Fragments"), Some("Don't Fragment"),
+                                           Some("Reserved")];
 
 pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect an IPv4 packet, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
     if data.len() < 20 {
         return Err(DissectError::Underflow { expected: Some(20), have: data.len(),
             message: "An IP packet must be at least 20 B".to_string() })
@@ -33,12 +49,19 @@ pub fn dissect(data : &[u8]) -> DissectResult {
     // IP version (should be "4")
     let version = data[0] >> 4;
     values.push(("Version", Val::Unsigned(version as u64)));
+    if version != 4 {
+        conformance::warn(&mut values, format!["IP version {} in an IPv4 header", version]);
+    }
 
     // Internet Header Length (IHL): number of 32b words in header
     let ihl = data[0] & 0x0f;
     values.push(("IHL", Val::Unsigned(ihl as u64)));
 
-    let header_lenght = ihl as usize * 4;
+    let mut header_lenght = ihl as usize * 4;
+    if ihl < 5 {
+        conformance::warn(&mut values, format!["IPv4 IHL {} is less than the minimum of 5", ihl]);
+        header_lenght = 20;
+    }
     if header_lenght > data.len() {
         return Err(DissectError::Underflow { expected: Some(header_lenght), have: data.len(),
             message: "IP packet IHL (header length) greater than available data".to_string() });
@@ -53,49 +76,178 @@ pub fn dissect(data : &[u8]) -> DissectResult {
     values.push(("ECN", Val::Unsigned(ecn as u64)));
 
     // Total length (including header)
-    let length = unsigned(&data[2..4], Endianness::BigEndian);
-    values.push(("Length", Val::Unsigned(length.unwrap())));
+    let length = read_be_u16(data, 2)?;
+    values.push(("Length", Val::Unsigned(length as u64)));
 
     // Identification (of datagraph fragments): RFC 6864
-    values.push(("Identification", Val::Unsigned(data[8] as u64)));
+    values.push(("Identification", Val::Unsigned(read_be_u16(data, 4)? as u64)));
+
+    // Flags: the top three bits of the fragment offset field
+    values.push(("Flags", Val::BitFlags((data[6] >> 5) as u64, &FLAGS)));
+
+    // Time to live: the number of hops the packet may still take
+    values.push(("TTL", Val::Unsigned(data[8] as u64)));
+    if data[8] == 0 {
+        expert::add(&mut values, "TTL", "ip.ttl_zero", Severity::Warn,
+                    "a TTL of 0 should never be sent or forwarded");
+    }
 
     // Protocol number (assigned by IANA)
     let protocol = data[9];
-    values.push(("Protocol", Val::Unsigned(protocol as u64)));
+    values.push(("Protocol", iana::PROTOCOLS.enum_val(protocol)));
 
     // Header checksum
     values.push(("Checksum", Val::Bytes(&data[10..12])));
+    if check_checksum(context) {
+        let status = checksum::verify_ipv4_header(&data[..header_lenght]);
+        values.push(("Checksum Status", Val::Symbol(status.name())));
+        checksum_info(&mut values, status);
+    }
 
     // Source and destination addresses
     let source = &data[12..16];
-    values.push(("Source", Val::Address {
-        bytes: source,
-        encoded: source.iter().map(|b| b.to_string()).collect::<Vec<_>>().join("."),
-    }));
+    values.push(("Source", Val::ipv4(source)));
 
     let dest = &data[16..20];
-    values.push(("Destination", Val::Address {
-        bytes: dest,
-        encoded: dest.iter().map(|b| b.to_string()).collect::<Vec<_>>().join("."),
-    }));
+    values.push(("Destination", Val::ipv4(dest)));
 
     if header_lenght > 20 {
         let options = &data[20..header_lenght];
         values.push(("Options", Val::Bytes(options)));
     }
 
-    // Parse the remainder according to the specified protocol.
-    let remainder = &data[header_lenght..];
+    // Parse the remainder (without any link-layer padding) according to the
+    // specified protocol. TCP and UDP checksums can only be verified when the
+    // whole segment is present.
+    let length = length as usize;
+    let fragment = data[6] & 0x3f != 0 || data[7] != 0;
+    let complete = !fragment && length >= header_lenght && length <= data.len();
+    let end = if length >= header_lenght && length <= data.len() { length } else { data.len() };
+
+    let remainder = &data[header_lenght..end];
+
+    // Only the first fragment starts with the next protocol's header.
+    let fragment_offset = ((data[6] & 0x1f) as usize) << 8 | data[7] as usize;
+    if fragment_offset != 0 {
+        values.push(("Fragment Offset", Val::Unsigned(fragment_offset as u64 * 8)));
+        values.push(("Payload", Val::Undissected("IPv4 fragment", remainder)));
+        return Ok(Box::new(Val::Object("IPv4", values)));
+    }
+
     match protocol {
-        6 => values.push(("Payload", Val::Payload(tcp::dissect(remainder)))),
-        // TODO: UDP, TCP, etc.
-        _ => values.push(("Payload", Val::Undissected("Unknown", remainder)))
+        6 | 17 | 33 | 136 => values.push(("Payload", Val::Payload(remainder, transport(protocol, source, dest, remainder,
+                                                                 complete, context)))),
+        _ => values.push(("Payload", payload(Table::IpProtocol, protocol, remainder, context))),
     };
 
     Ok(Box::new(Val::Object("IPv4", values)))
 }
 
-mod tcp;
+/// Dissect a raw IP packet of either version, e.g., from a tunnel interface
+/// (which has no link-layer header).
+pub fn dissect_raw(data : &[u8]) -> DissectResult {
+    dissect_raw_with(data, &mut Context::new())
+}
+
+/// Dissect a raw IP packet of either version, and its payload within a
+/// context.
+pub fn dissect_raw_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    match data.first().map(|b| b >> 4) {
+        Some(4) => context.dissect(dissect_with, data),
+        Some(6) => context.dissect(ipv6::dissect_with, data),
+        Some(version) => Err(DissectError::malformed(ErrorCode::UnsupportedVersion,
+            format!["IP version {} is neither IPv4 nor IPv6", version])),
+        None => Err(DissectError::Underflow { expected: Some(1), have: 0,
+            message: "A raw IP packet must not be empty".to_string() }),
+    }
+}
+
+/// Dissect the payload of an IP packet (other than a transport segment) by
+/// its protocol number, as bound in the `registry` table for IPv4 or IPv6.
+fn payload<'data>(table: Table, protocol: u8, data: &'data [u8], context: &mut Context)
+    -> Val<'data> {
+
+    match registry::lookup(table, protocol as u64) {
+        Some(dissector) => registry::payload(data, dissector.dissect(data, context)),
+        None => Val::Undissected("Unknown", data),
+    }
+}
+
+/// Dissect a TCP, UDP, DCCP or UDP-Lite segment. Their checksums cover a
+/// pseudo-header taken from the IP header, so they can only be verified at
+/// this layer.
+fn transport<'data>(protocol: u8, source: &[u8], destination: &[u8], segment: &'data [u8],
+                    complete: bool, context: &mut Context) -> DissectResult<'data> {
+
+    // A Decode-As rule for the conversation overrides the port-based choice.
+    let rule = flow_key(protocol, source, destination, segment)
+                   .and_then(|key| decode_as::lookup_flow(&key));
+
+    let mut payload = match protocol {
+        6 => context.dissect(|data, context| tcp::dissect_as(data, rule, context), segment),
+        33 => context.dissect_stateless(dccp::dissect, segment),
+        136 => context.dissect(|data, context| udp::dissect_lite_as(data, rule, context), segment),
+        _ => context.dissect(|data, context| udp::dissect_as(data, rule, context), segment),
+    };
+
+    if complete && check_checksum(context) {
+        let status = match protocol {
+            33 => checksum::verify_partial(protocol, source, destination, segment,
+                                           dccp::checksum_coverage(segment)),
+            136 => checksum::verify_partial(protocol, source, destination, segment,
+                                            udp::lite_checksum_coverage(segment)),
+            _ => checksum::verify_transport(protocol, source, destination, segment),
+        };
+
+        if let Ok(ref mut transport) = payload {
+            if let Val::Object(_, ref mut values) = **transport {
+                values.push(("Checksum Status", Val::Symbol(status.name())));
+                checksum_info(values, status);
+            }
+        }
+    }
+
+    payload
+}
+
+/// The conversation that a transport-layer segment belongs to.
+fn flow_key(protocol: u8, source: &[u8], destination: &[u8], segment: &[u8]) -> Option<FlowKey> {
+    Some(FlowKey {
+        protocol: protocol,
+        source: flow::ip_addr(source)?,
+        source_port: read_be_u16(segment, 0).ok()?,
+        destination: flow::ip_addr(destination)?,
+        destination_port: read_be_u16(segment, 2).ok()?,
+    })
+}
+
+/// Whether checksums are to be verified (the `ip.check_checksum` preference).
+fn check_checksum(context: &Context) -> bool {
+    context.preferences().bool("ip.check_checksum").unwrap_or(true)
+}
+
+/// Note a checksum that doesn't match, or that was probably left for the
+/// NIC to fill in.
+fn checksum_info(values: &mut NamedValues, status: checksum::Status) {
+    match status {
+        checksum::Status::Bad => expert::add(values, "Checksum", "checksum.bad", Severity::Error,
+                                             "checksum doesn't match"),
+        checksum::Status::Offloaded => expert::add(values, "Checksum", "checksum.offloaded",
+                                                   Severity::Chat,
+                                                   "checksum likely offloaded to the NIC"),
+        _ => {},
+    }
+}
+
+pub mod dccp;
+pub mod gre;
+pub mod icmp;
+pub mod igmp;
+pub mod ipsec;
+pub mod ipv6;
+pub mod sctp;
+pub mod tcp;
+mod udp;
 
 #[cfg(test)]
 mod test {
@@ -114,11 +266,95 @@ mod test {
         assert_eq!(val["DSCP"].as_unsigned().unwrap(), 0);
         assert_eq!(val["ECN"].as_unsigned().unwrap(), 0);
         assert_eq!(val["Length"].as_unsigned().unwrap(), 60);
-        assert_eq!(val["Identification"].as_unsigned().unwrap(), 46);
+        assert_eq!(val["Flags"].as_bitflags_bit_name("Don't Fragment"), Some(true));
+        assert_eq!(val["Flags"].to_string(), "010 (Don't Fragment)");
+        assert_eq!(val["Identification"].as_unsigned().unwrap(), 0);
+        assert_eq!(val["TTL"].as_unsigned().unwrap(), 46);
         assert_eq!(val["Protocol"].as_unsigned().unwrap(), 6);
+        assert_eq!(val["Protocol"].to_string(), "TCP (6)");
         assert_eq!(val["Checksum"].as_bytes().unwrap(), &[0xa1u8, 0x24]);
         assert_eq!(val["Source"].as_address_encoded().unwrap(), "46.137.186.243");
         assert_eq!(val["Destination"].as_address_encoded().unwrap(), "192.168.1.115");
         assert!(val["Payload"].is_payload());
     }
+
+    #[test]
+    fn dissect_ip_checksums() {
+        let data = [0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x66, 0xca,
+                    10, 0, 0, 1, 10, 0, 0, 2,
+                    0xd4, 0x31, 0x00, 0x35, 0x00, 0x0c, 0x79, 0xcf,
+                    0xde, 0xad, 0xbe, 0xef];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Checksum Status"].as_symbol().unwrap(), "Good");
+        assert_eq!(val["Payload"]["Checksum Status"].as_symbol().unwrap(), "Good");
+
+        let mut offloaded = data;
+        offloaded[10] = 0;
+        offloaded[11] = 0;
+        offloaded[26] = 0x14;
+        offloaded[27] = 0x20;
+
+        let val = *dissect(&offloaded).unwrap();
+        assert_eq!(val["Checksum Status"].as_symbol().unwrap(), "Likely offloaded");
+        assert_eq!(val["Payload"]["Checksum Status"].as_symbol().unwrap(), "Likely offloaded");
+
+        let mut context = Context::new();
+        context.preferences_mut().set_str("ip.check_checksum", "false").unwrap();
+        let val = *dissect_with(&offloaded, &mut context).unwrap();
+        assert!(val.get("Checksum Status").is_err());
+        assert!(val["Payload"].get("Checksum Status").is_err());
+    }
+
+    #[test]
+    fn dissect_versions() {
+        // An IPv4 header that claims to be version 5 is still dissected...
+        let data = [0x55, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Version"].as_unsigned().unwrap(), 5);
+        assert_eq!(conformance::warnings(&val).len(), 1);
+
+        // ...but a raw packet of neither version can't be dissected at all.
+        assert_eq!(dissect_raw(&data).unwrap_err().code(), ErrorCode::UnsupportedVersion);
+    }
+
+    #[test]
+    fn dissect_fragments() {
+        // The first fragment of a UDP datagram (More Fragments set)
+        let mut data = [0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x20, 0x00, 0x40, 0x11, 0, 0,
+                        10, 0, 0, 1, 10, 0, 0, 2,
+                        0xd4, 0x31, 0x00, 0x35, 0x00, 0x14, 0, 0,
+                        0xde, 0xad, 0xbe, 0xef];
+        let val = *dissect(&data).unwrap();
+        assert!(val.get("Fragment Offset").is_err());
+        assert_eq!(val["Payload"]["Destination Port"].as_unsigned().unwrap(), 53);
+
+        // A later fragment, whose payload continues the datagram's
+        data[7] = 1;
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Fragment Offset"].as_unsigned().unwrap(), 8);
+        assert_eq!(val["Payload"], Val::Undissected("IPv4 fragment", &data[20..]));
+    }
+
+    #[test]
+    fn dissect_partial_checksums() {
+        // UDP-Lite covering only its header, so damaged data is still "Good"
+        let mut data = [0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00, 0x00, 0x40, 136, 0, 0,
+                        10, 0, 0, 1, 10, 0, 0, 2,
+                        0xd4, 0x31, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00,
+                        0xde, 0xad, 0xbe, 0xef];
+        let sum = checksum::sum(&data[12..20]) + 136 + 12 + checksum::sum(&data[20..28]);
+        let udp_lite = !checksum::fold(sum);
+        data[26] = (udp_lite >> 8) as u8;
+        data[27] = udp_lite as u8;
+
+        data[31] = 0;
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Payload"]["Checksum Coverage"].as_unsigned().unwrap(), 8);
+        assert_eq!(val["Payload"]["Checksum Status"].as_symbol().unwrap(), "Good");
+
+        data[21] = 0x36;
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Payload"]["Checksum Status"].as_symbol().unwrap(), "Bad");
+    }
 }
@@ -0,0 +1,774 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Stream Control Transmission Protocol (SCTP) packets, and
+//! reassembly of the user messages carried in their DATA chunks.
+//!
+//! A user message (e.g., a Diameter or S1AP PDU) may be fragmented across
+//! several DATA chunks with consecutive transmission sequence numbers (TSNs),
+//! flagged as the beginning and end of the message. A `Reassembler` tap
+//! collects fragments, reorders them by TSN, drops retransmissions and
+//! delivers complete messages per stream, in stream sequence number order for
+//! ordered messages.
+//!
+//! See [RFC 4960](https://tools.ietf.org/html/rfc4960).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use DissectError;
+use DissectResult;
+use Val;
+use NamedValues;
+use budget::{self, MemoryBudget};
+use expert::Info;
+use flow::FlowKey;
+use tap::{PacketInfo, Tap};
+use {read_be_u16, read_be_u32};
+
+const DATA: u8 = 0;
+const SACK: u8 = 3;
+
+/// DATA chunk flags
+const END: u8 = 0x01;
+const BEGINNING: u8 = 0x02;
+const UNORDERED: u8 = 0x04;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 12 {
+        return Err(DissectError::Underflow { expected: Some(12), have: data.len(),
+            message: "An SCTP packet must be at least 12 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    values.push(("Source Port", Val::Unsigned(read_be_u16(data, 0)? as u64)));
+    values.push(("Destination Port", Val::Unsigned(read_be_u16(data, 2)? as u64)));
+    values.push(("Verification Tag", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+    values.push(("Checksum", Val::Bytes(&data[8..12])));
+
+    let mut chunks = &data[12..];
+    while chunks.len() >= 4 {
+        let length = read_be_u16(chunks, 2)? as usize;
+        if length < 4 || length > chunks.len() {
+            return Err(DissectError::Underflow { expected: Some(length), have: chunks.len(),
+                message: format!["SCTP chunk length {} is invalid", length] });
+        }
+
+        values.push(("Chunk", chunk(&chunks[..length])?));
+
+        // Chunks are padded to a multiple of four bytes.
+        let padded = (length + 3) / 4 * 4;
+        chunks = &chunks[::std::cmp::min(padded, chunks.len())..];
+    }
+
+    Ok(Box::new(Val::Object("SCTP", values)))
+}
+
+fn chunk(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    let chunk_type = data[0];
+    let flags = data[1];
+    values.push(("Type", Val::Unsigned(chunk_type as u64)));
+    values.push(("Type Name", Val::Symbol(match chunk_type {
+        DATA => "DATA",
+        1 => "INIT",
+        2 => "INIT ACK",
+        SACK => "SACK",
+        4 => "HEARTBEAT",
+        5 => "HEARTBEAT ACK",
+        6 => "ABORT",
+        7 => "SHUTDOWN",
+        8 => "SHUTDOWN ACK",
+        9 => "ERROR",
+        10 => "COOKIE ECHO",
+        11 => "COOKIE ACK",
+        14 => "SHUTDOWN COMPLETE",
+        _ => "Unknown",
+    })));
+    values.push(("Flags", Val::Unsigned(flags as u64)));
+    values.push(("Length", Val::Unsigned(data.len() as u64)));
+
+    match chunk_type {
+        DATA if data.len() >= 16 => {
+            values.push(("TSN", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+            values.push(("Stream Identifier", Val::Unsigned(read_be_u16(data, 8)? as u64)));
+            values.push(("Stream Sequence Number", Val::Unsigned(read_be_u16(data, 10)? as u64)));
+
+            let ppid = read_be_u32(data, 12)? as u64;
+            values.push(("Payload Protocol Identifier", Val::Unsigned(ppid)));
+            if let Some(name) = protocol_name(ppid) {
+                values.push(("Payload Protocol", Val::Symbol(name)));
+            }
+
+            values.push(("Beginning", Val::Unsigned((flags & BEGINNING != 0) as u64)));
+            values.push(("End", Val::Unsigned((flags & END != 0) as u64)));
+            values.push(("Unordered", Val::Unsigned((flags & UNORDERED != 0) as u64)));
+            values.push(("User Data", Val::Bytes(&data[16..])));
+        },
+
+        1 | 2 if data.len() >= 20 => {
+            values.push(("Initiate Tag", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+            let window = read_be_u32(data, 8)? as u64;
+            values.push(("Advertised Receiver Window", Val::Unsigned(window)));
+            values.push(("Outbound Streams", Val::Unsigned(read_be_u16(data, 12)? as u64)));
+            values.push(("Inbound Streams", Val::Unsigned(read_be_u16(data, 14)? as u64)));
+            values.push(("Initial TSN", Val::Unsigned(read_be_u32(data, 16)? as u64)));
+        },
+
+        SACK if data.len() >= 16 => {
+            values.push(("Cumulative TSN Ack", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+            let window = read_be_u32(data, 8)? as u64;
+            values.push(("Advertised Receiver Window", Val::Unsigned(window)));
+            values.push(("Gap Ack Blocks", Val::Unsigned(read_be_u16(data, 12)? as u64)));
+            values.push(("Duplicate TSNs", Val::Unsigned(read_be_u16(data, 14)? as u64)));
+        },
+
+        _ => if data.len() > 4 {
+            values.push(("Value", Val::Bytes(&data[4..])));
+        },
+    }
+
+    Ok(Val::Object("Chunk", values))
+}
+
+/// The name of a payload protocol identifier (assigned by IANA).
+pub fn protocol_name(ppid: u64) -> Option<&'static str> {
+    Some(match ppid {
+        3 => "M3UA",
+        18 => "S1AP",
+        19 => "RUA",
+        27 => "X2AP",
+        46 => "Diameter",
+        47 => "Diameter (DTLS)",
+        60 => "NGAP",
+        61 => "XnAP",
+        _ => return None,
+    })
+}
+
+/// A reassembled user message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub stream: u16,
+
+    /// Stream sequence number (meaningless for unordered messages).
+    pub sequence: u16,
+    pub unordered: bool,
+    pub ppid: u32,
+    pub data: Vec<u8>,
+
+    /// Packet number of the final fragment.
+    pub number: u64,
+}
+
+/// A fragment waiting for the rest of its message.
+struct Fragment {
+    flags: u8,
+    stream: u16,
+    sequence: u16,
+    ppid: u32,
+    data: Vec<u8>,
+}
+
+/// How many fragments may wait for the rest of their messages, in each
+/// direction of an association.
+const MAX_FRAGMENTS: usize = 4096;
+
+/// State for one direction of an association.
+///
+/// TSNs start at random and wrap around, so they are kept extended to 64 bits
+/// (see `extend`), which keep increasing and can be compared as integers.
+struct Direction {
+    /// The highest (extended) TSN seen so far.
+    highest: Option<u64>,
+
+    /// Fragments, whose data is charged to a budget.
+    fragments: BTreeMap<u64, Fragment>,
+    budget: MemoryBudget,
+
+    /// Runs of consecutive TSNs in `fragments` (first to last), and the
+    /// TSNs of the fragments that begin and end messages.
+    runs: BTreeMap<u64, u64>,
+    beginnings: BTreeSet<u64>,
+    ends: BTreeSet<u64>,
+
+    /// The receiver's cumulative TSN ack: every TSN up to it was received.
+    acked: Option<u64>,
+
+    /// TSNs delivered since then, to recognise retransmissions.
+    delivered: BTreeSet<u64>,
+
+    /// Per stream: the next expected sequence number and ordered messages
+    /// (with the TSNs they began at) that arrived before it.
+    next_sequence: HashMap<u16, u16>,
+    held: HashMap<u16, BTreeMap<u16, (u64, Message)>>,
+}
+
+/// A tap that reassembles SCTP user messages.
+///
+/// SACKs are followed to forget what the receiver has acknowledged: the
+/// TSNs of delivered messages, fragments of messages that will never be
+/// complete in the capture and ordered messages held back for a message
+/// that was never seen. At most `MAX_FRAGMENTS` fragments are buffered in
+/// each direction; beyond that, or when the memory budget is exceeded, the
+/// oldest partial messages are dropped.
+pub struct Reassembler {
+    directions: HashMap<FlowKey, Direction>,
+    messages: HashMap<(FlowKey, u16), Vec<Message>>,
+    budget: MemoryBudget,
+    retransmissions: u64,
+    dropped: u64,
+    notes: Vec<Info>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::with_budget(MemoryBudget::unlimited())
+    }
+
+    /// A reassembler whose buffered fragments are limited by a budget.
+    pub fn with_budget(budget: MemoryBudget) -> Reassembler {
+        Reassembler {
+            directions: HashMap::new(),
+            messages: HashMap::new(),
+            budget: budget,
+            retransmissions: 0,
+            dropped: 0,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Messages delivered on a stream (in one direction of an association).
+    pub fn stream(&self, key: &FlowKey, stream: u16) -> &[Message] {
+        self.messages.get(&(*key, stream)).map(|m| &m[..]).unwrap_or(&[])
+    }
+
+    /// The streams that have delivered messages.
+    pub fn streams(&self) -> Vec<(FlowKey, u16)> {
+        self.messages.keys().cloned().collect()
+    }
+
+    /// Remove and return all delivered messages, in no particular order of
+    /// stream.
+    pub fn take_messages(&mut self) -> Vec<(FlowKey, Message)> {
+        self.messages.drain()
+            .flat_map(|((key, _), messages)| messages.into_iter().map(move |m| (key, m)))
+            .collect()
+    }
+
+    /// DATA chunks that had already been received.
+    pub fn retransmissions(&self) -> u64 {
+        self.retransmissions
+    }
+
+    /// Fragments discarded without completing a message: because the rest
+    /// of it was acknowledged without being seen, or to stay within limits.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Expert notes about the partial messages dropped to stay within
+    /// limits, oldest first.
+    pub fn notes(&self) -> &[Info] {
+        &self.notes
+    }
+
+    /// Take the expert notes about the partial messages dropped so far.
+    pub fn take_notes(&mut self) -> Vec<Info> {
+        self.notes.split_off(0)
+    }
+
+    /// Account for a DATA chunk.
+    pub fn data_chunk(&mut self, key: FlowKey, number: u64, tsn: u32, flags: u8, stream: u16,
+                      sequence: u16, ppid: u32, data: &[u8]) {
+
+        let tsn = {
+            let budget = &self.budget;
+            let direction = self.directions.entry(key)
+                .or_insert_with(|| Direction::new(budget.clone()));
+            let tsn = direction.extend(tsn);
+            if direction.acked.is_some_and(|acked| tsn <= acked)
+                || direction.delivered.contains(&tsn) || direction.fragments.contains_key(&tsn) {
+                self.retransmissions += 1;
+                return;
+            }
+
+            direction.insert(tsn, Fragment {
+                flags: flags, stream: stream, sequence: sequence, ppid: ppid, data: data.to_vec(),
+            });
+
+            while direction.fragments.len() > MAX_FRAGMENTS {
+                let dropped = direction.drop_oldest();
+                self.dropped += dropped as u64;
+                self.notes.push(note(&key, dropped, "fragment limit"));
+            }
+
+            tsn
+        };
+
+        while self.budget.is_exceeded() {
+            // Drop partial messages from whichever direction has the most.
+            let fullest = self.directions.iter_mut()
+                .filter(|&(_, ref d)| !d.fragments.is_empty())
+                .max_by_key(|&(_, ref d)| d.fragments.len());
+            match fullest {
+                Some((key, direction)) => {
+                    let dropped = direction.drop_oldest();
+                    self.dropped += dropped as u64;
+                    self.notes.push(note(key, dropped, "memory budget"));
+                },
+                None => break,
+            }
+        }
+
+        let direction = self.directions.get_mut(&key).unwrap();
+        let message = match direction.assemble(tsn, number) {
+            Some(m) => m,
+            None => return,
+        };
+
+        let delivered = self.messages.entry((key, message.stream)).or_insert(Vec::new());
+        if message.unordered {
+            delivered.push(message);
+            return;
+        }
+
+        let stream = message.stream;
+        direction.held.entry(stream).or_insert(BTreeMap::new())
+            .insert(message.sequence, (tsn, message));
+        direction.release(stream, delivered);
+    }
+
+    /// Account for a SACK acknowledging every TSN up to `ack`, sent by the
+    /// receiver of the direction `key`.
+    pub fn acknowledge(&mut self, key: FlowKey, ack: u32) {
+        let direction = match self.directions.get_mut(&key) {
+            Some(direction) => direction,
+            None => return,
+        };
+
+        let ack = direction.extend(ack);
+        if direction.acked.is_some_and(|acked| ack <= acked) {
+            return;
+        }
+        direction.acked = Some(ack);
+        direction.delivered = direction.delivered.split_off(&(ack + 1));
+
+        // Missing TSNs up to `ack` were received without the capture seeing
+        // them, so they will never arrive. A run that starts with the rest of
+        // a message begun before it can't complete, and neither can the last
+        // message of a run that ends before `ack` (the messages before it
+        // would have been complete). A run that reaches `ack` may still be
+        // continued by the TSNs after it.
+        let runs = direction.runs.range(..=ack).map(|(&f, &l)| (f, l)).collect::<Vec<_>>();
+        for (first, last) in runs {
+            let dead = if last < ack {
+                last
+            } else {
+                match direction.beginnings.range(first..=last).next() {
+                    Some(&beginning) if beginning == first => continue,
+                    Some(&beginning) => beginning - 1,
+                    None => last,
+                }
+            };
+
+            for tsn in first..=dead {
+                direction.remove(tsn);
+                self.dropped += 1;
+            }
+
+            direction.runs.remove(&first);
+            if dead < last {
+                direction.runs.insert(dead + 1, last);
+            }
+        }
+
+        // Likewise, ordered messages can't be waiting for anything sent
+        // before `ack`.
+        let streams = direction.held.keys().cloned().collect::<Vec<_>>();
+        for stream in streams {
+            loop {
+                let sequence = match direction.held[&stream].values().min_by_key(|m| m.0) {
+                    Some(&(tsn, ref message)) if tsn <= ack => message.sequence,
+                    _ => break,
+                };
+
+                direction.next_sequence.insert(stream, sequence);
+                let delivered = self.messages.entry((key, stream)).or_insert(Vec::new());
+                direction.release(stream, delivered);
+
+                if !direction.held.contains_key(&stream) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Direction {
+    fn new(budget: MemoryBudget) -> Direction {
+        Direction {
+            highest: None,
+            fragments: BTreeMap::new(),
+            budget: budget,
+            runs: BTreeMap::new(),
+            beginnings: BTreeSet::new(),
+            ends: BTreeSet::new(),
+            acked: None,
+            delivered: BTreeSet::new(),
+            next_sequence: HashMap::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// A TSN extended to 64 bits: the one nearest the highest TSN seen so
+    /// far, as serial numbers are compared (RFC 1982).
+    fn extend(&mut self, tsn: u32) -> u64 {
+        // Start well clear of zero, for TSNs sent before the first one seen.
+        let highest = *self.highest.get_or_insert(tsn as u64 | 1 << 32);
+        let extended = (highest as i64 + tsn.wrapping_sub(highest as u32) as i32 as i64) as u64;
+        if extended > highest {
+            self.highest = Some(extended);
+        }
+        extended
+    }
+
+    /// Buffer a fragment, joining it to the runs before and after it.
+    fn insert(&mut self, tsn: u64, fragment: Fragment) {
+        let mut first = tsn;
+        let mut last = tsn;
+
+        if let Some((&start, &end)) = self.runs.range(..tsn).next_back() {
+            if end + 1 == tsn {
+                first = start;
+            }
+        }
+        if let Some(end) = self.runs.remove(&(tsn + 1)) {
+            last = end;
+        }
+        self.runs.insert(first, last);
+
+        if fragment.flags & BEGINNING != 0 {
+            self.beginnings.insert(tsn);
+        }
+        if fragment.flags & END != 0 {
+            self.ends.insert(tsn);
+        }
+        self.budget.charge(fragment.data.len());
+        self.fragments.insert(tsn, fragment);
+    }
+
+    /// Remove a fragment (but not its run).
+    fn remove(&mut self, tsn: u64) -> Option<Fragment> {
+        self.beginnings.remove(&tsn);
+        self.ends.remove(&tsn);
+
+        let fragment = self.fragments.remove(&tsn);
+        if let Some(ref f) = fragment {
+            self.budget.release(f.data.len());
+        }
+        fragment
+    }
+
+    /// Drop the run of fragments with the lowest TSNs, returning how many
+    /// fragments it held.
+    fn drop_oldest(&mut self) -> usize {
+        let (first, last) = match self.runs.iter().next() {
+            Some((&first, &last)) => (first, last),
+            None => return 0,
+        };
+
+        self.runs.remove(&first);
+        for tsn in first..=last {
+            self.remove(tsn);
+        }
+        (last - first) as usize + 1
+    }
+
+    /// Try to complete the message containing the fragment with TSN `tsn`.
+    fn assemble(&mut self, tsn: u64, number: u64) -> Option<Message> {
+        if !self.fragments.contains_key(&tsn) {
+            return None;
+        }
+
+        // The message must begin and end within the run of consecutive
+        // fragments containing `tsn`.
+        let (start, end) = self.runs.range(..=tsn).next_back().map(|(&s, &e)| (s, e))?;
+        let first = *self.beginnings.range(start..=tsn).next_back()?;
+        let last = *self.ends.range(tsn..=end).next()?;
+
+        let mut message = {
+            let head = &self.fragments[&first];
+            Message {
+                stream: head.stream,
+                sequence: head.sequence,
+                unordered: head.flags & UNORDERED != 0,
+                ppid: head.ppid,
+                data: Vec::new(),
+                number: number,
+            }
+        };
+
+        for t in first..=last {
+            let fragment = self.remove(t).unwrap();
+            message.data.extend(fragment.data);
+            self.delivered.insert(t);
+        }
+
+        self.runs.remove(&start);
+        if start < first {
+            self.runs.insert(start, first - 1);
+        }
+        if last < end {
+            self.runs.insert(last + 1, end);
+        }
+
+        Some(message)
+    }
+
+    /// Deliver the held messages of a stream that are next in order.
+    fn release(&mut self, stream: u16, delivered: &mut Vec<Message>) {
+        let next = self.next_sequence.entry(stream).or_insert(0);
+        let held = match self.held.get_mut(&stream) {
+            Some(held) => held,
+            None => return,
+        };
+
+        while let Some((_, message)) = held.remove(&*next) {
+            delivered.push(message);
+            *next = next.wrapping_add(1);
+        }
+
+        if held.is_empty() {
+            self.held.remove(&stream);
+        }
+    }
+}
+
+impl Drop for Direction {
+    fn drop(&mut self) {
+        self.budget.release(self.fragments.values().map(|f| f.data.len()).sum());
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Reassembler {
+        Reassembler::new()
+    }
+}
+
+impl Tap for Reassembler {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        let key = match FlowKey::from_val(info.packet) {
+            Some(key) => key,
+            None => return,
+        };
+
+        let chunks = match layer.as_object() {
+            Some((_, values)) => values,
+            None => return,
+        };
+
+        for &(_, ref chunk) in chunks.iter().filter(|&&(name, _)| name == "Chunk") {
+            let field = |name| chunk.get(name).ok().and_then(|v| v.as_unsigned());
+            match field("Type").map(|t| t as u8) {
+                Some(DATA) => {},
+                Some(SACK) => {
+                    if let Some(ack) = field("Cumulative TSN Ack") {
+                        self.acknowledge(key.reversed(), ack as u32);
+                    }
+                    continue;
+                },
+                _ => continue,
+            }
+
+            let data = chunk.get("User Data").ok().and_then(|v| v.as_bytes());
+            if let (Some(tsn), Some(flags), Some(stream), Some(sequence), Some(ppid), Some(data)) =
+                (field("TSN"), field("Flags"), field("Stream Identifier"),
+                 field("Stream Sequence Number"), field("Payload Protocol Identifier"), data) {
+
+                self.data_chunk(key, info.number, tsn as u32, flags as u8, stream as u16,
+                                sequence as u16, ppid as u32, data);
+            }
+        }
+    }
+}
+
+/// An expert note about partial messages dropped to stay within a limit.
+fn note(key: &FlowKey, fragments: usize, limit: &str) -> Info {
+    let plural = if fragments == 1 { "" } else { "s" };
+    budget::note("SCTP", format!["{} fragment{} of partial messages on {} dropped to stay \
+                                  within the {}", fragments, plural, key, limit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ip;
+    use tap::Taps;
+
+    fn packet(chunks: &[(u32, u8, u16, &[u8])]) -> Vec<u8> {
+        let mut sctp = vec![0x0b, 0x59, 0x0b, 0x59, 0, 0, 0, 1, 0, 0, 0, 0];
+        for &(tsn, flags, sequence, data) in chunks {
+            let length = 16 + data.len();
+            sctp.extend_from_slice(&[DATA, flags, (length >> 8) as u8, length as u8]);
+            sctp.extend_from_slice(&[(tsn >> 24) as u8, (tsn >> 16) as u8, (tsn >> 8) as u8, tsn as u8]);
+            sctp.extend_from_slice(&[0, 1, (sequence >> 8) as u8, sequence as u8, 0, 0, 0, 46]);
+            sctp.extend_from_slice(data);
+            while sctp.len() % 4 != 0 {
+                sctp.push(0);
+            }
+        }
+
+        let length = 20 + sctp.len();
+        let mut ip = vec![0x45, 0, (length >> 8) as u8, length as u8, 0, 0, 0, 0, 64, 132, 0, 0,
+                          10, 0, 0, 1, 10, 0, 0, 2];
+        ip.extend(sctp);
+        ip
+    }
+
+    #[test]
+    fn dissect_sctp() {
+        let data = packet(&[(7, BEGINNING | END, 0, b"hello")]);
+        let val = *ip::dissect(&data).unwrap();
+        let chunk = &val["Payload"]["Chunk"];
+
+        assert_eq!(val["Payload"]["Destination Port"].as_unsigned().unwrap(), 2905);
+        assert_eq!(chunk["Type Name"].as_symbol().unwrap(), "DATA");
+        assert_eq!(chunk["TSN"].as_unsigned().unwrap(), 7);
+        assert_eq!(chunk["Payload Protocol"].as_symbol().unwrap(), "Diameter");
+        assert_eq!(chunk["User Data"].as_bytes().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn reassemble_messages() {
+        let packets = vec![
+            // Message 1 (SSN 1) arrives before message 0, whose fragments
+            // are out of order and partly retransmitted.
+            packet(&[(13, BEGINNING | END, 1, b"second")]),
+            packet(&[(11, 0, 0, b"rs"), (12, END, 0, b"t")]),
+            packet(&[(10, BEGINNING, 0, b"fi")]),
+            packet(&[(12, END, 0, b"t")]),
+            packet(&[(14, BEGINNING | END | UNORDERED, 9, b"urgent")]),
+        ];
+
+        let mut reassembler = Reassembler::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("SCTP", &mut reassembler);
+
+            for data in &packets {
+                let val = ip::dissect(data).unwrap();
+                taps.dispatch(Duration::new(0, 0), data.len(), &val);
+            }
+        }
+
+        let streams = reassembler.streams();
+        assert_eq!(streams.len(), 1);
+
+        let messages = reassembler.stream(&streams[0].0, 1);
+        let data = messages.iter().map(|m| &m.data[..]).collect::<Vec<_>>();
+        assert_eq!(data, vec![&b"first"[..], b"second", b"urgent"]);
+        assert_eq!(messages[0].number, 3);
+        assert_eq!(messages[0].ppid, 46);
+        assert_eq!(reassembler.retransmissions(), 1);
+    }
+
+    #[test]
+    fn prune_acknowledged() {
+        let key = FlowKey::from_val(&ip::dissect(&packet(&[])).unwrap()).unwrap();
+        let mut reassembler = Reassembler::new();
+
+        // The capture starts after messages 0 to 4, and a message that was
+        // acknowledged without being seen in full never completes.
+        reassembler.data_chunk(key, 1, 20, BEGINNING | END, 1, 5, 46, b"fifth");
+        reassembler.data_chunk(key, 2, 21, BEGINNING, 1, 6, 46, b"six");
+        assert!(reassembler.stream(&key, 1).is_empty());
+
+        reassembler.acknowledge(key, 22);
+        assert_eq!(reassembler.stream(&key, 1).len(), 1);
+        assert_eq!(reassembler.dropped(), 1);
+
+        reassembler.data_chunk(key, 3, 22, END, 1, 6, 46, b"th");
+        assert_eq!(reassembler.retransmissions(), 1);
+
+        // Partial messages beyond the limit are dropped, oldest first.
+        for tsn in 100..(101 + MAX_FRAGMENTS as u32) {
+            reassembler.data_chunk(key, 4, tsn, 0, 1, 7, 46, b"-");
+        }
+        assert_eq!(reassembler.dropped(), 1 + MAX_FRAGMENTS as u64 + 1);
+        assert_eq!(reassembler.notes()[0].to_string(),
+                   "Warn: SCTP: 4097 fragments of partial messages on 10.0.0.1:2905 -> \
+                    10.0.0.2:2905 dropped to stay within the fragment limit");
+
+        // As are the oldest partial messages when over budget.
+        let budget = MemoryBudget::new(4);
+        let mut reassembler = Reassembler::with_budget(budget.clone());
+        reassembler.data_chunk(key, 1, 1, BEGINNING, 1, 0, 46, b"abc");
+        reassembler.data_chunk(key, 2, 5, BEGINNING, 1, 1, 46, b"de");
+        assert_eq!((reassembler.dropped(), budget.used()), (1, 2));
+        assert_eq!(reassembler.notes()[0].code, "budget_exceeded");
+        assert_eq!(reassembler.notes()[0].to_string(),
+                   "Warn: SCTP: 1 fragment of partial messages on 10.0.0.1:2905 -> \
+                    10.0.0.2:2905 dropped to stay within the memory budget");
+
+        drop(reassembler);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn acknowledge_incomplete() {
+        let key = FlowKey::from_val(&ip::dissect(&packet(&[])).unwrap()).unwrap();
+        let mut reassembler = Reassembler::new();
+
+        // A message continuing past the cumulative ack is still completed.
+        reassembler.data_chunk(key, 1, 20, BEGINNING, 1, 0, 46, b"fi");
+        reassembler.data_chunk(key, 2, 21, 0, 1, 0, 46, b"rs");
+        reassembler.acknowledge(key, 21);
+        reassembler.data_chunk(key, 3, 22, END, 1, 0, 46, b"t");
+
+        let data = reassembler.stream(&key, 1).iter().map(|m| &m.data[..]).collect::<Vec<_>>();
+        assert_eq!(data, vec![&b"first"[..]]);
+        assert_eq!(reassembler.dropped(), 0);
+
+        // But not one whose beginning went unseen.
+        reassembler.data_chunk(key, 4, 24, 0, 2, 0, 46, b"co");
+        reassembler.data_chunk(key, 5, 25, BEGINNING, 1, 1, 46, b"th");
+        reassembler.acknowledge(key, 25);
+        reassembler.data_chunk(key, 6, 26, END, 1, 1, 46, b"ird");
+
+        let data = reassembler.stream(&key, 1).iter().map(|m| &m.data[..]).collect::<Vec<_>>();
+        assert_eq!(data, vec![&b"first"[..], b"third"]);
+        assert!(reassembler.stream(&key, 2).is_empty());
+        assert_eq!(reassembler.dropped(), 1);
+    }
+
+    #[test]
+    fn tsn_wraparound() {
+        let key = FlowKey::from_val(&ip::dissect(&packet(&[])).unwrap()).unwrap();
+        let mut reassembler = Reassembler::new();
+
+        reassembler.data_chunk(key, 1, 0, END, 1, 0, 46, b"p");
+        reassembler.data_chunk(key, 2, 0xfffffffe, BEGINNING, 1, 0, 46, b"wr");
+        reassembler.acknowledge(key, 0xfffffffe);
+        reassembler.data_chunk(key, 3, 0xffffffff, 0, 1, 0, 46, b"a");
+        reassembler.data_chunk(key, 4, 1, BEGINNING | END, 1, 1, 46, b"next");
+
+        let data = reassembler.stream(&key, 1).iter().map(|m| &m.data[..]).collect::<Vec<_>>();
+        assert_eq!(data, vec![&b"wrap"[..], b"next"]);
+        assert_eq!(reassembler.dropped(), 0);
+
+        // TSNs before the cumulative ack, across the wrap, are retransmissions.
+        reassembler.acknowledge(key, 1);
+        reassembler.data_chunk(key, 5, 0xffffffff, 0, 1, 0, 46, b"a");
+        assert_eq!(reassembler.retransmissions(), 1);
+    }
+}
@@ -15,15 +15,39 @@
 //!
 //! See [RFC 791](https://tools.ietf.org/html/rfc791).
 
-use Endianness;
 use DissectError;
 use DissectResult;
 use Val;
 use NamedValues;
+use bittorrent;
+use conformance;
+use context::Context;
+use heuristic;
+use http2;
+use iana;
 use raw;
-use unsigned;
+use registry::{self, Handler, Table};
+use zmtp;
+use {read_be_u16, read_be_u32};
+
+static FLAGS: [Option<&'static str>; 9] = [
+    Some("FIN"), Some("SYN"), Some("RST"), Some("PSH"),
+    Some("ACK"), Some("URG"), Some("ECE"), Some("CWR"), Some("NS")];
 
 pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a TCP segment, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    dissect_as(data, None, context)
+}
+
+/// Dissect a TCP segment within a context, and its payload with the dissector
+/// named by its conversation's Decode-As rule (if any).
+pub fn dissect_as<'data>(data: &'data [u8], rule: Option<Handler>, context: &mut Context)
+    -> DissectResult<'data> {
+
     if data.len() < 20 {
         return Err(DissectError::Underflow { expected: Some(20), have: data.len(),
             message: "An TCP packet must be at least 20 B".to_string() })
@@ -31,42 +55,46 @@ pub fn dissect(data : &[u8]) -> DissectResult {
 
     let mut values = NamedValues::new();
 
-    let source_port = unsigned(&data[0..2], Endianness::BigEndian);
-    values.push(("Source Port", Val::Unsigned(source_port.unwrap())));
+    let source_port = read_be_u16(data, 0)?;
+    values.push(("Source Port", iana::PORTS.enum_val(source_port)));
 
-    let destination_port = unsigned(&data[2..4], Endianness::BigEndian);
-    values.push(("Destination Port", Val::Unsigned(destination_port.unwrap())));
+    let destination_port = read_be_u16(data, 2)?;
+    values.push(("Destination Port", iana::PORTS.enum_val(destination_port)));
 
-    let sequence_number = unsigned(&data[4..8], Endianness::BigEndian);
-    values.push(("Sequence Number", Val::Unsigned(sequence_number.unwrap())));
+    let sequence_number = read_be_u32(data, 4)?;
+    values.push(("Sequence Number", Val::Unsigned(sequence_number as u64)));
 
-    let acknowledgement_number = unsigned(&data[8..12], Endianness::BigEndian);
-    values.push(("Acknowledgement Number", Val::Unsigned(acknowledgement_number.unwrap())));
+    let acknowledgement_number = read_be_u32(data, 8)?;
+    values.push(("Acknowledgement Number", Val::Unsigned(acknowledgement_number as u64)));
 
-    //TODO: need better Val for this
-    let offset = data[12];
+    // Data offset: number of 32b words in header
+    let offset = data[12] >> 4;
     values.push(("Offset", Val::Unsigned(offset as u64)));
 
-    let header_lenght = offset as usize * 4;
+    let mut header_lenght = offset as usize * 4;
+    if offset < 5 {
+        conformance::warn(&mut values, format!["TCP data offset {} is less than the minimum of 5", offset]);
+        header_lenght = 20;
+    }
+
     if header_lenght > data.len() {
         return Err(DissectError::Underflow { expected: Some(header_lenght), have: data.len(),
             message: "TCP packet offset (header length) greater than available data".to_string() });
     }
 
-    let flags = data[14];
-    values.push(("Flags", Val::BitFlags8(flags, [
-                                         Some("CWR"), Some("ECE"), Some("URG"), Some("ACK"),
-                                         Some("PSH"), Some("RST"), Some("SYN"), Some("FIN")])));
+    // Nine flag bits, including the (historic) ECN-nonce bit: RFC 3540
+    let flags = ((data[12] & 0x01) as u64) << 8 | data[13] as u64;
+    values.push(("Flags", Val::BitFlags(flags, &FLAGS)));
 
-    let window = unsigned(&data[14..16], Endianness::BigEndian);
-    values.push(("Window", Val::Unsigned(window.unwrap())));
+    let window = read_be_u16(data, 14)?;
+    values.push(("Window", Val::Unsigned(window as u64)));
 
     //TODO: Val::Checksum ? need parts of IP header?!
     let checksum = &data[16..18];
     values.push(("Checksum", Val::Bytes(checksum)));
 
-    let urgent_pointer = unsigned(&data[18..20], Endianness::BigEndian);
-    values.push(("Urgent Pointer", Val::Unsigned(urgent_pointer.unwrap() as u64)));
+    let urgent_pointer = read_be_u16(data, 18)?;
+    values.push(("Urgent Pointer", Val::Unsigned(urgent_pointer as u64)));
 
     if header_lenght > 20 {
         let options = &data[20..header_lenght];
@@ -74,18 +102,56 @@ pub fn dissect(data : &[u8]) -> DissectResult {
     }
 
     let remainder = &data[header_lenght..];
-    values.push(("Payload", Val::Payload(raw("Data", remainder))));
+    values.push(("Payload", payload_with(source_port, destination_port, remainder, rule,
+                                         context)));
 
     Ok(Box::new(Val::Object("TCP", values)))
 }
 
+/// Pick a dissector for a TCP payload based on its ports (as given by the
+/// `http.tcp_ports` preference or bound in the `registry`) and content.
+pub fn payload(source_port: u16, destination_port: u16, data: &[u8]) -> Val {
+    payload_with(source_port, destination_port, data, None, &mut Context::new())
+}
+
+/// Dissect a TCP payload (see `payload`) within a context, unless a Decode-As
+/// rule for its conversation names another dissector.
+pub fn payload_with<'data>(source_port: u16, destination_port: u16, data: &'data [u8],
+                           rule: Option<Handler>, context: &mut Context) -> Val<'data> {
+    if data.is_empty() {
+        return Val::Payload(data, raw("Data", data));
+    }
+
+    if let Some(handler) = rule {
+        return Val::Payload(data, context.dissect_stateless(handler, data));
+    }
+
+    let http = context.preferences().ports("http.tcp_ports").unwrap_or(&[]);
+    if http.contains(&source_port) || http.contains(&destination_port) {
+        Val::Payload(data, heuristic::dissect(data, heuristic::Content::Http))
+    } else if let Some(dissector) = registry::lookup_ports(Table::TcpPort, source_port,
+                                                           destination_port) {
+        Val::Payload(data, dissector.dissect(data, context))
+    } else if bittorrent::looks_like_handshake(data) {
+        Val::Payload(data, bittorrent::dissect(data))
+    } else if zmtp::looks_like_greeting(data) {
+        Val::Payload(data, zmtp::dissect(data))
+    } else if data.starts_with(http2::PREFACE) {
+        Val::Payload(data, http2::dissect(data))
+    } else if let Some(result) = heuristic::dissect_best(data, false, &heuristic::Weights::default()) {
+        Val::Payload(data, result)
+    } else {
+        Val::Payload(data, raw("Data", data))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn dissect_tcp() {
-        let data = [1, 187, 252, 235, 74, 97, 130, 175, 50, 220, 74, 238, 5, 18, 56, 144, 237, 13, 0, 0, 2, 4, 5, 180, 4, 2, 8, 10, 15, 68, 221, 156, 29, 26, 35, 62, 1, 3, 3, 6];
+        let data = [1, 187, 252, 235, 74, 97, 130, 175, 50, 220, 74, 238, 160, 18, 56, 144, 237, 13, 0, 0, 2, 4, 5, 180, 4, 2, 8, 10, 15, 68, 221, 156, 29, 26, 35, 62, 1, 3, 3, 6];
 
         let val = *dissect(&data).unwrap();
         println!("{}", &val);
@@ -93,5 +159,31 @@ mod test {
 
         assert_eq!(val["Source Port"].as_unsigned().unwrap(), 443);
         assert_eq!(val["Destination Port"].as_unsigned().unwrap(), 64747);
+        assert_eq!(val["Offset"].as_unsigned().unwrap(), 10);
+        assert_eq!(val["Flags"].as_bitflags_bit_name("SYN"), Some(true));
+        assert_eq!(val["Flags"].as_bitflags_bit_name("ACK"), Some(true));
+        assert_eq!(val["Flags"].as_bitflags_bit_name("FIN"), Some(false));
+        assert_eq!(val["Flags"].as_bitflags_bit_name("NS"), Some(false));
+        assert_eq!(val["Flags"].to_string(), "000010010 (SYN+ACK)");
+        assert_eq!(val["Options"].as_bytes().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn http_ports() {
+        let data = [0x00, 0xff, 0x00, 0xff];
+        let name = |port, context: &mut Context| {
+            match payload_with(40000, port, &data, None, context) {
+                Val::Payload(_, Ok(val)) => match *val { Val::Object(name, _) => name, _ => "" },
+                _ => "",
+            }
+        };
+
+        let mut context = Context::new();
+        assert_eq!(name(8080, &mut context), "HTTP");
+        assert_eq!(name(8000, &mut context), "Data");
+
+        context.preferences_mut().set_str("http.tcp_ports", "[8000]").unwrap();
+        assert_eq!(name(8080, &mut context), "Data");
+        assert_eq!(name(8000, &mut context), "HTTP");
     }
 }
@@ -0,0 +1,185 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of User Datagram Protocol (UDP) and UDP-Lite packets.
+//!
+//! UDP-Lite replaces the UDP length with the number of bytes covered by the
+//! checksum, so that applications can accept datagrams with damaged data.
+//!
+//! See [RFC 768](https://tools.ietf.org/html/rfc768) and
+//! [RFC 3828](https://tools.ietf.org/html/rfc3828).
+
+use DissectError;
+use DissectResult;
+use Val;
+use NamedValues;
+use bittorrent;
+use context::Context;
+use heuristic;
+use iana;
+use raw;
+use read_be_u16;
+use registry::{self, Handler, Table};
+use rtp;
+
+/// Dissect a UDP datagram, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    dissect_as(data, None, context)
+}
+
+/// Dissect a UDP datagram within a context, and its payload with the
+/// dissector named by its conversation's Decode-As rule (if any).
+pub fn dissect_as<'data>(data: &'data [u8], rule: Option<Handler>, context: &mut Context)
+    -> DissectResult<'data> {
+
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "A UDP packet must be at least 8 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let source_port = read_be_u16(data, 0)?;
+    values.push(("Source Port", iana::PORTS.enum_val(source_port)));
+
+    let destination_port = read_be_u16(data, 2)?;
+    values.push(("Destination Port", iana::PORTS.enum_val(destination_port)));
+
+    // Length of header and data; anything past it is padding from a lower layer
+    let length = read_be_u16(data, 4)? as usize;
+    values.push(("Length", Val::Unsigned(length as u64)));
+
+    let checksum = &data[6..8];
+    values.push(("Checksum", Val::Bytes(checksum)));
+
+    let end = if length >= 8 && length <= data.len() { length } else { data.len() };
+    let remainder = &data[8..end];
+    values.push(("Payload", payload(source_port, destination_port, remainder, rule, context)));
+
+    Ok(Box::new(Val::Object("UDP", values)))
+}
+
+/// Dissect a UDP-Lite packet, whose length is that of the IP payload (see
+/// `dissect_as`).
+pub fn dissect_lite_as<'data>(data: &'data [u8], rule: Option<Handler>, context: &mut Context)
+    -> DissectResult<'data> {
+
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "A UDP-Lite packet must be at least 8 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let source_port = read_be_u16(data, 0)?;
+    values.push(("Source Port", iana::PORTS.enum_val(source_port)));
+
+    let destination_port = read_be_u16(data, 2)?;
+    values.push(("Destination Port", iana::PORTS.enum_val(destination_port)));
+
+    // Zero means the whole packet; otherwise it must at least cover the header.
+    let coverage = read_be_u16(data, 4)? as usize;
+    values.push(("Checksum Coverage", Val::Unsigned(coverage as u64)));
+    if coverage != 0 && (coverage < 8 || coverage > data.len()) {
+        return Err(DissectError::InvalidData(
+            format!["UDP-Lite checksum coverage {} is invalid", coverage]));
+    }
+
+    values.push(("Checksum", Val::Bytes(&data[6..8])));
+    values.push(("Payload", payload(source_port, destination_port, &data[8..], rule, context)));
+
+    Ok(Box::new(Val::Object("UDP-Lite", values)))
+}
+
+/// The number of bytes covered by a UDP-Lite checksum.
+pub fn lite_checksum_coverage(segment: &[u8]) -> usize {
+    match read_be_u16(segment, 4) {
+        Ok(0) | Err(_) => segment.len(),
+        Ok(coverage) => coverage as usize,
+    }
+}
+
+/// Pick a dissector for a UDP payload based on its conversation's Decode-As
+/// rule (if any), its ports (as bound in the `registry`) and content.
+fn payload<'data>(source_port: u16, destination_port: u16, data: &'data [u8],
+                  rule: Option<Handler>, context: &mut Context) -> Val<'data> {
+
+    if let Some(handler) = rule {
+        return Val::Payload(data, context.dissect_stateless(handler, data));
+    }
+
+    if let Some(dissector) = registry::lookup_ports(Table::UdpPort, source_port, destination_port) {
+        return Val::Payload(data, dissector.dissect(data, context));
+    }
+
+    // DHT nodes and trackers may use any port, but their messages are distinctive.
+    if bittorrent::looks_like_krpc(data) {
+        return Val::Payload(data, bittorrent::dissect_krpc(data));
+    }
+
+    if bittorrent::looks_like_tracker_connect(data) {
+        return Val::Payload(data, bittorrent::dissect_tracker_request(data));
+    }
+
+    // RTP uses dynamically-negotiated ports: even for RTP, odd for RTCP.
+    if source_port % 2 == 0 && destination_port % 2 == 0 && rtp::looks_like_rtp(data) {
+        return Val::Payload(data, rtp::dissect(data));
+    }
+
+    if source_port % 2 == 1 && destination_port % 2 == 1 && rtp::looks_like_rtcp(data) {
+        return Val::Payload(data, rtp::dissect_rtcp(data));
+    }
+
+    match heuristic::dissect_best(data, true, &heuristic::Weights::default()) {
+        Some(result) => Val::Payload(data, result),
+        None => Val::Payload(data, raw("Data", data)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_udp() {
+        let data = [0xd4, 0x31, 0x00, 0x35, 0x00, 0x0c, 0x5c, 0x0e, 0xde, 0xad, 0xbe, 0xef];
+
+        let val = *dissect_with(&data, &mut Context::new()).unwrap();
+        println!("{}", &val.pretty_print(0));
+
+        assert_eq!(val["Source Port"].as_unsigned().unwrap(), 54321);
+        assert_eq!(val["Destination Port"].as_unsigned().unwrap(), 53);
+        assert_eq!(val["Length"].as_unsigned().unwrap(), 12);
+        assert_eq!(val["Payload"]["raw data"].as_bytes().unwrap(), &[0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn dissect_udp_rtp_heuristic() {
+        let data = [0x13, 0x88, 0x13, 0x8a, 0x00, 0x18, 0x00, 0x00,
+                    0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xa0,
+                    0x12, 0x34, 0x56, 0x78, 0xff, 0xff, 0xff, 0xff];
+
+        let val = *dissect_with(&data, &mut Context::new()).unwrap();
+        assert_eq!(val["Payload"]["SSRC"].as_unsigned().unwrap(), 0x12345678);
+    }
+
+    #[test]
+    fn dissect_udp_lite() {
+        let data = [0xd4, 0x31, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, 0xde, 0xad, 0xbe, 0xef];
+
+        let val = *dissect_lite_as(&data, None, &mut Context::new()).unwrap();
+        assert_eq!(val["Checksum Coverage"].as_unsigned().unwrap(), 8);
+        assert_eq!(lite_checksum_coverage(&data), 8);
+        assert_eq!(val["Payload"]["raw data"].as_bytes().unwrap(), &[0xde, 0xad, 0xbe, 0xef]);
+
+        let mut bad = data;
+        bad[5] = 4;
+        assert!(dissect_lite_as(&bad, None, &mut Context::new()).is_err());
+    }
+}
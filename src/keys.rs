@@ -0,0 +1,280 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Providers of decryption keys.
+//!
+//! Decrypting layers (TLS, WPA, IPsec...) ask a `KeyProvider` for the key
+//! they need, identified by a `KeyId`, rather than reading key files
+//! themselves. Keys can come from a key log file or a pcapng Decryption
+//! Secrets Block (`StaticKeys`), from an OS keystore's command-line tool
+//! (`KeystoreCommand`) or from the embedding application itself: any
+//! `Fn(&KeyId) -> Option<Vec<u8>>` is a provider, so secrets never have to
+//! be written to disk.
+//!
+//! ```
+//! use rshark::keys::{KeyId, KeyProvider, KeyProviders, StaticKeys};
+//!
+//! let log = StaticKeys::parse_key_log("CLIENT_RANDOM 0102 a0a1a2\n").unwrap();
+//! let keys = KeyProviders::new()
+//!     .add(log)
+//!     .add(|id: &KeyId| match id {
+//!         &KeyId::Wpa { ref ssid } if ssid == b"lab" => Some(vec![0x42; 32]),
+//!         _ => None,
+//!     });
+//!
+//! let tls = KeyId::Tls { label: "CLIENT_RANDOM".to_string(), client_random: vec![1, 2] };
+//! assert_eq!(keys.key(&tls), Some(vec![0xa0, 0xa1, 0xa2]));
+//! assert_eq!(keys.key(&KeyId::Wpa { ssid: b"lab".to_vec() }), Some(vec![0x42; 32]));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Command;
+
+use DissectError;
+use pcapng::{DecryptionSecrets, SecretsType};
+
+/// What a key is needed for.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum KeyId {
+    /// A TLS secret, as named in the NSS key log format: a label such as
+    /// `CLIENT_RANDOM` or `CLIENT_TRAFFIC_SECRET_0` and the session's
+    /// client random.
+    Tls { label: String, client_random: Vec<u8> },
+
+    /// The pairwise master key (or pre-shared key) of a WPA network.
+    Wpa { ssid: Vec<u8> },
+
+    /// The key of an IPsec security association.
+    Esp { spi: u32, destination: IpAddr },
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &KeyId::Tls { ref label, ref client_random } => write![f, "tls {} {}", label, hex(client_random)],
+            &KeyId::Wpa { ref ssid } => write![f, "wpa {}", hex(ssid)],
+            &KeyId::Esp { spi, ref destination } => write![f, "esp {:08x} {}", spi, destination],
+        }
+    }
+}
+
+/// A source of decryption keys.
+pub trait KeyProvider {
+    /// The key identified by `id`, if this provider knows it.
+    fn key(&self, id: &KeyId) -> Option<Vec<u8>>;
+}
+
+/// A callback into the embedding application.
+impl<F> KeyProvider for F where F: Fn(&KeyId) -> Option<Vec<u8>> {
+    fn key(&self, id: &KeyId) -> Option<Vec<u8>> {
+        self(id)
+    }
+}
+
+/// Several providers, asked in the order they were added.
+#[derive(Default)]
+pub struct KeyProviders {
+    providers: Vec<Box<dyn KeyProvider>>,
+}
+
+impl KeyProviders {
+    pub fn new() -> KeyProviders {
+        KeyProviders::default()
+    }
+
+    pub fn add<P: KeyProvider + 'static>(mut self, provider: P) -> KeyProviders {
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.providers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+}
+
+impl KeyProvider for KeyProviders {
+    fn key(&self, id: &KeyId) -> Option<Vec<u8>> {
+        self.providers.iter().filter_map(|p| p.key(id)).next()
+    }
+}
+
+/// Keys held in memory, e.g., from a key log file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StaticKeys {
+    keys: HashMap<KeyId, Vec<u8>>,
+}
+
+impl StaticKeys {
+    pub fn new() -> StaticKeys {
+        StaticKeys::default()
+    }
+
+    pub fn insert(&mut self, id: KeyId, key: Vec<u8>) {
+        self.keys.insert(id, key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Read an NSS key log file (as written by browsers' `SSLKEYLOGFILE`).
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<StaticKeys, DissectError> {
+        let file = File::open(path.as_ref()).map_err(|e| DissectError::InvalidData(
+            format!["unable to open '{}': {}", path.as_ref().display(), e]))?;
+
+        let mut keys = StaticKeys::new();
+        keys.read_key_log(BufReader::new(file))?;
+        Ok(keys)
+    }
+
+    /// Parse an NSS key log.
+    pub fn parse_key_log(log: &str) -> Result<StaticKeys, DissectError> {
+        let mut keys = StaticKeys::new();
+        keys.read_key_log(log.as_bytes())?;
+        Ok(keys)
+    }
+
+    /// Add the TLS keys from the Decryption Secrets Blocks of a pcapng file.
+    pub fn from_secrets(secrets: &[DecryptionSecrets]) -> Result<StaticKeys, DissectError> {
+        let mut keys = StaticKeys::new();
+        for s in secrets.iter().filter(|s| s.secrets_type == SecretsType::TlsKeyLog) {
+            keys.read_key_log(&s.data[..])?;
+        }
+
+        Ok(keys)
+    }
+
+    /// Add the keys in an NSS key log: lines of `LABEL CLIENT_RANDOM SECRET`
+    /// (the latter two in hex) and `#` comments.
+    pub fn read_key_log<R: BufRead>(&mut self, log: R) -> Result<(), DissectError> {
+        for (i, line) in log.lines().enumerate() {
+            let line = line.map_err(|e| DissectError::InvalidData(e.to_string()))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let (client_random, secret) = match fields.len() {
+                3 => (unhex(fields[1]), unhex(fields[2])),
+                _ => (None, None),
+            };
+
+            match (client_random, secret) {
+                (Some(client_random), Some(secret)) => self.insert(KeyId::Tls {
+                    label: fields[0].to_string(),
+                    client_random: client_random,
+                }, secret),
+                _ => return Err(DissectError::InvalidData(
+                    format!["invalid key log entry on line {}: '{}'", i + 1, line])),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn key(&self, id: &KeyId) -> Option<Vec<u8>> {
+        self.keys.get(id).cloned()
+    }
+}
+
+/// Keys looked up by running an OS keystore's command-line tool, e.g.,
+/// `secret-tool lookup` (libsecret) or `security find-generic-password -w`
+/// (macOS). The key's ID is appended to the command's arguments as words
+/// (e.g., `esp 00001234 10.0.0.1`) and the command is expected to print the
+/// key in hex.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeystoreCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl KeystoreCommand {
+    pub fn new(program: &str, args: &[&str]) -> KeystoreCommand {
+        KeystoreCommand {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+impl KeyProvider for KeystoreCommand {
+    fn key(&self, id: &KeyId) -> Option<Vec<u8>> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .args(id.to_string().split(' '))
+            .output()
+            .ok()?;
+
+        if !output.status.success() {
+            return None;
+        }
+
+        unhex(String::from_utf8_lossy(&output.stdout).trim())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!["{:02x}", b]).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty() || s.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..s.len()).step_by(2).map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok())).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_log_and_secrets() {
+        let secrets = DecryptionSecrets {
+            secrets_type: SecretsType::TlsKeyLog,
+            data: b"# comment\nCLIENT_RANDOM 0a0b 1122\nSERVER_TRAFFIC_SECRET_0 0a0b 3344\n".to_vec(),
+        };
+
+        let keys = StaticKeys::from_secrets(&[secrets]).unwrap();
+        assert_eq!(keys.len(), 2);
+
+        let id = KeyId::Tls { label: "SERVER_TRAFFIC_SECRET_0".to_string(), client_random: vec![0x0a, 0x0b] };
+        assert_eq!(keys.key(&id), Some(vec![0x33, 0x44]));
+        assert_eq!(id.to_string(), "tls SERVER_TRAFFIC_SECRET_0 0a0b");
+
+        assert!(StaticKeys::parse_key_log("CLIENT_RANDOM 0a0b\n").is_err());
+        assert!(StaticKeys::parse_key_log("CLIENT_RANDOM 0a0b xyz\n").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keystore_command() {
+        let esp = KeyId::Esp { spi: 0x1234, destination: "10.0.0.1".parse().unwrap() };
+        let keystore = KeystoreCommand::new("sh", &["-c", "test \"$3\" = 10.0.0.1 && echo c0ffee", "sh"]);
+        assert_eq!(keystore.key(&esp), Some(vec![0xc0, 0xff, 0xee]));
+        assert_eq!(keystore.key(&KeyId::Wpa { ssid: vec![] }), None);
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Byte ranges of the protocol layers in a dissected packet.
+//!
+//! Every payload records the bytes it was dissected from, so the extent of
+//! each layer (and where its payload starts) is known without re-deriving
+//! header lengths from field values:
+//!
+//! ```
+//! use rshark::layers;
+//!
+//! let packet = [
+//!     0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+//!     0x45, 0x00, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+//!     10, 0, 0, 1, 10, 0, 0, 2,
+//!     0xd4, 0x31, 0x04, 0xd2, 0x00, 0x0a, 0x00, 0x00,
+//!     b'h', b'i'];
+//! let val = rshark::ethernet::dissect(&packet).unwrap();
+//!
+//! let udp = layers::find(&packet, &val, "UDP").unwrap();
+//! assert_eq!((udp.start, udp.payload_start, udp.end), (34, Some(42), 44));
+//! assert_eq!(layers::payload(&packet, &val, "UDP"), Some(&b"hi"[..]));
+//! ```
+
+use std::ops::Range;
+
+use Val;
+
+/// The extent of one protocol layer within a packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    /// The name of the layer's `Val::Object`, e.g., "IPv4".
+    pub name: &'static str,
+
+    /// Where the layer's header starts.
+    pub start: usize,
+
+    /// Where the layer's payload starts, if it has one.
+    pub payload_start: Option<usize>,
+
+    /// The end of the layer (and its payload).
+    pub end: usize,
+}
+
+impl Layer {
+    /// The layer's header and payload.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// The layer's header, i.e., everything before its payload.
+    pub fn header(&self) -> Range<usize> {
+        self.start..self.payload_start.unwrap_or(self.end)
+    }
+
+    /// The layer's payload, if it has one.
+    pub fn payload(&self) -> Option<Range<usize>> {
+        self.payload_start.map(|start| start..self.end)
+    }
+}
+
+/// The layers of `packet`, outermost first, following the first payload
+/// of each layer down the protocol stack.
+///
+/// `packet` must be the data that `val` was dissected from. Layers whose
+/// bytes don't lie within it (e.g., reassembled or decoded values) end
+/// the stack.
+pub fn layers(packet: &[u8], val: &Val) -> Vec<Layer> {
+    let mut layers = Vec::new();
+    let mut current = Some((val, 0..packet.len()));
+
+    while let Some((val, range)) = current.take() {
+        let (name, values) = match val.as_object() {
+            Some(obj) => obj,
+            None => break,
+        };
+
+        let payload = values.iter()
+            .filter_map(|&(_, ref v)| match v {
+                &Val::Payload(data, ref result) => Some((data, result)),
+                _ => None,
+            })
+            .next();
+
+        let mut layer = Layer { name: name, start: range.start, payload_start: None, end: range.end };
+        if let Some((data, result)) = payload {
+            if let Some(inner) = offset(packet, data) {
+                layer.payload_start = Some(inner.start);
+                if let &Ok(ref v) = result {
+                    current = Some((&**v, inner));
+                }
+            }
+        }
+
+        layers.push(layer);
+    }
+
+    layers
+}
+
+/// The outermost layer with the given name.
+pub fn find(packet: &[u8], val: &Val, name: &str) -> Option<Layer> {
+    layers(packet, val).into_iter().find(|l| l.name == name)
+}
+
+/// The raw bytes of the named layer's payload, e.g., the TLS records
+/// carried by "TCP".
+pub fn payload<'a>(packet: &'a [u8], val: &Val, name: &str) -> Option<&'a [u8]> {
+    find(packet, val, name)
+        .and_then(|l| l.payload())
+        .map(|r| &packet[r])
+}
+
+/// The position of `data` within `packet`, if it lies within it.
+fn offset(packet: &[u8], data: &[u8]) -> Option<Range<usize>> {
+    let base = packet.as_ptr() as usize;
+    let start = data.as_ptr() as usize;
+
+    if start < base || start + data.len() > base + packet.len() {
+        return None;
+    }
+
+    Some(start - base..start - base + data.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    #[test]
+    fn layer_boundaries() {
+        // Ethernet, IPv4 with a 4 B option, TCP with 20 B header and 3 B payload
+        let mut packet = vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+                              0x46, 0x00, 0x00, 0x2f, 0x00, 0x01, 0x00, 0x00, 0x40, 0x06, 0x00, 0x00,
+                              10, 0, 0, 1, 10, 0, 0, 2, 0x01, 0x01, 0x01, 0x00,
+                              0xd4, 0x31, 0x23, 0x28, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff,
+                              0x00, 0x00, 0x00, 0x00];
+        packet.extend_from_slice(b"abc");
+        let val = ethernet::dissect(&packet).unwrap();
+
+        let stack = layers(&packet, &val);
+        let names = stack.iter().map(|l| l.name).collect::<Vec<_>>();
+        assert_eq!(&names[..3], &["Ethernet frame", "IPv4", "TCP"]);
+        assert_eq!(stack[0].header(), 0..14);
+        assert_eq!(stack[1].header(), 14..38);
+        assert_eq!(stack[2].payload(), Some(58..61));
+        assert_eq!(payload(&packet, &val, "TCP"), Some(&b"abc"[..]));
+
+        // Bytes from elsewhere aren't part of the packet.
+        assert_eq!(offset(&packet, b"abc"), None);
+    }
+}
@@ -54,13 +54,19 @@
 //! }
 //! ```
 //!
+//! Packets read from a capture file needn't start with an Ethernet header:
+//! `rshark::dissect_linktype()` chooses the first dissector from the
+//! capture's link type.
+//!
 //! A `Val` can represent an arbitrary tree of structured data
 //! (useful in graphical displays) and can be pretty-printed with indentation for
-//! sub-objects.
+//! sub-objects (see `output::pretty`).
 
 #![doc(html_logo_url = "https://raw.githubusercontent.com/musec/rusty-shark/master/artwork/wordmark.png")]
 
 extern crate byteorder;
+#[cfg(feature = "serde")]
+extern crate serde;
 #[macro_use]
 extern crate itertools;
 #[macro_use]
@@ -69,23 +75,28 @@ extern crate nom;
 use byteorder::ReadBytesExt;
 use std::fmt;
 use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ops::Index;
 use std::error::Error;
 
 use itertools::Itertools;
 use nom::{IResult, Needed};
 
+use mac::MacAddr;
+
 /// A value parsed from a packet.
 ///
 /// # TODO
 /// This value type isn't as expressive as would be required for a real
-/// Wireshark replacement just yet. Additional needs include:
+/// Wireshark replacement just yet. (The bytes that values came from are
+/// tracked by reference; see `provenance`. Notes like "parsed ok but checksum
+/// doesn't match" are recorded as fields; see `expert`.) Additional needs
+/// include:
 ///
-///  * tracking original bytes (by reference or by index?)
-///  * supporting error metadata (e.g., "parsed ok but checksum doesn't match")
 ///  * supporting asynchronous sub-object parsing (some sort of promises?)
 ///
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize))]
 pub enum Val<'data> {
     /// A signed integer, in machine-native representation.
     Signed(i64),
@@ -105,11 +116,20 @@ pub enum Val<'data> {
     /// Single byte bit flags.
     BitFlags8(u8, [Option<&'static str>; 8]),
 
+    /// Bit flags of up to 64 bits: as many as there are (optional) names,
+    /// the first of which names the least significant bit.
+    BitFlags(u64, &'static [Option<&'static str>]),
+
     /// A sub-object is an ordered set of name, value pairs.
     Object(&'static str, NamedValues<'data>),
 
-    /// A payload, which can be dissected and fail
-    Payload(DissectResult<'data>),
+    /// Repeated values of the same kind, e.g., DNS answers or cipher suites.
+    /// Elements are accessed by their index: `get("2")`.
+    List(Vec<Val<'data>>),
+
+    /// A payload, which can be dissected and fail, along with the bytes it
+    /// was dissected from.
+    Payload(&'data [u8], DissectResult<'data>),
 
     /// Raw bytes, e.g., a checksum or just unparsed data.
     Bytes(&'data [u8]),
@@ -117,29 +137,22 @@ pub enum Val<'data> {
     /// Raw bytes of payload that has no dissector implemented
     Undissected(&'static str, &'data [u8]),
 
-    // TODO: labeled or enum variant for enumerations like protocols: 6 (tcp), 17 (udp)
-    // try avoid Boxing (allocations) - perhaps pointer to detail dissect function
-    // Signed(i64, Option<Dissector>)
+    /// An enumerated number, e.g., an IP protocol number, with its symbolic
+    /// name if it has one (see `EnumMap::enum_val`).
+    Enum { value: u64, name: Option<&'static str> },
+
+    /// A point in time, relative to the Unix epoch (see `time`).
+    Timestamp(time::Time),
+
+    /// An interval of time, e.g., a round-trip time or a timeout.
+    Duration(time::Time),
 }
 
 impl<'data> Val<'data> {
+    /// Render the value as indented text, starting at an indentation level
+    /// (see `output::pretty::PrettyPrinter` for colours, limits and offsets).
     pub fn pretty_print(&self, indent:usize) -> String {
-        match self {
-            &Val::Object(ref name, ref values) => {
-                let mut s = "\n".to_string();
-                let prefix =
-                    ::std::iter::repeat(" ").take(2 * indent).collect::<String>();
-
-                s = s + &format!["{}[{}]\n", prefix, name];
-                for &(ref k, ref v) in values {
-                    s = s + &format!["{}{}: {}\n", prefix, k, v.pretty_print(indent + 1)]
-                }
-                s
-            }
-            &Val::Payload(Ok(ref v)) => format!["-> {}", v.pretty_print(indent + 1)],
-            &Val::Payload(Err(ref e)) => format!["<< Error: {} >>", e],
-            _ => format!["{}", self]
-        }
+        output::pretty::PrettyPrinter::new().indent(indent).to_string(self)
     }
 
     /// Returns true if the `Val` is a Signed. Returns false otherwise.
@@ -161,11 +174,54 @@ impl<'data> Val<'data> {
         self.as_unsigned().is_some()
     }
 
-    /// If the `Val` is an Unsigned, returns the associated u64.
+    /// If the `Val` is an Unsigned (or an Enum), returns the associated u64.
     /// Returns None otherwise.
     pub fn as_unsigned(&self) -> Option<u64> {
         match self {
             &Val::Unsigned(val) => Some(val),
+            &Val::Enum { value, .. } => Some(value),
+            _ => None
+        }
+    }
+
+    /// Returns true if the `Val` is an Enum. Returns false otherwise.
+    pub fn is_enum(&self) -> bool {
+        self.as_enum().is_some()
+    }
+
+    /// If the `Val` is an Enum, returns the associated value and name.
+    /// Returns None otherwise.
+    pub fn as_enum(&self) -> Option<(u64, Option<&'static str>)> {
+        match self {
+            &Val::Enum { value, name } => Some((value, name)),
+            _ => None
+        }
+    }
+
+    /// Returns true if the `Val` is a Timestamp. Returns false otherwise.
+    pub fn is_timestamp(&self) -> bool {
+        self.as_timestamp().is_some()
+    }
+
+    /// If the `Val` is a Timestamp, returns the associated Time.
+    /// Returns None otherwise.
+    pub fn as_timestamp(&self) -> Option<time::Time> {
+        match self {
+            &Val::Timestamp(t) => Some(t),
+            _ => None
+        }
+    }
+
+    /// Returns true if the `Val` is a Duration. Returns false otherwise.
+    pub fn is_duration(&self) -> bool {
+        self.as_duration().is_some()
+    }
+
+    /// If the `Val` is a Duration, returns the associated Time.
+    /// Returns None otherwise.
+    pub fn as_duration(&self) -> Option<time::Time> {
+        match self {
+            &Val::Duration(t) => Some(t),
             _ => None
         }
     }
@@ -221,6 +277,66 @@ impl<'data> Val<'data> {
         }
     }
 
+    /// An IPv4 address, dotted-decimal encoded. `bytes` must be 4 B long.
+    pub fn ipv4(bytes: &'data [u8]) -> Val<'data> {
+        Val::Address {
+            bytes: bytes,
+            encoded: Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]).to_string(),
+        }
+    }
+
+    /// An IPv6 address, encoded as recommended by RFC 5952. `bytes` must be
+    /// 16 B long.
+    pub fn ipv6(bytes: &'data [u8]) -> Val<'data> {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(bytes);
+        Val::Address { bytes: bytes, encoded: Ipv6Addr::from(octets).to_string() }
+    }
+
+    /// A MAC address, encoded as colon-separated hex bytes. `bytes` must be
+    /// 6 B long.
+    pub fn mac(bytes: &'data [u8]) -> Val<'data> {
+        Val::Address { bytes: bytes, encoded: MacAddr::from_bytes(bytes).unwrap().to_string() }
+    }
+
+    /// If the `Val` is a 4 B Address, returns it as an IPv4 address.
+    /// Returns None otherwise.
+    pub fn as_ipv4(&self) -> Option<Ipv4Addr> {
+        match self {
+            &Val::Address { bytes, .. } if bytes.len() == 4 =>
+                Some(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+            _ => None
+        }
+    }
+
+    /// If the `Val` is a 16 B Address, returns it as an IPv6 address.
+    /// Returns None otherwise.
+    pub fn as_ipv6(&self) -> Option<Ipv6Addr> {
+        match self {
+            &Val::Address { bytes, .. } if bytes.len() == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(bytes);
+                Some(Ipv6Addr::from(octets))
+            },
+            _ => None
+        }
+    }
+
+    /// If the `Val` is an IPv4 or IPv6 Address, returns it as an `IpAddr`.
+    /// Returns None otherwise.
+    pub fn as_ip(&self) -> Option<IpAddr> {
+        self.as_ipv4().map(IpAddr::V4).or_else(|| self.as_ipv6().map(IpAddr::V6))
+    }
+
+    /// If the `Val` is a 6 B Address, returns it as a MAC address.
+    /// Returns None otherwise.
+    pub fn as_mac(&self) -> Option<MacAddr> {
+        match self {
+            &Val::Address { bytes, .. } if bytes.len() == 6 => MacAddr::from_bytes(bytes),
+            _ => None
+        }
+    }
+
     pub fn is_bitflags8(&self) -> bool {
         match self {
             &Val::BitFlags8(_, _) => true,
@@ -248,6 +364,38 @@ impl<'data> Val<'data> {
         }
     }
 
+    /// Returns true if the `Val` is a BitFlags8 or BitFlags. Returns false
+    /// otherwise.
+    pub fn is_bitflags(&self) -> bool {
+        self.as_bitflags().is_some()
+    }
+
+    /// If the `Val` is a BitFlags8 or BitFlags, returns the flags and the
+    /// names of the bits, least significant first. Returns None otherwise.
+    pub fn as_bitflags(&self) -> Option<(u64, &[Option<&'static str>])> {
+        match self {
+            &Val::BitFlags8(flags, ref names) => Some((flags as u64, names)),
+            &Val::BitFlags(flags, names) => Some((flags, names)),
+            _ => None
+        }
+    }
+
+    /// Whether a bit of a BitFlags8 or BitFlags is set. Returns None if the
+    /// `Val` is neither or doesn't have the bit.
+    pub fn as_bitflags_bit_no(&self, bit: u8) -> Option<bool> {
+        self.as_bitflags()
+            .and_then(|(flags, names)| if (bit as usize) < names.len() { Some(flags) } else { None })
+            .map(|flags| flags >> bit & 1 == 1)
+    }
+
+    /// Whether the bit with the given name is set. Returns None if the `Val`
+    /// isn't a BitFlags8 or BitFlags or has no such bit.
+    pub fn as_bitflags_bit_name(&self, name: &str) -> Option<bool> {
+        self.as_bitflags()
+            .and_then(|(_, names)| names.iter().position(|&n| n == Some(name)))
+            .and_then(|bit| self.as_bitflags_bit_no(bit as u8))
+    }
+
     /// Returns true if the `Val` is a Object. Returns false otherwise.
     pub fn is_object(&self) -> bool {
         self.as_object().is_some()
@@ -262,6 +410,19 @@ impl<'data> Val<'data> {
         }
     }
 
+    /// Returns true if the `Val` is a List. Returns false otherwise.
+    pub fn is_list(&self) -> bool {
+        self.as_list().is_some()
+    }
+
+    /// If the `Val` is a List, returns its elements. Returns None otherwise.
+    pub fn as_list<'val>(&'val self) -> Option<&'val [Val<'data>]> {
+        match self {
+            &Val::List(ref values) => Some(values),
+            _ => None
+        }
+    }
+
     /// Returns true if the `Val` is a Payload. Returns false otherwise.
     pub fn is_payload(&self) -> bool {
         self.as_payload().is_some()
@@ -271,7 +432,16 @@ impl<'data> Val<'data> {
     /// Returns None otherwise.
     pub fn as_payload(&self) -> Option<&'data DissectResult> {
         match self {
-            &Val::Payload(ref val) => Some(val),
+            &Val::Payload(_, ref val) => Some(val),
+            _ => None
+        }
+    }
+
+    /// If the `Val` is a Payload, returns the bytes it was dissected from.
+    /// Returns None otherwise.
+    pub fn as_payload_bytes(&self) -> Option<&'data [u8]> {
+        match self {
+            &Val::Payload(data, _) => Some(data),
             _ => None
         }
     }
@@ -308,8 +478,10 @@ impl<'data> Val<'data> {
         match self {
             &Val::Object(_, ref values) => values.iter().find(|&&(ref k, ref _v)| k == &index)
                 .ok_or(AccessError::not_found(index, self)).map(|v| &v.1),
-            &Val::Payload(Ok(ref val)) => val.get(index),
-            &Val::Payload(Err(ref e)) => Err(AccessError::dissect_error(index, e)),
+            &Val::List(ref values) => index.parse::<usize>().ok().and_then(|i| values.get(i))
+                .ok_or(AccessError::not_found(index, self)),
+            &Val::Payload(_, Ok(ref val)) => val.get(index),
+            &Val::Payload(_, Err(ref e)) => Err(AccessError::dissect_error(index, e)),
             _ => Err(AccessError::leaf_variant(self))
         }
     }
@@ -331,6 +503,22 @@ impl<'data> Val<'data> {
             }
         })
     }
+
+    /// Find the outermost protocol layer (`Val::Object`) with the given name,
+    /// following successfully-dissected payloads down the protocol stack.
+    pub fn layer<'val>(&'val self, name: &str) -> Option<&'val Val<'data>> {
+        match self {
+            &Val::Object(n, _) if n == name => Some(self),
+            &Val::Object(_, ref values) => values.iter()
+                .filter_map(|&(_, ref v)| match v {
+                    &Val::Payload(_, Ok(ref inner)) => inner.layer(name),
+                    _ => None
+                })
+                .next(),
+            &Val::Payload(_, Ok(ref val)) => val.layer(name),
+            _ => None
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -393,28 +581,25 @@ impl<'data> fmt::Display for Val<'data> {
             &Val::String(ref s) => write![f, "\"{}\"", s],
             &Val::Symbol(ref s) => write![f, "{}", s],
             &Val::Address { ref encoded, .. } => write![f, "{}", encoded],
-            &Val::BitFlags8(ref flags, ref desc) => {
-                let mut bit = 1u8;
-                write![f, "{:08b} ({})", flags, (0..8).into_iter().filter_map(move |i| {
-                    let val = if let Some(desc) = desc[i] {
-                        if flags & bit > 0 {
-                            Some(desc)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
-                    bit = bit << 1;
-                    val
-                }).format("+", |val, f| f(&format_args!("{}", val)))]
+            &Val::Enum { value, name: Some(name) } => write![f, "{} ({})", name, value],
+            &Val::Enum { value, name: None } => write![f, "{}", value],
+            &Val::Timestamp(t) => write![f, "{}", time::format_timestamp(t, time::TimeFormat::Utc)],
+            &Val::Duration(t) => write![f, "{}", time::format_duration(t)],
+            &Val::BitFlags8(..) | &Val::BitFlags(..) => {
+                let (flags, desc) = self.as_bitflags().unwrap();
+                write![f, "{:0width$b} ({})", flags, desc.iter().enumerate()
+                    .filter_map(|(i, desc)| desc.filter(|_| flags >> i & 1 == 1))
+                    .format("+", |val, f| f(&format_args!("{}", val))), width = desc.len()]
             },
             &Val::Object(ref name, ref values) => {
                 write![f, "{} -> {{ {} }}", name, values.iter()
                     .format(", ", |kv, f| f(&format_args!("{}: {}", kv.0, kv.1)))]
             },
-            &Val::Payload(Ok(ref val)) => write![f, "({})", val],
-            &Val::Payload(Err(ref e)) => write![f, "<<{}>>", e],
+            &Val::List(ref values) => {
+                write![f, "[{}]", values.iter().format(", ", |v, f| f(&format_args!("{}", v)))]
+            },
+            &Val::Payload(_, Ok(ref val)) => write![f, "({})", val],
+            &Val::Payload(_, Err(ref e)) => write![f, "<<{}>>", e],
             &Val::Bytes(ref bytes) => {
                 try![write![f, "{} B [", bytes.len()]];
 
@@ -457,10 +642,31 @@ impl<'data> fmt::Display for Val<'data> {
 
 
 /// An error related to packet dissection (underflow, bad value, etc.).
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum DissectError {
     Underflow { expected: Option<usize>, have: usize, message: String, },
     InvalidData(String),
+
+    /// Invalid data that falls into a specific, machine-readable category.
+    Malformed { code: ErrorCode, message: String },
+}
+
+impl DissectError {
+    /// A `Malformed` error with the given code.
+    pub fn malformed<S: Into<String>>(code: ErrorCode, message: S) -> DissectError {
+        DissectError::Malformed { code: code, message: message.into() }
+    }
+
+    /// The kind of error, for programs that need to tell errors apart
+    /// without parsing their messages.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            &DissectError::Underflow { .. } => ErrorCode::Truncated,
+            &DissectError::InvalidData(_) => ErrorCode::InvalidData,
+            &DissectError::Malformed { code, .. } => code,
+        }
+    }
 }
 
 impl fmt::Display for DissectError {
@@ -473,6 +679,59 @@ impl fmt::Display for DissectError {
                     have, message],
             },
             &DissectError::InvalidData(ref msg) => write![f, "invalid data: {}", msg],
+            &DissectError::Malformed { code, ref message } =>
+                write![f, "invalid data ({}): {}", code.name(), message],
+        }
+    }
+}
+
+/// Stable identifiers for kinds of dissection errors.
+///
+/// Numeric values and names will not change between releases; new codes may
+/// be added.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum ErrorCode {
+    /// Not enough data, e.g., a truncated header.
+    Truncated = 1,
+
+    /// Invalid data of no more specific kind.
+    InvalidData = 2,
+
+    /// A magic number or protocol signature didn't match.
+    BadMagic = 3,
+
+    /// A protocol version that isn't supported.
+    UnsupportedVersion = 4,
+
+    /// A length field that is inconsistent with the data.
+    InvalidLength = 5,
+
+    /// A protocol identifier that no dissector is known for.
+    UnknownProtocol = 6,
+
+    /// The dissector panicked.
+    DissectorPanic = 7,
+
+    /// State was evicted to stay within a memory budget.
+    BudgetExceeded = 8,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            &ErrorCode::Truncated => "truncated",
+            &ErrorCode::InvalidData => "invalid_data",
+            &ErrorCode::BadMagic => "bad_magic",
+            &ErrorCode::UnsupportedVersion => "unsupported_version",
+            &ErrorCode::InvalidLength => "invalid_length",
+            &ErrorCode::UnknownProtocol => "unknown_protocol",
+            &ErrorCode::DissectorPanic => "dissector_panic",
+            &ErrorCode::BudgetExceeded => "budget_exceeded",
         }
     }
 }
@@ -508,6 +767,7 @@ pub type NamedValues<'data> = Vec<(&'static str, Val<'data>)>;
 pub type Dissector<'data> = fn(&'data [u8]) -> DissectResult<'data>;
 
 /// Little- or big-endian integer representations.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Endianness {
     BigEndian,
     LittleEndian,
@@ -575,6 +835,154 @@ pub fn unsigned(buffer: &[u8], endianness: Endianness) -> Result<u64, DissectErr
     }
 }
 
+/// The `len` bytes at `offset` in `data`, or an underflow error if `data`
+/// is too short.
+fn field(data: &[u8], offset: usize, len: usize) -> Result<&[u8], DissectError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= data.len() => Ok(&data[offset..end]),
+        _ => Err(DissectError::Underflow { expected: Some(offset.saturating_add(len)), have: data.len(),
+            message: format!["{} B field at offset {} greater than available data", len, offset] }),
+    }
+}
+
+/// Read the byte at `offset` in `data`.
+pub fn read_u8(data: &[u8], offset: usize) -> Result<u8, DissectError> {
+    field(data, offset, 1).map(|b| b[0])
+}
+
+/// Read a big-endian `u16` at `offset` in `data`.
+pub fn read_be_u16(data: &[u8], offset: usize) -> Result<u16, DissectError> {
+    field(data, offset, 2).map(|b| {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(b);
+        u16::from_be_bytes(bytes)
+    })
+}
+
+/// Read a big-endian `u32` at `offset` in `data`.
+pub fn read_be_u32(data: &[u8], offset: usize) -> Result<u32, DissectError> {
+    field(data, offset, 4).map(|b| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(b);
+        u32::from_be_bytes(bytes)
+    })
+}
+
+/// Read a big-endian `u64` at `offset` in `data`.
+pub fn read_be_u64(data: &[u8], offset: usize) -> Result<u64, DissectError> {
+    field(data, offset, 8).map(|b| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(b);
+        u64::from_be_bytes(bytes)
+    })
+}
+
+/// Read a little-endian `u16` at `offset` in `data`.
+pub fn read_le_u16(data: &[u8], offset: usize) -> Result<u16, DissectError> {
+    field(data, offset, 2).map(|b| {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(b);
+        u16::from_le_bytes(bytes)
+    })
+}
+
+/// Read a little-endian `u32` at `offset` in `data`.
+pub fn read_le_u32(data: &[u8], offset: usize) -> Result<u32, DissectError> {
+    field(data, offset, 4).map(|b| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(b);
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Read a little-endian `u64` at `offset` in `data`.
+pub fn read_le_u64(data: &[u8], offset: usize) -> Result<u64, DissectError> {
+    field(data, offset, 8).map(|b| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(b);
+        u64::from_le_bytes(bytes)
+    })
+}
+
+/// A table of symbolic names for the values of a numeric field.
+///
+/// Tables are usually defined with `enum_map!`:
+///
+/// ```
+/// #[macro_use] extern crate rshark;
+/// use rshark::Val;
+///
+/// enum_map!(OPCODES {
+///     1 => "Request",
+///     2 => "Reply",
+/// });
+///
+/// # fn main() {
+/// assert_eq!(OPCODES.val(2u8), Val::Symbol("Reply"));
+/// assert_eq!(OPCODES.val(9u8), Val::String("Unknown (9)".to_string()));
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct EnumMap(pub &'static [(u64, &'static str)]);
+
+impl EnumMap {
+    /// The name of a value, if it is in the table.
+    pub fn name<T: Into<u64>>(&self, value: T) -> Option<&'static str> {
+        let value = value.into();
+        self.0.iter().find(|&&(v, _)| v == value).map(|&(_, name)| name)
+    }
+
+    /// The name of a value, or "Unknown (N)".
+    pub fn format<T: Into<u64>>(&self, value: T) -> String {
+        let value = value.into();
+        self.name(value).map(str::to_string).unwrap_or(format!["Unknown ({})", value])
+    }
+
+    /// An `Enum` of a value and its name, if it is in the table.
+    pub fn enum_val<'data, T: Into<u64>>(&self, value: T) -> Val<'data> {
+        let value = value.into();
+        Val::Enum { value: value, name: self.name(value) }
+    }
+
+    /// A symbol for a value in the table or, failing that, an "Unknown (N)"
+    /// string.
+    pub fn val<'data, T: Into<u64>>(&self, value: T) -> Val<'data> {
+        let value = value.into();
+        match self.name(value) {
+            Some(name) => Val::Symbol(name),
+            None => Val::String(format!["Unknown ({})", value]),
+        }
+    }
+}
+
+/// Define a static `EnumMap` of symbolic names for numeric values.
+#[macro_export]
+macro_rules! enum_map {
+    ($name:ident { $($value:expr => $symbol:expr),* $(,)* }) => {
+        static $name: $crate::EnumMap = $crate::EnumMap(&[$(($value as u64, $symbol)),*]);
+    };
+    (pub $name:ident { $($value:expr => $symbol:expr),* $(,)* }) => {
+        pub static $name: $crate::EnumMap = $crate::EnumMap(&[$(($value as u64, $symbol)),*]);
+    };
+}
+
+/// Dissect a packet from a capture with the given libpcap link type (DLT),
+/// e.g., as reported by a `source::Source`, starting at the right dissector
+/// for that link type rather than assuming Ethernet.
+///
+/// ```
+/// use rshark::output::pcap::LINKTYPE_RAW;
+///
+/// let packet = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+/// let val = rshark::dissect_linktype(LINKTYPE_RAW, &packet).unwrap();
+/// assert_eq!(val["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+///
+/// assert!(rshark::dissect_linktype(0xffff, &packet).is_err());
+/// ```
+pub fn dissect_linktype(dlt: u32, data: &[u8]) -> DissectResult {
+    preset::dissect(dlt, data)
+}
+
 /// Dissector of last resort: store raw bytes without interpretation.
 pub fn raw<'data>(name: &'static str, data: &'data [u8]) -> DissectResult<'data> {
     let mut obj = NamedValues::new();
@@ -582,8 +990,89 @@ pub fn raw<'data>(name: &'static str, data: &'data [u8]) -> DissectResult<'data>
     Ok(Box::new(Val::Object(name, obj)))
 }
 
+pub mod access;
+pub mod amqp;
+pub mod batch;
+pub mod bittorrent;
+pub mod bpf;
+pub mod budget;
+pub mod can;
+pub mod checksum;
+pub mod cidr;
+pub mod conformance;
+pub mod context;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod decode_as;
+pub mod dedup;
+pub mod diff;
+pub mod eapol;
 pub mod ethernet;
+pub mod expert;
+pub mod flow;
+pub mod heuristic;
+pub mod http2;
+pub mod iana;
+pub mod ieee80211;
+pub mod ieee802154;
+pub mod intern;
 pub mod ip;
+pub mod keys;
+pub mod layers;
+pub mod llc;
+pub mod loopback;
+pub mod mac;
+pub mod mpls;
+pub mod mysql;
+pub mod names;
+pub mod nats;
+pub mod netbios;
+pub mod nfs;
+pub mod output;
+pub mod overlay;
+pub mod owned;
+pub mod payload;
+pub mod pcapng;
+pub mod pdu;
+pub mod pipeline;
+pub mod ppp;
+pub mod preferences;
+pub mod preset;
+pub mod progress;
+pub mod provenance;
+pub mod registry;
+pub mod replay;
+pub mod rpc;
+pub mod rtmp;
+pub mod rtp;
+pub mod rtsp;
+pub mod s7comm;
+pub mod search;
+pub mod sdp;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod smb2;
+pub mod snapshot;
+pub mod socks;
+pub mod session;
+pub mod sll;
+pub mod source;
+pub mod ssdp;
+pub mod stats;
+pub mod stp;
+pub mod summary;
+pub mod tap;
+pub mod timeshift;
+pub mod topology;
+pub mod triage;
+pub mod time;
+pub mod ttl;
+pub mod usb;
+pub mod visit;
+pub mod watch;
+pub mod zmtp;
+
+pub use diff::diff;
 
 #[cfg(test)]
 mod test {
@@ -594,7 +1083,7 @@ mod test {
         let mut payload = NamedValues::new();
 
         payload.push(("bar", Val::Unsigned(42)));
-        obj.push(("foo", Val::Payload(Ok(Box::new(Val::Object("test", payload))))));
+        obj.push(("foo", Val::Payload(&[], Ok(Box::new(Val::Object("test", payload))))));
 
         Val::Object("test", obj)
     }
@@ -604,7 +1093,7 @@ mod test {
         let mut payload = NamedValues::new();
 
         payload.push(("bar", Val::Unsigned(42)));
-        obj.push(("foo", Val::Payload(Err(DissectError::InvalidData("error".to_string())))));
+        obj.push(("foo", Val::Payload(&[], Err(DissectError::InvalidData("error".to_string())))));
 
         Val::Object("test", obj)
     }
@@ -619,13 +1108,29 @@ mod test {
         Val::Object("test", obj)
     }
 
+    #[test]
+    fn read_integers() {
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09];
+        assert_eq!(read_u8(&data, 8).unwrap(), 9);
+        assert_eq!(read_be_u16(&data, 1).unwrap(), 0x0203);
+        assert_eq!(read_le_u16(&data, 1).unwrap(), 0x0302);
+        assert_eq!(read_be_u32(&data, 5).unwrap(), 0x06070809);
+        assert_eq!(read_le_u64(&data, 0).unwrap(), 0x0807060504030201);
+
+        match read_be_u32(&data, 6) {
+            Err(DissectError::Underflow { expected: Some(10), have: 9, .. }) => {},
+            other => panic!["expected an underflow, got {:?}", other],
+        }
+        assert!(read_be_u64(&data, usize::max_value()).is_err());
+    }
+
     #[test]
     fn val_index() {
         assert_eq!(test_object()["foo"]["bar"], Val::Unsigned(42));
     }
 
     #[test]
-    #[should_panic(expected = "indexing error: access error: no value for index 'baz' found in: Object(\"test\", [(\"foo\", Payload(Ok(Object(\"test\", [(\"bar\", Unsigned(42))]))))])")]
+    #[should_panic(expected = "indexing error: access error: no value for index 'baz' found in: Object(\"test\", [(\"foo\", Payload([], Ok(Object(\"test\", [(\"bar\", Unsigned(42))]))))])")]
     fn val_index_not_found() {
         let _ = test_object()["baz"]["bar"];
     }
@@ -653,6 +1158,38 @@ mod test {
         assert_eq!(test_object().get("foo").unwrap().get("bar").unwrap(), &Val::Unsigned(42));
     }
 
+    #[test]
+    fn val_list() {
+        let list = Val::Object("test", vec![
+            ("answers", Val::List(vec![Val::Unsigned(1), test_object(), Val::Symbol("x")])),
+        ]);
+
+        assert_eq!(list.lookup("answers.1.foo.bar"), Some(&Val::Unsigned(42)));
+        assert_eq!(list["answers"].as_list().unwrap().len(), 3);
+        assert!(list.lookup("answers.3").is_none());
+        assert!(list.lookup("answers.x").is_none());
+        assert_eq!(list["answers"]["2"].to_string(), "x");
+        assert_eq!(Val::List(vec![Val::Unsigned(1), Val::Signed(-1)]).to_string(), "[1, -1]");
+    }
+
+    #[test]
+    fn typed_addresses() {
+        let bytes = [10, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+        let v4 = Val::ipv4(&bytes[..4]);
+        assert_eq!(v4.as_address_encoded(), Some("10.0.0.1"));
+        assert_eq!(v4.as_ipv4(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(v4.as_ipv6(), None);
+
+        let v6 = Val::ipv6(&bytes);
+        assert_eq!(v6.as_address_encoded(), Some("a00:1::1"));
+        assert_eq!(v6.as_ip().map(|ip| ip.is_ipv6()), Some(true));
+
+        let mac = Val::mac(&bytes[..6]);
+        assert_eq!(mac.as_mac().unwrap().to_string(), "0a:00:00:01:00:00");
+        assert_eq!(Val::Bytes(&bytes[..6]).as_mac(), None);
+    }
+
     #[test]
     fn val_get_not_found() {
         match test_object().get("baz").unwrap_err() {
@@ -677,6 +1214,18 @@ mod test {
         }
     }
 
+    #[test]
+    fn dissect_error_codes() {
+        let underflow = DissectError::Underflow { expected: Some(4), have: 2, message: "x".to_string() };
+        assert_eq!(underflow.code(), ErrorCode::Truncated);
+        assert_eq!(DissectError::InvalidData("x".to_string()).code(), ErrorCode::InvalidData);
+
+        let magic = DissectError::malformed(ErrorCode::BadMagic, "no magic");
+        assert_eq!(magic.code(), ErrorCode::BadMagic);
+        assert_eq!(magic.code().code(), 3);
+        assert_eq!(format!["{}", magic], "invalid data (bad_magic): no magic");
+    }
+
     #[test]
     fn val_get_path() {
         assert_eq!(test_object().get_path(&["foo", "bar"]).unwrap(), &Val::Unsigned(42));
@@ -692,6 +1241,21 @@ mod test {
         assert_eq!(test_object().lookup("foo.bar.baz"), None);
     }
 
+    #[test]
+    fn val_layer() {
+        let mut inner = NamedValues::new();
+        inner.push(("bar", Val::Unsigned(42)));
+
+        let mut outer = NamedValues::new();
+        outer.push(("foo", Val::Payload(&[], Ok(Box::new(Val::Object("inner", inner))))));
+        let obj = Val::Object("outer", outer);
+
+        assert_eq!(obj.layer("outer"), Some(&obj));
+        assert_eq!(obj.layer("inner").unwrap()["bar"], Val::Unsigned(42));
+        assert_eq!(obj.layer("IPv4"), None);
+        assert_eq!(test_object_err_payload().layer("inner"), None);
+    }
+
     #[test]
     fn flags_access_by_bit_no() {
         let ref flags = flags_test_object()["flags"];
@@ -712,6 +1276,21 @@ mod test {
         assert_eq!(flags.as_bitflags8_bit_no(9), Some(false));
     }
 
+    #[test]
+    fn wide_flags() {
+        static NAMES: [Option<&'static str>; 9] = [Some("FIN"), Some("SYN"), None, None,
+                                                   Some("ACK"), None, None, None, Some("NS")];
+        let flags = Val::BitFlags(0x112, &NAMES);
+
+        assert_eq!(flags.as_bitflags_bit_name("NS"), Some(true));
+        assert_eq!(flags.as_bitflags_bit_name("FIN"), Some(false));
+        assert_eq!(flags.as_bitflags_bit_no(9), None);
+        assert_eq!(flags.to_string(), "100010010 (SYN+ACK+NS)");
+
+        let ref flags = flags_test_object()["flags"];
+        assert_eq!(flags.as_bitflags_bit_name("bar"), Some(true));
+    }
+
     #[test]
     fn flags_access_by_bit_name() {
         let ref flags = flags_test_object()["flags"];
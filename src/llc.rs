@@ -0,0 +1,147 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of IEEE 802.2 Logical Link Control (LLC) headers and their
+//! Subnetwork Access Protocol (SNAP) extension.
+//!
+//! LLC follows the header of 802.3 frames that carry a length rather than an
+//! Ethertype (and of 802.11 frames). Its service access points (SAPs) name a
+//! handful of protocols directly, e.g., Spanning Tree; SNAP (SAP 0xaa) adds
+//! an organization (OUI) and protocol ID, which for OUI 00-00-00 is an
+//! Ethertype.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use context::Context;
+use ethernet;
+use read_be_u16;
+use stp;
+
+enum_map!(pub SAPS {
+    0x00 => "Null",
+    0x06 => "IP",
+    0x42 => "Spanning Tree",
+    0xaa => "SNAP",
+    0xe0 => "IPX",
+    0xf0 => "NetBIOS",
+    0xfe => "ISO Network Layer",
+    0xff => "Global",
+});
+
+enum_map!(pub ORGANIZATIONS {
+    0x000000 => "Encapsulated Ethernet",
+    0x00000c => "Cisco",
+    0x0000f8 => "Bridge Tunnel",
+    0x0080c2 => "IEEE 802.1",
+});
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect an LLC header, and any SNAP payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 3 {
+        return Err(DissectError::Underflow { expected: Some(3), have: data.len(),
+            message: "An LLC header must be at least 3 B".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+
+    // The low bits of the SAPs are the individual/group and command/response bits.
+    let dsap = data[0];
+    let ssap = data[1];
+    values.push(("DSAP", Val::Unsigned(dsap as u64)));
+    values.push(("DSAP Name", SAPS.val(dsap & 0xfe)));
+    values.push(("SSAP", Val::Unsigned(ssap as u64)));
+    values.push(("SSAP Name", SAPS.val(ssap & 0xfe)));
+
+    // Unnumbered (U) frames have a one-byte control field, information (I)
+    // and supervisory (S) frames a two-byte one.
+    let (control, header) = if data[2] & 0x03 == 0x03 {
+        (data[2] as u16, 3)
+    } else {
+        if data.len() < 4 {
+            return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+                message: "An LLC I or S frame header must be 4 B".to_string() });
+        }
+        (read_be_u16(data, 2)?, 4)
+    };
+    values.push(("Control", Val::Unsigned(control as u64)));
+    values.push(("Frame Type", Val::Symbol(match control & 0x03 {
+        0x03 => "Unnumbered",
+        0x01 => "Supervisory",
+        _ => "Information",
+    })));
+
+    let remainder = &data[header..];
+
+    if dsap == 0xaa && ssap == 0xaa {
+        if remainder.len() < 5 {
+            return Err(DissectError::Underflow { expected: Some(header + 5), have: data.len(),
+                message: "A SNAP header must be 5 B".to_string() });
+        }
+
+        let oui = (remainder[0] as u32) << 16 | (remainder[1] as u32) << 8 | remainder[2] as u32;
+        let pid = read_be_u16(remainder, 3)?;
+        values.push(("Organization Code", Val::Unsigned(oui as u64)));
+        values.push(("Organization Name", ORGANIZATIONS.val(oui)));
+        values.push(("Protocol ID", Val::Unsigned(pid as u64)));
+
+        let payload = &remainder[5..];
+        values.push(("Payload", match (oui, pid) {
+            (0x000000, ethertype) | (0x0000f8, ethertype) =>
+                ethernet::dissect_ethertype_with(ethertype, payload, context),
+            (0x00000c, 0x2000) => Val::Undissected("CDP", payload),
+            (0x00000c, 0x2003) => Val::Undissected("VTP", payload),
+            (0x00000c, 0x010b) => Val::Payload(payload, stp::dissect(payload)),
+            _ => Val::Undissected("SNAP", payload),
+        }));
+    } else {
+        values.push(("Payload", match dsap & 0xfe {
+            0x42 => Val::Payload(remainder, stp::dissect(remainder)),
+            0xe0 => Val::Undissected("IPX", remainder),
+            0xf0 => Val::Undissected("NetBIOS", remainder),
+            _ => Val::Undissected("LLC", remainder),
+        }));
+    }
+
+    Ok(Box::new(Val::Object("LLC", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    #[test]
+    fn dissect_llc() {
+        // An 802.1D TCN BPDU, padded to the minimum frame length
+        let data = [0x01, 0x80, 0xc2, 0, 0, 0, 0x02, 0, 0, 0, 0, 1, 0x00, 0x07,
+                    0x42, 0x42, 0x03, 0x00, 0x00, 0x00, 0x80,
+                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        let val = *ethernet::dissect(&data).unwrap();
+        assert_eq!(val["Payload"]["DSAP Name"].as_symbol().unwrap(), "Spanning Tree");
+        assert_eq!(val["Payload"]["Frame Type"].as_symbol().unwrap(), "Unnumbered");
+        assert_eq!(val["Payload"]["Payload"]["BPDU Type Name"].as_symbol().unwrap(),
+                   "Topology Change Notification");
+
+        // SNAP-encapsulated IPv4
+        let snap = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00,
+                    0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        let val = *dissect(&snap).unwrap();
+        assert_eq!(val["Protocol ID"].as_unsigned().unwrap(), 0x0800);
+        assert_eq!(val["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        assert!(dissect(&snap[..5]).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of BSD loopback encapsulation (DLT_NULL and DLT_LOOP), used on
+//! macOS and BSD loopback interfaces and some VPN interfaces (e.g., `utun`).
+//!
+//! Packets start with a 4-byte address family. DLT_NULL gives it in the byte
+//! order of the machine that captured the packet, which we guess from which
+//! half of the word is non-zero; DLT_LOOP always gives it in network order.
+//! The value of AF_INET6 varies between systems.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use context::Context;
+use ip;
+use {read_be_u32, read_le_u32};
+
+// Address families (AF_) of all of the systems that use this encapsulation
+enum_map!(pub FAMILIES {
+    2 => "IPv4",
+    7 => "OSI",
+    23 => "IPX",
+    24 => "IPv6",
+    28 => "IPv6",
+    30 => "IPv6",
+});
+
+/// Dissect a DLT_NULL packet, whose family is in host byte order.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a DLT_NULL packet, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    let mut family = header(data, read_le_u32)?;
+    if family & 0xffff_0000 != 0 {
+        family = family.swap_bytes();
+    }
+
+    encapsulated(family, data, context)
+}
+
+/// Dissect a DLT_LOOP packet, whose family is in network byte order.
+pub fn dissect_loop(data : &[u8]) -> DissectResult {
+    dissect_loop_with(data, &mut Context::new())
+}
+
+/// Dissect a DLT_LOOP packet, and its payload within a context.
+pub fn dissect_loop_with<'data>(data: &'data [u8], context: &mut Context)
+    -> DissectResult<'data> {
+
+    let family = header(data, read_be_u32)?;
+    encapsulated(family, data, context)
+}
+
+fn header(data: &[u8], read: fn(&[u8], usize) -> Result<u32, DissectError>)
+    -> Result<u32, DissectError> {
+
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "A loopback header must be 4 B".to_string() });
+    }
+
+    read(data, 0)
+}
+
+fn encapsulated<'data>(family: u32, data: &'data [u8], context: &mut Context)
+    -> DissectResult<'data> {
+
+    let mut values = NamedValues::new();
+    values.push(("Family", FAMILIES.val(family)));
+
+    let payload = &data[4..];
+    values.push(("Payload", match family {
+        2 | 24 | 28 | 30 => Val::Payload(payload, ip::dissect_raw_with(payload, context)),
+        _ => Val::Undissected("Unknown", payload),
+    }));
+
+    Ok(Box::new(Val::Object("Null/Loopback", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_families() {
+        let ip = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 127, 0, 0, 1, 127, 0, 0, 2];
+
+        // Little- and big-endian captures of AF_INET
+        for header in &[[2, 0, 0, 0], [0, 0, 0, 2]] {
+            let mut data = header.to_vec();
+            data.extend_from_slice(&ip);
+
+            let val = *dissect(&data).unwrap();
+            assert_eq!(val["Family"].as_symbol().unwrap(), "IPv4");
+            assert_eq!(val["Payload"]["Destination"].as_address_encoded().unwrap(), "127.0.0.2");
+        }
+
+        let val = *dissect_loop(&[0, 0, 0, 23, 0xff, 0xff]).unwrap();
+        assert_eq!(val["Family"].as_symbol().unwrap(), "IPX");
+        assert!(dissect_loop(&[0, 0, 2]).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! IEEE 802 MAC (EUI-48) addresses.
+//!
+//! The standard library has types for IP addresses but not for hardware
+//! addresses; `MacAddr` fills the gap for `Val::as_mac`. With the `oui`
+//! feature, `MacAddr::vendor` names the vendor of the common OUIs (address
+//! prefixes) from a small embedded table; otherwise it knows none.
+//!
+//! ```
+//! use rshark::mac::MacAddr;
+//!
+//! let mac: MacAddr = "a0:0b:ba:84:2d:0e".parse().unwrap();
+//! assert_eq!(mac.octets(), [0xa0, 0x0b, 0xba, 0x84, 0x2d, 0x0e]);
+//! assert_eq!(mac.to_string(), "a0:0b:ba:84:2d:0e");
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    pub fn new(octets: [u8; 6]) -> MacAddr {
+        MacAddr(octets)
+    }
+
+    /// The address in the first six bytes of a slice, if it has them.
+    pub fn from_bytes(bytes: &[u8]) -> Option<MacAddr> {
+        if bytes.len() < 6 {
+            return None;
+        }
+
+        let mut octets = [0; 6];
+        octets.copy_from_slice(&bytes[..6]);
+        Some(MacAddr(octets))
+    }
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// The Organizationally Unique Identifier: the first three bytes.
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.0 == [0xff; 6]
+    }
+
+    /// Whether the address is a group (multicast or broadcast) address.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    /// Whether the address was assigned locally rather than by its vendor.
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+
+    /// The organization that the address's OUI is assigned to, if it is
+    /// known. Group addresses are looked up by their OUI, e.g., IPv4
+    /// multicast addresses (01:00:5e:...) belong to the IANA.
+    pub fn vendor(&self) -> Option<&'static str> {
+        if self.is_local() {
+            return None;
+        }
+
+        let oui = [self.0[0] & !0x01, self.0[1], self.0[2]];
+        VENDORS.binary_search_by_key(&oui, |&(o, _)| o).ok().map(|i| VENDORS[i].1)
+    }
+}
+
+/// OUIs and their (abbreviated) vendors, sorted by OUI.
+#[cfg(feature = "oui")]
+static VENDORS: &'static [([u8; 3], &'static str)] = &[
+    ([0x00, 0x00, 0x0c], "Cisco"),
+    ([0x00, 0x00, 0x5e], "IANA"),
+    ([0x00, 0x01, 0x42], "Cisco"),
+    ([0x00, 0x03, 0x93], "Apple"),
+    ([0x00, 0x03, 0xff], "Microsoft"),
+    ([0x00, 0x04, 0x96], "Extreme Networks"),
+    ([0x00, 0x05, 0x5d], "D-Link"),
+    ([0x00, 0x05, 0x69], "VMware"),
+    ([0x00, 0x05, 0x85], "Juniper"),
+    ([0x00, 0x08, 0x9b], "QNAP"),
+    ([0x00, 0x09, 0x0f], "Fortinet"),
+    ([0x00, 0x0a, 0x95], "Apple"),
+    ([0x00, 0x0a, 0xf7], "Broadcom"),
+    ([0x00, 0x0b, 0x86], "Aruba"),
+    ([0x00, 0x0c, 0x29], "VMware"),
+    ([0x00, 0x0c, 0x42], "MikroTik"),
+    ([0x00, 0x0d, 0xb9], "PC Engines"),
+    ([0x00, 0x0f, 0xac], "IEEE 802.11"),
+    ([0x00, 0x10, 0x18], "Broadcom"),
+    ([0x00, 0x10, 0xdb], "Juniper"),
+    ([0x00, 0x11, 0x32], "Synology"),
+    ([0x00, 0x14, 0x22], "Dell"),
+    ([0x00, 0x14, 0x4f], "Oracle"),
+    ([0x00, 0x14, 0x6c], "Netgear"),
+    ([0x00, 0x15, 0x5d], "Microsoft"),
+    ([0x00, 0x16, 0x3e], "Xensource"),
+    ([0x00, 0x17, 0x88], "Philips Lighting"),
+    ([0x00, 0x18, 0x0a], "Cisco Meraki"),
+    ([0x00, 0x1a, 0x11], "Google"),
+    ([0x00, 0x1a, 0x1e], "Aruba"),
+    ([0x00, 0x1a, 0xa0], "Dell"),
+    ([0x00, 0x1b, 0x17], "Palo Alto Networks"),
+    ([0x00, 0x1b, 0x21], "Intel"),
+    ([0x00, 0x1b, 0x2f], "Netgear"),
+    ([0x00, 0x1b, 0x54], "Cisco"),
+    ([0x00, 0x1b, 0x63], "Apple"),
+    ([0x00, 0x1b, 0x78], "HP"),
+    ([0x00, 0x1c, 0x14], "VMware"),
+    ([0x00, 0x1c, 0x42], "Parallels"),
+    ([0x00, 0x1d, 0x0f], "TP-Link"),
+    ([0x00, 0x1e, 0x67], "Intel"),
+    ([0x00, 0x1e, 0xc9], "Dell"),
+    ([0x00, 0x1f, 0x12], "Juniper"),
+    ([0x00, 0x21, 0x28], "Oracle"),
+    ([0x00, 0x21, 0x5a], "HP"),
+    ([0x00, 0x25, 0x00], "Apple"),
+    ([0x00, 0x25, 0xb5], "Cisco"),
+    ([0x00, 0x26, 0x5a], "D-Link"),
+    ([0x00, 0x26, 0xbb], "Apple"),
+    ([0x00, 0x27, 0x22], "Ubiquiti"),
+    ([0x00, 0x50, 0x56], "VMware"),
+    ([0x00, 0x50, 0xf2], "Microsoft"),
+    ([0x00, 0x60, 0x2f], "Cisco"),
+    ([0x00, 0x80, 0xc2], "IEEE 802.1"),
+    ([0x00, 0xe0, 0x4c], "Realtek"),
+    ([0x04, 0x18, 0xd6], "Ubiquiti"),
+    ([0x08, 0x00, 0x20], "Oracle"),
+    ([0x08, 0x00, 0x27], "PCS Systemtechnik"),
+    ([0x18, 0xb4, 0x30], "Nest Labs"),
+    ([0x24, 0xa4, 0x3c], "Ubiquiti"),
+    ([0x3c, 0x07, 0x54], "Apple"),
+    ([0x3c, 0x5a, 0xb4], "Google"),
+    ([0x3c, 0xd9, 0x2b], "HP"),
+    ([0x3c, 0xfd, 0xfe], "Intel"),
+    ([0x44, 0x65, 0x0d], "Amazon"),
+    ([0x4c, 0x5e, 0x0c], "MikroTik"),
+    ([0x50, 0xc7, 0xbf], "TP-Link"),
+    ([0xa4, 0x5e, 0x60], "Apple"),
+    ([0xb8, 0x27, 0xeb], "Raspberry Pi"),
+    ([0xdc, 0xa6, 0x32], "Raspberry Pi"),
+    ([0xe4, 0x5f, 0x01], "Raspberry Pi"),
+    ([0xf0, 0x18, 0x98], "Apple"),
+    ([0xf4, 0xf5, 0xd8], "Google"),
+    ([0xf8, 0xbc, 0x12], "Dell"),
+];
+
+#[cfg(not(feature = "oui"))]
+static VENDORS: &'static [([u8; 3], &'static str)] = &[];
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> MacAddr {
+        MacAddr(octets)
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write![f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", b[0], b[1], b[2], b[3], b[4], b[5]]
+    }
+}
+
+/// Parses six hexadecimal bytes separated by ':' or '-'.
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MacAddr, String> {
+        let parts = s.split(|c| c == ':' || c == '-').collect::<Vec<_>>();
+        if parts.len() != 6 {
+            return Err(format!["'{}' is not a MAC address", s]);
+        }
+
+        let mut octets = [0; 6];
+        for (octet, part) in octets.iter_mut().zip(parts) {
+            *octet = u8::from_str_radix(part, 16)
+                .map_err(|e| format!["bad byte '{}' in MAC address: {}", part, e])?;
+        }
+
+        Ok(MacAddr(octets))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_macs() {
+        let mac = "01-00-5E-00-00-FB".parse::<MacAddr>().unwrap();
+        assert_eq!(mac.to_string(), "01:00:5e:00:00:fb");
+        assert!(mac.is_multicast() && !mac.is_broadcast() && !mac.is_local());
+        assert_eq!(mac.oui(), [0x01, 0x00, 0x5e]);
+
+        assert!("01:02:03:04:05".parse::<MacAddr>().is_err());
+        assert!("01:02:03:04:05:zz".parse::<MacAddr>().is_err());
+        assert_eq!(MacAddr::from_bytes(&[0; 5]), None);
+    }
+
+    #[test]
+    #[cfg(feature = "oui")]
+    fn lookup_vendors() {
+        assert!(VENDORS.windows(2).all(|w| w[0].0 < w[1].0));
+
+        let mac = MacAddr::new([0x00, 0x0c, 0x29, 0x12, 0x34, 0x56]);
+        assert_eq!(mac.vendor(), Some("VMware"));
+        assert_eq!("01:00:5e:00:00:fb".parse::<MacAddr>().unwrap().vendor(), Some("IANA"));
+        assert_eq!("02:0c:29:12:34:56".parse::<MacAddr>().unwrap().vendor(), None);
+    }
+}
@@ -13,19 +13,29 @@ extern crate rustc_serialize;
 extern crate pcap;
 
 use docopt::Docopt;
+use rshark::output::text::{render, RenderOptions, Style};
+use rshark::pipeline::{DropPolicy, Pipeline};
+use rshark::source::{Packet, PacketSource, SourceStats};
+use rshark::watch::{parse_interval, Watch};
+use std::time::Duration;
 
 
 // TODO: use docopt_macros once rust-lang/rust#28089 is resolved
 const USAGE: &'static str = "
 Usage: rshark [options] <source>
+       rshark watch [options] -i <source>
        rshark (--help | --version)
 
 Options:
-    -f, --filter         BFP filter (see http://biot.com/capstats/bpf.html)
+    -f, --filter=<expr>  BFP filter (see http://biot.com/capstats/bpf.html) [default: ]
     -h, --help           Show this message
+    -i, --interface      Watch a live interface (or file) rather than dissecting
     -p, --promiscuous    Listen to all packets
     -s, --snaplen=<len>  Bytes to capture from each packet [default: 5000]
+    --stats=<interval>   Interval between watch counters, e.g., 1s or 500ms [default: 1s]
+    --style=<style>      Output style: compact, tree or verbose [default: tree]
     -t, --timeout=<ms>   Packet read timeout, in ms [default: 10]
+    --top=<n>            Top talkers to list in watch counters [default: 3]
     -v, --version        Show the version of rshark
 ";
 
@@ -34,10 +44,15 @@ const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
 #[derive(RustcDecodable)]
 struct Args {
+    cmd_watch: bool,
     arg_source: String,
     flag_filter: String,
+    flag_interface: bool,
     flag_snaplen: i32,
+    flag_stats: String,
+    flag_style: String,
     flag_timeout: i32,
+    flag_top: usize,
     flag_promiscuous: bool,
     flag_version: bool,
 }
@@ -56,22 +71,40 @@ fn main() {
         return;
     }
 
+    if args.cmd_watch {
+        return watch(&args);
+    }
+
+    let style = args.flag_style.parse::<Style>().unwrap_or_else(|e| {
+        println!["{}", e];
+        std::process::exit(1);
+    });
+    let options = RenderOptions::new(style);
+
     let result = open_capture(&args)
-        .map(|mut c| {
-            let mut count = 0;
+        .map(|c| {
+            let mut source = LiveSource { capture: c, stats: SourceStats::default() };
+            let pipeline = Pipeline::new(4096).policy(DropPolicy::DropOldest);
 
-            while let Some(packet) = c.next() {
+            // Tunnel interfaces (tun, WireGuard...) capture bare IP packets.
+            let dissector = rshark::preset::select(Some(&args.arg_source), source.link_type())
+                .map(|p| p.dissector)
+                .unwrap_or(rshark::ethernet::dissect);
+
+            let stats = pipeline.run(&mut source, |packet| {
                 println!("received {}-B packet:", packet.data.len());
 
-                match rshark::ethernet::dissect(packet.data) {
-                    Ok(dissected) => print!["{}", dissected.pretty_print(1)],
+                match dissector(&packet.data) {
+                    Ok(dissected) => print!["{}", render(&dissected, &options)],
                     Err(e) => println!["Error: {}", e],
                 }
+            });
 
-                count += 1;
+            if stats.dropped() > 0 {
+                println!["Dropped {} packets (dissection could not keep up)", stats.dropped()];
             }
 
-            count
+            stats.processed
         })
         ;
 
@@ -86,6 +119,74 @@ fn main() {
 }
 
 
+/// Print counters of the packets matching the filter, once per interval,
+/// until the capture ends (or forever, for a live interface).
+fn watch(args: &Args) {
+    let interval = parse_interval(&args.flag_stats).unwrap_or_else(|e| {
+        println!["{}", e];
+        std::process::exit(1);
+    });
+
+    // The filter has already been applied by libpcap.
+    let result = open_capture(args).map(|c| {
+        let mut source = LiveSource { capture: c, stats: SourceStats::default() };
+        let dissector = rshark::preset::select(Some(&args.arg_source), source.link_type())
+            .map(|p| p.dissector)
+            .unwrap_or(rshark::ethernet::dissect);
+
+        let mut watch = Watch::new(interval).talkers(dissector, args.flag_top);
+        println!["{:>12} {:>13} {:>12} {:>16} {:>18}  top talkers",
+                 "time", "packets", "bytes", "rate", "throughput"];
+
+        while let Some(Ok(packet)) = source.next_packet() {
+            if let Some(summary) = watch.observe(&packet) {
+                println!["{}", summary];
+            }
+        }
+
+        if let Some(summary) = watch.finish() {
+            println!["{}", summary];
+        }
+    });
+
+    if let Err(e) = result {
+        println!["{}", e];
+        std::process::exit(1);
+    }
+}
+
+
+/// A libpcap capture (of a live interface or a file) as a `PacketSource`.
+struct LiveSource {
+    capture: pcap::Capture<pcap::Activated>,
+    stats: SourceStats,
+}
+
+impl PacketSource for LiveSource {
+    fn next_packet(&mut self) -> Option<std::io::Result<Packet>> {
+        let packet = self.capture.next().map(|p| Packet {
+            timestamp: Duration::new(p.header.ts.tv_sec as u64, p.header.ts.tv_usec as u32 * 1000),
+            original_length: p.header.len as usize,
+            data: p.data.to_vec(),
+        });
+
+        if let Some(ref p) = packet {
+            self.stats.count(p);
+        }
+
+        packet.map(Ok)
+    }
+
+    fn link_type(&self) -> u32 {
+        self.capture.get_datalink().0 as u32
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.stats.clone()
+    }
+}
+
+
 fn open_capture(args: &Args) -> PcapResult {
     let device = try![open_device(args)];
 
@@ -0,0 +1,118 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Multi-Protocol Label Switching (MPLS) label stacks
+//! (ethertypes 0x8847 and 0x8848).
+//!
+//! MPLS doesn't say what it carries, so the payload under the bottom of
+//! the stack is identified heuristically: explicit-null labels and the
+//! first nibble of an IP header identify IPv4 and IPv6, and anything else
+//! is taken to be an Ethernet pseudowire (with or without a control word).
+//!
+//! See [RFC 3032](https://tools.ietf.org/html/rfc3032) and
+//! [RFC 4385](https://tools.ietf.org/html/rfc4385).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use context::Context;
+use ethernet;
+use ip;
+use read_be_u32;
+
+enum_map!(pub RESERVED_LABELS {
+    0 => "IPv4 Explicit NULL",
+    1 => "Router Alert",
+    2 => "IPv6 Explicit NULL",
+    3 => "Implicit NULL",
+    7 => "Entropy Label Indicator",
+    13 => "Generic Associated Channel",
+    14 => "OAM Alert",
+    15 => "Extension",
+});
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a label stack, and what's under it within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    let mut values = NamedValues::new();
+    let mut offset = 0;
+
+    loop {
+        if data.len() < offset + 4 {
+            return Err(DissectError::Underflow { expected: Some(offset + 4), have: data.len(),
+                message: "MPLS label stack greater than available data".to_string() });
+        }
+
+        let entry = read_be_u32(data, offset)?;
+        let label = entry >> 12;
+        let bottom = entry & 0x100 != 0;
+
+        let mut fields = NamedValues::new();
+        fields.push(("Label", Val::Unsigned(label as u64)));
+        if label < 16 {
+            fields.push(("Label Name", RESERVED_LABELS.val(label)));
+        }
+        fields.push(("Traffic Class", Val::Unsigned(((entry >> 9) & 0x07) as u64)));
+        fields.push(("Bottom of Stack", Val::Unsigned(bottom as u64)));
+        fields.push(("TTL", Val::Unsigned((entry & 0xff) as u64)));
+        values.push(("Label Stack Entry", Val::Object("MPLS Label", fields)));
+
+        offset += 4;
+        if bottom {
+            let rest = &data[offset..];
+            values.push(("Payload", Val::Payload(rest, payload(label, rest, context))));
+            return Ok(Box::new(Val::Object("MPLS", values)));
+        }
+    }
+}
+
+/// Dissect whatever is under the bottom of the label stack.
+fn payload<'data>(label: u32, data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    match (label, data.first().map(|b| b >> 4)) {
+        (0, _) | (_, Some(4)) => context.dissect(ip::dissect_with, data),
+        (2, _) | (_, Some(6)) => context.dissect(ip::ipv6::dissect_with, data),
+
+        // A pseudowire control word starts with a zero nibble.
+        (_, Some(0)) if data.len() >= 4 => context.dissect(ethernet::dissect_with, &data[4..]),
+        _ => context.dissect(ethernet::dissect_with, data),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INNER: [u8; 20] = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+
+    #[test]
+    fn dissect_mpls() {
+        // Labels 16000 and 17 over IPv4
+        let mut data = vec![0x03, 0xe8, 0x00, 0x40, 0x00, 0x01, 0x11, 0x3f];
+        data.extend_from_slice(&INNER);
+        let val = *dissect(&data).unwrap();
+
+        assert_eq!(val["Label Stack Entry"]["Label"].as_unsigned().unwrap(), 16000);
+        assert_eq!(val["Label Stack Entry"]["Bottom of Stack"].as_unsigned().unwrap(), 0);
+        assert_eq!(val["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        // An Ethernet pseudowire with a control word
+        let mut data = vec![0x00, 0x02, 0x01, 0x40, 0, 0, 0, 0, 0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00];
+        data.extend_from_slice(&INNER);
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Label Stack Entry"]["Label"].as_unsigned().unwrap(), 32);
+        assert_eq!(val["Payload"]["Payload"]["Source"].as_address_encoded().unwrap(), "10.0.0.1");
+
+        assert!(dissect(&[0x00, 0x00, 0x01, 0x40]).unwrap()["Payload"].as_payload().unwrap().is_err());
+        assert!(dissect(&[0x00, 0x01, 0x10, 0x40]).is_err());
+    }
+}
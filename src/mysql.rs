@@ -0,0 +1,453 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the MySQL client/server protocol on TCP port 3306.
+//!
+//! Every MySQL packet has a 3 B little-endian payload length and a 1 B
+//! sequence number. What the payload holds depends on the direction and on
+//! the state of the connection, which isn't tracked here: packets are
+//! classified by their sequence numbers and leading octets instead. A
+//! segment holding several packets (e.g., a whole result set) is
+//! represented as a chain of "Next Packet" payloads.
+//!
+//! See the [MySQL client/server protocol](https://dev.mysql.com/doc/dev/mysql-server/latest/PAGE_PROTOCOL.html).
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use Val;
+use NamedValues;
+
+/// Capability flags that change the layout of the handshake response.
+const CLIENT_CONNECT_WITH_DB: u64 = 0x8;
+const CLIENT_PROTOCOL_41: u64 = 0x200;
+const CLIENT_SECURE_CONNECTION: u64 = 0x8000;
+const CLIENT_PLUGIN_AUTH: u64 = 0x80000;
+const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u64 = 0x200000;
+
+/// Dissect packets sent from a client to the server.
+pub fn dissect_request(data : &[u8]) -> DissectResult {
+    let (payload, mut values) = header(data)?;
+    let sequence = data[3];
+
+    if sequence == 1 && payload.len() >= 32 && payload[9..32].iter().all(|&b| b == 0) {
+        handshake_response(payload, &mut values)?;
+    } else if sequence == 0 && !payload.is_empty() {
+        command(payload, &mut values);
+    } else {
+        values.push(("Packet Type", Val::Symbol("Authentication Data")));
+        values.push(("Data", Val::Bytes(payload)));
+    }
+
+    next(data, payload, &mut values, dissect_request);
+    Ok(Box::new(Val::Object("MySQL", values)))
+}
+
+/// Dissect packets sent from the server to a client.
+pub fn dissect_response(data : &[u8]) -> DissectResult {
+    let (payload, mut values) = header(data)?;
+    let sequence = data[3];
+
+    match payload.first() {
+        Some(&0x0a) if sequence == 0 => handshake(payload, &mut values)?,
+        Some(&0x00) if payload.len() >= 7 => ok(payload, &mut values)?,
+        Some(&0xfe) if payload.len() < 9 => {
+            values.push(("Packet Type", Val::Symbol("EOF")));
+            values.push(("Warnings", Val::Unsigned(le(payload, 1, 2)?)));
+            values.push(("Status Flags", Val::Unsigned(le(payload, 3, 2)?)));
+        },
+        Some(&0xfe) => {
+            values.push(("Packet Type", Val::Symbol("Auth Switch Request")));
+            let (plugin, offset) = null_string(payload, 1)?;
+            values.push(("Auth Plugin", Val::String(plugin)));
+            values.push(("Auth Plugin Data", Val::Bytes(&payload[offset..])));
+        },
+        Some(&0xff) => error(payload, &mut values)?,
+        Some(_) => result_set(payload, sequence, &mut values)?,
+        None => {},
+    }
+
+    next(data, payload, &mut values, dissect_response);
+    Ok(Box::new(Val::Object("MySQL", values)))
+}
+
+/// The name of a command (the first octet of a command packet).
+pub fn command_name(command: u8) -> &'static str {
+    match command {
+        0x01 => "Quit",
+        0x02 => "Init DB",
+        0x03 => "Query",
+        0x04 => "Field List",
+        0x05 => "Create DB",
+        0x06 => "Drop DB",
+        0x07 => "Refresh",
+        0x09 => "Statistics",
+        0x0a => "Process Info",
+        0x0c => "Process Kill",
+        0x0d => "Debug",
+        0x0e => "Ping",
+        0x11 => "Change User",
+        0x12 => "Binlog Dump",
+        0x16 => "Stmt Prepare",
+        0x17 => "Stmt Execute",
+        0x18 => "Stmt Send Long Data",
+        0x19 => "Stmt Close",
+        0x1a => "Stmt Reset",
+        0x1b => "Set Option",
+        0x1c => "Stmt Fetch",
+        0x1f => "Reset Connection",
+        _ => "Unknown",
+    }
+}
+
+/// The packet length and sequence number common to all packets.
+fn header(data: &[u8]) -> Result<(&[u8], NamedValues), DissectError> {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "A MySQL packet must have a 4 B header".to_string() })
+    }
+
+    let length = le(data, 0, 3)? as usize;
+    let payload = slice(data, 4, length)?;
+
+    let mut values = NamedValues::new();
+    values.push(("Length", Val::Unsigned(length as u64)));
+    values.push(("Sequence", Val::Unsigned(data[3] as u64)));
+
+    Ok((payload, values))
+}
+
+/// Chain any packets following this one in the same segment.
+fn next<'data>(data: &'data [u8], payload: &[u8], values: &mut NamedValues<'data>,
+               dissector: fn(&'data [u8]) -> DissectResult<'data>) {
+    let rest = &data[4 + payload.len()..];
+    if !rest.is_empty() {
+        values.push(("Next Packet", Val::Payload(rest, dissector(rest))));
+    }
+}
+
+/// The server's initial handshake (protocol version 10).
+fn handshake<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    values.push(("Packet Type", Val::Symbol("Handshake")));
+    values.push(("Protocol Version", Val::Unsigned(data[0] as u64)));
+
+    let (version, offset) = null_string(data, 1)?;
+    values.push(("Server Version", Val::String(version)));
+    values.push(("Connection ID", Val::Unsigned(le(data, offset, 4)?)));
+
+    let mut salt = slice(data, offset + 4, 8)?.to_vec();
+
+    let capabilities = le(data, offset + 13, 2)?;
+    values.push(("Character Set", Val::Unsigned(le(data, offset + 15, 1)?)));
+    values.push(("Status Flags", Val::Unsigned(le(data, offset + 16, 2)?)));
+
+    // Everything after the lower capability flags is optional.
+    if data.len() > offset + 18 {
+        let capabilities = capabilities | le(data, offset + 18, 2)? << 16;
+        values.push(("Capabilities", Val::Unsigned(capabilities)));
+
+        let salt_length = le(data, offset + 20, 1)? as usize;
+        let mut offset = offset + 31;
+
+        if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+            let length = if salt_length > 8 { salt_length - 8 } else { 13 };
+            let part = slice(data, offset, length)?;
+            salt.extend(part.iter().take_while(|&&b| b != 0));
+            offset += length;
+        }
+
+        if capabilities & CLIENT_PLUGIN_AUTH != 0 {
+            let (plugin, _) = null_string(data, offset)
+                .unwrap_or((String::from_utf8_lossy(&data[offset..]).into_owned(), data.len()));
+            values.push(("Auth Plugin", Val::String(plugin)));
+        }
+    } else {
+        values.push(("Capabilities", Val::Unsigned(capabilities)));
+    }
+
+    values.push(("Auth Plugin Data Length", Val::Unsigned(salt.len() as u64)));
+    Ok(())
+}
+
+/// The client's reply to the handshake (or a request to switch to TLS).
+fn handshake_response<'data>(data: &'data [u8], values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    let capabilities = le(data, 0, 4)?;
+    if capabilities & CLIENT_PROTOCOL_41 == 0 {
+        return Err(DissectError::malformed(ErrorCode::UnsupportedVersion,
+            "MySQL handshake responses before protocol 4.1 are not supported"));
+    }
+
+    if data.len() == 32 {
+        values.push(("Packet Type", Val::Symbol("SSL Request")));
+    } else {
+        values.push(("Packet Type", Val::Symbol("Handshake Response")));
+    }
+
+    values.push(("Capabilities", Val::Unsigned(capabilities)));
+    values.push(("Max Packet Size", Val::Unsigned(le(data, 4, 4)?)));
+    values.push(("Character Set", Val::Unsigned(le(data, 8, 1)?)));
+
+    if data.len() == 32 {
+        return Ok(());
+    }
+
+    let (user, mut offset) = null_string(data, 32)?;
+    values.push(("Username", Val::String(user)));
+
+    let (length, next) = if capabilities & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0 {
+        let (length, next) = lenenc(data, offset)?;
+        (length.unwrap_or(0) as usize, next)
+    } else if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+        (le(data, offset, 1)? as usize, offset + 1)
+    } else {
+        let end = data[offset..].iter().position(|&b| b == 0).unwrap_or(data.len() - offset);
+        (end, offset)
+    };
+    values.push(("Auth Response", Val::Bytes(slice(data, next, length)?)));
+    offset = next + length;
+    if capabilities & (CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA | CLIENT_SECURE_CONNECTION) == 0 {
+        offset += 1;
+    }
+
+    if capabilities & CLIENT_CONNECT_WITH_DB != 0 && offset < data.len() {
+        let (database, next) = null_string(data, offset)?;
+        values.push(("Database", Val::String(database)));
+        offset = next;
+    }
+
+    if capabilities & CLIENT_PLUGIN_AUTH != 0 && offset < data.len() {
+        let (plugin, _) = null_string(data, offset)?;
+        values.push(("Auth Plugin", Val::String(plugin)));
+    }
+
+    Ok(())
+}
+
+/// A command from the client, e.g., COM_QUERY.
+fn command<'data>(data: &'data [u8], values: &mut NamedValues<'data>) {
+    let command = data[0];
+    let argument = &data[1..];
+
+    values.push(("Packet Type", Val::Symbol("Command")));
+    values.push(("Command", Val::Unsigned(command as u64)));
+    values.push(("Command Name", Val::Symbol(command_name(command))));
+
+    match command {
+        0x02 => values.push(("Schema", Val::String(String::from_utf8_lossy(argument).into_owned()))),
+        0x03 | 0x16 => values.push(("Statement", Val::String(String::from_utf8_lossy(argument).into_owned()))),
+        0x17 | 0x19 | 0x1a if argument.len() >= 4 =>
+            values.push(("Statement ID", Val::Unsigned(le(argument, 0, 4).unwrap()))),
+        0x01 | 0x0e | 0x1f => {},
+        _ if !argument.is_empty() => values.push(("Arguments", Val::Bytes(argument))),
+        _ => {},
+    }
+}
+
+fn ok<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    values.push(("Packet Type", Val::Symbol("OK")));
+
+    let (rows, offset) = lenenc(data, 1)?;
+    values.push(("Affected Rows", Val::Unsigned(rows.unwrap_or(0))));
+
+    let (id, offset) = lenenc(data, offset)?;
+    values.push(("Last Insert ID", Val::Unsigned(id.unwrap_or(0))));
+
+    values.push(("Status Flags", Val::Unsigned(le(data, offset, 2)?)));
+    values.push(("Warnings", Val::Unsigned(le(data, offset + 2, 2)?)));
+
+    if data.len() > offset + 4 {
+        values.push(("Info", Val::String(String::from_utf8_lossy(&data[offset + 4..]).into_owned())));
+    }
+
+    Ok(())
+}
+
+fn error<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    values.push(("Packet Type", Val::Symbol("Error")));
+    values.push(("Error Code", Val::Unsigned(le(data, 1, 2)?)));
+
+    let mut message = &data[3..];
+    if message.first() == Some(&b'#') && message.len() >= 6 {
+        values.push(("SQL State", Val::String(String::from_utf8_lossy(&message[1..6]).into_owned())));
+        message = &message[6..];
+    }
+
+    values.push(("Error Message", Val::String(String::from_utf8_lossy(message).into_owned())));
+    Ok(())
+}
+
+/// A packet of a text result set: the column count, a column definition or
+/// a row.
+fn result_set<'data>(data: &'data [u8], sequence: u8, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    if sequence == 1 {
+        if let Ok((Some(count), end)) = lenenc(data, 0) {
+            if end == data.len() {
+                values.push(("Packet Type", Val::Symbol("Column Count")));
+                values.push(("Column Count", Val::Unsigned(count)));
+                return Ok(());
+            }
+        }
+    }
+
+    // Column definitions always start with the catalog "def".
+    if data.starts_with(b"\x03def") {
+        values.push(("Packet Type", Val::Symbol("Column Definition")));
+
+        let mut offset = 0;
+        for &name in ["Catalog", "Schema", "Table", "Original Table", "Name", "Original Name"].iter() {
+            let (value, next) = lenenc_string(data, offset)?;
+            values.push((name, Val::String(value.unwrap_or_default())));
+            offset = next;
+        }
+
+        // The length of the fixed-length fields (always 0x0c)
+        let (_, offset) = lenenc(data, offset)?;
+        values.push(("Character Set", Val::Unsigned(le(data, offset, 2)?)));
+        values.push(("Column Length", Val::Unsigned(le(data, offset + 2, 4)?)));
+        values.push(("Column Type", Val::Unsigned(le(data, offset + 6, 1)?)));
+        values.push(("Flags", Val::Unsigned(le(data, offset + 7, 2)?)));
+        values.push(("Decimals", Val::Unsigned(le(data, offset + 9, 1)?)));
+        return Ok(());
+    }
+
+    values.push(("Packet Type", Val::Symbol("Row")));
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let (value, next) = lenenc_string(data, offset)?;
+        values.push(("Value", value.map(Val::String).unwrap_or(Val::Symbol("NULL"))));
+        offset = next;
+    }
+
+    Ok(())
+}
+
+/// A length-encoded integer (None for the NULL marker, 0xfb) and the offset
+/// of what follows it.
+fn lenenc(data: &[u8], offset: usize) -> Result<(Option<u64>, usize), DissectError> {
+    match le(data, offset, 1)? {
+        0xfb => Ok((None, offset + 1)),
+        0xfc => Ok((Some(le(data, offset + 1, 2)?), offset + 3)),
+        0xfd => Ok((Some(le(data, offset + 1, 3)?), offset + 4)),
+        0xfe => Ok((Some(le(data, offset + 1, 8)?), offset + 9)),
+        0xff => Err(DissectError::InvalidData("invalid MySQL length-encoded integer".to_string())),
+        n => Ok((Some(n), offset + 1)),
+    }
+}
+
+fn lenenc_string(data: &[u8], offset: usize) -> Result<(Option<String>, usize), DissectError> {
+    match lenenc(data, offset)? {
+        (Some(length), next) => {
+            let bytes = slice(data, next, length as usize)?;
+            Ok((Some(String::from_utf8_lossy(bytes).into_owned()), next + length as usize))
+        },
+        (None, next) => Ok((None, next)),
+    }
+}
+
+/// A NUL-terminated string and the offset of what follows it.
+fn null_string(data: &[u8], offset: usize) -> Result<(String, usize), DissectError> {
+    let rest = slice(data, offset, data.len().saturating_sub(offset))?;
+    match rest.iter().position(|&b| b == 0) {
+        Some(end) => Ok((String::from_utf8_lossy(&rest[..end]).into_owned(), offset + end + 1)),
+        None => Err(DissectError::malformed(ErrorCode::Truncated, "MySQL string is not NUL-terminated")),
+    }
+}
+
+fn le(data: &[u8], offset: usize, length: usize) -> Result<u64, DissectError> {
+    Ok(slice(data, offset, length)?.iter().rev().fold(0, |value, &b| value << 8 | b as u64))
+}
+
+fn slice(data: &[u8], offset: usize, length: usize) -> Result<&[u8], DissectError> {
+    data.get(offset..offset + length).ok_or(DissectError::Underflow {
+        expected: Some(offset + length), have: data.len(),
+        message: "MySQL field extends past the end of the data".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Prefix a payload with a MySQL packet header.
+    fn packet(sequence: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![payload.len() as u8, 0, 0, sequence];
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn dissect_handshake() {
+        let mut greeting = vec![0x0a];
+        greeting.extend_from_slice(b"8.0.36\0");
+        greeting.extend_from_slice(&[0x2a, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 0,
+                                     0xff, 0xff, 0xff, 0x02, 0x00, 0xff, 0xdf, 21]);
+        greeting.extend_from_slice(&[0; 10]);
+        greeting.extend_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 0]);
+        greeting.extend_from_slice(b"caching_sha2_password\0");
+
+        let greeting = packet(0, &greeting);
+        let val = *dissect_response(&greeting).unwrap();
+        assert_eq!(val["Packet Type"].as_symbol().unwrap(), "Handshake");
+        assert_eq!(val["Server Version"].as_string().unwrap(), "8.0.36");
+        assert_eq!(val["Connection ID"].as_unsigned().unwrap(), 42);
+        assert_eq!(val["Auth Plugin Data Length"].as_unsigned().unwrap(), 20);
+        assert_eq!(val["Auth Plugin"].as_string().unwrap(), "caching_sha2_password");
+
+        let mut login = vec![0x0d, 0xa2, 0x0a, 0x00, 0, 0, 0, 1, 0xff];
+        login.extend_from_slice(&[0; 23]);
+        login.extend_from_slice(b"root\0");
+        login.extend_from_slice(&[4, 0xde, 0xad, 0xbe, 0xef]);
+        login.extend_from_slice(b"shop\0mysql_native_password\0");
+
+        let login = packet(1, &login);
+        let val = *dissect_request(&login).unwrap();
+        assert_eq!(val["Packet Type"].as_symbol().unwrap(), "Handshake Response");
+        assert_eq!(val["Username"].as_string().unwrap(), "root");
+        assert_eq!(val["Auth Response"].as_bytes().unwrap(), &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(val["Database"].as_string().unwrap(), "shop");
+        assert_eq!(val["Auth Plugin"].as_string().unwrap(), "mysql_native_password");
+    }
+
+    #[test]
+    fn dissect_query_and_result_set() {
+        let query = packet(0, b"\x03SELECT id FROM t");
+        let val = *dissect_request(&query).unwrap();
+        assert_eq!(val["Command Name"].as_symbol().unwrap(), "Query");
+        assert_eq!(val["Statement"].as_string().unwrap(), "SELECT id FROM t");
+
+        let mut data = packet(1, &[1]);
+        data.extend(packet(2, b"\x03def\x04shop\x01t\x01t\x02id\x02id\x0c\x3f\x00\x0b\x00\x00\x00\x03\x03\x42\x00\x00\x00"));
+        data.extend(packet(3, &[0xfe, 0, 0, 0x22, 0]));
+        data.extend(packet(4, b"\x017"));
+        data.extend(packet(5, &[0xfb]));
+        data.extend(packet(6, &[0xfe, 0, 0, 0x22, 0]));
+
+        let val = *dissect_response(&data).unwrap();
+        assert_eq!(val["Column Count"].as_unsigned().unwrap(), 1);
+
+        let column = &val["Next Packet"];
+        assert_eq!(column["Packet Type"].as_symbol().unwrap(), "Column Definition");
+        assert_eq!(column["Name"].as_string().unwrap(), "id");
+        assert_eq!(column["Column Type"].as_unsigned().unwrap(), 3);
+
+        let row = &column["Next Packet"]["Next Packet"];
+        assert_eq!(row["Value"].as_string().unwrap(), "7");
+        assert_eq!(row["Next Packet"]["Value"].as_symbol().unwrap(), "NULL");
+
+        let error = packet(1, b"\xff\x7a\x04#42S02Table 'shop.x' doesn't exist");
+        let error = *dissect_response(&error).unwrap();
+        assert_eq!(error["Error Code"].as_unsigned().unwrap(), 1146);
+        assert_eq!(error["SQL State"].as_string().unwrap(), "42S02");
+    }
+}
@@ -0,0 +1,259 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Friendly names for hosts and services.
+//!
+//! A `NameMap` collects the host names and service labels learned during an
+//! analysis (e.g., from NetBIOS name registrations or from the protocols
+//! dissected on each flow). It can be exported to a simple text file and
+//! imported again, so that reports rendered later or on another machine show
+//! the same names:
+//!
+//! ```text
+//! # rshark name map
+//! host    192.168.0.7     FRED
+//! service tcp/445 SMB2
+//! ```
+//!
+//! Fields are separated by whitespace; a name is the rest of its line.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{BufRead, Write};
+use std::net::IpAddr;
+
+use Val;
+use flow::FlowTable;
+use tap::{PacketInfo, Tap};
+
+const HEADER: &'static str = "# rshark name map";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NameMap {
+    hosts: BTreeMap<IpAddr, String>,
+
+    /// Service labels, keyed by IP protocol number and port.
+    services: BTreeMap<(u8, u16), String>,
+}
+
+impl NameMap {
+    pub fn new() -> NameMap {
+        NameMap::default()
+    }
+
+    pub fn insert_host(&mut self, address: IpAddr, name: &str) {
+        self.hosts.insert(address, name.to_string());
+    }
+
+    pub fn insert_service(&mut self, protocol: u8, port: u16, name: &str) {
+        self.services.insert((protocol, port), name.to_string());
+    }
+
+    pub fn host(&self, address: &IpAddr) -> Option<&str> {
+        self.hosts.get(address).map(|s| s.as_str())
+    }
+
+    pub fn service(&self, protocol: u8, port: u16) -> Option<&str> {
+        self.services.get(&(protocol, port)).map(|s| s.as_str())
+    }
+
+    /// A host's name if it has one, otherwise its address.
+    pub fn host_or_address(&self, address: &IpAddr) -> String {
+        self.host(address).map(|s| s.to_string()).unwrap_or(address.to_string())
+    }
+
+    pub fn hosts(&self) -> &BTreeMap<IpAddr, String> {
+        &self.hosts
+    }
+
+    pub fn services(&self) -> &BTreeMap<(u8, u16), String> {
+        &self.services
+    }
+
+    /// Add all of another map's names, replacing any conflicting ones.
+    pub fn merge(&mut self, other: &NameMap) {
+        self.hosts.extend(other.hosts.iter().map(|(k, v)| (*k, v.clone())));
+        self.services.extend(other.services.iter().map(|(k, v)| (*k, v.clone())));
+    }
+
+    /// Label each flow's responder port with the application protocol that
+    /// was dissected on it.
+    pub fn learn_services(&mut self, flows: &FlowTable) {
+        for flow in flows.flows() {
+            if let Some(service) = flow.service {
+                self.services.entry((flow.key.protocol, flow.key.destination_port))
+                    .or_insert(service.to_string());
+            }
+        }
+    }
+
+    /// Export the map in its text format.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln![out, "{}", HEADER]?;
+
+        for (address, name) in &self.hosts {
+            writeln![out, "host\t{}\t{}", address, name]?;
+        }
+
+        for (&(protocol, port), name) in &self.services {
+            writeln![out, "service\t{}/{}\t{}", protocol_name(protocol), port, name]?;
+        }
+
+        Ok(())
+    }
+
+    /// Import a map from its text format.
+    pub fn read_from<R: BufRead>(input: R) -> io::Result<NameMap> {
+        let mut map = NameMap::new();
+
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData,
+                                                     format!["line {}: {}", i + 1, what]);
+
+            let mut fields = line.splitn(3, char::is_whitespace);
+            let kind = fields.next().unwrap_or("");
+            let key = fields.next().ok_or(invalid("missing key"))?;
+            let name = fields.next().map(|n| n.trim()).unwrap_or("");
+            if name.is_empty() {
+                return Err(invalid("missing name"));
+            }
+
+            match kind {
+                "host" => {
+                    let address = key.parse().map_err(|_| invalid("invalid address"))?;
+                    map.hosts.insert(address, name.to_string());
+                },
+
+                "service" => {
+                    let mut parts = key.splitn(2, '/');
+                    let protocol = parts.next().and_then(protocol_number)
+                        .ok_or(invalid("invalid protocol"))?;
+                    let port = parts.next().and_then(|p| p.parse().ok())
+                        .ok_or(invalid("invalid port"))?;
+                    map.services.insert((protocol, port), name.to_string());
+                },
+
+                _ => return Err(invalid("expected 'host' or 'service'")),
+            }
+        }
+
+        Ok(map)
+    }
+}
+
+/// Learns host names from NetBIOS name service answers and registrations.
+impl Tap for NameMap {
+    fn tap(&mut self, _: &PacketInfo, layer: &Val) {
+        let values = match layer.as_object() {
+            Some((_, values)) => values,
+            None => return,
+        };
+
+        let records = values.iter()
+            .filter(|&&(k, _)| k == "Answer" || k == "Additional Record")
+            .filter_map(|&(_, ref record)| record.as_object());
+
+        for (_, record) in records {
+            let name = record.iter()
+                .find(|&&(k, _)| k == "Name")
+                .and_then(|&(_, ref n)| n.get("Name").ok())
+                .and_then(Val::as_string);
+
+            let name = match name {
+                Some(n) if !n.is_empty() => n,
+                _ => continue,
+            };
+
+            let addresses = record.iter()
+                .filter(|&&(k, _)| k == "Address")
+                .filter_map(|&(_, ref a)| a.get("Address").ok())
+                .filter_map(Val::as_address_encoded)
+                .filter_map(|a| a.parse::<IpAddr>().ok());
+
+            for address in addresses {
+                self.hosts.insert(address, name.to_string());
+            }
+        }
+    }
+}
+
+fn protocol_name(protocol: u8) -> String {
+    match protocol {
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        132 => "sctp".to_string(),
+        _ => protocol.to_string(),
+    }
+}
+
+fn protocol_number(name: &str) -> Option<u8> {
+    match name {
+        "tcp" => Some(6),
+        "udp" => Some(17),
+        "sctp" => Some(132),
+        _ => name.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use netbios;
+    use tap::Taps;
+
+    #[test]
+    fn export_and_import() {
+        let mut map = NameMap::new();
+        map.insert_host("192.168.0.7".parse().unwrap(), "FRED");
+        map.insert_host("2001:db8::1".parse().unwrap(), "file server");
+        map.insert_service(6, 445, "SMB2");
+        map.insert_service(47, 0, "GRE");
+
+        let mut text = Vec::new();
+        map.write_to(&mut text).unwrap();
+        assert!(text.starts_with(b"# rshark name map\nhost\t192.168.0.7\tFRED\n"));
+
+        let imported = NameMap::read_from(&text[..]).unwrap();
+        assert_eq!(imported, map);
+        assert_eq!(imported.host_or_address(&"2001:db8::1".parse().unwrap()), "file server");
+        assert_eq!(imported.service(6, 445), Some("SMB2"));
+
+        assert!(NameMap::read_from(&b"host 10.0.0.1\n"[..]).is_err());
+        assert!(NameMap::read_from(&b"service tcp/http web\n"[..]).is_err());
+        assert!(NameMap::read_from(&b"alias a b\n"[..]).is_err());
+    }
+
+    #[test]
+    fn learn_netbios_names() {
+        // Registration of FRED<20> at 192.168.0.7
+        let mut data = vec![0x12, 0x34, 0x29, 0x10, 0, 1, 0, 0, 0, 0, 0, 1, 0x20];
+        data.extend(b"EGFCEFEECACACACACACACACACACACACA".iter().cloned());
+        data.extend(vec![0, 0, 0x20, 0, 1,
+                         0xc0, 12, 0, 0x20, 0, 1, 0, 0x04, 0x93, 0xe0, 0, 6,
+                         0x60, 0x00, 192, 168, 0, 7]);
+
+        let val = netbios::dissect_name_service(&data).unwrap();
+
+        let mut map = NameMap::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("NetBIOS Name Service", &mut map);
+            taps.dispatch(Duration::new(0, 0), data.len(), &val);
+        }
+
+        assert_eq!(map.host(&"192.168.0.7".parse().unwrap()), Some("FRED"));
+    }
+}
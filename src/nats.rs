@@ -0,0 +1,166 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the NATS client protocol, on TCP port 4222.
+//!
+//! NATS is line-based: each operation is a verb and its arguments on a
+//! CRLF-terminated line, and PUB, HPUB, MSG and HMSG lines are followed by
+//! a payload whose size the line gives. A segment may carry several
+//! operations, each dissected as an "Operation" object; a payload cut off
+//! by the end of the segment is kept as far as it goes. INFO and CONNECT
+//! carry JSON, which is kept as a string.
+//!
+//! See the [NATS protocol](https://docs.nats.io/reference/reference-protocols/nats-protocol).
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use NamedValues;
+use Val;
+
+pub const PORT: u16 = 4222;
+
+/// The operations, in the canonical case (verbs are case-insensitive).
+const VERBS: [&'static str; 12] = [
+    "INFO", "CONNECT", "PUB", "HPUB", "SUB", "UNSUB", "MSG", "HMSG", "PING", "PONG", "+OK", "-ERR",
+];
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let (length, operation) = operation(&data[offset..])?;
+        values.push(("Operation", operation));
+        offset += length;
+    }
+
+    Ok(Box::new(Val::Object("NATS", values)))
+}
+
+/// Dissect the operation at the start of `data`, returning its length.
+fn operation(data: &[u8]) -> Result<(usize, Val), DissectError> {
+    let line_end = data.windows(2).position(|w| w == b"\r\n");
+    let line = String::from_utf8_lossy(&data[..line_end.unwrap_or(data.len())]).into_owned();
+    let mut length = line_end.map(|e| e + 2).unwrap_or(data.len());
+
+    let (verb, arguments) = match line.find(|c: char| c == ' ' || c == '\t') {
+        Some(space) => (&line[..space], line[space..].trim()),
+        None => (line.as_str(), ""),
+    };
+    let verb = match VERBS.iter().find(|v| v.eq_ignore_ascii_case(verb)) {
+        Some(verb) => *verb,
+        None => return Err(DissectError::malformed(ErrorCode::BadMagic,
+            format!["'{}' is not a NATS operation", verb])),
+    };
+
+    let mut values = NamedValues::new();
+    values.push(("Verb", Val::Symbol(verb)));
+
+    let arguments = arguments.split_whitespace().collect::<Vec<_>>();
+    let argument = |i: usize| Val::String(arguments.get(i).cloned().unwrap_or("").to_string());
+
+    // Operations with payloads end with the header size (if any) and the
+    // total size, with an optional reply subject before them.
+    let (names, sizes): (&[&'static str], usize) = match verb {
+        "PUB" => (&["Subject"], 1),
+        "HPUB" => (&["Subject"], 2),
+        "MSG" => (&["Subject", "Subscription ID"], 1),
+        "HMSG" => (&["Subject", "Subscription ID"], 2),
+        "SUB" => {
+            values.push(("Subject", argument(0)));
+            if arguments.len() > 2 {
+                values.push(("Queue Group", argument(1)));
+            }
+            values.push(("Subscription ID", argument(arguments.len().saturating_sub(1))));
+            (&[], 0)
+        },
+        "UNSUB" => {
+            values.push(("Subscription ID", argument(0)));
+            if let Some(max) = arguments.get(1).and_then(|m| m.parse::<u64>().ok()) {
+                values.push(("Max Messages", Val::Unsigned(max)));
+            }
+            (&[], 0)
+        },
+        "INFO" | "CONNECT" => {
+            values.push(("JSON", Val::String(line[verb.len()..].trim().to_string())));
+            (&[], 0)
+        },
+        "-ERR" => {
+            values.push(("Error", Val::String(line[verb.len()..].trim().trim_matches('\'').to_string())));
+            (&[], 0)
+        },
+        _ => (&[], 0),
+    };
+
+    if sizes > 0 {
+        if arguments.len() < names.len() + sizes {
+            return Err(DissectError::InvalidData(format!["NATS {} has too few arguments", verb]));
+        }
+
+        for (i, name) in names.iter().enumerate() {
+            values.push((name, argument(i)));
+        }
+        if arguments.len() > names.len() + sizes {
+            values.push(("Reply Subject", argument(names.len())));
+        }
+
+        let size = |i: usize| {
+            let size = arguments[arguments.len() - i];
+            size.parse::<usize>().map_err(|_|
+                DissectError::InvalidData(format!["NATS {} size '{}' is invalid", verb, size]))
+        };
+        let total = size(1)?;
+        let headers = if sizes == 2 { size(2)? } else { 0 };
+        if headers > total {
+            return Err(DissectError::InvalidData(
+                format!["NATS {} header size {} exceeds its total size {}", verb, headers, total]));
+        }
+
+        values.push(("Size", Val::Unsigned(total as u64)));
+        let payload = &data[length.min(data.len())..(length + total).min(data.len())];
+        if sizes == 2 {
+            let text = String::from_utf8_lossy(&payload[..headers.min(payload.len())]);
+            for header in text.lines().skip(1).filter(|h| !h.trim().is_empty()) {
+                values.push(("Header", Val::String(header.trim_end_matches('\r').to_string())));
+            }
+        }
+        values.push(("Payload", Val::Bytes(&payload[headers.min(payload.len())..])));
+
+        // The payload is followed by CRLF.
+        length = (length + total + 2).min(data.len());
+    }
+
+    Ok((length, Val::Object("NATS Operation", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_operations() {
+        let data = b"SUB orders.* workers 7\r\nPUB orders.new _INBOX.1 5\r\nhello\r\nPING\r\n\
+                     HMSG orders.new 7 20 25\r\nNATS/1.0\r\nId: 42\r\n\r\nworld\r\n";
+
+        let val = *dissect(data).unwrap();
+        let operations = val.as_object().unwrap().1;
+        let verbs = operations.iter().map(|&(_, ref o)| o["Verb"].as_symbol().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(verbs, vec!["SUB", "PUB", "PING", "HMSG"]);
+
+        assert_eq!(operations[0].1["Queue Group"].as_string().unwrap(), "workers");
+        assert_eq!(operations[1].1["Reply Subject"].as_string().unwrap(), "_INBOX.1");
+        assert_eq!(operations[1].1["Payload"].as_bytes().unwrap(), b"hello");
+        assert_eq!(operations[3].1["Header"].as_string().unwrap(), "Id: 42");
+        assert_eq!(operations[3].1["Payload"].as_bytes().unwrap(), b"world");
+
+        assert!(dissect(b"GET / HTTP/1.1\r\n").is_err());
+    }
+}
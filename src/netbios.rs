@@ -0,0 +1,458 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of NetBIOS over TCP/IP: the name service (UDP 137), datagram
+//! service (UDP 138) and session service (TCP 139, and the Direct TCP
+//! framing used by SMB on TCP 445).
+//!
+//! NetBIOS names are 16 B (15 characters padded with spaces plus a suffix
+//! byte that identifies the service) and are carried in the "half-ASCII"
+//! first-level encoding, in which each nibble becomes a letter from 'A' to 'P'.
+//!
+//! See [RFC 1001](https://tools.ietf.org/html/rfc1001) and
+//! [RFC 1002](https://tools.ietf.org/html/rfc1002).
+
+use Endianness;
+use DissectError;
+use DissectResult;
+use Val;
+use NamedValues;
+use raw;
+use smb2;
+use unsigned;
+
+/// Dissect a NetBIOS Name Service (NBNS) message.
+pub fn dissect_name_service(data : &[u8]) -> DissectResult {
+    expect(data, 12, "NetBIOS name service header")?;
+
+    let mut values = NamedValues::new();
+
+    values.push(("Transaction ID", Val::Unsigned(be(&data[0..2]))));
+
+    let flags = be(&data[2..4]);
+    let response = flags & 0x8000 != 0;
+    let opcode = (flags >> 11) & 0x0f;
+    values.push(("Response", Val::Unsigned(response as u64)));
+    values.push(("Opcode", Val::Unsigned(opcode)));
+    values.push(("Opcode Name", Val::Symbol(match opcode {
+        0 => "Query",
+        5 => "Registration",
+        6 => "Release",
+        7 => "WACK",
+        8 | 9 => "Refresh",
+        _ => "Unknown",
+    })));
+
+    values.push(("Flags", Val::BitFlags8((flags >> 4) as u8,
+        [Some("Broadcast"), None, None, Some("Recursion Available"),
+         Some("Recursion Desired"), Some("Truncated"), Some("Authoritative Answer"), None])));
+
+    if response {
+        values.push(("Response Code", Val::Unsigned(flags & 0x0f)));
+    }
+
+    let questions = be(&data[4..6]);
+    let answers = be(&data[6..8]);
+    let authorities = be(&data[8..10]);
+    let additional = be(&data[10..12]);
+
+    let mut offset = 12;
+
+    for _ in 0..questions {
+        let (name, next) = name(data, offset)?;
+        expect(&data[next..], 4, "NetBIOS name service question")?;
+
+        let mut question = NamedValues::new();
+        question.push(("Name", name));
+        question.push(("Type", Val::Symbol(record_type(be(&data[next..next + 2])))));
+        question.push(("Class", Val::Unsigned(be(&data[next + 2..next + 4]))));
+        values.push(("Question", Val::Object("Question", question)));
+
+        offset = next + 4;
+    }
+
+    for &(section, count) in [("Answer", answers), ("Authority", authorities),
+                              ("Additional Record", additional)].iter() {
+        for _ in 0..count {
+            let (record, next) = resource_record(data, offset)?;
+            values.push((section, record));
+            offset = next;
+        }
+    }
+
+    Ok(Box::new(Val::Object("NetBIOS Name Service", values)))
+}
+
+/// Dissect a NetBIOS Datagram Service (NBDS) message.
+pub fn dissect_datagram(data : &[u8]) -> DissectResult {
+    expect(data, 10, "NetBIOS datagram header")?;
+
+    let mut values = NamedValues::new();
+
+    let message_type = data[0];
+    values.push(("Message Type", Val::Unsigned(message_type as u64)));
+    values.push(("Message Type Name", Val::Symbol(match message_type {
+        0x10 => "Direct Unique Datagram",
+        0x11 => "Direct Group Datagram",
+        0x12 => "Broadcast Datagram",
+        0x13 => "Datagram Error",
+        0x14 => "Datagram Query Request",
+        0x15 => "Datagram Positive Query Response",
+        0x16 => "Datagram Negative Query Response",
+        _ => "Unknown",
+    })));
+
+    values.push(("Flags", Val::BitFlags8(data[1],
+        [Some("More"), Some("First"), None, None, None, None, None, None])));
+    values.push(("Datagram ID", Val::Unsigned(be(&data[2..4]))));
+    values.push(("Source IP", ipv4(&data[4..8])));
+    values.push(("Source Port", Val::Unsigned(be(&data[8..10]))));
+
+    match message_type {
+        0x10..=0x12 => {
+            expect(data, 14, "NetBIOS datagram header")?;
+
+            // Length of the names and user data that follow the header
+            let length = be(&data[10..12]) as usize;
+            values.push(("Datagram Length", Val::Unsigned(length as u64)));
+            values.push(("Packet Offset", Val::Unsigned(be(&data[12..14]))));
+
+            let (source, offset) = name(data, 14)?;
+            values.push(("Source Name", source));
+
+            let (destination, offset) = name(data, offset)?;
+            values.push(("Destination Name", destination));
+
+            // Datagrams usually carry SMB mailslot messages (e.g., browser
+            // announcements); SMB1 isn't dissected yet.
+            let end = if 14 + length >= offset && 14 + length <= data.len() { 14 + length }
+                      else { data.len() };
+            values.push(("User Data", Val::Undissected("SMB", &data[offset..end])));
+        },
+        0x13 => {
+            expect(data, 11, "NetBIOS datagram error")?;
+            values.push(("Error Code", Val::Unsigned(data[10] as u64)));
+        },
+        0x14..=0x16 => {
+            let (destination, _) = name(data, 10)?;
+            values.push(("Destination Name", destination));
+        },
+        _ => values.push(("Data", Val::Bytes(&data[10..]))),
+    }
+
+    Ok(Box::new(Val::Object("NetBIOS Datagram", values)))
+}
+
+/// Dissect a NetBIOS Session Service message (or an SMB Direct TCP message,
+/// which uses the same four-byte framing).
+pub fn dissect_session(data : &[u8]) -> DissectResult {
+    expect(data, 4, "NetBIOS session message")?;
+
+    let mut values = NamedValues::new();
+
+    let message_type = data[0];
+    values.push(("Message Type", Val::Unsigned(message_type as u64)));
+    values.push(("Message Type Name", Val::Symbol(match message_type {
+        0x00 => "Session Message",
+        0x81 => "Session Request",
+        0x82 => "Positive Session Response",
+        0x83 => "Negative Session Response",
+        0x84 => "Retarget Session Response",
+        0x85 => "Session Keep Alive",
+        _ => "Unknown",
+    })));
+
+    // The length is 17 bits in RFC 1002, but Direct TCP uses all 24.
+    let length = (data[1] as usize) << 16 | (data[2] as usize) << 8 | data[3] as usize;
+    values.push(("Length", Val::Unsigned(length as u64)));
+
+    // Without TCP reassembly, messages may be split across segments:
+    // dissect as much of the message as we have.
+    let end = if 4 + length < data.len() { 4 + length } else { data.len() };
+    let message = &data[4..end];
+
+    match message_type {
+        0x00 => values.push(("Payload", match message.get(0..4) {
+            Some(b"\xfeSMB") => Val::Payload(message, smb2::dissect(message)),
+            Some(b"\xffSMB") => Val::Undissected("SMB1", message),
+            _ => Val::Undissected("Unknown", message),
+        })),
+        0x81 => {
+            let (called, offset) = name(data, 4)?;
+            values.push(("Called Name", called));
+
+            let (calling, _) = name(data, offset)?;
+            values.push(("Calling Name", calling));
+        },
+        0x83 => {
+            expect(message, 1, "NetBIOS negative session response")?;
+            values.push(("Error Code", Val::Unsigned(message[0] as u64)));
+            values.push(("Error", Val::Symbol(match message[0] {
+                0x80 => "Not listening on called name",
+                0x81 => "Not listening for calling name",
+                0x82 => "Called name not present",
+                0x83 => "Insufficient resources",
+                0x8f => "Unspecified error",
+                _ => "Unknown",
+            })));
+        },
+        0x84 => {
+            expect(message, 6, "NetBIOS retarget session response")?;
+            values.push(("Retarget IP", ipv4(&message[0..4])));
+            values.push(("Retarget Port", Val::Unsigned(be(&message[4..6]))));
+        },
+        _ => if !message.is_empty() { values.push(("Data", Val::Bytes(message))) },
+    }
+
+    if end < data.len() {
+        values.push(("Trailing Data", Val::Bytes(&data[end..])));
+    }
+
+    Ok(Box::new(Val::Object("NetBIOS Session", values)))
+}
+
+/// Decode a first-level-encoded NetBIOS name (RFC 1001 §14.1).
+///
+/// Returns the name (without padding) and its suffix byte.
+pub fn decode_name(encoded: &[u8]) -> Result<(String, u8), DissectError> {
+    if encoded.len() != 32 {
+        return Err(DissectError::InvalidData(
+            format!["encoded NetBIOS names are 32 B, not {} B", encoded.len()]));
+    }
+
+    let mut decoded = Vec::with_capacity(16);
+    for pair in encoded.chunks(2) {
+        if pair.iter().any(|&c| c < b'A' || c > b'P') {
+            return Err(DissectError::InvalidData(
+                "invalid character in encoded NetBIOS name".to_string()));
+        }
+
+        decoded.push((pair[0] - b'A') << 4 | (pair[1] - b'A'));
+    }
+
+    let suffix = decoded[15];
+    let name = String::from_utf8_lossy(&decoded[..15]).trim_end().to_string();
+
+    Ok((name, suffix))
+}
+
+/// The conventional meaning of a NetBIOS name suffix.
+pub fn suffix_name(suffix: u8) -> &'static str {
+    match suffix {
+        0x00 => "Workstation",
+        0x03 => "Messenger",
+        0x1b => "Domain Master Browser",
+        0x1c => "Domain Controllers",
+        0x1d => "Master Browser",
+        0x1e => "Browser Election",
+        0x20 => "File Server",
+        _ => "Unknown",
+    }
+}
+
+/// Parse a (possibly compressed) encoded name at `offset` within `message`,
+/// returning it and the offset of the data that follows it.
+fn name(message: &[u8], offset: usize) -> Result<(Val<'static>, usize), DissectError> {
+    let mut labels = Vec::new();
+    let mut position = offset;
+    let mut next = None;
+
+    // Follow at most a few compression pointers, so loops can't hang us.
+    for _ in 0..8 {
+        loop {
+            expect(&message[position.min(message.len())..], 1, "NetBIOS name")?;
+            let length = message[position] as usize;
+
+            if length & 0xc0 == 0xc0 {
+                expect(&message[position..], 2, "NetBIOS name pointer")?;
+                next = next.or(Some(position + 2));
+                position = (length & 0x3f) << 8 | message[position + 1] as usize;
+                break;
+            }
+
+            if length == 0 {
+                let end = next.unwrap_or(position + 1);
+
+                let first = labels.first().cloned().unwrap_or(&[][..]);
+                let (name, suffix) = decode_name(first)?;
+                let scope = labels[1..].iter()
+                    .map(|l| String::from_utf8_lossy(l).to_string())
+                    .collect::<Vec<_>>()
+                    .join(".");
+
+                let mut values = NamedValues::new();
+                values.push(("Name", Val::String(name)));
+                values.push(("Suffix", Val::Unsigned(suffix as u64)));
+                values.push(("Suffix Name", Val::Symbol(suffix_name(suffix))));
+                if !scope.is_empty() {
+                    values.push(("Scope", Val::String(scope)));
+                }
+
+                return Ok((Val::Object("NetBIOS Name", values), end));
+            }
+
+            expect(&message[position + 1..], length, "NetBIOS name label")?;
+            labels.push(&message[position + 1..position + 1 + length]);
+            position += 1 + length;
+        }
+    }
+
+    Err(DissectError::InvalidData("too many NetBIOS name compression pointers".to_string()))
+}
+
+fn resource_record(data: &[u8], offset: usize) -> Result<(Val, usize), DissectError> {
+    let (name, offset) = name(data, offset)?;
+    expect(&data[offset..], 10, "NetBIOS name service resource record")?;
+
+    let record_type = be(&data[offset..offset + 2]);
+    let length = be(&data[offset + 8..offset + 10]) as usize;
+    let end = offset + 10 + length;
+    expect(&data[offset..], 10 + length, "NetBIOS name service resource data")?;
+
+    let mut values = NamedValues::new();
+    values.push(("Name", name));
+    values.push(("Type", Val::Symbol(self::record_type(record_type))));
+    values.push(("Class", Val::Unsigned(be(&data[offset + 2..offset + 4]))));
+    values.push(("TTL", Val::Unsigned(unsigned(&data[offset + 4..offset + 8],
+                                                Endianness::BigEndian).unwrap())));
+
+    let rdata = &data[offset + 10..end];
+    match record_type {
+        // NB: (flags, address) pairs
+        0x20 => for entry in rdata.chunks(6).filter(|e| e.len() == 6) {
+            let flags = be(&entry[0..2]);
+
+            let mut address = NamedValues::new();
+            address.push(("Group", Val::Unsigned((flags >> 15) & 1)));
+            address.push(("Owner Node Type", Val::Symbol(node_type(flags >> 13))));
+            address.push(("Address", ipv4(&entry[2..6])));
+            values.push(("Address", Val::Object("NetBIOS Address", address)));
+        },
+
+        // NBSTAT: the node's name table (followed by statistics)
+        0x21 if !rdata.is_empty() => {
+            let count = rdata[0] as usize;
+            expect(&rdata[1..], 18 * count, "NetBIOS node status names")?;
+
+            for entry in rdata[1..1 + 18 * count].chunks(18) {
+                let flags = be(&entry[16..18]);
+
+                let mut node = NamedValues::new();
+                node.push(("Name", Val::String(
+                    String::from_utf8_lossy(&entry[..15]).trim_end().to_string())));
+                node.push(("Suffix", Val::Unsigned(entry[15] as u64)));
+                node.push(("Suffix Name", Val::Symbol(suffix_name(entry[15]))));
+                node.push(("Group", Val::Unsigned((flags >> 15) & 1)));
+                node.push(("Owner Node Type", Val::Symbol(node_type(flags >> 13))));
+                node.push(("Active", Val::Unsigned((flags >> 10) & 1)));
+                values.push(("Node Name", Val::Object("NetBIOS Node Name", node)));
+            }
+
+            let statistics = &rdata[1 + 18 * count..];
+            if statistics.len() >= 6 {
+                values.push(("Unit ID", Val::Bytes(&statistics[..6])));
+            }
+        },
+
+        _ => values.push(("Data", Val::Payload(rdata, raw("Data", rdata)))),
+    }
+
+    Ok((Val::Object("Resource Record", values), end))
+}
+
+fn record_type(value: u64) -> &'static str {
+    match value {
+        0x01 => "A",
+        0x02 => "NS",
+        0x0a => "NULL",
+        0x20 => "NB",
+        0x21 => "NBSTAT",
+        _ => "Unknown",
+    }
+}
+
+fn node_type(value: u64) -> &'static str {
+    match value & 0x03 {
+        0 => "B-node",
+        1 => "P-node",
+        2 => "M-node",
+        _ => "H-node",
+    }
+}
+
+fn ipv4(bytes: &[u8]) -> Val {
+    Val::Address {
+        bytes: bytes,
+        encoded: bytes.iter().map(|b| b.to_string()).collect::<Vec<_>>().join("."),
+    }
+}
+
+fn be(data: &[u8]) -> u64 {
+    unsigned(data, Endianness::BigEndian).unwrap()
+}
+
+fn expect(data: &[u8], len: usize, what: &str) -> Result<(), DissectError> {
+    if data.len() < len {
+        return Err(DissectError::Underflow { expected: Some(len), have: data.len(),
+            message: format!["{} needs {} B, have {} B", what, len, data.len()] });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// "FRED" with suffix 0x20, first-level encoded.
+    const FRED: &'static [u8] = b"EGFCEFEECACACACACACACACACACACACA";
+
+    #[test]
+    fn decode_names() {
+        assert_eq!(decode_name(FRED).unwrap(), ("FRED".to_string(), 0x20));
+        assert!(decode_name(b"ZZ").is_err());
+    }
+
+    #[test]
+    fn dissect_registration() {
+        let mut data = vec![0x12, 0x34, 0x29, 0x10, 0, 1, 0, 0, 0, 0, 0, 1, 0x20];
+        data.extend(FRED.iter().cloned());
+        data.extend(vec![0, 0, 0x20, 0, 1,
+                         0xc0, 12, 0, 0x20, 0, 1, 0, 0x04, 0x93, 0xe0, 0, 6,
+                         0x60, 0x00, 192, 168, 0, 7]);
+
+        let val = *dissect_name_service(&data).unwrap();
+        println!("{}", &val.pretty_print(0));
+
+        assert_eq!(val["Opcode Name"].as_symbol().unwrap(), "Registration");
+        assert_eq!(val["Flags"].as_bitflags8_bit_name("Broadcast"), Some(true));
+        assert_eq!(val["Question"]["Name"]["Name"].as_string().unwrap(), "FRED");
+        assert_eq!(val["Question"]["Name"]["Suffix Name"].as_symbol().unwrap(), "File Server");
+
+        let record = &val["Additional Record"];
+        assert_eq!(record["Name"]["Name"].as_string().unwrap(), "FRED");
+        assert_eq!(record["TTL"].as_unsigned().unwrap(), 300000);
+        assert_eq!(record["Address"]["Owner Node Type"].as_symbol().unwrap(), "H-node");
+        assert_eq!(record["Address"]["Address"].as_address_encoded().unwrap(), "192.168.0.7");
+    }
+
+    #[test]
+    fn dissect_session_request() {
+        let mut data = vec![0x81, 0, 0, 68, 0x20];
+        data.extend(FRED.iter().cloned());
+        data.extend(vec![0, 0x20]);
+        data.extend(FRED.iter().cloned());
+        data.push(0);
+
+        let val = *dissect_session(&data).unwrap();
+        assert_eq!(val["Message Type Name"].as_symbol().unwrap(), "Session Request");
+        assert_eq!(val["Called Name"]["Name"].as_string().unwrap(), "FRED");
+        assert_eq!(val["Calling Name"]["Suffix"].as_unsigned().unwrap(), 0x20);
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Network File System (NFS) version 3 and 4 calls, carried
+//! by ONC-RPC (see `rpc`).
+//!
+//! File handles and names are what reveal which files were accessed. NFSv3
+//! calls give them as procedure arguments; NFSv4 calls are COMPOUNDs of
+//! operations, e.g., PUTFH, LOOKUP and READ. Operations with complex
+//! arguments (OPEN, SETATTR...) end dissection of a COMPOUND, since the
+//! operations after them can't be found without decoding them.
+//!
+//! See [RFC 1813](https://tools.ietf.org/html/rfc1813) and
+//! [RFC 7530](https://tools.ietf.org/html/rfc7530).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use rpc::Xdr;
+
+enum_map!(pub V3_PROCEDURES {
+    0 => "NULL",
+    1 => "GETATTR",
+    2 => "SETATTR",
+    3 => "LOOKUP",
+    4 => "ACCESS",
+    5 => "READLINK",
+    6 => "READ",
+    7 => "WRITE",
+    8 => "CREATE",
+    9 => "MKDIR",
+    10 => "SYMLINK",
+    11 => "MKNOD",
+    12 => "REMOVE",
+    13 => "RMDIR",
+    14 => "RENAME",
+    15 => "LINK",
+    16 => "READDIR",
+    17 => "READDIRPLUS",
+    18 => "FSSTAT",
+    19 => "FSINFO",
+    20 => "PATHCONF",
+    21 => "COMMIT",
+});
+
+enum_map!(pub V4_OPERATIONS {
+    3 => "ACCESS",
+    4 => "CLOSE",
+    5 => "COMMIT",
+    6 => "CREATE",
+    9 => "GETATTR",
+    10 => "GETFH",
+    11 => "LINK",
+    12 => "LOCK",
+    15 => "LOOKUP",
+    16 => "LOOKUPP",
+    18 => "OPEN",
+    22 => "PUTFH",
+    23 => "PUTPUBFH",
+    24 => "PUTROOTFH",
+    25 => "READ",
+    26 => "READDIR",
+    27 => "READLINK",
+    28 => "REMOVE",
+    29 => "RENAME",
+    31 => "RESTOREFH",
+    32 => "SAVEFH",
+    33 => "SECINFO",
+    34 => "SETATTR",
+    38 => "WRITE",
+    42 => "EXCHANGE_ID",
+    43 => "CREATE_SESSION",
+    44 => "DESTROY_SESSION",
+    53 => "SEQUENCE",
+});
+
+/// Maximum sizes of file handles and names.
+const V3_HANDLE: usize = 64;
+const V4_HANDLE: usize = 128;
+const NAME: usize = 4096;
+
+/// Dissect the arguments of a call to an NFS procedure.
+pub fn dissect_call(version: u32, procedure: u32, data : &[u8]) -> DissectResult {
+    let mut xdr = Xdr::new(data);
+    let mut values = NamedValues::new();
+
+    match version {
+        3 => {
+            values.push(("Procedure Name", V3_PROCEDURES.val(procedure)));
+            v3_call(procedure, &mut xdr, &mut values)?;
+        },
+        4 if procedure == 0 => values.push(("Procedure Name", Val::Symbol("NULL"))),
+        4 => {
+            values.push(("Procedure Name", Val::Symbol("COMPOUND")));
+            v4_compound(&mut xdr, &mut values)?;
+        },
+        _ => return Err(DissectError::InvalidData(format!["NFS version {} is not supported", version])),
+    }
+
+    let remaining = xdr.remaining();
+    if !remaining.is_empty() {
+        values.push(("Remainder", Val::Undissected("NFS arguments", remaining)));
+    }
+
+    Ok(Box::new(Val::Object("NFS", values)))
+}
+
+fn v3_call<'data>(procedure: u32, xdr: &mut Xdr<'data>, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    // Every procedure but NULL starts with a file handle (of a directory, for
+    // those that name an entry in it).
+    if procedure == 0 {
+        return Ok(());
+    }
+    values.push(("File Handle", Val::Bytes(xdr.opaque(V3_HANDLE)?)));
+
+    match procedure {
+        // LOOKUP, CREATE, MKDIR, SYMLINK, MKNOD, REMOVE, RMDIR
+        3 | 8 | 9 | 10 | 11 | 12 | 13 => values.push(("Name", Val::String(xdr.string(NAME)?))),
+        6 => {
+            values.push(("Offset", Val::Unsigned(xdr.u64()?)));
+            values.push(("Count", Val::Unsigned(xdr.u32()? as u64)));
+        },
+        7 => {
+            values.push(("Offset", Val::Unsigned(xdr.u64()?)));
+            values.push(("Count", Val::Unsigned(xdr.u32()? as u64)));
+            values.push(("Stable", Val::Unsigned(xdr.u32()? as u64)));
+            values.push(("Data", Val::Bytes(xdr.opaque(usize::max_value())?)));
+        },
+        14 => {
+            values.push(("Name", Val::String(xdr.string(NAME)?)));
+            values.push(("To Directory Handle", Val::Bytes(xdr.opaque(V3_HANDLE)?)));
+            values.push(("To Name", Val::String(xdr.string(NAME)?)));
+        },
+        15 => {
+            values.push(("Directory Handle", Val::Bytes(xdr.opaque(V3_HANDLE)?)));
+            values.push(("Name", Val::String(xdr.string(NAME)?)));
+        },
+        _ => {},
+    }
+
+    Ok(())
+}
+
+fn v4_compound<'data>(xdr: &mut Xdr<'data>, values: &mut NamedValues<'data>)
+    -> Result<(), DissectError> {
+
+    values.push(("Tag", Val::String(xdr.string(NAME)?)));
+    values.push(("Minor Version", Val::Unsigned(xdr.u32()? as u64)));
+
+    let count = xdr.u32()?;
+    values.push(("Operation Count", Val::Unsigned(count as u64)));
+
+    for _ in 0..count {
+        let opcode = xdr.u32()?;
+        let mut operation = NamedValues::new();
+        operation.push(("Operation", V4_OPERATIONS.val(opcode)));
+
+        let known = v4_operation(opcode, xdr, &mut operation)?;
+        values.push(("Operation", Val::Object("NFSv4 Operation", operation)));
+        if !known {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Dissect the arguments of an NFSv4 operation, if we know how to find
+/// where they end.
+fn v4_operation<'data>(opcode: u32, xdr: &mut Xdr<'data>, values: &mut NamedValues<'data>)
+    -> Result<bool, DissectError> {
+
+    match opcode {
+        3 => values.push(("Access", Val::Unsigned(xdr.u32()? as u64))),
+        9 => {
+            let words = xdr.u32()? as usize;
+            values.push(("Attribute Bitmap", Val::Bytes(xdr.opaque_fixed(words.saturating_mul(4))?)));
+        },
+        10 | 16 | 23 | 24 | 27 | 31 | 32 => {},
+        15 | 28 => values.push(("Name", Val::String(xdr.string(NAME)?))),
+        11 => values.push(("New Name", Val::String(xdr.string(NAME)?))),
+        22 => values.push(("File Handle", Val::Bytes(xdr.opaque(V4_HANDLE)?))),
+        25 => {
+            values.push(("State ID", Val::Bytes(xdr.opaque_fixed(16)?)));
+            values.push(("Offset", Val::Unsigned(xdr.u64()?)));
+            values.push(("Count", Val::Unsigned(xdr.u32()? as u64)));
+        },
+        29 => {
+            values.push(("Old Name", Val::String(xdr.string(NAME)?)));
+            values.push(("New Name", Val::String(xdr.string(NAME)?)));
+        },
+        38 => {
+            values.push(("State ID", Val::Bytes(xdr.opaque_fixed(16)?)));
+            values.push(("Offset", Val::Unsigned(xdr.u64()?)));
+            values.push(("Stable", Val::Unsigned(xdr.u32()? as u64)));
+            values.push(("Data", Val::Bytes(xdr.opaque(usize::max_value())?)));
+        },
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use rpc;
+
+    fn call(program_version: u8, procedure: u8, arguments: &[u8]) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0x86, 0xa3,
+                            0, 0, 0, program_version, 0, 0, 0, procedure];
+
+        // AUTH_SYS as root@"nfs", then AUTH_NONE
+        data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0, 0, 0, 0, 0, 3, b'n', b'f', b's', 0,
+                                 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(arguments);
+        data
+    }
+
+    #[test]
+    fn dissect_v3_lookup() {
+        let message = call(3, 3, &[0, 0, 0, 4, 0xfe, 0xed, 0xfa, 0xce,
+                                   0, 0, 0, 6, b's', b'h', b'a', b'd', b'o', b'w', 0, 0]);
+
+        // Over TCP, with record marking
+        let mut record = vec![0x80, 0, 0, message.len() as u8];
+        record.extend_from_slice(&message);
+
+        let val = *rpc::dissect_record(&record).unwrap();
+        let rpc = &val["Payload"];
+        assert_eq!(rpc["Program Name"].as_symbol().unwrap(), "NFS");
+        assert_eq!(rpc["Credentials"]["Machine Name"].as_string().unwrap(), "nfs");
+        assert_eq!(rpc["Credentials"]["UID"].as_unsigned().unwrap(), 0);
+
+        let nfs = &rpc["Arguments"];
+        assert_eq!(nfs["Procedure Name"].as_symbol().unwrap(), "LOOKUP");
+        assert_eq!(nfs["File Handle"].as_bytes().unwrap(), &[0xfe, 0xed, 0xfa, 0xce]);
+        assert_eq!(nfs["Name"].as_string().unwrap(), "shadow");
+    }
+
+    #[test]
+    fn dissect_v4_compound() {
+        // PUTROOTFH, LOOKUP "etc", GETFH, then an OPEN that ends dissection
+        let message = call(4, 1, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4,
+                                   0, 0, 0, 24,
+                                   0, 0, 0, 15, 0, 0, 0, 3, b'e', b't', b'c', 0,
+                                   0, 0, 0, 10,
+                                   0, 0, 0, 18, 0xde, 0xad]);
+
+        let val = *rpc::dissect(&message).unwrap();
+        let nfs = &val["Arguments"];
+        assert_eq!(nfs["Procedure Name"].as_symbol().unwrap(), "COMPOUND");
+
+        let operations = val.layer("NFS").unwrap().as_object().unwrap().1.iter()
+            .filter(|&&(name, _)| name == "Operation")
+            .map(|&(_, ref op)| op["Operation"].as_symbol().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(operations, vec!["PUTROOTFH", "LOOKUP", "GETFH", "OPEN"]);
+        assert_eq!(nfs["Remainder"].as_undissected().unwrap().1, &[0xde, 0xad]);
+    }
+}
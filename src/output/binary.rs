@@ -0,0 +1,484 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! A compact binary export of dissections.
+//!
+//! Every distinct string in the exported dissections (field names, layer
+//! names, symbols, string values and encoded addresses) is written once, to a
+//! dictionary at the start of the export, and referred to by its number
+//! thereafter. Integers are written as LEB128 varints. Loading an export
+//! back interns its names and symbols, so that millions of dissections share
+//! a single copy of each. The bytes that payloads were dissected from
+//! aren't exported, so loaded payloads have none.
+//!
+//! ```
+//! use rshark::intern::Interner;
+//! use rshark::output::binary::{decode, Encoder};
+//!
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let mut encoder = Encoder::new();
+//! encoder.encode(&packet);
+//! encoder.encode(&packet);
+//! let export = encoder.finish();
+//!
+//! let mut names = Interner::new();
+//! let loaded = decode(&export, &mut names).unwrap();
+//! assert_eq!(loaded.len(), 2);
+//! assert_eq!(loaded[1], *packet);
+//! ```
+
+use std::collections::HashMap;
+
+use DissectError;
+use ErrorCode;
+use Val;
+use NamedValues;
+use intern::Interner;
+use time::Time;
+
+const MAGIC: &'static [u8] = b"RSHD";
+const VERSION: u8 = 1;
+
+const SIGNED: u8 = 0;
+const UNSIGNED: u8 = 1;
+const STRING: u8 = 2;
+const SYMBOL: u8 = 3;
+const ADDRESS: u8 = 4;
+const BITFLAGS8: u8 = 5;
+const OBJECT: u8 = 6;
+const PAYLOAD: u8 = 7;
+const PAYLOAD_ERROR: u8 = 8;
+const BYTES: u8 = 9;
+const UNDISSECTED: u8 = 10;
+const ENUM: u8 = 11;
+const BITFLAGS: u8 = 12;
+const LIST: u8 = 13;
+const TIMESTAMP: u8 = 14;
+const DURATION: u8 = 15;
+
+/// Accumulates dissections and their dictionary.
+#[derive(Default)]
+pub struct Encoder {
+    ids: HashMap<String, u64>,
+    dictionary: Vec<String>,
+    records: Vec<u8>,
+    count: u64,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    /// Add a dissection to the export.
+    pub fn encode(&mut self, val: &Val) {
+        let mut record = Vec::new();
+        self.value(val, &mut record);
+        self.records.extend(record);
+        self.count += 1;
+    }
+
+    /// The number of distinct strings seen so far.
+    pub fn dictionary_len(&self) -> usize {
+        self.dictionary.len()
+    }
+
+    /// The complete export: header, dictionary and dissections.
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        varint(self.dictionary.len() as u64, &mut out);
+        for s in &self.dictionary {
+            varint(s.len() as u64, &mut out);
+            out.extend_from_slice(s.as_bytes());
+        }
+
+        varint(self.count, &mut out);
+        out.extend(self.records);
+        out
+    }
+
+    /// Write a string's dictionary number.
+    fn id(&mut self, s: &str, out: &mut Vec<u8>) {
+        let id = self.lookup(s);
+        varint(id, out);
+    }
+
+    fn lookup(&mut self, s: &str) -> u64 {
+        let next = self.dictionary.len() as u64;
+        let id = *self.ids.entry(s.to_string()).or_insert(next);
+        if id == next {
+            self.dictionary.push(s.to_string());
+        }
+
+        id
+    }
+
+    fn value(&mut self, val: &Val, out: &mut Vec<u8>) {
+        match val {
+            &Val::Signed(i) => {
+                out.push(SIGNED);
+                varint(((i << 1) ^ (i >> 63)) as u64, out);
+            },
+            &Val::Unsigned(u) => {
+                out.push(UNSIGNED);
+                varint(u, out);
+            },
+            &Val::String(ref s) => {
+                out.push(STRING);
+                self.id(s, out);
+            },
+            &Val::Symbol(s) => {
+                out.push(SYMBOL);
+                self.id(s, out);
+            },
+            &Val::Address { bytes, ref encoded } => {
+                out.push(ADDRESS);
+                bytes_field(bytes, out);
+                self.id(encoded, out);
+            },
+            &Val::BitFlags8(flags, ref names) => {
+                out.push(BITFLAGS8);
+                out.push(flags);
+
+                // Zero for an unnamed bit, otherwise the name's number plus one
+                for name in names.iter() {
+                    let id = name.map(|n| self.lookup(n) + 1).unwrap_or(0);
+                    varint(id, out);
+                }
+            },
+            &Val::BitFlags(flags, names) => {
+                out.push(BITFLAGS);
+                varint(flags, out);
+                varint(names.len() as u64, out);
+                for name in names {
+                    let id = name.map(|n| self.lookup(n) + 1).unwrap_or(0);
+                    varint(id, out);
+                }
+            },
+            &Val::Object(name, ref values) => {
+                out.push(OBJECT);
+                self.id(name, out);
+                varint(values.len() as u64, out);
+                for &(key, ref v) in values {
+                    self.id(key, out);
+                    self.value(v, out);
+                }
+            },
+            &Val::List(ref values) => {
+                out.push(LIST);
+                varint(values.len() as u64, out);
+                for v in values {
+                    self.value(v, out);
+                }
+            },
+            &Val::Timestamp(t) | &Val::Duration(t) => {
+                out.push(if val.is_timestamp() { TIMESTAMP } else { DURATION });
+                varint(((t.seconds << 1) ^ (t.seconds >> 63)) as u64, out);
+                varint(t.nanoseconds as u64, out);
+            },
+            &Val::Payload(_, Ok(ref inner)) => {
+                out.push(PAYLOAD);
+                self.value(inner, out);
+            },
+            &Val::Payload(_, Err(ref e)) => {
+                out.push(PAYLOAD_ERROR);
+                varint(e.code().code() as u64, out);
+                let message = match e {
+                    &DissectError::Underflow { ref message, .. } => message,
+                    &DissectError::InvalidData(ref message) => message,
+                    &DissectError::Malformed { ref message, .. } => message,
+                };
+                self.id(message, out);
+            },
+            &Val::Bytes(bytes) => {
+                out.push(BYTES);
+                bytes_field(bytes, out);
+            },
+            &Val::Undissected(name, bytes) => {
+                out.push(UNDISSECTED);
+                self.id(name, out);
+                bytes_field(bytes, out);
+            },
+            &Val::Enum { value, name } => {
+                out.push(ENUM);
+                varint(value, out);
+
+                // Zero for no name, as for bit flags
+                let id = name.map(|n| self.lookup(n) + 1).unwrap_or(0);
+                varint(id, out);
+            },
+        }
+    }
+}
+
+/// Load the dissections from an export. Byte strings refer to the export
+/// itself; names and symbols are interned.
+pub fn decode<'data>(data: &'data [u8], names: &mut Interner) -> Result<Vec<Val<'data>>, DissectError> {
+    if !data.starts_with(MAGIC) {
+        return Err(DissectError::malformed(ErrorCode::BadMagic, "not an rshark binary export"));
+    }
+
+    if data.get(4) != Some(&VERSION) {
+        return Err(DissectError::malformed(ErrorCode::UnsupportedVersion,
+            format!["unsupported binary export version {:?}", data.get(4)]));
+    }
+
+    let mut decoder = Decoder { data: data, offset: 5, dictionary: Vec::new(), names: names };
+
+    let count = decoder.varint()?;
+    for _ in 0..count {
+        let s = decoder.bytes()?;
+        let s = ::std::str::from_utf8(s)
+            .map_err(|e| DissectError::InvalidData(format!["invalid dictionary string: {}", e]))?;
+        decoder.dictionary.push(s);
+    }
+
+    let count = decoder.varint()?;
+    let mut values = Vec::new();
+    for _ in 0..count {
+        values.push(decoder.value()?);
+    }
+
+    Ok(values)
+}
+
+struct Decoder<'data, 'n> {
+    data: &'data [u8],
+    offset: usize,
+    dictionary: Vec<&'data str>,
+    names: &'n mut Interner,
+}
+
+impl<'data, 'n> Decoder<'data, 'n> {
+    fn value(&mut self) -> Result<Val<'data>, DissectError> {
+        let tag = self.byte()?;
+
+        Ok(match tag {
+            SIGNED => {
+                let z = self.varint()?;
+                Val::Signed((z >> 1) as i64 ^ -((z & 1) as i64))
+            },
+            UNSIGNED => Val::Unsigned(self.varint()?),
+            STRING => Val::String(self.string()?.to_string()),
+            SYMBOL => Val::Symbol(self.name()?),
+            ADDRESS => {
+                let bytes = self.bytes()?;
+                Val::Address { bytes: bytes, encoded: self.string()?.to_string() }
+            },
+            BITFLAGS8 => {
+                let flags = self.byte()?;
+                let mut names = [None; 8];
+                for name in names.iter_mut() {
+                    *name = match self.varint()? {
+                        0 => None,
+                        id => Some(self.names.intern(self.entry(id - 1)?)?),
+                    };
+                }
+                Val::BitFlags8(flags, names)
+            },
+            BITFLAGS => {
+                let flags = self.varint()?;
+                let count = self.varint()?;
+                if count > 64 {
+                    return Err(DissectError::InvalidData(format!["{} bit flags", count]));
+                }
+
+                let mut names = Vec::new();
+                for _ in 0..count {
+                    names.push(match self.varint()? {
+                        0 => None,
+                        id => Some(self.names.intern(self.entry(id - 1)?)?),
+                    });
+                }
+                Val::BitFlags(flags, self.names.intern_bit_names(names)?)
+            },
+            OBJECT => {
+                let name = self.name()?;
+                let count = self.varint()?;
+                let mut values = NamedValues::new();
+                for _ in 0..count {
+                    let key = self.name()?;
+                    values.push((key, self.value()?));
+                }
+                Val::Object(name, values)
+            },
+            TIMESTAMP | DURATION => {
+                let z = self.varint()?;
+                let seconds = (z >> 1) as i64 ^ -((z & 1) as i64);
+                let t = Time::new(seconds, self.varint()? as u32);
+                if tag == TIMESTAMP { Val::Timestamp(t) } else { Val::Duration(t) }
+            },
+            LIST => {
+                let count = self.varint()?;
+                let mut values = Vec::new();
+                for _ in 0..count {
+                    values.push(self.value()?);
+                }
+                Val::List(values)
+            },
+            PAYLOAD => Val::Payload(&[], Ok(Box::new(self.value()?))),
+            PAYLOAD_ERROR => {
+                let code = self.varint()?;
+                let message = self.string()?.to_string();
+                Val::Payload(&[], Err(match code {
+                    1 => DissectError::Underflow { expected: None, have: 0, message: message },
+                    2 => DissectError::InvalidData(message),
+                    _ => DissectError::malformed(error_code(code), message),
+                }))
+            },
+            BYTES => Val::Bytes(self.bytes()?),
+            UNDISSECTED => {
+                let name = self.name()?;
+                Val::Undissected(name, self.bytes()?)
+            },
+            ENUM => {
+                let value = self.varint()?;
+                let name = match self.varint()? {
+                    0 => None,
+                    id => Some(self.names.intern(self.entry(id - 1)?)?),
+                };
+                Val::Enum { value: value, name: name }
+            },
+            _ => return Err(DissectError::InvalidData(format!["invalid value tag {}", tag])),
+        })
+    }
+
+    fn name(&mut self) -> Result<&'static str, DissectError> {
+        let s = self.string()?;
+        self.names.intern(s)
+    }
+
+    fn string(&mut self) -> Result<&'data str, DissectError> {
+        let id = self.varint()?;
+        self.entry(id)
+    }
+
+    fn entry(&self, id: u64) -> Result<&'data str, DissectError> {
+        self.dictionary.get(id as usize).cloned()
+            .ok_or(DissectError::InvalidData(format!["no dictionary entry {}", id]))
+    }
+
+    /// A length-prefixed byte string.
+    fn bytes(&mut self) -> Result<&'data [u8], DissectError> {
+        let length = self.varint()? as usize;
+        self.bytes_exact(length)
+    }
+
+    fn byte(&mut self) -> Result<u8, DissectError> {
+        Ok(self.bytes_exact(1)?[0])
+    }
+
+    fn bytes_exact(&mut self, length: usize) -> Result<&'data [u8], DissectError> {
+        let end = self.offset.saturating_add(length);
+        let bytes = self.data.get(self.offset..end)
+            .ok_or(DissectError::Underflow { expected: Some(end), have: self.data.len(),
+                message: "binary export is truncated".to_string() })?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, DissectError> {
+        decode_varint(self.data, &mut self.offset)
+    }
+}
+
+fn bytes_field(bytes: &[u8], out: &mut Vec<u8>) {
+    varint(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+}
+
+fn varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn decode_varint(data: &[u8], offset: &mut usize) -> Result<u64, DissectError> {
+    let mut value = 0;
+    for shift in 0..10 {
+        let b = *data.get(*offset).ok_or(DissectError::Underflow {
+            expected: Some(*offset + 1), have: data.len(),
+            message: "binary export is truncated".to_string() })?;
+        *offset += 1;
+
+        value |= ((b & 0x7f) as u64) << (7 * shift);
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(DissectError::InvalidData("varint is too long".to_string()))
+}
+
+fn error_code(code: u64) -> ErrorCode {
+    match code {
+        1 => ErrorCode::Truncated,
+        3 => ErrorCode::BadMagic,
+        4 => ErrorCode::UnsupportedVersion,
+        5 => ErrorCode::InvalidLength,
+        6 => ErrorCode::UnknownProtocol,
+        7 => ErrorCode::DissectorPanic,
+        8 => ErrorCode::BudgetExceeded,
+        _ => ErrorCode::InvalidData,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    #[test]
+    fn round_trip() {
+        let data = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+                    0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+                    10, 0, 0, 1, 10, 0, 0, 2,
+                    0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+        let packet = ethernet::dissect(&data).unwrap();
+        let failed = Val::Object("Test", vec![
+            ("Signed", Val::Signed(-3)),
+            ("Flags", Val::BitFlags8(0x81, [Some("A"), None, None, None, None, None, None, Some("H")])),
+            ("Wide Flags", Val::BitFlags(0x100, &[None, Some("B"), None, None, None, None, None,
+                                                  None, Some("I")])),
+            ("List", Val::List(vec![Val::Unsigned(1), Val::Symbol("two")])),
+            ("Time", Val::Timestamp(Time::new(-2, 5))),
+            ("RTT", Val::Duration(Time::new(0, 250_000))),
+            ("Payload", Val::Payload(&[], Err(DissectError::malformed(ErrorCode::BadMagic, "bad")))),
+        ]);
+
+        let mut encoder = Encoder::new();
+        encoder.encode(&packet);
+        let distinct = encoder.dictionary_len();
+
+        // Each name is written once, not once per packet.
+        for _ in 1..100 {
+            encoder.encode(&packet);
+        }
+        assert_eq!(encoder.dictionary_len(), distinct);
+
+        encoder.encode(&failed);
+        let export = encoder.finish();
+
+        let mut names = Interner::new();
+        let loaded = decode(&export, &mut names).unwrap();
+        assert_eq!(loaded.len(), 101);
+        assert_eq!(loaded[99].to_string(), packet.to_string());
+        assert_eq!(loaded[100], failed);
+        assert!(loaded[0].as_object().unwrap().0.as_ptr() == loaded[1].as_object().unwrap().0.as_ptr());
+
+        assert!(decode(&export[..export.len() - 1], &mut names).is_err());
+        assert!(decode(b"RSHD\x02", &mut names).is_err());
+    }
+}
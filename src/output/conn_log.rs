@@ -0,0 +1,214 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Zeek-style connection logs (`conn.log`).
+//!
+//! Flows from a `FlowTable` can be written either in Zeek's tab-separated
+//! format (including its `#fields` and `#types` header) or as one JSON object
+//! per line, so that rshark can slot into pipelines built around Zeek logs.
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use flow::{Flow, FlowTable};
+use super::json_string;
+
+const FIELDS: [(&'static str, &'static str); 14] = [
+    ("ts", "time"),
+    ("uid", "string"),
+    ("id.orig_h", "addr"),
+    ("id.orig_p", "port"),
+    ("id.resp_h", "addr"),
+    ("id.resp_p", "port"),
+    ("proto", "enum"),
+    ("service", "string"),
+    ("duration", "interval"),
+    ("orig_bytes", "count"),
+    ("resp_bytes", "count"),
+    ("conn_state", "string"),
+    ("orig_pkts", "count"),
+    ("resp_pkts", "count"),
+];
+
+/// Write all flows in Zeek's tab-separated log format.
+pub fn write_tsv<W: Write>(out: &mut W, flows: &FlowTable) -> io::Result<()> {
+    writeln![out, "#separator \\x09"]?;
+    writeln![out, "#set_separator\t,"]?;
+    writeln![out, "#empty_field\t(empty)"]?;
+    writeln![out, "#unset_field\t-"]?;
+    writeln![out, "#path\tconn"]?;
+    writeln![out, "#fields\t{}", FIELDS.iter().map(|f| f.0).collect::<Vec<_>>().join("\t")]?;
+    writeln![out, "#types\t{}", FIELDS.iter().map(|f| f.1).collect::<Vec<_>>().join("\t")]?;
+
+    for flow in flows.flows() {
+        writeln![out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                 time(flow.first_seen), uid(flow),
+                 flow.key.source, flow.key.source_port,
+                 flow.key.destination, flow.key.destination_port,
+                 proto(flow), service(flow).unwrap_or("-".to_string()),
+                 time(flow.duration()), flow.orig_bytes, flow.resp_bytes,
+                 conn_state(flow), flow.orig_packets, flow.resp_packets]?;
+    }
+
+    Ok(())
+}
+
+/// Write all flows as JSON objects, one per line (Zeek's `LogAscii::use_json`).
+pub fn write_json<W: Write>(out: &mut W, flows: &FlowTable) -> io::Result<()> {
+    for flow in flows.flows() {
+        write![out, "{{\"ts\":{},\"uid\":{},\"id.orig_h\":{},\"id.orig_p\":{},\
+                     \"id.resp_h\":{},\"id.resp_p\":{},\"proto\":\"{}\",",
+               time(flow.first_seen), json_string(&uid(flow)),
+               json_string(&flow.key.source.to_string()), flow.key.source_port,
+               json_string(&flow.key.destination.to_string()), flow.key.destination_port,
+               proto(flow)]?;
+
+        if let Some(service) = service(flow) {
+            write![out, "\"service\":{},", json_string(&service)]?;
+        }
+
+        writeln![out, "\"duration\":{},\"orig_bytes\":{},\"resp_bytes\":{},\
+                       \"conn_state\":\"{}\",\"orig_pkts\":{},\"resp_pkts\":{}}}",
+                 time(flow.duration()), flow.orig_bytes, flow.resp_bytes,
+                 conn_state(flow), flow.orig_packets, flow.resp_packets]?;
+    }
+
+    Ok(())
+}
+
+/// A Zeek-style connection identifier.
+pub fn uid(flow: &Flow) -> String {
+    format!["C{:x}", flow.id]
+}
+
+/// Summarize the TCP handshake and teardown into a Zeek `conn_state` value.
+///
+/// UDP flows are `SF` if the responder ever replied and `S0` otherwise.
+pub fn conn_state(flow: &Flow) -> &'static str {
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+
+    if flow.key.protocol != 6 {
+        return if flow.resp_packets > 0 { "SF" } else { "S0" };
+    }
+
+    let (orig, resp) = (flow.orig_flags, flow.resp_flags);
+
+    if orig & SYN == 0 { "OTH" }
+    else if resp & SYN == 0 {
+        if resp & RST != 0 { "REJ" }
+        else if orig & FIN != 0 { "SH" }
+        else { "S0" }
+    }
+    else if orig & RST != 0 { "RSTO" }
+    else if resp & RST != 0 { "RSTR" }
+    else if orig & FIN != 0 && resp & FIN != 0 { "SF" }
+    else if orig & FIN != 0 { "S2" }
+    else if resp & FIN != 0 { "S3" }
+    else { "S1" }
+}
+
+fn proto(flow: &Flow) -> &'static str {
+    match flow.key.protocol {
+        6 => "tcp",
+        17 => "udp",
+        _ => "unknown_transport",
+    }
+}
+
+fn service(flow: &Flow) -> Option<String> {
+    flow.service.map(|s| s.to_lowercase())
+}
+
+fn time(t: Duration) -> String {
+    format!["{}.{:06}", t.as_secs(), t.subsec_nanos() / 1000]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use flow::FlowTable;
+    use ip;
+
+    // 10.0.0.1:5004 -> 10.0.0.2:5006, carrying RTP
+    const RTP: [u8; 44] = [
+        0x45, 0x00, 0x00, 0x2c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        10, 0, 0, 1, 10, 0, 0, 2,
+        0x13, 0x8c, 0x13, 0x8e, 0x00, 0x18, 0x00, 0x00,
+        0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xa0, 0x12, 0x34, 0x56, 0x78,
+        0xff, 0xff, 0xff, 0xff];
+
+    // [2001:db8::1]:40000 -> [2001:db8::2]:80, carrying 4 B of TCP data
+    const TCP6: [u8; 64] = [
+        0x60, 0x00, 0x00, 0x00, 0x00, 0x18, 0x06, 0x40,
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2,
+        0x9c, 0x40, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+        b'p', b'i', b'n', b'g'];
+
+    // The reply, with 6 B of data behind a hop-by-hop options header
+    const TCP6_REPLY: [u8; 74] = [
+        0x60, 0x00, 0x00, 0x00, 0x00, 0x22, 0x00, 0x40,
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2,
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+        0x06, 0x00, 0x01, 0x04, 0, 0, 0, 0,
+        0x00, 0x50, 0x9c, 0x40, 0, 0, 0, 1, 0, 0, 0, 5, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+        b'p', b'o', b'n', b'g', b'!', b'!'];
+
+    fn table() -> FlowTable {
+        let mut table = FlowTable::new();
+        table.track(Duration::new(1444000000, 123456789), &ip::dissect(&RTP).unwrap());
+        table.track(Duration::new(1444000001, 123456789), &ip::dissect(&RTP).unwrap());
+        table
+    }
+
+    #[test]
+    fn conn_log_tsv() {
+        let mut out = Vec::new();
+        write_tsv(&mut out, &table()).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[5].starts_with("#fields\tts\tuid\tid.orig_h"));
+        assert_eq!(lines[7], "1444000000.123456\tC0\t10.0.0.1\t5004\t10.0.0.2\t5006\tudp\trtp\t\
+                              1.000000\t32\t0\tS0\t2\t0");
+    }
+
+    #[test]
+    fn conn_log_json() {
+        let mut out = Vec::new();
+        write_json(&mut out, &table()).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "{\"ts\":1444000000.123456,\"uid\":\"C0\",\"id.orig_h\":\"10.0.0.1\",\
+                    \"id.orig_p\":5004,\"id.resp_h\":\"10.0.0.2\",\"id.resp_p\":5006,\
+                    \"proto\":\"udp\",\"service\":\"rtp\",\"duration\":1.000000,\
+                    \"orig_bytes\":32,\"resp_bytes\":0,\"conn_state\":\"S0\",\
+                    \"orig_pkts\":2,\"resp_pkts\":0}\n");
+    }
+
+    #[test]
+    fn conn_log_ipv6() {
+        let mut table = FlowTable::new();
+        table.track(Duration::new(1444000000, 0), &ip::ipv6::dissect(&TCP6).unwrap());
+        table.track(Duration::new(1444000001, 0), &ip::ipv6::dissect(&TCP6_REPLY).unwrap());
+
+        let mut out = Vec::new();
+        write_tsv(&mut out, &table).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().last().unwrap(),
+                   "1444000000.000000\tC0\t2001:db8::1\t40000\t2001:db8::2\t80\ttcp\thttp\t\
+                    1.000000\t4\t6\tOTH\t1\t1");
+    }
+}
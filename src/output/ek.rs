@@ -0,0 +1,115 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Newline-delimited JSON for the Elasticsearch (or OpenSearch) bulk API,
+//! after `tshark -T ek`.
+//!
+//! Each packet is written as two lines: a bulk `index` action naming a daily
+//! `packets-YYYY-MM-DD` index, then the packet's document. The document holds
+//! the capture time in milliseconds since the epoch and the packet's layers,
+//! rendered as by `json::layers` and preceded by a `frame` layer with the
+//! packet's number, time and capture interface. The output can be POSTed to
+//! `_bulk` as it is.
+//!
+//! ```
+//! use rshark::output::ek::EkWriter;
+//! use rshark::output::json::JsonOptions;
+//!
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let mut writer = EkWriter::new(Vec::new(), JsonOptions::new());
+//! writer.write_packet(std::time::Duration::new(0, 0), Some("eth0"), &packet).unwrap();
+//!
+//! let output = String::from_utf8(writer.into_inner()).unwrap();
+//! let lines = output.lines().collect::<Vec<_>>();
+//! assert_eq!(lines[0], r#"{"index":{"_index":"packets-1970-01-01","_type":"doc"}}"#);
+//! assert!(lines[1].starts_with(r#"{"timestamp":"0","layers":{"frame":{"frame.number":1,"#));
+//! ```
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use Val;
+use time::{format_timestamp, Time, TimeFormat};
+use super::{iso8601, json_string};
+use super::json::{layers, JsonOptions};
+
+/// Writes packets as bulk index actions and documents.
+pub struct EkWriter<W: Write> {
+    out: W,
+    options: JsonOptions,
+    packets: u64,
+}
+
+impl<W: Write> EkWriter<W> {
+    pub fn new(out: W, options: JsonOptions) -> EkWriter<W> {
+        EkWriter { out: out, options: options, packets: 0 }
+    }
+
+    /// Write the index action and document of a packet captured at
+    /// `timestamp`, on the named interface if it is known.
+    pub fn write_packet(&mut self, timestamp: Duration, interface: Option<&str>, packet: &Val)
+        -> io::Result<()> {
+
+        self.packets += 1;
+
+        let mut frame = vec![
+            format!["\"frame.number\":{}", self.packets],
+            format!["\"frame.time\":{}",
+                    json_string(&format_timestamp(Time::from_duration(timestamp), TimeFormat::Utc))],
+        ];
+        if let Some(name) = interface {
+            frame.push(format!["\"frame.interface_name\":{}", json_string(name)]);
+        }
+
+        let layers = layers(packet, &self.options);
+        let others = &layers[1..layers.len() - 1];
+
+        writeln![self.out, "{{\"index\":{{\"_index\":\"packets-{}\",\"_type\":\"doc\"}}}}",
+                 &iso8601(timestamp)[..10]]?;
+        writeln![self.out, "{{\"timestamp\":\"{}\",\"layers\":{{\"frame\":{{{}}}{}{}}}}}",
+                 timestamp.as_secs() * 1000 + timestamp.subsec_nanos() as u64 / 1_000_000,
+                 frame.join(","), if others.is_empty() { "" } else { "," }, others]
+    }
+
+    /// The number of packets written so far.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_bulk_lines() {
+        let mut writer = EkWriter::new(Vec::new(), JsonOptions::new());
+        writer.write_packet(Duration::new(1444004800, 123456789), None,
+                            &Val::Object("A", vec![("x", Val::Unsigned(1))])).unwrap();
+        writer.write_packet(Duration::new(1444004801, 0), Some("wg0"), &Val::Bytes(&[])).unwrap();
+        assert_eq!(writer.packets(), 2);
+
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(),
+                   "{\"index\":{\"_index\":\"packets-2015-10-05\",\"_type\":\"doc\"}}\n\
+                    {\"timestamp\":\"1444004800123\",\"layers\":{\"frame\":{\"frame.number\":1,\
+                    \"frame.time\":\"2015-10-05T00:26:40.123456789Z\"},\"A\":{\"x\":1}}}\n\
+                    {\"index\":{\"_index\":\"packets-2015-10-05\",\"_type\":\"doc\"}}\n\
+                    {\"timestamp\":\"1444004801000\",\"layers\":{\"frame\":{\"frame.number\":2,\
+                    \"frame.time\":\"2015-10-05T00:26:41.000000000Z\",\
+                    \"frame.interface_name\":\"wg0\"}}}\n");
+    }
+}
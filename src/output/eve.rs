@@ -0,0 +1,150 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Suricata-compatible EVE-JSON event records.
+//!
+//! Each event is written as a single line of JSON following the
+//! [EVE schema](https://suricata.readthedocs.io/en/latest/output/eve/eve-json-format.html),
+//! so SIEM parsers that already ingest Suricata output can ingest rshark's.
+//! Only `flow` and `alert` records are produced for now: `dns`, `tls` and
+//! `http` records will follow once those protocols have dissectors.
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use flow::{Flow, FlowKey, FlowTable};
+use super::{iso8601, json_string};
+
+/// A rule match to be reported as an EVE `alert` record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert<'a> {
+    pub signature_id: u32,
+    pub rev: u32,
+    pub signature: &'a str,
+    pub category: &'a str,
+    /// 1 (high) to 4 (low), as in Suricata.
+    pub severity: u8,
+}
+
+/// Write an `alert` record for a packet seen at `timestamp`.
+pub fn write_alert<W: Write>(out: &mut W, timestamp: Duration, key: &FlowKey,
+                             flow_id: Option<u64>, alert: &Alert) -> io::Result<()> {
+
+    header(out, timestamp, flow_id, "alert", key)?;
+    writeln![out, ",\"alert\":{{\"action\":\"allowed\",\"gid\":1,\"signature_id\":{},\
+                   \"rev\":{},\"signature\":{},\"category\":{},\"severity\":{}}}}}",
+             alert.signature_id, alert.rev, json_string(alert.signature),
+             json_string(alert.category), alert.severity]
+}
+
+/// Write a `flow` record summarizing a single flow.
+pub fn write_flow<W: Write>(out: &mut W, flow: &Flow) -> io::Result<()> {
+    header(out, flow.last_seen, Some(flow.id), "flow", &flow.key)?;
+
+    if let Some(service) = flow.service {
+        write![out, ",\"app_proto\":{}", json_string(&service.to_lowercase())]?;
+    }
+
+    writeln![out, ",\"flow\":{{\"pkts_toserver\":{},\"pkts_toclient\":{},\
+                   \"bytes_toserver\":{},\"bytes_toclient\":{},\"start\":\"{}\",\
+                   \"end\":\"{}\",\"age\":{},\"state\":\"{}\"}}}}",
+             flow.orig_packets, flow.resp_packets, flow.orig_bytes, flow.resp_bytes,
+             iso8601(flow.first_seen), iso8601(flow.last_seen),
+             flow.duration().as_secs(), state(flow)]
+}
+
+/// Write a `flow` record for every flow in a table.
+pub fn write_flows<W: Write>(out: &mut W, flows: &FlowTable) -> io::Result<()> {
+    for flow in flows.flows() {
+        write_flow(out, flow)?;
+    }
+
+    Ok(())
+}
+
+/// The fields common to all EVE records (without the closing brace).
+fn header<W: Write>(out: &mut W, timestamp: Duration, flow_id: Option<u64>,
+                    event_type: &str, key: &FlowKey) -> io::Result<()> {
+
+    write![out, "{{\"timestamp\":\"{}\"", iso8601(timestamp)]?;
+    if let Some(id) = flow_id {
+        write![out, ",\"flow_id\":{}", id]?;
+    }
+
+    write![out, ",\"event_type\":\"{}\",\"src_ip\":\"{}\",\"src_port\":{},\
+                 \"dest_ip\":\"{}\",\"dest_port\":{},\"proto\":\"{}\"",
+           event_type, key.source, key.source_port, key.destination, key.destination_port,
+           match key.protocol { 6 => "TCP", 17 => "UDP", _ => "unknown" }]
+}
+
+fn state(flow: &Flow) -> &'static str {
+    const FIN_OR_RST: u8 = 0x05;
+
+    if (flow.orig_flags | flow.resp_flags) & FIN_OR_RST != 0 { "closed" }
+    else if flow.resp_packets > 0 { "established" }
+    else { "new" }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use flow::FlowTable;
+    use ip;
+
+    // 10.0.0.1:5004 -> 10.0.0.2:5006, carrying RTP
+    const RTP: [u8; 44] = [
+        0x45, 0x00, 0x00, 0x2c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        10, 0, 0, 1, 10, 0, 0, 2,
+        0x13, 0x8c, 0x13, 0x8e, 0x00, 0x18, 0x00, 0x00,
+        0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xa0, 0x12, 0x34, 0x56, 0x78,
+        0xff, 0xff, 0xff, 0xff];
+
+    #[test]
+    fn eve_flow() {
+        let mut table = FlowTable::new();
+        table.track(Duration::new(1444004800, 0), &ip::dissect(&RTP).unwrap());
+
+        let mut out = Vec::new();
+        write_flows(&mut out, &table).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "{\"timestamp\":\"2015-10-05T00:26:40.000000+0000\",\"flow_id\":0,\
+                    \"event_type\":\"flow\",\"src_ip\":\"10.0.0.1\",\"src_port\":5004,\
+                    \"dest_ip\":\"10.0.0.2\",\"dest_port\":5006,\"proto\":\"UDP\",\
+                    \"app_proto\":\"rtp\",\"flow\":{\"pkts_toserver\":1,\"pkts_toclient\":0,\
+                    \"bytes_toserver\":16,\"bytes_toclient\":0,\
+                    \"start\":\"2015-10-05T00:26:40.000000+0000\",\
+                    \"end\":\"2015-10-05T00:26:40.000000+0000\",\"age\":0,\"state\":\"new\"}}\n");
+    }
+
+    #[test]
+    fn eve_alert() {
+        let key = FlowKey::from_val(&ip::dissect(&RTP).unwrap()).unwrap();
+        let alert = Alert {
+            signature_id: 2000001,
+            rev: 3,
+            signature: "RTP to \"unexpected\" host",
+            category: "Policy Violation",
+            severity: 2,
+        };
+
+        let mut out = Vec::new();
+        write_alert(&mut out, Duration::new(0, 1000), &key, None, &alert).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "{\"timestamp\":\"1970-01-01T00:00:00.000001+0000\",\"event_type\":\"alert\",\
+                    \"src_ip\":\"10.0.0.1\",\"src_port\":5004,\"dest_ip\":\"10.0.0.2\",\
+                    \"dest_port\":5006,\"proto\":\"UDP\",\"alert\":{\"action\":\"allowed\",\
+                    \"gid\":1,\"signature_id\":2000001,\"rev\":3,\
+                    \"signature\":\"RTP to \\\"unexpected\\\" host\",\
+                    \"category\":\"Policy Violation\",\"severity\":2}}\n");
+    }
+}
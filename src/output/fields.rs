@@ -0,0 +1,275 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Delimited rows of selected fields, after `tshark -T fields -e ...`.
+//!
+//! Fields are selected by dot-separated paths, as for `Val::lookup`, except
+//! that a path may start with the name of a layer anywhere in the protocol
+//! stack: "IPv4.Source" or "UDP.Destination Port". A path can match more
+//! than once, e.g., a field that is repeated within its layer, the
+//! elements of a list or the layers of a tunnelled packet; `Occurrence`
+//! picks which of the matches to write. Values are written as tshark writes
+//! them: named numbers as their numbers, strings without quotes and bytes
+//! in hex.
+//!
+//! ```
+//! use rshark::output::fields::{row, FieldOptions};
+//!
+//! let mut data = vec![0; 12];
+//! data.extend_from_slice(&[0x88, 0xb5]);
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let options = FieldOptions::csv();
+//! assert_eq!(row(&packet, &["Ethernet frame.Source", "Ethernet frame.Type", "IPv4.Source"],
+//!                &options),
+//!            r#""00:00:00:00:00:00","34997","""#);
+//! ```
+
+use std::io;
+use std::io::Write;
+
+use Val;
+use super::show;
+
+/// Which of a path's matches to write.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Occurrence {
+    First,
+    Last,
+    /// All of the matches, separated by the aggregator.
+    All,
+}
+
+/// How to write rows of fields.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldOptions {
+    /// Separates the fields of a row.
+    pub separator: String,
+
+    /// Separates the matches of a single field.
+    pub aggregator: String,
+
+    pub occurrence: Occurrence,
+
+    /// Write the paths as a header row before the first packet.
+    pub header: bool,
+
+    /// Quote each field with this character (doubling any occurrences of
+    /// it within the field).
+    pub quote: Option<char>,
+}
+
+impl FieldOptions {
+    /// tshark's defaults: tab-separated, unquoted, all occurrences
+    /// separated by commas and no header.
+    pub fn new() -> FieldOptions {
+        FieldOptions {
+            separator: "\t".to_string(),
+            aggregator: ",".to_string(),
+            occurrence: Occurrence::All,
+            header: false,
+            quote: None,
+        }
+    }
+
+    /// Comma-separated values with quoted fields and a header row.
+    pub fn csv() -> FieldOptions {
+        FieldOptions {
+            separator: ",".to_string(),
+            aggregator: ";".to_string(),
+            occurrence: Occurrence::All,
+            header: true,
+            quote: Some('"'),
+        }
+    }
+}
+
+impl Default for FieldOptions {
+    fn default() -> FieldOptions {
+        FieldOptions::new()
+    }
+}
+
+/// Writes a row of fields for each packet.
+pub struct FieldWriter<W: Write> {
+    out: W,
+    paths: Vec<String>,
+    options: FieldOptions,
+    rows: u64,
+}
+
+impl<W: Write> FieldWriter<W> {
+    pub fn new(out: W, paths: &[&str], options: FieldOptions) -> FieldWriter<W> {
+        FieldWriter {
+            out: out,
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            options: options,
+            rows: 0,
+        }
+    }
+
+    pub fn write_packet(&mut self, packet: &Val) -> io::Result<()> {
+        let paths = self.paths.iter().map(String::as_str).collect::<Vec<_>>();
+
+        if self.rows == 0 && self.options.header {
+            let header = paths.iter().map(|p| quote(p, &self.options)).collect::<Vec<_>>();
+            writeln![self.out, "{}", header.join(&self.options.separator)]?;
+        }
+
+        self.rows += 1;
+        writeln![self.out, "{}", row(packet, &paths, &self.options)]
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// The row of fields for a packet, without a line ending.
+pub fn row(packet: &Val, paths: &[&str], options: &FieldOptions) -> String {
+    paths.iter()
+        .map(|path| {
+            let matches = values(packet, path);
+            let shown = match options.occurrence {
+                Occurrence::First => matches.first().map(|v| show(v)).unwrap_or_default(),
+                Occurrence::Last => matches.last().map(|v| show(v)).unwrap_or_default(),
+                Occurrence::All => matches.iter().map(|v| show(v)).collect::<Vec<_>>()
+                    .join(&options.aggregator),
+            };
+            quote(&shown, options)
+        })
+        .collect::<Vec<_>>()
+        .join(&options.separator)
+}
+
+/// All of the values at a path, in the order they appear in the packet.
+/// The path may start with a layer name; otherwise it starts at `packet`.
+pub fn values<'val, 'data>(packet: &'val Val<'data>, path: &str) -> Vec<&'val Val<'data>> {
+    let keys = path.split('.').collect::<Vec<_>>();
+
+    let mut found = Vec::new();
+    let mut matched = Vec::new();
+    layers(packet, keys[0], &mut found);
+
+    if found.is_empty() {
+        collect(packet, &keys, &mut matched);
+    } else {
+        for layer in found {
+            collect(layer, &keys[1..], &mut matched);
+        }
+    }
+
+    matched
+}
+
+/// Find every layer with a given name, including those of tunnelled packets.
+fn layers<'val, 'data>(val: &'val Val<'data>, name: &str, found: &mut Vec<&'val Val<'data>>) {
+    match *val {
+        Val::Object(n, ref values) => {
+            if n == name {
+                found.push(val);
+            }
+            for &(_, ref v) in values {
+                if let Val::Payload(_, Ok(ref inner)) = *v {
+                    layers(inner, name, found);
+                }
+            }
+        },
+        Val::Payload(_, Ok(ref inner)) => layers(inner, name, found),
+        _ => {},
+    }
+}
+
+fn collect<'val, 'data>(val: &'val Val<'data>, keys: &[&str], matched: &mut Vec<&'val Val<'data>>) {
+    let (key, rest) = match keys.split_first() {
+        Some((key, rest)) => (*key, rest),
+        None => {
+            match *val {
+                Val::List(ref values) => matched.extend(values.iter()),
+                _ => matched.push(val),
+            }
+            return;
+        },
+    };
+
+    match *val {
+        Val::Object(_, ref values) => for &(k, ref v) in values {
+            if k == key {
+                collect(v, rest, matched);
+            }
+        },
+        Val::List(ref values) => match key.parse::<usize>() {
+            Ok(i) => if let Some(v) = values.get(i) { collect(v, rest, matched) },
+            Err(_) => for v in values {
+                collect(v, keys, matched);
+            },
+        },
+        Val::Payload(_, Ok(ref inner)) => collect(inner, keys, matched),
+        _ => {},
+    }
+}
+
+fn quote(field: &str, options: &FieldOptions) -> String {
+    match options.quote {
+        Some(q) => {
+            let doubled = field.replace(q, &format!["{}{}", q, q]);
+            format!["{}{}{}", q, doubled, q]
+        },
+        None => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet() -> Val<'static> {
+        let inner = Val::Object("Inner", vec![
+            ("Name", Val::String("say \"hi\"".to_string())),
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(4)])),
+        ]);
+
+        Val::Object("Outer", vec![
+            ("Type", Val::Enum { value: 2048, name: Some("IPv4") }),
+            ("Option", Val::Unsigned(1)),
+            ("Option", Val::Unsigned(2)),
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Outer", vec![
+                ("Type", Val::Unsigned(7)),
+                ("Payload", Val::Payload(&[], Ok(Box::new(inner)))),
+            ]))))),
+        ])
+    }
+
+    #[test]
+    fn extract_fields() {
+        let packet = packet();
+        let mut options = FieldOptions::new();
+
+        assert_eq!(row(&packet, &["Outer.Type", "Outer.Option", "Inner.Hops", "Inner.Hops.1",
+                                  "Payload.Type", "Nonexistent"], &options),
+                   "2048,7\t1,2\t3,4\t4\t7\t");
+
+        options.occurrence = Occurrence::Last;
+        assert_eq!(row(&packet, &["Outer.Type", "Inner.Name"], &options), "7\tsay \"hi\"");
+    }
+
+    #[test]
+    fn write_csv() {
+        let mut writer = FieldWriter::new(Vec::new(), &["Outer.Type", "Inner.Name"],
+                                          FieldOptions::csv());
+        writer.write_packet(&packet()).unwrap();
+        writer.write_packet(&Val::Object("Outer", vec![])).unwrap();
+
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(),
+                   "\"Outer.Type\",\"Inner.Name\"\n\
+                    \"2048;7\",\"say \"\"hi\"\"\"\n\
+                    \"\",\"\"\n");
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Hex and ASCII dumps of packets, annotated with the fields that own their
+//! bytes.
+//!
+//! Each line shows an offset, the bytes in hex and as ASCII and the fields
+//! that those bytes were dissected into, as found by `provenance`: a field
+//! is named after its layer, e.g., "IPv4.Source", and bytes that belong to
+//! a layer but to none of its located fields (e.g., options decoded into
+//! numbers) are attributed to the layer itself. With `color`, each
+//! field's bytes and name are given an ANSI colour of their own.
+//!
+//! ```
+//! use rshark::output::hexdump::{hexdump, HexdumpOptions};
+//!
+//! let mut data = vec![0xff; 12];
+//! data.extend_from_slice(&[0x88, 0xb5, 1, 2]);
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let dump = hexdump(&data, &packet, &HexdumpOptions::new());
+//! assert_eq!(dump, "0000  ff ff ff ff ff ff ff ff  ff ff ff ff 88 b5 01 02  \
+//!                   ................  Ethernet frame.Destination, Ethernet frame.Source, \
+//!                   Ethernet frame, Ethernet frame.Payload\n");
+//! ```
+
+use Val;
+use provenance::{self, Node};
+
+/// Colours (ANSI SGR parameters) given to fields in turn.
+const PALETTE: [&'static str; 6] = ["31", "32", "33", "34", "35", "36"];
+
+/// How to dump a packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HexdumpOptions {
+    pub bytes_per_line: usize,
+
+    /// Colour each field's bytes and name.
+    pub color: bool,
+}
+
+impl HexdumpOptions {
+    pub fn new() -> HexdumpOptions {
+        HexdumpOptions { bytes_per_line: 16, color: false }
+    }
+}
+
+impl Default for HexdumpOptions {
+    fn default() -> HexdumpOptions {
+        HexdumpOptions::new()
+    }
+}
+
+/// Dump a packet, annotating each line with the fields of its dissection.
+pub fn hexdump(packet: &[u8], val: &Val, options: &HexdumpOptions) -> String {
+    let tree = provenance::annotate(packet, val);
+    let owners = (0..packet.len()).map(|i| owner(&tree, i)).collect::<Vec<_>>();
+
+    // Fields in the order they first appear, for colouring
+    let mut fields: Vec<&str> = Vec::new();
+    for owner in owners.iter().filter_map(|o| o.as_ref()) {
+        if !fields.contains(&owner.as_str()) {
+            fields.push(owner);
+        }
+    }
+    let color = |owner: &Option<String>| owner.as_ref()
+        .filter(|_| options.color)
+        .and_then(|o| fields.iter().position(|f| f == o))
+        .map(|i| PALETTE[i % PALETTE.len()]);
+
+    let per_line = options.bytes_per_line.max(1);
+    let mut out = String::new();
+
+    for (line, chunk) in packet.chunks(per_line).enumerate() {
+        let start = line * per_line;
+        let line_owners = &owners[start..start + chunk.len()];
+
+        let mut hex = String::new();
+        let mut ascii = String::new();
+        for (i, (&b, owner)) in chunk.iter().zip(line_owners).enumerate() {
+            if i > 0 {
+                hex.push(' ');
+            }
+            if i > 0 && i % 8 == 0 {
+                hex.push(' ');
+            }
+
+            let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' };
+            hex.push_str(&paint(&format!["{:02x}", b], color(owner)));
+            ascii.push_str(&paint(&c.to_string(), color(owner)));
+        }
+
+        // Pad short lines so that their ASCII and fields line up with the others'.
+        let width = per_line * 3 - 1 + (per_line - 1) / 8;
+        let shown = chunk.len() * 3 - 1 + (chunk.len() - 1) / 8;
+        hex.push_str(&" ".repeat(width - shown));
+
+        let mut names: Vec<String> = Vec::new();
+        for owner in line_owners {
+            if let Some(ref name) = *owner {
+                let painted = paint(name, color(owner));
+                if !names.contains(&painted) {
+                    names.push(painted);
+                }
+            }
+        }
+
+        out.push_str(&format!["{:04x}  {}  {}", start, hex, ascii]);
+        if !names.is_empty() {
+            out.push_str(&" ".repeat(per_line - chunk.len() + 2));
+            out.push_str(&names.join(", "));
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// The name of the innermost field that a byte was dissected into, prefixed
+/// by its layer's name.
+fn owner(root: &Node, offset: usize) -> Option<String> {
+    if !root.span.map(|s| s.contains(offset)).unwrap_or(false) {
+        return None;
+    }
+
+    let mut node = root;
+    let mut layer = root.name;
+    let mut fields: Vec<&str> = Vec::new();
+
+    while let Some(child) = node.children.iter().find(|c| c.span.map(|s| s.contains(offset))
+                                                                 .unwrap_or(false)) {
+        match *node.val {
+            Val::Payload(_, Ok(_)) => {
+                layer = child.name;
+                fields.clear();
+            },
+            _ if fields.last() != Some(&child.name) => fields.push(child.name),
+            _ => {},
+        }
+        node = child;
+    }
+
+    Some(match fields.len() {
+        0 => layer.to_string(),
+        _ => format!["{}.{}", layer, fields.join(".")],
+    })
+}
+
+fn paint(text: &str, color: Option<&str>) -> String {
+    match color {
+        Some(sgr) => format!["\x1b[{}m{}\x1b[0m", sgr, text],
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    // 10.0.0.1:5000 -> 10.0.0.2:53 over Ethernet, with a 2 B UDP payload
+    const PACKET: [u8; 44] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+        0x45, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        10, 0, 0, 1, 10, 0, 0, 2,
+        0x13, 0x88, 0x00, 0x35, 0x00, 0x0a, 0x00, 0x00, 0x41, 0x42];
+
+    #[test]
+    fn annotate_lines() {
+        let val = ethernet::dissect(&PACKET).unwrap();
+        let dump = hexdump(&PACKET, &val, &HexdumpOptions::new());
+        let lines = dump.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "0010  00 1e 00 00 00 00 40 11  00 00 0a 00 00 01 0a 00  \
+                              ......@.........  IPv4.Length, IPv4.Identification, \
+                              IPv4.Flags, IPv4, IPv4.TTL, IPv4.Protocol, IPv4.Checksum, \
+                              IPv4.Source, IPv4.Destination");
+        assert_eq!(lines[2], "0020  00 02 13 88 00 35 00 0a  00 00 41 42              \
+                              .....5....AB      IPv4.Destination, UDP.Source Port, \
+                              UDP.Destination Port, UDP.Length, UDP.Checksum, \
+                              Data.raw data");
+    }
+
+    #[test]
+    fn color_fields() {
+        let val = ethernet::dissect(&PACKET).unwrap();
+        let options = HexdumpOptions { bytes_per_line: 2, color: true };
+        let dump = hexdump(&PACKET, &val, &options);
+
+        assert!(dump.starts_with("0000  \x1b[31m00\x1b[0m \x1b[31m11\x1b[0m  \
+                                  \x1b[31m.\x1b[0m\x1b[31m.\x1b[0m  \
+                                  \x1b[31mEthernet frame.Destination\x1b[0m\n"));
+    }
+}
@@ -0,0 +1,247 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! JSON rendering of dissections, after `tshark -T json`.
+//!
+//! A `JsonWriter` writes a JSON array with one document per packet, each of
+//! which holds the packet's protocol layers in `_source.layers`. As with
+//! tshark, layers are listed side by side rather than nested within each
+//! other's payloads (unless `flatten` is turned off) and expert information
+//! appears as `_ws.expert` fields. Unlike tshark, fields that appear more
+//! than once in a layer are grouped into an array rather than written as
+//! duplicate keys, and numbers are written as JSON numbers.
+//!
+//! ```
+//! use rshark::output::json::{layers, JsonOptions};
+//!
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let json = layers(&packet, &JsonOptions::new());
+//! assert!(json.starts_with(r#"{"Ethernet frame":{"Destination":"00:00:00:00:00:00","#));
+//! ```
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use Val;
+use NamedValues;
+use conformance;
+use expert::{CONFORMANCE, EXPERT_INFO};
+use time::{format_seconds, format_timestamp, TimeFormat};
+use super::{iso8601, json_string};
+
+/// The field name of expert information (and of conformance warnings).
+pub const EXPERT_KEY: &'static str = "_ws.expert";
+
+/// How to render a dissection as JSON.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonOptions {
+    /// Write byte strings as colon-separated hex ("de:ad:be:ef") rather
+    /// than arrays of numbers.
+    pub hex_bytes: bool,
+
+    /// List encapsulated layers alongside their parents rather than within
+    /// the payload fields that carried them.
+    pub flatten: bool,
+
+    /// Include expert information and conformance warnings.
+    pub expert_info: bool,
+}
+
+impl JsonOptions {
+    pub fn new() -> JsonOptions {
+        JsonOptions { hex_bytes: true, flatten: true, expert_info: true }
+    }
+}
+
+impl Default for JsonOptions {
+    fn default() -> JsonOptions {
+        JsonOptions::new()
+    }
+}
+
+/// Writes packets as a JSON array, as `tshark -T json` does.
+pub struct JsonWriter<W: Write> {
+    out: W,
+    options: JsonOptions,
+    packets: u64,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(out: W, options: JsonOptions) -> JsonWriter<W> {
+        JsonWriter { out: out, options: options, packets: 0 }
+    }
+
+    /// Write the document of a packet captured at `timestamp`.
+    pub fn write_packet(&mut self, timestamp: Duration, packet: &Val) -> io::Result<()> {
+        self.out.write_all(if self.packets == 0 { b"[\n" } else { b",\n" })?;
+        self.packets += 1;
+
+        write![self.out, "  {{\"_index\":\"packets-{}\",\"_type\":\"doc\",\"_score\":null,\
+                          \"_source\":{{\"layers\":{}}}}}",
+               &iso8601(timestamp)[..10], layers(packet, &self.options)]
+    }
+
+    /// Close the array, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(if self.packets == 0 { b"[]\n" } else { b"\n]\n" })?;
+        Ok(self.out)
+    }
+}
+
+/// Render the layers of a dissection as a JSON object, keyed by layer name.
+pub fn layers(val: &Val, options: &JsonOptions) -> String {
+    let mut found = Vec::new();
+    if options.flatten {
+        flatten(val, options, &mut found);
+    } else if let Some((name, values)) = val.as_object() {
+        found.push((name, object(values, options)));
+    }
+
+    object_of(found)
+}
+
+/// The (flattened) layers of a dissection, outermost first.
+fn flatten(val: &Val, options: &JsonOptions, layers: &mut Vec<(&'static str, String)>) {
+    match val {
+        &Val::Object(name, ref values) => {
+            layers.push((name, object(values, options)));
+            for &(_, ref v) in values {
+                if let &Val::Payload(_, Ok(ref inner)) = v {
+                    flatten(inner, options, layers);
+                }
+            }
+        },
+        &Val::Payload(_, Ok(ref inner)) => flatten(inner, options, layers),
+        _ => {},
+    }
+}
+
+fn object(values: &NamedValues, options: &JsonOptions) -> String {
+    let fields = values.iter()
+        .filter_map(|&(key, ref v)| match (key, v) {
+            (EXPERT_INFO, _) | (conformance::WARNING, _) if !options.expert_info => None,
+            (conformance::WARNING, &Val::String(ref message)) =>
+                Some((EXPERT_KEY, format!["{{\"Code\":\"{}\",\"Severity\":\"Warn\",\
+                                           \"Message\":{}}}", CONFORMANCE, json_string(message)])),
+            (EXPERT_INFO, _) => value(v, options).map(|json| (EXPERT_KEY, json)),
+            (_, &Val::Payload(_, Ok(_))) if options.flatten => None,
+            _ => value(v, options).map(|json| (key, json)),
+        })
+        .collect();
+
+    object_of(fields)
+}
+
+/// A JSON object, grouping the values of repeated keys into arrays.
+fn object_of(fields: Vec<(&'static str, String)>) -> String {
+    let mut grouped: Vec<(&'static str, Vec<String>)> = Vec::new();
+    for (key, json) in fields {
+        match grouped.iter().position(|&(k, _)| k == key) {
+            Some(i) => grouped[i].1.push(json),
+            None => grouped.push((key, vec![json])),
+        }
+    }
+
+    let members = grouped.into_iter()
+        .map(|(key, mut values)| if values.len() == 1 {
+            format!["{}:{}", json_string(key), values.remove(0)]
+        } else {
+            format!["{}:[{}]", json_string(key), values.join(",")]
+        })
+        .collect::<Vec<_>>();
+
+    format!["{{{}}}", members.join(",")]
+}
+
+fn value(val: &Val, options: &JsonOptions) -> Option<String> {
+    Some(match val {
+        &Val::Signed(i) => i.to_string(),
+        &Val::Unsigned(u) => u.to_string(),
+        &Val::String(ref s) => json_string(s),
+        &Val::Symbol(s) => json_string(s),
+        &Val::Address { ref encoded, .. } => json_string(encoded),
+        &Val::BitFlags8(..) | &Val::BitFlags(..) => val.as_bitflags().unwrap().0.to_string(),
+        &Val::Enum { value, .. } => value.to_string(),
+        &Val::Timestamp(t) => json_string(&format_timestamp(t, TimeFormat::Utc)),
+        &Val::Duration(t) => format_seconds(t),
+        &Val::Object(_, ref values) => object(values, options),
+        &Val::List(ref values) => format!["[{}]", values.iter()
+            .filter_map(|v| value(v, options))
+            .collect::<Vec<_>>()
+            .join(",")],
+        &Val::Payload(_, Ok(ref inner)) => layers(inner, options),
+        &Val::Payload(_, Err(ref e)) => format!["{{\"_ws.malformed\":{}}}", json_string(&e.to_string())],
+        &Val::Bytes(bytes) | &Val::Undissected(_, bytes) => self::bytes(bytes, options),
+    })
+}
+
+fn bytes(bytes: &[u8], options: &JsonOptions) -> String {
+    if options.hex_bytes {
+        json_string(&bytes.iter().map(|b| format!["{:02x}", b]).collect::<Vec<_>>().join(":"))
+    } else {
+        format!["[{}]", bytes.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(",")]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use expert::{self, Severity};
+
+    fn packet() -> Val<'static> {
+        let mut inner = vec![
+            ("Name", Val::String("x".to_string())),
+            ("Data", Val::Bytes(&[0xde, 0xad])),
+            ("Data", Val::Bytes(&[0xbe, 0xef])),
+        ];
+        expert::add(&mut inner, "Name", "name.short", Severity::Note, "short");
+
+        Val::Object("Outer", vec![
+            ("Type", Val::Enum { value: 2048, name: Some("IPv4") }),
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(4)])),
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", inner))))),
+        ])
+    }
+
+    #[test]
+    fn render_layers() {
+        let mut options = JsonOptions::new();
+        assert_eq!(layers(&packet(), &options),
+                   "{\"Outer\":{\"Type\":2048,\"Hops\":[3,4]},\
+                    \"Inner\":{\"Name\":\"x\",\"Data\":[\"de:ad\",\"be:ef\"],\
+                    \"_ws.expert\":{\"Field\":\"Name\",\"Code\":\"name.short\",\
+                    \"Severity\":\"Note\",\"Message\":\"short\"}}}");
+
+        options.flatten = false;
+        options.hex_bytes = false;
+        options.expert_info = false;
+        assert_eq!(layers(&packet(), &options),
+                   "{\"Outer\":{\"Type\":2048,\"Hops\":[3,4],\
+                    \"Payload\":{\"Inner\":{\"Name\":\"x\",\"Data\":[[222,173],[190,239]]}}}}");
+    }
+
+    #[test]
+    fn write_packets() {
+        let mut writer = JsonWriter::new(Vec::new(), JsonOptions::new());
+        writer.write_packet(Duration::new(1444004800, 0), &Val::Object("A", vec![])).unwrap();
+        writer.write_packet(Duration::new(1444004801, 0), &Val::Object("B", vec![])).unwrap();
+
+        assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(),
+                   "[\n  {\"_index\":\"packets-2015-10-05\",\"_type\":\"doc\",\"_score\":null,\
+                    \"_source\":{\"layers\":{\"A\":{}}}},\n  \
+                    {\"_index\":\"packets-2015-10-05\",\"_type\":\"doc\",\"_score\":null,\
+                    \"_source\":{\"layers\":{\"B\":{}}}}\n]\n");
+
+        let empty = JsonWriter::new(Vec::new(), JsonOptions::new());
+        assert_eq!(empty.finish().unwrap(), b"[]\n");
+    }
+}
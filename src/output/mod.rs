@@ -0,0 +1,94 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Rendering of dissection results in formats understood by other tools.
+
+use std::time::Duration;
+
+use Val;
+use time;
+
+pub mod binary;
+pub mod conn_log;
+pub mod ek;
+pub mod eve;
+pub mod fields;
+pub mod hexdump;
+pub mod json;
+pub mod pcap;
+pub mod pdml;
+pub mod pretty;
+pub mod redact;
+pub mod strip;
+pub mod text;
+
+/// Quote and escape a string for inclusion in JSON output.
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!["\\u{:04x}", c as u32]),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// A value as other tools show it in fields: numbers with names as their
+/// numbers, strings without quotes and bytes as colon-separated hex.
+fn show(val: &Val) -> String {
+    match *val {
+        Val::String(ref s) => s.clone(),
+        Val::Enum { value, .. } => value.to_string(),
+        Val::BitFlags8(..) | Val::BitFlags(..) => val.as_bitflags().unwrap().0.to_string(),
+        Val::Bytes(bytes) | Val::Undissected(_, bytes) =>
+            bytes.iter().map(|b| format!["{:02x}", b]).collect::<Vec<_>>().join(":"),
+        ref other => other.to_string(),
+    }
+}
+
+/// Format a time since the Unix epoch as an ISO 8601 UTC timestamp with
+/// microsecond precision.
+fn iso8601(t: Duration) -> String {
+    let secs = t.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = time::civil_from_days(days as i64);
+
+    format!["{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}+0000",
+            year, month, day, secs / 3600, secs / 60 % 60, secs % 60,
+            t.subsec_nanos() / 1000]
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{iso8601, json_string};
+
+    #[test]
+    fn format_iso8601() {
+        assert_eq!(iso8601(Duration::new(0, 0)), "1970-01-01T00:00:00.000000+0000");
+        assert_eq!(iso8601(Duration::new(951782400, 0)), "2000-02-29T00:00:00.000000+0000");
+        assert_eq!(iso8601(Duration::new(1444004800, 123456789)), "2015-10-05T00:26:40.123456+0000");
+    }
+
+    #[test]
+    fn escape_json() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\"\\\n\x01"), "\"a \\\"b\\\"\\\\\\n\\u0001\"");
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Writing of classic libpcap capture files.
+//!
+//! See [the libpcap file format](https://wiki.wireshark.org/Development/LibpcapFileFormat).
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use checksum;
+
+/// libpcap link type for Ethernet.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+
+/// libpcap link type for raw IP packets (of either version).
+pub const LINKTYPE_RAW: u32 = 101;
+
+/// libpcap link types for raw IPv4 and IPv6 packets.
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+
+/// Writes packets to a (little-endian, microsecond-resolution) pcap file.
+pub struct PcapWriter<W: Write> {
+    out: W,
+    link_type: u32,
+    snaplen: u32,
+    fix_checksums: bool,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the file header for a capture of the given link type.
+    pub fn new(mut out: W, link_type: u32, snaplen: u32) -> io::Result<PcapWriter<W>> {
+        write_u32(&mut out, 0xa1b2c3d4)?;
+        out.write_all(&[2, 0, 4, 0])?;
+        write_u32(&mut out, 0)?;
+        write_u32(&mut out, 0)?;
+        write_u32(&mut out, snaplen)?;
+        write_u32(&mut out, link_type)?;
+
+        Ok(PcapWriter { out: out, link_type: link_type, snaplen: snaplen, fix_checksums: false })
+    }
+
+    /// Recompute IPv4, TCP and UDP lengths and checksums of Ethernet frames
+    /// as they are written, e.g., after rewriting or crafting packets.
+    ///
+    /// Frames that have been truncated (i.e., that are shorter than their
+    /// original length) are written unmodified.
+    pub fn fix_checksums(mut self, fix: bool) -> PcapWriter<W> {
+        self.fix_checksums = fix;
+        self
+    }
+
+    /// Write a packet captured at `timestamp` (since the Unix epoch) that was
+    /// `original_length` bytes long on the wire. Data beyond the snapshot
+    /// length is dropped.
+    pub fn write_packet(&mut self, timestamp: Duration, original_length: usize, data: &[u8])
+        -> io::Result<()> {
+
+        let mut fixed;
+        let mut data = data;
+        if self.fix_checksums && self.link_type == LINKTYPE_ETHERNET
+                && data.len() == original_length && data.len() > 14 && data[12..14] == [0x08, 0x00] {
+            fixed = data.to_vec();
+            checksum::fix_ipv4(&mut fixed[14..]);
+            data = &fixed;
+        }
+
+        let data = if data.len() > self.snaplen as usize { &data[..self.snaplen as usize] }
+                   else { data };
+
+        write_u32(&mut self.out, timestamp.as_secs() as u32)?;
+        write_u32(&mut self.out, timestamp.subsec_nanos() / 1000)?;
+        write_u32(&mut self.out, data.len() as u32)?;
+        write_u32(&mut self.out, original_length as u32)?;
+        self.out.write_all(data)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use checksum;
+
+    #[test]
+    fn write_pcap() {
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET, 4).unwrap();
+        writer.write_packet(Duration::new(1, 2000), 6, &[1, 2, 3, 4, 5, 6]).unwrap();
+
+        let file = writer.into_inner();
+        assert_eq!(file.len(), 24 + 16 + 4);
+        assert_eq!(&file[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&file[20..24], &[1, 0, 0, 0]);
+        assert_eq!(&file[24..40], &[1, 0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, 6, 0, 0, 0]);
+        assert_eq!(&file[40..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn write_fixed_checksums() {
+        let frame = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+            10, 0, 0, 1, 10, 0, 0, 2,
+            0xd4, 0x31, 0x00, 0x35, 0x00, 0x00, 0x00, 0x00];
+
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET, 65535).unwrap()
+            .fix_checksums(true);
+        writer.write_packet(Duration::new(0, 0), frame.len(), &frame).unwrap();
+
+        let file = writer.into_inner();
+        let ip = &file[40 + 14..];
+        assert_eq!(&ip[2..4], &[0, 28]);
+        assert_eq!(checksum::verify_ipv4_header(&ip[..20]), checksum::Status::Good);
+        assert_eq!(checksum::verify_transport(17, &ip[12..16], &ip[16..20], &ip[20..]),
+                   checksum::Status::Good);
+    }
+}
@@ -0,0 +1,255 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Wireshark's Packet Details Markup Language (PDML), as `tshark -T pdml`.
+//!
+//! Each packet is a `<packet>` element holding a `geninfo` protocol (frame
+//! number, length and capture time) followed by one `<proto>` per layer,
+//! with a (possibly nested) `<field>` for each value. Positions and sizes
+//! come from `provenance`: values that borrow the packet's bytes or are
+//! decoded from a fixed header layout have their own, while other decoded
+//! values (numbers, strings, etc.) are given the position of their layer
+//! and a size of zero, as Wireshark does for generated fields.
+//!
+//! rshark's field names aren't Wireshark's: a field is named after its
+//! layer and field names, lower-cased, e.g., `ipv4.source` rather than
+//! `ip.src`.
+//!
+//! ```
+//! use rshark::output::pdml;
+//!
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let xml = pdml::packet(1, std::time::Duration::new(0, 0), &data, &packet);
+//! assert!(xml.contains(r#"<field name="ethernet_frame.source" showname="Source: 00:00:00:00:00:00""#));
+//! assert!(xml.contains(r#"size="6" pos="6" show="00:00:00:00:00:00" value="000000000000"/>"#));
+//! ```
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use Val;
+use provenance::{self, Node, Span};
+use time::{format_timestamp, Time, TimeFormat};
+use super::show;
+
+/// Writes packets as a PDML document.
+pub struct PdmlWriter<W: Write> {
+    out: W,
+    packets: u64,
+}
+
+impl<W: Write> PdmlWriter<W> {
+    pub fn new(out: W) -> PdmlWriter<W> {
+        PdmlWriter { out: out, packets: 0 }
+    }
+
+    /// Write a packet captured at `timestamp` and its dissection.
+    pub fn write_packet(&mut self, timestamp: Duration, data: &[u8], packet: &Val)
+        -> io::Result<()> {
+
+        if self.packets == 0 {
+            self.header()?;
+        }
+
+        self.packets += 1;
+        self.out.write_all(self::packet(self.packets, timestamp, data, packet).as_bytes())
+    }
+
+    /// Close the document, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.packets == 0 {
+            self.header()?;
+        }
+
+        self.out.write_all(b"</pdml>\n")?;
+        Ok(self.out)
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        writeln![self.out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>"]?;
+        writeln![self.out, "<pdml version=\"0\" creator=\"rshark/{}\">", env!("CARGO_PKG_VERSION")]
+    }
+}
+
+/// Render the `<packet>` element of a packet's dissection, where `number`
+/// is the packet's (1-based) position in its capture.
+pub fn packet(number: u64, timestamp: Duration, data: &[u8], val: &Val) -> String {
+    let mut xml = String::from("<packet>\n");
+    let time = Time::from_duration(timestamp);
+
+    xml.push_str(&format!["  <proto name=\"geninfo\" pos=\"0\" showname=\"General information\" \
+                           size=\"{}\">\n", data.len()]);
+    for &(name, showname, ref show, ref value) in &[
+        ("num", "Number", number.to_string(), format!["{:x}", number]),
+        ("len", "Frame Length", data.len().to_string(), format!["{:x}", data.len()]),
+        ("caplen", "Captured Length", data.len().to_string(), format!["{:x}", data.len()]),
+        ("timestamp", "Captured Time", format_timestamp(time, TimeFormat::Utc),
+         format_timestamp(time, TimeFormat::Epoch)),
+    ] {
+        xml.push_str(&format!["    <field name=\"{}\" pos=\"0\" show=\"{}\" showname=\"{}\" \
+                               value=\"{}\" size=\"{}\"/>\n",
+                              name, escape(show), showname, escape(value), data.len()]);
+    }
+    xml.push_str("  </proto>\n");
+
+    protos(&provenance::annotate(data, val), data, &mut xml);
+    xml.push_str("</packet>\n");
+    xml
+}
+
+/// Write the `<proto>` of a layer, followed by those of the layers it
+/// encapsulates.
+fn protos(layer: &Node, data: &[u8], xml: &mut String) {
+    match *layer.val {
+        Val::Object(..) => {},
+        Val::Payload(_, Ok(_)) => return protos(&layer.children[0], data, xml),
+        _ => return,
+    }
+
+    let name = abbreviation(layer.name);
+    let span = layer.span.unwrap_or(Span { offset: 0, length: 0 });
+    xml.push_str(&format!["  <proto name=\"{}\" showname=\"{}\" size=\"{}\" pos=\"{}\">\n",
+                          name, escape(layer.name), span.length, span.offset]);
+
+    let mut encapsulated = Vec::new();
+    for child in &layer.children {
+        field(child, &name, span.offset, data, 2, xml, &mut encapsulated);
+    }
+    xml.push_str("  </proto>\n");
+
+    for inner in encapsulated {
+        protos(inner, data, xml);
+    }
+}
+
+/// Write the `<field>` of a value, saving any dissected payloads to be
+/// written as protocols of their own.
+fn field<'n, 'val, 'data>(node: &'n Node<'val, 'data>, prefix: &str, layer_pos: usize,
+                          data: &[u8], depth: usize, xml: &mut String,
+                          encapsulated: &mut Vec<&'n Node<'val, 'data>>) {
+
+    if let Val::Payload(_, Ok(_)) = *node.val {
+        encapsulated.push(&node.children[0]);
+        return;
+    }
+
+    let name = format!["{}.{}", prefix, abbreviation(node.name)];
+    let (show, showname) = match *node.val {
+        Val::Object(..) | Val::List(..) => (String::new(), node.name.to_string()),
+        Val::Payload(_, Err(ref e)) => (e.to_string(), format!["{}: {}", node.name, e]),
+        ref val => {
+            let show = show(val);
+            let showname = match *val {
+                Val::String(..) | Val::Bytes(..) | Val::Undissected(..) =>
+                    format!["{}: {}", node.name, show],
+                _ => format!["{}: {}", node.name, val],
+            };
+            (show, showname)
+        },
+    };
+
+    let indent = "  ".repeat(depth);
+    xml.push_str(&format!["{}<field name=\"{}\" showname=\"{}\"", indent, escape(&name),
+                          escape(&showname)]);
+
+    match node.span {
+        Some(span) => xml.push_str(&format![" size=\"{}\" pos=\"{}\" show=\"{}\" value=\"{}\"",
+                                            span.length, span.offset, escape(&show),
+                                            hex(&data[span.offset..span.end()])]),
+        None => xml.push_str(&format![" size=\"0\" pos=\"{}\" show=\"{}\"", layer_pos,
+                                      escape(&show)]),
+    }
+
+    if node.children.is_empty() {
+        xml.push_str("/>\n");
+        return;
+    }
+
+    xml.push_str(">\n");
+    for child in &node.children {
+        field(child, &name, layer_pos, data, depth + 1, xml, encapsulated);
+    }
+    xml.push_str(&format!["{}</field>\n", indent]);
+}
+
+/// A PDML name for a layer or field: lower-case, with anything but letters
+/// and digits replaced by underscores.
+fn abbreviation(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!["{:02x}", b]).collect()
+}
+
+/// Escape a string for use as an XML attribute value.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push_str(&format!["&#x{:x};", c as u32]),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!["\\x{:02x}", c as u32]),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    // 10.0.0.1:5000 -> 10.0.0.2:53 over Ethernet, with a 2 B UDP payload
+    const PACKET: [u8; 44] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+        0x45, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        10, 0, 0, 1, 10, 0, 0, 2,
+        0x13, 0x88, 0x00, 0x35, 0x00, 0x0a, 0x00, 0x00, 0xde, 0xad];
+
+    #[test]
+    fn render_packet() {
+        let val = ethernet::dissect(&PACKET).unwrap();
+        let xml = packet(7, Duration::new(1444004800, 0), &PACKET, &val);
+
+        assert!(xml.starts_with("<packet>\n  <proto name=\"geninfo\""));
+        assert!(xml.contains("<field name=\"num\" pos=\"0\" show=\"7\" showname=\"Number\" \
+                              value=\"7\" size=\"44\"/>"));
+        assert!(xml.contains("<proto name=\"ipv4\" showname=\"IPv4\" size=\"30\" pos=\"14\">"));
+        assert!(xml.contains("<field name=\"ipv4.source\" showname=\"Source: 10.0.0.1\" \
+                              size=\"4\" pos=\"26\" show=\"10.0.0.1\" value=\"0a000001\"/>"));
+        assert!(xml.contains("<field name=\"udp.destination_port\" \
+                              showname=\"Destination Port: domain (53)\" size=\"2\" pos=\"36\" \
+                              show=\"53\" value=\"0035\"/>"));
+        assert!(xml.contains("show=\"checksum likely offloaded to the NIC\"/>"));
+        let (ip, udp) = (xml.find("<proto name=\"ipv4\""), xml.find("<proto name=\"udp\""));
+        assert!(ip.unwrap() < udp.unwrap());
+        assert!(xml.ends_with("  </proto>\n</packet>\n"));
+    }
+
+    #[test]
+    fn write_document() {
+        let mut writer = PdmlWriter::new(Vec::new());
+        writer.write_packet(Duration::new(0, 0), &[], &Val::Object("A & B", vec![])).unwrap();
+        let xml = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<pdml version=\"0\""));
+        assert!(xml.contains("<proto name=\"a___b\" showname=\"A &amp; B\" size=\"0\" pos=\"0\">"));
+        assert!(xml.ends_with("</packet>\n</pdml>\n"));
+    }
+}
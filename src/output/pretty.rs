@@ -0,0 +1,338 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! A configurable pretty-printer for dissections, e.g., for terminals.
+//!
+//! A `PrettyPrinter` writes one line per value, indenting the fields of
+//! objects (and elements of lists) below them and following dissected
+//! payloads into the layers they carry. It can colour each kind of value
+//! with ANSI escapes, elide long byte strings, stop at a maximum depth,
+//! truncate lines to a width and show the offsets of values within their
+//! packet (see `provenance`). It writes to any `fmt::Write` or `io::Write`.
+//!
+//! ```
+//! use rshark::output::pretty::PrettyPrinter;
+//!
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let text = PrettyPrinter::new().offsets(&data).to_string(&packet);
+//! assert!(text.starts_with("Ethernet frame\n  Destination [0..6]: 00:00:00:00:00:00\n"));
+//! ```
+
+use std::fmt;
+use std::io;
+
+use Val;
+use provenance::locate;
+
+/// ANSI SGR parameters (e.g., "1;34" for bold blue) for each kind of value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Colors {
+    pub layer: &'static str,
+    pub name: &'static str,
+    pub number: &'static str,
+    pub string: &'static str,
+    pub address: &'static str,
+    pub bytes: &'static str,
+    pub time: &'static str,
+    pub error: &'static str,
+}
+
+impl Colors {
+    /// A palette for terminals with dark or light backgrounds.
+    pub fn ansi() -> Colors {
+        Colors {
+            layer: "1;34",
+            name: "36",
+            number: "33",
+            string: "32",
+            address: "35",
+            bytes: "2",
+            time: "34",
+            error: "1;31",
+        }
+    }
+}
+
+/// Writes dissections as indented text.
+#[derive(Clone, Debug)]
+pub struct PrettyPrinter<'packet> {
+    indent: usize,
+    indent_width: usize,
+    colors: Option<Colors>,
+    max_bytes: usize,
+    max_depth: Option<usize>,
+    width: Option<usize>,
+    packet: Option<&'packet [u8]>,
+}
+
+impl<'packet> PrettyPrinter<'packet> {
+    /// A printer without colours, offsets or limits, except that byte
+    /// strings are elided after 16 B.
+    pub fn new() -> PrettyPrinter<'packet> {
+        PrettyPrinter {
+            indent: 0,
+            indent_width: 2,
+            colors: None,
+            max_bytes: 16,
+            max_depth: None,
+            width: None,
+            packet: None,
+        }
+    }
+
+    /// Start at a level of indentation, e.g., to print part of a dissection.
+    pub fn indent(mut self, level: usize) -> PrettyPrinter<'packet> {
+        self.indent = level;
+        self
+    }
+
+    /// Spaces per level of indentation.
+    pub fn indent_width(mut self, spaces: usize) -> PrettyPrinter<'packet> {
+        self.indent_width = spaces;
+        self
+    }
+
+    pub fn colors(mut self, colors: Colors) -> PrettyPrinter<'packet> {
+        self.colors = Some(colors);
+        self
+    }
+
+    /// The most bytes of a byte string to show (in hex) before eliding the rest.
+    pub fn max_bytes(mut self, max: usize) -> PrettyPrinter<'packet> {
+        self.max_bytes = max;
+        self
+    }
+
+    /// The deepest level of fields to show, below which objects and lists
+    /// are summarized on one line.
+    pub fn max_depth(mut self, depth: usize) -> PrettyPrinter<'packet> {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Truncate lines to a width (not counting colour escapes).
+    pub fn width(mut self, columns: usize) -> PrettyPrinter<'packet> {
+        self.width = Some(columns);
+        self
+    }
+
+    /// Show the byte ranges of values within `packet`, the bytes that the
+    /// dissection came from.
+    pub fn offsets(mut self, packet: &'packet [u8]) -> PrettyPrinter<'packet> {
+        self.packet = Some(packet);
+        self
+    }
+
+    pub fn write<W: fmt::Write>(&self, out: &mut W, val: &Val) -> fmt::Result {
+        self.value(out, None, val, self.indent, 0)
+    }
+
+    pub fn write_io<W: io::Write>(&self, out: &mut W, val: &Val) -> io::Result<()> {
+        let mut adapter = Adapter { out: out, error: None };
+        match self.write(&mut adapter, val) {
+            Ok(()) => Ok(()),
+            Err(fmt::Error) => Err(adapter.error.unwrap_or_else(
+                || io::Error::new(io::ErrorKind::Other, "formatting error"))),
+        }
+    }
+
+    pub fn to_string(&self, val: &Val) -> String {
+        let mut s = String::new();
+        self.write(&mut s, val).unwrap();
+        s
+    }
+
+    /// Write a line for a value (named if it is a field) and lines for
+    /// whatever it contains.
+    fn value<W: fmt::Write>(&self, out: &mut W, name: Option<&str>, val: &Val,
+                            indent: usize, depth: usize) -> fmt::Result {
+
+        let prefix = " ".repeat(indent * self.indent_width);
+        let label = name.map(|n| match self.span(val) {
+            Some((start, end)) => format!["{} [{}..{}]", n, start, end],
+            None => n.to_string(),
+        });
+        let expand = self.max_depth.map(|max| depth < max).unwrap_or(true);
+
+        match *val {
+            Val::Object(layer, ref values) => {
+                let summary = if expand { "" } else { " ..." };
+                self.line(out, &prefix, label.as_ref().map(String::as_str),
+                          &format!["{}{}", layer, summary], self.color(|c| c.layer))?;
+
+                if expand {
+                    for &(k, ref v) in values {
+                        self.value(out, Some(k), v, indent + 1, depth + 1)?;
+                    }
+                }
+                Ok(())
+            },
+
+            Val::List(ref values) => {
+                self.line(out, &prefix, label.as_ref().map(String::as_str),
+                          &format!["{} elements{}", values.len(), if expand { "" } else { " ..." }],
+                          None)?;
+
+                if expand {
+                    for (i, v) in values.iter().enumerate() {
+                        self.value(out, Some(&i.to_string()), v, indent + 1, depth + 1)?;
+                    }
+                }
+                Ok(())
+            },
+
+            // Layers aren't indented below the payloads that carry them.
+            Val::Payload(_, Ok(ref inner)) => match label {
+                Some(ref label) => {
+                    self.line(out, &prefix, Some(label), "->", None)?;
+                    self.value(out, None, inner, indent, depth)
+                },
+                None => self.value(out, None, inner, indent, depth),
+            },
+
+            Val::Payload(_, Err(ref e)) =>
+                self.line(out, &prefix, label.as_ref().map(String::as_str),
+                          &format!["<< Error: {} >>", e], self.color(|c| c.error)),
+
+            ref leaf => self.line(out, &prefix, label.as_ref().map(String::as_str),
+                                  &self.leaf(leaf), self.color(|c| color_of(c, leaf))),
+        }
+    }
+
+    /// Write a line, truncated to the width and coloured.
+    fn line<W: fmt::Write>(&self, out: &mut W, prefix: &str, label: Option<&str>, text: &str,
+                           color: Option<&str>) -> fmt::Result {
+
+        let mut used = prefix.chars().count();
+        out.write_str(prefix)?;
+
+        if let Some(label) = label {
+            used += label.chars().count() + 2;
+            write![out, "{}: ", paint(label, self.color(|c| c.name))]?;
+        }
+
+        let text = match self.width {
+            Some(width) if used + text.chars().count() > width => {
+                let keep = width.saturating_sub(used + 3);
+                format!["{}...", text.chars().take(keep).collect::<String>()]
+            },
+            _ => text.to_string(),
+        };
+
+        writeln![out, "{}", paint(&text, color)]
+    }
+
+    fn leaf(&self, val: &Val) -> String {
+        match *val {
+            Val::Bytes(bytes) => self.bytes(bytes),
+            Val::Undissected(name, bytes) => format!["{}: {}", name, self.bytes(bytes)],
+            ref other => other.to_string(),
+        }
+    }
+
+    fn bytes(&self, bytes: &[u8]) -> String {
+        let shown = bytes.iter().take(self.max_bytes)
+            .map(|b| format!["{:02x}", b])
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let elided = if bytes.len() > self.max_bytes { " ..." } else { "" };
+        format!["{} B [{}{}]", bytes.len(), shown, elided]
+    }
+
+    /// The range of packet bytes a value borrows, if offsets are shown.
+    fn span(&self, val: &Val) -> Option<(usize, usize)> {
+        let bytes = match *val {
+            Val::Bytes(bytes) | Val::Address { bytes, .. } | Val::Undissected(_, bytes)
+                | Val::Payload(bytes, _) => bytes,
+            _ => return None,
+        };
+
+        self.packet.and_then(|p| locate(p, bytes)).map(|s| (s.offset, s.end()))
+    }
+
+    fn color<F: Fn(&Colors) -> &'static str>(&self, f: F) -> Option<&'static str> {
+        self.colors.as_ref().map(f)
+    }
+}
+
+impl<'packet> Default for PrettyPrinter<'packet> {
+    fn default() -> PrettyPrinter<'packet> {
+        PrettyPrinter::new()
+    }
+}
+
+fn color_of(colors: &Colors, val: &Val) -> &'static str {
+    match *val {
+        Val::Signed(_) | Val::Unsigned(_) | Val::Enum { .. } | Val::BitFlags8(..)
+            | Val::BitFlags(..) => colors.number,
+        Val::String(_) | Val::Symbol(_) => colors.string,
+        Val::Address { .. } => colors.address,
+        Val::Timestamp(_) | Val::Duration(_) => colors.time,
+        _ => colors.bytes,
+    }
+}
+
+fn paint(text: &str, color: Option<&str>) -> String {
+    match color {
+        Some(sgr) => format!["\x1b[{}m{}\x1b[0m", sgr, text],
+        None => text.to_string(),
+    }
+}
+
+/// Lets a `fmt::Write` printer write to an `io::Write`, keeping any I/O error.
+struct Adapter<'a, W: 'a + io::Write> {
+    out: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write> fmt::Write for Adapter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_all(s.as_bytes()).map_err(|e| { self.error = Some(e); fmt::Error })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(data: &[u8]) -> Val {
+        Val::Object("Outer", vec![
+            ("Type", Val::Enum { value: 2048, name: Some("IPv4") }),
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(4)])),
+            ("Payload", Val::Payload(data, Ok(Box::new(Val::Object("Inner", vec![
+                ("Name", Val::String("a long string".to_string())),
+                ("Data", Val::Bytes(&data[2..])),
+            ]))))),
+        ])
+    }
+
+    #[test]
+    fn print_options() {
+        let data = [0, 1, 2, 3, 4, 5];
+        let val = packet(&data);
+
+        assert_eq!(PrettyPrinter::new().to_string(&val),
+                   "Outer\n  Type: IPv4 (2048)\n  Hops: 2 elements\n    0: 3\n    1: 4\n  \
+                    Payload: ->\n  Inner\n    Name: \"a long string\"\n    \
+                    Data: 4 B [02 03 04 05]\n");
+
+        assert_eq!(PrettyPrinter::new().max_depth(1).max_bytes(2).width(16).offsets(&data)
+                       .to_string(&val),
+                   "Outer\n  Type: IPv4 ...\n  Hops: 2 ele...\n  \
+                    Payload [0..6]: ...\n  Inner ...\n");
+
+        let mut colored = Vec::new();
+        PrettyPrinter::new().colors(Colors::ansi()).max_depth(0)
+            .write_io(&mut colored, &val).unwrap();
+        assert_eq!(colored, b"\x1b[1;34mOuter ...\x1b[0m\n");
+    }
+}
@@ -0,0 +1,204 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Masking of sensitive fields before dissections are exported.
+//!
+//! A `Redactor` holds field paths and predicates registered by the user.
+//! Field paths start with the name of an object (e.g., a protocol layer) and
+//! continue with field names, so `IPv4.Source` matches the `Source` field of
+//! every IPv4 header and `SMB2.Body.Path` matches a field nested within it.
+//! Predicates are applied to every leaf value. Matching values (or whole
+//! sub-trees, for paths that name objects) are replaced by `REDACTED`.
+//!
+//! ```
+//! use rshark::Val;
+//! use rshark::output::redact::{Redactor, REDACTED};
+//!
+//! let redactor = Redactor::new().path("HTTP.Authorization").card_numbers();
+//! let val = redactor.redact(Val::Object("HTTP", vec![
+//!     ("Authorization", Val::String("Basic dXNlcjpwYXNz".to_string())),
+//!     ("Note", Val::String("card 4111 1111 1111 1111".to_string())),
+//! ]));
+//!
+//! assert_eq!(val["Authorization"].as_symbol(), Some(REDACTED));
+//! assert_eq!(val["Note"].as_symbol(), Some(REDACTED));
+//! ```
+
+use Val;
+
+/// The value that replaces redacted fields.
+pub const REDACTED: &'static str = "<redacted>";
+
+/// A set of rules describing which fields to mask.
+pub struct Redactor {
+    paths: Vec<String>,
+    predicates: Vec<Box<dyn Fn(&Val) -> bool>>,
+}
+
+impl Redactor {
+    pub fn new() -> Redactor {
+        Redactor { paths: Vec::new(), predicates: Vec::new() }
+    }
+
+    /// Redact the field (or object) at a path such as `HTTP.Authorization`.
+    pub fn path(mut self, path: &str) -> Redactor {
+        self.paths.push(path.to_string());
+        self
+    }
+
+    /// Redact any leaf value for which `predicate` returns true.
+    pub fn predicate<F>(mut self, predicate: F) -> Redactor
+        where F: Fn(&Val) -> bool + 'static {
+
+        self.predicates.push(Box::new(predicate));
+        self
+    }
+
+    /// Redact strings that contain something that looks like a payment card
+    /// number (13–19 digits, possibly grouped, passing the Luhn check).
+    pub fn card_numbers(self) -> Redactor {
+        self.predicate(|val| match val {
+            &Val::String(ref s) => contains_card_number(s),
+            _ => false,
+        })
+    }
+
+    /// Returns true if no rules have been registered.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.predicates.is_empty()
+    }
+
+    /// Apply the redaction rules to a dissected value.
+    pub fn redact<'data>(&self, val: Val<'data>) -> Val<'data> {
+        if self.is_empty() {
+            return val;
+        }
+
+        self.redact_at(val, &[])
+    }
+
+    /// Redact `val`, which can be reached from the enclosing objects via the
+    /// (partial) paths in `paths`.
+    fn redact_at<'data>(&self, val: Val<'data>, paths: &[String]) -> Val<'data> {
+        match val {
+            Val::Object(name, values) => {
+                let mut paths = paths.to_vec();
+                paths.push(name.to_string());
+
+                Val::Object(name, values.into_iter()
+                    .map(|(key, value)| {
+                        let paths = paths.iter()
+                            .map(|p| format!["{}.{}", p, key])
+                            .collect::<Vec<_>>();
+
+                        if paths.iter().any(|p| self.paths.contains(p)) {
+                            (key, Val::Symbol(REDACTED))
+                        } else {
+                            (key, self.redact_at(value, &paths))
+                        }
+                    })
+                    .collect())
+            },
+
+            Val::Payload(data, Ok(inner)) => Val::Payload(data, Ok(Box::new(self.redact_at(*inner, paths)))),
+            Val::Payload(data, Err(e)) => Val::Payload(data, Err(e)),
+
+            leaf => if self.predicates.iter().any(|p| p(&leaf)) { Val::Symbol(REDACTED) }
+                    else { leaf },
+        }
+    }
+}
+
+/// Returns true if `s` contains a run of 13–19 digits (which may be separated
+/// by single spaces or dashes) that passes the Luhn check.
+pub fn contains_card_number(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    let mut start = 0;
+
+    while start < bytes.len() {
+        if !(bytes[start] as char).is_digit(10) {
+            start += 1;
+            continue;
+        }
+
+        let mut digits = Vec::new();
+        let mut i = start;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'0'..=b'9' => digits.push(bytes[i] - b'0'),
+                b' ' | b'-' if i + 1 < bytes.len() && (bytes[i + 1] as char).is_digit(10)
+                               && (bytes[i - 1] as char).is_digit(10) => {},
+                _ => break,
+            }
+
+            i += 1;
+
+            if digits.len() >= 13 && luhn(&digits) {
+                return true;
+            }
+        }
+
+        start = i;
+    }
+
+    false
+}
+
+fn luhn(digits: &[u8]) -> bool {
+    if digits.len() < 13 || digits.len() > 19 {
+        return false;
+    }
+
+    let sum: u32 = digits.iter().rev().enumerate()
+        .map(|(i, &d)| match (i % 2, d * 2) {
+            (0, _) => d as u32,
+            (_, doubled) if doubled > 9 => doubled as u32 - 9,
+            (_, doubled) => doubled as u32,
+        })
+        .sum();
+
+    sum % 10 == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    #[test]
+    fn card_numbers() {
+        assert!(contains_card_number("4111111111111111"));
+        assert!(contains_card_number("pay with 4111-1111-1111-1111 today"));
+        assert!(!contains_card_number("4111111111111112"));
+        assert!(!contains_card_number("call 555 1234"));
+    }
+
+    #[test]
+    fn redact_paths() {
+        let data = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+            10, 0, 0, 1, 10, 0, 0, 2,
+            0xd4, 0x31, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+
+        let val = *ethernet::dissect(&data).unwrap();
+        let redactor = Redactor::new()
+            .path("IPv4.Source")
+            .path("UDP.Source Port")
+            .predicate(|v| v.as_unsigned() == Some(53));
+
+        let val = redactor.redact(val);
+        let ip = val.layer("IPv4").unwrap();
+        assert_eq!(ip["Source"].as_symbol(), Some(REDACTED));
+        assert_eq!(ip["Destination"].as_address_encoded(), Some("10.0.0.2"));
+        assert_eq!(ip["Payload"]["Source Port"].as_symbol(), Some(REDACTED));
+        assert_eq!(ip["Payload"]["Destination Port"].as_symbol(), Some(REDACTED));
+        assert_eq!(ip["Payload"]["Length"].as_unsigned(), Some(8));
+    }
+}
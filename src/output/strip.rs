@@ -0,0 +1,100 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Export of captures with application payloads stripped.
+//!
+//! Stripped packets keep their headers, timestamps and original lengths, so a
+//! capture can be shared for troubleshooting without the (potentially
+//! sensitive) content it carried.
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use Val;
+use super::pcap::PcapWriter;
+
+/// The offset at which a packet's transport-layer payload starts, i.e., the
+/// combined length of all headers up to and including TCP or UDP.
+///
+/// Returns `None` if the packet doesn't carry TCP or UDP.
+pub fn transport_payload_offset(packet: &Val) -> Option<usize> {
+    let (name, values) = match packet.as_object() {
+        Some(obj) => obj,
+        None => return None,
+    };
+
+    let field = |key| values.iter()
+        .find(|&&(k, _)| k == key)
+        .and_then(|&(_, ref v)| v.as_unsigned())
+        .map(|v| v as usize);
+
+    let header = match name {
+        "Ethernet frame" => 14,
+        "IPv4" => 4 * field("IHL").unwrap_or(0),
+        "TCP" => return field("Offset").map(|o| 4 * o),
+        "UDP" => return Some(8),
+        _ => return None,
+    };
+
+    values.iter()
+        .filter_map(|&(_, ref v)| match v {
+            &Val::Payload(_, Ok(ref inner)) => transport_payload_offset(inner),
+            _ => None,
+        })
+        .next()
+        .map(|offset| header + offset)
+}
+
+/// Write a packet with everything after the first `keep` bytes of its
+/// transport payload removed. Packets without TCP or UDP are written whole.
+pub fn write_stripped<W: Write>(writer: &mut PcapWriter<W>, timestamp: Duration,
+                                data: &[u8], packet: &Val, keep: usize) -> io::Result<()> {
+
+    let end = match transport_payload_offset(packet) {
+        Some(offset) if offset + keep < data.len() => offset + keep,
+        _ => data.len(),
+    };
+
+    writer.write_packet(timestamp, data.len(), &data[..end])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ethernet;
+    use output::pcap::{PcapWriter, LINKTYPE_ETHERNET};
+
+    const UDP: [u8; 46] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+        0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        10, 0, 0, 1, 10, 0, 0, 2,
+        0xd4, 0x31, 0x00, 0x35, 0x00, 0x0c, 0x00, 0x00,
+        b's', b'e', b'c', b'r'];
+
+    #[test]
+    fn payload_offset() {
+        let val = ethernet::dissect(&UDP).unwrap();
+        assert_eq!(transport_payload_offset(&val), Some(42));
+        assert_eq!(transport_payload_offset(&Val::Unsigned(1)), None);
+    }
+
+    #[test]
+    fn strip_payload() {
+        let val = ethernet::dissect(&UDP).unwrap();
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET, 65535).unwrap();
+
+        write_stripped(&mut writer, Duration::new(0, 0), &UDP, &val, 1).unwrap();
+
+        let file = writer.into_inner();
+        assert_eq!(&file[32..40], &[43, 0, 0, 0, 46, 0, 0, 0]);
+        assert_eq!(&file[40..], &UDP[..43]);
+    }
+}
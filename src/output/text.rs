@@ -0,0 +1,298 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Human-readable text rendering of dissections.
+//!
+//! Three styles are built in:
+//!
+//!  * `Compact`: one line per protocol layer, with its fields inline;
+//!  * `Tree`: every field on its own line, indented below its layer;
+//!  * `Verbose`: a lookalike of `tshark -V`, in which each layer is a
+//!    top-level section and only sub-structures within a layer are indented.
+//!
+//! ```
+//! use rshark::output::text::{render, RenderOptions, Style};
+//!
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let compact = render(&packet, &RenderOptions::new(Style::Compact));
+//! assert!(compact.starts_with("Ethernet frame: Destination=00:00:00:00:00:00,"));
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use Val;
+use time::{self, TimeFormat};
+
+/// A text rendering style.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Style {
+    Compact,
+    Tree,
+    Verbose,
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Style, String> {
+        match s {
+            "compact" => Ok(Style::Compact),
+            "tree" => Ok(Style::Tree),
+            "verbose" => Ok(Style::Verbose),
+            _ => Err(format!["unknown style '{}' (expected compact, tree or verbose)", s]),
+        }
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}", match *self {
+            Style::Compact => "compact",
+            Style::Tree => "tree",
+            Style::Verbose => "verbose",
+        }]
+    }
+}
+
+/// How to render a dissection as text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub style: Style,
+
+    /// Spaces per level of indentation.
+    pub indent: usize,
+
+    /// The most bytes of a byte string to show (in hex) before eliding the rest.
+    pub max_bytes: usize,
+
+    /// How to show timestamps.
+    pub time_format: TimeFormat,
+}
+
+impl RenderOptions {
+    pub fn new(style: Style) -> RenderOptions {
+        RenderOptions {
+            style: style,
+            indent: match style { Style::Verbose => 4, _ => 2 },
+            max_bytes: 16,
+            time_format: TimeFormat::Utc,
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions::new(Style::Tree)
+    }
+}
+
+/// Render a dissection (or part of one) as text, ending with a newline.
+pub fn render(val: &Val, options: &RenderOptions) -> String {
+    let mut out = String::new();
+
+    match options.style {
+        Style::Compact => compact(val, options, &mut out),
+        Style::Tree => tree(None, val, 0, options, &mut out),
+        Style::Verbose => verbose(val, 0, options, &mut out),
+    }
+
+    out
+}
+
+fn compact(val: &Val, options: &RenderOptions, out: &mut String) {
+    let (name, values) = match val.as_object() {
+        Some(obj) => obj,
+        None => {
+            out.push_str(&scalar(val, options));
+            out.push('\n');
+            return;
+        },
+    };
+
+    let mut payloads = Vec::new();
+    let fields = values.iter()
+        .filter_map(|&(k, ref v)| match v {
+            &Val::Payload(_, Ok(ref inner)) => { payloads.push(&**inner); None },
+            &Val::Payload(_, Err(ref e)) => Some(format!["{}=<<{}>>", k, e]),
+            _ => Some(format!["{}={}", k, inline_value(v, options)]),
+        })
+        .collect::<Vec<_>>();
+
+    out.push_str(&format!["{}: {}\n", name, fields.join(", ")]);
+
+    for inner in payloads {
+        compact(inner, options, out);
+    }
+}
+
+/// A sub-structure within a compact line.
+fn inline(val: &Val, options: &RenderOptions) -> String {
+    match val.as_object() {
+        Some((_, values)) => values.iter()
+            .map(|&(k, ref v)| format!["{}={}", k, inline_value(v, options)])
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => scalar(val, options),
+    }
+}
+
+fn inline_value(val: &Val, options: &RenderOptions) -> String {
+    match val {
+        &Val::Object(..) => format!["{{{}}}", inline(val, options)],
+        &Val::List(ref values) => format!["[{}]", values.iter()
+            .map(|v| inline_value(v, options))
+            .collect::<Vec<_>>()
+            .join(", ")],
+        _ => scalar(val, options),
+    }
+}
+
+fn tree(key: Option<&str>, val: &Val, depth: usize, options: &RenderOptions, out: &mut String) {
+    let prefix = " ".repeat(depth * options.indent);
+    let label = key.map(|k| format!["{}: ", k]).unwrap_or_default();
+
+    match val {
+        &Val::Object(name, ref values) => {
+            out.push_str(&format!["{}{}[{}]\n", prefix, label, name]);
+            for &(k, ref v) in values {
+                tree(Some(k), v, depth + 1, options, out);
+            }
+        },
+        &Val::List(ref values) => {
+            out.push_str(&format!["{}{}{} elements\n", prefix, label, values.len()]);
+            for (i, v) in values.iter().enumerate() {
+                tree(Some(&i.to_string()), v, depth + 1, options, out);
+            }
+        },
+        &Val::Payload(_, Ok(ref inner)) => tree(key, inner, depth, options, out),
+        &Val::Payload(_, Err(ref e)) => out.push_str(&format!["{}{}<< Error: {} >>\n", prefix, label, e]),
+        _ => out.push_str(&format!["{}{}{}\n", prefix, label, scalar(val, options)]),
+    }
+}
+
+fn verbose(val: &Val, depth: usize, options: &RenderOptions, out: &mut String) {
+    let (name, values) = match val.as_object() {
+        Some(obj) => obj,
+        None => {
+            out.push_str(&scalar(val, options));
+            out.push('\n');
+            return;
+        },
+    };
+
+    let prefix = " ".repeat(depth * options.indent);
+    if depth == 0 {
+        out.push_str(&format!["{}\n", name]);
+    }
+
+    // Encapsulated layers follow as sections of their own, as in tshark.
+    let mut payloads = Vec::new();
+    let field_prefix = format!["{}{}", prefix, " ".repeat(options.indent)];
+
+    for &(k, ref v) in values {
+        match v {
+            &Val::Payload(_, Ok(ref inner)) if depth == 0 => payloads.push(&**inner),
+            &Val::Payload(_, Ok(ref inner)) => {
+                out.push_str(&format!["{}{}\n", field_prefix, k]);
+                verbose_fields(inner, depth + 1, options, out);
+            },
+            &Val::Payload(_, Err(ref e)) =>
+                out.push_str(&format!["{}[Malformed {}: {}]\n", field_prefix, k, e]),
+            &Val::Object(..) => {
+                out.push_str(&format!["{}{}\n", field_prefix, k]);
+                verbose_fields(v, depth + 1, options, out);
+            },
+            &Val::List(ref elements) => {
+                out.push_str(&format!["{}{}\n", field_prefix, k]);
+                for e in elements {
+                    verbose_fields(e, depth + 1, options, out);
+                }
+            },
+            &Val::String(ref s) => out.push_str(&format!["{}{}: {}\n", field_prefix, k, s]),
+            _ => out.push_str(&format!["{}{}: {}\n", field_prefix, k, scalar(v, options)]),
+        }
+    }
+
+    for inner in payloads {
+        verbose(inner, 0, options, out);
+    }
+}
+
+/// The fields of a sub-structure, indented below the field that holds it.
+fn verbose_fields(val: &Val, depth: usize, options: &RenderOptions, out: &mut String) {
+    match val.as_object() {
+        Some(_) => verbose(val, depth, options, out),
+        None => {
+            out.push_str(&" ".repeat((depth + 1) * options.indent));
+            out.push_str(&scalar(val, options));
+            out.push('\n');
+        },
+    }
+}
+
+fn scalar(val: &Val, options: &RenderOptions) -> String {
+    match val {
+        &Val::Bytes(bytes) => hex(bytes, options.max_bytes),
+        &Val::Undissected(name, bytes) => format!["{}: {}", name, hex(bytes, options.max_bytes)],
+        &Val::Timestamp(t) => time::format_timestamp(t, options.time_format),
+        _ => val.to_string(),
+    }
+}
+
+fn hex(bytes: &[u8], max: usize) -> String {
+    let mut s = bytes.iter().take(max).map(|b| format!["{:02x}", b]).collect::<String>();
+    if bytes.len() > max {
+        s.push_str(&format!["... ({} B)", bytes.len()]);
+    }
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Val;
+
+    fn packet() -> Val<'static> {
+        Val::Object("Outer", vec![
+            ("Type", Val::Unsigned(2048)),
+            ("Flags", Val::Object("Flags", vec![("More", Val::Unsigned(1))])),
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(4)])),
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", vec![
+                ("Name", Val::String("x".to_string())),
+                ("Data", Val::Bytes(&[0xde, 0xad, 0xbe, 0xef])),
+            ]))))),
+        ])
+    }
+
+    #[test]
+    fn render_styles() {
+        let val = packet();
+
+        assert_eq!(render(&val, &RenderOptions::new(Style::Compact)),
+                   "Outer: Type=2048, Flags={More=1}, Hops=[3, 4]\nInner: Name=\"x\", Data=deadbeef\n");
+
+        assert_eq!(render(&val, &RenderOptions::new(Style::Tree)),
+                   "[Outer]\n  Type: 2048\n  Flags: [Flags]\n    More: 1\n  Hops: 2 elements\n    0: 3\n    1: 4\n  Payload: [Inner]\n    Name: \"x\"\n    Data: deadbeef\n");
+
+        let mut options = RenderOptions::new(Style::Verbose);
+        options.max_bytes = 2;
+        assert_eq!(render(&val, &options),
+                   "Outer\n    Type: 2048\n    Flags\n        More: 1\n    Hops\n        3\n        4\nInner\n    Name: x\n    Data: dead... (4 B)\n");
+
+        let mut options = RenderOptions::new(Style::Compact);
+        options.time_format = TimeFormat::Epoch;
+        assert_eq!(render(&Val::Timestamp(time::Time::new(1, 5)), &options), "1.000000005\n");
+
+        assert_eq!("verbose".parse::<Style>(), Ok(Style::Verbose));
+        assert!("pdml".parse::<Style>().is_err());
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of datacenter overlay encapsulations: VXLAN (UDP port 4789)
+//! and GENEVE (UDP port 6081).
+//!
+//! Both carry a tenant's frames between hypervisors; the inner frame is
+//! dissected in turn, so an overlay capture shows the outer and inner
+//! protocol stacks together.
+//!
+//! See [RFC 7348](https://tools.ietf.org/html/rfc7348) and
+//! [RFC 8926](https://tools.ietf.org/html/rfc8926).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use context::Context;
+use ethernet;
+use ip;
+use {read_be_u16, read_be_u32};
+
+pub const VXLAN_PORT: u16 = 4789;
+pub const GENEVE_PORT: u16 = 6081;
+
+pub fn dissect_vxlan(data : &[u8]) -> DissectResult {
+    dissect_vxlan_with(data, &mut Context::new())
+}
+
+/// Dissect a VXLAN header, and the frame it carries within a context.
+pub fn dissect_vxlan_with<'data>(data: &'data [u8], context: &mut Context)
+    -> DissectResult<'data> {
+
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "A VXLAN header must be 8 B".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Flags", Val::BitFlags8(data[0], [
+                 None, None, None, Some("VNI Valid"), None, None, None, None])));
+    values.push(("VNI", Val::Unsigned((read_be_u32(data, 4)? >> 8) as u64)));
+    let frame = &data[8..];
+    values.push(("Payload", Val::Payload(frame, context.dissect(ethernet::dissect_with, frame))));
+
+    Ok(Box::new(Val::Object("VXLAN", values)))
+}
+
+pub fn dissect_geneve(data : &[u8]) -> DissectResult {
+    dissect_geneve_with(data, &mut Context::new())
+}
+
+/// Dissect a GENEVE header, and the packet it carries within a context.
+pub fn dissect_geneve_with<'data>(data: &'data [u8], context: &mut Context)
+    -> DissectResult<'data> {
+
+    if data.len() < 8 {
+        return Err(DissectError::Underflow { expected: Some(8), have: data.len(),
+            message: "A GENEVE header must be at least 8 B".to_string() });
+    }
+
+    let options_length = (data[0] & 0x3f) as usize * 4;
+    let protocol = read_be_u16(data, 2)?;
+
+    let mut values = NamedValues::new();
+    values.push(("Version", Val::Unsigned((data[0] >> 6) as u64)));
+    values.push(("Options Length", Val::Unsigned(options_length as u64)));
+    values.push(("Flags", Val::BitFlags8(data[1], [
+                 None, None, None, None, None, None, Some("Critical Options"), Some("OAM")])));
+    values.push(("Protocol Type", Val::Unsigned(protocol as u64)));
+    values.push(("VNI", Val::Unsigned((read_be_u32(data, 4)? >> 8) as u64)));
+
+    if data.len() < 8 + options_length {
+        return Err(DissectError::Underflow { expected: Some(8 + options_length), have: data.len(),
+            message: "GENEVE options greater than available data".to_string() });
+    }
+
+    let mut options = &data[8..8 + options_length];
+    while !options.is_empty() {
+        if options.len() < 4 {
+            return Err(DissectError::Underflow { expected: Some(4), have: options.len(),
+                message: "A GENEVE option must be at least 4 B".to_string() });
+        }
+
+        let length = 4 + (options[3] & 0x1f) as usize * 4;
+        if options.len() < length {
+            return Err(DissectError::Underflow { expected: Some(length), have: options.len(),
+                message: "GENEVE option greater than available data".to_string() });
+        }
+
+        let mut option = NamedValues::new();
+        option.push(("Class", Val::Unsigned(read_be_u16(options, 0)? as u64)));
+        option.push(("Type", Val::Unsigned(options[2] as u64)));
+        option.push(("Critical", Val::Unsigned((options[2] >> 7) as u64)));
+        option.push(("Data", Val::Bytes(&options[4..length])));
+        values.push(("Option", Val::Object("GENEVE Option", option)));
+
+        options = &options[length..];
+    }
+
+    let payload = &data[8 + options_length..];
+    values.push(("Payload", match protocol {
+        0x6558 => Val::Payload(payload, context.dissect(ethernet::dissect_with, payload)),
+        0x0800 => Val::Payload(payload, context.dissect(ip::dissect_with, payload)),
+        0x86dd => Val::Payload(payload, context.dissect(ip::ipv6::dissect_with, payload)),
+        _ => Val::Undissected("Unknown", payload),
+    }));
+
+    Ok(Box::new(Val::Object("GENEVE", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INNER: [u8; 34] = [
+        0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00,
+        0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+
+    #[test]
+    fn dissect_overlays() {
+        let mut vxlan = vec![0x08, 0, 0, 0, 0x00, 0x12, 0x34, 0];
+        vxlan.extend_from_slice(&INNER);
+        let val = *dissect_vxlan(&vxlan).unwrap();
+        assert_eq!(val["VNI"].as_unsigned().unwrap(), 0x1234);
+        assert_eq!(val["Payload"]["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        // One 4 B option (class 0x0102, type 3) with 4 B of data
+        let mut geneve = vec![0x02, 0x00, 0x65, 0x58, 0x00, 0x00, 0x2a, 0,
+                              0x01, 0x02, 0x03, 0x01, 0xde, 0xad, 0xbe, 0xef];
+        geneve.extend_from_slice(&INNER);
+        let val = *dissect_geneve(&geneve).unwrap();
+        assert_eq!(val["VNI"].as_unsigned().unwrap(), 42);
+        assert_eq!(val["Option"]["Class"].as_unsigned().unwrap(), 0x0102);
+        assert_eq!(val["Option"]["Data"].as_bytes().unwrap(), &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(val["Payload"]["Payload"]["Source"].as_address_encoded().unwrap(), "10.0.0.1");
+
+        assert!(dissect_geneve(&geneve[..12]).is_err());
+    }
+}
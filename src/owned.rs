@@ -0,0 +1,181 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissections that own their bytes.
+//!
+//! A `Val` borrows the packet it was dissected from, which is what makes
+//! dissection cheap, but it can't outlive the capture buffer. `Val::to_owned`
+//! copies a dissection (including the bytes of its payloads and addresses)
+//! into an `OwnedVal`, which can be kept in a GUI's model or sent to another
+//! thread. `OwnedVal::as_val` borrows it back as a `Val` for the accessors,
+//! renderers and analyses that work on `Val`s.
+//!
+//! ```
+//! let owned = {
+//!     let data = vec![0; 14];
+//!     rshark::ethernet::dissect(&data).unwrap().to_owned()
+//! };
+//!
+//! let handle = std::thread::spawn(move || owned.as_val()["Source"].to_string());
+//! assert_eq!(handle.join().unwrap(), "00:00:00:00:00:00");
+//! ```
+
+use std::fmt;
+
+use DissectError;
+use Val;
+#[cfg(feature = "serde")]
+use serialization;
+use time::Time;
+
+/// Field, layer and bit names, and symbols. (An alias, so that serde
+/// doesn't try to borrow them from its input.)
+type Name = &'static str;
+
+/// A copy of a `Val` that owns all of its data.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename = "Val"))]
+pub enum OwnedVal {
+    Signed(i64),
+    Unsigned(u64),
+    String(String),
+    Symbol(
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "serialization::name"))]
+        Name),
+    Address { bytes: Vec<u8>, encoded: String },
+    BitFlags8(
+        u8,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "serialization::bit_names8"))]
+        [Option<Name>; 8]),
+    BitFlags(
+        u64,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "serialization::bit_names"))]
+        &'static [Option<Name>]),
+    Object(
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "serialization::name"))]
+        Name,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "serialization::fields"))]
+        Vec<(Name, OwnedVal)>),
+    List(Vec<OwnedVal>),
+    Payload(Vec<u8>, Result<Box<OwnedVal>, DissectError>),
+    Bytes(Vec<u8>),
+    Undissected(
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "serialization::name"))]
+        Name,
+        Vec<u8>),
+    Enum {
+        value: u64,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "serialization::optional_name"))]
+        name: Option<Name>,
+    },
+    Timestamp(Time),
+    Duration(Time),
+}
+
+impl<'data> Val<'data> {
+    /// Copy the value, and everything it contains, into an `OwnedVal`.
+    pub fn to_owned(&self) -> OwnedVal {
+        match *self {
+            Val::Signed(i) => OwnedVal::Signed(i),
+            Val::Unsigned(u) => OwnedVal::Unsigned(u),
+            Val::String(ref s) => OwnedVal::String(s.clone()),
+            Val::Symbol(s) => OwnedVal::Symbol(s),
+            Val::Address { bytes, ref encoded } =>
+                OwnedVal::Address { bytes: bytes.to_vec(), encoded: encoded.clone() },
+            Val::BitFlags8(flags, names) => OwnedVal::BitFlags8(flags, names),
+            Val::BitFlags(flags, names) => OwnedVal::BitFlags(flags, names),
+            Val::Object(name, ref values) =>
+                OwnedVal::Object(name, values.iter().map(|&(k, ref v)| (k, v.to_owned())).collect()),
+            Val::List(ref values) => OwnedVal::List(values.iter().map(Val::to_owned).collect()),
+            Val::Payload(bytes, ref result) => OwnedVal::Payload(bytes.to_vec(), match *result {
+                Ok(ref inner) => Ok(Box::new((**inner).to_owned())),
+                Err(ref e) => Err(e.clone()),
+            }),
+            Val::Bytes(bytes) => OwnedVal::Bytes(bytes.to_vec()),
+            Val::Undissected(name, bytes) => OwnedVal::Undissected(name, bytes.to_vec()),
+            Val::Enum { value, name } => OwnedVal::Enum { value: value, name: name },
+            Val::Timestamp(t) => OwnedVal::Timestamp(t),
+            Val::Duration(t) => OwnedVal::Duration(t),
+        }
+    }
+}
+
+impl OwnedVal {
+    /// Borrow the value as a `Val`.
+    pub fn as_val(&self) -> Val {
+        match *self {
+            OwnedVal::Signed(i) => Val::Signed(i),
+            OwnedVal::Unsigned(u) => Val::Unsigned(u),
+            OwnedVal::String(ref s) => Val::String(s.clone()),
+            OwnedVal::Symbol(s) => Val::Symbol(s),
+            OwnedVal::Address { ref bytes, ref encoded } =>
+                Val::Address { bytes: bytes, encoded: encoded.clone() },
+            OwnedVal::BitFlags8(flags, names) => Val::BitFlags8(flags, names),
+            OwnedVal::BitFlags(flags, names) => Val::BitFlags(flags, names),
+            OwnedVal::Object(name, ref values) =>
+                Val::Object(name, values.iter().map(|&(k, ref v)| (k, v.as_val())).collect()),
+            OwnedVal::List(ref values) => Val::List(values.iter().map(OwnedVal::as_val).collect()),
+            OwnedVal::Payload(ref bytes, ref result) => Val::Payload(bytes, match *result {
+                Ok(ref inner) => Ok(Box::new(inner.as_val())),
+                Err(ref e) => Err(e.clone()),
+            }),
+            OwnedVal::Bytes(ref bytes) => Val::Bytes(bytes),
+            OwnedVal::Undissected(name, ref bytes) => Val::Undissected(name, bytes),
+            OwnedVal::Enum { value, name } => Val::Enum { value: value, name: name },
+            OwnedVal::Timestamp(t) => Val::Timestamp(t),
+            OwnedVal::Duration(t) => Val::Duration(t),
+        }
+    }
+
+    /// A field of an object (or element of a list), following successfully
+    /// dissected payloads as `Val::get` does.
+    pub fn get(&self, index: &str) -> Option<&OwnedVal> {
+        match *self {
+            OwnedVal::Object(_, ref values) => values.iter().find(|&&(k, _)| k == index).map(|v| &v.1),
+            OwnedVal::List(ref values) => index.parse::<usize>().ok().and_then(|i| values.get(i)),
+            OwnedVal::Payload(_, Ok(ref inner)) => inner.get(index),
+            _ => None,
+        }
+    }
+
+    /// The value at a dot-separated path, e.g., "Payload.Source".
+    pub fn lookup(&self, path: &str) -> Option<&OwnedVal> {
+        path.split('.').fold(Some(self), |val, index| val.and_then(|v| v.get(index)))
+    }
+}
+
+impl fmt::Display for OwnedVal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{}", self.as_val()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    #[test]
+    fn round_trip() {
+        let data = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+                    0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+                    10, 0, 0, 1, 10, 0, 0, 2,
+                    0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+
+        let packet = ethernet::dissect(&data).unwrap();
+        let owned = packet.to_owned();
+
+        assert_eq!(owned.as_val(), *packet);
+        assert_eq!(owned.to_string(), packet.to_string());
+        assert_eq!(owned.lookup("Payload.Source"),
+                   Some(&OwnedVal::Address { bytes: vec![10, 0, 0, 1], encoded: "10.0.0.1".to_string() }));
+        assert_eq!(owned.lookup("Payload.Nonexistent"), None);
+    }
+}
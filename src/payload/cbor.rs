@@ -0,0 +1,215 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of CBOR (Concise Binary Object Representation) data items.
+//!
+//! Maps become "CBOR Map"s of alternating `Key` and `Value` fields, arrays
+//! become "CBOR Array"s of `Element`s and tagged items become "CBOR Tag"s.
+//! Floating-point numbers are formatted as strings; `false`, `true`, `null`
+//! and `undefined` are symbols. Indefinite-length text strings are joined,
+//! but indefinite-length byte strings are shown as their `Chunk`s.
+//!
+//! See [RFC 8949](https://tools.ietf.org/html/rfc8949).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use super::MAX_DEPTH;
+
+const BREAK: u8 = 0xff;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut offset = 0;
+    let mut values = NamedValues::new();
+
+    // A CBOR sequence (RFC 8742) is several items back to back.
+    while offset < data.len() {
+        values.push(("Item", item(data, &mut offset, 0)?));
+    }
+
+    Ok(Box::new(Val::Object("CBOR", values)))
+}
+
+fn truncated(data: &[u8], expected: usize) -> DissectError {
+    DissectError::Underflow { expected: Some(expected), have: data.len(),
+        message: "CBOR data item is truncated".to_string() }
+}
+
+fn bytes<'data>(data: &'data [u8], offset: &mut usize, length: u64)
+    -> Result<&'data [u8], DissectError> {
+
+    let end = (*offset as u64).checked_add(length).filter(|&e| e <= data.len() as u64)
+        .ok_or_else(|| truncated(data, offset.saturating_add(length as usize)))? as usize;
+    let bytes = &data[*offset..end];
+    *offset = end;
+    Ok(bytes)
+}
+
+/// The argument of an initial byte, or None for an indefinite length.
+fn argument(data: &[u8], offset: &mut usize, info: u8) -> Result<Option<u64>, DissectError> {
+    let length = match info {
+        0..=23 => return Ok(Some(info as u64)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => return Ok(None),
+        _ => return Err(DissectError::InvalidData(
+            format!["CBOR additional information {} is reserved", info])),
+    };
+
+    Ok(Some(bytes(data, offset, length)?.iter().fold(0, |n, &b| n << 8 | b as u64)))
+}
+
+fn item<'data>(data: &'data [u8], offset: &mut usize, depth: usize)
+    -> Result<Val<'data>, DissectError> {
+
+    if depth > MAX_DEPTH {
+        return Err(DissectError::InvalidData("CBOR data is nested too deeply".to_string()));
+    }
+
+    let initial = *data.get(*offset).ok_or_else(|| truncated(data, *offset + 1))?;
+    *offset += 1;
+    let major = initial >> 5;
+    let info = initial & 0x1f;
+
+    // Simple values and floats have their own meaning for the argument.
+    if major == 7 {
+        return Ok(match info {
+            20 => Val::Symbol("false"),
+            21 => Val::Symbol("true"),
+            22 => Val::Symbol("null"),
+            23 => Val::Symbol("undefined"),
+            24 => Val::Unsigned(bytes(data, offset, 1)?[0] as u64),
+            25 => {
+                let half = bytes(data, offset, 2)?;
+                Val::String(format!["{}", half_float((half[0] as u16) << 8 | half[1] as u16)])
+            },
+            26 => {
+                let bits = bytes(data, offset, 4)?.iter().fold(0, |n, &b| n << 8 | b as u32);
+                Val::String(format!["{}", f32::from_bits(bits)])
+            },
+            27 => {
+                let bits = bytes(data, offset, 8)?.iter().fold(0, |n, &b| n << 8 | b as u64);
+                Val::String(format!["{}", f64::from_bits(bits)])
+            },
+            31 => return Err(DissectError::InvalidData("CBOR break outside an item".to_string())),
+            _ => Val::Unsigned(info as u64),
+        });
+    }
+
+    let argument = argument(data, offset, info)?;
+    let indefinite = |major| DissectError::InvalidData(
+        format!["CBOR major type {} can't have an indefinite length", major]);
+
+    Ok(match (major, argument) {
+        (0, Some(n)) => Val::Unsigned(n),
+        (1, Some(n)) if n <= ::std::i64::MAX as u64 => Val::Signed(-1 - n as i64),
+        (1, Some(n)) => Val::String(format!["-{}", n as u128 + 1]),
+        (2, Some(length)) => Val::Bytes(bytes(data, offset, length)?),
+        (3, Some(length)) =>
+            Val::String(String::from_utf8_lossy(bytes(data, offset, length)?).into_owned()),
+
+        (2, None) => Val::Object("CBOR Chunked Bytes", items(data, offset, depth)?.into_iter()
+                                 .map(|chunk| ("Chunk", chunk)).collect()),
+        (3, None) => Val::String(items(data, offset, depth)?.iter()
+                                 .filter_map(|chunk| chunk.as_string()).collect()),
+
+        (4, length) => {
+            let elements = match length {
+                Some(length) => (0..length).map(|_| item(data, offset, depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => items(data, offset, depth)?,
+            };
+            Val::Object("CBOR Array", elements.into_iter().map(|e| ("Element", e)).collect())
+        },
+        (5, length) => {
+            let entries = match length {
+                Some(length) => (0..length.saturating_mul(2))
+                    .map(|_| item(data, offset, depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => items(data, offset, depth)?,
+            };
+            if entries.len() % 2 != 0 {
+                return Err(DissectError::InvalidData(
+                    "CBOR map has a key without a value".to_string()));
+            }
+            Val::Object("CBOR Map", entries.into_iter().enumerate()
+                        .map(|(i, e)| (if i % 2 == 0 { "Key" } else { "Value" }, e))
+                        .collect())
+        },
+        (6, Some(tag)) => {
+            let mut values = NamedValues::new();
+            values.push(("Tag", Val::Unsigned(tag)));
+            values.push(("Value", item(data, offset, depth + 1)?));
+            Val::Object("CBOR Tag", values)
+        },
+        (major, _) => return Err(indefinite(major)),
+    })
+}
+
+/// Items up to a break.
+fn items<'data>(data: &'data [u8], offset: &mut usize, depth: usize)
+    -> Result<Vec<Val<'data>>, DissectError> {
+
+    let mut items = Vec::new();
+    loop {
+        match data.get(*offset) {
+            Some(&BREAK) => { *offset += 1; return Ok(items); },
+            Some(_) => items.push(item(data, offset, depth + 1)?),
+            None => return Err(truncated(data, *offset + 1)),
+        }
+    }
+}
+
+/// An IEEE 754 half-precision number.
+fn half_float(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0. => ::std::f64::INFINITY,
+        31 => ::std::f64::NAN,
+        _ => (mantissa + 1024.) * 2f64.powi(exponent as i32 - 25),
+    };
+
+    if half & 0x8000 != 0 { -magnitude } else { magnitude }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_items() {
+        // {"a": [1, -2, 1.5], "b": h'0102'}, then 0("x") and [_ true, null]
+        let data = [0xa2, 0x61, b'a', 0x83, 0x01, 0x21, 0xf9, 0x3e, 0x00,
+                    0x61, b'b', 0x42, 1, 2,
+                    0xc0, 0x61, b'x',
+                    0x9f, 0xf5, 0xf6, 0xff];
+        let val = *dissect(&data).unwrap();
+        let items = val.as_object().unwrap().1;
+        assert_eq!(items.len(), 3);
+
+        let map = items[0].1.as_object().unwrap().1;
+        assert_eq!(map[0].1.as_string().unwrap(), "a");
+        let array = map[1].1.as_object().unwrap().1;
+        assert_eq!(array[0].1.as_unsigned().unwrap(), 1);
+        assert_eq!(array[1].1.as_signed().unwrap(), -2);
+        assert_eq!(array[2].1.as_string().unwrap(), "1.5");
+        assert_eq!(map[3].1.as_bytes().unwrap(), &[1, 2]);
+
+        assert_eq!(items[1].1["Tag"].as_unsigned().unwrap(), 0);
+        assert_eq!(items[2].1["Element"].as_symbol().unwrap(), "true");
+
+        assert!(dissect(&[0x83, 0x01]).is_err());
+        assert!(dissect(&[0x1c]).is_err());
+    }
+}
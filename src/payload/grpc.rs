@@ -0,0 +1,110 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of gRPC messages, as carried in HTTP/2 DATA frames (see
+//! `http2`).
+//!
+//! Each message is prefixed by a compressed flag and a 4 B length, and a
+//! DATA frame may hold several messages or part of one. The messages
+//! themselves are serialized protocol buffers: complete, uncompressed ones
+//! are decoded by `payload::protobuf`, naming their fields if `dissect_with`
+//! is given a registry that knows the message type.
+//!
+//! See [gRPC over HTTP2](https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use payload::protobuf;
+use read_be_u32;
+
+/// The conventional port of gRPC examples and many deployments.
+pub const PORT: u16 = 50051;
+
+/// Whether data is a sequence of length-prefixed gRPC messages, the last of
+/// which may be incomplete.
+pub fn looks_like_grpc(data: &[u8]) -> bool {
+    let mut offset = 0;
+    while offset + 5 <= data.len() {
+        if data[offset] > 1 {
+            return false;
+        }
+        offset += 5 + read_be_u32(data, offset + 1).unwrap() as usize;
+    }
+
+    offset == data.len() || (offset > data.len() && data.len() >= 5)
+}
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &protobuf::Registry::new(), None)
+}
+
+/// Dissect messages of a protobuf message type (by full name) from a
+/// registry, e.g., the type that `Registry::message_for_path` gives for the
+/// call's path.
+pub fn dissect_with<'data>(data: &'data [u8], registry: &protobuf::Registry, message: Option<&str>)
+    -> DissectResult<'data> {
+
+    let mut values = NamedValues::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let prefix = &data[offset..];
+        if prefix.len() < 5 {
+            return Err(DissectError::Underflow { expected: Some(offset + 5), have: data.len(),
+                message: "A gRPC message prefix must be 5 B".to_string() });
+        }
+
+        let compressed = prefix[0];
+        if compressed > 1 {
+            return Err(DissectError::InvalidData(
+                format!["gRPC compressed flag {} is invalid", compressed]));
+        }
+        let length = read_be_u32(prefix, 1)? as usize;
+
+        // The last message may continue in the next frame.
+        let end = 5usize.saturating_add(length).min(prefix.len());
+
+        let mut fields = NamedValues::new();
+        fields.push(("Compressed", Val::Unsigned(compressed as u64)));
+        fields.push(("Length", Val::Unsigned(length as u64)));
+        fields.push(("Data", Val::Bytes(&prefix[5..end])));
+        if compressed == 0 && end == 5 + length && length > 0 {
+            let message_data = &prefix[5..end];
+            fields.push(("Protobuf", Val::Payload(message_data,
+                                                  registry.decode(message, message_data))));
+        }
+        values.push(("Message", Val::Object("gRPC Message", fields)));
+
+        offset += end;
+    }
+
+    Ok(Box::new(Val::Object("gRPC", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_messages() {
+        let data = [0, 0, 0, 0, 2, 0x08, 0x01, 1, 0, 0, 0, 9, 0x1f, 0x8b];
+        assert!(looks_like_grpc(&data));
+
+        let val = *dissect(&data).unwrap();
+        let messages = val.as_object().unwrap().1;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].1["Data"].as_bytes().unwrap(), &[0x08, 0x01]);
+        assert_eq!(messages[0].1["Protobuf"]["Field"]["Value"].as_unsigned().unwrap(), 1);
+        assert_eq!(messages[1].1["Compressed"].as_unsigned().unwrap(), 1);
+
+        assert!(!looks_like_grpc(b"GET / HTTP/1.1\r\n"));
+    }
+}
@@ -0,0 +1,229 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of JSON documents.
+//!
+//! Objects become "JSON Object"s of alternating `Key` and `Value` fields
+//! (member names aren't known until run time, so they can't be field
+//! names), and arrays become "JSON Array"s of `Element`s. Numbers that are
+//! integers become `Signed` or `Unsigned` values and others are kept as
+//! written; `true`, `false` and `null` are symbols.
+//!
+//! See [RFC 8259](https://tools.ietf.org/html/rfc8259).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use super::MAX_DEPTH;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let text = ::std::str::from_utf8(data)
+        .map_err(|e| DissectError::InvalidData(format!["JSON must be UTF-8: {}", e]))?;
+
+    let mut parser = Parser { text: text, offset: 0 };
+    let value = parser.value(0)?;
+    parser.whitespace();
+    if parser.offset < text.len() {
+        return Err(parser.error("trailing data after the JSON value"));
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Value", value));
+    Ok(Box::new(Val::Object("JSON", values)))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> DissectError {
+        DissectError::InvalidData(format!["JSON {} at offset {}", message, self.offset])
+    }
+
+    fn whitespace(&mut self) {
+        let rest = &self.text[self.offset..];
+        self.offset += rest.len() - rest.trim_start_matches(|c| " \t\r\n".contains(c)).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.offset).cloned()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), DissectError> {
+        self.whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!["expected '{}'", c as char]));
+        }
+        self.offset += 1;
+        Ok(())
+    }
+
+    fn value<'data>(&mut self, depth: usize) -> Result<Val<'data>, DissectError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("is nested too deeply"));
+        }
+
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') => {
+                self.offset += 1;
+                let mut values = NamedValues::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.offset += 1;
+                } else {
+                    loop {
+                        self.whitespace();
+                        values.push(("Key", Val::String(self.string()?)));
+                        self.expect(b':')?;
+                        values.push(("Value", self.value(depth + 1)?));
+                        if !self.separator(b'}')? {
+                            break;
+                        }
+                    }
+                }
+                Ok(Val::Object("JSON Object", values))
+            },
+            Some(b'[') => {
+                self.offset += 1;
+                let mut values = NamedValues::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.offset += 1;
+                } else {
+                    loop {
+                        values.push(("Element", self.value(depth + 1)?));
+                        if !self.separator(b']')? {
+                            break;
+                        }
+                    }
+                }
+                Ok(Val::Object("JSON Array", values))
+            },
+            Some(b'"') => Ok(Val::String(self.string()?)),
+            Some(b't') => self.literal("true"),
+            Some(b'f') => self.literal("false"),
+            Some(b'n') => self.literal("null"),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    /// Consume a `,` (returning true) or the closing bracket (false).
+    fn separator(&mut self, close: u8) -> Result<bool, DissectError> {
+        self.whitespace();
+        match self.peek() {
+            Some(b',') => { self.offset += 1; Ok(true) },
+            Some(c) if c == close => { self.offset += 1; Ok(false) },
+            _ => Err(self.error(&format!["expected ',' or '{}'", close as char])),
+        }
+    }
+
+    fn literal<'data>(&mut self, literal: &'static str) -> Result<Val<'data>, DissectError> {
+        if !self.text[self.offset..].starts_with(literal) {
+            return Err(self.error("expected a value"));
+        }
+        self.offset += literal.len();
+        Ok(Val::Symbol(literal))
+    }
+
+    fn number<'data>(&mut self) -> Result<Val<'data>, DissectError> {
+        let rest = &self.text[self.offset..];
+        let length = rest.find(|c: char| !"+-.eE0123456789".contains(c)).unwrap_or(rest.len());
+        let number = &rest[..length];
+        self.offset += length;
+
+        if let Ok(n) = number.parse::<u64>() {
+            Ok(Val::Unsigned(n))
+        } else if let Ok(n) = number.parse::<i64>() {
+            Ok(Val::Signed(n))
+        } else if number.parse::<f64>().is_ok() {
+            Ok(Val::String(number.to_string()))
+        } else {
+            Err(self.error(&format!["number '{}' is invalid", number]))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DissectError> {
+        if self.peek() != Some(b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.offset += 1;
+
+        let mut s = String::new();
+        loop {
+            let rest = &self.text[self.offset..];
+            let end = rest.find(|c| c == '"' || c == '\\')
+                .ok_or_else(|| self.error("string is unterminated"))?;
+            s.push_str(&rest[..end]);
+            self.offset += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(s);
+            }
+
+            let escape = self.peek().ok_or_else(|| self.error("string is unterminated"))?;
+            self.offset += 1;
+            match escape {
+                b'"' | b'\\' | b'/' => s.push(escape as char),
+                b'b' => s.push('\u{8}'),
+                b'f' => s.push('\u{c}'),
+                b'n' => s.push('\n'),
+                b'r' => s.push('\r'),
+                b't' => s.push('\t'),
+                b'u' => {
+                    let mut c = self.hex4()?;
+                    // A surrogate pair
+                    if c >= 0xd800 && c < 0xdc00 && self.text[self.offset..].starts_with("\\u") {
+                        self.offset += 2;
+                        let low = self.hex4()?;
+                        c = 0x10000 + ((c - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                    }
+                    s.push(::std::char::from_u32(c).unwrap_or('\u{fffd}'));
+                },
+                _ => return Err(self.error("string escape is invalid")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, DissectError> {
+        let digits = self.text.get(self.offset..self.offset + 4)
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("\\u escape is invalid"))?;
+        self.offset += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_document() {
+        let val = *dissect(br#" {"name": "caf\u00e9", "ids": [1, -2, 3.5], "ok": true, "x": null} "#)
+            .unwrap();
+        let object = &val["Value"];
+        let members = object.as_object().unwrap().1;
+        assert_eq!(members[0].1.as_string().unwrap(), "name");
+        assert_eq!(members[1].1.as_string().unwrap(), "café");
+
+        let ids = members[3].1.as_object().unwrap().1;
+        assert_eq!(ids[0].1.as_unsigned().unwrap(), 1);
+        assert_eq!(ids[1].1.as_signed().unwrap(), -2);
+        assert_eq!(ids[2].1.as_string().unwrap(), "3.5");
+        assert_eq!(members[5].1.as_symbol().unwrap(), "true");
+
+        assert!(dissect(b"{\"a\": }").is_err());
+        assert!(dissect(b"[1, 2] 3").is_err());
+        assert!(dissect(b"\"unterminated").is_err());
+    }
+}
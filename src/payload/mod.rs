@@ -0,0 +1,121 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Decoding of application payloads: the RPC framings and serialization
+//! formats that protocol dissectors hand their contents to.
+//!
+//! Schema-based formats can only name their fields given a description of
+//! the schema, so their decoders take optional, user-supplied descriptors
+//! and otherwise show field numbers, types and raw values.
+//!
+//! Self-describing formats (JSON, XML, CBOR and MessagePack) are "content
+//! dissectors": protocols that carry bodies of a declared media type (e.g.,
+//! HTTP's `Content-Type`) look them up in `Contents`, where dissectors can
+//! also be registered for the bodies sent to or from a port.
+//!
+//! ```
+//! use rshark::payload::Contents;
+//!
+//! let contents = Contents::new();
+//! let body = contents.dissect("application/problem+json; charset=utf-8", b"{\"status\": 404}")
+//!     .unwrap()
+//!     .unwrap();
+//!
+//! assert_eq!(body["Value"]["Value"].as_unsigned().unwrap(), 404);
+//! ```
+
+use DissectResult;
+
+pub mod cbor;
+pub mod grpc;
+pub mod json;
+pub mod msgpack;
+pub mod protobuf;
+pub mod thrift;
+pub mod xml;
+
+/// How deeply the decoders will nest values, e.g., objects within arrays,
+/// within a single payload: deeper payloads are rejected rather than risking
+/// the stack. (Nesting of the dissectors themselves is bounded separately,
+/// by `context::MAX_DEPTH`.)
+pub const MAX_DEPTH: usize = 64;
+
+/// Dissects a structured body.
+pub type ContentDissector = fn(&[u8]) -> DissectResult;
+
+/// Content dissectors by media type and by port.
+#[derive(Clone)]
+pub struct Contents {
+    media_types: Vec<(&'static str, ContentDissector)>,
+    ports: Vec<(u16, ContentDissector)>,
+}
+
+impl Contents {
+    /// Dissectors for JSON, XML, CBOR and MessagePack, by their media types
+    /// and structured syntax suffixes (e.g., `application/soap+xml`).
+    pub fn new() -> Contents {
+        Contents {
+            media_types: vec![
+                ("application/json", json::dissect),
+                ("text/json", json::dissect),
+                ("+json", json::dissect),
+                ("application/xml", xml::dissect),
+                ("text/xml", xml::dissect),
+                ("+xml", xml::dissect),
+                ("application/cbor", cbor::dissect),
+                ("+cbor", cbor::dissect),
+                ("application/msgpack", msgpack::dissect),
+                ("application/x-msgpack", msgpack::dissect),
+                ("application/vnd.msgpack", msgpack::dissect),
+            ],
+            ports: Vec::new(),
+        }
+    }
+
+    /// Dissect bodies of a media type (or, if it starts with `+`, a
+    /// structured syntax suffix), in preference to any dissector already
+    /// registered for it.
+    pub fn media_type(mut self, media_type: &'static str, dissector: ContentDissector) -> Contents {
+        self.media_types.insert(0, (media_type, dissector));
+        self
+    }
+
+    /// Dissect the bodies sent to or from a port.
+    pub fn port(mut self, port: u16, dissector: ContentDissector) -> Contents {
+        self.ports.insert(0, (port, dissector));
+        self
+    }
+
+    /// The dissector for a content type, ignoring case and parameters.
+    pub fn for_media_type(&self, content_type: &str) -> Option<ContentDissector> {
+        let media_type = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+
+        self.media_types.iter()
+            .find(|&&(t, _)| t.eq_ignore_ascii_case(&media_type)
+                  || (t.starts_with('+') && media_type.ends_with(&t.to_lowercase())))
+            .map(|&(_, dissector)| dissector)
+    }
+
+    pub fn for_port(&self, port: u16) -> Option<ContentDissector> {
+        self.ports.iter().find(|&&(p, _)| p == port).map(|&(_, dissector)| dissector)
+    }
+
+    /// Dissect a body of a content type, if there is a dissector for it.
+    pub fn dissect<'data>(&self, content_type: &str, data: &'data [u8])
+        -> Option<DissectResult<'data>> {
+
+        self.for_media_type(content_type).map(|dissect| dissect(data))
+    }
+}
+
+impl Default for Contents {
+    fn default() -> Contents {
+        Contents::new()
+    }
+}
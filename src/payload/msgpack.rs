@@ -0,0 +1,166 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of MessagePack objects.
+//!
+//! Maps become "MessagePack Map"s of alternating `Key` and `Value` fields,
+//! arrays become "MessagePack Array"s of `Element`s and extension types
+//! become "MessagePack Extension"s with their `Type` and `Data`.
+//! Floating-point numbers are formatted as strings; `nil`, `false` and
+//! `true` are symbols.
+//!
+//! See the [specification](https://github.com/msgpack/msgpack/blob/master/spec.md).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use super::MAX_DEPTH;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut offset = 0;
+    let mut values = NamedValues::new();
+
+    // Streams of objects are common, e.g., in RPC.
+    while offset < data.len() {
+        values.push(("Object", object(data, &mut offset, 0)?));
+    }
+
+    Ok(Box::new(Val::Object("MessagePack", values)))
+}
+
+fn bytes<'data>(data: &'data [u8], offset: &mut usize, length: usize)
+    -> Result<&'data [u8], DissectError> {
+
+    let end = offset.checked_add(length).filter(|&e| e <= data.len())
+        .ok_or_else(|| DissectError::Underflow {
+            expected: Some(offset.saturating_add(length)), have: data.len(),
+            message: "MessagePack object is truncated".to_string() })?;
+    let bytes = &data[*offset..end];
+    *offset = end;
+    Ok(bytes)
+}
+
+/// A big-endian unsigned integer of `length` bytes.
+fn uint(data: &[u8], offset: &mut usize, length: usize) -> Result<u64, DissectError> {
+    Ok(bytes(data, offset, length)?.iter().fold(0, |n, &b| n << 8 | b as u64))
+}
+
+/// A big-endian signed integer of `length` bytes.
+fn int(data: &[u8], offset: &mut usize, length: usize) -> Result<i64, DissectError> {
+    let shift = 64 - 8 * length as u32;
+    Ok((uint(data, offset, length)? << shift) as i64 >> shift)
+}
+
+fn object<'data>(data: &'data [u8], offset: &mut usize, depth: usize)
+    -> Result<Val<'data>, DissectError> {
+
+    if depth > MAX_DEPTH {
+        return Err(DissectError::InvalidData("MessagePack data is nested too deeply".to_string()));
+    }
+
+    let format = uint(data, offset, 1)? as u8;
+    Ok(match format {
+        0x00..=0x7f => Val::Unsigned(format as u64),
+        0x80..=0x8f => map(data, offset, (format & 0x0f) as u64, depth)?,
+        0x90..=0x9f => array(data, offset, (format & 0x0f) as u64, depth)?,
+        0xa0..=0xbf => string(bytes(data, offset, (format & 0x1f) as usize)?),
+        0xc0 => Val::Symbol("nil"),
+        0xc2 => Val::Symbol("false"),
+        0xc3 => Val::Symbol("true"),
+        0xc4..=0xc6 => {
+            let length = uint(data, offset, 1 << (format - 0xc4))? as usize;
+            Val::Bytes(bytes(data, offset, length)?)
+        },
+        0xc7..=0xc9 => {
+            let length = uint(data, offset, 1 << (format - 0xc7))? as usize;
+            extension(data, offset, length)?
+        },
+        0xca => Val::String(format!["{}", f32::from_bits(uint(data, offset, 4)? as u32)]),
+        0xcb => Val::String(format!["{}", f64::from_bits(uint(data, offset, 8)?)]),
+        0xcc..=0xcf => Val::Unsigned(uint(data, offset, 1 << (format - 0xcc))?),
+        0xd0..=0xd3 => Val::Signed(int(data, offset, 1 << (format - 0xd0))?),
+        0xd4..=0xd8 => extension(data, offset, 1 << (format - 0xd4))?,
+        0xd9..=0xdb => {
+            let length = uint(data, offset, 1 << (format - 0xd9))? as usize;
+            string(bytes(data, offset, length)?)
+        },
+        0xdc | 0xdd => {
+            let length = uint(data, offset, 2 << (format - 0xdc))?;
+            array(data, offset, length, depth)?
+        },
+        0xde | 0xdf => {
+            let length = uint(data, offset, 2 << (format - 0xde))?;
+            map(data, offset, length, depth)?
+        },
+        0xe0..=0xff => Val::Signed(format as i8 as i64),
+        _ => return Err(DissectError::InvalidData(
+            format!["MessagePack format 0x{:02x} is never used", format])),
+    })
+}
+
+fn string<'data>(bytes: &[u8]) -> Val<'data> {
+    Val::String(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn array<'data>(data: &'data [u8], offset: &mut usize, length: u64, depth: usize)
+    -> Result<Val<'data>, DissectError> {
+
+    let mut values = NamedValues::new();
+    for _ in 0..length {
+        values.push(("Element", object(data, offset, depth + 1)?));
+    }
+    Ok(Val::Object("MessagePack Array", values))
+}
+
+fn map<'data>(data: &'data [u8], offset: &mut usize, length: u64, depth: usize)
+    -> Result<Val<'data>, DissectError> {
+
+    let mut values = NamedValues::new();
+    for _ in 0..length {
+        values.push(("Key", object(data, offset, depth + 1)?));
+        values.push(("Value", object(data, offset, depth + 1)?));
+    }
+    Ok(Val::Object("MessagePack Map", values))
+}
+
+fn extension<'data>(data: &'data [u8], offset: &mut usize, length: usize)
+    -> Result<Val<'data>, DissectError> {
+
+    let mut values = NamedValues::new();
+    values.push(("Type", Val::Signed(int(data, offset, 1)?)));
+    values.push(("Data", Val::Bytes(bytes(data, offset, length)?)));
+    Ok(Val::Object("MessagePack Extension", values))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_objects() {
+        // {"id": 300, "tags": ["a", -3]}, then nil
+        let data = [0x82, 0xa2, b'i', b'd', 0xcd, 0x01, 0x2c,
+                    0xa4, b't', b'a', b'g', b's', 0x92, 0xa1, b'a', 0xfd,
+                    0xc0];
+        let val = *dissect(&data).unwrap();
+        let objects = val.as_object().unwrap().1;
+        assert_eq!(objects.len(), 2);
+
+        let map = objects[0].1.as_object().unwrap().1;
+        assert_eq!(map[0].1.as_string().unwrap(), "id");
+        assert_eq!(map[1].1.as_unsigned().unwrap(), 300);
+        assert_eq!(map[3].1["Element"].as_string().unwrap(), "a");
+        assert_eq!(map[3].1.as_object().unwrap().1[1].1.as_signed().unwrap(), -3);
+        assert_eq!(objects[1].1.as_symbol().unwrap(), "nil");
+
+        assert!(dissect(&[0x92, 0x01]).is_err());
+        assert!(dissect(&[0xc1]).is_err());
+    }
+}
@@ -0,0 +1,482 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Decoding of Protocol Buffers wire format.
+//!
+//! The wire format only tags fields with their numbers and a wire type
+//! (varint, 64 b, length-delimited or 32 b), so without a schema `dissect`
+//! can only show those and the raw values. A `Registry` loads schemas from
+//! compiled `FileDescriptorSet`s (as written by `protoc
+//! --descriptor_set_out`) and names the message type expected on a port or
+//! a gRPC path; `Registry::decode` then gives fields their names and typed
+//! values, with enums by name and nested messages decoded recursively.
+//!
+//! ```
+//! use rshark::payload::protobuf;
+//!
+//! // Field 1, varint 150
+//! let val = protobuf::dissect(&[0x08, 0x96, 0x01]).unwrap();
+//! assert_eq!(val["Field"]["Number"].as_unsigned().unwrap(), 1);
+//! assert_eq!(val["Field"]["Value"].as_unsigned().unwrap(), 150);
+//! ```
+//!
+//! See the [encoding guide](https://protobuf.dev/programming-guides/encoding/).
+
+use std::collections::HashMap;
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use intern::Interner;
+use super::MAX_DEPTH;
+
+enum_map!(pub WIRE_TYPES {
+    0 => "Varint",
+    1 => "64-bit",
+    2 => "Length-delimited",
+    3 => "Start Group",
+    4 => "End Group",
+    5 => "32-bit",
+});
+
+// Field types, from descriptor.proto
+const DOUBLE: u64 = 1;
+const FLOAT: u64 = 2;
+const INT64: u64 = 3;
+const UINT64: u64 = 4;
+const INT32: u64 = 5;
+const FIXED64: u64 = 6;
+const FIXED32: u64 = 7;
+const BOOL: u64 = 8;
+const STRING: u64 = 9;
+const MESSAGE: u64 = 11;
+const BYTES: u64 = 12;
+const UINT32: u64 = 13;
+const ENUM: u64 = 14;
+const SFIXED32: u64 = 15;
+const SFIXED64: u64 = 16;
+const SINT32: u64 = 17;
+const SINT64: u64 = 18;
+
+/// A field's value as encoded on the wire.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Wire<'data> {
+    Varint(u64),
+    Fixed64(u64),
+    Delimited(&'data [u8]),
+    Fixed32(u32),
+}
+
+impl<'data> Wire<'data> {
+    fn wire_type(&self) -> u8 {
+        match *self {
+            Wire::Varint(_) => 0,
+            Wire::Fixed64(_) => 1,
+            Wire::Delimited(_) => 2,
+            Wire::Fixed32(_) => 5,
+        }
+    }
+
+    fn number(&self) -> u64 {
+        match *self {
+            Wire::Varint(n) | Wire::Fixed64(n) => n,
+            Wire::Fixed32(n) => n as u64,
+            Wire::Delimited(_) => 0,
+        }
+    }
+
+    fn string(&self) -> String {
+        match *self {
+            Wire::Delimited(s) => String::from_utf8_lossy(s).into_owned(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn varint(data: &[u8], offset: &mut usize) -> Result<u64, DissectError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let b = *data.get(*offset).ok_or_else(|| truncated(data, *offset + 1))?;
+        *offset += 1;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(DissectError::InvalidData("Protobuf varint is too long".to_string()))
+}
+
+fn fixed(data: &[u8], offset: &mut usize, length: usize) -> Result<u64, DissectError> {
+    let bytes = data.get(*offset..*offset + length).ok_or_else(|| truncated(data, *offset + length))?;
+    *offset += length;
+    Ok(bytes.iter().rev().fold(0, |n, &b| n << 8 | b as u64))
+}
+
+fn truncated(data: &[u8], expected: usize) -> DissectError {
+    DissectError::Underflow { expected: Some(expected), have: data.len(),
+        message: "Protobuf message is truncated".to_string() }
+}
+
+/// Split a message into its fields.
+fn fields(data: &[u8]) -> Result<Vec<(u32, Wire)>, DissectError> {
+    let mut fields = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let key = varint(data, &mut offset)?;
+        let number = (key >> 3) as u32;
+        if number == 0 {
+            return Err(DissectError::InvalidData("Protobuf field number 0 is invalid".to_string()));
+        }
+
+        let value = match key & 7 {
+            0 => Wire::Varint(varint(data, &mut offset)?),
+            1 => Wire::Fixed64(fixed(data, &mut offset, 8)?),
+            2 => {
+                let length = varint(data, &mut offset)? as usize;
+                let end = offset.checked_add(length).filter(|&e| e <= data.len())
+                    .ok_or_else(|| truncated(data, offset.saturating_add(length)))?;
+                let bytes = &data[offset..end];
+                offset = end;
+                Wire::Delimited(bytes)
+            },
+            5 => Wire::Fixed32(fixed(data, &mut offset, 4)? as u32),
+            wire_type => return Err(DissectError::InvalidData(
+                format!["Protobuf wire type {} ({}) is not supported", wire_type,
+                        WIRE_TYPES.format(wire_type)])),
+        };
+        fields.push((number, value));
+    }
+
+    Ok(fields)
+}
+
+/// Decode a message without a schema, showing field numbers, wire types and
+/// raw values.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    Registry::new().decode(None, data)
+}
+
+/// A field of a message type.
+#[derive(Clone, Debug)]
+struct FieldType {
+    name: &'static str,
+    kind: u64,
+
+    /// The full name of a message or enum type, without a leading dot.
+    type_name: String,
+}
+
+#[derive(Clone, Debug)]
+struct MessageType {
+    name: &'static str,
+    fields: HashMap<u32, FieldType>,
+}
+
+/// Message types loaded from descriptor sets, and the message types
+/// expected on ports and gRPC paths.
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    names: Interner,
+    messages: HashMap<String, MessageType>,
+    enums: HashMap<String, HashMap<i32, &'static str>>,
+    ports: HashMap<u16, String>,
+    paths: HashMap<String, String>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Load the message and enum types of a serialized `FileDescriptorSet`.
+    pub fn load(&mut self, descriptor_set: &[u8]) -> Result<(), DissectError> {
+        for (number, file) in fields(descriptor_set)? {
+            if let (1, Wire::Delimited(file)) = (number, file) {
+                let entries = fields(file)?;
+                let package = entries.iter().find(|&&(n, _)| n == 2).map(|&(_, ref p)| p.string())
+                    .unwrap_or_default();
+
+                for &(number, ref value) in &entries {
+                    match (number, *value) {
+                        (4, Wire::Delimited(message)) => self.load_message(&package, message)?,
+                        (5, Wire::Delimited(e)) => self.load_enum(&package, e)?,
+                        _ => {},
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A `DescriptorProto`, within a package or message named `scope`.
+    fn load_message(&mut self, scope: &str, descriptor: &[u8]) -> Result<(), DissectError> {
+        let entries = fields(descriptor)?;
+        let name = entries.iter().find(|&&(n, _)| n == 1).map(|&(_, ref n)| n.string())
+            .unwrap_or_default();
+        let full_name = if scope.is_empty() { name.clone() } else { format!["{}.{}", scope, name] };
+
+        let mut message = MessageType { name: self.names.intern(&name)?, fields: HashMap::new() };
+        for &(number, ref value) in &entries {
+            match (number, *value) {
+                (2, Wire::Delimited(field)) => {
+                    let field = fields(field)?;
+                    let get = |n| field.iter().find(|&&(number, _)| number == n).map(|&(_, ref v)| *v);
+
+                    let number = get(3).map(|n| n.number()).unwrap_or(0) as u32;
+                    let name = get(1).map(|n| n.string()).unwrap_or_default();
+                    message.fields.insert(number, FieldType {
+                        name: self.names.intern(&name)?,
+                        kind: get(5).map(|t| t.number()).unwrap_or(0),
+                        type_name: get(6).map(|t| t.string()).unwrap_or_default()
+                            .trim_start_matches('.').to_string(),
+                    });
+                },
+                (3, Wire::Delimited(nested)) => self.load_message(&full_name, nested)?,
+                (4, Wire::Delimited(e)) => self.load_enum(&full_name, e)?,
+                _ => {},
+            }
+        }
+
+        self.messages.insert(full_name, message);
+        Ok(())
+    }
+
+    /// An `EnumDescriptorProto`.
+    fn load_enum(&mut self, scope: &str, descriptor: &[u8]) -> Result<(), DissectError> {
+        let mut name = String::new();
+        let mut values = HashMap::new();
+
+        for (number, value) in fields(descriptor)? {
+            match (number, value) {
+                (1, value) => name = value.string(),
+                (2, Wire::Delimited(value)) => {
+                    let value = fields(value)?;
+                    let label = value.iter().find(|&&(n, _)| n == 1).map(|&(_, ref v)| v.string());
+                    let number = value.iter().find(|&&(n, _)| n == 2).map(|&(_, ref v)| v.number());
+                    if let (Some(label), Some(number)) = (label, number) {
+                        values.insert(number as i32, self.names.intern(&label)?);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let full_name = if scope.is_empty() { name } else { format!["{}.{}", scope, name] };
+        self.enums.insert(full_name, values);
+        Ok(())
+    }
+
+    /// Expect messages of a type (by full name, e.g., `pkg.Request`) on a
+    /// port.
+    pub fn bind_port(&mut self, port: u16, message: &str) {
+        self.ports.insert(port, message.to_string());
+    }
+
+    /// Expect messages of a type on a gRPC path, e.g.,
+    /// `/pkg.Service/Method`.
+    pub fn bind_path(&mut self, path: &str, message: &str) {
+        self.paths.insert(path.to_string(), message.to_string());
+    }
+
+    pub fn message_for_port(&self, port: u16) -> Option<&str> {
+        self.ports.get(&port).map(String::as_str)
+    }
+
+    pub fn message_for_path(&self, path: &str) -> Option<&str> {
+        self.paths.get(path).map(String::as_str)
+    }
+
+    /// Whether a message type has been loaded.
+    pub fn contains(&self, message: &str) -> bool {
+        self.messages.contains_key(message)
+    }
+
+    /// Decode a message of the named type, or without a schema if there is
+    /// no name or it hasn't been loaded.
+    pub fn decode<'data>(&self, message: Option<&str>, data: &'data [u8]) -> DissectResult<'data> {
+        self.message(message.and_then(|m| self.messages.get(m)), data, 0).map(Box::new)
+    }
+
+    fn message<'data>(&self, message: Option<&MessageType>, data: &'data [u8], depth: usize)
+        -> Result<Val<'data>, DissectError> {
+
+        if depth > MAX_DEPTH {
+            return Err(DissectError::InvalidData("Protobuf data is nested too deeply".to_string()));
+        }
+
+        let mut values = NamedValues::new();
+        for (number, wire) in fields(data)? {
+            let field = message.and_then(|m| m.fields.get(&number));
+
+            let mut fields = NamedValues::new();
+            fields.push(("Number", Val::Unsigned(number as u64)));
+            if let Some(field) = field {
+                fields.push(("Name", Val::Symbol(field.name)));
+            }
+            fields.push(("Wire Type", WIRE_TYPES.val(wire.wire_type())));
+            fields.push(("Value", match field {
+                Some(field) => self.value(field, wire, depth)?,
+                None => raw(wire),
+            }));
+            values.push(("Field", Val::Object("Protobuf Field", fields)));
+        }
+
+        Ok(Val::Object(message.map(|m| m.name).unwrap_or("Protobuf"), values))
+    }
+
+    fn value<'data>(&self, field: &FieldType, wire: Wire<'data>, depth: usize)
+        -> Result<Val<'data>, DissectError> {
+
+        // Repeated scalars may be packed into one length-delimited field.
+        if let (Wire::Delimited(packed), true) = (wire, scalar_wire_type(field.kind).is_some()) {
+            let mut values = NamedValues::new();
+            let mut offset = 0;
+            while offset < packed.len() {
+                let element = match scalar_wire_type(field.kind) {
+                    Some(0) => Wire::Varint(varint(packed, &mut offset)?),
+                    Some(1) => Wire::Fixed64(fixed(packed, &mut offset, 8)?),
+                    _ => Wire::Fixed32(fixed(packed, &mut offset, 4)? as u32),
+                };
+                values.push(("Element", self.value(field, element, depth)?));
+            }
+            return Ok(Val::Object("Protobuf Packed", values));
+        }
+
+        Ok(match (field.kind, wire) {
+            (DOUBLE, Wire::Fixed64(n)) => Val::String(format!["{}", f64::from_bits(n)]),
+            (FLOAT, Wire::Fixed32(n)) => Val::String(format!["{}", f32::from_bits(n)]),
+            (INT64, Wire::Varint(n)) | (SFIXED64, Wire::Fixed64(n)) => Val::Signed(n as i64),
+            (INT32, Wire::Varint(n)) => Val::Signed(n as i32 as i64),
+            (SFIXED32, Wire::Fixed32(n)) => Val::Signed(n as i32 as i64),
+            (UINT64, Wire::Varint(n)) | (UINT32, Wire::Varint(n)) | (FIXED64, Wire::Fixed64(n)) =>
+                Val::Unsigned(n),
+            (FIXED32, Wire::Fixed32(n)) => Val::Unsigned(n as u64),
+            (SINT32, Wire::Varint(n)) | (SINT64, Wire::Varint(n)) =>
+                Val::Signed((n >> 1) as i64 ^ -((n & 1) as i64)),
+            (BOOL, Wire::Varint(n)) => Val::Unsigned((n != 0) as u64),
+            (ENUM, Wire::Varint(n)) => match self.enums.get(&field.type_name)
+                    .and_then(|e| e.get(&(n as i32))) {
+                Some(name) => Val::Symbol(name),
+                None => Val::Signed(n as i32 as i64),
+            },
+            (STRING, Wire::Delimited(s)) => Val::String(String::from_utf8_lossy(s).into_owned()),
+            (BYTES, Wire::Delimited(b)) => Val::Bytes(b),
+            (MESSAGE, Wire::Delimited(m)) =>
+                self.message(self.messages.get(&field.type_name), m, depth + 1)?,
+
+            // A mismatch between the schema and the data
+            (_, wire) => raw(wire),
+        })
+    }
+}
+
+/// The wire type of a scalar field type, which can be packed.
+fn scalar_wire_type(kind: u64) -> Option<u8> {
+    match kind {
+        INT64 | UINT64 | INT32 | BOOL | UINT32 | ENUM | SINT32 | SINT64 => Some(0),
+        DOUBLE | FIXED64 | SFIXED64 => Some(1),
+        FLOAT | FIXED32 | SFIXED32 => Some(5),
+        _ => None,
+    }
+}
+
+/// A value without a schema: numbers as unsigned integers, and
+/// length-delimited data (a string, bytes or a message) as a string if it's
+/// printable text.
+fn raw(wire: Wire) -> Val {
+    match wire {
+        Wire::Delimited(bytes) => match ::std::str::from_utf8(bytes) {
+            Ok(s) if !s.is_empty() && !s.chars().any(|c| c.is_control()) => Val::String(s.to_string()),
+            _ => Val::Bytes(bytes),
+        },
+        wire => Val::Unsigned(wire.number()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A length-delimited field.
+    fn delimited(number: u8, data: &[u8]) -> Vec<u8> {
+        let mut field = vec![number << 3 | 2, data.len() as u8];
+        field.extend_from_slice(data);
+        field
+    }
+
+    #[test]
+    fn decode_with_descriptors() {
+        // package demo;
+        // enum Kind { UNKNOWN = 0; USER = 1; }
+        // message Request { string name = 1; Kind kind = 2; repeated sint32 ids = 3; }
+        let mut request = delimited(1, b"Request");
+        let mut name = delimited(1, b"name");
+        name.extend_from_slice(&[0x18, 1, 0x28, STRING as u8]);
+        request.extend(delimited(2, &name));
+        let mut kind = delimited(1, b"kind");
+        kind.extend_from_slice(&[0x18, 2, 0x28, ENUM as u8]);
+        kind.extend(delimited(6, b".demo.Kind"));
+        request.extend(delimited(2, &kind));
+        let mut ids = delimited(1, b"ids");
+        ids.extend_from_slice(&[0x18, 3, 0x28, SINT32 as u8]);
+        request.extend(delimited(2, &ids));
+
+        let mut kinds = delimited(1, b"Kind");
+        let mut unknown = delimited(1, b"UNKNOWN");
+        unknown.extend_from_slice(&[0x10, 0]);
+        kinds.extend(delimited(2, &unknown));
+        let mut user = delimited(1, b"USER");
+        user.extend_from_slice(&[0x10, 1]);
+        kinds.extend(delimited(2, &user));
+
+        let mut file = delimited(1, b"demo.proto");
+        file.extend(delimited(2, b"demo"));
+        file.extend(delimited(4, &request));
+        file.extend(delimited(5, &kinds));
+
+        let mut registry = Registry::new();
+        registry.load(&delimited(1, &file)).unwrap();
+        registry.bind_port(7000, "demo.Request");
+        assert!(registry.contains("demo.Request"));
+
+        // name = "bob", kind = USER, ids = [1, -2] (packed)
+        let mut data = delimited(1, b"bob");
+        data.extend_from_slice(&[0x10, 1]);
+        data.extend(delimited(3, &[2, 3]));
+
+        let val = *registry.decode(registry.message_for_port(7000), &data).unwrap();
+        let fields = val.as_object().unwrap().1;
+        assert_eq!(val.as_object().unwrap().0, "Request");
+        assert_eq!(fields[0].1["Name"].as_symbol().unwrap(), "name");
+        assert_eq!(fields[0].1["Value"].as_string().unwrap(), "bob");
+        assert_eq!(fields[1].1["Value"].as_symbol().unwrap(), "USER");
+
+        let ids = fields[2].1["Value"].as_object().unwrap().1.iter()
+            .map(|&(_, ref v)| v.as_signed().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, -2]);
+    }
+
+    #[test]
+    fn decode_without_descriptors() {
+        let val = *dissect(&[0x0a, 3, b'b', b'o', b'b', 0x1d, 1, 0, 0, 0]).unwrap();
+        let fields = val.as_object().unwrap().1;
+        assert_eq!(fields[0].1["Wire Type"].as_symbol().unwrap(), "Length-delimited");
+        assert_eq!(fields[0].1["Value"].as_string().unwrap(), "bob");
+        assert_eq!(fields[1].1["Number"].as_unsigned().unwrap(), 3);
+        assert_eq!(fields[1].1["Value"].as_unsigned().unwrap(), 1);
+
+        assert!(dissect(&[0x0a, 3, b'b']).is_err());
+        assert!(dissect(&[0x0b]).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Apache Thrift messages in the binary and compact
+//! protocols, framed or not.
+//!
+//! A message names its method and carries a struct: the method's arguments
+//! for a call, its result for a reply. Both protocols tag every field with
+//! its number and type, so the field tree can always be decoded, but field
+//! names are only in the IDL: `Descriptors` supplies them, by method. A
+//! framed message is prefixed by its length.
+//!
+//! See the [Thrift protocol specifications](https://github.com/apache/thrift/tree/master/doc/specs).
+
+use std::collections::HashMap;
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use super::MAX_DEPTH;
+
+enum_map!(pub MESSAGE_TYPES {
+    1 => "Call",
+    2 => "Reply",
+    3 => "Exception",
+    4 => "Oneway",
+});
+
+/// The default port of Thrift's example servers and many deployments.
+pub const PORT: u16 = 9090;
+
+const BINARY_VERSION: u16 = 0x8001;
+const COMPACT_ID: u8 = 0x82;
+
+/// The names of a struct's fields, and descriptors of the fields that are
+/// structs themselves.
+///
+/// Names are `&'static str`s, like every field name; names only known at
+/// run time (e.g., read from an IDL file) can be interned (see `intern`).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Descriptor {
+    name: &'static str,
+    fields: Vec<(i16, &'static str, Option<Descriptor>)>,
+}
+
+impl Descriptor {
+    pub fn new(name: &'static str) -> Descriptor {
+        Descriptor { name: name, fields: Vec::new() }
+    }
+
+    pub fn field(mut self, id: i16, name: &'static str) -> Descriptor {
+        self.fields.push((id, name, None));
+        self
+    }
+
+    /// A field that is a struct, or a list, set or map of them.
+    pub fn structure(mut self, id: i16, name: &'static str, descriptor: Descriptor) -> Descriptor {
+        self.fields.push((id, name, Some(descriptor)));
+        self
+    }
+
+    fn lookup(&self, id: i16) -> Option<&(i16, &'static str, Option<Descriptor>)> {
+        self.fields.iter().find(|&&(i, _, _)| i == id)
+    }
+}
+
+/// Descriptors of methods' arguments and results, by method name.
+#[derive(Clone, Debug, Default)]
+pub struct Descriptors {
+    arguments: HashMap<String, Descriptor>,
+    results: HashMap<String, Descriptor>,
+}
+
+impl Descriptors {
+    pub fn new() -> Descriptors {
+        Descriptors::default()
+    }
+
+    pub fn arguments(mut self, method: &str, descriptor: Descriptor) -> Descriptors {
+        self.arguments.insert(method.to_string(), descriptor);
+        self
+    }
+
+    /// The result struct: field 0 is the return value and the others are
+    /// declared exceptions.
+    pub fn result(mut self, method: &str, descriptor: Descriptor) -> Descriptors {
+        self.results.insert(method.to_string(), descriptor);
+        self
+    }
+}
+
+/// Whether data starts with a Thrift message header, framed or not.
+pub fn looks_like_thrift(data: &[u8]) -> bool {
+    let unframed = |d: &[u8]| d.len() >= 2 && (((d[0] as u16) << 8 | d[1] as u16) == BINARY_VERSION
+                                               || (d[0] == COMPACT_ID && d[1] & 0x1f == 1));
+
+    unframed(data) || (data.len() > 4 && unframed(&data[4..]))
+}
+
+/// Dissect a Thrift message without field names.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &Descriptors::default())
+}
+
+/// Dissect a Thrift message, naming the fields of methods that have
+/// descriptors.
+pub fn dissect_with<'data>(data: &'data [u8], descriptors: &Descriptors) -> DissectResult<'data> {
+    let mut values = NamedValues::new();
+    let mut message = data;
+
+    // A frame length before the version word: binary messages start with
+    // 0x80 and compact ones with 0x82, so a 4 B length doesn't.
+    if data.len() >= 4 && data[0] & 0x80 == 0 {
+        let length = (data[0] as usize) << 24 | (data[1] as usize) << 16
+            | (data[2] as usize) << 8 | data[3] as usize;
+        values.push(("Transport", Val::Symbol("Framed")));
+        values.push(("Frame Length", Val::Unsigned(length as u64)));
+        message = &data[4..(4 + length).min(data.len())];
+    } else {
+        values.push(("Transport", Val::Symbol("Unframed")));
+    }
+
+    let compact = match message.first() {
+        Some(&COMPACT_ID) => true,
+        Some(&0x80) => false,
+        _ => return Err(DissectError::InvalidData("Data is not a Thrift message".to_string())),
+    };
+    let mut reader = Reader { data: message, offset: 0, compact: compact };
+
+    let (message_type, method, sequence) = if compact {
+        reader.offset = 1;
+        let type_version = reader.byte()?;
+        if type_version & 0x1f != 1 {
+            return Err(DissectError::InvalidData(
+                format!["Thrift compact protocol version {} is not 1", type_version & 0x1f]));
+        }
+        let sequence = reader.varint()? as u32 as i32;
+        (type_version >> 5, reader.string()?, sequence)
+    } else {
+        let version = reader.i32()? as u32;
+        if version >> 16 != BINARY_VERSION as u32 {
+            return Err(DissectError::InvalidData(
+                format!["Thrift binary protocol version 0x{:04x} is not 0x8001", version >> 16]));
+        }
+        let method = reader.string()?;
+        ((version & 0xff) as u8, method, reader.i32()?)
+    };
+
+    values.push(("Protocol", Val::Symbol(if compact { "Compact" } else { "Binary" })));
+    values.push(("Message Type", MESSAGE_TYPES.val(message_type)));
+    values.push(("Method", Val::String(method.clone())));
+    values.push(("Sequence ID", Val::Signed(sequence as i64)));
+
+    let exception = Descriptor::new("TApplicationException").field(1, "Message").field(2, "Type");
+    let descriptor = match message_type {
+        1 | 4 => descriptors.arguments.get(&method),
+        2 => descriptors.results.get(&method),
+        _ => Some(&exception),
+    };
+    values.push(("Body", reader.structure(descriptor, 0)?));
+
+    Ok(Box::new(Val::Object("Thrift", values)))
+}
+
+/// The types of values, whichever protocol encoded them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Type {
+    /// A compact struct field's boolean is in its type.
+    Bool(Option<bool>),
+    Byte,
+    Double,
+    I16,
+    I32,
+    I64,
+    Binary,
+    Struct,
+    Map,
+    Set,
+    List,
+    Uuid,
+}
+
+impl Type {
+    fn binary(code: u8) -> Option<Type> {
+        Some(match code {
+            2 => Type::Bool(None),
+            3 => Type::Byte,
+            4 => Type::Double,
+            6 => Type::I16,
+            8 => Type::I32,
+            10 => Type::I64,
+            11 => Type::Binary,
+            12 => Type::Struct,
+            13 => Type::Map,
+            14 => Type::Set,
+            15 => Type::List,
+            16 => Type::Uuid,
+            _ => return None,
+        })
+    }
+
+    fn compact(code: u8) -> Option<Type> {
+        Some(match code {
+            1 => Type::Bool(Some(true)),
+            2 => Type::Bool(Some(false)),
+            3 => Type::Byte,
+            4 => Type::I16,
+            5 => Type::I32,
+            6 => Type::I64,
+            7 => Type::Double,
+            8 => Type::Binary,
+            9 => Type::List,
+            10 => Type::Set,
+            11 => Type::Map,
+            12 => Type::Struct,
+            13 => Type::Uuid,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Type::Bool(_) => "bool",
+            Type::Byte => "byte",
+            Type::Double => "double",
+            Type::I16 => "i16",
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::Binary => "binary",
+            Type::Struct => "struct",
+            Type::Map => "map",
+            Type::Set => "set",
+            Type::List => "list",
+            Type::Uuid => "uuid",
+        }
+    }
+}
+
+struct Reader<'data> {
+    data: &'data [u8],
+    offset: usize,
+    compact: bool,
+}
+
+impl<'data> Reader<'data> {
+    fn bytes(&mut self, length: usize) -> Result<&'data [u8], DissectError> {
+        let end = self.offset.checked_add(length).filter(|&e| e <= self.data.len())
+            .ok_or_else(|| DissectError::Underflow { expected: self.offset.checked_add(length),
+                have: self.data.len(), message: "Thrift message is truncated".to_string() })?;
+
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, DissectError> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn big_endian(&mut self, length: usize) -> Result<u64, DissectError> {
+        Ok(self.bytes(length)?.iter().fold(0, |n, &b| n << 8 | b as u64))
+    }
+
+    fn varint(&mut self) -> Result<u64, DissectError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(DissectError::InvalidData("Thrift varint is too long".to_string()))
+    }
+
+    fn zigzag(&mut self) -> Result<i64, DissectError> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn i32(&mut self) -> Result<i32, DissectError> {
+        if self.compact { self.zigzag().map(|n| n as i32) } else { self.big_endian(4).map(|n| n as i32) }
+    }
+
+    fn length(&mut self) -> Result<usize, DissectError> {
+        let length = if self.compact { self.varint()? as i64 } else { self.i32()? as i64 };
+        if length < 0 || length as usize > self.data.len() - self.offset {
+            return Err(DissectError::InvalidData(format!["Thrift length {} is invalid", length]));
+        }
+
+        Ok(length as usize)
+    }
+
+    fn binary(&mut self) -> Result<&'data [u8], DissectError> {
+        let length = self.length()?;
+        self.bytes(length)
+    }
+
+    fn string(&mut self) -> Result<String, DissectError> {
+        self.binary().map(|s| String::from_utf8_lossy(s).into_owned())
+    }
+
+    fn element_type(&mut self, code: u8) -> Result<Type, DissectError> {
+        let t = if self.compact { Type::compact(code) } else { Type::binary(code) };
+        match t {
+            // In collections, compact booleans are bytes.
+            Some(Type::Bool(_)) => Ok(Type::Bool(None)),
+            Some(t) => Ok(t),
+            None => Err(DissectError::InvalidData(format!["Thrift type {} is invalid", code])),
+        }
+    }
+
+    fn structure(&mut self, descriptor: Option<&Descriptor>, depth: usize)
+        -> Result<Val<'data>, DissectError> {
+
+        if depth > MAX_DEPTH {
+            return Err(DissectError::InvalidData("Thrift data is nested too deeply".to_string()));
+        }
+
+        let mut values = NamedValues::new();
+        let mut last_id = 0;
+
+        loop {
+            let header = self.byte()?;
+            if header == 0 {
+                break;
+            }
+
+            let (t, id) = if self.compact {
+                let t = Type::compact(header & 0x0f).ok_or_else(|| DissectError::InvalidData(
+                    format!["Thrift compact type {} is invalid", header & 0x0f]))?;
+                let delta = (header >> 4) as i16;
+                let id = if delta != 0 { last_id + delta } else { self.zigzag()? as i16 };
+                (t, id)
+            } else {
+                let t = self.element_type(header)?;
+                (t, self.big_endian(2)? as i16)
+            };
+            last_id = id;
+
+            let field = descriptor.and_then(|d| d.lookup(id));
+            let mut fields = NamedValues::new();
+            fields.push(("ID", Val::Signed(id as i64)));
+            if let Some(&(_, name, _)) = field {
+                fields.push(("Name", Val::Symbol(name)));
+            }
+            fields.push(("Type", Val::Symbol(t.name())));
+            fields.push(("Value", self.value(t, field.and_then(|f| f.2.as_ref()), depth + 1)?));
+            values.push(("Field", Val::Object("Thrift Field", fields)));
+        }
+
+        Ok(Val::Object(descriptor.map(|d| d.name).unwrap_or("Thrift Struct"), values))
+    }
+
+    fn value(&mut self, t: Type, descriptor: Option<&Descriptor>, depth: usize)
+        -> Result<Val<'data>, DissectError> {
+
+        Ok(match t {
+            Type::Bool(Some(b)) => Val::Unsigned(b as u64),
+            Type::Bool(None) => Val::Unsigned((self.byte()? == 1) as u64),
+            Type::Byte => Val::Signed(self.byte()? as i8 as i64),
+            Type::I16 if self.compact => Val::Signed(self.zigzag()? as i16 as i64),
+            Type::I16 => Val::Signed(self.big_endian(2)? as i16 as i64),
+            Type::I32 => Val::Signed(self.i32()? as i64),
+            Type::I64 if self.compact => Val::Signed(self.zigzag()?),
+            Type::I64 => Val::Signed(self.big_endian(8)? as i64),
+            Type::Double => {
+                let bits = if self.compact { self.big_endian(8)?.swap_bytes() } else { self.big_endian(8)? };
+                Val::String(format!["{}", f64::from_bits(bits)])
+            },
+            Type::Binary => {
+                let bytes = self.binary()?;
+                match ::std::str::from_utf8(bytes) {
+                    Ok(s) if !s.chars().any(|c| c.is_control()) => Val::String(s.to_string()),
+                    _ => Val::Bytes(bytes),
+                }
+            },
+            Type::Uuid => Val::Bytes(self.bytes(16)?),
+            Type::Struct => self.structure(descriptor, depth)?,
+            Type::List | Type::Set => {
+                let (element, size) = if self.compact {
+                    let header = self.byte()?;
+                    let size = if header >> 4 == 15 { self.length()? } else { (header >> 4) as usize };
+                    (self.element_type(header & 0x0f)?, size)
+                } else {
+                    let code = self.byte()?;
+                    (self.element_type(code)?, self.length()?)
+                };
+
+                let mut values = NamedValues::new();
+                for _ in 0..size {
+                    values.push(("Element", self.value(element, descriptor, depth + 1)?));
+                }
+                Val::Object(if t == Type::List { "Thrift List" } else { "Thrift Set" }, values)
+            },
+            Type::Map => {
+                // Compact maps leave out the key and value types when empty.
+                let (size, types) = if self.compact {
+                    let size = self.length()?;
+                    let types = if size > 0 { self.byte()? } else { 0 };
+                    (size, (types >> 4, types & 0x0f))
+                } else {
+                    let key = self.byte()?;
+                    let value = self.byte()?;
+                    (self.length()?, (key, value))
+                };
+
+                let mut values = NamedValues::new();
+                if size > 0 {
+                    let key = self.element_type(types.0)?;
+                    let value = self.element_type(types.1)?;
+                    for _ in 0..size {
+                        values.push(("Key", self.value(key, None, depth + 1)?));
+                        values.push(("Value", self.value(value, descriptor, depth + 1)?));
+                    }
+                }
+                Val::Object("Thrift Map", values)
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_binary_call() {
+        // Framed call to add(1: i32 a = 2, 2: list<string> tags = ["x"])
+        let data = [0, 0, 0, 36,
+                    0x80, 0x01, 0x00, 0x01, 0, 0, 0, 3, b'a', b'd', b'd', 0, 0, 0, 7,
+                    8, 0, 1, 0, 0, 0, 2,
+                    15, 0, 2, 11, 0, 0, 0, 1, 0, 0, 0, 1, b'x',
+                    0];
+        assert!(looks_like_thrift(&data));
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Transport"].as_symbol().unwrap(), "Framed");
+        assert_eq!(val["Message Type"].as_symbol().unwrap(), "Call");
+        assert_eq!(val["Method"].as_string().unwrap(), "add");
+        assert_eq!(val["Sequence ID"].as_signed().unwrap(), 7);
+        assert_eq!(val["Body"]["Field"]["Value"].as_signed().unwrap(), 2);
+
+        let descriptors = Descriptors::new()
+            .arguments("add", Descriptor::new("add_args").field(1, "a").field(2, "tags"));
+        let val = *dissect_with(&data, &descriptors).unwrap();
+        let fields = val["Body"].as_object().unwrap().1;
+        assert_eq!(fields[0].1["Name"].as_symbol().unwrap(), "a");
+        assert_eq!(fields[1].1["Value"]["Element"].as_string().unwrap(), "x");
+    }
+
+    #[test]
+    fn dissect_compact_reply() {
+        // Reply to ping() with 0: bool success = true and 1: map<string, i32> {"n": -1}
+        let data = [0x82, 0x41, 0x05, 4, b'p', b'i', b'n', b'g',
+                    0x01, 0x00,
+                    0x1b, 1, 0x85, 1, b'n', 1,
+                    0];
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Protocol"].as_symbol().unwrap(), "Compact");
+        assert_eq!(val["Message Type"].as_symbol().unwrap(), "Reply");
+        assert_eq!(val["Sequence ID"].as_signed().unwrap(), 5);
+
+        let fields = val["Body"].as_object().unwrap().1;
+        assert_eq!(fields[0].1["Value"].as_unsigned().unwrap(), 1);
+        assert_eq!(fields[1].1["Value"]["Key"].as_string().unwrap(), "n");
+        assert_eq!(fields[1].1["Value"]["Value"].as_signed().unwrap(), -1);
+
+        assert!(dissect(&data[..10]).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of XML documents.
+//!
+//! Each element becomes an "XML Element" with its `Name`, its attributes
+//! ("XML Attribute"s with a `Name` and `Value`) and its content in document
+//! order: child `Element`s and non-blank `Text` (including CDATA sections).
+//! The predefined and numeric entity references are expanded; comments,
+//! processing instructions and the document type declaration are skipped,
+//! except for the XML declaration itself. This is not a validating parser,
+//! but mismatched tags are reported.
+//!
+//! See [XML 1.0](https://www.w3.org/TR/xml/).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use super::MAX_DEPTH;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let text = ::std::str::from_utf8(data)
+        .map_err(|e| DissectError::InvalidData(format!["XML must be UTF-8: {}", e]))?;
+    let mut parser = Parser { text: text.trim_start_matches('\u{feff}'), offset: 0 };
+    let mut values = NamedValues::new();
+
+    parser.whitespace();
+    if parser.rest().starts_with("<?xml") {
+        let end = parser.find("?>")?;
+        values.push(("Declaration", Val::String(parser.rest()[5..end].trim().to_string())));
+        parser.offset += end + 2;
+    }
+
+    parser.misc()?;
+    if !parser.rest().starts_with('<') {
+        return Err(parser.error("expected the root element"));
+    }
+    values.push(("Element", parser.element(0)?));
+
+    parser.misc()?;
+    if !parser.rest().is_empty() {
+        return Err(parser.error("trailing data after the root element"));
+    }
+
+    Ok(Box::new(Val::Object("XML", values)))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> DissectError {
+        DissectError::InvalidData(format!["XML {} at offset {}", message, self.offset])
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    /// The offset of `s` in the rest of the text.
+    fn find(&self, s: &str) -> Result<usize, DissectError> {
+        self.rest().find(s).ok_or_else(|| self.error(&format!["is missing '{}'", s]))
+    }
+
+    fn whitespace(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skip whitespace, comments, processing instructions and document type
+    /// declarations.
+    fn misc(&mut self) -> Result<(), DissectError> {
+        loop {
+            self.whitespace();
+            let end = if self.rest().starts_with("<!--") {
+                self.find("-->")? + 3
+            } else if self.rest().starts_with("<?") {
+                self.find("?>")? + 2
+            } else if self.rest().starts_with("<!DOCTYPE") {
+                // An internal subset may contain '>'.
+                let rest = self.rest();
+                let subset = match (rest.find('['), rest.find('>')) {
+                    (Some(open), Some(close)) if open < close => self.find("]")?,
+                    _ => 0,
+                };
+                subset + rest[subset..].find('>').ok_or_else(|| self.error("is missing '>'"))? + 1
+            } else {
+                return Ok(());
+            };
+            self.offset += end;
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, DissectError> {
+        let rest = self.rest();
+        let length = rest.find(|c: char| c.is_whitespace() || "/>=".contains(c)).unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.offset += length;
+        Ok(&rest[..length])
+    }
+
+    fn element<'data>(&mut self, depth: usize) -> Result<Val<'data>, DissectError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("is nested too deeply"));
+        }
+
+        self.offset += 1;
+        let name = self.name()?;
+        let mut values = NamedValues::new();
+        values.push(("Name", Val::String(name.to_string())));
+
+        loop {
+            self.whitespace();
+            if self.rest().starts_with("/>") {
+                self.offset += 2;
+                return Ok(Val::Object("XML Element", values));
+            } else if self.rest().starts_with('>') {
+                self.offset += 1;
+                break;
+            }
+
+            let attribute = self.name()?;
+            self.whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(self.error(&format!["attribute '{}' has no value", attribute]));
+            }
+            self.offset += 1;
+            self.whitespace();
+
+            let quote = self.rest().chars().next().filter(|&q| q == '"' || q == '\'')
+                .ok_or_else(|| self.error("expected a quoted attribute value"))?;
+            self.offset += 1;
+            let end = self.find(if quote == '"' { "\"" } else { "'" })?;
+            let value = unescape(&self.rest()[..end]);
+            self.offset += end + 1;
+
+            let mut fields = NamedValues::new();
+            fields.push(("Name", Val::String(attribute.to_string())));
+            fields.push(("Value", Val::String(value)));
+            values.push(("Attribute", Val::Object("XML Attribute", fields)));
+        }
+
+        // Content, up to the matching end tag
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.offset += 2;
+                let end = self.name()?;
+                if end != name {
+                    return Err(self.error(&format!["end tag '{}' doesn't match '{}'", end, name]));
+                }
+                self.whitespace();
+                if !self.rest().starts_with('>') {
+                    return Err(self.error("end tag is unterminated"));
+                }
+                self.offset += 1;
+                return Ok(Val::Object("XML Element", values));
+            } else if rest.starts_with("<![CDATA[") {
+                let end = self.find("]]>")?;
+                values.push(("Text", Val::String(rest[9..end].to_string())));
+                self.offset += end + 3;
+            } else if rest.starts_with("<!--") || rest.starts_with("<?") {
+                self.misc()?;
+            } else if rest.starts_with('<') {
+                values.push(("Element", self.element(depth + 1)?));
+            } else if rest.is_empty() {
+                return Err(self.error(&format!["element '{}' is unterminated", name]));
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                let text = rest[..end].trim();
+                if !text.is_empty() {
+                    values.push(("Text", Val::String(unescape(text))));
+                }
+                self.offset += end;
+            }
+        }
+    }
+}
+
+/// Expand entity references.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = s;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok()
+                .and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(::std::char::from_u32),
+            _ => None,
+        };
+
+        match c {
+            Some(c) => { unescaped.push(c); rest = &rest[end + 1..]; },
+            None => { unescaped.push('&'); rest = &rest[1..]; },
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_document() {
+        let val = *dissect(b"<?xml version=\"1.0\"?>\n<!-- feed -->\n\
+            <feed lang='en'><title>Fish &amp; chips</title><empty/>\
+            <![CDATA[<raw>]]></feed>").unwrap();
+        assert_eq!(val["Declaration"].as_string().unwrap(), "version=\"1.0\"");
+
+        let feed = &val["Element"];
+        assert_eq!(feed["Name"].as_string().unwrap(), "feed");
+        assert_eq!(feed["Attribute"]["Value"].as_string().unwrap(), "en");
+        assert_eq!(feed["Element"]["Text"].as_string().unwrap(), "Fish & chips");
+
+        let content = feed.as_object().unwrap().1;
+        assert_eq!(content[3].1["Name"].as_string().unwrap(), "empty");
+        assert_eq!(content[4].1.as_string().unwrap(), "<raw>");
+
+        assert!(dissect(b"<a><b></a></b>").is_err());
+        assert!(dissect(b"<a>").is_err());
+    }
+}
@@ -0,0 +1,330 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Reading and writing pcapng blocks.
+//!
+//! This module currently understands the block framing and Decryption
+//! Secrets Blocks (DSBs), which embed key material such as TLS key logs in a
+//! capture file so that it can be decrypted without any out-of-band files.
+//!
+//! See [draft-tuexen-opsawg-pcapng](https://datatracker.ietf.org/doc/draft-tuexen-opsawg-pcapng/).
+
+use std::io;
+use std::io::Write;
+
+use DissectError;
+use Endianness;
+use ErrorCode;
+use unsigned;
+
+pub const SECTION_HEADER: u32 = 0x0a0d0d0a;
+pub const INTERFACE_DESCRIPTION: u32 = 0x00000001;
+pub const SIMPLE_PACKET: u32 = 0x00000003;
+pub const ENHANCED_PACKET: u32 = 0x00000006;
+pub const DECRYPTION_SECRETS: u32 = 0x0000000a;
+
+/// The option code of comments, valid in any block.
+pub const OPT_COMMENT: u16 = 1;
+
+const BYTE_ORDER_MAGIC: u64 = 0x1a2b3c4d;
+
+/// A single pcapng block.
+#[derive(Debug, PartialEq)]
+pub struct Block<'data> {
+    pub block_type: u32,
+
+    /// The block body, excluding type and length fields.
+    pub body: &'data [u8],
+
+    /// Byte order of the section containing this block.
+    pub endianness: Endianness,
+}
+
+/// Iterator over the blocks of a pcapng file, following the byte order of
+/// each section.
+pub struct Blocks<'data> {
+    data: &'data [u8],
+    endianness: Endianness,
+}
+
+pub fn blocks<'data>(data: &'data [u8]) -> Blocks<'data> {
+    Blocks { data: data, endianness: Endianness::LittleEndian }
+}
+
+impl<'data> Iterator for Blocks<'data> {
+    type Item = Result<Block<'data>, DissectError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let result = self.next_block();
+        if result.is_err() {
+            self.data = &[];
+        }
+
+        Some(result)
+    }
+}
+
+impl<'data> Blocks<'data> {
+    /// The number of bytes not yet consumed.
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn next_block(&mut self) -> Result<Block<'data>, DissectError> {
+        let data = self.data;
+        if data.len() < 12 {
+            return Err(DissectError::Underflow { expected: Some(12), have: data.len(),
+                message: "A pcapng block must be at least 12 B".to_string() });
+        }
+
+        // A section header sets the byte order for the blocks that follow it.
+        if unsigned(&data[0..4], Endianness::BigEndian).unwrap() == SECTION_HEADER as u64 {
+            self.endianness = match unsigned(&data[8..12], Endianness::BigEndian).unwrap() {
+                BYTE_ORDER_MAGIC => Endianness::BigEndian,
+                _ if unsigned(&data[8..12], Endianness::LittleEndian).unwrap() == BYTE_ORDER_MAGIC
+                    => Endianness::LittleEndian,
+                _ => return Err(DissectError::malformed(ErrorCode::BadMagic,
+                        "pcapng section header has an invalid byte-order magic")),
+            };
+        }
+
+        let block_type = unsigned(&data[0..4], self.endianness).unwrap() as u32;
+        let length = unsigned(&data[4..8], self.endianness).unwrap() as usize;
+
+        if length < 12 || length % 4 != 0 {
+            return Err(DissectError::malformed(ErrorCode::InvalidLength,
+                format!["invalid pcapng block length: {} B", length]));
+        }
+
+        if length > data.len() {
+            return Err(DissectError::Underflow { expected: Some(length), have: data.len(),
+                message: "pcapng block length greater than available data".to_string() });
+        }
+
+        if unsigned(&data[length - 4..length], self.endianness).unwrap() as usize != length {
+            return Err(DissectError::malformed(ErrorCode::InvalidLength,
+                "pcapng block's trailing length doesn't match its header"));
+        }
+
+        self.data = &data[length..];
+
+        Ok(Block { block_type: block_type, body: &data[8..length - 4], endianness: self.endianness })
+    }
+}
+
+/// The kind of key material held in a Decryption Secrets Block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SecretsType {
+    /// NSS key log format, as written by browsers' `SSLKEYLOGFILE`.
+    TlsKeyLog,
+    /// WireGuard key log, as written by `extract-handshakes.sh`.
+    WireGuardKeyLog,
+    Other(u32),
+}
+
+impl SecretsType {
+    fn from_u32(value: u32) -> SecretsType {
+        match value {
+            0x544c534b => SecretsType::TlsKeyLog,
+            0x57474b4c => SecretsType::WireGuardKeyLog,
+            x => SecretsType::Other(x),
+        }
+    }
+
+    fn to_u32(&self) -> u32 {
+        match self {
+            &SecretsType::TlsKeyLog => 0x544c534b,
+            &SecretsType::WireGuardKeyLog => 0x57474b4c,
+            &SecretsType::Other(x) => x,
+        }
+    }
+}
+
+/// Key material from (or for) a Decryption Secrets Block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecryptionSecrets {
+    pub secrets_type: SecretsType,
+    pub data: Vec<u8>,
+}
+
+impl DecryptionSecrets {
+    /// Parse the body of a Decryption Secrets Block.
+    pub fn parse(block: &Block) -> Result<DecryptionSecrets, DissectError> {
+        let body = block.body;
+        if block.block_type != DECRYPTION_SECRETS {
+            return Err(DissectError::InvalidData(
+                format!["pcapng block type {:#x} is not a Decryption Secrets Block", block.block_type]));
+        }
+
+        if body.len() < 8 {
+            return Err(DissectError::Underflow { expected: Some(8), have: body.len(),
+                message: "A pcapng Decryption Secrets Block must be at least 8 B".to_string() });
+        }
+
+        let secrets_type = unsigned(&body[0..4], block.endianness).unwrap() as u32;
+        let length = unsigned(&body[4..8], block.endianness).unwrap() as usize;
+        if 8 + length > body.len() {
+            return Err(DissectError::Underflow { expected: Some(8 + length), have: body.len(),
+                message: "pcapng secrets length greater than available data".to_string() });
+        }
+
+        Ok(DecryptionSecrets {
+            secrets_type: SecretsType::from_u32(secrets_type),
+            data: body[8..8 + length].to_vec(),
+        })
+    }
+
+    /// Write these secrets as a (little-endian) Decryption Secrets Block.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let padding = (4 - self.data.len() % 4) % 4;
+        let length = 20 + self.data.len() + padding;
+
+        write_u32(out, DECRYPTION_SECRETS)?;
+        write_u32(out, length as u32)?;
+        write_u32(out, self.secrets_type.to_u32())?;
+        write_u32(out, self.data.len() as u32)?;
+        out.write_all(&self.data)?;
+        out.write_all(&[0; 3][..padding])?;
+        write_u32(out, length as u32)
+    }
+}
+
+/// Collect the key material from all Decryption Secrets Blocks in a pcapng file.
+pub fn secrets(data: &[u8]) -> Result<Vec<DecryptionSecrets>, DissectError> {
+    let mut secrets = Vec::new();
+
+    for block in blocks(data) {
+        let block = block?;
+        if block.block_type == DECRYPTION_SECRETS {
+            secrets.push(DecryptionSecrets::parse(&block)?);
+        }
+    }
+
+    Ok(secrets)
+}
+
+/// Write a (little-endian) Section Header Block with no options and an
+/// unspecified section length.
+pub fn write_section_header<W: Write>(out: &mut W) -> io::Result<()> {
+    write_section_header_with_comments(out, &[])
+}
+
+/// Write a (little-endian) Section Header Block with `opt_comment` options
+/// and an unspecified section length.
+pub fn write_section_header_with_comments<W: Write>(out: &mut W, comments: &[&str]) -> io::Result<()> {
+    let mut options = Vec::new();
+    for comment in comments {
+        let padding = (4 - comment.len() % 4) % 4;
+        options.extend_from_slice(&[OPT_COMMENT as u8, 0, comment.len() as u8, (comment.len() >> 8) as u8]);
+        options.extend_from_slice(comment.as_bytes());
+        options.extend_from_slice(&[0; 3][..padding]);
+    }
+    if !options.is_empty() {
+        options.extend_from_slice(&[0; 4]);
+    }
+
+    let length = 28 + options.len() as u32;
+    write_u32(out, SECTION_HEADER)?;
+    write_u32(out, length)?;
+    write_u32(out, BYTE_ORDER_MAGIC as u32)?;
+    out.write_all(&[1, 0, 0, 0])?;
+    out.write_all(&[0xff; 8])?;
+    out.write_all(&options)?;
+    write_u32(out, length)
+}
+
+/// The `opt_comment` options of a Section Header Block.
+pub fn section_comments(block: &Block) -> Result<Vec<String>, DissectError> {
+    if block.block_type != SECTION_HEADER || block.body.len() < 16 {
+        return Err(DissectError::InvalidData("not a pcapng Section Header Block".to_string()));
+    }
+
+    let mut comments = Vec::new();
+    let mut options = &block.body[16..];
+    while options.len() >= 4 {
+        let code = unsigned(&options[0..2], block.endianness).unwrap() as u16;
+        let length = unsigned(&options[2..4], block.endianness).unwrap() as usize;
+        if code == 0 {
+            break;
+        }
+
+        if options.len() < 4 + length {
+            return Err(DissectError::Underflow { expected: Some(4 + length), have: options.len(),
+                message: "pcapng option length greater than available data".to_string() });
+        }
+
+        if code == OPT_COMMENT {
+            comments.push(String::from_utf8_lossy(&options[4..4 + length]).into_owned());
+        }
+
+        options = &options[::std::cmp::min(options.len(), 4 + (length + 3) / 4 * 4)..];
+    }
+
+    Ok(comments)
+}
+
+fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Endianness;
+
+    const KEY_LOG: &'static [u8] = b"CLIENT_RANDOM 0102 0304\n";
+
+    #[test]
+    fn write_and_read_secrets() {
+        let mut file = Vec::new();
+        write_section_header(&mut file).unwrap();
+        DecryptionSecrets { secrets_type: SecretsType::TlsKeyLog, data: KEY_LOG.to_vec() }
+            .write(&mut file).unwrap();
+
+        assert_eq!(file.len(), 28 + 20 + KEY_LOG.len());
+
+        let secrets = secrets(&file).unwrap();
+        assert_eq!(secrets.len(), 1);
+        assert_eq!(secrets[0].secrets_type, SecretsType::TlsKeyLog);
+        assert_eq!(&secrets[0].data[..], KEY_LOG);
+    }
+
+    #[test]
+    fn read_big_endian() {
+        let file = [
+            0x0a, 0x0d, 0x0d, 0x0a, 0x00, 0x00, 0x00, 0x1c, 0x1a, 0x2b, 0x3c, 0x4d,
+            0x00, 0x01, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0x00, 0x00, 0x00, 0x1c,
+            0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x18, 0x57, 0x47, 0x4b, 0x4c,
+            0x00, 0x00, 0x00, 0x03, 0x61, 0x62, 0x63, 0x00, 0x00, 0x00, 0x00, 0x18];
+
+        let blocks = blocks(&file).collect::<Vec<_>>();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].as_ref().unwrap().endianness, Endianness::BigEndian);
+
+        let secrets = secrets(&file).unwrap();
+        assert_eq!(secrets[0].secrets_type, SecretsType::WireGuardKeyLog);
+        assert_eq!(&secrets[0].data[..], b"abc");
+    }
+
+    #[test]
+    fn truncated_block() {
+        let mut file = Vec::new();
+        write_section_header(&mut file).unwrap();
+        file.truncate(20);
+
+        let blocks = blocks(&file).collect::<Vec<_>>();
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].is_err());
+    }
+}
@@ -0,0 +1,617 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Export of application-layer PDUs reassembled from TCP streams.
+//!
+//! A `PduExtractor` taps TCP, puts each direction of each connection back in
+//! order and cuts the resulting byte stream into the messages of the
+//! protocol it carries (one DNS message, one HTTP response, one SMB
+//! command...), however they were split across segments. Each `Pdu` is
+//! exported as its bytes along with the flow and packets it came from, ready
+//! for a downstream parser.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::pdu::PduExtractor;
+//! use rshark::tap::Taps;
+//!
+//! // A DNS-over-TCP query, split across two segments
+//! let header = [0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+//!               0xd4, 0x31, 0x00, 0x35, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0];
+//! let mut first = header.to_vec();
+//! first.extend_from_slice(&[0x00, 0x04, 0xab]);
+//! let mut second = header.to_vec();
+//! second[27] = 3;
+//! second.extend_from_slice(&[0xcd, 0x01, 0x00]);
+//!
+//! let mut pdus = PduExtractor::new();
+//! {
+//!     let mut taps = Taps::new();
+//!     taps.register("TCP", &mut pdus);
+//!     for data in &[first, second] {
+//!         let packet = rshark::ip::dissect(data).unwrap();
+//!         taps.dispatch(Duration::new(0, 0), data.len(), &packet);
+//!     }
+//! }
+//!
+//! let pdus = pdus.pdus();
+//! assert_eq!(pdus.len(), 1);
+//! assert_eq!((pdus[0].protocol, &pdus[0].data[..]), ("DNS", &[0x00, 0x04, 0xab, 0xcd, 0x01, 0x00][..]));
+//! assert_eq!(pdus[0].packets, vec![1, 2]);
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use Val;
+use budget::{self, MemoryBudget};
+use expert::Info;
+use flow::FlowKey;
+use tap::{PacketInfo, Tap};
+
+/// Finds the length of the PDU at the start of a stream, if enough of the
+/// stream is available to tell.
+pub type Framer = fn(&[u8]) -> Option<usize>;
+
+/// An application-layer message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pdu {
+    pub protocol: &'static str,
+
+    /// The flow, as seen from the PDU's sender.
+    pub flow: FlowKey,
+
+    /// Timestamps of the first and last packets that carried the PDU.
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+
+    /// Numbers of the packets that carried the PDU.
+    pub packets: Vec<u64>,
+
+    pub data: Vec<u8>,
+}
+
+/// How far beyond the next byte expected a segment may start and still be
+/// queued until the gap before it is filled.
+const MAX_AHEAD: u32 = 1 << 20;
+
+/// One direction of a TCP connection.
+#[derive(Default)]
+struct Stream {
+    /// The sequence number of the next byte expected.
+    next: Option<u32>,
+
+    /// How many bytes have been put in order, i.e., the stream offset of
+    /// the next byte expected.
+    offset: u64,
+
+    /// In-order data not yet part of a complete PDU.
+    buffer: Vec<u8>,
+
+    /// The buffer offset where each packet's data ends, with its number
+    /// and timestamp.
+    segments: Vec<(usize, u64, Duration)>,
+
+    /// Segments received ahead of a gap, by stream offset.
+    pending: BTreeMap<u64, (Vec<u8>, u64, Duration)>,
+    pending_bytes: usize,
+
+    /// The number of the last packet seen, for LRU eviction.
+    last_seen: u64,
+}
+
+impl Stream {
+    fn receive(&mut self, sequence: u32, data: &[u8], number: u64, timestamp: Duration) {
+        let next = *self.next.get_or_insert(sequence);
+        let ahead = sequence.wrapping_sub(next) as i32;
+
+        if ahead > 0 {
+            if ahead as u32 <= MAX_AHEAD {
+                self.queue(self.offset + ahead as u64, data, number, timestamp);
+            }
+            return;
+        }
+
+        // Skip whatever has already been received (retransmissions).
+        let skip = ahead.unsigned_abs() as usize;
+        if skip >= data.len() {
+            return;
+        }
+
+        self.append(&data[skip..], number, timestamp);
+
+        loop {
+            let start = match self.pending.keys().next() {
+                Some(&start) if start <= self.offset => start,
+                _ => break,
+            };
+
+            let (d, n, t) = self.pending.remove(&start).unwrap();
+            self.pending_bytes -= d.len();
+
+            let skip = (self.offset - start) as usize;
+            if skip < d.len() {
+                self.append(&d[skip..], n, t);
+            }
+        }
+    }
+
+    /// Hold on to a segment until the data before it arrives, keeping the
+    /// longer of any two segments that start at the same offset.
+    fn queue(&mut self, start: u64, data: &[u8], number: u64, timestamp: Duration) {
+        if self.pending.get(&start).is_some_and(|queued| queued.0.len() >= data.len()) {
+            return;
+        }
+
+        self.pending_bytes += data.len();
+        if let Some((old, _, _)) = self.pending.insert(start, (data.to_vec(), number, timestamp)) {
+            self.pending_bytes -= old.len();
+        }
+    }
+
+    /// Bytes held in the buffer or queued.
+    fn held(&self) -> usize {
+        self.buffer.len() + self.pending_bytes
+    }
+
+    fn append(&mut self, data: &[u8], number: u64, timestamp: Duration) {
+        self.buffer.extend_from_slice(data);
+        self.segments.push((self.buffer.len(), number, timestamp));
+        self.next = self.next.map(|n| n.wrapping_add(data.len() as u32));
+        self.offset += data.len() as u64;
+    }
+
+    /// Take the complete PDU at the start of the buffer, if there is one.
+    fn take(&mut self, framer: Framer) -> Option<(Vec<u8>, Vec<(u64, Duration)>)> {
+        let length = match framer(&self.buffer) {
+            Some(length) if length > 0 && length <= self.buffer.len() => length,
+            _ => return None,
+        };
+
+        let carriers = self.segments.iter()
+            .scan(0, |start, &(end, n, t)| { let s = *start; *start = end; Some((s, n, t)) })
+            .take_while(|&(start, _, _)| start < length)
+            .map(|(_, n, t)| (n, t))
+            .collect();
+
+        let data = self.buffer.drain(..length).collect();
+        self.segments = self.segments.iter()
+            .filter(|&&(end, _, _)| end > length)
+            .map(|&(end, n, t)| (end - length, n, t))
+            .collect();
+
+        Some((data, carriers))
+    }
+}
+
+/// A tap on "TCP" layers that reassembles and exports application PDUs.
+///
+/// Streams are forgotten when they are closed (FIN or RST) and the data
+/// they hold is charged to a `MemoryBudget`: when it is exceeded, the
+/// least recently seen streams are dropped, along with any partial PDUs.
+pub struct PduExtractor {
+    framers: Vec<(u16, &'static str, Framer)>,
+    streams: HashMap<FlowKey, Stream>,
+    pdus: Vec<Pdu>,
+
+    /// Streams ordered by the last packet seen, for LRU eviction.
+    lru: BTreeMap<u64, FlowKey>,
+    budget: MemoryBudget,
+    evictions: u64,
+    notes: Vec<Info>,
+}
+
+impl PduExtractor {
+    /// An extractor for DNS (port 53), HTTP (80 and 8080), TPKT (102),
+    /// ONC-RPC, e.g., NFS (111 and 2049), NetBIOS sessions, i.e., SMB (139
+    /// and 445) and MySQL (3306).
+    pub fn new() -> PduExtractor {
+        PduExtractor::with_budget(MemoryBudget::unlimited())
+    }
+
+    /// An extractor for the same protocols as `new`, whose buffered data
+    /// is limited by a budget.
+    pub fn with_budget(budget: MemoryBudget) -> PduExtractor {
+        PduExtractor {
+            framers: vec![
+                (53, "DNS", dns),
+                (80, "HTTP", http),
+                (102, "TPKT", tpkt),
+                (111, "ONC-RPC", rpc),
+                (139, "NetBIOS Session", netbios_session),
+                (445, "NetBIOS Session", netbios_session),
+                (2049, "ONC-RPC", rpc),
+                (3306, "MySQL", mysql),
+                (8080, "HTTP", http),
+            ],
+            streams: HashMap::new(),
+            pdus: Vec::new(),
+            lru: BTreeMap::new(),
+            budget: budget,
+            evictions: 0,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Frame the streams to or from `port` as `protocol`, in preference to
+    /// any framer already registered for that port.
+    pub fn framer(mut self, port: u16, protocol: &'static str, framer: Framer) -> PduExtractor {
+        self.framers.insert(0, (port, protocol, framer));
+        self
+    }
+
+    /// The PDUs extracted so far, in the order they were completed.
+    pub fn pdus(&self) -> &[Pdu] {
+        &self.pdus
+    }
+
+    /// Take the PDUs extracted so far.
+    pub fn take(&mut self) -> Vec<Pdu> {
+        self.pdus.split_off(0)
+    }
+
+    /// Number of streams dropped (with any partial PDUs) to stay within the
+    /// memory budget.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Expert notes about the streams dropped, oldest first.
+    pub fn notes(&self) -> &[Info] {
+        &self.notes
+    }
+
+    /// Take the expert notes about the streams dropped so far.
+    pub fn take_notes(&mut self) -> Vec<Info> {
+        self.notes.split_off(0)
+    }
+
+    /// Forget a stream, releasing the data it holds.
+    fn drop_stream(&mut self, key: &FlowKey) {
+        if let Some(stream) = self.streams.remove(key) {
+            self.lru.remove(&stream.last_seen);
+            self.budget.release(stream.held());
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let key = match self.lru.keys().next() {
+            Some(&oldest) => self.lru[&oldest],
+            None => return,
+        };
+
+        let held = self.streams.get(&key).map(|s| s.held()).unwrap_or(0);
+        self.drop_stream(&key);
+        self.evictions += 1;
+        self.notes.push(budget::note("TCP",
+            format!["stream {} dropped with {} B of partial PDUs to stay within the memory \
+                     budget", key, held]));
+    }
+
+    fn framing(&self, key: &FlowKey) -> Option<(&'static str, Framer)> {
+        self.framers.iter()
+            .find(|&&(port, _, _)| key.source_port == port || key.destination_port == port)
+            .map(|&(_, protocol, framer)| (protocol, framer))
+    }
+}
+
+impl Tap for PduExtractor {
+    fn tap(&mut self, info: &PacketInfo, tcp: &Val) {
+        let key = match FlowKey::from_val(info.packet) {
+            Some(key) if key.protocol == 6 => key,
+            _ => return,
+        };
+
+        let (protocol, framer) = match self.framing(&key) {
+            Some(framing) => framing,
+            None => return,
+        };
+
+        let sequence = tcp.get("Sequence Number").ok().and_then(|s| s.as_unsigned());
+        let flag = |name| tcp.get("Flags").ok()
+            .and_then(|f| f.as_bitflags_bit_name(name))
+            .unwrap_or(false);
+        let (syn, fin, rst) = (flag("SYN"), flag("FIN"), flag("RST"));
+        let data = tcp.get("Payload").ok().and_then(|p| p.as_payload_bytes()).unwrap_or(&[]);
+
+        let sequence = match sequence {
+            Some(s) => s as u32,
+            None => return,
+        };
+
+        let stream = self.streams.entry(key).or_insert_with(Stream::default);
+        self.lru.remove(&stream.last_seen);
+        self.lru.insert(info.number, key);
+        stream.last_seen = info.number;
+
+        let held = stream.held();
+        if syn {
+            stream.next = Some(sequence.wrapping_add(1));
+        } else if !data.is_empty() {
+            stream.receive(sequence, data, info.number, info.timestamp);
+        }
+
+        while let Some((data, carriers)) = stream.take(framer) {
+            self.pdus.push(Pdu {
+                protocol: protocol,
+                flow: key,
+                first_seen: carriers.iter().map(|&(_, t)| t).min().unwrap_or(info.timestamp),
+                last_seen: carriers.iter().map(|&(_, t)| t).max().unwrap_or(info.timestamp),
+                packets: carriers.iter().map(|&(n, _)| n).collect(),
+                data: data,
+            });
+        }
+
+        if stream.held() > held {
+            self.budget.charge(stream.held() - held);
+        } else {
+            self.budget.release(held - stream.held());
+        }
+
+        if fin || rst {
+            self.drop_stream(&key);
+        }
+
+        while self.budget.is_exceeded() && !self.lru.is_empty() {
+            self.evict_oldest();
+        }
+    }
+}
+
+impl Drop for PduExtractor {
+    fn drop(&mut self) {
+        self.budget.release(self.streams.values().map(Stream::held).sum());
+    }
+}
+
+/// DNS over TCP: a two-byte length prefix.
+pub fn dns(data: &[u8]) -> Option<usize> {
+    if data.len() < 2 {
+        return None;
+    }
+
+    Some(2 + ((data[0] as usize) << 8 | data[1] as usize))
+}
+
+/// TPKT (RFC 1006): a four-byte header including the total length.
+pub fn tpkt(data: &[u8]) -> Option<usize> {
+    if data.len() < 4 {
+        return None;
+    }
+
+    // The length includes the header, so anything shorter is invalid.
+    match (data[2] as usize) << 8 | data[3] as usize {
+        length if length < 4 => None,
+        length => Some(length),
+    }
+}
+
+/// ONC-RPC over TCP: a record of fragments, each with a four-byte header
+/// giving its length and (in the top bit) whether it is the last.
+pub fn rpc(data: &[u8]) -> Option<usize> {
+    let mut length = 0;
+    loop {
+        let mark = data.get(length..length + 4)?;
+        length += 4 + ((mark[0] as usize & 0x7f) << 24 | (mark[1] as usize) << 16
+                       | (mark[2] as usize) << 8 | mark[3] as usize);
+        if mark[0] & 0x80 != 0 {
+            return Some(length);
+        }
+    }
+}
+
+/// NetBIOS session service: a four-byte header with a 17 b length.
+pub fn netbios_session(data: &[u8]) -> Option<usize> {
+    if data.len() < 4 {
+        return None;
+    }
+
+    Some(4 + ((data[1] as usize & 0x01) << 16 | (data[2] as usize) << 8 | data[3] as usize))
+}
+
+/// MySQL: a three-byte little-endian length and a sequence number.
+pub fn mysql(data: &[u8]) -> Option<usize> {
+    if data.len() < 4 {
+        return None;
+    }
+
+    Some(4 + (data[0] as usize | (data[1] as usize) << 8 | (data[2] as usize) << 16))
+}
+
+/// HTTP/1.x: headers, then a body delimited by Content-Length or chunked
+/// transfer coding (messages with neither have no body).
+pub fn http(data: &[u8]) -> Option<usize> {
+    let headers = match find(data, b"\r\n\r\n") {
+        Some(end) => end + 4,
+        None => return None,
+    };
+
+    let mut length = None;
+    let mut chunked = false;
+    for line in String::from_utf8_lossy(&data[..headers]).split("\r\n") {
+        let mut parts = line.splitn(2, ':');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim().to_lowercase(), value.trim().to_lowercase()),
+            _ => continue,
+        };
+
+        if name == "content-length" {
+            length = value.parse::<usize>().ok();
+        } else if name == "transfer-encoding" && value.contains("chunked") {
+            chunked = true;
+        }
+    }
+
+    if !chunked {
+        return headers.checked_add(length.unwrap_or(0));
+    }
+
+    // Each chunk is a hex size line, the data and a CRLF; a zero-size
+    // chunk and a blank line end the body.
+    let mut offset = headers;
+    loop {
+        let line = match find(&data[offset..], b"\r\n") {
+            Some(line) => line,
+            None => return None,
+        };
+
+        let size = String::from_utf8_lossy(&data[offset..offset + line]);
+        let size = match usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16) {
+            Ok(size) => size,
+            Err(_) => return Some(data.len()),
+        };
+
+        offset += line + 2;
+        if size == 0 {
+            // Skip any trailers, up to the blank line.
+            return find(&data[offset - 2..], b"\r\n\r\n").map(|end| offset - 2 + end + 4);
+        }
+
+        offset = offset.checked_add(size).and_then(|o| o.checked_add(2))?;
+        if offset > data.len() {
+            return None;
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ip;
+    use tap::Taps;
+
+    fn segment(source_port: u16, destination_port: u16, sequence: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                            (source_port >> 8) as u8, source_port as u8,
+                            (destination_port >> 8) as u8, destination_port as u8,
+                            (sequence >> 24) as u8, (sequence >> 16) as u8, (sequence >> 8) as u8, sequence as u8,
+                            0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0];
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn reassemble_pdus() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let packets = vec![
+            segment(80, 4000, 999, 0x12, b""),
+            segment(80, 4000, 1000, 0x18, &response[..20]),
+            // Out of order, then a retransmission of the first segment
+            segment(80, 4000, 1030, 0x18, &response[30..]),
+            segment(80, 4000, 1000, 0x18, &response[..20]),
+            segment(80, 4000, 1020, 0x18, &response[20..30]),
+            // Two requests in one segment
+            segment(4000, 80, 1, 0x18, b"GET / HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"),
+        ];
+
+        let mut extractor = PduExtractor::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("TCP", &mut extractor);
+            for (i, data) in packets.iter().enumerate() {
+                let packet = ip::dissect(data).unwrap();
+                taps.dispatch(Duration::new(i as u64, 0), data.len(), &packet);
+            }
+        }
+
+        let pdus = extractor.take();
+        assert_eq!(pdus.len(), 3);
+        assert_eq!(&pdus[0].data[..], &response[..]);
+        assert_eq!(pdus[0].packets, vec![2, 5, 3]);
+        assert_eq!(pdus[0].flow.source_port, 80);
+        assert_eq!(&pdus[2].data[..], b"GET /b HTTP/1.1\r\n\r\n");
+        assert_eq!(pdus[2].packets, vec![6]);
+    }
+
+    #[test]
+    fn bound_streams() {
+        let budget = MemoryBudget::new(100);
+        let mut extractor = PduExtractor::with_budget(budget.clone());
+        let packets = vec![
+            segment(53, 4000, 999, 0x12, b""),
+            // Too far ahead to queue, then queued ahead of a gap
+            segment(53, 4000, 1000 + MAX_AHEAD + 1, 0x18, &[0; 10]),
+            segment(53, 4000, 1004, 0x18, &[0; 10]),
+            // Half a DNS message, then closed
+            segment(53, 4000, 1000, 0x19, &[0x00, 0x20]),
+            // Another stream, which outgrows the budget
+            segment(4000, 53, 1, 0x18, &[0x01, 0x00]),
+            segment(4000, 53, 3, 0x18, &[0; 99]),
+        ];
+
+        {
+            let mut taps = Taps::new();
+            taps.register("TCP", &mut extractor);
+            for (i, data) in packets.iter().enumerate() {
+                let packet = ip::dissect(data).unwrap();
+                taps.dispatch(Duration::new(i as u64, 0), data.len(), &packet);
+
+                if i == 2 {
+                    assert_eq!(budget.used(), 10);
+                }
+                if i == 3 {
+                    assert_eq!(budget.used(), 0);
+                }
+            }
+        }
+
+        assert!(extractor.pdus().is_empty());
+        assert_eq!(extractor.evictions(), 1);
+        assert_eq!(extractor.take_notes()[0].to_string(),
+                   "Warn: TCP: stream 10.0.0.1:4000 -> 10.0.0.2:53 dropped with 101 B of partial \
+                    PDUs to stay within the memory budget");
+        assert!(extractor.notes().is_empty());
+        assert_eq!(budget.used(), 0);
+
+        // What is still held is released when the extractor is dropped.
+        let mut extractor = PduExtractor::with_budget(budget.clone());
+        {
+            let mut taps = Taps::new();
+            taps.register("TCP", &mut extractor);
+            let packet = ip::dissect(&packets[4]).unwrap();
+            taps.dispatch(Duration::new(0, 0), packets[4].len(), &packet);
+        }
+        assert_eq!(budget.used(), 2);
+        drop(extractor);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn sequence_half_way_round() {
+        let mut stream = Stream::default();
+        stream.receive(1000, b"abc", 1, Duration::new(0, 0));
+
+        // As far behind as it is ahead: taken to be an old retransmission
+        stream.receive(1003u32.wrapping_add(1 << 31), b"def", 2, Duration::new(0, 0));
+        assert_eq!(&stream.buffer[..], b"abc");
+        assert_eq!(stream.next, Some(1003));
+    }
+
+    #[test]
+    fn frame_http() {
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\nNEXT";
+        assert_eq!(http(chunked), Some(chunked.len() - 4));
+        assert_eq!(http(&chunked[..50]), None);
+        assert_eq!(http(b"HTTP/1.1 204 No Content\r\n"), None);
+        assert_eq!(netbios_session(&[0x00, 0x01, 0x00, 0x02]), Some(4 + 0x10002));
+        assert_eq!(rpc(&[0x00, 0, 0, 2, 1, 2, 0x80, 0, 0, 1, 3]), Some(11));
+        assert_eq!(rpc(&[0x00, 0, 0, 2, 1, 2]), None);
+
+        let huge = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nabc";
+        assert_eq!(http(huge), None);
+        assert_eq!(tpkt(&[0x03, 0x00, 0x00, 0x07, 0xf0]), Some(7));
+        assert_eq!(tpkt(&[0x03, 0x00, 0x00, 0x00]), None);
+        assert_eq!(tpkt(&[0x03, 0x00, 0x00, 0x03]), None);
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! A bounded queue between packet capture and dissection workers.
+//!
+//! Live traffic is bursty: if dissection can't keep up, something has to
+//! give. A `Pipeline` reads packets from a `PacketSource` on the calling
+//! thread and hands them to worker threads through a queue of fixed
+//! capacity. When the queue is full, the `DropPolicy` decides whether capture
+//! waits (losing packets in the kernel instead) or which packet is discarded,
+//! and every discarded packet is counted in the `PipelineStats`.
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use source::{Packet, PacketSource};
+
+/// What to do with a packet when the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DropPolicy {
+    /// Stop capturing until there is room (nothing is dropped by the
+    /// pipeline, but the capture mechanism may drop packets meanwhile).
+    Block,
+
+    /// Discard the packet that has just been captured.
+    DropNewest,
+
+    /// Discard the packet that has waited longest, keeping the most recent.
+    DropOldest,
+}
+
+/// What happened to the packets that passed through a pipeline.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineStats {
+    /// Packets read from the source.
+    pub captured: u64,
+
+    /// Packets handed to a worker.
+    pub processed: u64,
+
+    pub dropped_newest: u64,
+    pub dropped_oldest: u64,
+
+    /// Packets dropped by the capture mechanism itself, if it reports them.
+    pub source_dropped: Option<u64>,
+
+    /// Errors reported by the source (which end the capture).
+    pub source_errors: u64,
+
+    /// The greatest number of packets that were waiting at once.
+    pub max_depth: usize,
+}
+
+impl PipelineStats {
+    /// All packets lost by the pipeline.
+    pub fn dropped(&self) -> u64 {
+        self.dropped_newest + self.dropped_oldest
+    }
+}
+
+/// A capture-to-dissection pipeline.
+pub struct Pipeline {
+    capacity: usize,
+    policy: DropPolicy,
+    workers: usize,
+}
+
+impl Pipeline {
+    /// A pipeline that can hold `capacity` packets waiting for dissection.
+    pub fn new(capacity: usize) -> Pipeline {
+        Pipeline { capacity: ::std::cmp::max(capacity, 1), policy: DropPolicy::Block, workers: 1 }
+    }
+
+    pub fn policy(mut self, policy: DropPolicy) -> Pipeline {
+        self.policy = policy;
+        self
+    }
+
+    pub fn workers(mut self, workers: usize) -> Pipeline {
+        self.workers = ::std::cmp::max(workers, 1);
+        self
+    }
+
+    /// Capture every packet from `source`, passing each one to `handler` on a
+    /// worker thread. Returns when the source is exhausted (or fails) and all
+    /// queued packets have been handled.
+    pub fn run<S, F>(&self, source: &mut S, handler: F) -> PipelineStats
+        where S: PacketSource + ?Sized, F: Fn(Packet) + Sync {
+
+        let queue = Queue {
+            state: Mutex::new(State { packets: VecDeque::new(), closed: false, stats: PipelineStats::default() }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        };
+
+        thread::scope(|scope| {
+            for _ in 0..self.workers {
+                scope.spawn(|| while let Some(packet) = queue.pop() {
+                    handler(packet);
+                });
+            }
+
+            let mut errors = 0;
+            while let Some(result) = source.next_packet() {
+                match result {
+                    Ok(packet) => queue.push(packet, self.capacity, self.policy),
+                    Err(_) => {
+                        errors += 1;
+                        break;
+                    },
+                }
+            }
+
+            let mut state = queue.state.lock().unwrap();
+            state.closed = true;
+            state.stats.source_errors = errors;
+            state.stats.source_dropped = source.stats().dropped;
+            queue.not_empty.notify_all();
+        });
+
+        queue.state.into_inner().unwrap().stats
+    }
+}
+
+struct State {
+    packets: VecDeque<Packet>,
+    closed: bool,
+    stats: PipelineStats,
+}
+
+struct Queue {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl Queue {
+    fn push(&self, packet: Packet, capacity: usize, policy: DropPolicy) {
+        let mut state = self.state.lock().unwrap();
+        state.stats.captured += 1;
+
+        if state.packets.len() >= capacity {
+            match policy {
+                DropPolicy::Block => {
+                    while state.packets.len() >= capacity {
+                        state = self.not_full.wait(state).unwrap();
+                    }
+                },
+                DropPolicy::DropNewest => {
+                    state.stats.dropped_newest += 1;
+                    return;
+                },
+                DropPolicy::DropOldest => {
+                    state.packets.pop_front();
+                    state.stats.dropped_oldest += 1;
+                },
+            }
+        }
+
+        state.packets.push_back(packet);
+        state.stats.max_depth = ::std::cmp::max(state.stats.max_depth, state.packets.len());
+        self.not_empty.notify_one();
+    }
+
+    fn pop(&self) -> Option<Packet> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(packet) = state.packets.pop_front() {
+                state.stats.processed += 1;
+                self.not_full.notify_one();
+                return Some(packet);
+            }
+
+            if state.closed {
+                return None;
+            }
+
+            state = self.not_empty.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use source::{Packet, PacketSource, SourceStats, VecSource};
+
+    /// Opens a gate once every packet has been captured, so that workers
+    /// can be held up until the queue has overflowed.
+    struct Gated {
+        source: VecSource,
+        gate: Option<mpsc::Sender<()>>,
+    }
+
+    impl PacketSource for Gated {
+        fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+            let next = self.source.next_packet();
+            if next.is_none() {
+                self.gate.take();
+            }
+            next
+        }
+
+        fn link_type(&self) -> u32 { self.source.link_type() }
+        fn stats(&self) -> SourceStats { self.source.stats() }
+    }
+
+    fn packets(n: u8) -> VecSource {
+        let packets = (0..n)
+            .map(|i| Packet { timestamp: Duration::new(i as u64, 0), original_length: 1, data: vec![i] })
+            .collect();
+
+        VecSource::new(packets, 1)
+    }
+
+    #[test]
+    fn drop_policies() {
+        let handled = Mutex::new(Vec::new());
+        let stats = Pipeline::new(2).workers(3).run(&mut packets(50), |p| handled.lock().unwrap().push(p.data[0]));
+        assert_eq!((stats.captured, stats.processed, stats.dropped()), (50, 50, 0));
+        assert!(stats.max_depth <= 2);
+        assert_eq!(handled.lock().unwrap().len(), 50);
+
+        for &policy in &[DropPolicy::DropNewest, DropPolicy::DropOldest] {
+            let (open, gate) = mpsc::channel::<()>();
+            let gate = Mutex::new(gate);
+            let handled = Mutex::new(Vec::new());
+
+            let mut source = Gated { source: packets(10), gate: Some(open) };
+            let stats = Pipeline::new(2).policy(policy).run(&mut source, |p| {
+                let _ = gate.lock().unwrap().recv();
+                handled.lock().unwrap().push(p.data[0]);
+            });
+
+            // At most one packet reaches the worker before the queue fills.
+            assert_eq!(stats.captured, 10);
+            assert_eq!(stats.processed + stats.dropped(), 10);
+            assert!(stats.dropped() >= 7);
+
+            let handled = handled.lock().unwrap();
+            match policy {
+                DropPolicy::DropNewest => assert_eq!(handled[0], 0),
+                _ => assert_eq!(handled.last(), Some(&9)),
+            }
+        }
+    }
+}
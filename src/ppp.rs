@@ -0,0 +1,73 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Point-to-Point Protocol (PPP) frames, as captured on PPP
+//! interfaces (with or without the HDLC-like address and control fields).
+//!
+//! See [RFC 1661](https://tools.ietf.org/html/rfc1661) and
+//! [RFC 1662](https://tools.ietf.org/html/rfc1662).
+
+use DissectError;
+use DissectResult;
+use Val;
+use NamedValues;
+use context::Context;
+use ip;
+use read_be_u16;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a PPP frame, and its payload within a context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    // Address 0xff and control 0x03 are optional (and usually compressed away).
+    let framing = if data.len() >= 2 && data[0] == 0xff && data[1] == 0x03 { 2 } else { 0 };
+
+    if data.len() < framing + 2 {
+        return Err(DissectError::Underflow { expected: Some(framing + 2), have: data.len(),
+            message: "A PPP frame must have a 2 B protocol field".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let protocol = read_be_u16(data, framing)? as u64;
+    values.push(("Protocol", Val::Unsigned(protocol)));
+
+    let remainder = &data[framing + 2..];
+    values.push(("Payload", match protocol {
+        0x0021 => Val::Payload(remainder, context.dissect(ip::dissect_with, remainder)),
+        0x0057 => Val::Payload(remainder, context.dissect(ip::ipv6::dissect_with, remainder)),
+        0x8021 => Val::Undissected("IPCP", remainder),
+        0x8057 => Val::Undissected("IPv6CP", remainder),
+        0xc021 => Val::Undissected("LCP", remainder),
+        0xc023 => Val::Undissected("PAP", remainder),
+        0xc223 => Val::Undissected("CHAP", remainder),
+        _ => Val::Undissected("Unknown", remainder),
+    }));
+
+    Ok(Box::new(Val::Object("PPP", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_ppp() {
+        let lcp = [0xff, 0x03, 0xc0, 0x21, 0x01, 0x01, 0x00, 0x04];
+        let val = *dissect(&lcp).unwrap();
+        assert_eq!(val["Protocol"].as_unsigned().unwrap(), 0xc021);
+        assert_eq!(val["Payload"].as_undissected().unwrap().0, "LCP");
+
+        let ipv4 = [0x00, 0x21, 0x45, 0x00];
+        let val = *dissect(&ipv4).unwrap();
+        assert!(val["Payload"].as_payload().unwrap().is_err());
+    }
+}
@@ -0,0 +1,482 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Typed dissector preferences and the profiles that store them.
+//!
+//! A dissector declares each of its preferences (e.g., whether to validate
+//! checksums, where to find a TLS key log or which ports carry HTTP) with a
+//! name, a description and a typed default value, then reads them through
+//! its `context::Context`. Values are checked against the declared type
+//! when they are set, so a dissector never has to parse them itself.
+//!
+//! Preferences are saved to and loaded from profiles written in (a subset
+//! of) TOML: one `name = value` line per preference, where a value is a
+//! boolean, an integer, an array of ports or a quoted path. Names may be
+//! split across `[section]` headers and `#` starts a comment. Preferences
+//! that still have their default values are written commented out, so that
+//! a profile documents every preference but only overrides some. A profile
+//! is loaded all or nothing: if any entry is invalid, none are set.
+//!
+//! `Preferences::builtin()` declares the preferences of rshark's own
+//! dissectors, which is what a `Context` starts out with:
+//!
+//!  * `ip.check_checksum`: whether to verify IPv4, TCP, UDP, DCCP and
+//!    UDP-Lite checksums,
+//!  * `tls.keylog_file`: an NSS key log of TLS secrets (see
+//!    `Context::tls_keys`), and
+//!  * `http.tcp_ports`: TCP ports to dissect as HTTP.
+//!
+//! ```
+//! use rshark::preferences::{Preferences, Value};
+//!
+//! let mut preferences = Preferences::new();
+//! preferences.declare("tcp.check_checksum", "Check TCP checksums", Value::Bool(true));
+//! preferences.declare("http.tcp_ports", "TCP ports that carry HTTP", Value::Ports(vec![80]));
+//!
+//! preferences.load_toml("[http]\ntcp_ports = [80, 8080]  # and the proxy\n").unwrap();
+//! assert_eq!(preferences.ports("http.tcp_ports"), Some(&[80, 8080][..]));
+//! assert_eq!(preferences.bool("tcp.check_checksum"), Some(true));
+//!
+//! assert_eq!(preferences.to_toml(),
+//!            "# TCP ports that carry HTTP\nhttp.tcp_ports = [80, 8080]\n\n# Check TCP checksums\n\
+//!             #tcp.check_checksum = true\n");
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use DissectError;
+
+/// An invalid preference name, value or profile.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PreferenceError {
+    /// No preference of this name has been declared.
+    Unknown(String),
+
+    /// A value of another type than the preference was declared with.
+    WrongKind { name: String, expected: Kind, found: Kind },
+
+    /// Text that isn't a value of the preference's type.
+    InvalidValue { name: String, message: String },
+
+    /// An invalid profile entry, numbered from 1.
+    Profile { line: usize, message: String },
+
+    /// A profile that can't be read or written.
+    Io { path: PathBuf, message: String },
+}
+
+impl Error for PreferenceError {}
+
+impl fmt::Display for PreferenceError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &PreferenceError::Unknown(ref name) => write![f, "no such preference: '{}'", name],
+            &PreferenceError::WrongKind { ref name, expected, found } =>
+                write![f, "preference '{}' is a {}, not a {}", name, expected.name(), found.name()],
+            &PreferenceError::InvalidValue { ref name, ref message } =>
+                write![f, "invalid value for preference '{}': {}", name, message],
+            &PreferenceError::Profile { line, ref message } =>
+                write![f, "invalid profile entry on line {}: {}", line, message],
+            &PreferenceError::Io { ref path, ref message } =>
+                write![f, "{}: {}", path.display(), message],
+        }
+    }
+}
+
+impl From<PreferenceError> for DissectError {
+    fn from(error: PreferenceError) -> DissectError {
+        DissectError::InvalidData(error.to_string())
+    }
+}
+
+/// The types of preference values.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Bool,
+    Integer,
+    Ports,
+    Path,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Kind::Bool => "boolean",
+            Kind::Integer => "integer",
+            Kind::Ports => "port list",
+            Kind::Path => "path",
+        }
+    }
+
+    /// Parse a value of this type, in TOML form (though paths may also be
+    /// given without quotes, e.g., on a command line).
+    pub fn parse(&self, text: &str) -> Result<Value, String> {
+        let text = text.trim();
+        match *self {
+            Kind::Bool => match text {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(format!["'{}' is neither true nor false", text]),
+            },
+            Kind::Integer => text.parse().map(Value::Integer)
+                .map_err(|e| format!["'{}' isn't an integer: {}", text, e]),
+            Kind::Ports => {
+                if !text.starts_with('[') || !text.ends_with(']') {
+                    return Err(format!["'{}' isn't an array of ports", text]);
+                }
+
+                text[1..text.len() - 1].split(',')
+                    .map(str::trim)
+                    .filter(|port| !port.is_empty())
+                    .map(|port| port.parse().map_err(|_| format!["'{}' isn't a port", port]))
+                    .collect::<Result<Vec<u16>, String>>()
+                    .map(Value::Ports)
+            },
+            Kind::Path if text.starts_with('"') => unquote(text).map(|p| Value::Path(p.into())),
+            Kind::Path => Ok(Value::Path(text.into())),
+        }
+    }
+}
+
+/// The value of a preference.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    Ports(Vec<u16>),
+    Path(PathBuf),
+}
+
+impl Value {
+    pub fn kind(&self) -> Kind {
+        match *self {
+            Value::Bool(_) => Kind::Bool,
+            Value::Integer(_) => Kind::Integer,
+            Value::Ports(_) => Kind::Ports,
+            Value::Path(_) => Kind::Path,
+        }
+    }
+}
+
+/// Values in TOML form.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Bool(b) => write![f, "{}", b],
+            Value::Integer(i) => write![f, "{}", i],
+            Value::Ports(ref ports) => write![f, "[{}]", ports.iter().map(u16::to_string)
+                                                              .collect::<Vec<_>>().join(", ")],
+            Value::Path(ref path) => write![f, "\"{}\"", path.display().to_string()
+                                                             .replace('\\', "\\\\")
+                                                             .replace('"', "\\\"")],
+        }
+    }
+}
+
+/// A declared preference.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preference {
+    /// Dotted name, starting with the protocol, e.g., "tls.keylog_file".
+    pub name: &'static str,
+
+    pub description: &'static str,
+    pub default: Value,
+}
+
+/// Declared preferences and the values they have been given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preferences {
+    declared: BTreeMap<&'static str, Preference>,
+    values: BTreeMap<&'static str, Value>,
+}
+
+impl Preferences {
+    pub fn new() -> Preferences {
+        Preferences::default()
+    }
+
+    /// The preferences of rshark's own dissectors, at their defaults.
+    pub fn builtin() -> Preferences {
+        let mut preferences = Preferences::new();
+        preferences.declare("ip.check_checksum", "Verify IPv4, TCP, UDP and DCCP checksums",
+                            Value::Bool(true));
+        preferences.declare("tls.keylog_file", "NSS key log of TLS secrets (if any)",
+                            Value::Path(PathBuf::new()));
+        preferences.declare("http.tcp_ports", "TCP ports to dissect as HTTP",
+                            Value::Ports(vec![80, 8080]));
+        preferences
+    }
+
+    /// Declare a preference (again, replacing its description and default).
+    pub fn declare(&mut self, name: &'static str, description: &'static str, default: Value) {
+        if self.values.get(name).map(|v| v.kind() != default.kind()).unwrap_or(false) {
+            self.values.remove(name);
+        }
+
+        self.declared.insert(name, Preference {
+            name: name,
+            description: description,
+            default: default,
+        });
+    }
+
+    /// All declared preferences, by name.
+    pub fn declared(&self) -> Vec<&Preference> {
+        self.declared.values().collect()
+    }
+
+    /// Set a declared preference to a value of its type.
+    pub fn set(&mut self, name: &str, value: Value) -> Result<(), PreferenceError> {
+        let preference = self.preference(name)?;
+        if value.kind() != preference.default.kind() {
+            return Err(PreferenceError::WrongKind { name: name.to_string(),
+                                                    expected: preference.default.kind(),
+                                                    found: value.kind() });
+        }
+
+        let name = preference.name;
+        self.values.insert(name, value);
+        Ok(())
+    }
+
+    /// Set a declared preference from text, e.g., "true" or "[80, 8080]".
+    pub fn set_str(&mut self, name: &str, text: &str) -> Result<(), PreferenceError> {
+        let value = self.preference(name)?.default.kind().parse(text).map_err(|e|
+            PreferenceError::InvalidValue { name: name.to_string(), message: e })?;
+        self.set(name, value)
+    }
+
+    /// Return a preference to its default value.
+    pub fn reset(&mut self, name: &str) {
+        self.values.remove(name);
+    }
+
+    /// The value of a declared preference (or its default).
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name).or_else(|| self.declared.get(name).map(|p| &p.default))
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.get(name) {
+            Some(&Value::Bool(b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(&Value::Integer(i)) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn ports(&self, name: &str) -> Option<&[u16]> {
+        match self.get(name) {
+            Some(&Value::Ports(ref ports)) => Some(ports),
+            _ => None,
+        }
+    }
+
+    pub fn path(&self, name: &str) -> Option<&Path> {
+        match self.get(name) {
+            Some(&Value::Path(ref path)) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Whether a preference has been given a value other than its default.
+    pub fn is_changed(&self, name: &str) -> bool {
+        match (self.values.get(name), self.declared.get(name)) {
+            (Some(value), Some(preference)) => *value != preference.default,
+            _ => false,
+        }
+    }
+
+    /// Set the preferences in a TOML profile, unless any of its entries is
+    /// invalid.
+    pub fn load_toml(&mut self, toml: &str) -> Result<(), PreferenceError> {
+        let mut section = String::new();
+        let mut values = Vec::new();
+
+        for (i, line) in toml.lines().enumerate() {
+            let error = |message: String| PreferenceError::Profile { line: i + 1,
+                                                                     message: message };
+
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(error(format!["'{}' isn't of the form 'name = value'", line])),
+            };
+
+            let name = if section.is_empty() { key.to_string() }
+                       else { format!["{}.{}", section, key] };
+            let preference = self.preference(&name).map_err(|e| error(e.to_string()))?;
+            let kind = preference.default.kind();
+            let value = match kind {
+                Kind::Path if !value.starts_with('"') =>
+                    Err(format!["'{}' isn't a quoted path", value]),
+                _ => kind.parse(value),
+            }.map_err(&error)?;
+
+            values.push((preference.name, value));
+        }
+
+        self.values.extend(values);
+        Ok(())
+    }
+
+    /// Write every declared preference as a TOML profile, commenting out the
+    /// ones that have their default values.
+    pub fn to_toml(&self) -> String {
+        self.declared.values()
+            .map(|p| format!["# {}\n{}{} = {}\n", p.description,
+                             if self.is_changed(p.name) { "" } else { "#" }, p.name,
+                             self.get(p.name).unwrap_or(&p.default)])
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Load a TOML profile from a file.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PreferenceError> {
+        let mut toml = String::new();
+        File::open(path.as_ref()).and_then(|mut f| f.read_to_string(&mut toml))
+            .map_err(|e| PreferenceError::Io { path: path.as_ref().to_path_buf(),
+                                               message: e.to_string() })?;
+
+        self.load_toml(&toml)
+    }
+
+    /// Save a TOML profile to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PreferenceError> {
+        File::create(path.as_ref()).and_then(|mut f| f.write_all(self.to_toml().as_bytes()))
+            .map_err(|e| PreferenceError::Io { path: path.as_ref().to_path_buf(),
+                                               message: e.to_string() })
+    }
+
+    fn preference(&self, name: &str) -> Result<&Preference, PreferenceError> {
+        self.declared.get(name).ok_or_else(|| PreferenceError::Unknown(name.to_string()))
+    }
+}
+
+/// A line without any trailing `#` comment (outside of a quoted string).
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' if quoted && !escaped => { escaped = true; continue; },
+            '"' if !escaped => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {},
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// The contents of a TOML basic string.
+fn unquote(text: &str) -> Result<String, String> {
+    if text.len() < 2 || !text.ends_with('"') {
+        return Err(format!["'{}' isn't a quoted string", text]);
+    }
+
+    let mut s = String::new();
+    let mut chars = text[1..text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match (c, if c == '\\' { chars.next() } else { None }) {
+            ('\\', Some('\\')) => s.push('\\'),
+            ('\\', Some('"')) => s.push('"'),
+            ('\\', Some('t')) => s.push('\t'),
+            ('\\', Some('n')) => s.push('\n'),
+            ('\\', other) => return Err(format!["unsupported escape '\\{}' in '{}'",
+                                                other.map(|c| c.to_string()).unwrap_or_default(),
+                                                text]),
+            ('"', _) => return Err(format!["unescaped quote in '{}'", text]),
+            (c, _) => s.push(c),
+        }
+    }
+    Ok(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn preferences() -> Preferences {
+        let mut preferences = Preferences::new();
+        preferences.declare("tls.keylog_file", "TLS key log", Value::Path(PathBuf::new()));
+        preferences.declare("tcp.check_checksum", "Check TCP checksums", Value::Bool(false));
+        preferences.declare("rtp.max_jitter", "Jitter limit (ms)", Value::Integer(50));
+        preferences
+    }
+
+    #[test]
+    fn typed_values() {
+        let mut preferences = preferences();
+        preferences.set_str("tcp.check_checksum", "true").unwrap();
+        preferences.set_str("tls.keylog_file", "/tmp/keys.log").unwrap();
+
+        assert_eq!(preferences.bool("tcp.check_checksum"), Some(true));
+        assert_eq!(preferences.integer("rtp.max_jitter"), Some(50));
+        assert_eq!(preferences.path("tls.keylog_file"), Some(Path::new("/tmp/keys.log")));
+        assert_eq!(preferences.ports("tls.keylog_file"), None);
+
+        assert!(preferences.set("rtp.max_jitter", Value::Bool(true)).is_err());
+        assert!(preferences.set_str("rtp.max_jitter", "lots").is_err());
+        assert!(preferences.set_str("rtp.jitter", "1").is_err());
+
+        preferences.reset("tcp.check_checksum");
+        assert_eq!(preferences.bool("tcp.check_checksum"), Some(false));
+    }
+
+    #[test]
+    fn round_trip_toml() {
+        let mut preferences = preferences();
+        preferences.load_toml("\
+            # A comment\n\
+            [tls]\n\
+            keylog_file = \"C:\\\\keys #1.log\"  # trailing comment\n\
+            [rtp]\n\
+            max_jitter = -5\n").unwrap();
+
+        assert_eq!(preferences.path("tls.keylog_file"), Some(Path::new("C:\\keys #1.log")));
+        assert_eq!(preferences.integer("rtp.max_jitter"), Some(-5));
+
+        let toml = preferences.to_toml();
+        assert!(toml.contains("\n#tcp.check_checksum = false\n"));
+
+        let mut loaded = self::preferences();
+        loaded.load_toml(&toml).unwrap();
+        assert_eq!(loaded, preferences);
+
+        let error = loaded.load_toml("tls.keylog_file = /tmp/keys").unwrap_err();
+        assert_eq!(error, PreferenceError::Profile {
+            line: 1, message: "'/tmp/keys' isn't a quoted path".to_string() });
+        assert_eq!(loaded.load_toml("[tcp]\ncheck = true").unwrap_err().to_string(),
+                   "invalid profile entry on line 2: no such preference: 'tcp.check'");
+
+        // Nothing is set from a profile with any invalid entry.
+        assert!(loaded.load_toml("rtp.max_jitter = 7\ntcp.check_checksum = 1").is_err());
+        assert_eq!(loaded, preferences);
+    }
+}
@@ -0,0 +1,254 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Capture presets: the first dissector to use for a capture, chosen from its
+//! link type or the name of the interface it was captured on.
+//!
+//! Not every capture starts with an Ethernet header. Tunnel interfaces such
+//! as WireGuard's `wg0` or OpenVPN's `tun0` carry bare IP packets and PPP
+//! links carry PPP frames; dissecting them as Ethernet produces garbage.
+//!
+//! ```
+//! use rshark::preset;
+//!
+//! let preset = preset::select(Some("wg0"), 0xffff).unwrap();
+//! assert_eq!(preset.name, "Raw IP");
+//!
+//! let packet = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+//! assert!((preset.dissector)(&packet).is_ok());
+//! ```
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use can;
+use context::Context;
+use ethernet;
+use ieee80211;
+use ieee802154;
+use ip;
+use loopback;
+use output::pcap::{LINKTYPE_ETHERNET, LINKTYPE_IPV4, LINKTYPE_IPV6, LINKTYPE_RAW};
+use ppp;
+use registry::ContextHandler;
+use sll;
+use usb;
+
+/// How to dissect the packets of one kind of capture.
+pub struct Preset {
+    pub name: &'static str,
+
+    /// libpcap link types that this preset applies to.
+    pub link_types: &'static [u32],
+
+    /// Prefixes of the names of interfaces that produce such captures.
+    pub interfaces: &'static [&'static str],
+
+    /// The dissector for the first (outermost) layer of each packet.
+    pub dissector: fn(&[u8]) -> DissectResult,
+
+    /// The same dissector, passing a `Context` on to the layers within (if
+    /// the layer encapsulates others).
+    pub dissector_with: Option<ContextHandler>,
+}
+
+/// libpcap link types for PPP, without and with HDLC-like framing.
+pub const LINKTYPE_PPP: u32 = 9;
+pub const LINKTYPE_PPP_HDLC: u32 = 50;
+
+/// libpcap link types for 802.11 frames, without and with a Radiotap header.
+pub const LINKTYPE_IEEE802_11: u32 = 105;
+pub const LINKTYPE_IEEE802_11_RADIOTAP: u32 = 127;
+
+/// libpcap link types for BSD loopback encapsulation, in host (NULL) or
+/// network (LOOP) byte order.
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_LOOP: u32 = 108;
+
+/// libpcap link type for CAN frames captured by Linux SocketCAN.
+pub const LINKTYPE_CAN_SOCKETCAN: u32 = 227;
+
+/// libpcap link types for USB traffic captured by Linux usbmon, with 48 B
+/// and 64 B headers.
+pub const LINKTYPE_USB_LINUX: u32 = 189;
+pub const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+/// libpcap link types for IEEE 802.15.4 frames with and without an FCS.
+pub const LINKTYPE_IEEE802_15_4_WITHFCS: u32 = 195;
+pub const LINKTYPE_IEEE802_15_4_NOFCS: u32 = 230;
+
+/// libpcap link types for Linux cooked captures (e.g., on the "any" device).
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+pub static PRESETS: [Preset; 14] = [
+    Preset {
+        name: "Ethernet",
+        link_types: &[LINKTYPE_ETHERNET],
+        interfaces: &["eth", "en", "wl", "tap", "br", "veth", "docker", "bond"],
+        dissector: ethernet::dissect,
+        dissector_with: Some(ethernet::dissect_with),
+    },
+    Preset {
+        name: "Raw IP",
+        // DLT_RAW is 12 on most systems but 14 on OpenBSD.
+        link_types: &[LINKTYPE_RAW, LINKTYPE_IPV4, LINKTYPE_IPV6, 12, 14],
+        interfaces: &["wg", "tun", "ipsec", "vti", "gre"],
+        dissector: ip::dissect_raw,
+        dissector_with: Some(ip::dissect_raw_with),
+    },
+    Preset {
+        name: "PPP",
+        link_types: &[LINKTYPE_PPP, LINKTYPE_PPP_HDLC],
+        interfaces: &["ppp"],
+        dissector: ppp::dissect,
+        dissector_with: Some(ppp::dissect_with),
+    },
+    Preset {
+        name: "802.11",
+        link_types: &[LINKTYPE_IEEE802_11],
+        interfaces: &[],
+        dissector: ieee80211::dissect,
+        dissector_with: None,
+    },
+    Preset {
+        name: "802.11 with Radiotap",
+        link_types: &[LINKTYPE_IEEE802_11_RADIOTAP],
+        interfaces: &["mon"],
+        dissector: ieee80211::radiotap::dissect,
+        dissector_with: None,
+    },
+    Preset {
+        name: "Linux cooked capture",
+        link_types: &[LINKTYPE_LINUX_SLL],
+        interfaces: &["any"],
+        dissector: sll::dissect,
+        dissector_with: Some(sll::dissect_with),
+    },
+    Preset {
+        name: "Linux cooked capture v2",
+        link_types: &[LINKTYPE_LINUX_SLL2],
+        interfaces: &[],
+        dissector: sll::dissect_v2,
+        dissector_with: Some(sll::dissect_v2_with),
+    },
+    Preset {
+        name: "Null/Loopback",
+        link_types: &[LINKTYPE_NULL],
+        interfaces: &["lo0", "utun"],
+        dissector: loopback::dissect,
+        dissector_with: Some(loopback::dissect_with),
+    },
+    Preset {
+        name: "Loopback",
+        link_types: &[LINKTYPE_LOOP],
+        interfaces: &[],
+        dissector: loopback::dissect_loop,
+        dissector_with: Some(loopback::dissect_loop_with),
+    },
+    Preset {
+        name: "SocketCAN",
+        link_types: &[LINKTYPE_CAN_SOCKETCAN],
+        interfaces: &["can", "vcan", "slcan"],
+        dissector: can::dissect,
+        dissector_with: None,
+    },
+    Preset {
+        name: "usbmon",
+        link_types: &[LINKTYPE_USB_LINUX_MMAPPED],
+        interfaces: &["usbmon"],
+        dissector: usb::dissect,
+        dissector_with: None,
+    },
+    Preset {
+        name: "usbmon (legacy)",
+        link_types: &[LINKTYPE_USB_LINUX],
+        interfaces: &[],
+        dissector: usb::dissect_legacy,
+        dissector_with: None,
+    },
+    Preset {
+        name: "802.15.4",
+        link_types: &[LINKTYPE_IEEE802_15_4_WITHFCS],
+        interfaces: &[],
+        dissector: ieee802154::dissect,
+        dissector_with: Some(ieee802154::dissect_with),
+    },
+    Preset {
+        name: "802.15.4 without FCS",
+        link_types: &[LINKTYPE_IEEE802_15_4_NOFCS],
+        interfaces: &["wpan"],
+        dissector: ieee802154::dissect_nofcs,
+        dissector_with: Some(ieee802154::dissect_nofcs_with),
+    },
+];
+
+/// The preset for a libpcap link type.
+pub fn for_link_type(link_type: u32) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.link_types.contains(&link_type))
+}
+
+/// Dissect a packet according to the link type of its capture.
+pub fn dissect(link_type: u32, data: &[u8]) -> DissectResult {
+    dissect_with(link_type, data, &mut Context::new())
+}
+
+/// Dissect a packet according to the link type of its capture, within a
+/// context. Link layers that encapsulate other protocols pass the context on
+/// to their payloads, all the way to TCP and UDP.
+pub fn dissect_with<'data>(link_type: u32, data: &'data [u8], context: &mut Context)
+    -> DissectResult<'data> {
+
+    match for_link_type(link_type) {
+        Some(&Preset { dissector_with: Some(dissector), .. }) => context.dissect(dissector, data),
+        Some(preset) => context.dissect_stateless(preset.dissector, data),
+        None => Err(DissectError::malformed(ErrorCode::UnknownProtocol,
+                                            format!["unsupported link type {}", link_type])),
+    }
+}
+
+/// The preset for an interface, by its name (e.g., "wg0").
+pub fn for_interface(interface: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.interfaces.iter().any(|prefix| interface.starts_with(prefix)))
+}
+
+/// Choose a preset for a capture. A known link type is authoritative; the
+/// interface name is used when the link type is unknown.
+pub fn select(interface: Option<&str>, link_type: u32) -> Option<&'static Preset> {
+    for_link_type(link_type).or(interface.and_then(for_interface))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn select_presets() {
+        assert_eq!(for_interface("wg0").unwrap().name, "Raw IP");
+        assert_eq!(for_interface("tun1").unwrap().name, "Raw IP");
+        assert_eq!(for_interface("tap0").unwrap().name, "Ethernet");
+        assert_eq!(for_interface("ppp0").unwrap().name, "PPP");
+        assert_eq!(for_interface("enp0s3").unwrap().name, "Ethernet");
+        assert_eq!(for_interface("mon0").unwrap().name, "802.11 with Radiotap");
+        assert_eq!(for_interface("vcan0").unwrap().name, "SocketCAN");
+        assert!(for_interface("lo").is_none());
+
+        // The link type wins over the interface name.
+        assert_eq!(select(Some("wg0"), LINKTYPE_ETHERNET).unwrap().name, "Ethernet");
+        assert_eq!(select(None, LINKTYPE_IPV6).unwrap().name, "Raw IP");
+        assert_eq!(select(Some("lo0"), 0xffff).unwrap().name, "Null/Loopback");
+        assert_eq!(select(None, LINKTYPE_NULL).unwrap().name, "Null/Loopback");
+        assert!(select(None, 0xffff).is_none());
+
+        let sll = [0, 0, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0x86, 0xdd];
+        let val = dissect(LINKTYPE_LINUX_SLL, &sll).unwrap();
+        assert_eq!(val["Hardware Type"].as_symbol().unwrap(), "Ethernet");
+        assert!(dissect(0xffff, &sll).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Progress reporting for long-running operations.
+//!
+//! Reading a capture, indexing it and running statistics over it can all
+//! take a while. Each of these operations can be given a `Reporter`, which
+//! calls back with a `Progress` event (packets and bytes processed so far,
+//! the totals if they are known and an estimate of the time remaining) at
+//! most once per interval, and once more when the operation finishes.
+//!
+//! ```
+//! use rshark::progress::{Operation, ProgressSource, Reporter};
+//! use rshark::source::{Packet, PacketSource, VecSource};
+//! use std::time::Duration;
+//!
+//! let packet = Packet { timestamp: Duration::new(0, 0), original_length: 60, data: vec![0; 60] };
+//! let source = VecSource::new(vec![packet; 10], 1);
+//!
+//! let mut events = Vec::new();
+//! {
+//!     let reporter = Reporter::new(Operation::Reading, |p| events.push(p.clone()))
+//!         .interval(Duration::new(0, 0));
+//!
+//!     let mut source = ProgressSource::new(source, reporter);
+//!     while let Some(_) = source.next_packet() {}
+//! }
+//!
+//! assert_eq!(events.len(), 11);
+//! assert!(events[10].finished);
+//! assert_eq!(events[10].fraction(), Some(1.0));
+//! ```
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use source::{Packet, PacketSource, SourceStats};
+
+/// The kind of operation that is making progress.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operation {
+    Reading,
+    Indexing,
+    Dissecting,
+    Statistics,
+}
+
+/// A progress event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub operation: Operation,
+    pub packets: u64,
+    pub bytes: u64,
+
+    /// Totals for the whole operation, if they are known in advance.
+    pub total_packets: Option<u64>,
+    pub total_bytes: Option<u64>,
+
+    /// Time since the operation started.
+    pub elapsed: Duration,
+
+    /// Whether this is the final event of the operation.
+    pub finished: bool,
+}
+
+impl Progress {
+    /// The fraction of the operation that has been completed, by packets if
+    /// their total is known or else by bytes.
+    pub fn fraction(&self) -> Option<f64> {
+        if self.finished {
+            return Some(1.0);
+        }
+
+        let fraction = |done: u64, total: u64| if total == 0 { 1.0 } else {
+            (done as f64 / total as f64).min(1.0)
+        };
+
+        self.total_packets.map(|t| fraction(self.packets, t))
+            .or(self.total_bytes.map(|t| fraction(self.bytes, t)))
+    }
+
+    /// The estimated time remaining, extrapolated from the rate so far.
+    pub fn eta(&self) -> Option<Duration> {
+        match self.fraction() {
+            Some(f) if f >= 1.0 => Some(Duration::new(0, 0)),
+            Some(f) if f > 0.0 => Some(self.elapsed.mul_f64((1.0 - f) / f)),
+            _ => None,
+        }
+    }
+}
+
+/// Delivers progress events for one operation to a callback.
+pub struct Reporter<'a> {
+    operation: Operation,
+    total_packets: Option<u64>,
+    total_bytes: Option<u64>,
+    interval: Duration,
+    started: Instant,
+    last: Option<Instant>,
+    finished: bool,
+    callback: Box<dyn FnMut(&Progress) + 'a>,
+}
+
+impl<'a> Reporter<'a> {
+    /// A reporter that calls back at most every 100 ms.
+    pub fn new<F>(operation: Operation, callback: F) -> Reporter<'a> where F: FnMut(&Progress) + 'a {
+        Reporter {
+            operation: operation,
+            total_packets: None,
+            total_bytes: None,
+            interval: Duration::from_millis(100),
+            started: Instant::now(),
+            last: None,
+            finished: false,
+            callback: Box::new(callback),
+        }
+    }
+
+    pub fn total_packets(mut self, total: u64) -> Reporter<'a> {
+        self.total_packets = Some(total);
+        self
+    }
+
+    pub fn total_bytes(mut self, total: u64) -> Reporter<'a> {
+        self.total_bytes = Some(total);
+        self
+    }
+
+    /// The minimum time between events (other than the final one).
+    pub fn interval(mut self, interval: Duration) -> Reporter<'a> {
+        self.interval = interval;
+        self
+    }
+
+    /// Report the work done so far, if an interval has passed since the last
+    /// event.
+    pub fn update(&mut self, packets: u64, bytes: u64) {
+        let now = Instant::now();
+        let due = self.last.map(|last| now.duration_since(last) >= self.interval).unwrap_or(true);
+
+        if due && !self.finished {
+            self.last = Some(now);
+            self.report(packets, bytes, false);
+        }
+    }
+
+    /// Report the end of the operation. Only the first call has any effect.
+    pub fn finish(&mut self, packets: u64, bytes: u64) {
+        if !self.finished {
+            self.finished = true;
+            self.report(packets, bytes, true);
+        }
+    }
+
+    fn report(&mut self, packets: u64, bytes: u64, finished: bool) {
+        let progress = Progress {
+            operation: self.operation,
+            packets: packets,
+            bytes: bytes,
+            total_packets: self.total_packets,
+            total_bytes: self.total_bytes,
+            elapsed: self.started.elapsed(),
+            finished: finished,
+        };
+
+        (self.callback)(&progress);
+    }
+}
+
+/// A packet source that reports the progress of reading from another.
+pub struct ProgressSource<'a, S: PacketSource> {
+    source: S,
+    reporter: Reporter<'a>,
+}
+
+impl<'a, S: PacketSource> ProgressSource<'a, S> {
+    /// Wrap a source. If the reporter has no totals, the source's packet
+    /// count (if it knows it) is used.
+    pub fn new(source: S, mut reporter: Reporter<'a>) -> ProgressSource<'a, S> {
+        if reporter.total_packets.is_none() && reporter.total_bytes.is_none() {
+            reporter.total_packets = source.packet_count();
+        }
+
+        ProgressSource { source: source, reporter: reporter }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<'a, S: PacketSource> PacketSource for ProgressSource<'a, S> {
+    fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+        let next = self.source.next_packet();
+        let stats = self.source.stats();
+
+        match next {
+            Some(Ok(_)) => self.reporter.update(stats.packets, stats.bytes),
+            _ => self.reporter.finish(stats.packets, stats.bytes),
+        }
+
+        next
+    }
+
+    fn link_type(&self) -> u32 {
+        self.source.link_type()
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.source.stats()
+    }
+
+    fn packet_count(&self) -> Option<u64> {
+        self.source.packet_count()
+    }
+
+    fn seek(&mut self, index: u64) -> io::Result<()> {
+        self.source.seek(index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn fraction_and_eta() {
+        let mut progress = Progress {
+            operation: Operation::Statistics,
+            packets: 25,
+            bytes: 1000,
+            total_packets: None,
+            total_bytes: Some(4000),
+            elapsed: Duration::new(3, 0),
+            finished: false,
+        };
+
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert_eq!(progress.eta(), Some(Duration::new(9, 0)));
+
+        progress.total_packets = Some(50);
+        assert_eq!(progress.fraction(), Some(0.5));
+        assert_eq!(progress.eta(), Some(Duration::new(3, 0)));
+
+        progress.total_packets = None;
+        progress.total_bytes = None;
+        assert_eq!(progress.eta(), None);
+
+        progress.finished = true;
+        assert_eq!(progress.fraction(), Some(1.0));
+    }
+
+    #[test]
+    fn throttled_updates() {
+        let mut events = Vec::new();
+        {
+            let mut reporter = Reporter::new(Operation::Reading, |p: &Progress| events.push(p.packets))
+                .interval(Duration::new(3600, 0));
+
+            for i in 1..100 {
+                reporter.update(i, 0);
+            }
+            reporter.finish(100, 0);
+            reporter.finish(100, 0);
+        }
+
+        assert_eq!(events, vec![1, 100]);
+    }
+}
@@ -0,0 +1,280 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! The byte ranges that dissected values came from.
+//!
+//! Values that hold bytes (`Bytes`, `Address`, `Payload` and `Undissected`)
+//! borrow them from the packet, so their offsets can be recovered by
+//! reference rather than recorded by every dissector. `annotate` pairs each
+//! value of a dissection with its `Span`: layers span the payload they were
+//! dissected from and other objects and lists span their located fields
+//! (list elements are named after the list). Numbers and flags decoded from
+//! the fixed part of an IPv4, IPv6, TCP, UDP or UDP-Lite header are located
+//! by their offset within the layer (see `LAYOUTS`); other decoded values
+//! have no span of their own, so a front-end can only highlight their
+//! enclosing layer. Bytes copied out of the packet, e.g., by reassembly or
+//! decryption, aren't located either.
+//!
+//! `coverage` uses the same spans to report the bytes that no dissector
+//! interpreted: undissected payloads, payloads that failed to dissect and
+//! the "raw data" of the dissector of last resort.
+//!
+//! ```
+//! use rshark::provenance;
+//!
+//! // An Ethernet frame carrying an unknown EtherType
+//! let mut frame = vec![0xff; 12];
+//! frame.extend_from_slice(&[0x88, 0xb5, 1, 2, 3, 4]);
+//!
+//! let val = rshark::ethernet::dissect(&frame).unwrap();
+//! let tree = provenance::annotate(&frame, &val);
+//! assert_eq!(tree.path(0), vec!["Ethernet frame", "Destination"]);
+//!
+//! let coverage = provenance::coverage(&frame, &val);
+//! assert_eq!(coverage.unparsed, vec![provenance::Span { offset: 14, length: 4 }]);
+//! ```
+
+use Val;
+
+/// The offsets and lengths of decoded fields within the layers that have a
+/// fixed header layout.
+pub const LAYOUTS: &'static [(&'static str, &'static [(&'static str, usize, usize)])] = &[
+    ("IPv4", &[("Version", 0, 1), ("IHL", 0, 1), ("DSCP", 1, 1), ("ECN", 1, 1),
+               ("Length", 2, 2), ("Identification", 4, 2), ("Flags", 6, 1),
+               ("Fragment Offset", 6, 2), ("TTL", 8, 1), ("Protocol", 9, 1)]),
+    ("IPv6", &[("Version", 0, 1), ("DSCP", 0, 2), ("ECN", 1, 1), ("Flow Label", 1, 3),
+               ("Payload Length", 4, 2), ("Next Header", 6, 1), ("Hop Limit", 7, 1)]),
+    ("TCP", &[("Source Port", 0, 2), ("Destination Port", 2, 2), ("Sequence Number", 4, 4),
+              ("Acknowledgement Number", 8, 4), ("Offset", 12, 1), ("Flags", 12, 2),
+              ("Window", 14, 2), ("Urgent Pointer", 18, 2)]),
+    ("UDP", &[("Source Port", 0, 2), ("Destination Port", 2, 2), ("Length", 4, 2)]),
+    ("UDP-Lite", &[("Source Port", 0, 2), ("Destination Port", 2, 2),
+                   ("Checksum Coverage", 4, 2)]),
+];
+
+/// A range of a packet's bytes.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Span {
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Span {
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        offset >= self.offset && offset < self.end()
+    }
+
+    /// The smallest span covering both spans.
+    pub fn union(&self, other: &Span) -> Span {
+        let offset = self.offset.min(other.offset);
+        Span { offset: offset, length: self.end().max(other.end()) - offset }
+    }
+}
+
+/// Where `bytes` lie within `packet`, if they are part of it.
+pub fn locate(packet: &[u8], bytes: &[u8]) -> Option<Span> {
+    let start = packet.as_ptr() as usize;
+    let offset = (bytes.as_ptr() as usize).wrapping_sub(start);
+
+    if offset <= packet.len() && bytes.len() <= packet.len() - offset {
+        Some(Span { offset: offset, length: bytes.len() })
+    } else {
+        None
+    }
+}
+
+/// A dissected value and the bytes it came from.
+#[derive(Debug)]
+pub struct Node<'val, 'data: 'val> {
+    /// The value's field name, or the layer name of a dissected payload.
+    pub name: &'static str,
+    pub val: &'val Val<'data>,
+    pub span: Option<Span>,
+    pub children: Vec<Node<'val, 'data>>,
+}
+
+impl<'val, 'data> Node<'val, 'data> {
+    /// The innermost node whose span contains a byte, e.g., to select the
+    /// tree node for a byte clicked in a hex view.
+    pub fn innermost(&self, offset: usize) -> Option<&Node<'val, 'data>> {
+        if !self.span.map(|s| s.contains(offset)).unwrap_or(false) {
+            return None;
+        }
+
+        self.children.iter().filter_map(|c| c.innermost(offset)).next().or(Some(self))
+    }
+
+    /// The names of the nodes from this one to the innermost node
+    /// containing a byte, or an empty path if no node contains it.
+    pub fn path(&self, offset: usize) -> Vec<&'static str> {
+        if !self.span.map(|s| s.contains(offset)).unwrap_or(false) {
+            return Vec::new();
+        }
+
+        let mut path = vec![self.name];
+        if let Some(inner) = self.children.iter().map(|c| c.path(offset)).find(|p| !p.is_empty()) {
+            path.extend(inner);
+        }
+        path
+    }
+}
+
+/// Annotate a dissection of `packet` with the spans of its values.
+pub fn annotate<'val, 'data>(packet: &[u8], val: &'val Val<'data>) -> Node<'val, 'data> {
+    let name = val.as_object().map(|(name, _)| name).unwrap_or("");
+    node(packet, name, val, Some(Span { offset: 0, length: packet.len() }))
+}
+
+/// `span` is the extent of the bytes the value was dissected from, if known.
+fn node<'val, 'data>(packet: &[u8], name: &'static str, val: &'val Val<'data>, span: Option<Span>)
+    -> Node<'val, 'data> {
+
+    let mut children = Vec::new();
+    let span = match *val {
+        Val::Bytes(bytes) | Val::Address { bytes, .. } | Val::Undissected(_, bytes) =>
+            locate(packet, bytes),
+
+        Val::Payload(bytes, ref result) => {
+            let span = locate(packet, bytes);
+            if let Ok(ref inner) = *result {
+                let layer = inner.as_object().map(|(name, _)| name).unwrap_or(name);
+                children.push(node(packet, layer, inner, span));
+            }
+            span
+        },
+
+        Val::Object(..) | Val::List(..) => {
+            match *val {
+                Val::Object(layer, ref values) => children.extend(values.iter().map(|&(name, ref v)|
+                    node(packet, name, v, span.and_then(|s| field_span(layer, name, s))))),
+                Val::List(ref values) =>
+                    children.extend(values.iter().map(|v| node(packet, name, v, None))),
+                _ => {},
+            }
+            span.or_else(|| children.iter().filter_map(|c| c.span)
+                         .fold(None, |union, s| Some(union.map_or(s, |u: Span| u.union(&s)))))
+        },
+
+        _ => span,
+    };
+
+    Node { name: name, val: val, span: span, children: children }
+}
+
+/// Where a field of a layer lies, given the span of the whole layer.
+fn field_span(layer: &str, field: &str, span: Span) -> Option<Span> {
+    LAYOUTS.iter()
+        .find(|&&(name, _)| name == layer)
+        .and_then(|&(_, fields)| fields.iter().find(|&&(name, _, _)| name == field))
+        .filter(|&&(_, offset, length)| offset + length <= span.length)
+        .map(|&(_, offset, length)| Span { offset: span.offset + offset, length: length })
+}
+
+/// How much of a packet was interpreted by dissectors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coverage {
+    pub length: usize,
+
+    /// Bytes that no dissector interpreted, in order and without overlaps.
+    pub unparsed: Vec<Span>,
+}
+
+impl Coverage {
+    pub fn unparsed_bytes(&self) -> usize {
+        self.unparsed.iter().map(|s| s.length).sum()
+    }
+
+    pub fn parsed_bytes(&self) -> usize {
+        self.length - self.unparsed_bytes()
+    }
+}
+
+/// Find the bytes of a packet that no dissector interpreted.
+pub fn coverage(packet: &[u8], val: &Val) -> Coverage {
+    let mut spans = Vec::new();
+    unparsed(&annotate(packet, val), &mut spans);
+    spans.sort();
+
+    let mut unparsed: Vec<Span> = Vec::new();
+    for span in spans.into_iter().filter(|s| s.length > 0) {
+        match unparsed.last_mut() {
+            Some(last) if span.offset <= last.end() => *last = last.union(&span),
+            _ => unparsed.push(span),
+        }
+    }
+
+    Coverage { length: packet.len(), unparsed: unparsed }
+}
+
+fn unparsed(node: &Node, spans: &mut Vec<Span>) {
+    let whole = match *node.val {
+        Val::Undissected(..) | Val::Payload(_, Err(_)) => true,
+        Val::Bytes(_) => node.name == "raw data",
+        _ => false,
+    };
+
+    match (whole, node.span) {
+        (true, Some(span)) => spans.push(span),
+        _ => for child in &node.children {
+            unparsed(child, spans);
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet;
+
+    // 10.0.0.1:5000 -> 10.0.0.2:53 over Ethernet, with a 2 B UDP payload that
+    // isn't a DNS message
+    const PACKET: [u8; 44] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+        0x45, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        10, 0, 0, 1, 10, 0, 0, 2,
+        0x13, 0x88, 0x00, 0x35, 0x00, 0x0a, 0x00, 0x00, 0xde, 0xad];
+
+    #[test]
+    fn annotate_layers() {
+        let val = ethernet::dissect(&PACKET).unwrap();
+        let tree = annotate(&PACKET, &val);
+        assert_eq!(tree.span, Some(Span { offset: 0, length: 44 }));
+
+        let source = tree.innermost(6).unwrap();
+        assert_eq!(source.name, "Source");
+        assert_eq!(source.span, Some(Span { offset: 6, length: 6 }));
+
+        assert_eq!(&tree.path(27)[..3], &["Ethernet frame", "Payload", "IPv4"]);
+        let ip = tree.innermost(27).unwrap();
+        assert_eq!(ip.name, "Source");
+        assert_eq!(ip.span, Some(Span { offset: 26, length: 4 }));
+
+        let ttl = tree.innermost(22).unwrap();
+        assert_eq!((ttl.name, ttl.span), ("TTL", Some(Span { offset: 22, length: 1 })));
+        assert_eq!(tree.path(36), vec!["Ethernet frame", "Payload", "IPv4", "Payload", "UDP",
+                                       "Destination Port"]);
+
+        assert_eq!(tree.path(44), Vec::<&str>::new());
+        assert_eq!(locate(&PACKET, &[0, 1]), None);
+    }
+
+    #[test]
+    fn find_unparsed_bytes() {
+        let val = ethernet::dissect(&PACKET).unwrap();
+        let coverage = coverage(&PACKET, &val);
+
+        assert_eq!(coverage.length, 44);
+        assert_eq!(coverage.unparsed, vec![Span { offset: 42, length: 2 }]);
+        assert_eq!(coverage.parsed_bytes(), 42);
+    }
+}
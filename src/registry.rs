@@ -0,0 +1,468 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissectors by Ethertype, IP protocol number and port.
+//!
+//! Lower layers choose the dissector for their payload by looking up a
+//! binding such as "ethertype 0x800", "ip.proto 17" or "udp.port 53" in the
+//! global `Registry`, which starts out with rshark's own bindings. Other
+//! crates can add protocols (or replace rshark's dissectors) by registering
+//! bindings of their own; the most recently registered binding wins.
+//!
+//! A port may be bound to different dissectors for the payloads sent to it
+//! and from it, e.g., SOCKS requests to a proxy and its responses. Payloads
+//! that can only be recognized by their content (e.g., RTP, whose ports are
+//! negotiated) are still dissected by their carrier's heuristics, after any
+//! port bindings have been tried. TCP, UDP, DCCP and UDP-Lite are always
+//! dissected by rshark, since their checksums cover parts of the IP header.
+//! A `decode_as` rule overrides the registry's port bindings.
+//!
+//! ```
+//! use rshark::{DissectResult, Val};
+//!
+//! fn hello(data: &[u8]) -> DissectResult {
+//!     Ok(Box::new(Val::Object("Hello", vec![("Length", Val::Unsigned(data.len() as u64))])))
+//! }
+//!
+//! rshark::registry::register("udp.port 31337", "Hello", hello).unwrap();
+//!
+//! let packet = [
+//!     0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+//!     0x45, 0x00, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+//!     10, 0, 0, 1, 10, 0, 0, 2,
+//!     0xd4, 0x31, 0x7a, 0x69, 0x00, 0x0a, 0x00, 0x00,
+//!     b'h', b'i'];
+//! let val = rshark::ethernet::dissect(&packet).unwrap();
+//!
+//! assert_eq!(val.lookup("Payload.Payload.Payload.Length"), Some(&Val::Unsigned(2)));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{OnceLock, RwLock};
+
+use DissectError;
+use DissectResult;
+use Val;
+use context::Context;
+use decode_as;
+use {amqp, bittorrent, eapol, http2, mpls, mysql, nats, netbios, overlay, rpc, rtmp, rtsp,
+     s7comm, socks, ssdp};
+use ip::{self, gre, icmp, igmp, ipsec, sctp};
+use payload::{grpc, thrift};
+
+/// A dissector that can be bound in a `Registry`.
+pub type Handler = fn(&[u8]) -> DissectResult;
+
+/// A dissector that needs a `Context`, e.g., to dissect its own payload.
+pub type ContextHandler = for<'data> fn(&'data [u8], &mut Context) -> DissectResult<'data>;
+
+/// A dissector bound in a `Registry`.
+#[derive(Clone, Copy)]
+pub enum Bound {
+    Plain(Handler),
+    Context(ContextHandler),
+}
+
+impl Bound {
+    /// Dissect a payload, nested within a context.
+    pub fn dissect<'data>(&self, data: &'data [u8], context: &mut Context)
+        -> DissectResult<'data> {
+
+        match *self {
+            Bound::Plain(handler) => context.dissect_stateless(handler, data),
+            Bound::Context(handler) => context.dissect(handler, data),
+        }
+    }
+}
+
+/// A table of dissectors, keyed by one kind of protocol identifier.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Table {
+    /// Ethernet (and SNAP, SLL...) payloads, by Ethertype.
+    Ethertype,
+
+    /// IPv4 payloads, by protocol number.
+    IpProtocol,
+
+    /// IPv6 payloads, by next header (after any extension headers).
+    Ipv6NextHeader,
+
+    /// TCP payloads, by source or destination port.
+    TcpPort,
+
+    /// UDP payloads, by source or destination port.
+    UdpPort,
+}
+
+impl Table {
+    /// The table's name in bindings, e.g., "ip.proto".
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Table::Ethertype => "ethertype",
+            Table::IpProtocol => "ip.proto",
+            Table::Ipv6NextHeader => "ipv6.nxt",
+            Table::TcpPort => "tcp.port",
+            Table::UdpPort => "udp.port",
+        }
+    }
+}
+
+impl FromStr for Table {
+    type Err = DissectError;
+
+    fn from_str(s: &str) -> Result<Table, DissectError> {
+        [Table::Ethertype, Table::IpProtocol, Table::Ipv6NextHeader, Table::TcpPort,
+         Table::UdpPort].iter()
+            .find(|t| t.name() == s)
+            .cloned()
+            .ok_or_else(|| DissectError::InvalidData(format!["no dissector table '{}'", s]))
+    }
+}
+
+/// An entry in a dissector table, e.g., "udp.port 53".
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Binding {
+    pub table: Table,
+    pub value: u64,
+}
+
+impl FromStr for Binding {
+    type Err = DissectError;
+
+    /// Parse a table name and a decimal or (0x-prefixed) hexadecimal value.
+    fn from_str(s: &str) -> Result<Binding, DissectError> {
+        let mut words = s.split_whitespace();
+        let (table, value) = match (words.next(), words.next(), words.next()) {
+            (Some(table), Some(value), None) => (table, value),
+            _ => return Err(DissectError::InvalidData(
+                format!["binding '{}' isn't of the form 'table value'", s])),
+        };
+
+        let value = if value.starts_with("0x") {
+            u64::from_str_radix(&value[2..], 16)
+        } else {
+            value.parse()
+        };
+
+        Ok(Binding {
+            table: table.parse()?,
+            value: value.map_err(|e| DissectError::InvalidData(
+                format!["invalid value in binding '{}': {}", s, e]))?,
+        })
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.table {
+            Table::Ethertype => write![f, "{} 0x{:04x}", self.table.name(), self.value],
+            _ => write![f, "{} {}", self.table.name(), self.value],
+        }
+    }
+}
+
+/// Dissectors by binding.
+#[derive(Clone, Default)]
+pub struct Registry {
+    /// The name of each dissector bound to a value, with the dissectors
+    /// for payloads sent to the value (a port) and from it.
+    bindings: HashMap<Binding, Vec<(&'static str, Bound, Bound)>>,
+}
+
+impl Registry {
+    /// A registry without any bindings.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// A registry with rshark's own bindings.
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::new();
+
+        for &(ethertype, name, handler) in &[
+            (0x0806, "ARP", arp as Handler),
+            (0x8138, "IPX", ipx),
+            (0x888e, "EAPOL", eapol::dissect),
+        ] {
+            registry.bind(Table::Ethertype, ethertype, name, handler);
+        }
+
+        // Dissectors of layers that encapsulate others pass the context on.
+        for &(ethertype, name, handler) in &[
+            (0x0800, "IPv4", ip::dissect_with as ContextHandler),
+            (0x86dd, "IPv6", ip::ipv6::dissect_with),
+            (0x8847, "MPLS", mpls::dissect_with),
+            (0x8848, "MPLS", mpls::dissect_with),
+        ] {
+            registry.bind_context(Table::Ethertype, ethertype, name, handler);
+        }
+
+        for &(protocol, name, handler) in &[
+            (2, "IGMP", igmp::dissect as Handler),
+            (50, "ESP", ipsec::dissect_esp),
+            (132, "SCTP", sctp::dissect),
+        ] {
+            registry.bind(Table::IpProtocol, protocol, name, handler);
+        }
+
+        for &(protocol, name, handler) in &[
+            (1, "ICMP", icmp::dissect_with as ContextHandler),
+            (47, "GRE", gre::dissect_with),
+            (51, "AH", ipsec::dissect_ah_with),
+        ] {
+            registry.bind_context(Table::IpProtocol, protocol, name, handler);
+        }
+
+        for &(next_header, name, handler) in &[
+            (50, "ESP", ipsec::dissect_esp as Handler),
+            (58, "ICMPv6", icmpv6),
+            (132, "SCTP", sctp::dissect),
+        ] {
+            registry.bind(Table::Ipv6NextHeader, next_header, name, handler);
+        }
+
+        for &(next_header, name, handler) in &[
+            (47, "GRE", gre::dissect_with as ContextHandler),
+            (51, "AH", ipsec::dissect_ah_with),
+        ] {
+            registry.bind_context(Table::Ipv6NextHeader, next_header, name, handler);
+        }
+
+        for &(port, name, handler) in &[
+            (102, "TPKT", s7comm::dissect_tpkt as Handler),
+            (139, "NetBIOS Session", netbios::dissect_session),
+            (445, "NetBIOS Session", netbios::dissect_session),
+            (rpc::PORTMAPPER_PORT, "ONC-RPC", rpc::dissect_record),
+            (rpc::NFS_PORT, "ONC-RPC", rpc::dissect_record),
+            (rtsp::PORT, "RTSP", rtsp::dissect),
+            (rtmp::PORT, "RTMP", rtmp::dissect),
+            (nats::PORT, "NATS", nats::dissect),
+            (5672, "AMQP", amqp::dissect),
+            (thrift::PORT, "Thrift", thrift::dissect),
+            (grpc::PORT, "HTTP/2", http2::dissect),
+        ] {
+            registry.bind(Table::TcpPort, port as u64, name, handler);
+        }
+
+        for port in bittorrent::PORT..=bittorrent::LAST_PORT {
+            registry.bind(Table::TcpPort, port as u64, "BitTorrent", bittorrent::dissect);
+        }
+
+        registry.bind_directed(Table::TcpPort, socks::PORT as u64, "SOCKS",
+                               socks::dissect_request, socks::dissect_response);
+        registry.bind_directed(Table::TcpPort, 3306, "MySQL",
+                               mysql::dissect_request, mysql::dissect_response);
+
+        for &(port, name, handler) in &[
+            (137, "NetBIOS Name Service", netbios::dissect_name_service as Handler),
+            (138, "NetBIOS Datagram", netbios::dissect_datagram),
+            (rpc::PORTMAPPER_PORT, "ONC-RPC", rpc::dissect),
+            (rpc::NFS_PORT, "ONC-RPC", rpc::dissect),
+            (1900, "SSDP", ssdp::dissect),
+            (ipsec::NAT_T_PORT, "NAT-T", ipsec::dissect_nat_t),
+        ] {
+            registry.bind(Table::UdpPort, port as u64, name, handler);
+        }
+
+        registry.bind_context(Table::UdpPort, overlay::VXLAN_PORT as u64, "VXLAN",
+                              overlay::dissect_vxlan_with);
+        registry.bind_context(Table::UdpPort, overlay::GENEVE_PORT as u64, "GENEVE",
+                              overlay::dissect_geneve_with);
+
+        registry.bind_directed(Table::UdpPort, bittorrent::TRACKER_PORT as u64,
+                               "BitTorrent Tracker", bittorrent::dissect_tracker_request,
+                               bittorrent::dissect_tracker_response);
+
+        registry
+    }
+
+    /// Bind a dissector, in preference to any already bound to the same value.
+    pub fn bind(&mut self, table: Table, value: u64, name: &'static str, handler: Handler) {
+        self.bind_directed(table, value, name, handler, handler)
+    }
+
+    /// Bind a dissector that needs a `Context`.
+    pub fn bind_context(&mut self, table: Table, value: u64, name: &'static str,
+                        handler: ContextHandler) {
+        self.push(table, value, name, Bound::Context(handler), Bound::Context(handler))
+    }
+
+    /// Bind a port to one dissector for the payloads sent to it (e.g., a
+    /// server's requests) and another for those sent from it.
+    pub fn bind_directed(&mut self, table: Table, value: u64, name: &'static str,
+                         to: Handler, from: Handler) {
+        self.push(table, value, name, Bound::Plain(to), Bound::Plain(from))
+    }
+
+    fn push(&mut self, table: Table, value: u64, name: &'static str, to: Bound, from: Bound) {
+        self.bindings.entry(Binding { table: table, value: value })
+            .or_insert_with(Vec::new)
+            .push((name, to, from));
+    }
+
+    /// Bind a dissector by a binding such as "udp.port 53".
+    pub fn register(&mut self, binding: &str, name: &'static str, handler: Handler)
+        -> Result<(), DissectError> {
+
+        let binding = binding.parse::<Binding>()?;
+        self.bind(binding.table, binding.value, name, handler);
+        Ok(())
+    }
+
+    /// The name of the dissector bound to a value and the dissector itself
+    /// (for payloads sent to the value, if it is a port).
+    pub fn lookup(&self, table: Table, value: u64) -> Option<(&'static str, Bound)> {
+        self.lookup_directed(table, value, true)
+    }
+
+    /// The dissector bound to either of a pair of ports, trying the lower
+    /// (more likely to be well-known) port first.
+    pub fn lookup_ports(&self, table: Table, source: u16, destination: u16)
+        -> Option<(&'static str, Bound)> {
+
+        let (low, high) = if source <= destination { (source, destination) }
+                          else { (destination, source) };
+        self.lookup_directed(table, low as u64, low == destination)
+            .or_else(|| self.lookup_directed(table, high as u64, high == destination))
+    }
+
+    /// All bindings and the names of the dissectors they (currently) select,
+    /// in order.
+    pub fn bindings(&self) -> Vec<(Binding, &'static str)> {
+        let mut bindings = self.bindings.iter()
+            .filter_map(|(b, handlers)| handlers.last().map(|&(name, _, _)| (*b, name)))
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|&(b, _)| (b.table.name(), b.value));
+        bindings
+    }
+
+    fn lookup_directed(&self, table: Table, value: u64, to: bool)
+        -> Option<(&'static str, Bound)> {
+
+        self.bindings.get(&Binding { table: table, value: value })
+            .and_then(|handlers| handlers.last())
+            .map(|&(name, t, f)| (name, if to { t } else { f }))
+    }
+}
+
+/// The value of a payload dissected by a registered dissector.
+///
+/// Protocols that are recognized but not dissected (e.g., ARP) are bound to
+/// dissectors returning `Val::Undissected`, which stands for the payload
+/// itself rather than being wrapped in a `Val::Payload`.
+pub fn payload<'data>(data: &'data [u8], result: DissectResult<'data>) -> Val<'data> {
+    match result {
+        Ok(val) => match *val {
+            undissected @ Val::Undissected(..) => undissected,
+            val => Val::Payload(data, Ok(Box::new(val))),
+        },
+        Err(e) => Val::Payload(data, Err(e)),
+    }
+}
+
+fn arp(data: &[u8]) -> DissectResult {
+    Ok(Box::new(Val::Undissected("ARP", data)))
+}
+
+fn ipx(data: &[u8]) -> DissectResult {
+    Ok(Box::new(Val::Undissected("IPX", data)))
+}
+
+fn icmpv6(data: &[u8]) -> DissectResult {
+    Ok(Box::new(Val::Undissected("ICMPv6", data)))
+}
+
+/// The registry used by rshark's own dissectors.
+pub fn global() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(Registry::builtin()))
+}
+
+/// Bind a dissector in the global registry (see `Registry::register`).
+pub fn register(binding: &str, name: &'static str, handler: Handler) -> Result<(), DissectError> {
+    global().write().unwrap().register(binding, name, handler)
+}
+
+/// The dissector bound to a value in the global registry.
+pub fn lookup(table: Table, value: u64) -> Option<Bound> {
+    global().read().unwrap().lookup(table, value).map(|(_, handler)| handler)
+}
+
+/// The dissector bound to either of a pair of ports in the global registry,
+/// unless a `decode_as` rule names another.
+pub fn lookup_ports(table: Table, source: u16, destination: u16) -> Option<Bound> {
+    decode_as::lookup_ports(table, source, destination).map(Bound::Plain).or_else(|| {
+        global().read().unwrap().lookup_ports(table, source, destination)
+            .map(|(_, handler)| handler)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Val;
+
+    fn one(_: &[u8]) -> DissectResult {
+        Ok(Box::new(Val::Unsigned(1)))
+    }
+
+    fn two(_: &[u8]) -> DissectResult {
+        Ok(Box::new(Val::Unsigned(2)))
+    }
+
+    #[test]
+    fn parse_bindings() {
+        let binding = "ethertype 0x800".parse::<Binding>().unwrap();
+        assert_eq!(binding, Binding { table: Table::Ethertype, value: 0x800 });
+        assert_eq!(binding.to_string(), "ethertype 0x0800");
+        assert_eq!("udp.port 53".parse::<Binding>().unwrap().to_string(), "udp.port 53");
+
+        assert!("udp.port".parse::<Binding>().is_err());
+        assert!("sctp.port 1".parse::<Binding>().is_err());
+        assert!("tcp.port http".parse::<Binding>().is_err());
+    }
+
+    #[test]
+    fn later_bindings_win() {
+        let mut registry = Registry::new();
+        registry.register("tcp.port 8080", "One", one).unwrap();
+        registry.register("tcp.port 8080", "Two", two).unwrap();
+        registry.register("tcp.port 80", "One", one).unwrap();
+
+        assert_eq!(registry.lookup(Table::TcpPort, 8080).map(|(n, _)| n), Some("Two"));
+        assert_eq!(registry.lookup(Table::UdpPort, 8080).map(|(n, _)| n), None);
+        assert_eq!(registry.lookup_ports(Table::TcpPort, 8080, 80).map(|(n, _)| n), Some("One"));
+        assert_eq!(registry.bindings().iter().map(|&(b, n)| format!["{}: {}", b, n])
+                       .collect::<Vec<_>>(),
+                   vec!["tcp.port 80: One", "tcp.port 8080: Two"]);
+
+        assert_eq!(Registry::builtin().lookup(Table::IpProtocol, 47).map(|(n, _)| n),
+                   Some("GRE"));
+        assert_eq!(Registry::builtin().lookup(Table::Ipv6NextHeader, 1).map(|(n, _)| n), None);
+    }
+
+    #[test]
+    fn directed_bindings() {
+        let mut registry = Registry::new();
+        registry.bind_directed(Table::TcpPort, 1080, "Proxy", one, two);
+
+        let dissect = |source, destination| registry
+            .lookup_ports(Table::TcpPort, source, destination)
+            .map(|(_, bound)| bound.dissect(&[], &mut Context::new()).unwrap());
+        assert_eq!(dissect(40000, 1080), Some(Box::new(Val::Unsigned(1))));
+        assert_eq!(dissect(1080, 40000), Some(Box::new(Val::Unsigned(2))));
+        assert_eq!(dissect(40000, 40001), None);
+
+        let arp = Registry::builtin().lookup(Table::Ethertype, 0x806).unwrap().1;
+        assert_eq!(payload(&[1, 2], arp.dissect(&[1, 2], &mut Context::new())),
+                   Val::Undissected("ARP", &[1, 2]));
+    }
+}
@@ -0,0 +1,169 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Deterministic replay of captures using packet timestamps as the clock.
+//!
+//! Time-based analyses (flow timeouts, rate statistics, beaconing detection)
+//! should read the time from a `Clock` rather than the system.
+//! During live capture that is a `SystemClock`; when replaying a capture file
+//! it is the `VirtualClock` of a `Replay`, which advances with each packet's
+//! timestamp. The analyses then behave exactly as they would have live,
+//! no matter how quickly the file is read.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time, as a duration since the Unix epoch.
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// The wall clock, for live operation.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0))
+    }
+}
+
+/// A clock that only moves when it is advanced.
+///
+/// Clones share the same time, so analyses can hold a clone of a replay's clock.
+#[derive(Clone, Debug)]
+pub struct VirtualClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new(start: Duration) -> VirtualClock {
+        VirtualClock { now: Rc::new(Cell::new(start)) }
+    }
+
+    /// Move the clock forward to `t`. Clocks never run backwards, so earlier
+    /// times (e.g., from out-of-order packets) are ignored.
+    pub fn advance_to(&self, t: Duration) {
+        if t > self.now.get() {
+            self.now.set(t);
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// Replays timestamped packets, advancing a `VirtualClock` as it goes.
+///
+/// By default packets are replayed as quickly as they can be consumed;
+/// `speed` paces them relative to their original timing instead.
+pub struct Replay<I> {
+    packets: I,
+    clock: VirtualClock,
+    speed: Option<f64>,
+    started: Option<(Duration, Instant)>,
+}
+
+impl<I, T> Replay<I> where I: Iterator<Item=(Duration, T)> {
+    pub fn new(packets: I) -> Replay<I> {
+        Replay {
+            packets: packets,
+            clock: VirtualClock::new(Duration::new(0, 0)),
+            speed: None,
+            started: None,
+        }
+    }
+
+    /// Pace the replay at `speed` times the original rate (e.g., 1.0 for real
+    /// time, 10.0 for ten times faster).
+    pub fn speed(mut self, speed: f64) -> Replay<I> {
+        assert!(speed > 0.0, "replay speed must be positive");
+        self.speed = Some(speed);
+        self
+    }
+
+    /// The replay's virtual clock, which reads the timestamp of the most
+    /// recently replayed packet.
+    pub fn clock(&self) -> VirtualClock {
+        self.clock.clone()
+    }
+}
+
+impl<I, T> Iterator for Replay<I> where I: Iterator<Item=(Duration, T)> {
+    type Item = (Duration, T);
+
+    fn next(&mut self) -> Option<(Duration, T)> {
+        let (timestamp, packet) = match self.packets.next() {
+            Some(p) => p,
+            None => return None,
+        };
+
+        let (first, started) = *self.started.get_or_insert((timestamp, Instant::now()));
+
+        if let Some(speed) = self.speed {
+            if timestamp > first {
+                let offset = timestamp - first;
+                let nanos = offset.as_secs() as f64 * 1e9 + offset.subsec_nanos() as f64;
+                let nanos = (nanos / speed) as u64;
+                let target = Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32);
+
+                let elapsed = started.elapsed();
+                if target > elapsed {
+                    thread::sleep(target - elapsed);
+                }
+            }
+        }
+
+        self.clock.advance_to(timestamp);
+        Some((timestamp, packet))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn packets() -> Vec<(Duration, u32)> {
+        vec![(Duration::new(100, 0), 1),
+             (Duration::new(100, 20_000_000), 2),
+             (Duration::new(99, 0), 3),
+             (Duration::new(160, 0), 4)]
+    }
+
+    #[test]
+    fn virtual_time() {
+        let mut replay = Replay::new(packets().into_iter());
+        let clock = replay.clock();
+
+        assert_eq!(replay.next(), Some((Duration::new(100, 0), 1)));
+        assert_eq!(clock.now(), Duration::new(100, 0));
+
+        replay.next();
+        replay.next();
+        assert_eq!(clock.now(), Duration::new(100, 20_000_000));
+
+        replay.next();
+        assert_eq!(clock.now(), Duration::new(160, 0));
+        assert_eq!(replay.next(), None);
+    }
+
+    #[test]
+    fn paced_replay() {
+        let start = Instant::now();
+        let replayed = Replay::new(packets().into_iter().take(2)).speed(2.0).count();
+
+        assert_eq!(replayed, 2);
+        assert!(start.elapsed() >= Duration::new(0, 10_000_000));
+    }
+}
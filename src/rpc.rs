@@ -0,0 +1,292 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of ONC-RPC (Sun RPC) messages, as used by NFS, the portmapper
+//! and mountd.
+//!
+//! Over UDP, each datagram is one message. Over TCP, messages are framed by
+//! record marking: each fragment of a record has a four-byte header giving
+//! its length, with the top bit set on the last fragment. Calls name their
+//! program, version and procedure, but replies only echo the call's XID, so
+//! the arguments of calls are dissected (see `nfs`) but the results of
+//! replies are left undissected.
+//!
+//! See [RFC 5531](https://tools.ietf.org/html/rfc5531) and, for XDR,
+//! [RFC 4506](https://tools.ietf.org/html/rfc4506).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use nfs;
+
+enum_map!(pub PROGRAMS {
+    100000 => "Portmapper",
+    100003 => "NFS",
+    100005 => "Mount",
+    100021 => "Network Lock Manager",
+    100024 => "Status Monitor",
+    100227 => "NFS ACL",
+});
+
+enum_map!(pub AUTH_FLAVORS {
+    0 => "AUTH_NONE",
+    1 => "AUTH_SYS",
+    2 => "AUTH_SHORT",
+    3 => "AUTH_DH",
+    6 => "RPCSEC_GSS",
+});
+
+enum_map!(pub ACCEPT_STATUSES {
+    0 => "Success",
+    1 => "Program Unavailable",
+    2 => "Program Mismatch",
+    3 => "Procedure Unavailable",
+    4 => "Garbage Arguments",
+    5 => "System Error",
+});
+
+/// The well-known ports of the portmapper and NFS.
+pub const PORTMAPPER_PORT: u16 = 111;
+pub const NFS_PORT: u16 = 2049;
+
+const NFS: u32 = 100003;
+const AUTH_SYS: u32 = 1;
+
+/// A reader of XDR-encoded data: big-endian 4-byte units, with variable-length
+/// data padded to a multiple of 4 B.
+pub struct Xdr<'data> {
+    data: &'data [u8],
+    offset: usize,
+}
+
+impl<'data> Xdr<'data> {
+    pub fn new(data: &'data [u8]) -> Xdr<'data> {
+        Xdr { data: data, offset: 0 }
+    }
+
+    /// The data not yet read.
+    pub fn remaining(&self) -> &'data [u8] {
+        &self.data[self.offset.min(self.data.len())..]
+    }
+
+    fn fixed(&mut self, length: usize) -> Result<&'data [u8], DissectError> {
+        let padded = length.checked_add(3).map(|l| l & !3).unwrap_or(usize::max_value());
+        if padded > self.data.len() - self.offset {
+            return Err(DissectError::Underflow { expected: self.offset.checked_add(padded),
+                have: self.data.len(), message: "XDR data is truncated".to_string() });
+        }
+
+        self.offset += padded;
+        Ok(&self.data[self.offset - padded..self.offset - padded + length])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DissectError> {
+        self.fixed(4).map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DissectError> {
+        Ok((self.u32()? as u64) << 32 | self.u32()? as u64)
+    }
+
+    /// Fixed-length opaque data, e.g., an NFSv4 state ID.
+    pub fn opaque_fixed(&mut self, length: usize) -> Result<&'data [u8], DissectError> {
+        self.fixed(length)
+    }
+
+    /// Variable-length opaque data, which may be at most `limit` bytes long.
+    pub fn opaque(&mut self, limit: usize) -> Result<&'data [u8], DissectError> {
+        let length = self.u32()? as usize;
+        if length > limit {
+            return Err(DissectError::InvalidData(
+                format!["XDR opaque length {} exceeds its limit of {}", length, limit]));
+        }
+
+        self.fixed(length)
+    }
+
+    pub fn string(&mut self, limit: usize) -> Result<String, DissectError> {
+        self.opaque(limit).map(|s| String::from_utf8_lossy(s).into_owned())
+    }
+}
+
+/// Dissect an RPC message carried over TCP, with its record marking.
+pub fn dissect_record(data : &[u8]) -> DissectResult {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "An RPC record mark must be 4 B".to_string() });
+    }
+
+    let mark = (data[0] as usize) << 24 | (data[1] as usize) << 16 | (data[2] as usize) << 8
+        | data[3] as usize;
+    let last = mark & 0x8000_0000 != 0;
+    let length = mark & 0x7fff_ffff;
+
+    let mut values = NamedValues::new();
+    values.push(("Last Fragment", Val::Unsigned(last as u64)));
+    values.push(("Fragment Length", Val::Unsigned(length as u64)));
+
+    // A message split across fragments needs reassembly (see pdu::rpc).
+    let fragment = &data[4..(4 + length).min(data.len())];
+    if last {
+        values.push(("Payload", Val::Payload(fragment, dissect(fragment))));
+    } else {
+        values.push(("Payload", Val::Undissected("RPC fragment", fragment)));
+    }
+
+    Ok(Box::new(Val::Object("RPC Record", values)))
+}
+
+/// Dissect an RPC message (without record marking).
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut xdr = Xdr::new(data);
+    let mut values = NamedValues::new();
+
+    values.push(("XID", Val::Unsigned(xdr.u32()? as u64)));
+
+    match xdr.u32()? {
+        0 => {
+            values.push(("Message Type", Val::Symbol("Call")));
+            call(&mut xdr, &mut values)?;
+        },
+        1 => {
+            values.push(("Message Type", Val::Symbol("Reply")));
+            reply(&mut xdr, &mut values)?;
+        },
+        other => return Err(DissectError::InvalidData(
+            format!["RPC message type {} is invalid", other])),
+    }
+
+    Ok(Box::new(Val::Object("ONC-RPC", values)))
+}
+
+fn call<'data>(xdr: &mut Xdr<'data>, values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    let version = xdr.u32()?;
+    if version != 2 {
+        return Err(DissectError::InvalidData(format!["RPC version {} is not 2", version]));
+    }
+
+    let program = xdr.u32()?;
+    let program_version = xdr.u32()?;
+    let procedure = xdr.u32()?;
+    values.push(("Program", Val::Unsigned(program as u64)));
+    values.push(("Program Name", PROGRAMS.val(program)));
+    values.push(("Program Version", Val::Unsigned(program_version as u64)));
+    values.push(("Procedure", Val::Unsigned(procedure as u64)));
+
+    values.push(("Credentials", credentials(xdr)?));
+    values.push(("Verifier", authenticator(xdr)?));
+
+    let arguments = xdr.remaining();
+    values.push(("Arguments", match program {
+        NFS => Val::Payload(arguments, nfs::dissect_call(program_version, procedure, arguments)),
+        _ => Val::Undissected("RPC arguments", arguments),
+    }));
+
+    Ok(())
+}
+
+fn reply<'data>(xdr: &mut Xdr<'data>, values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    match xdr.u32()? {
+        0 => {
+            values.push(("Reply Status", Val::Symbol("Accepted")));
+            values.push(("Verifier", authenticator(xdr)?));
+
+            let status = xdr.u32()?;
+            values.push(("Accept Status", ACCEPT_STATUSES.val(status)));
+            match status {
+                0 => values.push(("Results", Val::Undissected("RPC results", xdr.remaining()))),
+                2 => {
+                    values.push(("Lowest Version", Val::Unsigned(xdr.u32()? as u64)));
+                    values.push(("Highest Version", Val::Unsigned(xdr.u32()? as u64)));
+                },
+                _ => {},
+            }
+        },
+        1 => {
+            values.push(("Reply Status", Val::Symbol("Denied")));
+            match xdr.u32()? {
+                0 => {
+                    values.push(("Reject Status", Val::Symbol("RPC Version Mismatch")));
+                    values.push(("Lowest Version", Val::Unsigned(xdr.u32()? as u64)));
+                    values.push(("Highest Version", Val::Unsigned(xdr.u32()? as u64)));
+                },
+                _ => {
+                    values.push(("Reject Status", Val::Symbol("Authentication Error")));
+                    values.push(("Authentication Status", Val::Unsigned(xdr.u32()? as u64)));
+                },
+            }
+        },
+        other => return Err(DissectError::InvalidData(
+            format!["RPC reply status {} is invalid", other])),
+    }
+
+    Ok(())
+}
+
+/// Credentials, which are decoded for AUTH_SYS (the UID and GIDs used for
+/// access control on the server).
+fn credentials<'data>(xdr: &mut Xdr<'data>) -> Result<Val<'data>, DissectError> {
+    let flavor = xdr.u32()?;
+    let body = xdr.opaque(400)?;
+
+    let mut values = NamedValues::new();
+    values.push(("Flavor", AUTH_FLAVORS.val(flavor)));
+
+    if flavor == AUTH_SYS {
+        let mut sys = Xdr::new(body);
+        values.push(("Stamp", Val::Unsigned(sys.u32()? as u64)));
+        values.push(("Machine Name", Val::String(sys.string(255)?)));
+        values.push(("UID", Val::Unsigned(sys.u32()? as u64)));
+        values.push(("GID", Val::Unsigned(sys.u32()? as u64)));
+
+        let groups = sys.u32()?;
+        if groups > 16 {
+            return Err(DissectError::InvalidData(
+                format!["AUTH_SYS credentials have {} auxiliary GIDs (at most 16)", groups]));
+        }
+        for _ in 0..groups {
+            values.push(("Auxiliary GID", Val::Unsigned(sys.u32()? as u64)));
+        }
+    } else if !body.is_empty() {
+        values.push(("Body", Val::Bytes(body)));
+    }
+
+    Ok(Val::Object("RPC Credentials", values))
+}
+
+fn authenticator<'data>(xdr: &mut Xdr<'data>) -> Result<Val<'data>, DissectError> {
+    let flavor = xdr.u32()?;
+    let body = xdr.opaque(400)?;
+
+    let mut values = NamedValues::new();
+    values.push(("Flavor", AUTH_FLAVORS.val(flavor)));
+    if !body.is_empty() {
+        values.push(("Body", Val::Bytes(body)));
+    }
+
+    Ok(Val::Object("RPC Authenticator", values))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_denied_reply() {
+        let data = [0, 0, 0, 9, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 2];
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Reply Status"].as_symbol().unwrap(), "Denied");
+        assert_eq!(val["Reject Status"].as_symbol().unwrap(), "RPC Version Mismatch");
+        assert_eq!(val["Highest Version"].as_unsigned().unwrap(), 2);
+
+        let mut xdr = Xdr::new(&[0, 0, 0, 5, b'h', b'e', b'l', b'l', b'o', 0, 0]);
+        assert!(xdr.string(255).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the Real-Time Messaging Protocol (RTMP), on TCP port 1935.
+//!
+//! After a fixed-size handshake, messages are split into chunks that are
+//! interleaved on chunk streams. Chunk headers are compressed: all but the
+//! first chunk of a stream may omit the fields that are unchanged from the
+//! previous chunk of that stream. Chunks are decoded from the start of a
+//! segment, which is enough when each segment begins with a fresh (format 0)
+//! chunk, as connection setup and most commands do; chunks that depend on a
+//! header from an earlier segment are left undissected. Protocol control
+//! messages and the names of AMF0 commands (`connect`, `play`, `publish`...)
+//! are decoded.
+//!
+//! See the [RTMP specification](https://rtmp.veriskope.com/docs/spec/).
+
+use std::collections::HashMap;
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use {read_be_u16, read_be_u32, read_le_u32};
+
+enum_map!(pub MESSAGE_TYPES {
+    1 => "Set Chunk Size",
+    2 => "Abort Message",
+    3 => "Acknowledgement",
+    4 => "User Control Message",
+    5 => "Window Acknowledgement Size",
+    6 => "Set Peer Bandwidth",
+    8 => "Audio",
+    9 => "Video",
+    15 => "AMF3 Data",
+    16 => "AMF3 Shared Object",
+    17 => "AMF3 Command",
+    18 => "AMF0 Data",
+    19 => "AMF0 Shared Object",
+    20 => "AMF0 Command",
+    22 => "Aggregate",
+});
+
+pub const PORT: u16 = 1935;
+
+/// The chunk size that each side uses until it sends Set Chunk Size.
+const DEFAULT_CHUNK_SIZE: usize = 128;
+
+/// The size of C1, S1, C2 and S2.
+const HANDSHAKE: usize = 1536;
+
+/// The header fields of a chunk stream's latest chunk, which later chunks of
+/// the stream may omit.
+#[derive(Clone, Copy, Default)]
+struct Header {
+    timestamp: u32,
+    extended: bool,
+    length: usize,
+    message_type: u8,
+    stream: u32,
+
+    /// Bytes of the current message still to come.
+    remaining: usize,
+}
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    // C0+C1 or S0+S1+S2: a version byte, then one or two 1536 B blocks
+    if data.first() == Some(&3) && (data.len() == 1 + HANDSHAKE || data.len() == 1 + 3 * HANDSHAKE) {
+        return dissect_handshake(data);
+    }
+
+    let mut values = NamedValues::new();
+    let mut streams = HashMap::new();
+    let mut chunk_size = DEFAULT_CHUNK_SIZE;
+    let mut offset = 0;
+
+    while offset < data.len() {
+        match chunk(&data[offset..], &mut streams, &mut chunk_size)? {
+            Some((length, val)) => {
+                values.push(("Chunk", val));
+                offset += length;
+            },
+            None => {
+                values.push(("Remainder", Val::Undissected("RTMP chunks", &data[offset..])));
+                break;
+            },
+        }
+    }
+
+    Ok(Box::new(Val::Object("RTMP", values)))
+}
+
+fn dissect_handshake(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+    values.push(("Message Type", Val::Symbol("Handshake")));
+    values.push(("Version", Val::Unsigned(data[0] as u64)));
+    values.push(("Time", Val::Unsigned(read_be_u32(data, 1)? as u64)));
+    values.push(("Zero", Val::Bytes(&data[5..9])));
+    values.push(("Random", Val::Bytes(&data[9..1 + HANDSHAKE])));
+    if data.len() > 1 + HANDSHAKE {
+        values.push(("Echo", Val::Bytes(&data[1 + HANDSHAKE..])));
+    }
+
+    Ok(Box::new(Val::Object("RTMP", values)))
+}
+
+/// Dissect one chunk, returning its length, or None if it continues a
+/// chunk stream whose header we haven't seen.
+fn chunk<'data>(data: &'data [u8], streams: &mut HashMap<u32, Header>, chunk_size: &mut usize)
+    -> Result<Option<(usize, Val<'data>)>, DissectError> {
+
+    let format = data[0] >> 6;
+    let (stream_id, mut offset) = match data[0] & 0x3f {
+        0 => (64 + field(data, 1, 1)?[0] as u32, 2),
+        1 => (64 + field(data, 1, 2).map(|b| b[0] as u32 | (b[1] as u32) << 8)?, 3),
+        id => (id as u32, 1),
+    };
+
+    let mut header = match (format, streams.get(&stream_id)) {
+        (0, _) => Header::default(),
+        (_, Some(h)) => *h,
+        (_, None) => return Ok(None),
+    };
+
+    let mut values = NamedValues::new();
+    values.push(("Format", Val::Unsigned(format as u64)));
+    values.push(("Chunk Stream ID", Val::Unsigned(stream_id as u64)));
+
+    if format < 3 {
+        let timestamp = number(data, offset, 3)?;
+        header.extended = timestamp == 0xff_ffff;
+        header.timestamp = timestamp;
+        offset += 3;
+    }
+    if format < 2 {
+        header.length = number(data, offset, 3)? as usize;
+        header.message_type = field(data, offset + 3, 1)?[0];
+        header.remaining = 0;
+        offset += 4;
+    }
+    if format == 0 {
+        header.stream = read_le_u32(field(data, offset, 4)?, 0)?;
+        offset += 4;
+    }
+    if header.extended {
+        header.timestamp = read_be_u32(field(data, offset, 4)?, 0)?;
+        offset += 4;
+    }
+
+    values.push((if format == 0 { "Timestamp" } else { "Timestamp Delta" },
+                 Val::Unsigned(header.timestamp as u64)));
+    values.push(("Message Length", Val::Unsigned(header.length as u64)));
+    values.push(("Message Type", Val::Unsigned(header.message_type as u64)));
+    values.push(("Message Type Name", MESSAGE_TYPES.val(header.message_type)));
+    values.push(("Message Stream ID", Val::Unsigned(header.stream as u64)));
+
+    // A chunk that doesn't continue a message starts a new one.
+    let first = header.remaining == 0;
+    if first {
+        header.remaining = header.length;
+    }
+
+    let length = header.remaining.min(*chunk_size);
+    let body = &data[offset.min(data.len())..(offset + length).min(data.len())];
+    header.remaining -= body.len();
+    streams.insert(stream_id, header);
+
+    if first && body.len() == header.length {
+        message(header.message_type, body, &mut values, chunk_size);
+    }
+    values.push(("Data", Val::Bytes(body)));
+
+    Ok(Some((offset + body.len(), Val::Object("RTMP Chunk", values))))
+}
+
+/// Decode a complete message carried by one chunk.
+fn message(message_type: u8, data: &[u8], values: &mut NamedValues, chunk_size: &mut usize) {
+    match message_type {
+        1 => if let Ok(size) = read_be_u32(data, 0) {
+            // The top bit is reserved.
+            let size = size & 0x7fff_ffff;
+            values.push(("Chunk Size", Val::Unsigned(size as u64)));
+            if size > 0 {
+                *chunk_size = size as usize;
+            }
+        },
+        2 => if let Ok(id) = read_be_u32(data, 0) {
+            values.push(("Aborted Chunk Stream ID", Val::Unsigned(id as u64)));
+        },
+        3 => if let Ok(sequence) = read_be_u32(data, 0) {
+            values.push(("Sequence Number", Val::Unsigned(sequence as u64)));
+        },
+        4 => if let Ok(event) = read_be_u16(data, 0) {
+            values.push(("Event Type", Val::Unsigned(event as u64)));
+        },
+        5 => if let Ok(size) = read_be_u32(data, 0) {
+            values.push(("Window Size", Val::Unsigned(size as u64)));
+        },
+        6 => if let (Ok(size), Some(&limit)) = (read_be_u32(data, 0), data.get(4)) {
+            values.push(("Window Size", Val::Unsigned(size as u64)));
+            values.push(("Limit Type", Val::Symbol(match limit {
+                0 => "Hard",
+                1 => "Soft",
+                2 => "Dynamic",
+                _ => "Unknown",
+            })));
+        },
+        // An AMF0 command starts with its name, as an AMF0 string (marker 2).
+        20 if data.first() == Some(&2) => if let Ok(length) = read_be_u16(data, 1) {
+            if let Some(name) = data.get(3..3 + length as usize) {
+                values.push(("Command", Val::String(String::from_utf8_lossy(name).into_owned())));
+            }
+        },
+        _ => {},
+    }
+}
+
+fn field(data: &[u8], offset: usize, length: usize) -> Result<&[u8], DissectError> {
+    data.get(offset..offset + length).ok_or_else(|| DissectError::Underflow {
+        expected: Some(offset + length), have: data.len(),
+        message: "RTMP chunk header is truncated".to_string() })
+}
+
+/// A big-endian number of `length` bytes.
+fn number(data: &[u8], offset: usize, length: usize) -> Result<u32, DissectError> {
+    Ok(field(data, offset, length)?.iter().fold(0, |n, &b| n << 8 | b as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_chunks() {
+        // Set Chunk Size to 4, then a "play" command split into 4 B chunks
+        let mut data = vec![0x02, 0, 0, 0, 0, 0, 4, 1, 0, 0, 0, 0, 0, 0, 0, 4,
+                            0x03, 0, 0, 0, 0, 0, 7, 20, 1, 0, 0, 0, 2, 0, 4, b'p',
+                            0xc3, b'l', b'a', b'y'];
+
+        let val = *dissect(&data).unwrap();
+        let chunks = val.as_object().unwrap().1;
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].1["Chunk Size"].as_unsigned().unwrap(), 4);
+        assert_eq!(chunks[1].1["Message Type Name"].as_symbol().unwrap(), "AMF0 Command");
+        assert_eq!(chunks[1].1["Message Stream ID"].as_unsigned().unwrap(), 1);
+        assert_eq!(chunks[2].1["Data"].as_bytes().unwrap(), b"lay");
+
+        // A chunk continuing a stream from an earlier segment
+        data.drain(..32);
+        let val = *dissect(&data).unwrap();
+        assert!(val["Remainder"].is_undissected());
+    }
+
+    #[test]
+    fn dissect_c0_c1() {
+        let mut data = vec![0; 1 + HANDSHAKE];
+        data[0] = 3;
+        data[4] = 42;
+
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Message Type"].as_symbol().unwrap(), "Handshake");
+        assert_eq!(val["Time"].as_unsigned().unwrap(), 42);
+    }
+}
@@ -0,0 +1,372 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the Real-time Transport Protocol (RTP) and its control
+//! protocol (RTCP).
+//!
+//! RTP sessions use dynamically-negotiated UDP ports, so the UDP dissector only
+//! finds them heuristically (see `looks_like_rtp` and `looks_like_rtcp`).
+//! Callers that know better can "decode as" RTP by calling `dissect` or
+//! `dissect_rtcp` on a payload directly.
+//!
+//! See [RFC 3550](https://tools.ietf.org/html/rfc3550).
+
+use Endianness;
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use Val;
+use NamedValues;
+use unsigned;
+use time::Time;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 12 {
+        return Err(DissectError::Underflow { expected: Some(12), have: data.len(),
+            message: "An RTP packet must be at least 12 B".to_string() })
+    }
+
+    let mut values = NamedValues::new();
+
+    let version = data[0] >> 6;
+    values.push(("Version", Val::Unsigned(version as u64)));
+
+    let padding = data[0] & 0x20 != 0;
+    values.push(("Padding", Val::Unsigned(padding as u64)));
+
+    let extension = data[0] & 0x10 != 0;
+    values.push(("Extension", Val::Unsigned(extension as u64)));
+
+    let csrc_count = (data[0] & 0x0f) as usize;
+    values.push(("CSRC Count", Val::Unsigned(csrc_count as u64)));
+
+    values.push(("Marker", Val::Unsigned((data[1] >> 7) as u64)));
+    values.push(("Payload Type", Val::Unsigned((data[1] & 0x7f) as u64)));
+
+    let sequence_number = unsigned(&data[2..4], Endianness::BigEndian);
+    values.push(("Sequence Number", Val::Unsigned(sequence_number.unwrap())));
+
+    let timestamp = unsigned(&data[4..8], Endianness::BigEndian);
+    values.push(("Timestamp", Val::Unsigned(timestamp.unwrap())));
+
+    let ssrc = unsigned(&data[8..12], Endianness::BigEndian);
+    values.push(("SSRC", Val::Unsigned(ssrc.unwrap())));
+
+    let mut offset = 12 + 4 * csrc_count;
+    if offset > data.len() {
+        return Err(DissectError::Underflow { expected: Some(offset), have: data.len(),
+            message: "RTP CSRC count greater than available data".to_string() });
+    }
+
+    if csrc_count > 0 {
+        let mut csrcs = NamedValues::new();
+        for csrc in data[12..offset].chunks(4) {
+            csrcs.push(("CSRC", Val::Unsigned(unsigned(csrc, Endianness::BigEndian).unwrap())));
+        }
+        values.push(("CSRC List", Val::Object("CSRC List", csrcs)));
+    }
+
+    if extension {
+        if offset + 4 > data.len() {
+            return Err(DissectError::Underflow { expected: Some(offset + 4), have: data.len(),
+                message: "RTP header extension truncated".to_string() });
+        }
+
+        let profile = unsigned(&data[offset..offset + 2], Endianness::BigEndian).unwrap();
+        let words = unsigned(&data[offset + 2..offset + 4], Endianness::BigEndian).unwrap();
+        let end = offset + 4 + 4 * words as usize;
+        if end > data.len() {
+            return Err(DissectError::Underflow { expected: Some(end), have: data.len(),
+                message: "RTP header extension length greater than available data".to_string() });
+        }
+
+        let mut ext = NamedValues::new();
+        ext.push(("Profile", Val::Unsigned(profile)));
+        ext.push(("Length", Val::Unsigned(words)));
+        ext.push(("Data", Val::Bytes(&data[offset + 4..end])));
+        values.push(("Header Extension", Val::Object("Header Extension", ext)));
+
+        offset = end;
+    }
+
+    let mut end = data.len();
+    if padding {
+        let pad = data[data.len() - 1] as usize;
+        if pad == 0 || offset + pad > data.len() {
+            return Err(DissectError::malformed(ErrorCode::InvalidLength,
+                format!["RTP padding of {} B doesn't fit in {} B of payload",
+                        pad, data.len() - offset]));
+        }
+        end -= pad;
+        values.push(("Padding Length", Val::Unsigned(pad as u64)));
+    }
+
+    values.push(("Payload", Val::Undissected("RTP payload", &data[offset..end])));
+
+    Ok(Box::new(Val::Object("RTP", values)))
+}
+
+pub fn dissect_rtcp(data : &[u8]) -> DissectResult {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "An RTCP packet must be at least 4 B".to_string() })
+    }
+
+    // RTCP packets are always sent in compound datagrams of one or more packets.
+    let mut values = NamedValues::new();
+    let mut offset = 0;
+
+    while offset + 4 <= data.len() {
+        let words = unsigned(&data[offset + 2..offset + 4], Endianness::BigEndian).unwrap();
+        let end = offset + 4 * (words as usize + 1);
+        if end > data.len() {
+            return Err(DissectError::Underflow { expected: Some(end), have: data.len(),
+                message: "RTCP packet length greater than available data".to_string() });
+        }
+
+        values.push(("Packet", rtcp_packet(&data[offset..end])?));
+        offset = end;
+    }
+
+    if offset < data.len() {
+        values.push(("Trailing Data", Val::Bytes(&data[offset..])));
+    }
+
+    Ok(Box::new(Val::Object("RTCP", values)))
+}
+
+/// Heuristically decide whether a UDP payload is RTP.
+///
+/// RTP has no magic number, so this only rejects data that is clearly not
+/// RTP: the wrong version, a payload type reserved to avoid RTCP conflicts
+/// or a CSRC list that doesn't fit.
+pub fn looks_like_rtp(data: &[u8]) -> bool {
+    if data.len() < 12 || data[0] >> 6 != 2 {
+        return false;
+    }
+
+    let payload_type = data[1] & 0x7f;
+    let csrc_count = (data[0] & 0x0f) as usize;
+
+    (payload_type < 72 || payload_type > 76) && 12 + 4 * csrc_count <= data.len()
+}
+
+/// Heuristically decide whether a UDP payload is RTCP.
+pub fn looks_like_rtcp(data: &[u8]) -> bool {
+    if data.len() < 8 || data[0] >> 6 != 2 {
+        return false;
+    }
+
+    let words = unsigned(&data[2..4], Endianness::BigEndian).unwrap() as usize;
+
+    data[1] >= 200 && data[1] <= 204 && 4 * (words + 1) <= data.len()
+}
+
+fn rtcp_packet(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Version", Val::Unsigned((data[0] >> 6) as u64)));
+    values.push(("Padding", Val::Unsigned(((data[0] >> 5) & 1) as u64)));
+
+    let count = (data[0] & 0x1f) as usize;
+    values.push(("Count", Val::Unsigned(count as u64)));
+
+    let packet_type = data[1];
+    values.push(("Packet Type", Val::Unsigned(packet_type as u64)));
+
+    let length = unsigned(&data[2..4], Endianness::BigEndian).unwrap();
+    values.push(("Length", Val::Unsigned(length)));
+
+    let body = &data[4..];
+    let name = match packet_type {
+        200 => {
+            expect(body, 24, "RTCP sender report")?;
+            values.push(("SSRC", Val::Unsigned(unsigned(&body[0..4], Endianness::BigEndian).unwrap())));
+            values.push(("NTP Timestamp", Val::Timestamp(Time::from_ntp(unsigned(&body[4..12], Endianness::BigEndian).unwrap()))));
+            values.push(("RTP Timestamp", Val::Unsigned(unsigned(&body[12..16], Endianness::BigEndian).unwrap())));
+            values.push(("Packet Count", Val::Unsigned(unsigned(&body[16..20], Endianness::BigEndian).unwrap())));
+            values.push(("Octet Count", Val::Unsigned(unsigned(&body[20..24], Endianness::BigEndian).unwrap())));
+            report_blocks(&body[24..], count, &mut values)?;
+            "Sender Report"
+        },
+        201 => {
+            expect(body, 4, "RTCP receiver report")?;
+            values.push(("SSRC", Val::Unsigned(unsigned(&body[0..4], Endianness::BigEndian).unwrap())));
+            report_blocks(&body[4..], count, &mut values)?;
+            "Receiver Report"
+        },
+        202 => {
+            sdes_chunks(body, count, &mut values)?;
+            "Source Description"
+        },
+        203 => {
+            expect(body, 4 * count, "RTCP BYE")?;
+            for ssrc in body[..4 * count].chunks(4) {
+                values.push(("SSRC", Val::Unsigned(unsigned(ssrc, Endianness::BigEndian).unwrap())));
+            }
+
+            let rest = &body[4 * count..];
+            if !rest.is_empty() {
+                let len = rest[0] as usize;
+                expect(&rest[1..], len, "RTCP BYE reason")?;
+                values.push(("Reason",
+                             Val::String(String::from_utf8_lossy(&rest[1..1 + len]).into_owned())));
+            }
+            "Goodbye"
+        },
+        204 => {
+            expect(body, 8, "RTCP APP")?;
+            values.push(("SSRC", Val::Unsigned(unsigned(&body[0..4], Endianness::BigEndian).unwrap())));
+            values.push(("Name", Val::String(String::from_utf8_lossy(&body[4..8]).into_owned())));
+            values.push(("Data", Val::Bytes(&body[8..])));
+            "Application Defined"
+        },
+        _ => {
+            values.push(("Data", Val::Bytes(body)));
+            "Unknown"
+        },
+    };
+
+    Ok(Val::Object(name, values))
+}
+
+fn report_blocks(data: &[u8], count: usize, values: &mut NamedValues) -> Result<(), DissectError> {
+    expect(data, 24 * count, "RTCP report blocks")?;
+
+    for block in data[..24 * count].chunks(24) {
+        let mut report = NamedValues::new();
+        report.push(("SSRC", Val::Unsigned(unsigned(&block[0..4], Endianness::BigEndian).unwrap())));
+        report.push(("Fraction Lost", Val::Unsigned(block[4] as u64)));
+
+        // Cumulative number of packets lost is a signed 24-bit integer.
+        let lost = ((block[5] as i32) << 24 | (block[6] as i32) << 16 | (block[7] as i32) << 8) >> 8;
+        report.push(("Cumulative Lost", Val::Signed(lost as i64)));
+
+        report.push(("Highest Sequence Number",
+                     Val::Unsigned(unsigned(&block[8..12], Endianness::BigEndian).unwrap())));
+        report.push(("Jitter", Val::Unsigned(unsigned(&block[12..16], Endianness::BigEndian).unwrap())));
+        report.push(("Last SR", Val::Unsigned(unsigned(&block[16..20], Endianness::BigEndian).unwrap())));
+        // In units of 1/65536 s, i.e., NTP short format
+        report.push(("Delay Since Last SR",
+                     Val::Duration(Time::from_ntp_short(unsigned(&block[20..24], Endianness::BigEndian).unwrap() as u32))));
+        values.push(("Report Block", Val::Object("Report Block", report)));
+    }
+
+    Ok(())
+}
+
+fn sdes_chunks(data: &[u8], count: usize, values: &mut NamedValues) -> Result<(), DissectError> {
+    let mut offset = 0;
+
+    for _ in 0..count {
+        expect(&data[offset..], 4, "RTCP SDES chunk")?;
+
+        let mut chunk = NamedValues::new();
+        chunk.push(("SSRC", Val::Unsigned(unsigned(&data[offset..offset + 4], Endianness::BigEndian).unwrap())));
+        offset += 4;
+
+        // A list of items, terminated by a null item and padded to 32 bits
+        loop {
+            expect(&data[offset..], 1, "RTCP SDES item")?;
+            let item_type = data[offset];
+            if item_type == 0 {
+                offset += 4 - offset % 4;
+                break;
+            }
+
+            expect(&data[offset..], 2, "RTCP SDES item")?;
+            let len = data[offset + 1] as usize;
+            expect(&data[offset + 2..], len, "RTCP SDES item")?;
+
+            let text = &data[offset + 2..offset + 2 + len];
+            let name = match item_type {
+                1 => "CNAME",
+                2 => "NAME",
+                3 => "EMAIL",
+                4 => "PHONE",
+                5 => "LOC",
+                6 => "TOOL",
+                7 => "NOTE",
+                8 => "PRIV",
+                _ => "Unknown",
+            };
+
+            chunk.push((name, Val::String(String::from_utf8_lossy(text).into_owned())));
+            offset += 2 + len;
+        }
+
+        values.push(("Chunk", Val::Object("SDES Chunk", chunk)));
+    }
+
+    Ok(())
+}
+
+fn expect(data: &[u8], len: usize, what: &str) -> Result<(), DissectError> {
+    if data.len() < len {
+        return Err(DissectError::Underflow { expected: Some(len), have: data.len(),
+            message: format!["{} needs {} B, have {} B", what, len, data.len()] });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_rtp() {
+        let data = [0x90, 0x88, 0x1b, 0x39, 0x00, 0x00, 0x03, 0x20, 0xde, 0xad, 0xbe, 0xef,
+                    0xbe, 0xde, 0x00, 0x01, 0x10, 0xaa, 0x00, 0x00, 0xd5, 0xd5, 0xd5, 0xd5];
+
+        let val = *dissect(&data).unwrap();
+        println!("{}", &val.pretty_print(0));
+
+        assert_eq!(val["Version"].as_unsigned().unwrap(), 2);
+        assert_eq!(val["Marker"].as_unsigned().unwrap(), 1);
+        assert_eq!(val["Payload Type"].as_unsigned().unwrap(), 8);
+        assert_eq!(val["Sequence Number"].as_unsigned().unwrap(), 6969);
+        assert_eq!(val["Timestamp"].as_unsigned().unwrap(), 800);
+        assert_eq!(val["SSRC"].as_unsigned().unwrap(), 0xdeadbeef);
+        assert_eq!(val["Header Extension"]["Profile"].as_unsigned().unwrap(), 0xbede);
+        assert_eq!(val["Payload"].as_undissected().unwrap().1, &[0xd5, 0xd5, 0xd5, 0xd5]);
+    }
+
+    #[test]
+    fn dissect_rtp_csrc_underflow() {
+        let data = [0x8f, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+        assert!(looks_like_rtp(&data) == false);
+        assert!(dissect(&data).is_err());
+    }
+
+    #[test]
+    fn dissect_rtcp_compound() {
+        let data = [
+            // Receiver report with no report blocks
+            0x80, 0xc9, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2a,
+            // SDES with a single CNAME item
+            0x81, 0xca, 0x00, 0x03, 0x00, 0x00, 0x00, 0x2a,
+            0x01, 0x03, b'b', b'o', b'b', 0x00, 0x00, 0x00,
+            // BYE with a reason
+            0x81, 0xcb, 0x00, 0x02, 0x00, 0x00, 0x00, 0x2a,
+            0x03, b'b', b'y', b'e',
+        ];
+
+        assert!(looks_like_rtcp(&data));
+
+        let val = *dissect_rtcp(&data).unwrap();
+        println!("{}", &val.pretty_print(0));
+
+        let packets = val.as_object().unwrap().1;
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].1.as_object().unwrap().0, "Receiver Report");
+        assert_eq!(packets[1].1["Chunk"]["CNAME"].as_string().unwrap(), "bob");
+        assert_eq!(packets[2].1["Reason"].as_string().unwrap(), "bye");
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the Real Time Streaming Protocol (RTSP), on TCP port 554.
+//!
+//! RTSP messages are HTTP-like. DESCRIBE responses carry an SDP body (see
+//! `sdp`) and SETUP exchanges a Transport header that says which ports the
+//! RTP and RTCP streams will use. Since those ports are negotiated rather
+//! than well-known, `RtpPorts` is a tap that records them so that the media
+//! that follows can be decoded as RTP rather than found heuristically.
+//! Media can also be interleaved on the RTSP connection itself, framed by a
+//! `$`, a channel number and a length.
+//!
+//! See [RFC 2326](https://tools.ietf.org/html/rfc2326).
+
+use std::collections::BTreeMap;
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use NamedValues;
+use Val;
+use raw;
+use read_be_u16;
+use rtp;
+use sdp;
+use tap::{PacketInfo, Tap};
+
+pub const PORT: u16 = 554;
+
+/// Headers surfaced as fields, by their (case-insensitive) names.
+const FIELDS: [(&'static str, &'static str); 8] = [
+    ("cseq", "CSeq"),
+    ("session", "Session"),
+    ("content-type", "Content Type"),
+    ("content-base", "Content Base"),
+    ("public", "Public"),
+    ("range", "Range"),
+    ("user-agent", "User Agent"),
+    ("server", "Server"),
+];
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.first() == Some(&b'$') {
+        return dissect_interleaved(data);
+    }
+
+    let end = data.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4).unwrap_or(data.len());
+    let head = String::from_utf8_lossy(&data[..end]);
+    let mut lines = head.lines().map(|l| l.trim_end_matches('\r'));
+
+    let start = lines.next().unwrap_or("");
+    let mut parts = start.splitn(3, ' ');
+    let mut values = NamedValues::new();
+
+    if start.starts_with("RTSP/") {
+        values.push(("Message Type", Val::Symbol("Response")));
+        values.push(("Version", Val::String(parts.next().unwrap_or("").to_string())));
+        values.push(("Status Code", Val::String(parts.next().unwrap_or("").to_string())));
+        values.push(("Reason", Val::String(parts.next().unwrap_or("").to_string())));
+    } else {
+        let method = parts.next().unwrap_or("");
+        let uri = parts.next().unwrap_or("");
+        let version = parts.next().unwrap_or("");
+        if !version.starts_with("RTSP/") {
+            return Err(DissectError::malformed(ErrorCode::BadMagic,
+                format!["'{}' is not an RTSP start line", start]));
+        }
+
+        values.push(("Message Type", Val::Symbol("Request")));
+        values.push(("Method", Val::String(method.to_string())));
+        values.push(("URI", Val::String(uri.to_string())));
+        values.push(("Version", Val::String(version.to_string())));
+    }
+
+    let mut fields = NamedValues::new();
+    let mut content_type = String::new();
+    let mut content_length = None;
+
+    for line in lines.take_while(|l| !l.is_empty()) {
+        if let Some(colon) = line.find(':') {
+            let name = line[..colon].trim().to_lowercase();
+            let value = line[colon + 1..].trim();
+
+            if let Some(&(_, field)) = FIELDS.iter().find(|&&(n, _)| n == name) {
+                fields.push((field, Val::String(value.to_string())));
+            }
+
+            match name.as_str() {
+                "content-type" => content_type = value.to_lowercase(),
+                "content-length" => content_length = value.parse::<usize>().ok(),
+                "transport" => for spec in value.split(',') {
+                    fields.push(("Transport", transport(spec)));
+                },
+                _ => {},
+            }
+        }
+
+        values.push(("Header", Val::String(line.to_string())));
+    }
+
+    values.extend(fields);
+
+    let body = &data[end..];
+    let body = &body[..content_length.unwrap_or(body.len()).min(body.len())];
+    if !body.is_empty() {
+        values.push(("Body", if content_type.starts_with("application/sdp") {
+            Val::Payload(body, sdp::dissect(body))
+        } else {
+            Val::Payload(body, raw("Data", body))
+        }));
+    }
+
+    Ok(Box::new(Val::Object("RTSP", values)))
+}
+
+/// Dissect media interleaved on the RTSP connection: by convention, even
+/// channels carry RTP and odd channels RTCP.
+fn dissect_interleaved(data : &[u8]) -> DissectResult {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "RTSP interleaved data must be at least 4 B".to_string() });
+    }
+
+    let channel = data[1];
+    let length = read_be_u16(data, 2)? as usize;
+    let payload = &data[4..(4 + length).min(data.len())];
+
+    let mut values = NamedValues::new();
+    values.push(("Message Type", Val::Symbol("Interleaved Data")));
+    values.push(("Channel", Val::Unsigned(channel as u64)));
+    values.push(("Length", Val::Unsigned(length as u64)));
+    values.push(("Payload", Val::Payload(payload, if channel % 2 == 0 {
+        rtp::dissect(payload)
+    } else {
+        rtp::dissect_rtcp(payload)
+    })));
+
+    Ok(Box::new(Val::Object("RTSP", values)))
+}
+
+/// One transport specification from a Transport header, e.g.,
+/// `RTP/AVP;unicast;client_port=5000-5001`.
+fn transport(spec: &str) -> Val<'static> {
+    let mut params = spec.trim().split(';');
+    let mut values = NamedValues::new();
+    values.push(("Protocol", Val::String(params.next().unwrap_or("").to_string())));
+
+    for param in params {
+        let mut kv = param.splitn(2, '=');
+        let key = kv.next().unwrap_or("").trim().to_lowercase();
+        let value = kv.next().unwrap_or("").trim();
+
+        let (first, second) = match key.as_str() {
+            "unicast" | "multicast" => {
+                values.push(("Delivery", Val::String(key.clone())));
+                continue;
+            },
+            "client_port" => ("Client RTP Port", "Client RTCP Port"),
+            "server_port" => ("Server RTP Port", "Server RTCP Port"),
+            "port" => ("RTP Port", "RTCP Port"),
+            "interleaved" => ("RTP Channel", "RTCP Channel"),
+            "destination" => {
+                values.push(("Destination", Val::String(value.to_string())));
+                continue;
+            },
+            "source" => {
+                values.push(("Source", Val::String(value.to_string())));
+                continue;
+            },
+            "ssrc" => {
+                if let Ok(ssrc) = u32::from_str_radix(value, 16) {
+                    values.push(("SSRC", Val::Unsigned(ssrc as u64)));
+                }
+                continue;
+            },
+            "mode" => {
+                values.push(("Mode", Val::String(value.trim_matches('"').to_string())));
+                continue;
+            },
+            _ => continue,
+        };
+
+        // A range such as 5000-5001, or a single port with RTCP on the next
+        let mut range = value.splitn(2, '-').map(|n| n.parse::<u16>().ok());
+        if let Some(Some(low)) = range.next() {
+            values.push((first, Val::Unsigned(low as u64)));
+            match range.next() {
+                Some(Some(high)) => values.push((second, Val::Unsigned(high as u64))),
+                _ => values.push((second, Val::Unsigned(low.wrapping_add(1) as u64))),
+            }
+        }
+    }
+
+    Val::Object("RTSP Transport", values)
+}
+
+/// What a negotiated port carries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    Rtp,
+    Rtcp,
+}
+
+/// A tap on "RTSP" that records the RTP and RTCP ports negotiated in
+/// Transport headers and SDP media descriptions.
+///
+/// Register it before dissecting the RTSP session, then use `dissect` to
+/// decode the UDP payloads of the media streams.
+#[derive(Default)]
+pub struct RtpPorts {
+    ports: BTreeMap<u16, Channel>,
+}
+
+impl RtpPorts {
+    pub fn new() -> RtpPorts {
+        RtpPorts::default()
+    }
+
+    /// Record a port pair, e.g., one found out-of-band.
+    pub fn register(&mut self, rtp_port: u16, rtcp_port: u16) {
+        self.ports.insert(rtp_port, Channel::Rtp);
+        self.ports.insert(rtcp_port, Channel::Rtcp);
+    }
+
+    /// What a port was negotiated to carry, if anything.
+    pub fn channel(&self, port: u16) -> Option<Channel> {
+        self.ports.get(&port).cloned()
+    }
+
+    /// Dissect a UDP payload as RTP or RTCP if either of its ports was
+    /// negotiated.
+    pub fn dissect<'data>(&self, source_port: u16, destination_port: u16, data: &'data [u8])
+        -> Option<DissectResult<'data>> {
+
+        match self.channel(destination_port).or_else(|| self.channel(source_port)) {
+            Some(Channel::Rtp) => Some(rtp::dissect(data)),
+            Some(Channel::Rtcp) => Some(rtp::dissect_rtcp(data)),
+            None => None,
+        }
+    }
+
+    fn pair(&mut self, values: &Val, rtp_port: &str, rtcp_port: &str) {
+        match (values.get(rtp_port), values.get(rtcp_port)) {
+            (Ok(&Val::Unsigned(rtp)), Ok(&Val::Unsigned(rtcp))) if rtp > 0 =>
+                self.register(rtp as u16, rtcp as u16),
+            _ => {},
+        }
+    }
+}
+
+impl Tap for RtpPorts {
+    fn tap(&mut self, _: &PacketInfo, layer: &Val) {
+        let values = match layer.as_object() {
+            Some((_, values)) => values,
+            None => return,
+        };
+
+        for &(name, ref value) in values {
+            if name == "Transport" {
+                self.pair(value, "Client RTP Port", "Client RTCP Port");
+                self.pair(value, "Server RTP Port", "Server RTCP Port");
+                self.pair(value, "RTP Port", "RTCP Port");
+            }
+        }
+
+        let media = layer.get("Body").ok().and_then(|body| body.layer("SDP"))
+            .and_then(|sdp| sdp.as_object());
+        if let Some((_, values)) = media {
+            for &(name, ref value) in values {
+                if let (true, Ok(&Val::Unsigned(port))) = (name == "Media", value.get("Port")) {
+                    if port > 0 && port < 0xffff {
+                        self.register(port as u16, port as u16 + 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use tap::Taps;
+
+    #[test]
+    fn dissect_setup() {
+        let request = b"SETUP rtsp://cam/stream/track1 RTSP/1.0\r\nCSeq: 3\r\n\
+                        Transport: RTP/AVP;unicast;client_port=5000-5001\r\n\r\n";
+        let val = *dissect(request).unwrap();
+        assert_eq!(val["Method"].as_string().unwrap(), "SETUP");
+        assert_eq!(val["CSeq"].as_string().unwrap(), "3");
+        assert_eq!(val["Transport"]["Client RTCP Port"].as_unsigned().unwrap(), 5001);
+
+        let reply = b"RTSP/1.0 200 OK\r\nCSeq: 3\r\nSession: 12345678\r\n\
+                      Transport: RTP/AVP;unicast;client_port=5000-5001;server_port=6970-6971;ssrc=1A2B3C4D\r\n\r\n";
+        let reply = dissect(reply).unwrap();
+        assert_eq!(reply["Transport"]["SSRC"].as_unsigned().unwrap(), 0x1a2b3c4d);
+
+        let mut ports = RtpPorts::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("RTSP", &mut ports);
+            taps.dispatch(Duration::new(0, 0), 0, &reply);
+        }
+        assert_eq!(ports.channel(6970), Some(Channel::Rtp));
+        assert_eq!(ports.channel(5001), Some(Channel::Rtcp));
+        assert_eq!(ports.channel(5002), None);
+
+        let rtp = [0x80, 0x60, 0x00, 0x01, 0, 0, 0, 0, 0x1a, 0x2b, 0x3c, 0x4d];
+        let val = ports.dissect(6970, 5000, &rtp).unwrap().unwrap();
+        assert_eq!(val["SSRC"].as_unsigned().unwrap(), 0x1a2b3c4d);
+    }
+
+    #[test]
+    fn dissect_describe_and_interleaved() {
+        let sdp = "v=0\r\ns=Camera\r\nm=video 5004 RTP/AVP 96\r\n";
+        let reply = format!["RTSP/1.0 200 OK\r\nCSeq: 2\r\nContent-Type: application/sdp\r\n\
+                             Content-Length: {}\r\n\r\n{}", sdp.len(), sdp];
+        let val = *dissect(reply.as_bytes()).unwrap();
+        assert_eq!(val["Status Code"].as_string().unwrap(), "200");
+        assert_eq!(val["Body"]["Media"]["Port"].as_unsigned().unwrap(), 5004);
+
+        let data = [b'$', 1, 0, 8, 0x80, 0xc9, 0x00, 0x01, 0x1a, 0x2b, 0x3c, 0x4d];
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Channel"].as_unsigned().unwrap(), 1);
+        assert!(val["Payload"].as_payload().unwrap().is_ok());
+
+        assert!(dissect(b"GET / HTTP/1.1\r\n\r\n").is_err());
+    }
+}
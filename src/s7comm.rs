@@ -0,0 +1,407 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of S7 communication ("S7comm"), the protocol spoken by Siemens
+//! S7 PLCs, along with the ISO transport layers that carry it on TCP port 102:
+//! TPKT framing and connection-oriented transport (COTP) TPDUs.
+//!
+//! See [RFC 1006](https://tools.ietf.org/html/rfc1006) (TPKT),
+//! [RFC 905](https://tools.ietf.org/html/rfc905) (COTP) and, for S7comm, the
+//! Wireshark wiki's [S7comm](https://wiki.wireshark.org/S7comm) page.
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use Val;
+use NamedValues;
+use raw;
+
+/// S7comm protocol ID, the first byte of every PDU.
+const PROTOCOL_ID: u8 = 0x32;
+
+const READ_VAR: u8 = 0x04;
+const WRITE_VAR: u8 = 0x05;
+const SETUP_COMMUNICATION: u8 = 0xf0;
+
+/// Dissect a TPKT (four bytes of version and length) and the COTP TPDU in it.
+pub fn dissect_tpkt(data : &[u8]) -> DissectResult {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "A TPKT header must be 4 B".to_string() })
+    }
+
+    if data[0] != 3 {
+        return Err(DissectError::malformed(ErrorCode::UnsupportedVersion,
+            format!["TPKT version {} is not 3", data[0]]));
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Version", Val::Unsigned(data[0] as u64)));
+
+    let length = be(data, 2, 2)? as usize;
+    values.push(("Length", Val::Unsigned(length as u64)));
+
+    if length < 4 {
+        return Err(DissectError::malformed(ErrorCode::InvalidLength,
+            format!["TPKT length {} is shorter than its header", length]));
+    }
+
+    let end = ::std::cmp::min(length, data.len());
+    values.push(("Payload", Val::Payload(&data[4..end], dissect_cotp(&data[4..end]))));
+
+    Ok(Box::new(Val::Object("TPKT", values)))
+}
+
+/// Dissect a COTP TPDU (and, for data TPDUs, the S7comm PDU it carries).
+pub fn dissect_cotp(data : &[u8]) -> DissectResult {
+    if data.len() < 2 {
+        return Err(DissectError::Underflow { expected: Some(2), have: data.len(),
+            message: "A COTP TPDU must be at least 2 B".to_string() })
+    }
+
+    let length = data[0] as usize;
+    if length + 1 > data.len() {
+        return Err(DissectError::Underflow { expected: Some(length + 1), have: data.len(),
+            message: "COTP length indicator greater than available data".to_string() });
+    }
+
+    // The length indicator covers the header, which starts with the PDU type.
+    if length == 0 {
+        return Err(DissectError::malformed(ErrorCode::InvalidLength,
+            "COTP length indicator of 0 leaves no room for the PDU type"));
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Length", Val::Unsigned(length as u64)));
+
+    let pdu_type = data[1] & 0xf0;
+    values.push(("PDU Type", Val::Unsigned(pdu_type as u64)));
+    values.push(("PDU Type Name", Val::Symbol(match pdu_type {
+        0xe0 => "Connection Request",
+        0xd0 => "Connection Confirm",
+        0x80 => "Disconnect Request",
+        0xc0 => "Disconnect Confirm",
+        0xf0 => "Data",
+        0x70 => "Error",
+        _ => "Unknown",
+    })));
+
+    let header = &data[1..length + 1];
+    let user_data = &data[length + 1..];
+
+    match pdu_type {
+        0xf0 => {
+            if header.len() < 2 {
+                return Err(DissectError::malformed(ErrorCode::InvalidLength,
+                    "COTP data TPDU header is too short"));
+            }
+
+            values.push(("TPDU Number", Val::Unsigned((header[1] & 0x7f) as u64)));
+            values.push(("Last Data Unit", Val::Unsigned((header[1] >> 7) as u64)));
+
+            let payload = match user_data.first() {
+                Some(&PROTOCOL_ID) => dissect(user_data),
+                _ => raw("Data", user_data),
+            };
+            values.push(("Payload", Val::Payload(user_data, payload)));
+        },
+
+        0xe0 | 0xd0 | 0x80 | 0xc0 => {
+            if header.len() < 6 {
+                return Err(DissectError::malformed(ErrorCode::InvalidLength,
+                    "COTP connection TPDU header is too short"));
+            }
+
+            values.push(("Destination Reference", Val::Unsigned(be(header, 1, 2)?)));
+            values.push(("Source Reference", Val::Unsigned(be(header, 3, 2)?)));
+            values.push(("Class", Val::Unsigned((header[5] >> 4) as u64)));
+            parameters(&header[6..], &mut values)?;
+        },
+
+        _ => values.push(("Header", Val::Bytes(&header[1..]))),
+    }
+
+    Ok(Box::new(Val::Object("COTP", values)))
+}
+
+/// Dissect an S7comm PDU.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 10 {
+        return Err(DissectError::Underflow { expected: Some(10), have: data.len(),
+            message: "An S7comm PDU must be at least 10 B".to_string() })
+    }
+
+    if data[0] != PROTOCOL_ID {
+        return Err(DissectError::malformed(ErrorCode::BadMagic,
+            format!["S7comm protocol ID is {:#04x}, not 0x32", data[0]]));
+    }
+
+    let mut values = NamedValues::new();
+
+    let rosctr = data[1];
+    values.push(("ROSCTR", Val::Unsigned(rosctr as u64)));
+    values.push(("ROSCTR Name", Val::Symbol(match rosctr {
+        1 => "Job",
+        2 => "Ack",
+        3 => "Ack_Data",
+        7 => "Userdata",
+        _ => "Unknown",
+    })));
+
+    values.push(("PDU Reference", Val::Unsigned(be(data, 4, 2)?)));
+
+    let parameter_length = be(data, 6, 2)? as usize;
+    values.push(("Parameter Length", Val::Unsigned(parameter_length as u64)));
+
+    let data_length = be(data, 8, 2)? as usize;
+    values.push(("Data Length", Val::Unsigned(data_length as u64)));
+
+    // Acknowledgements have a two-byte error field.
+    let mut offset = 10;
+    if rosctr == 2 || rosctr == 3 {
+        values.push(("Error Class", Val::Unsigned(be(data, 10, 1)?)));
+        values.push(("Error Code", Val::Unsigned(be(data, 11, 1)?)));
+        offset = 12;
+    }
+
+    let parameters = slice(data, offset, parameter_length, "parameters")?;
+    let item_data = slice(data, offset + parameter_length, data_length, "data")?;
+
+    if let Some(&function) = parameters.first() {
+        values.push(("Function", Val::Unsigned(function as u64)));
+        values.push(("Function Name", Val::Symbol(function_name(function))));
+
+        match function {
+            SETUP_COMMUNICATION if parameters.len() >= 8 => {
+                values.push(("Max AmQ Calling", Val::Unsigned(be(parameters, 2, 2)?)));
+                values.push(("Max AmQ Called", Val::Unsigned(be(parameters, 4, 2)?)));
+                values.push(("PDU Length", Val::Unsigned(be(parameters, 6, 2)?)));
+            },
+
+            READ_VAR | WRITE_VAR if parameters.len() >= 2 => {
+                let count = parameters[1] as usize;
+                values.push(("Item Count", Val::Unsigned(count as u64)));
+
+                // Jobs describe the variables to access; Ack_Data carries
+                // the values read (or the result of each write).
+                if rosctr == 1 {
+                    items(&parameters[2..], count, &mut values)?;
+                }
+
+                if (rosctr == 1 && function == WRITE_VAR) || (rosctr == 3 && function == READ_VAR) {
+                    data_items(item_data, count, &mut values)?;
+                } else if rosctr == 3 {
+                    for &code in item_data.iter().take(count) {
+                        values.push(("Return Code", Val::Unsigned(code as u64)));
+                    }
+                }
+            },
+
+            _ => {
+                values.push(("Parameters", Val::Bytes(parameters)));
+                if !item_data.is_empty() {
+                    values.push(("Data", Val::Bytes(item_data)));
+                }
+            },
+        }
+    }
+
+    Ok(Box::new(Val::Object("S7comm", values)))
+}
+
+/// The name of an S7comm function code.
+pub fn function_name(function: u8) -> &'static str {
+    match function {
+        0x00 => "CPU Services",
+        READ_VAR => "Read Var",
+        WRITE_VAR => "Write Var",
+        0x1a => "Request Download",
+        0x1b => "Download Block",
+        0x1c => "Download Ended",
+        0x1d => "Start Upload",
+        0x1e => "Upload",
+        0x1f => "End Upload",
+        0x28 => "PI-Service",
+        0x29 => "PLC Stop",
+        SETUP_COMMUNICATION => "Setup Communication",
+        _ => "Unknown",
+    }
+}
+
+/// COTP connection parameters (TPDU size and transport selectors).
+fn parameters<'data>(mut data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    while data.len() >= 2 {
+        let length = data[1] as usize;
+        let value = slice(data, 2, length, "COTP parameter")?;
+
+        match data[0] {
+            0xc0 if length == 1 => values.push(("TPDU Size", Val::Unsigned(1 << value[0]))),
+            0xc1 => values.push(("Source TSAP", Val::Bytes(value))),
+            0xc2 => values.push(("Destination TSAP", Val::Bytes(value))),
+            _ => values.push(("Parameter", Val::Bytes(&data[..length + 2]))),
+        }
+
+        data = &data[length + 2..];
+    }
+
+    Ok(())
+}
+
+/// Variable specifications of a Read Var or Write Var job.
+fn items<'data>(mut data: &'data [u8], count: usize, values: &mut NamedValues<'data>)
+        -> Result<(), DissectError> {
+
+    for _ in 0..count {
+        if data.len() < 2 {
+            break;
+        }
+
+        let length = data[1] as usize;
+        let spec = slice(data, 2, length, "S7comm item")?;
+        let mut item = NamedValues::new();
+
+        // S7ANY addressing (syntax ID 0x10) is by far the most common.
+        if data[0] == 0x12 && length >= 10 && spec[0] == 0x10 {
+            item.push(("Transport Size", Val::Unsigned(spec[1] as u64)));
+            item.push(("Length", Val::Unsigned(be(spec, 2, 2)?)));
+            item.push(("DB Number", Val::Unsigned(be(spec, 4, 2)?)));
+            item.push(("Area", Val::Unsigned(spec[6] as u64)));
+            item.push(("Area Name", Val::Symbol(area_name(spec[6]))));
+
+            let address = be(spec, 7, 3)?;
+            item.push(("Byte Address", Val::Unsigned(address >> 3)));
+            item.push(("Bit Address", Val::Unsigned(address & 0x07)));
+        } else {
+            item.push(("Specification", Val::Bytes(spec)));
+        }
+
+        values.push(("Item", Val::Object("Item", item)));
+        data = &data[length + 2..];
+    }
+
+    Ok(())
+}
+
+/// Values read by (or written by) Read Var and Write Var.
+fn data_items<'data>(mut data: &'data [u8], count: usize, values: &mut NamedValues<'data>)
+        -> Result<(), DissectError> {
+
+    for i in 0..count {
+        if data.len() < 4 {
+            break;
+        }
+
+        let mut item = NamedValues::new();
+        item.push(("Return Code", Val::Unsigned(data[0] as u64)));
+
+        let transport_size = data[1];
+        item.push(("Transport Size", Val::Unsigned(transport_size as u64)));
+
+        // Bit, byte and integer lengths are given in bits.
+        let mut length = be(data, 2, 2)? as usize;
+        if transport_size == 3 || transport_size == 4 || transport_size == 5 {
+            length = (length + 7) / 8;
+        }
+
+        item.push(("Data", Val::Bytes(slice(data, 4, length, "S7comm data item")?)));
+        values.push(("Data Item", Val::Object("Data Item", item)));
+
+        // Items other than the last are padded to an even length.
+        let padded = 4 + length + if i + 1 < count { length % 2 } else { 0 };
+        data = &data[::std::cmp::min(padded, data.len())..];
+    }
+
+    Ok(())
+}
+
+fn area_name(area: u8) -> &'static str {
+    match area {
+        0x81 => "Inputs",
+        0x82 => "Outputs",
+        0x83 => "Flags",
+        0x84 => "Data Blocks",
+        0x85 => "Instance Data Blocks",
+        0x1c => "Counters",
+        0x1d => "Timers",
+        _ => "Unknown",
+    }
+}
+
+fn be(data: &[u8], offset: usize, length: usize) -> Result<u64, DissectError> {
+    let bytes = slice(data, offset, length, "field")?;
+
+    // Not unsigned(): S7 addresses are 24 bits long.
+    Ok(bytes.iter().fold(0, |value, &b| value << 8 | b as u64))
+}
+
+fn slice<'data>(data: &'data [u8], offset: usize, length: usize, what: &str)
+        -> Result<&'data [u8], DissectError> {
+
+    data.get(offset..offset + length).ok_or(DissectError::Underflow {
+        expected: Some(offset + length), have: data.len(),
+        message: format!["S7comm {} would extend past the end of the data", what],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_connection_request() {
+        let data = [0x03, 0x00, 0x00, 0x16,
+                    0x11, 0xe0, 0x00, 0x00, 0x00, 0x01, 0x00,
+                    0xc0, 0x01, 0x0a, 0xc1, 0x02, 0x01, 0x00, 0xc2, 0x02, 0x01, 0x02];
+
+        let val = *dissect_tpkt(&data).unwrap();
+        let cotp = &val["Payload"];
+        assert_eq!(cotp["PDU Type Name"].as_symbol().unwrap(), "Connection Request");
+        assert_eq!(cotp["Source Reference"].as_unsigned().unwrap(), 1);
+        assert_eq!(cotp["TPDU Size"].as_unsigned().unwrap(), 1024);
+        assert_eq!(cotp["Destination TSAP"].as_bytes().unwrap(), &[0x01, 0x02]);
+    }
+
+    #[test]
+    fn dissect_read_var() {
+        let job = [0x03, 0x00, 0x00, 0x1f,
+                   0x02, 0xf0, 0x80,
+                   0x32, 0x01, 0x00, 0x00, 0x05, 0x00, 0x00, 0x0e, 0x00, 0x00,
+                   0x04, 0x01, 0x12, 0x0a, 0x10, 0x02, 0x00, 0x04, 0x00, 0x01, 0x84, 0x00, 0x00, 0x50];
+
+        let val = *dissect_tpkt(&job).unwrap();
+        let s7 = &val["Payload"]["Payload"];
+        assert_eq!(s7["ROSCTR Name"].as_symbol().unwrap(), "Job");
+        assert_eq!(s7["Function Name"].as_symbol().unwrap(), "Read Var");
+        assert_eq!(s7["Item"]["Area Name"].as_symbol().unwrap(), "Data Blocks");
+        assert_eq!(s7["Item"]["DB Number"].as_unsigned().unwrap(), 1);
+        assert_eq!(s7["Item"]["Byte Address"].as_unsigned().unwrap(), 10);
+        assert_eq!(s7["Item"]["Length"].as_unsigned().unwrap(), 4);
+
+        let response = [0x32, 0x03, 0x00, 0x00, 0x00, 0x05, 0x00, 0x02, 0x00, 0x08, 0x00, 0x00,
+                        0x04, 0x01, 0xff, 0x04, 0x00, 0x20, 0xde, 0xad, 0xbe, 0xef];
+
+        let val = *dissect(&response).unwrap();
+        assert_eq!(val["ROSCTR Name"].as_symbol().unwrap(), "Ack_Data");
+        assert_eq!(val["Data Item"]["Return Code"].as_unsigned().unwrap(), 0xff);
+        assert_eq!(val["Data Item"]["Data"].as_bytes().unwrap(), &[0xde, 0xad, 0xbe, 0xef]);
+
+        assert!(dissect(&response[..11]).is_err());
+    }
+
+    #[test]
+    fn reject_short_cotp_headers() {
+        let empty = [0x03, 0x00, 0x00, 0x07, 0x00, 0xf0, 0x80];
+        let val = *dissect_tpkt(&empty).unwrap();
+        assert_eq!(val["Payload"].as_payload().unwrap().as_ref().unwrap_err().code(),
+                   ErrorCode::InvalidLength);
+
+        assert!(dissect_cotp(&[0x01, 0xf0]).is_err());
+        assert!(dissect_cotp(&[0x02, 0xe0, 0x00]).is_err());
+    }
+}
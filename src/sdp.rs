@@ -0,0 +1,125 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Session Description Protocol (SDP) bodies, as carried by
+//! RTSP (see `rtsp`).
+//!
+//! A description is a list of `type=value` lines: session-level lines, then
+//! one "Media" object per `m=` line with the lines that follow it. Media
+//! ports are where RTP (and, on the next port up, RTCP) will be sent.
+//!
+//! See [RFC 4566](https://tools.ietf.org/html/rfc4566).
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use NamedValues;
+use Val;
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text.lines().map(|l| l.trim_end_matches('\r')).filter(|l| !l.is_empty());
+
+    if !lines.clone().next().map(|l| l.starts_with("v=")).unwrap_or(false) {
+        return Err(DissectError::malformed(ErrorCode::BadMagic,
+            "An SDP description must start with a v= line".to_string()));
+    }
+
+    let mut values = NamedValues::new();
+    let mut media: Option<NamedValues> = None;
+
+    for line in &mut lines {
+        if line.len() < 2 || line.as_bytes()[1] != b'=' {
+            return Err(DissectError::InvalidData(format!["'{}' is not an SDP line", line]));
+        }
+        let value = &line[2..];
+
+        if line.starts_with("m=") {
+            if let Some(m) = media.take() {
+                values.push(("Media", Val::Object("SDP Media", m)));
+            }
+            media = Some(media_description(value)?);
+            continue;
+        }
+
+        let field = match &line[..1] {
+            "v" => "Version",
+            "o" => "Origin",
+            "s" => "Session Name",
+            "i" => "Information",
+            "u" => "URI",
+            "c" => "Connection",
+            "b" => "Bandwidth",
+            "t" => "Time",
+            "a" => "Attribute",
+            _ => "Line",
+        };
+        let value = Val::String(if field == "Line" { line } else { value }.to_string());
+
+        match media {
+            Some(ref mut m) => m.push((field, value)),
+            None => values.push((field, value)),
+        }
+    }
+
+    if let Some(m) = media {
+        values.push(("Media", Val::Object("SDP Media", m)));
+    }
+
+    Ok(Box::new(Val::Object("SDP", values)))
+}
+
+/// The fields of an `m=` line: `<media> <port>[/<count>] <proto> <fmt> ...`.
+fn media_description(value: &str) -> Result<NamedValues<'static>, DissectError> {
+    let mut parts = value.split_whitespace();
+    let mut values = NamedValues::new();
+
+    values.push(("Type", Val::String(parts.next().unwrap_or("").to_string())));
+
+    let port = parts.next().unwrap_or("");
+    let mut port_parts = port.splitn(2, '/');
+    match port_parts.next().and_then(|p| p.parse::<u16>().ok()) {
+        Some(p) => values.push(("Port", Val::Unsigned(p as u64))),
+        None => return Err(DissectError::InvalidData(format!["SDP media port '{}' is invalid", port])),
+    }
+    if let Some(count) = port_parts.next().and_then(|c| c.parse::<u64>().ok()) {
+        values.push(("Port Count", Val::Unsigned(count)));
+    }
+
+    values.push(("Protocol", Val::String(parts.next().unwrap_or("").to_string())));
+    for format in parts {
+        values.push(("Format", Val::String(format.to_string())));
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_sdp() {
+        let data = b"v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=Camera\r\nc=IN IP4 0.0.0.0\r\n\
+                     m=video 5004 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n\
+                     m=audio 0 RTP/AVP 0\r\n";
+
+        let val = *dissect(data).unwrap();
+        assert_eq!(val["Session Name"].as_string().unwrap(), "Camera");
+
+        let media = val.as_object().unwrap().1.iter()
+            .filter(|&&(name, _)| name == "Media")
+            .map(|&(_, ref m)| (m["Type"].as_string().unwrap(), m["Port"].as_unsigned().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(media, vec![("video", 5004), ("audio", 0)]);
+        assert_eq!(val["Media"]["Attribute"].as_string().unwrap(), "rtpmap:96 H264/90000");
+
+        assert!(dissect(b"RTSP/1.0 200 OK\r\n").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Searching dissection trees for values, e.g., across nested tunnels.
+//!
+//! `Val::find` returns the paths (in `Val::lookup` form) of the values that
+//! satisfy a predicate and `Val::find_all` those whose paths match a glob.
+//! In a glob, a `*` on its own matches any number of steps (including none)
+//! and a `*` within a step matches any characters of a field name, so
+//! "*.Checksum" matches every layer's checksum and "*.*Port" every port.
+//!
+//! ```
+//! use std::net::Ipv4Addr;
+//!
+//! let packet = [
+//!     0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+//!     0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+//!     10, 0, 0, 1, 10, 0, 0, 2,
+//!     0xd4, 0x31, 0x04, 0xd2, 0x00, 0x08, 0x00, 0x00];
+//! let val = rshark::ethernet::dissect(&packet).unwrap();
+//!
+//! let ip = Ipv4Addr::new(10, 0, 0, 2);
+//! assert_eq!(val.find(|v| v.as_ipv4() == Some(ip)), vec!["Payload.Destination"]);
+//! assert_eq!(val.find_all("*.Checksum"), vec!["Payload.Checksum", "Payload.Payload.Checksum"]);
+//! ```
+
+use Val;
+use visit::{Step, Visitor};
+
+impl<'data> Val<'data> {
+    /// The paths of all values (within and including this one) that satisfy
+    /// a predicate, in order.
+    pub fn find<F: Fn(&Val) -> bool>(&self, predicate: F) -> Vec<String> {
+        let mut search = Search { matches: |_: &[Step], val: &Val| predicate(val), found: Vec::new() };
+        self.walk(&mut search);
+        search.found
+    }
+
+    /// The paths of all values whose paths match a glob (see `search`).
+    pub fn find_all(&self, glob: &str) -> Vec<String> {
+        let pattern = glob.split('.').collect::<Vec<_>>();
+        let mut search = Search { matches: |path: &[Step], _: &Val| matches(&pattern, path),
+                                  found: Vec::new() };
+        self.walk(&mut search);
+        search.found
+    }
+}
+
+struct Search<F> {
+    matches: F,
+    found: Vec<String>,
+}
+
+impl<F: Fn(&[Step], &Val) -> bool> Visitor for Search<F> {
+    fn enter(&mut self, path: &[Step], val: &Val) -> bool {
+        // A dissected payload shares its path with the layer inside it.
+        if let Val::Payload(_, Ok(_)) = *val {
+            return true;
+        }
+
+        if (self.matches)(path, val) {
+            self.found.push(Step::join(path));
+        }
+        true
+    }
+}
+
+/// Whether a path matches a glob, split into steps.
+fn matches(pattern: &[&str], path: &[Step]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"*", rest)) => (0..path.len() + 1).any(|skip| matches(rest, &path[skip..])),
+        Some((step, rest)) => match path.split_first() {
+            Some((first, others)) => wildcard(step, &first.to_string()) && matches(rest, others),
+            None => false,
+        },
+    }
+}
+
+/// Whether a name matches a pattern in which `*` matches any characters.
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let parts = pattern.split('*').collect::<Vec<_>>();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || !name[first.len()..].ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_values() {
+        let val = Val::Object("Outer", vec![
+            ("Source Port", Val::Unsigned(53)),
+            ("Hops", Val::List(vec![Val::Unsigned(3), Val::Unsigned(53)])),
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", vec![
+                ("Destination Port", Val::Unsigned(53)),
+                ("Checksum", Val::Bytes(&[0, 0])),
+            ]))))),
+        ]);
+
+        assert_eq!(val.find(|v| v.as_unsigned() == Some(53)),
+                   vec!["Source Port", "Hops.1", "Payload.Destination Port"]);
+        assert_eq!(val.find_all("*.*Port"), vec!["Source Port", "Payload.Destination Port"]);
+        assert_eq!(val.find_all("Hops.*"), vec!["Hops", "Hops.0", "Hops.1"]);
+        assert_eq!(val.find_all("Payload"), vec!["Payload"]);
+        assert_eq!(val.find_all("*.Missing"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn match_wildcards() {
+        assert!(wildcard("*Port", "Source Port"));
+        assert!(wildcard("S*r*t", "Source Port"));
+        assert!(!wildcard("S*x*t", "Source Port"));
+        assert!(!wildcard("Port*Port", "Port"));
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! serde support (with the `serde` feature).
+//!
+//! `Val` implements `Serialize` and `OwnedVal` implements both `Serialize`
+//! and `Deserialize`, with the same representation: each value is an
+//! externally-tagged variant, e.g., `{"Unsigned": 4}` in JSON, objects keep
+//! their name and the order of their fields (as a sequence of key-value
+//! pairs, since keys may repeat) and payloads keep their bytes and their
+//! dissection error, if any. A serialized `Val` can therefore be loaded as an
+//! `OwnedVal` by any serde format.
+//!
+//! Names and symbols are `&'static str`s, so deserializing interns them in a
+//! process-wide `Interner` (see `intern`); input with more distinct names than
+//! an `Interner` may hold fails to deserialize.
+//!
+//! ```
+//! extern crate rshark;
+//! extern crate serde_json;
+//!
+//! use rshark::owned::OwnedVal;
+//!
+//! # fn main() {
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let json = serde_json::to_string(&packet).unwrap();
+//! assert!(json.starts_with(r#"{"Object":["Ethernet frame",[["Destination",{"Address""#));
+//!
+//! let loaded: OwnedVal = serde_json::from_str(&json).unwrap();
+//! assert_eq!(loaded.as_val(), *packet);
+//! # }
+//! ```
+
+use std::sync::{Mutex, OnceLock};
+
+use serde::de::{Deserialize, Deserializer, Error};
+
+use DissectError;
+use intern::Interner;
+use owned::OwnedVal;
+
+/// Intern a name or symbol for the rest of the program.
+pub fn intern(s: &str) -> Result<&'static str, DissectError> {
+    interner().lock().unwrap().intern(s)
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static NAMES: OnceLock<Mutex<Interner>> = OnceLock::new();
+    NAMES.get_or_init(|| Mutex::new(Interner::new()))
+}
+
+pub fn name<'de, D: Deserializer<'de>>(d: D) -> Result<&'static str, D::Error> {
+    intern(&String::deserialize(d)?).map_err(D::Error::custom)
+}
+
+pub fn optional_name<'de, D: Deserializer<'de>>(d: D) -> Result<Option<&'static str>, D::Error> {
+    Option::<String>::deserialize(d)?.map(|s| intern(&s)).transpose().map_err(D::Error::custom)
+}
+
+pub fn bit_names8<'de, D: Deserializer<'de>>(d: D) -> Result<[Option<&'static str>; 8], D::Error> {
+    let names = Vec::<Option<String>>::deserialize(d)?;
+    if names.len() != 8 {
+        return Err(D::Error::invalid_length(names.len(), &"8 bit names"));
+    }
+
+    let mut interned = [None; 8];
+    for (i, name) in names.iter().enumerate() {
+        interned[i] = name.as_ref().map(|n| intern(n)).transpose().map_err(D::Error::custom)?;
+    }
+    Ok(interned)
+}
+
+pub fn bit_names<'de, D: Deserializer<'de>>(d: D)
+    -> Result<&'static [Option<&'static str>], D::Error> {
+
+    let names = Vec::<Option<String>>::deserialize(d)?;
+    if names.len() > 64 {
+        return Err(D::Error::invalid_length(names.len(), &"at most 64 bit names"));
+    }
+
+    let interned = names.iter().map(|n| n.as_ref().map(|n| intern(n)).transpose())
+        .collect::<Result<_, _>>()
+        .map_err(D::Error::custom)?;
+    interner().lock().unwrap().intern_bit_names(interned).map_err(D::Error::custom)
+}
+
+pub fn fields<'de, D: Deserializer<'de>>(d: D)
+    -> Result<Vec<(&'static str, OwnedVal)>, D::Error> {
+
+    Vec::<(String, OwnedVal)>::deserialize(d)?.into_iter()
+        .map(|(k, v)| intern(&k).map(|k| (k, v)))
+        .collect::<Result<_, _>>()
+        .map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde_json;
+
+    use DissectError;
+    use ErrorCode;
+    use Val;
+    use owned::OwnedVal;
+    use time::Time;
+
+    #[test]
+    fn round_trip() {
+        let val = Val::Object("Test", vec![
+            ("Flags", Val::BitFlags8(0x81, [Some("A"), None, None, None, None, None, None, Some("H")])),
+            ("Wide Flags", Val::BitFlags(0x100, &[None, Some("B"), None, None, None, None, None,
+                                                  None, Some("I")])),
+            ("List", Val::List(vec![Val::Enum { value: 6, name: Some("TCP") }, Val::Signed(-1)])),
+            ("Time", Val::Timestamp(Time::new(-2, 5))),
+            ("Payload", Val::Payload(&[1, 2], Err(DissectError::malformed(ErrorCode::BadMagic, "bad")))),
+            ("Payload", Val::Undissected("Unknown", &[3])),
+        ]);
+
+        let json = serde_json::to_string(&val).unwrap();
+        assert!(json.contains(r#"["Payload",{"Payload":[[1,2],{"Err":{"Malformed""#));
+
+        let owned: OwnedVal = serde_json::from_str(&json).unwrap();
+        assert_eq!(owned.as_val(), val);
+        assert_eq!(serde_json::to_string(&owned).unwrap(), json);
+
+        assert!(serde_json::from_str::<OwnedVal>(r#"{"BitFlags8":[1,[null]]}"#).is_err());
+    }
+}
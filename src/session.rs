@@ -0,0 +1,235 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Analysis sessions over a loaded capture.
+//!
+//! A `Session` reads a capture once and keeps its packets, so that when the
+//! configuration changes (a preference, a Decode-As rule or a decryption
+//! key) the packets can be dissected again without re-reading the capture,
+//! as with Wireshark's "reload". Only what the change can affect is
+//! rebuilt: the flow table depends on the network and transport layers,
+//! so it survives changes that only affect application-layer dissection.
+//!
+//! ```
+//! use rshark::session::{Change, Session};
+//! use rshark::source::{Packet, VecSource};
+//! use std::time::Duration;
+//!
+//! let packet = Packet { timestamp: Duration::new(0, 0), original_length: 14, data: vec![0; 14] };
+//! let mut source = VecSource::new(vec![packet], 1);
+//! let mut session = Session::load(&mut source, rshark::ethernet::dissect).unwrap();
+//! assert_eq!(session.stats().dissected, 1);
+//!
+//! let reload = session.redissect(Change::Keys);
+//! assert_eq!(reload.generation, 1);
+//! assert!(!reload.flows_rebuilt);
+//! ```
+
+use std::io;
+
+use DissectResult;
+use batch::{self, BatchStats};
+use flow::FlowTable;
+use source::{Packet, PacketSource};
+use tap::Taps;
+
+/// A configuration change that calls for re-dissection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// A preference, by name (e.g., "tcp.reassemble").
+    Preference(String),
+
+    /// A Decode-As rule was added or removed.
+    DecodeAs,
+
+    /// Decryption keys were added or changed.
+    Keys,
+
+    /// The dissector of the outermost layer was replaced.
+    Dissector,
+}
+
+impl Change {
+    /// Whether this change can affect how network and transport layers are
+    /// dissected (and thus which flows packets belong to).
+    pub fn affects_flows(&self) -> bool {
+        match self {
+            &Change::Preference(ref name) =>
+                ["eth.", "ip.", "ipv6.", "tcp.", "udp."].iter().any(|p| name.starts_with(p)),
+            &Change::DecodeAs | &Change::Keys => false,
+            &Change::Dissector => true,
+        }
+    }
+}
+
+/// What a re-dissection did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reload {
+    /// How many times the session has been dissected again.
+    pub generation: u64,
+    pub stats: BatchStats,
+    pub flows_rebuilt: bool,
+}
+
+pub struct Session {
+    packets: Vec<Packet>,
+    link_type: u32,
+    dissector: fn(&[u8]) -> DissectResult,
+    threads: usize,
+    stats: BatchStats,
+    flows: FlowTable,
+    generation: u64,
+}
+
+impl Session {
+    /// Read every packet from `source` and dissect them.
+    pub fn load<S>(source: &mut S, dissector: fn(&[u8]) -> DissectResult) -> io::Result<Session>
+        where S: PacketSource + ?Sized {
+
+        let mut packets = Vec::new();
+        while let Some(packet) = source.next_packet() {
+            packets.push(packet?);
+        }
+
+        let mut session = Session {
+            packets: packets,
+            link_type: source.link_type(),
+            dissector: dissector,
+            threads: 1,
+            stats: BatchStats::default(),
+            flows: FlowTable::new(),
+            generation: 0,
+        };
+
+        session.dissect(true, None);
+        Ok(session)
+    }
+
+    /// Dissect on up to `threads` threads (the default is 1).
+    pub fn threads(mut self, threads: usize) -> Session {
+        self.threads = threads;
+        self
+    }
+
+    pub fn packets(&self) -> &[Packet] {
+        &self.packets
+    }
+
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    pub fn stats(&self) -> &BatchStats {
+        &self.stats
+    }
+
+    pub fn flows(&self) -> &FlowTable {
+        &self.flows
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Dissect one packet with the current configuration.
+    pub fn dissect_packet(&self, index: usize) -> Option<DissectResult> {
+        self.packets.get(index).map(|p| (self.dissector)(&p.data))
+    }
+
+    /// Replace the dissector of the outermost layer and dissect again.
+    pub fn set_dissector(&mut self, dissector: fn(&[u8]) -> DissectResult) -> Reload {
+        self.dissector = dissector;
+        self.redissect(Change::Dissector)
+    }
+
+    /// Dissect all packets again after a configuration change.
+    pub fn redissect(&mut self, change: Change) -> Reload {
+        self.reload(change, None)
+    }
+
+    /// Dissect all packets again, passing each one to `taps` so that their
+    /// analyses reflect the new configuration.
+    pub fn redissect_with(&mut self, change: Change, taps: &mut Taps) -> Reload {
+        self.reload(change, Some(taps))
+    }
+
+    fn reload(&mut self, change: Change, taps: Option<&mut Taps>) -> Reload {
+        let rebuild = change.affects_flows();
+        self.generation += 1;
+        self.dissect(rebuild, taps);
+
+        Reload { generation: self.generation, stats: self.stats.clone(), flows_rebuilt: rebuild }
+    }
+
+    fn dissect(&mut self, track_flows: bool, mut taps: Option<&mut Taps>) {
+        let packets = &self.packets;
+        let data = packets.iter().map(|p| &p.data[..]).collect::<Vec<_>>();
+        let batch = batch::dissect_all_parallel(&data, self.dissector, self.threads);
+
+        if track_flows {
+            self.flows = FlowTable::new();
+        }
+
+        for (packet, result) in packets.iter().zip(&batch.results) {
+            if let &Ok(ref val) = result {
+                if track_flows {
+                    self.flows.track(packet.timestamp, val);
+                }
+
+                if let Some(ref mut taps) = taps {
+                    taps.dispatch(packet.timestamp, packet.original_length, val);
+                }
+            }
+        }
+
+        self.stats = batch.stats;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ethernet;
+    use ip;
+    use source::{Packet, VecSource};
+    use tap::{Counter, Taps};
+
+    #[test]
+    fn redissect_session() {
+        let udp = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                       0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+        let packets = (0..3).map(|i| Packet {
+            timestamp: Duration::new(i, 0),
+            original_length: udp.len(),
+            data: udp.clone(),
+        }).collect();
+
+        // Dissected as Ethernet, these packets have no flows.
+        let mut session = Session::load(&mut VecSource::new(packets, 1), ethernet::dissect).unwrap().threads(2);
+        assert_eq!(session.flows().len(), 0);
+
+        let reload = session.set_dissector(ip::dissect_raw);
+        assert!(reload.flows_rebuilt);
+        assert_eq!(reload.stats.dissected, 3);
+        assert_eq!(session.flows().len(), 1);
+
+        let mut counter = Counter::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("UDP", &mut counter);
+            let reload = session.redissect_with(Change::Preference("dns.port".to_string()), &mut taps);
+            assert_eq!((reload.generation, reload.flows_rebuilt), (2, false));
+        }
+
+        assert_eq!(counter.packets, 3);
+        assert_eq!(session.flows().flows()[0].orig_packets, 3);
+        assert!(session.dissect_packet(0).unwrap().is_ok());
+    }
+}
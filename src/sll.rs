@@ -0,0 +1,145 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Linux "cooked" capture pseudo-headers (SLL and SLL2).
+//!
+//! Captures on the "any" device (`tcpdump -i any`) can't use the link-layer
+//! headers of the interfaces involved, so Linux replaces them with a
+//! pseudo-header giving the packet's direction, the link-layer type and
+//! source address, and the protocol (usually an Ethertype). SLL2 also gives
+//! the index of the interface the packet was captured on.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use context::Context;
+use ethernet;
+use llc;
+use {read_be_u16, read_be_u32};
+
+enum_map!(pub PACKET_TYPES {
+    0 => "Unicast to us",
+    1 => "Broadcast",
+    2 => "Multicast",
+    3 => "Unicast to another host",
+    4 => "Sent by us",
+});
+
+// Link-layer (ARPHRD_) types
+enum_map!(pub HARDWARE_TYPES {
+    1 => "Ethernet",
+    512 => "PPP",
+    772 => "Loopback",
+    776 => "IPv6-in-IPv4",
+    778 => "GRE",
+    801 => "802.11",
+    803 => "802.11 with Radiotap",
+    824 => "Netlink",
+    65534 => "None",
+});
+
+/// Dissect a packet with an SLL (v1) pseudo-header.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_with(data, &mut Context::new())
+}
+
+/// Dissect a packet with an SLL (v1) pseudo-header, and its payload within a
+/// context.
+pub fn dissect_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 16 {
+        return Err(DissectError::Underflow { expected: Some(16), have: data.len(),
+            message: "An SLL header must be 16 B".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+    let packet_type = read_be_u16(data, 0)?;
+    let hardware_type = read_be_u16(data, 2)?;
+    let address_length = read_be_u16(data, 4)? as usize;
+    let protocol = read_be_u16(data, 14)?;
+
+    values.push(("Packet Type", PACKET_TYPES.val(packet_type)));
+    values.push(("Hardware Type", HARDWARE_TYPES.val(hardware_type)));
+    values.push(("Address Length", Val::Unsigned(address_length as u64)));
+    values.push(("Source", Val::Bytes(&data[6..6 + address_length.min(8)])));
+    values.push(("Protocol", Val::Unsigned(protocol as u64)));
+    values.push(("Payload", payload(hardware_type, protocol, &data[16..], context)));
+
+    Ok(Box::new(Val::Object("Linux cooked capture", values)))
+}
+
+/// Dissect a packet with an SLL2 pseudo-header.
+pub fn dissect_v2(data : &[u8]) -> DissectResult {
+    dissect_v2_with(data, &mut Context::new())
+}
+
+/// Dissect a packet with an SLL2 pseudo-header, and its payload within a
+/// context.
+pub fn dissect_v2_with<'data>(data: &'data [u8], context: &mut Context) -> DissectResult<'data> {
+    if data.len() < 20 {
+        return Err(DissectError::Underflow { expected: Some(20), have: data.len(),
+            message: "An SLL2 header must be 20 B".to_string() });
+    }
+
+    let mut values = NamedValues::new();
+    let protocol = read_be_u16(data, 0)?;
+    let hardware_type = read_be_u16(data, 8)?;
+    let address_length = data[11] as usize;
+
+    values.push(("Protocol", Val::Unsigned(protocol as u64)));
+    values.push(("Interface Index", Val::Unsigned(read_be_u32(data, 4)? as u64)));
+    values.push(("Hardware Type", HARDWARE_TYPES.val(hardware_type)));
+    values.push(("Packet Type", PACKET_TYPES.val(data[10])));
+    values.push(("Address Length", Val::Unsigned(address_length as u64)));
+    values.push(("Source", Val::Bytes(&data[12..12 + address_length.min(8)])));
+    values.push(("Payload", payload(hardware_type, protocol, &data[20..], context)));
+
+    Ok(Box::new(Val::Object("Linux cooked capture v2", values)))
+}
+
+/// The protocol field is an Ethertype, except for a few values that stand for
+/// frames without one.
+fn payload<'data>(hardware_type: u16, protocol: u16, data: &'data [u8], context: &mut Context)
+    -> Val<'data> {
+
+    match (hardware_type, protocol) {
+        (824, _) => Val::Undissected("Netlink", data),
+        (_, 0x0001) => Val::Undissected("Novell 802.3", data),
+        (_, 0x0004) => Val::Payload(data, context.dissect(llc::dissect_with, data)),
+        (_, 0x000c) => Val::Undissected("CAN", data),
+        _ => ethernet::dissect_ethertype_with(protocol, data, context),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_sll() {
+        let ip = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+
+        let mut v1 = vec![0x00, 0x04, 0x00, 0x01, 0x00, 0x06, 0x02, 0, 0, 0, 0, 1, 0, 0, 0x08, 0x00];
+        v1.extend_from_slice(&ip);
+        let val = *dissect(&v1).unwrap();
+        assert_eq!(val["Packet Type"].as_symbol().unwrap(), "Sent by us");
+        assert_eq!(val["Source"].as_bytes().unwrap(), &[0x02, 0, 0, 0, 0, 1]);
+        assert_eq!(val["Payload"]["Destination"].as_address_encoded().unwrap(), "10.0.0.2");
+
+        let mut v2 = vec![0x08, 0x00, 0, 0, 0, 0, 0, 3, 0x00, 0x01, 0x00, 0x06,
+                          0x02, 0, 0, 0, 0, 1, 0, 0];
+        v2.extend_from_slice(&ip);
+        let val = *dissect_v2(&v2).unwrap();
+        assert_eq!(val["Interface Index"].as_unsigned().unwrap(), 3);
+        assert_eq!(val["Packet Type"].as_symbol().unwrap(), "Unicast to us");
+        assert_eq!(val["Payload"]["Source"].as_address_encoded().unwrap(), "10.0.0.1");
+
+        assert!(dissect_v2(&v2[..19]).is_err());
+    }
+}
@@ -0,0 +1,449 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Server Message Block (SMB) version 2 and 3 messages.
+//!
+//! SMB2 messages are carried over TCP port 445 inside a four-byte session
+//! header (see `netbios::dissect_session`). Compound requests and responses are
+//! represented as a chain of "Next Command" payloads.
+//!
+//! See [MS-SMB2](https://msdn.microsoft.com/en-us/library/cc246482.aspx).
+
+use Endianness;
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use Val;
+use NamedValues;
+use unsigned;
+
+/// Dissect an SMB2 message, starting at the 64 B SMB2 header.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 64 {
+        return Err(DissectError::Underflow { expected: Some(64), have: data.len(),
+            message: "An SMB2 message must be at least 64 B".to_string() })
+    }
+
+    if &data[0..4] != b"\xfeSMB" {
+        return Err(DissectError::malformed(ErrorCode::BadMagic, "SMB2 protocol ID is not 0xFE 'SMB'"));
+    }
+
+    let mut values = NamedValues::new();
+
+    values.push(("Header Length", Val::Unsigned(le(data, 4, 2)?)));
+    values.push(("Credit Charge", Val::Unsigned(le(data, 6, 2)?)));
+
+    let flags = le(data, 16, 4)?;
+    let response = flags & 0x01 != 0;
+
+    // The status field is only a status in responses; in SMB 3.x requests it
+    // holds a channel sequence number.
+    let status = le(data, 8, 4)?;
+    if response {
+        values.push(("NT Status", Val::Unsigned(status)));
+        values.push(("NT Status Name", Val::Symbol(nt_status(status as u32))));
+    } else {
+        values.push(("Channel Sequence", Val::Unsigned(status & 0xffff)));
+    }
+
+    let command = le(data, 12, 2)?;
+    values.push(("Command", Val::Unsigned(command)));
+    values.push(("Command Name", Val::Symbol(command_name(command))));
+
+    values.push((if response { "Credits Granted" } else { "Credits Requested" },
+                 Val::Unsigned(le(data, 14, 2)?)));
+    values.push(("Flags", Val::Unsigned(flags)));
+    values.push(("Response", Val::Unsigned(response as u64)));
+
+    let next_command = le(data, 20, 4)? as usize;
+    values.push(("Next Command", Val::Unsigned(next_command as u64)));
+    values.push(("Message ID", Val::Unsigned(le(data, 24, 8)?)));
+
+    if flags & 0x02 != 0 {
+        values.push(("Async ID", Val::Unsigned(le(data, 32, 8)?)));
+    } else {
+        values.push(("Tree ID", Val::Unsigned(le(data, 36, 4)?)));
+    }
+
+    values.push(("Session ID", Val::Unsigned(le(data, 40, 8)?)));
+    values.push(("Signature", Val::Bytes(&data[48..64])));
+
+    let end = if next_command >= 64 && next_command <= data.len() { next_command } else { data.len() };
+    let message = &data[..end];
+
+    // Error responses have their own (uninteresting) body format.
+    let body = if response && status != 0 && status != STATUS_MORE_PROCESSING_REQUIRED {
+        Ok(raw_body(message))
+    } else {
+        match (command, response) {
+            (0x00, false) => negotiate_request(message),
+            (0x00, true) => negotiate_response(message),
+            (0x01, false) => session_setup_request(message),
+            (0x01, true) => session_setup_response(message),
+            (0x03, false) => tree_connect_request(message),
+            (0x03, true) => tree_connect_response(message),
+            (0x05, false) => create_request(message),
+            (0x05, true) => create_response(message),
+            (0x08, false) => read_request(message),
+            (0x08, true) => read_response(message),
+            (0x09, false) => write_request(message),
+            (0x09, true) => write_response(message),
+            _ => Ok(raw_body(message)),
+        }
+    };
+
+    values.push(("Body", Val::Payload(message, body.map(Box::new))));
+
+    if end < data.len() {
+        values.push(("Next", Val::Payload(&data[end..], dissect(&data[end..]))));
+    }
+
+    Ok(Box::new(Val::Object("SMB2", values)))
+}
+
+const STATUS_MORE_PROCESSING_REQUIRED: u64 = 0xc0000016;
+
+/// Read a little-endian integer at `offset`, or fail with an underflow.
+fn le(data: &[u8], offset: usize, len: usize) -> Result<u64, DissectError> {
+    if offset + len > data.len() {
+        return Err(DissectError::Underflow { expected: Some(offset + len), have: data.len(),
+            message: format!["SMB2 field at offset {} needs {} B", offset, len] });
+    }
+
+    unsigned(&data[offset..offset + len], Endianness::LittleEndian)
+}
+
+/// Slice a buffer addressed by an offset (from the start of the SMB2 header)
+/// and length.
+fn buffer(data: &[u8], offset: u64, length: u64) -> Result<&[u8], DissectError> {
+    let (offset, length) = (offset as usize, length as usize);
+    if offset + length > data.len() {
+        return Err(DissectError::Underflow { expected: Some(offset + length), have: data.len(),
+            message: "SMB2 buffer extends past the end of the message".to_string() });
+    }
+
+    Ok(&data[offset..offset + length])
+}
+
+fn utf16le(bytes: &[u8]) -> String {
+    let units = bytes.chunks(2)
+        .filter(|c| c.len() == 2)
+        .map(|c| c[0] as u16 | (c[1] as u16) << 8)
+        .collect::<Vec<_>>();
+
+    String::from_utf16_lossy(&units)
+}
+
+fn raw_body(data: &[u8]) -> Val {
+    let mut values = NamedValues::new();
+    values.push(("Data", Val::Bytes(&data[64..])));
+    Val::Object("Body", values)
+}
+
+fn negotiate_request(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    let count = le(data, 66, 2)?;
+    values.push(("Dialect Count", Val::Unsigned(count)));
+    values.push(("Security Mode", Val::Unsigned(le(data, 68, 2)?)));
+    values.push(("Capabilities", Val::Unsigned(le(data, 72, 4)?)));
+    values.push(("Client GUID", Val::Bytes(buffer(data, 76, 16)?)));
+
+    for i in 0..count as usize {
+        values.push(("Dialect", Val::Unsigned(le(data, 100 + 2 * i, 2)?)));
+    }
+
+    Ok(Val::Object("Negotiate Request", values))
+}
+
+fn negotiate_response(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Security Mode", Val::Unsigned(le(data, 66, 2)?)));
+    values.push(("Dialect", Val::Unsigned(le(data, 68, 2)?)));
+    values.push(("Server GUID", Val::Bytes(buffer(data, 72, 16)?)));
+    values.push(("Capabilities", Val::Unsigned(le(data, 88, 4)?)));
+    values.push(("Max Transact Size", Val::Unsigned(le(data, 92, 4)?)));
+    values.push(("Max Read Size", Val::Unsigned(le(data, 96, 4)?)));
+    values.push(("Max Write Size", Val::Unsigned(le(data, 100, 4)?)));
+    values.push(("System Time", Val::Unsigned(le(data, 104, 8)?)));
+
+    let blob = buffer(data, le(data, 120, 2)?, le(data, 122, 2)?)?;
+    values.push(("Security Blob", Val::Bytes(blob)));
+
+    Ok(Val::Object("Negotiate Response", values))
+}
+
+fn session_setup_request(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Flags", Val::Unsigned(le(data, 66, 1)?)));
+    values.push(("Security Mode", Val::Unsigned(le(data, 67, 1)?)));
+    values.push(("Capabilities", Val::Unsigned(le(data, 68, 4)?)));
+    values.push(("Previous Session ID", Val::Unsigned(le(data, 80, 8)?)));
+
+    let blob = buffer(data, le(data, 76, 2)?, le(data, 78, 2)?)?;
+    values.push(("Security Blob", Val::Bytes(blob)));
+
+    Ok(Val::Object("Session Setup Request", values))
+}
+
+fn session_setup_response(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Session Flags", Val::Unsigned(le(data, 66, 2)?)));
+
+    let blob = buffer(data, le(data, 68, 2)?, le(data, 70, 2)?)?;
+    values.push(("Security Blob", Val::Bytes(blob)));
+
+    Ok(Val::Object("Session Setup Response", values))
+}
+
+fn tree_connect_request(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    let path = buffer(data, le(data, 68, 2)?, le(data, 70, 2)?)?;
+    values.push(("Path", Val::String(utf16le(path))));
+
+    Ok(Val::Object("Tree Connect Request", values))
+}
+
+fn tree_connect_response(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    let share_type = le(data, 66, 1)?;
+    values.push(("Share Type", Val::Unsigned(share_type)));
+    values.push(("Share Type Name", Val::Symbol(match share_type {
+        1 => "Disk",
+        2 => "Named pipe",
+        3 => "Printer",
+        _ => "Unknown",
+    })));
+    values.push(("Share Flags", Val::Unsigned(le(data, 68, 4)?)));
+    values.push(("Capabilities", Val::Unsigned(le(data, 72, 4)?)));
+    values.push(("Maximal Access", Val::Unsigned(le(data, 76, 4)?)));
+
+    Ok(Val::Object("Tree Connect Response", values))
+}
+
+fn create_request(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Oplock Level", Val::Unsigned(le(data, 67, 1)?)));
+    values.push(("Impersonation Level", Val::Unsigned(le(data, 68, 4)?)));
+    values.push(("Desired Access", Val::Unsigned(le(data, 88, 4)?)));
+    values.push(("File Attributes", Val::Unsigned(le(data, 92, 4)?)));
+    values.push(("Share Access", Val::Unsigned(le(data, 96, 4)?)));
+    values.push(("Create Disposition", Val::Unsigned(le(data, 100, 4)?)));
+    values.push(("Create Options", Val::Unsigned(le(data, 104, 4)?)));
+
+    let name = buffer(data, le(data, 108, 2)?, le(data, 110, 2)?)?;
+    values.push(("File Name", Val::String(utf16le(name))));
+
+    Ok(Val::Object("Create Request", values))
+}
+
+fn create_response(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Oplock Level", Val::Unsigned(le(data, 66, 1)?)));
+    values.push(("Create Action", Val::Unsigned(le(data, 68, 4)?)));
+    values.push(("Creation Time", Val::Unsigned(le(data, 72, 8)?)));
+    values.push(("Last Access Time", Val::Unsigned(le(data, 80, 8)?)));
+    values.push(("Last Write Time", Val::Unsigned(le(data, 88, 8)?)));
+    values.push(("Change Time", Val::Unsigned(le(data, 96, 8)?)));
+    values.push(("Allocation Size", Val::Unsigned(le(data, 104, 8)?)));
+    values.push(("End of File", Val::Unsigned(le(data, 112, 8)?)));
+    values.push(("File Attributes", Val::Unsigned(le(data, 120, 4)?)));
+    values.push(("File ID", Val::Bytes(buffer(data, 128, 16)?)));
+
+    Ok(Val::Object("Create Response", values))
+}
+
+fn read_request(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Length", Val::Unsigned(le(data, 68, 4)?)));
+    values.push(("Offset", Val::Unsigned(le(data, 72, 8)?)));
+    values.push(("File ID", Val::Bytes(buffer(data, 80, 16)?)));
+    values.push(("Minimum Count", Val::Unsigned(le(data, 96, 4)?)));
+
+    Ok(Val::Object("Read Request", values))
+}
+
+fn read_response(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    let length = le(data, 68, 4)?;
+    values.push(("Data Length", Val::Unsigned(length)));
+    values.push(("Data Remaining", Val::Unsigned(le(data, 72, 4)?)));
+    values.push(("Data", Val::Bytes(buffer(data, le(data, 66, 1)?, length)?)));
+
+    Ok(Val::Object("Read Response", values))
+}
+
+fn write_request(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    let length = le(data, 68, 4)?;
+    values.push(("Length", Val::Unsigned(length)));
+    values.push(("Offset", Val::Unsigned(le(data, 72, 8)?)));
+    values.push(("File ID", Val::Bytes(buffer(data, 80, 16)?)));
+    values.push(("Data", Val::Bytes(buffer(data, le(data, 66, 2)?, length)?)));
+
+    Ok(Val::Object("Write Request", values))
+}
+
+fn write_response(data: &[u8]) -> Result<Val, DissectError> {
+    let mut values = NamedValues::new();
+
+    values.push(("Count", Val::Unsigned(le(data, 68, 4)?)));
+    values.push(("Remaining", Val::Unsigned(le(data, 72, 4)?)));
+
+    Ok(Val::Object("Write Response", values))
+}
+
+fn command_name(command: u64) -> &'static str {
+    match command {
+        0x00 => "NEGOTIATE",
+        0x01 => "SESSION_SETUP",
+        0x02 => "LOGOFF",
+        0x03 => "TREE_CONNECT",
+        0x04 => "TREE_DISCONNECT",
+        0x05 => "CREATE",
+        0x06 => "CLOSE",
+        0x07 => "FLUSH",
+        0x08 => "READ",
+        0x09 => "WRITE",
+        0x0a => "LOCK",
+        0x0b => "IOCTL",
+        0x0c => "CANCEL",
+        0x0d => "ECHO",
+        0x0e => "QUERY_DIRECTORY",
+        0x0f => "CHANGE_NOTIFY",
+        0x10 => "QUERY_INFO",
+        0x11 => "SET_INFO",
+        0x12 => "OPLOCK_BREAK",
+        _ => "Unknown",
+    }
+}
+
+/// Names of common NT status codes.
+///
+/// See [MS-ERREF](https://msdn.microsoft.com/en-us/library/cc704588.aspx).
+pub fn nt_status(status: u32) -> &'static str {
+    match status {
+        0x00000000 => "STATUS_SUCCESS",
+        0x00000103 => "STATUS_PENDING",
+        0x0000010b => "STATUS_NOTIFY_CLEANUP",
+        0x0000010c => "STATUS_NOTIFY_ENUM_DIR",
+        0x80000005 => "STATUS_BUFFER_OVERFLOW",
+        0x80000006 => "STATUS_NO_MORE_FILES",
+        0xc0000001 => "STATUS_UNSUCCESSFUL",
+        0xc0000002 => "STATUS_NOT_IMPLEMENTED",
+        0xc0000003 => "STATUS_INVALID_INFO_CLASS",
+        0xc000000d => "STATUS_INVALID_PARAMETER",
+        0xc000000f => "STATUS_NO_SUCH_FILE",
+        0xc0000010 => "STATUS_INVALID_DEVICE_REQUEST",
+        0xc0000011 => "STATUS_END_OF_FILE",
+        0xc0000016 => "STATUS_MORE_PROCESSING_REQUIRED",
+        0xc0000022 => "STATUS_ACCESS_DENIED",
+        0xc0000023 => "STATUS_BUFFER_TOO_SMALL",
+        0xc0000033 => "STATUS_OBJECT_NAME_INVALID",
+        0xc0000034 => "STATUS_OBJECT_NAME_NOT_FOUND",
+        0xc0000035 => "STATUS_OBJECT_NAME_COLLISION",
+        0xc000003a => "STATUS_OBJECT_PATH_NOT_FOUND",
+        0xc0000043 => "STATUS_SHARING_VIOLATION",
+        0xc0000054 => "STATUS_FILE_LOCK_CONFLICT",
+        0xc0000056 => "STATUS_DELETE_PENDING",
+        0xc0000061 => "STATUS_PRIVILEGE_NOT_HELD",
+        0xc0000064 => "STATUS_NO_SUCH_USER",
+        0xc000006a => "STATUS_WRONG_PASSWORD",
+        0xc000006d => "STATUS_LOGON_FAILURE",
+        0xc000006e => "STATUS_ACCOUNT_RESTRICTION",
+        0xc000006f => "STATUS_INVALID_LOGON_HOURS",
+        0xc0000071 => "STATUS_PASSWORD_EXPIRED",
+        0xc0000072 => "STATUS_ACCOUNT_DISABLED",
+        0xc00000ba => "STATUS_FILE_IS_A_DIRECTORY",
+        0xc00000bb => "STATUS_NOT_SUPPORTED",
+        0xc00000c9 => "STATUS_NETWORK_NAME_DELETED",
+        0xc00000cc => "STATUS_BAD_NETWORK_NAME",
+        0xc0000101 => "STATUS_DIRECTORY_NOT_EMPTY",
+        0xc0000103 => "STATUS_NOT_A_DIRECTORY",
+        0xc0000120 => "STATUS_CANCELLED",
+        0xc0000128 => "STATUS_FILE_CLOSED",
+        0xc0000193 => "STATUS_ACCOUNT_EXPIRED",
+        0xc0000203 => "STATUS_USER_SESSION_DELETED",
+        0xc0000224 => "STATUS_PASSWORD_MUST_CHANGE",
+        0xc0000234 => "STATUS_ACCOUNT_LOCKED_OUT",
+        0xc0000257 => "STATUS_PATH_NOT_COVERED",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use netbios;
+
+    fn header(command: u8, flags: u8, status: u32, next: u8) -> Vec<u8> {
+        let mut h = vec![0xfe, b'S', b'M', b'B', 64, 0, 1, 0,
+                         status as u8, (status >> 8) as u8, (status >> 16) as u8, (status >> 24) as u8,
+                         command, 0, 1, 0, flags, 0, 0, 0, next, 0, 0, 0,
+                         7, 0, 0, 0, 0, 0, 0, 0,
+                         0, 0, 0, 0, 5, 0, 0, 0,
+                         0x11, 0x22, 0, 0, 0, 0, 0, 0];
+        h.extend(vec![0; 16]);
+        h
+    }
+
+    #[test]
+    fn dissect_tree_connect() {
+        let mut msg = header(0x03, 0, 0, 0);
+        msg.extend(vec![9, 0, 0, 0, 72, 0, 12, 0]);
+        msg.extend(b"\\\0\\\0h\0o\0s\0t\0".iter().cloned());
+
+        let mut data = vec![0, 0, 0, msg.len() as u8];
+        data.extend(msg);
+
+        let val = *netbios::dissect_session(&data).unwrap();
+        println!("{}", &val.pretty_print(0));
+
+        let smb = &val["Payload"];
+        assert_eq!(smb["Command Name"].as_symbol().unwrap(), "TREE_CONNECT");
+        assert_eq!(smb["Message ID"].as_unsigned().unwrap(), 7);
+        assert_eq!(smb["Tree ID"].as_unsigned().unwrap(), 5);
+        assert_eq!(smb["Session ID"].as_unsigned().unwrap(), 0x2211);
+        assert_eq!(smb["Body"]["Path"].as_string().unwrap(), "\\\\host");
+    }
+
+    #[test]
+    fn dissect_error_response() {
+        let mut msg = header(0x05, 0x01, 0xc0000034, 0);
+        msg.extend(vec![9, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let val = *dissect(&msg).unwrap();
+        assert_eq!(val["Response"].as_unsigned().unwrap(), 1);
+        assert_eq!(val["NT Status Name"].as_symbol().unwrap(), "STATUS_OBJECT_NAME_NOT_FOUND");
+        assert!(val["Body"]["Data"].is_bytes());
+    }
+
+    #[test]
+    fn dissect_compound() {
+        let mut msg = header(0x05, 0, 0, 128);
+        msg.extend(vec![0; 64]);
+        msg.extend(header(0x06, 0x04, 0, 0));
+        msg.extend(vec![24, 0]);
+
+        let val = *dissect(&msg).unwrap();
+        assert_eq!(val["Command Name"].as_symbol().unwrap(), "CREATE");
+        assert_eq!(val["Body"]["File Name"].as_string().unwrap(), "");
+        assert_eq!(val["Next"]["Command Name"].as_symbol().unwrap(), "CLOSE");
+    }
+}
@@ -0,0 +1,254 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Snapshots of the configuration that an analysis was run with.
+//!
+//! Two analysts dissecting the same capture can get different results from
+//! different rshark versions, dissectors, preferences or plugins. A
+//! `ConfigSnapshot` records all of these; exporting it along with the
+//! results (as a JSON record or a pcapng section comment) makes an analysis
+//! reproducible and its discrepancies with another one explainable.
+//!
+//! ```
+//! use rshark::snapshot::ConfigSnapshot;
+//!
+//! let mine = ConfigSnapshot::current().preference("tcp.reassemble", "true");
+//! let theirs = ConfigSnapshot::current().preference("tcp.reassemble", "false");
+//!
+//! let mut file = Vec::new();
+//! mine.write_pcapng_header(&mut file).unwrap();
+//! let loaded = ConfigSnapshot::from_pcapng(&file).unwrap().unwrap();
+//!
+//! assert_eq!(loaded, mine);
+//! assert_eq!(loaded.differences(&theirs),
+//!            vec!["preference tcp.reassemble: true vs. false"]);
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::Write;
+
+use DissectError;
+use output::json_string;
+use pcapng;
+
+/// The dissectors built into this version of rshark.
+pub static DISSECTORS: &'static [&'static str] = &[
+    "6LoWPAN", "AH", "AMQP", "BitTorrent DHT", "BitTorrent Tracker", "BitTorrent", "CAN", "CBOR",
+    "DCCP", "DNS", "EAPOL", "ERSPAN", "ESP", "Ethernet", "GENEVE", "GRE", "HTTP", "HTTP/2", "ICMP",
+    "ICMPv6", "IEEE 802.11", "IEEE 802.15.4", "IGMP", "IPv4", "IPv6", "JSON", "LLC", "MPLS",
+    "MessagePack", "MySQL", "NFS", "NetBIOS", "Null/Loopback", "ONC-RPC", "PPP", "Protobuf", "RTMP",
+    "RTP", "RTSP", "Radiotap", "S7comm", "SCTP", "SDP", "SLL", "SMB2", "SOCKS", "SSDP", "STP",
+    "TCP", "TLS", "Thrift", "UDP", "UDP-Lite", "USB", "VXLAN", "XML", "ZMTP", "gRPC",
+];
+
+/// The first line of a snapshot's text form.
+const HEADER: &'static str = "rshark configuration";
+
+/// The configuration an analysis was run with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigSnapshot {
+    /// The rshark version.
+    pub version: String,
+
+    /// A fingerprint of the dissector registry, which changes whenever a
+    /// dissector is added or removed.
+    pub registry: String,
+
+    pub dissectors: Vec<String>,
+    pub preferences: BTreeMap<String, String>,
+    pub plugins: Vec<String>,
+}
+
+impl ConfigSnapshot {
+    /// The configuration of this build, with no preferences or plugins.
+    pub fn current() -> ConfigSnapshot {
+        let dissectors = DISSECTORS.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+
+        ConfigSnapshot {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            registry: fingerprint(&dissectors),
+            dissectors: dissectors,
+            preferences: BTreeMap::new(),
+            plugins: Vec::new(),
+        }
+    }
+
+    pub fn preference(mut self, key: &str, value: &str) -> ConfigSnapshot {
+        self.preferences.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn plugin(mut self, name: &str) -> ConfigSnapshot {
+        self.plugins.push(name.to_string());
+        self
+    }
+
+    /// Human-readable explanations of how this configuration differs from
+    /// another.
+    pub fn differences(&self, other: &ConfigSnapshot) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        if self.version != other.version {
+            differences.push(format!["version: {} vs. {}", self.version, other.version]);
+        }
+
+        if self.registry != other.registry {
+            differences.push(format!["dissector registry: {} vs. {}", self.registry, other.registry]);
+            for d in self.dissectors.iter().filter(|d| !other.dissectors.contains(d)) {
+                differences.push(format!["dissector {}: only in the first", d]);
+            }
+            for d in other.dissectors.iter().filter(|d| !self.dissectors.contains(d)) {
+                differences.push(format!["dissector {}: only in the second", d]);
+            }
+        }
+
+        let keys = self.preferences.keys().chain(other.preferences.keys()).collect::<BTreeSet<_>>();
+        for key in keys {
+            let (a, b) = (self.preferences.get(key), other.preferences.get(key));
+            if a != b {
+                differences.push(format!["preference {}: {} vs. {}", key,
+                                         a.map(|s| s.as_str()).unwrap_or("(unset)"),
+                                         b.map(|s| s.as_str()).unwrap_or("(unset)")]);
+            }
+        }
+
+        for p in self.plugins.iter().filter(|p| !other.plugins.contains(p)) {
+            differences.push(format!["plugin {}: only in the first", p]);
+        }
+        for p in other.plugins.iter().filter(|p| !self.plugins.contains(p)) {
+            differences.push(format!["plugin {}: only in the second", p]);
+        }
+
+        differences
+    }
+
+    /// A JSON object, for inclusion in JSON exports.
+    pub fn to_json(&self) -> String {
+        let list = |items: &[String]| items.iter().map(|i| json_string(i)).collect::<Vec<_>>().join(",");
+
+        format!["{{\"version\":{},\"registry\":{},\"dissectors\":[{}],\"preferences\":{{{}}},\"plugins\":[{}]}}",
+                json_string(&self.version), json_string(&self.registry), list(&self.dissectors),
+                self.preferences.iter()
+                    .map(|(k, v)| format!["{}:{}", json_string(k), json_string(v)])
+                    .collect::<Vec<_>>().join(","),
+                list(&self.plugins)]
+    }
+
+    /// A line-oriented text form, as stored in pcapng comments.
+    pub fn to_text(&self) -> String {
+        let mut text = format!["{}\nversion {}\nregistry {}\n", HEADER, self.version, self.registry];
+        for d in &self.dissectors {
+            text += &format!["dissector {}\n", d];
+        }
+        for (k, v) in &self.preferences {
+            text += &format!["preference {}={}\n", k, v];
+        }
+        for p in &self.plugins {
+            text += &format!["plugin {}\n", p];
+        }
+
+        text
+    }
+
+    /// Parse the text form.
+    pub fn from_text(text: &str) -> Result<ConfigSnapshot, DissectError> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(DissectError::InvalidData("not an rshark configuration snapshot".to_string()));
+        }
+
+        let mut snapshot = ConfigSnapshot {
+            version: String::new(),
+            registry: String::new(),
+            dissectors: Vec::new(),
+            preferences: BTreeMap::new(),
+            plugins: Vec::new(),
+        };
+
+        for line in lines.filter(|l| !l.is_empty()) {
+            let mut parts = line.splitn(2, ' ');
+            let (key, value) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+            match key {
+                "version" => snapshot.version = value.to_string(),
+                "registry" => snapshot.registry = value.to_string(),
+                "dissector" => snapshot.dissectors.push(value.to_string()),
+                "plugin" => snapshot.plugins.push(value.to_string()),
+                "preference" => {
+                    let mut kv = value.splitn(2, '=');
+                    snapshot.preferences.insert(kv.next().unwrap_or("").to_string(),
+                                                kv.next().unwrap_or("").to_string());
+                },
+                _ => return Err(DissectError::InvalidData(
+                    format!["invalid configuration snapshot line: '{}'", line])),
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Write a pcapng Section Header Block that records this snapshot in
+    /// a comment.
+    pub fn write_pcapng_header<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        pcapng::write_section_header_with_comments(out, &[&self.to_text()])
+    }
+
+    /// The snapshot recorded in the first section header of a pcapng
+    /// file, if any.
+    pub fn from_pcapng(data: &[u8]) -> Result<Option<ConfigSnapshot>, DissectError> {
+        let header = match pcapng::blocks(data).next() {
+            Some(block) => block?,
+            None => return Ok(None),
+        };
+
+        for comment in pcapng::section_comments(&header)? {
+            if comment.starts_with(HEADER) {
+                return ConfigSnapshot::from_text(&comment).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// A 32-bit FNV-1a hash of the names of the dissectors, in hex.
+fn fingerprint(dissectors: &[String]) -> String {
+    let mut hash: u32 = 0x811c9dc5;
+    for d in dissectors {
+        for &b in d.as_bytes().iter().chain(b"\n") {
+            hash = (hash ^ b as u32).wrapping_mul(0x01000193);
+        }
+    }
+
+    format!["{:08x}", hash]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snapshot_differences() {
+        let a = ConfigSnapshot::current().preference("tls.keylog", "keys.txt").plugin("modbus");
+        let mut b = ConfigSnapshot::current();
+        b.dissectors.push("Gopher".to_string());
+        b.registry = fingerprint(&b.dissectors);
+
+        assert_eq!(ConfigSnapshot::from_text(&a.to_text()).unwrap(), a);
+        assert_eq!(a.differences(&b), vec![
+            format!["dissector registry: {} vs. {}", a.registry, b.registry],
+            "dissector Gopher: only in the second".to_string(),
+            "preference tls.keylog: keys.txt vs. (unset)".to_string(),
+            "plugin modbus: only in the first".to_string(),
+        ]);
+
+        assert!(a.to_json().ends_with(",\"preferences\":{\"tls.keylog\":\"keys.txt\"},\"plugins\":[\"modbus\"]}"));
+    }
+}
@@ -0,0 +1,339 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of SOCKS 4, 4a and 5 proxy handshakes, on TCP port 1080.
+//!
+//! A SOCKS client names its target (an address or host name, and a port)
+//! in a CONNECT request; SOCKS 5 clients first offer authentication methods
+//! and may authenticate with a username and password. Once the proxy has
+//! replied, the connection carries the target protocol, which can't be
+//! recognized from the proxy's port. `Tunnels` is a tap that records the
+//! target of each proxied connection, so that what follows the handshake
+//! can be dissected as if it had been sent to the target directly.
+//!
+//! See [RFC 1928](https://tools.ietf.org/html/rfc1928),
+//! [RFC 1929](https://tools.ietf.org/html/rfc1929) and the
+//! [SOCKS 4 protocol](https://www.openssh.com/txt/socks4.protocol).
+
+use std::collections::HashMap;
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use flow::FlowKey;
+use ip::tcp;
+use read_be_u16;
+use tap::{PacketInfo, Tap};
+
+enum_map!(pub COMMANDS {
+    1 => "CONNECT",
+    2 => "BIND",
+    3 => "UDP ASSOCIATE",
+});
+
+enum_map!(pub METHODS {
+    0 => "No Authentication",
+    1 => "GSSAPI",
+    2 => "Username/Password",
+    0xff => "No Acceptable Methods",
+});
+
+enum_map!(pub V4_REPLIES {
+    90 => "Granted",
+    91 => "Rejected",
+    92 => "Rejected: No identd",
+    93 => "Rejected: identd Mismatch",
+});
+
+enum_map!(pub V5_REPLIES {
+    0 => "Succeeded",
+    1 => "General Failure",
+    2 => "Not Allowed by Ruleset",
+    3 => "Network Unreachable",
+    4 => "Host Unreachable",
+    5 => "Connection Refused",
+    6 => "TTL Expired",
+    7 => "Command Not Supported",
+    8 => "Address Type Not Supported",
+});
+
+pub const PORT: u16 = 1080;
+
+const CONNECT: u8 = 1;
+
+/// Dissect a message from a SOCKS client.
+pub fn dissect_request(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+
+    match data.first() {
+        Some(&4) if data.len() >= 9 && data.last() == Some(&0) => {
+            values.push(("Version", Val::Unsigned(4)));
+            values.push(("Message Type", Val::Symbol("Request")));
+            values.push(("Command", COMMANDS.val(data[1])));
+            values.push(("Port", Val::Unsigned(read_be_u16(data, 2)? as u64)));
+
+            let mut strings = data[8..].split(|&b| b == 0);
+            let user = strings.next().unwrap_or(&[]);
+            values.push(("User ID", Val::String(String::from_utf8_lossy(user).into_owned())));
+
+            // SOCKS 4a: an address of 0.0.0.x (x != 0) means a host name follows.
+            if data[4..7] == [0, 0, 0] && data[7] != 0 {
+                let host = strings.next().unwrap_or(&[]);
+                values.push(("Host Name", Val::String(String::from_utf8_lossy(host).into_owned())));
+            } else {
+                values.push(("Address", ipv4(&data[4..8])));
+            }
+        },
+        Some(&5) if data.len() >= 2 && data.len() == 2 + data[1] as usize => {
+            values.push(("Version", Val::Unsigned(5)));
+            values.push(("Message Type", Val::Symbol("Method Selection")));
+            for &method in &data[2..] {
+                values.push(("Method", METHODS.val(method)));
+            }
+        },
+        Some(&5) if data.len() >= 4 => {
+            values.push(("Version", Val::Unsigned(5)));
+            values.push(("Message Type", Val::Symbol("Request")));
+            values.push(("Command", COMMANDS.val(data[1])));
+            address(&data[3..], &mut values)?;
+        },
+        Some(&1) if data.len() >= 2 => {
+            let user_end = 2 + data[1] as usize;
+            let user = field(data, 2, data[1] as usize)?;
+            let password_length = *field(data, user_end, 1)?.first().unwrap_or(&0) as usize;
+            let password = field(data, user_end + 1, password_length)?;
+
+            values.push(("Version", Val::Unsigned(1)));
+            values.push(("Message Type", Val::Symbol("Username/Password Authentication")));
+            values.push(("Username", Val::String(String::from_utf8_lossy(user).into_owned())));
+            values.push(("Password", Val::String(String::from_utf8_lossy(password).into_owned())));
+        },
+        _ => values.push(("Tunneled Data", Val::Undissected("SOCKS tunnel", data))),
+    }
+
+    Ok(Box::new(Val::Object("SOCKS", values)))
+}
+
+/// Dissect a message from a SOCKS proxy.
+pub fn dissect_response(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+
+    match (data.first(), data.len()) {
+        (Some(&0), 8) => {
+            values.push(("Version", Val::Unsigned(4)));
+            values.push(("Message Type", Val::Symbol("Reply")));
+            values.push(("Reply", V4_REPLIES.val(data[1])));
+            values.push(("Port", Val::Unsigned(read_be_u16(data, 2)? as u64)));
+            values.push(("Address", ipv4(&data[4..8])));
+        },
+        (Some(&5), 2) => {
+            values.push(("Version", Val::Unsigned(5)));
+            values.push(("Message Type", Val::Symbol("Method Selection")));
+            values.push(("Method", METHODS.val(data[1])));
+        },
+        (Some(&5), n) if n >= 4 => {
+            values.push(("Version", Val::Unsigned(5)));
+            values.push(("Message Type", Val::Symbol("Reply")));
+            values.push(("Reply", V5_REPLIES.val(data[1])));
+            address(&data[3..], &mut values)?;
+        },
+        (Some(&1), 2) => {
+            values.push(("Version", Val::Unsigned(1)));
+            values.push(("Message Type", Val::Symbol("Username/Password Authentication")));
+            values.push(("Status", Val::Symbol(if data[1] == 0 { "Success" } else { "Failure" })));
+        },
+        _ => values.push(("Tunneled Data", Val::Undissected("SOCKS tunnel", data))),
+    }
+
+    Ok(Box::new(Val::Object("SOCKS", values)))
+}
+
+/// A SOCKS 5 address (with its type) and port.
+fn address<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> Result<(), DissectError> {
+    let end = match data[0] {
+        1 => {
+            values.push(("Address", ipv4(field(data, 1, 4)?)));
+            5
+        },
+        3 => {
+            let length = *field(data, 1, 1)?.first().unwrap_or(&0) as usize;
+            let name = field(data, 2, length)?;
+            values.push(("Host Name", Val::String(String::from_utf8_lossy(name).into_owned())));
+            2 + length
+        },
+        4 => {
+            let bytes = field(data, 1, 16)?;
+            values.push(("Address", Val::Address {
+                bytes: bytes,
+                encoded: bytes.chunks(2).map(|w| format!["{:x}", (w[0] as u16) << 8 | w[1] as u16])
+                    .collect::<Vec<_>>().join(":"),
+            }));
+            17
+        },
+        other => return Err(DissectError::InvalidData(
+            format!["SOCKS address type {} is invalid", other])),
+    };
+
+    values.push(("Port", Val::Unsigned(read_be_u16(data, end)? as u64)));
+    Ok(())
+}
+
+fn field(data: &[u8], offset: usize, length: usize) -> Result<&[u8], DissectError> {
+    data.get(offset..offset + length).ok_or_else(|| DissectError::Underflow {
+        expected: Some(offset + length), have: data.len(),
+        message: "SOCKS message is truncated".to_string() })
+}
+
+fn ipv4(bytes: &[u8]) -> Val {
+    Val::Address {
+        bytes: bytes,
+        encoded: bytes.iter().map(|b| b.to_string()).collect::<Vec<_>>().join("."),
+    }
+}
+
+/// The target of a proxied connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Target {
+    /// The target's address or host name.
+    pub host: String,
+    pub port: u16,
+}
+
+/// A tap on "SOCKS" that records the targets of CONNECT requests, keyed by
+/// the flow from client to proxy. Connections that the proxy refuses are
+/// forgotten.
+#[derive(Default)]
+pub struct Tunnels {
+    targets: HashMap<FlowKey, Target>,
+}
+
+impl Tunnels {
+    pub fn new() -> Tunnels {
+        Tunnels::default()
+    }
+
+    /// The target of a proxied connection, seen from either end.
+    pub fn target(&self, key: &FlowKey) -> Option<&Target> {
+        self.targets.get(key).or_else(|| self.targets.get(&key.reversed()))
+    }
+
+    /// Dissect a TCP payload on a proxied connection as if it had been sent
+    /// to (or by) the target.
+    pub fn dissect<'data>(&self, key: &FlowKey, data: &'data [u8]) -> Option<Val<'data>> {
+        if let Some(target) = self.targets.get(key) {
+            Some(tcp::payload(key.source_port, target.port, data))
+        } else if let Some(target) = self.targets.get(&key.reversed()) {
+            Some(tcp::payload(target.port, key.destination_port, data))
+        } else {
+            None
+        }
+    }
+}
+
+impl Tap for Tunnels {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        let key = match FlowKey::from_val(info.packet) {
+            Some(key) => key,
+            None => return,
+        };
+
+        let version = layer.get("Version").ok().and_then(|v| v.as_unsigned());
+        let request = key.destination_port == PORT;
+
+        match (request, layer.get("Command"), layer.get("Reply")) {
+            (true, Ok(command), _) if command.as_symbol() == COMMANDS.name(CONNECT) => {
+                let host = layer.get("Host Name").ok().and_then(|h| h.as_string())
+                    .or_else(|| layer.get("Address").ok().and_then(|a| a.as_address_encoded()));
+                let port = layer.get("Port").ok().and_then(|p| p.as_unsigned());
+
+                if let (Some(host), Some(port)) = (host, port) {
+                    self.targets.insert(key, Target { host: host.to_string(), port: port as u16 });
+                }
+            },
+            (false, _, Ok(reply)) => {
+                let granted = match version {
+                    Some(4) => reply.as_symbol() == V4_REPLIES.name(90u8),
+                    _ => reply.as_symbol() == V5_REPLIES.name(0u8),
+                };
+                if !granted {
+                    self.targets.remove(&key.reversed());
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use tap::Taps;
+
+    #[test]
+    fn dissect_socks5() {
+        let val = *dissect_request(&[5, 2, 0, 2]).unwrap();
+        assert_eq!(val["Message Type"].as_symbol().unwrap(), "Method Selection");
+        assert_eq!(val["Method"].as_symbol().unwrap(), "No Authentication");
+
+        let val = *dissect_request(&[1, 3, b'b', b'o', b'b', 2, b'p', b'w']).unwrap();
+        assert_eq!(val["Username"].as_string().unwrap(), "bob");
+        assert_eq!(val["Password"].as_string().unwrap(), "pw");
+
+        let val = *dissect_request(&[5, 1, 0, 3, 11, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+                                     b'.', b'c', b'o', b'm', 0x01, 0xbb]).unwrap();
+        assert_eq!(val["Command"].as_symbol().unwrap(), "CONNECT");
+        assert_eq!(val["Host Name"].as_string().unwrap(), "example.com");
+        assert_eq!(val["Port"].as_unsigned().unwrap(), 443);
+
+        let val = *dissect_response(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(val["Reply"].as_symbol().unwrap(), "Connection Refused");
+
+        assert!(dissect_request(&[5, 1, 0, 9, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn track_socks4_tunnel() {
+        // An IPv4 + TCP packet from 10.0.0.1:40000 to the proxy at 10.0.0.2
+        fn packet(source_port: u16, destination_port: u16, payload: &[u8]) -> Vec<u8> {
+            let mut data = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+            if source_port == PORT {
+                data[12..20].copy_from_slice(&[10, 0, 0, 2, 10, 0, 0, 1]);
+            }
+            data.extend_from_slice(&[(source_port >> 8) as u8, source_port as u8,
+                                     (destination_port >> 8) as u8, destination_port as u8,
+                                     0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x18, 0, 0, 0, 0, 0, 0]);
+            data.extend_from_slice(payload);
+            data
+        }
+
+        // CONNECT to 192.168.1.1:3306 as "root"
+        let request = packet(40000, PORT, &[4, 1, 0x0c, 0xea, 192, 168, 1, 1, b'r', b'o', b'o', b't', 0]);
+        let reply = packet(PORT, 40000, &[0, 90, 0, 0, 0, 0, 0, 0]);
+
+        let mut tunnels = Tunnels::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("SOCKS", &mut tunnels);
+            for data in &[request, reply] {
+                let val = ::ip::dissect(data).unwrap();
+                taps.dispatch(Duration::new(0, 0), data.len(), &val);
+            }
+        }
+
+        let tunneled = packet(40000, PORT, &[1, 0, 0, 0, 0x03, b'S', b'E', b'L']);
+        let val = ::ip::dissect(&tunneled).unwrap();
+        let key = FlowKey::from_val(&val).unwrap();
+        assert_eq!(tunnels.target(&key.reversed()).unwrap().host, "192.168.1.1");
+
+        let inner = tunnels.dissect(&key, &tunneled[40..]).unwrap();
+        assert!(inner.layer("MySQL").is_some());
+    }
+}
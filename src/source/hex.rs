@@ -0,0 +1,116 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Import of packets from hex dumps, in the format read by `text2pcap` and
+//! written by `od -Ax -tx1` or Wireshark's "Copy as Hex Dump":
+//!
+//! ```text
+//! 0000  00 11 22 33 44 55 66 77 88 99 aa bb 08 00 45 00  .."3DUfw......E.
+//! 0010  00 1c                                            ..
+//! ```
+//!
+//! Each line starts with the hexadecimal offset of its first byte; an offset
+//! of zero starts a new packet. Any text dump after the bytes is ignored, as
+//! are lines that don't start with an offset. Packets are given timestamps
+//! one microsecond apart, starting at the epoch.
+
+use std::io;
+use std::time::Duration;
+
+use super::{invalid_data, Packet, VecSource};
+
+/// Parse a hex dump into a packet source.
+pub fn import(text: &str, link_type: u32) -> io::Result<VecSource> {
+    // (line number, offset, candidate byte tokens)
+    let mut lines = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let offset = match tokens.next().map(|t| t.trim_end_matches(':')) {
+            Some(t) if t.len() >= 2 && t.chars().all(|c| c.is_digit(16)) =>
+                usize::from_str_radix(t, 16).map_err(invalid_data)?,
+            _ => continue,
+        };
+
+        let bytes = tokens
+            .take_while(|t| t.len() == 2 && t.chars().all(|c| c.is_digit(16)))
+            .map(|t| u8::from_str_radix(t, 16).unwrap())
+            .collect::<Vec<_>>();
+
+        lines.push((number + 1, offset, bytes));
+    }
+
+    let mut packets: Vec<Packet> = Vec::new();
+    let mut data = Vec::new();
+
+    for i in 0..lines.len() {
+        let (number, offset, ref bytes) = lines[i];
+
+        if offset == 0 && !data.is_empty() {
+            packets.push(packet(packets.len(), data));
+            data = Vec::new();
+        }
+
+        if offset != data.len() {
+            return Err(invalid_data(format!["line {}: expected offset {:#x}, found {:#x}",
+                                            number, data.len(), offset]));
+        }
+
+        // The next line's offset tells us how many of these tokens are bytes
+        // (the rest may be a text dump that happens to look like hex).
+        let count = match lines.get(i + 1) {
+            Some(&(_, next, _)) if next > offset => ::std::cmp::min(next - offset, bytes.len()),
+            _ => bytes.len(),
+        };
+
+        data.extend_from_slice(&bytes[..count]);
+    }
+
+    if !data.is_empty() {
+        packets.push(packet(packets.len(), data));
+    }
+
+    Ok(VecSource::new(packets, link_type))
+}
+
+fn packet(index: usize, data: Vec<u8>) -> Packet {
+    Packet {
+        timestamp: Duration::new(0, 1000 * index as u32),
+        original_length: data.len(),
+        data: data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use source::PacketSource;
+
+    #[test]
+    fn import_dump() {
+        let dump = "\
+            Frame 1\n\
+            0000  00 11 22 33 44 55 66 77 88 99 aa bb 08 00 45 00  ..\"3DUfw......E.\n\
+            0010  00 1c ab                                         ..\n\
+            \n\
+            0000  de ad be ef                                      ....\n";
+
+        let mut source = import(dump, 1).unwrap();
+        let first = source.next_packet().unwrap().unwrap();
+        assert_eq!(first.data.len(), 19);
+        assert_eq!(&first.data[16..], &[0x00, 0x1c, 0xab]);
+
+        let second = source.next_packet().unwrap().unwrap();
+        assert_eq!(second.data, vec![0xde, 0xad, 0xbe, 0xef]);
+        assert!(second.timestamp > first.timestamp);
+        assert!(source.next_packet().is_none());
+
+        assert!(import("0000 00 11\n0004 22\n", 1).is_err());
+    }
+}
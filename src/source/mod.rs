@@ -0,0 +1,165 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Sources of captured packets.
+//!
+//! Everything that produces packets (capture files, live interfaces, hex
+//! dumps, in-memory vectors) implements `PacketSource`, so that code that
+//! consumes packets only needs to be written once.
+
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+
+pub mod hex;
+pub mod pcap;
+pub mod pcapng;
+
+/// A captured packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    /// Capture time, since the Unix epoch.
+    pub timestamp: Duration,
+
+    /// The length of the packet on the wire, which may be more than was
+    /// captured.
+    pub original_length: usize,
+
+    /// The captured bytes.
+    pub data: Vec<u8>,
+}
+
+/// Counters describing what a source has produced.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceStats {
+    pub packets: u64,
+
+    /// Captured (not original) bytes.
+    pub bytes: u64,
+
+    /// Packets dropped by the capture mechanism, if it reports them.
+    pub dropped: Option<u64>,
+}
+
+impl SourceStats {
+    /// Account for a packet that is being returned by a source.
+    pub fn count(&mut self, packet: &Packet) {
+        self.packets += 1;
+        self.bytes += packet.data.len() as u64;
+    }
+}
+
+/// A source of captured packets.
+pub trait PacketSource {
+    /// The next packet, `None` at the end of the capture or an error if the
+    /// capture can't be read.
+    fn next_packet(&mut self) -> Option<io::Result<Packet>>;
+
+    /// The link-layer type of the packets (a libpcap `LINKTYPE_` value).
+    fn link_type(&self) -> u32;
+
+    fn stats(&self) -> SourceStats;
+
+    /// The total number of packets in the source, if it is known in advance.
+    fn packet_count(&self) -> Option<u64> {
+        None
+    }
+
+    /// Reposition the source so that the next packet returned is the one with
+    /// the given (zero-based) index. Not all sources support seeking.
+    fn seek(&mut self, _index: u64) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "packet source does not support seeking"))
+    }
+}
+
+/// A source of packets held in memory.
+pub struct VecSource {
+    packets: Vec<Packet>,
+    link_type: u32,
+    position: usize,
+    stats: SourceStats,
+}
+
+impl VecSource {
+    pub fn new(packets: Vec<Packet>, link_type: u32) -> VecSource {
+        VecSource { packets: packets, link_type: link_type, position: 0, stats: SourceStats::default() }
+    }
+}
+
+impl PacketSource for VecSource {
+    fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+        let packet = match self.packets.get(self.position) {
+            Some(p) => p.clone(),
+            None => return None,
+        };
+
+        self.position += 1;
+        self.stats.count(&packet);
+        Some(Ok(packet))
+    }
+
+    fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.stats.clone()
+    }
+
+    fn packet_count(&self) -> Option<u64> {
+        Some(self.packets.len() as u64)
+    }
+
+    fn seek(&mut self, index: u64) -> io::Result<()> {
+        if index as usize > self.packets.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!["no packet {} in a source of {}", index, self.packets.len()]));
+        }
+
+        self.position = index as usize;
+        Ok(())
+    }
+}
+
+/// Iterate over the packets of a source.
+impl Iterator for dyn PacketSource {
+    type Item = io::Result<Packet>;
+
+    fn next(&mut self) -> Option<io::Result<Packet>> {
+        self.next_packet()
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error where E: Into<Box<dyn Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn vec_source() {
+        let packet = |n| Packet { timestamp: Duration::new(n, 0), original_length: 60, data: vec![n as u8; 4] };
+        let mut source: Box<dyn PacketSource> = Box::new(VecSource::new(vec![packet(1), packet(2)], 1));
+
+        assert_eq!(source.next_packet().unwrap().unwrap(), packet(1));
+        assert_eq!(source.next_packet().unwrap().unwrap(), packet(2));
+        assert!(source.next_packet().is_none());
+
+        source.seek(1).unwrap();
+        assert_eq!(source.by_ref().map(|p| p.unwrap().timestamp).collect::<Vec<_>>(),
+                   vec![Duration::new(2, 0)]);
+        assert!(source.seek(3).is_err());
+
+        assert_eq!(source.stats(), SourceStats { packets: 3, bytes: 12, dropped: None });
+        assert_eq!(source.link_type(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Reading of classic libpcap capture files (of either byte order, with
+//! microsecond or nanosecond timestamps).
+//!
+//! See [the libpcap file format](https://wiki.wireshark.org/Development/LibpcapFileFormat).
+
+use std::io;
+use std::io::Read;
+use std::time::Duration;
+
+use Endianness;
+use unsigned;
+use super::{invalid_data, Packet, PacketSource, SourceStats};
+
+/// Reads packets from a pcap file.
+pub struct PcapReader<R: Read> {
+    input: R,
+    endianness: Endianness,
+    nanosecond: bool,
+    link_type: u32,
+    snaplen: u32,
+    stats: SourceStats,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read the file header.
+    pub fn new(mut input: R) -> io::Result<PcapReader<R>> {
+        let mut header = [0; 24];
+        input.read_exact(&mut header)?;
+
+        let (endianness, nanosecond) = match unsigned(&header[0..4], Endianness::LittleEndian).unwrap() {
+            0xa1b2c3d4 => (Endianness::LittleEndian, false),
+            0xa1b23c4d => (Endianness::LittleEndian, true),
+            0xd4c3b2a1 => (Endianness::BigEndian, false),
+            0x4d3cb2a1 => (Endianness::BigEndian, true),
+            magic => return Err(invalid_data(format!["not a pcap file (magic {:#010x})", magic])),
+        };
+
+        let field = |range: ::std::ops::Range<usize>| unsigned(&header[range], endianness).unwrap() as u32;
+
+        Ok(PcapReader {
+            input: input,
+            endianness: endianness,
+            nanosecond: nanosecond,
+            snaplen: field(16..20),
+            link_type: field(20..24),
+            stats: SourceStats::default(),
+        })
+    }
+
+    pub fn snaplen(&self) -> u32 {
+        self.snaplen
+    }
+
+    fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut header = [0; 16];
+
+        // A clean end of file can only occur between records.
+        match self.input.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.input.read_exact(&mut header[1..])?,
+        }
+
+        let endianness = self.endianness;
+        let field = |range: ::std::ops::Range<usize>| unsigned(&header[range], endianness).unwrap() as u32;
+
+        let seconds = field(0..4);
+        let fraction = field(4..8);
+        let captured = field(8..12) as usize;
+        let original = field(12..16) as usize;
+
+        // Don't trust a corrupt length to size our allocation.
+        if captured > 0x4000000 {
+            return Err(invalid_data(format!["implausible pcap record length {}", captured]));
+        }
+
+        let mut data = vec![0; captured];
+        self.input.read_exact(&mut data)?;
+
+        let nanos = if self.nanosecond { fraction } else { fraction.saturating_mul(1000) };
+
+        Ok(Some(Packet {
+            timestamp: Duration::new(seconds as u64, nanos),
+            original_length: original,
+            data: data,
+        }))
+    }
+}
+
+impl<R: Read> PacketSource for PcapReader<R> {
+    fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+        match self.read_packet() {
+            Ok(Some(packet)) => {
+                self.stats.count(&packet);
+                Some(Ok(packet))
+            },
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use output::pcap::{PcapWriter, LINKTYPE_ETHERNET};
+    use source::PacketSource;
+
+    #[test]
+    fn read_written_file() {
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET, 65535).unwrap();
+        writer.write_packet(Duration::new(5, 6000), 4, &[1, 2, 3, 4]).unwrap();
+        writer.write_packet(Duration::new(7, 0), 100, &[5, 6]).unwrap();
+        let file = writer.into_inner();
+
+        let mut reader = PcapReader::new(&file[..]).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_ETHERNET);
+
+        let first = reader.next_packet().unwrap().unwrap();
+        assert_eq!(first.timestamp, Duration::new(5, 6000));
+        assert_eq!(first.data, vec![1, 2, 3, 4]);
+
+        let second = reader.next_packet().unwrap().unwrap();
+        assert_eq!(second.original_length, 100);
+        assert!(reader.next_packet().is_none());
+        assert_eq!(reader.stats().packets, 2);
+
+        // Truncated records are errors, not the end of the file.
+        let mut truncated = PcapReader::new(&file[..file.len() - 1]).unwrap();
+        assert!(truncated.next_packet().unwrap().is_ok());
+        assert!(truncated.next_packet().unwrap().is_err());
+
+        assert!(PcapReader::new(&[0u8; 24][..]).is_err());
+    }
+}
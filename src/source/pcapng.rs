@@ -0,0 +1,286 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Reading of packets from pcapng capture files.
+//!
+//! The file is read into memory and indexed when the source is created, so
+//! seeking to any packet is cheap. The source's link type is that of the
+//! first interface; packets captured on other interfaces are returned as-is.
+
+use std::io;
+use std::io::Read;
+use std::time::Duration;
+
+use Endianness;
+use pcapng::{blocks, Block, ENHANCED_PACKET, INTERFACE_DESCRIPTION, SIMPLE_PACKET};
+use progress::Reporter;
+use unsigned;
+use super::{invalid_data, Packet, PacketSource, SourceStats};
+
+/// An interface described by an Interface Description Block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interface {
+    pub link_type: u32,
+    pub snaplen: u32,
+
+    /// Timestamp units per second (from the `if_tsresol` option).
+    pub resolution: u64,
+}
+
+/// Reads packets from a pcapng file.
+pub struct PcapngReader {
+    interfaces: Vec<Interface>,
+    packets: Vec<Packet>,
+
+    /// The error that stopped indexing, returned after the last good packet.
+    error: Option<io::Error>,
+    position: usize,
+    stats: SourceStats,
+}
+
+impl PcapngReader {
+    pub fn new<R: Read>(mut input: R) -> io::Result<PcapngReader> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        PcapngReader::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<PcapngReader> {
+        PcapngReader::index_bytes(data, None)
+    }
+
+    /// Index a file held in memory, reporting progress as it goes.
+    pub fn from_bytes_with_progress(data: &[u8], reporter: &mut Reporter) -> io::Result<PcapngReader> {
+        PcapngReader::index_bytes(data, Some(reporter))
+    }
+
+    fn index_bytes(data: &[u8], mut reporter: Option<&mut Reporter>) -> io::Result<PcapngReader> {
+        let mut reader = PcapngReader {
+            interfaces: Vec::new(),
+            packets: Vec::new(),
+            error: None,
+            position: 0,
+            stats: SourceStats::default(),
+        };
+
+        let mut blocks = blocks(data);
+        while let Some(block) = blocks.next() {
+            let result = block.map_err(|e| invalid_data(e.to_string()))
+                .and_then(|block| reader.index(&block));
+
+            if let Some(ref mut reporter) = reporter {
+                reporter.update(reader.packets.len() as u64, (data.len() - blocks.remaining()) as u64);
+            }
+
+            if let Err(e) = result {
+                if reader.interfaces.is_empty() {
+                    return Err(e);
+                }
+
+                reader.error = Some(e);
+                break;
+            }
+        }
+
+        if let Some(reporter) = reporter {
+            reporter.finish(reader.packets.len() as u64, (data.len() - blocks.remaining()) as u64);
+        }
+
+        if reader.interfaces.is_empty() && reader.error.is_none() {
+            return Err(invalid_data("pcapng file has no interfaces"));
+        }
+
+        Ok(reader)
+    }
+
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    fn index(&mut self, block: &Block) -> io::Result<()> {
+        let body = block.body;
+        let field = |offset: usize, len: usize| -> io::Result<u64> {
+            body.get(offset..offset + len)
+                .map(|bytes| unsigned(bytes, block.endianness).unwrap())
+                .ok_or(invalid_data(format!["pcapng block type {} is truncated", block.block_type]))
+        };
+
+        match block.block_type {
+            INTERFACE_DESCRIPTION => {
+                let interface = Interface {
+                    link_type: field(0, 2)? as u32,
+                    snaplen: field(4, 4)? as u32,
+                    resolution: resolution(body.get(8..).unwrap_or(&[]), block.endianness),
+                };
+                self.interfaces.push(interface);
+            },
+
+            ENHANCED_PACKET => {
+                let interface = self.interface(field(0, 4)? as usize)?;
+                let timestamp = field(4, 4)? << 32 | field(8, 4)?;
+                let captured = field(12, 4)? as usize;
+                let original = field(16, 4)? as usize;
+
+                let data = body.get(20..20 + captured)
+                    .ok_or(invalid_data("pcapng packet data is truncated"))?;
+
+                let nanos = (timestamp % interface.resolution) as u128 * 1000000000
+                    / interface.resolution as u128;
+
+                self.packets.push(Packet {
+                    timestamp: Duration::new(timestamp / interface.resolution, nanos as u32),
+                    original_length: original,
+                    data: data.to_vec(),
+                });
+            },
+
+            SIMPLE_PACKET => {
+                let interface = self.interface(0)?;
+                let original = field(0, 4)? as usize;
+
+                let mut captured = ::std::cmp::min(original, body.len() - 4);
+                if interface.snaplen != 0 {
+                    captured = ::std::cmp::min(captured, interface.snaplen as usize);
+                }
+
+                self.packets.push(Packet {
+                    timestamp: Duration::new(0, 0),
+                    original_length: original,
+                    data: body[4..4 + captured].to_vec(),
+                });
+            },
+
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    fn interface(&self, id: usize) -> io::Result<Interface> {
+        self.interfaces.get(id).cloned()
+            .ok_or(invalid_data(format!["pcapng packet refers to unknown interface {}", id]))
+    }
+}
+
+impl PacketSource for PcapngReader {
+    fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+        match self.packets.get(self.position) {
+            Some(packet) => {
+                self.position += 1;
+                self.stats.count(packet);
+                Some(Ok(packet.clone()))
+            },
+            None => self.error.take().map(Err),
+        }
+    }
+
+    fn link_type(&self) -> u32 {
+        self.interfaces.first().map(|i| i.link_type).unwrap_or(0)
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.stats.clone()
+    }
+
+    fn packet_count(&self) -> Option<u64> {
+        Some(self.packets.len() as u64)
+    }
+
+    fn seek(&mut self, index: u64) -> io::Result<()> {
+        if index as usize > self.packets.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!["no packet {} in a file of {}", index, self.packets.len()]));
+        }
+
+        self.position = index as usize;
+        Ok(())
+    }
+}
+
+/// Find the timestamp resolution (units per second) in interface options.
+fn resolution(mut options: &[u8], endianness: Endianness) -> u64 {
+    while options.len() >= 4 {
+        let code = unsigned(&options[0..2], endianness).unwrap();
+        let length = unsigned(&options[2..4], endianness).unwrap() as usize;
+
+        match code {
+            0 => break,
+            9 if length == 1 && options.len() > 4 => {
+                let value = options[4];
+                let exponent = (value & 0x7f) as u32;
+
+                return match (value & 0x80 != 0, exponent) {
+                    (true, e) if e < 64 => 1 << e,
+                    (false, e) if e <= 19 => 10u64.pow(e),
+                    _ => 1000000,
+                };
+            },
+            _ => {},
+        }
+
+        let padded = 4 + (length + 3) / 4 * 4;
+        if padded > options.len() {
+            break;
+        }
+        options = &options[padded..];
+    }
+
+    1000000
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use pcapng::write_section_header;
+    use source::PacketSource;
+
+    fn block(file: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+        let length = 12 + body.len() as u32;
+        let le = |v: u32| vec![v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8];
+
+        file.extend(le(block_type));
+        file.extend(le(length));
+        file.extend(body.iter().cloned());
+        file.extend(le(length));
+    }
+
+    #[test]
+    fn read_packets() {
+        let mut file = Vec::new();
+        write_section_header(&mut file).unwrap();
+
+        // Ethernet, nanosecond resolution
+        block(&mut file, INTERFACE_DESCRIPTION,
+              &[1, 0, 0, 0, 0, 0, 1, 0, 9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+
+        // 1.5 s since the epoch: 1500000000 ns = 0x59682f00
+        block(&mut file, ENHANCED_PACKET,
+              &[0, 0, 0, 0, 0, 0, 0, 0, 0x00, 0x2f, 0x68, 0x59, 2, 0, 0, 0, 60, 0, 0, 0,
+                0xaa, 0xbb, 0, 0]);
+
+        block(&mut file, ENHANCED_PACKET, &[7, 0, 0, 0]);
+
+        let mut reader = PcapngReader::from_bytes(&file).unwrap();
+        assert_eq!(reader.link_type(), 1);
+        assert_eq!(reader.interfaces()[0].resolution, 1000000000);
+
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.timestamp, Duration::new(1, 500000000));
+        assert_eq!(packet.original_length, 60);
+        assert_eq!(packet.data, vec![0xaa, 0xbb]);
+
+        // The malformed block is reported after the good packets.
+        assert!(reader.next_packet().unwrap().is_err());
+        assert!(reader.next_packet().is_none());
+
+        reader.seek(0).unwrap();
+        assert!(reader.next_packet().unwrap().is_ok());
+    }
+}
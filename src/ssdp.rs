@@ -0,0 +1,107 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the Simple Service Discovery Protocol (SSDP) used by UPnP,
+//! on UDP port 1900.
+//!
+//! SSDP messages are HTTP-like: a start line (`M-SEARCH`, `NOTIFY` or a
+//! response status), then headers. Every header is kept as a "Header" value
+//! and the ones that identify devices and services (ST, NT, NTS, USN,
+//! Location, Server...) are also surfaced as fields of their own. Both IoT
+//! device discovery and SSDP reflection attacks show up in these fields.
+//!
+//! See the [UPnP Device Architecture](https://openconnectivity.org/upnp-specs/UPnP-arch-DeviceArchitecture-v2.0-20200417.pdf).
+
+use DissectError;
+use DissectResult;
+use ErrorCode;
+use Val;
+use NamedValues;
+
+/// Headers surfaced as fields, by their (case-insensitive) names.
+const FIELDS: [(&'static str, &'static str); 10] = [
+    ("host", "Host"),
+    ("st", "Search Target"),
+    ("nt", "Notification Type"),
+    ("nts", "Notification Sub Type"),
+    ("usn", "USN"),
+    ("location", "Location"),
+    ("server", "Server"),
+    ("man", "MAN"),
+    ("mx", "MX"),
+    ("cache-control", "Cache Control"),
+];
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut lines = data.split(|&b| b == b'\n')
+        .map(|line| String::from_utf8_lossy(line).trim_end_matches('\r').to_string());
+
+    let start = lines.next().unwrap_or_default();
+    let mut values = NamedValues::new();
+
+    if start.starts_with("HTTP/") {
+        let mut parts = start.splitn(3, ' ');
+        values.push(("Message Type", Val::Symbol("Response")));
+        values.push(("Version", Val::String(parts.next().unwrap_or("").to_string())));
+        values.push(("Status Code", Val::String(parts.next().unwrap_or("").to_string())));
+        values.push(("Reason", Val::String(parts.next().unwrap_or("").to_string())));
+    } else {
+        let mut parts = start.splitn(3, ' ');
+        let method = parts.next().unwrap_or("");
+        values.push(("Message Type", Val::Symbol(match method {
+            "M-SEARCH" => "M-SEARCH",
+            "NOTIFY" => "NOTIFY",
+            _ => return Err(DissectError::malformed(ErrorCode::BadMagic,
+                format!["'{}' is not an SSDP start line", start])),
+        })));
+        values.push(("URI", Val::String(parts.next().unwrap_or("").to_string())));
+        values.push(("Version", Val::String(parts.next().unwrap_or("").to_string())));
+    }
+
+    let mut fields = NamedValues::new();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        if let Some(colon) = line.find(':') {
+            let name = line[..colon].trim().to_lowercase();
+            let value = line[colon + 1..].trim().to_string();
+
+            if let Some(&(_, field)) = FIELDS.iter().find(|&&(n, _)| n == name) {
+                fields.push((field, Val::String(value)));
+            }
+        }
+
+        values.push(("Header", Val::String(line)));
+    }
+
+    values.extend(fields);
+    Ok(Box::new(Val::Object("SSDP", values)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_ssdp() {
+        let search = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+                       MAN: \"ssdp:discover\"\r\nMX: 2\r\nST: ssdp:all\r\n\r\n";
+        let val = *dissect(search).unwrap();
+        assert_eq!(val["Message Type"].as_symbol().unwrap(), "M-SEARCH");
+        assert_eq!(val["Search Target"].as_string().unwrap(), "ssdp:all");
+        assert_eq!(val["MAN"].as_string().unwrap(), "\"ssdp:discover\"");
+
+        let reply = b"HTTP/1.1 200 OK\r\nLocation: http://192.168.1.1:49152/desc.xml\r\n\
+                      USN: uuid:1234::upnp:rootdevice\r\nST: upnp:rootdevice\r\n\r\n";
+        let val = *dissect(reply).unwrap();
+        assert_eq!(val["Message Type"].as_symbol().unwrap(), "Response");
+        assert_eq!(val["Location"].as_string().unwrap(), "http://192.168.1.1:49152/desc.xml");
+        assert_eq!(val["USN"].as_string().unwrap(), "uuid:1234::upnp:rootdevice");
+
+        assert!(dissect(b"GET / HTTP/1.1\r\n\r\n").is_err());
+    }
+}
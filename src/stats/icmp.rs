@@ -0,0 +1,260 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! ICMP statistics: counts by type and code, the busiest sources and
+//! destinations, and message rates over time.
+//!
+//! Floods of unreachable or redirect messages are often the first sign of an
+//! outage or a scan, so error messages get their own time series, and the
+//! flows whose datagrams they quote are counted.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::stats::icmp::IcmpStats;
+//! use rshark::tap::Taps;
+//!
+//! let mut icmp = IcmpStats::new(Duration::new(1, 0));
+//! {
+//!     let mut taps = Taps::new();
+//!     taps.register("ICMP", &mut icmp);
+//!     // taps.dispatch(...) for each packet
+//! }
+//!
+//! print!("{}", icmp.report(10));
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use Val;
+use flow::FlowKey;
+use tap::{PacketInfo, Tap};
+use super::{top, TimeSeries};
+
+/// Messages of one type and code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Kind {
+    /// The layer the message was found in ("ICMP" or "ICMPv6").
+    pub protocol: &'static str,
+    pub message_type: u8,
+    pub code: u8,
+    pub type_name: Option<&'static str>,
+    pub code_name: Option<&'static str>,
+    pub packets: u64,
+}
+
+impl Kind {
+    /// Whether this kind of message reports an error (rather than, e.g., an
+    /// echo request or router advertisement).
+    pub fn is_error(&self) -> bool {
+        match (self.protocol, self.message_type) {
+            ("ICMPv6", t) => t < 128,
+            (_, t) => t == 3 || t == 4 || t == 5 || t == 11 || t == 12,
+        }
+    }
+}
+
+/// A tap that aggregates ICMP (and ICMPv6) messages.
+pub struct IcmpStats {
+    kinds: BTreeMap<(&'static str, u8, u8), Kind>,
+    sources: HashMap<String, u64>,
+    destinations: HashMap<String, u64>,
+    triggers: HashMap<FlowKey, u64>,
+    messages: TimeSeries,
+    errors: TimeSeries,
+}
+
+impl IcmpStats {
+    /// Collect statistics, with time series in intervals of `interval`.
+    pub fn new(interval: Duration) -> IcmpStats {
+        IcmpStats {
+            kinds: BTreeMap::new(),
+            sources: HashMap::new(),
+            destinations: HashMap::new(),
+            triggers: HashMap::new(),
+            messages: TimeSeries::new(interval),
+            errors: TimeSeries::new(interval),
+        }
+    }
+
+    /// Message counts by protocol, type and code.
+    pub fn kinds(&self) -> Vec<&Kind> {
+        self.kinds.values().collect()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.kinds.values().map(|k| k.packets).sum()
+    }
+
+    pub fn top_sources(&self, n: usize) -> Vec<(String, u64)> {
+        top(&self.sources, n)
+    }
+
+    pub fn top_destinations(&self, n: usize) -> Vec<(String, u64)> {
+        top(&self.destinations, n)
+    }
+
+    /// The flows that triggered the most error messages, by the datagrams
+    /// they quote.
+    pub fn top_triggers(&self, n: usize) -> Vec<(FlowKey, u64)> {
+        top(&self.triggers, n)
+    }
+
+    /// All messages over time.
+    pub fn messages(&self) -> &TimeSeries {
+        &self.messages
+    }
+
+    /// Error messages (unreachable, redirect, time exceeded...) over time.
+    pub fn errors(&self) -> &TimeSeries {
+        &self.errors
+    }
+
+    /// A human-readable report, listing the `n` busiest hosts.
+    pub fn report(&self, n: usize) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "ICMP messages: {}", self.total()).unwrap();
+        for kind in self.kinds.values() {
+            writeln!(out, "  {:<6} {:>3}/{:<3} {:>8}  {}{}", kind.protocol,
+                     kind.message_type, kind.code, kind.packets,
+                     kind.type_name.unwrap_or("Unknown"),
+                     kind.code_name.map(|c| format![" ({})", c]).unwrap_or(String::new())).unwrap();
+        }
+
+        for &(title, ref hosts) in &[("Top sources", self.top_sources(n)),
+                                      ("Top destinations", self.top_destinations(n))] {
+            writeln!(out, "{}:", title).unwrap();
+            for &(ref host, count) in hosts {
+                writeln!(out, "  {:<40} {:>8}", host, count).unwrap();
+            }
+        }
+
+        writeln!(out, "Top triggering flows:").unwrap();
+        for (key, count) in self.top_triggers(n) {
+            let flow = format!["{} {} -> {}", key.protocol,
+                               SocketAddr::new(key.source, key.source_port),
+                               SocketAddr::new(key.destination, key.destination_port)];
+            writeln!(out, "  {:<40} {:>8}", flow, count).unwrap();
+        }
+
+        let interval = self.messages.interval();
+        writeln!(out, "Messages per {:?} (errors):", interval).unwrap();
+        let errors = self.errors.buckets().into_iter().collect::<BTreeMap<_, _>>();
+        for (start, count) in self.messages.buckets() {
+            writeln!(out, "  {:>10.3} {:>8} ({})", start.as_secs_f64(), count,
+                     errors.get(&start).cloned().unwrap_or(0)).unwrap();
+        }
+
+        out
+    }
+}
+
+impl Tap for IcmpStats {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        let protocol = match layer.as_object() {
+            Some((name, _)) => name,
+            None => return,
+        };
+
+        let number = |key| layer.get(key).ok().and_then(|v| v.as_unsigned()).map(|v| v as u8);
+        let name = |key| layer.get(key).ok().and_then(|v| v.as_symbol());
+
+        let (message_type, code) = match (number("Type"), number("Code")) {
+            (Some(t), Some(c)) => (t, c),
+            _ => return,
+        };
+
+        let kind = self.kinds.entry((protocol, message_type, code)).or_insert(Kind {
+            protocol: protocol,
+            message_type: message_type,
+            code: code,
+            type_name: name("Type Name"),
+            code_name: name("Code Name"),
+            packets: 0,
+        });
+        kind.packets += 1;
+
+        self.messages.add(info.timestamp);
+        if kind.is_error() {
+            self.errors.add(info.timestamp);
+        }
+
+        let ip = info.packet.layer("IPv4").or(info.packet.layer("IPv6"));
+        let address = |key| ip.and_then(|ip| ip.get(key).ok())
+            .and_then(|a| a.as_address_encoded())
+            .map(|a| a.to_string());
+
+        if let Some(source) = address("Source") {
+            *self.sources.entry(source).or_insert(0) += 1;
+        }
+
+        if let Some(destination) = address("Destination") {
+            *self.destinations.entry(destination).or_insert(0) += 1;
+        }
+
+        if let Some(key) = FlowKey::quoted(info.packet) {
+            *self.triggers.entry(key).or_insert(0) += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ip;
+    use tap::Taps;
+
+    fn packet(source: u8, message_type: u8, code: u8) -> Vec<u8> {
+        vec![0x45, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00,
+             10, 0, 0, source, 10, 0, 0, 254,
+             message_type, code, 0, 0, 0, 0, 0, 0,
+             // The quoted datagram: UDP from 10.0.0.254:5000 to 10.0.0.<source>:53
+             0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+             10, 0, 0, 254, 10, 0, 0, source,
+             0x13, 0x88, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]
+    }
+
+    #[test]
+    fn icmp_statistics() {
+        let packets = vec![packet(1, 8, 0), packet(1, 3, 3), packet(2, 3, 3), packet(1, 3, 1)];
+
+        let mut stats = IcmpStats::new(Duration::new(1, 0));
+        {
+            let mut taps = Taps::new();
+            taps.register("ICMP", &mut stats);
+
+            for (i, data) in packets.iter().enumerate() {
+                let val = ip::dissect(data).unwrap();
+                taps.dispatch(Duration::new(i as u64 / 2, 0), data.len(), &val);
+            }
+        }
+
+        assert_eq!(stats.total(), 4);
+
+        let port = stats.kinds().into_iter().find(|k| k.message_type == 3 && k.code == 3).unwrap();
+        assert_eq!(port.packets, 2);
+        assert_eq!(port.code_name, Some("Port Unreachable"));
+
+        assert_eq!(stats.top_sources(1), vec![("10.0.0.1".to_string(), 3)]);
+        assert_eq!(stats.top_destinations(5), vec![("10.0.0.254".to_string(), 4)]);
+        assert_eq!(stats.messages().buckets(), vec![(Duration::new(0, 0), 2), (Duration::new(1, 0), 2)]);
+        assert_eq!(stats.errors().buckets(), vec![(Duration::new(0, 0), 1), (Duration::new(1, 0), 2)]);
+
+        let triggers = stats.top_triggers(1);
+        assert_eq!(triggers[0].0.destination, "10.0.0.1".parse::<::std::net::IpAddr>().unwrap());
+        assert_eq!(triggers[0].1, 2);
+
+        assert!(stats.report(3).contains("Port Unreachable"));
+        assert!(stats.report(3).contains("17 10.0.0.254:5000 -> 10.0.0.1:53"));
+    }
+}
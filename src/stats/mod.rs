@@ -0,0 +1,86 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Statistics reports, built as taps (see `rshark::tap`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::Duration;
+
+pub mod icmp;
+pub mod multicast;
+pub mod tunnel;
+
+/// The `n` keys with the highest counts, most frequent first (ties are
+/// broken by key so that reports are stable).
+pub fn top<K>(counts: &HashMap<K, u64>, n: usize) -> Vec<(K, u64)> where K: Clone + Eq + Hash + Ord {
+    let mut entries = counts.iter().map(|(k, &v)| (k.clone(), v)).collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(n);
+    entries
+}
+
+/// Event counts in fixed-width time intervals.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeSeries {
+    interval: Duration,
+    buckets: BTreeMap<u64, u64>,
+}
+
+impl TimeSeries {
+    pub fn new(interval: Duration) -> TimeSeries {
+        assert!(interval > Duration::new(0, 0), "time series interval must be non-zero");
+        TimeSeries { interval: interval, buckets: BTreeMap::new() }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Count an event at time `t`.
+    pub fn add(&mut self, t: Duration) {
+        let bucket = (t.as_nanos() / self.interval.as_nanos()) as u64;
+        *self.buckets.entry(bucket).or_insert(0) += 1;
+    }
+
+    /// The start time and count of each interval that saw events, in order.
+    pub fn buckets(&self) -> Vec<(Duration, u64)> {
+        self.buckets.iter()
+            .map(|(&bucket, &count)| (self.interval * bucket as u32, count))
+            .collect()
+    }
+
+    /// The interval with the most events.
+    pub fn peak(&self) -> Option<(Duration, u64)> {
+        self.buckets().into_iter().fold(None, |peak, (t, count)| match peak {
+            Some((_, most)) if most >= count => peak,
+            _ => Some((t, count)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn series_and_top() {
+        let mut series = TimeSeries::new(Duration::new(10, 0));
+        for &t in &[1, 5, 12, 31, 33, 39] {
+            series.add(Duration::new(t, 0));
+        }
+
+        assert_eq!(series.buckets(), vec![(Duration::new(0, 0), 2), (Duration::new(10, 0), 1),
+                                          (Duration::new(30, 0), 3)]);
+        assert_eq!(series.peak(), Some((Duration::new(30, 0), 3)));
+
+        let counts = vec![("a", 1), ("b", 5), ("c", 5)].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(top(&counts, 2), vec![("b", 5), ("c", 5)]);
+    }
+}
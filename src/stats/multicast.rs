@@ -0,0 +1,306 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! UDP unicast/multicast/broadcast classification and multicast group
+//! membership.
+//!
+//! Each UDP datagram is classified by its destination. Multicast datagrams
+//! are aggregated by group, with the hosts that sent to the group; IGMP
+//! reports and leaves are correlated with the groups to find their
+//! receivers. A group with senders but no receivers is being flooded to
+//! nobody (or its receivers joined before the capture started); this is a
+//! common sight in AV-over-IP and industrial networks.
+//!
+//! ```
+//! use rshark::stats::multicast::MulticastStats;
+//! use rshark::tap::Taps;
+//!
+//! let mut stats = MulticastStats::new();
+//! {
+//!     let mut taps = Taps::new();
+//!     taps.register_all(&["IPv4", "IPv6"], &mut stats);
+//!     // ... dispatch packets ...
+//! }
+//! print!["{}", stats.report()];
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use Val;
+use flow::ip_addr;
+use ip::igmp;
+use tap::{PacketInfo, Tap};
+
+/// How a datagram is addressed.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Cast {
+    Unicast,
+    Multicast,
+    Broadcast,
+}
+
+/// Classify a destination. A link-layer broadcast (e.g., to a subnet's
+/// directed broadcast address) counts as a broadcast.
+pub fn classify(destination: &IpAddr, link_broadcast: bool) -> Cast {
+    match destination {
+        _ if link_broadcast => Cast::Broadcast,
+        &IpAddr::V4(a) if a.is_broadcast() => Cast::Broadcast,
+        a if a.is_multicast() => Cast::Multicast,
+        _ => Cast::Unicast,
+    }
+}
+
+/// A multicast group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub address: IpAddr,
+
+    /// UDP datagrams sent to the group.
+    pub packets: u64,
+    pub bytes: u64,
+
+    pub senders: BTreeSet<IpAddr>,
+
+    /// Hosts that have joined the group (and not since left it).
+    pub receivers: BTreeSet<IpAddr>,
+
+    pub joins: u64,
+    pub leaves: u64,
+
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+}
+
+impl Group {
+    fn new(address: IpAddr, timestamp: Duration) -> Group {
+        Group {
+            address: address,
+            packets: 0,
+            bytes: 0,
+            senders: BTreeSet::new(),
+            receivers: BTreeSet::new(),
+            joins: 0,
+            leaves: 0,
+            first_seen: timestamp,
+            last_seen: timestamp,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MulticastStats {
+    /// Packets and bytes of UDP traffic, by classification.
+    casts: BTreeMap<Cast, (u64, u64)>,
+    groups: BTreeMap<IpAddr, Group>,
+}
+
+impl MulticastStats {
+    pub fn new() -> MulticastStats {
+        MulticastStats::default()
+    }
+
+    pub fn packets(&self, cast: Cast) -> u64 {
+        self.casts.get(&cast).map(|c| c.0).unwrap_or(0)
+    }
+
+    pub fn bytes(&self, cast: Cast) -> u64 {
+        self.casts.get(&cast).map(|c| c.1).unwrap_or(0)
+    }
+
+    /// All groups that were sent to or joined, by address.
+    pub fn groups(&self) -> Vec<&Group> {
+        self.groups.values().collect()
+    }
+
+    pub fn group(&self, address: &IpAddr) -> Option<&Group> {
+        self.groups.get(address)
+    }
+
+    /// Groups with traffic but no joins seen.
+    pub fn unjoined(&self) -> Vec<&Group> {
+        self.groups.values().filter(|g| g.packets > 0 && g.joins == 0).collect()
+    }
+
+    /// A human-readable report.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "UDP traffic:").unwrap();
+        for &cast in &[Cast::Unicast, Cast::Multicast, Cast::Broadcast] {
+            writeln!(out, "  {:<10} {:>10} packets {:>12} bytes",
+                     format!["{:?}", cast], self.packets(cast), self.bytes(cast)).unwrap();
+        }
+
+        writeln!(out, "Multicast groups:").unwrap();
+        for group in self.groups.values() {
+            writeln!(out, "  {:<40} {:>8} packets, {} senders, {} receivers ({} joins, {} leaves)",
+                     group.address, group.packets, group.senders.len(), group.receivers.len(),
+                     group.joins, group.leaves).unwrap();
+        }
+
+        out
+    }
+
+    fn group_mut(&mut self, address: IpAddr, timestamp: Duration) -> &mut Group {
+        let group = self.groups.entry(address).or_insert(Group::new(address, timestamp));
+        group.last_seen = timestamp;
+        group
+    }
+
+    fn datagram(&mut self, info: &PacketInfo, source: IpAddr, destination: IpAddr) {
+        let link_broadcast = info.packet.layer("Ethernet frame")
+            .and_then(|e| e.get("Destination").ok())
+            .and_then(Val::as_mac)
+            .map(|d| d.is_broadcast())
+            .unwrap_or(false);
+
+        let cast = classify(&destination, link_broadcast);
+        let counts = self.casts.entry(cast).or_insert((0, 0));
+        counts.0 += 1;
+        counts.1 += info.length as u64;
+
+        if cast == Cast::Multicast {
+            let group = self.group_mut(destination, info.timestamp);
+            group.packets += 1;
+            group.bytes += info.length as u64;
+            group.senders.insert(source);
+        }
+    }
+
+    fn igmp(&mut self, info: &PacketInfo, host: IpAddr, message: &Val) {
+        let (_, values) = match message.as_object() {
+            Some(obj) => obj,
+            None => return,
+        };
+
+        let address = |v: &Val| v.as_address_bytes().and_then(ip_addr);
+        let message_type = message.get("Type").ok().and_then(Val::as_unsigned).unwrap_or(0) as u8;
+
+        match message_type {
+            igmp::V1_MEMBERSHIP_REPORT | igmp::V2_MEMBERSHIP_REPORT | igmp::LEAVE_GROUP => {
+                if let Some(group) = message.get("Group Address").ok().and_then(address) {
+                    self.membership(info, group, host, message_type != igmp::LEAVE_GROUP);
+                }
+            },
+
+            igmp::V3_MEMBERSHIP_REPORT => {
+                for &(_, ref record) in values.iter().filter(|&&(k, _)| k == "Group Record") {
+                    let group = match record.get("Multicast Address").ok().and_then(address) {
+                        Some(g) => g,
+                        None => continue,
+                    };
+
+                    let record_type = record.get("Record Type").ok().and_then(Val::as_unsigned);
+                    let sources = record.get("Number of Sources").ok().and_then(Val::as_unsigned);
+
+                    // Excluding sources (possibly none) is a join; including
+                    // no sources is a leave.
+                    match (record_type, sources) {
+                        (Some(2), _) | (Some(4), _) => self.membership(info, group, host, true),
+                        (Some(3), Some(0)) => self.membership(info, group, host, false),
+                        (Some(1), Some(n)) | (Some(3), Some(n)) | (Some(5), Some(n)) if n > 0 =>
+                            self.membership(info, group, host, true),
+                        _ => {},
+                    }
+                }
+            },
+
+            _ => {},
+        }
+    }
+
+    fn membership(&mut self, info: &PacketInfo, address: IpAddr, host: IpAddr, join: bool) {
+        let group = self.group_mut(address, info.timestamp);
+        if join {
+            group.joins += 1;
+            group.receivers.insert(host);
+        } else {
+            group.leaves += 1;
+            group.receivers.remove(&host);
+        }
+    }
+}
+
+/// Taps IPv4 and/or IPv6 layers.
+impl Tap for MulticastStats {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        let address = |key| layer.get(key).ok().and_then(Val::as_address_bytes).and_then(ip_addr);
+        let (source, destination) = match (address("Source"), address("Destination")) {
+            (Some(s), Some(d)) => (s, d),
+            _ => return,
+        };
+
+        match layer.get("Payload") {
+            Ok(&Val::Payload(_, Ok(ref payload))) => match **payload {
+                Val::Object("UDP", _) => self.datagram(info, source, destination),
+                Val::Object("IGMP", _) => self.igmp(info, source, payload),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ip;
+    use tap::Taps;
+
+    fn ipv4(source: u8, destination: [u8; 4], protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x45, 0, 0, 20 + payload.len() as u8, 0, 0, 0, 0, 1, protocol, 0, 0,
+                            10, 0, 0, source];
+        data.extend_from_slice(&destination);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn multicast_groups() {
+        let udp = [0x13, 0x88, 0x13, 0x88, 0x00, 0x0a, 0x00, 0x00, 0xab, 0xcd];
+        let packets = vec![
+            ipv4(1, [239, 1, 1, 1], 17, &udp),
+            ipv4(2, [239, 1, 1, 1], 17, &udp),
+            ipv4(1, [239, 9, 9, 9], 17, &udp),
+            ipv4(1, [255, 255, 255, 255], 17, &udp),
+            ipv4(1, [10, 0, 0, 2], 17, &udp),
+            ipv4(5, [239, 1, 1, 1], 2, &[0x16, 0, 0, 0, 239, 1, 1, 1]),
+            ipv4(6, [239, 1, 1, 1], 2, &[0x16, 0, 0, 0, 239, 1, 1, 1]),
+            ipv4(6, [224, 0, 0, 2], 2, &[0x17, 0, 0, 0, 239, 1, 1, 1]),
+        ];
+
+        let mut stats = MulticastStats::new();
+        {
+            let mut taps = Taps::new();
+            taps.register_all(&["IPv4", "IPv6"], &mut stats);
+
+            for (i, data) in packets.iter().enumerate() {
+                let val = ip::dissect(data).unwrap();
+                taps.dispatch(Duration::new(i as u64, 0), data.len(), &val);
+            }
+        }
+
+        assert_eq!(stats.packets(Cast::Multicast), 3);
+        assert_eq!(stats.packets(Cast::Broadcast), 1);
+        assert_eq!(stats.packets(Cast::Unicast), 1);
+
+        let group = stats.group(&"239.1.1.1".parse().unwrap()).unwrap();
+        assert_eq!(group.packets, 2);
+        assert_eq!(group.senders.len(), 2);
+        assert_eq!(group.receivers.iter().map(|r| r.to_string()).collect::<Vec<_>>(), vec!["10.0.0.5"]);
+        assert_eq!((group.joins, group.leaves), (2, 1));
+
+        assert_eq!(stats.unjoined().iter().map(|g| g.address.to_string()).collect::<Vec<_>>(),
+                   vec!["239.9.9.9"]);
+    }
+}
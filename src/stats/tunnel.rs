@@ -0,0 +1,238 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Traffic broken down by VLAN, overlay network and tunnel.
+//!
+//! In a multi-tenant network, the outer VLAN ID, the VXLAN or GENEVE network
+//! identifier (VNI) and the endpoints of GRE or IPsec tunnels say whose
+//! traffic a packet is. Each packet is counted against its outermost VLAN,
+//! every VNI it carries and every tunnel it passes through.
+//!
+//! The tap looks at whole packets, so it can be registered for both the
+//! "Ethernet frame" and IP layers (to cover raw IP captures) without counting
+//! any packet twice:
+//!
+//! ```
+//! use rshark::stats::tunnel::TunnelStats;
+//! use rshark::tap::Taps;
+//!
+//! let mut tunnels = TunnelStats::new();
+//! {
+//!     let mut taps = Taps::new();
+//!     taps.register_all(&["Ethernet frame", "IPv4", "IPv6"], &mut tunnels);
+//!     // taps.dispatch(...) for each packet
+//! }
+//!
+//! print!("{}", tunnels.report());
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use Val;
+use tap::{PacketInfo, Tap};
+
+/// The endpoints of a GRE or IPsec tunnel, and its key (GRE) or SPI (ESP).
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Tunnel {
+    pub protocol: &'static str,
+    pub source: String,
+    pub destination: String,
+    pub key: Option<u64>,
+}
+
+/// Packets and (original) bytes attributed to a VLAN, VNI or tunnel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Traffic {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, bytes: usize) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// A tap that attributes traffic to VLANs, overlay networks and tunnels.
+#[derive(Default)]
+pub struct TunnelStats {
+    vlans: BTreeMap<u16, Traffic>,
+    vnis: BTreeMap<(&'static str, u64), Traffic>,
+    tunnels: BTreeMap<Tunnel, Traffic>,
+    untagged: Traffic,
+    last_packet: Option<u64>,
+}
+
+impl TunnelStats {
+    pub fn new() -> TunnelStats {
+        TunnelStats::default()
+    }
+
+    /// Traffic by outermost VLAN ID.
+    pub fn vlans(&self) -> &BTreeMap<u16, Traffic> {
+        &self.vlans
+    }
+
+    /// Traffic by overlay protocol ("VXLAN" or "GENEVE") and VNI.
+    pub fn vnis(&self) -> &BTreeMap<(&'static str, u64), Traffic> {
+        &self.vnis
+    }
+
+    pub fn tunnels(&self) -> &BTreeMap<Tunnel, Traffic> {
+        &self.tunnels
+    }
+
+    /// Traffic with no VLAN tag.
+    pub fn untagged(&self) -> Traffic {
+        self.untagged
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "VLANs:").unwrap();
+        for (id, traffic) in &self.vlans {
+            writeln!(out, "  {:<40} {:>8} {:>12} B", id, traffic.packets, traffic.bytes).unwrap();
+        }
+        writeln!(out, "  {:<40} {:>8} {:>12} B", "(untagged)", self.untagged.packets, self.untagged.bytes).unwrap();
+
+        writeln!(out, "Overlay networks:").unwrap();
+        for (&(protocol, vni), traffic) in &self.vnis {
+            writeln!(out, "  {:<40} {:>8} {:>12} B", format!["{} {}", protocol, vni],
+                     traffic.packets, traffic.bytes).unwrap();
+        }
+
+        writeln!(out, "Tunnels:").unwrap();
+        for (tunnel, traffic) in &self.tunnels {
+            let key = tunnel.key.map(|k| format![" ({:x})", k]).unwrap_or(String::new());
+            writeln!(out, "  {:<40} {:>8} {:>12} B",
+                     format!["{} {} -> {}{}", tunnel.protocol, tunnel.source, tunnel.destination, key],
+                     traffic.packets, traffic.bytes).unwrap();
+        }
+
+        out
+    }
+
+    fn walk(&mut self, val: &Val, endpoints: Option<(String, String)>, length: usize) {
+        let (name, values) = match val.as_object() {
+            Some(obj) => obj,
+            None => return,
+        };
+
+        let field = |key| values.iter().find(|&&(k, _)| k == key).map(|&(_, ref v)| v);
+        let number = |key| field(key).and_then(|v| v.as_unsigned());
+        let address = |key| field(key).and_then(|v| v.as_address_encoded()).map(|a| a.to_string());
+
+        let endpoints = match name {
+            "IPv4" | "IPv6" => match (address("Source"), address("Destination")) {
+                (Some(source), Some(destination)) => Some((source, destination)),
+                _ => endpoints,
+            },
+            _ => endpoints,
+        };
+
+        match name {
+            "VXLAN" | "GENEVE" => if let Some(vni) = number("VNI") {
+                self.vnis.entry((name, vni)).or_insert_with(Traffic::default).add(length);
+            },
+            "GRE" | "ESP" | "AH" => if let Some((ref source, ref destination)) = endpoints {
+                let tunnel = Tunnel {
+                    protocol: name,
+                    source: source.clone(),
+                    destination: destination.clone(),
+                    key: number("Key").or(number("SPI")),
+                };
+                self.tunnels.entry(tunnel).or_insert_with(Traffic::default).add(length);
+            },
+            _ => {},
+        }
+
+        for &(_, ref v) in values {
+            if let &Val::Payload(_, Ok(ref inner)) = v {
+                self.walk(inner, endpoints.clone(), length);
+            }
+        }
+    }
+}
+
+impl Tap for TunnelStats {
+    fn tap(&mut self, info: &PacketInfo, _layer: &Val) {
+        if self.last_packet == Some(info.number) {
+            return;
+        }
+        self.last_packet = Some(info.number);
+
+        let vlan = info.packet.as_object()
+            .and_then(|(_, values)| values.iter().find(|&&(k, _)| k == "VLAN Tag"))
+            .and_then(|&(_, ref tag)| tag["ID"].as_unsigned());
+
+        match vlan {
+            Some(id) => self.vlans.entry(id as u16).or_insert_with(Traffic::default).add(info.length),
+            None => self.untagged.add(info.length),
+        }
+
+        self.walk(info.packet, None, info.length);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ethernet;
+    use tap::Taps;
+
+    const INNER: [u8; 34] = [
+        0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00,
+        0x45, 0, 0, 20, 0, 0, 0, 0, 64, 253, 0, 0, 192, 168, 0, 1, 192, 168, 0, 2];
+
+    fn frame(vlan: Option<u8>, protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1];
+        if let Some(id) = vlan {
+            data.extend_from_slice(&[0x81, 0x00, 0x00, id]);
+        }
+        data.extend_from_slice(&[0x08, 0x00, 0x45, 0, 0, 0, 0, 0, 0, 0, 64, protocol, 0, 0,
+                                 10, 0, 0, 1, 10, 0, 0, 2]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn tunnel_breakdown() {
+        let mut vxlan = vec![0x12, 0xb5, 0x12, 0xb5, 0, 0, 0, 0, 0x08, 0, 0, 0, 0x00, 0x00, 0x07, 0];
+        vxlan.extend_from_slice(&INNER);
+        let mut gre = vec![0x20, 0x00, 0x65, 0x58, 0, 0, 0, 9];
+        gre.extend_from_slice(&INNER);
+
+        let packets = vec![frame(Some(10), 17, &vxlan), frame(Some(10), 17, &vxlan),
+                           frame(None, 47, &gre), frame(Some(20), 253, &[])];
+
+        let mut stats = TunnelStats::new();
+        {
+            let mut taps = Taps::new();
+            taps.register_all(&["Ethernet frame", "IPv4"], &mut stats);
+            for (i, data) in packets.iter().enumerate() {
+                let val = ethernet::dissect(data).unwrap();
+                taps.dispatch(Duration::new(i as u64, 0), data.len(), &val);
+            }
+        }
+
+        assert_eq!(stats.vlans()[&10].packets, 2);
+        assert_eq!(stats.vlans()[&20].packets, 1);
+        assert_eq!(stats.untagged().packets, 1);
+        assert_eq!(stats.vnis()[&("VXLAN", 7)].packets, 2);
+
+        let (tunnel, traffic) = stats.tunnels().iter().next().unwrap();
+        assert_eq!((tunnel.protocol, &tunnel.source[..], tunnel.key), ("GRE", "10.0.0.1", Some(9)));
+        assert_eq!(traffic.bytes, packets[2].len() as u64);
+        assert!(stats.report().contains("VXLAN 7"));
+    }
+}
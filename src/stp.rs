@@ -0,0 +1,128 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of Spanning Tree Protocol bridge PDUs (BPDUs): 802.1D
+//! configuration and topology change notification (TCN) BPDUs and 802.1w
+//! rapid spanning tree (RST) BPDUs.
+//!
+//! BPDUs are carried by LLC (DSAP and SSAP 0x42) in length-typed frames.
+//! Timers are in units of 1/256 s on the wire and are dissected as such.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use {read_be_u16, read_be_u32};
+
+enum_map!(pub BPDU_TYPES {
+    0x00 => "Configuration",
+    0x02 => "Rapid/Multiple Spanning Tree",
+    0x80 => "Topology Change Notification",
+});
+
+enum_map!(pub PORT_ROLES {
+    0 => "Unknown",
+    1 => "Alternate or Backup",
+    2 => "Root",
+    3 => "Designated",
+});
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    if data.len() < 4 {
+        return Err(DissectError::Underflow { expected: Some(4), have: data.len(),
+            message: "A BPDU must be at least 4 B".to_string() });
+    }
+
+    let bpdu_type = data[3];
+
+    let mut values = NamedValues::new();
+    values.push(("Protocol Identifier", Val::Unsigned(read_be_u16(data, 0)? as u64)));
+    values.push(("Version", Val::Unsigned(data[2] as u64)));
+    values.push(("BPDU Type", Val::Unsigned(bpdu_type as u64)));
+    values.push(("BPDU Type Name", BPDU_TYPES.val(bpdu_type)));
+
+    if bpdu_type == 0x80 {
+        return Ok(Box::new(Val::Object("STP", values)));
+    }
+
+    let length = if bpdu_type == 0x02 { 36 } else { 35 };
+    if data.len() < length {
+        return Err(DissectError::Underflow { expected: Some(length), have: data.len(),
+            message: format!["A {} BPDU must be {} B", BPDU_TYPES.format(bpdu_type), length] });
+    }
+
+    let flags = data[4];
+    values.push(("Flags", Val::BitFlags8(flags, [
+                 Some("Topology Change"), Some("Proposal"), None, None,
+                 Some("Learning"), Some("Forwarding"), Some("Agreement"),
+                 Some("Topology Change Acknowledgment")])));
+    if bpdu_type == 0x02 {
+        values.push(("Port Role", PORT_ROLES.val((flags >> 2) & 0x03)));
+    }
+
+    values.push(("Root Identifier", bridge_id(&data[5..13])?));
+    values.push(("Root Path Cost", Val::Unsigned(read_be_u32(data, 13)? as u64)));
+    values.push(("Bridge Identifier", bridge_id(&data[17..25])?));
+    values.push(("Port Identifier", Val::Unsigned(read_be_u16(data, 25)? as u64)));
+    values.push(("Message Age", Val::Unsigned(read_be_u16(data, 27)? as u64)));
+    values.push(("Max Age", Val::Unsigned(read_be_u16(data, 29)? as u64)));
+    values.push(("Hello Time", Val::Unsigned(read_be_u16(data, 31)? as u64)));
+    values.push(("Forward Delay", Val::Unsigned(read_be_u16(data, 33)? as u64)));
+
+    if bpdu_type == 0x02 {
+        values.push(("Version 1 Length", Val::Unsigned(data[35] as u64)));
+    }
+
+    Ok(Box::new(Val::Object("STP", values)))
+}
+
+/// A bridge identifier: a 4 b priority, a 12 b system ID extension (usually
+/// the VLAN) and a MAC address.
+fn bridge_id(data: &[u8]) -> Result<Val, DissectError> {
+    let priority = read_be_u16(data, 0)?;
+
+    let mut values = NamedValues::new();
+    values.push(("Priority", Val::Unsigned((priority & 0xf000) as u64)));
+    values.push(("System ID Extension", Val::Unsigned((priority & 0x0fff) as u64)));
+    values.push(("Address", Val::Address {
+        bytes: &data[2..8],
+        encoded: data[2..8].iter().map(|b| format!["{:02x}", b]).collect::<Vec<_>>().join(":"),
+    }));
+
+    Ok(Val::Object("Bridge Identifier", values))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_stp() {
+        // An RST BPDU from a designated, forwarding port of bridge 32768/1
+        let rst = [0x00, 0x00, 0x02, 0x02, 0x3c,
+                   0x80, 0x01, 0x00, 0x1b, 0x2c, 0x3d, 0x4e, 0x5f,
+                   0x00, 0x00, 0x00, 0x04,
+                   0x80, 0x01, 0x00, 0x1b, 0x2c, 0x3d, 0x4e, 0x60,
+                   0x80, 0x02, 0x00, 0x01, 0x14, 0x00, 0x02, 0x00, 0x0f, 0x00, 0x00];
+        let val = *dissect(&rst).unwrap();
+
+        assert_eq!(val["BPDU Type Name"].as_symbol().unwrap(), "Rapid/Multiple Spanning Tree");
+        assert_eq!(val["Port Role"].as_symbol().unwrap(), "Designated");
+        assert_eq!(val["Flags"].as_bitflags8_bit_name("Forwarding"), Some(true));
+        assert_eq!(val["Root Identifier"]["Priority"].as_unsigned().unwrap(), 32768);
+        assert_eq!(val["Root Identifier"]["System ID Extension"].as_unsigned().unwrap(), 1);
+        assert_eq!(val["Root Identifier"]["Address"].as_address_encoded().unwrap(), "00:1b:2c:3d:4e:5f");
+        assert_eq!(val["Root Path Cost"].as_unsigned().unwrap(), 4);
+        assert_eq!(val["Hello Time"].as_unsigned().unwrap(), 2 * 256);
+
+        let tcn = *dissect(&[0x00, 0x00, 0x00, 0x80]).unwrap();
+        assert_eq!(tcn["BPDU Type Name"].as_symbol().unwrap(), "Topology Change Notification");
+        assert!(dissect(&rst[..20]).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! One-line packet summaries: the columns of Wireshark's packet list.
+//!
+//! `summary` follows each layer's "Payload" down the protocol stack. The
+//! source and destination are the addresses of the innermost layer that has
+//! them (so IP addresses rather than MACs, where there are both), the
+//! protocol is the innermost layer other than raw data and the info column
+//! is written by the innermost layer with an entry in `INFO`.
+//!
+//! ```
+//! let packet = [
+//!     0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+//!     0x45, 0x00, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+//!     10, 0, 0, 1, 10, 0, 0, 2,
+//!     0xd4, 0x31, 0x04, 0xd2, 0x00, 0x0a, 0x00, 0x00,
+//!     b'h', b'i'];
+//! let val = rshark::ethernet::dissect(&packet).unwrap();
+//!
+//! let summary = rshark::summary::summary(&val);
+//! assert_eq!(summary.to_string(), "10.0.0.1 → 10.0.0.2 UDP 54321 → 1234 Len=2");
+//! ```
+
+use std::fmt;
+
+use Val;
+
+/// Writes the info column for a layer, given the layer's `Val::Object`.
+pub type InfoBuilder = fn(&Val) -> Option<String>;
+
+/// The info column builders, by layer name.
+pub static INFO: &'static [(&'static str, InfoBuilder)] = &[
+    ("Ethernet frame", ethernet),
+    ("ICMP", icmp),
+    ("TCP", tcp),
+    ("UDP", udp),
+];
+
+/// The columns of a packet list.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Summary {
+    pub source: String,
+    pub destination: String,
+    pub protocol: &'static str,
+    pub info: String,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{} → {} {} {}", self.source, self.destination, self.protocol, self.info]
+    }
+}
+
+/// Summarize a dissected packet.
+pub fn summary(packet: &Val) -> Summary {
+    let mut summary = Summary::default();
+
+    let mut layer = match *packet {
+        Val::Payload(_, Ok(ref inner)) => &**inner,
+        _ => packet,
+    };
+
+    while let Some((name, _)) = layer.as_object() {
+        if let (Ok(source), Ok(destination)) = (layer.get("Source"), layer.get("Destination")) {
+            if source.is_address() && destination.is_address() {
+                summary.source = source.to_string();
+                summary.destination = destination.to_string();
+            }
+        }
+
+        if name != "Data" {
+            summary.protocol = name;
+        }
+
+        if let Some(info) = INFO.iter().find(|&&(n, _)| n == name).and_then(|&(_, f)| f(layer)) {
+            summary.info = info;
+        }
+
+        layer = match layer.get("Payload") {
+            Ok(&Val::Payload(_, Ok(ref inner))) => inner,
+            _ => break,
+        };
+    }
+
+    summary
+}
+
+fn ethernet(layer: &Val) -> Option<String> {
+    layer.get("Type").ok()
+        .and_then(|t| t.as_enum())
+        .map(|(value, name)| match name {
+            Some(name) => format!["Ethertype {}", name],
+            None => format!["Ethertype 0x{:04x}", value],
+        })
+}
+
+fn icmp(layer: &Val) -> Option<String> {
+    let kind = match layer.get("Type Name").ok().and_then(|n| n.as_symbol()) {
+        Some(name) => name.to_string(),
+        None => format!["Type {}", layer.get("Type").ok()?.as_unsigned()?],
+    };
+
+    match (layer.get("Identifier").ok(), layer.get("Sequence Number").ok()) {
+        (Some(id), Some(seq)) => Some(format!["{} id={} seq={}", kind, show(id), show(seq)]),
+        _ => Some(kind),
+    }
+}
+
+fn tcp(layer: &Val) -> Option<String> {
+    let (source, destination) = ports(layer)?;
+    let flags = layer.get("Flags").ok()?;
+    let (bits, names) = flags.as_bitflags()?;
+
+    let set = names.iter().enumerate()
+        .filter(|&(i, _)| bits >> i & 1 == 1)
+        .filter_map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+
+    let mut info = format!["{} → {} [{}] Seq={}", source, destination, set.join(", "),
+                           layer.get("Sequence Number").ok()?];
+    if flags.as_bitflags_bit_name("ACK") == Some(true) {
+        info.push_str(&format![" Ack={}", layer.get("Acknowledgement Number").ok()?]);
+    }
+    info.push_str(&format![" Win={} Len={}", layer.get("Window").ok()?, payload_length(layer)]);
+
+    Some(info)
+}
+
+fn udp(layer: &Val) -> Option<String> {
+    let (source, destination) = ports(layer)?;
+    Some(format!["{} → {} Len={}", source, destination, payload_length(layer)])
+}
+
+/// A layer's source and destination port numbers.
+fn ports(layer: &Val) -> Option<(u64, u64)> {
+    let port = |name| layer.get(name).ok().and_then(|p| p.as_enum()).map(|(n, _)| n);
+    Some((port("Source Port")?, port("Destination Port")?))
+}
+
+fn payload_length(layer: &Val) -> usize {
+    match layer.get("Payload") {
+        Ok(&Val::Payload(bytes, _)) | Ok(&Val::Undissected(_, bytes)) | Ok(&Val::Bytes(bytes)) =>
+            bytes.len(),
+        _ => 0,
+    }
+}
+
+/// A number as a number, or a byte string in hex.
+fn show(val: &Val) -> String {
+    match *val {
+        Val::Bytes(bytes) => format!["0x{}", bytes.iter().map(|b| format!["{:02x}", b])
+                                               .collect::<String>()],
+        ref other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethernet::dissect;
+
+    #[test]
+    fn summarize_tcp() {
+        // 192.168.0.1:443 -> 192.168.0.2:64747, SYN+ACK
+        let packet = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00,
+            192, 168, 0, 1, 192, 168, 0, 2,
+            0x01, 0xbb, 0xfc, 0xeb, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x0b,
+            0x50, 0x12, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00];
+
+        let val = dissect(&packet).unwrap();
+        assert_eq!(summary(&val), Summary {
+            source: "192.168.0.1".to_string(),
+            destination: "192.168.0.2".to_string(),
+            protocol: "TCP",
+            info: "443 → 64747 [SYN, ACK] Seq=100 Ack=11 Win=65535 Len=0".to_string(),
+        });
+    }
+
+    #[test]
+    fn summarize_link_layer() {
+        let mut packet = vec![0xff; 6];
+        packet.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x88, 0xb5, 1, 2]);
+
+        let val = dissect(&packet).unwrap();
+        let summary = summary(&val);
+        assert_eq!(summary.source, "00:11:22:33:44:55");
+        assert_eq!(summary.destination, "ff:ff:ff:ff:ff:ff");
+        assert_eq!(summary.protocol, "Ethernet frame");
+        assert_eq!(summary.info, "Ethertype Local Experimental");
+    }
+}
@@ -0,0 +1,232 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Subscription to dissection events ("taps").
+//!
+//! A `Tap` registers interest in a protocol layer (i.e., a `Val::Object`
+//! name such as `"UDP"` or `"SMB2"`) with a `Taps` dispatcher. Each dissected
+//! packet is walked once by the dispatcher, which hands every matching layer
+//! to its listeners along with information about the packet that carried it.
+//! Statistics, exporters and analyses should be built as taps rather than
+//! each re-walking the dissection tree.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::tap::{Counter, Taps};
+//!
+//! let mut frames = Counter::new();
+//! let mut ipv4 = Counter::new();
+//! {
+//!     let mut taps = Taps::new();
+//!     taps.register("Ethernet frame", &mut frames);
+//!     taps.register("IPv4", &mut ipv4);
+//!
+//!     let data = [0; 14];
+//!     let packet = rshark::ethernet::dissect(&data).unwrap();
+//!     taps.dispatch(Duration::new(0, 0), data.len(), &packet);
+//! }
+//!
+//! assert_eq!(frames.packets, 1);
+//! assert_eq!(ipv4.packets, 0);
+//! ```
+
+use std::time::Duration;
+
+use Val;
+use progress::Reporter;
+
+/// Information about the packet that a tapped layer was found in.
+pub struct PacketInfo<'a> {
+    /// Sequence number of the packet, starting at 1.
+    pub number: u64,
+
+    /// Capture time, since the Unix epoch.
+    pub timestamp: Duration,
+
+    /// Length of the packet as captured.
+    pub length: usize,
+
+    /// The complete dissection of the packet.
+    pub packet: &'a Val<'a>,
+}
+
+/// A listener for dissection events.
+pub trait Tap {
+    /// Called for each occurrence of the tapped layer within a packet.
+    fn tap(&mut self, info: &PacketInfo, layer: &Val);
+}
+
+impl<'a, T: Tap + ?Sized> Tap for &'a mut T {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        (**self).tap(info, layer)
+    }
+}
+
+/// Adapts a closure to the `Tap` trait.
+struct FnTap<F>(F);
+
+impl<F> Tap for FnTap<F> where F: FnMut(&PacketInfo, &Val) {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        (self.0)(info, layer)
+    }
+}
+
+/// Dispatches dissected packets to the taps registered for their layers.
+pub struct Taps<'t> {
+    listeners: Vec<(Vec<&'static str>, Box<dyn Tap + 't>)>,
+    packets: u64,
+    bytes: u64,
+    progress: Option<Reporter<'t>>,
+}
+
+impl<'t> Taps<'t> {
+    pub fn new() -> Taps<'t> {
+        Taps { listeners: Vec::new(), packets: 0, bytes: 0, progress: None }
+    }
+
+    /// Register a tap for every layer named `layer`. A listener may be
+    /// passed by value or (to read its results afterwards) by reference.
+    pub fn register<T>(&mut self, layer: &'static str, tap: T) where T: Tap + 't {
+        self.listeners.push((vec![layer], Box::new(tap)));
+    }
+
+    /// Register one tap for every layer named in `layers` (e.g., both "IPv4"
+    /// and "IPv6").
+    pub fn register_all<T>(&mut self, layers: &[&'static str], tap: T) where T: Tap + 't {
+        self.listeners.push((layers.to_vec(), Box::new(tap)));
+    }
+
+    /// Register a closure as a tap for every layer named `layer`.
+    pub fn register_fn<F>(&mut self, layer: &'static str, f: F)
+        where F: FnMut(&PacketInfo, &Val) + 't {
+
+        self.register(layer, FnTap(f));
+    }
+
+    /// The number of packets dispatched so far.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Report the progress of the statistics pass as packets are dispatched.
+    pub fn report_progress(&mut self, reporter: Reporter<'t>) {
+        self.progress = Some(reporter);
+    }
+
+    /// Signal the end of the pass to the progress reporter, if any.
+    pub fn finish(&mut self) {
+        if let Some(ref mut reporter) = self.progress {
+            reporter.finish(self.packets, self.bytes);
+        }
+    }
+
+    /// Deliver a dissected packet to the registered taps.
+    pub fn dispatch(&mut self, timestamp: Duration, length: usize, packet: &Val) {
+        self.packets += 1;
+        self.bytes += length as u64;
+
+        let info = PacketInfo {
+            number: self.packets,
+            timestamp: timestamp,
+            length: length,
+            packet: packet,
+        };
+
+        if !self.listeners.is_empty() {
+            self.walk(&info, packet);
+        }
+
+        if let Some(ref mut reporter) = self.progress {
+            reporter.update(self.packets, self.bytes);
+        }
+    }
+
+    fn walk(&mut self, info: &PacketInfo, val: &Val) {
+        match val {
+            &Val::Object(name, ref values) => {
+                for &mut (ref layers, ref mut tap) in self.listeners.iter_mut() {
+                    if layers.contains(&name) {
+                        tap.tap(info, val);
+                    }
+                }
+
+                for &(_, ref v) in values {
+                    self.walk(info, v);
+                }
+            },
+            &Val::List(ref values) => for v in values {
+                self.walk(info, v);
+            },
+            &Val::Payload(_, Ok(ref inner)) => self.walk(info, inner),
+            _ => {},
+        }
+    }
+}
+
+/// A tap that counts the packets (and bytes) containing a layer.
+///
+/// A packet that contains the layer more than once is only counted once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+    last: u64,
+}
+
+impl Counter {
+    pub fn new() -> Counter {
+        Counter::default()
+    }
+}
+
+impl Tap for Counter {
+    fn tap(&mut self, info: &PacketInfo, _: &Val) {
+        if info.number != self.last {
+            self.last = info.number;
+            self.packets += 1;
+            self.bytes += info.length as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use Val;
+
+    #[test]
+    fn dispatch_layers() {
+        let packet = Val::Object("Outer", vec![
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", vec![
+                ("Value", Val::Unsigned(42)),
+                ("Nested", Val::Object("Inner", vec![])),
+            ]))))),
+        ]);
+
+        let mut inner = Counter::new();
+        let mut values = Vec::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("Inner", &mut inner);
+            taps.register_fn("Inner", |info: &PacketInfo, layer: &Val| {
+                values.push((info.number, layer.get("Value").ok().and_then(|v| v.as_unsigned())));
+            });
+            taps.register_fn("Missing", |_: &PacketInfo, _: &Val| panic!("unexpected layer"));
+
+            taps.dispatch(Duration::new(1, 0), 100, &packet);
+            taps.dispatch(Duration::new(2, 0), 100, &packet);
+            assert_eq!(taps.packets(), 2);
+        }
+
+        assert_eq!(inner.packets, 2);
+        assert_eq!(inner.bytes, 200);
+        assert_eq!(values, vec![(1, Some(42)), (1, None), (2, Some(42)), (2, None)]);
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Times and durations carried by packets.
+//!
+//! `Val::Timestamp` holds a point in time (relative to the Unix epoch) and
+//! `Val::Duration` a span of time, both as a `Time`. Dissectors convert from
+//! the protocol's own representation (e.g., `Time::from_ntp`) and leave the
+//! formatting to front-ends: a `Val` displays timestamps in UTC, while
+//! `format_timestamp` can also show them as seconds since the epoch or
+//! relative to another time, e.g., the first packet of a capture.
+//!
+//! ```
+//! use rshark::time::{format_timestamp, Time, TimeFormat};
+//! use rshark::Val;
+//!
+//! let t = Time::new(1444004800, 123456789);
+//! assert_eq!(Val::Timestamp(t).to_string(), "2015-10-05T00:26:40.123456789Z");
+//! assert_eq!(format_timestamp(t, TimeFormat::Epoch), "1444004800.123456789");
+//!
+//! let start = Time::new(1444004799, 0);
+//! assert_eq!(format_timestamp(t, TimeFormat::Relative(start)), "+1.123456789");
+//! ```
+
+use std::time::Duration;
+
+const NANOS_PER_SECOND: u32 = 1_000_000_000;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_EPOCH_OFFSET: i64 = 2_208_988_800;
+
+/// A time (or difference between times) in seconds and nanoseconds. The
+/// nanoseconds are always positive, so -1.5 s is -2 s + 500,000,000 ns.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Time {
+    pub seconds: i64,
+    pub nanoseconds: u32,
+}
+
+impl Time {
+    /// A time from seconds and nanoseconds, carrying whole seconds out of
+    /// the nanoseconds.
+    pub fn new(seconds: i64, nanoseconds: u32) -> Time {
+        Time {
+            seconds: seconds + (nanoseconds / NANOS_PER_SECOND) as i64,
+            nanoseconds: nanoseconds % NANOS_PER_SECOND,
+        }
+    }
+
+    pub fn from_duration(d: Duration) -> Time {
+        Time::new(d.as_secs() as i64, d.subsec_nanos())
+    }
+
+    pub fn from_micros(micros: u64) -> Time {
+        Time::new((micros / 1_000_000) as i64, (micros % 1_000_000) as u32 * 1000)
+    }
+
+    /// A 64 b NTP timestamp: 32.32 fixed-point seconds since 1900 (RFC 5905).
+    pub fn from_ntp(ntp: u64) -> Time {
+        let fraction = ((ntp & 0xffff_ffff) * NANOS_PER_SECOND as u64) >> 32;
+        Time::new((ntp >> 32) as i64 - NTP_EPOCH_OFFSET, fraction as u32)
+    }
+
+    /// A 32 b NTP short format duration: 16.16 fixed-point seconds.
+    pub fn from_ntp_short(ntp: u32) -> Time {
+        let fraction = ((ntp & 0xffff) as u64 * NANOS_PER_SECOND as u64) >> 16;
+        Time::new((ntp >> 16) as i64, fraction as u32)
+    }
+
+    /// The time elapsed from `earlier` to this time (negative if `earlier`
+    /// is in fact later).
+    pub fn since(&self, earlier: Time) -> Time {
+        let mut seconds = self.seconds - earlier.seconds;
+        let nanoseconds = if self.nanoseconds >= earlier.nanoseconds {
+            self.nanoseconds - earlier.nanoseconds
+        } else {
+            seconds -= 1;
+            self.nanoseconds + NANOS_PER_SECOND - earlier.nanoseconds
+        };
+
+        Time { seconds: seconds, nanoseconds: nanoseconds }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.seconds < 0
+    }
+}
+
+/// How to show a timestamp.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimeFormat {
+    /// An ISO 8601 date and time in UTC.
+    Utc,
+
+    /// Seconds since the Unix epoch.
+    Epoch,
+
+    /// Seconds since (or before) a reference time.
+    Relative(Time),
+}
+
+impl Default for TimeFormat {
+    fn default() -> TimeFormat {
+        TimeFormat::Utc
+    }
+}
+
+pub fn format_timestamp(t: Time, format: TimeFormat) -> String {
+    match format {
+        TimeFormat::Utc => {
+            let (days, secs) = (t.seconds.div_euclid(86400), t.seconds.rem_euclid(86400));
+            let (year, month, day) = civil_from_days(days);
+            format!["{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+                    year, month, day, secs / 3600, secs / 60 % 60, secs % 60, t.nanoseconds]
+        },
+        TimeFormat::Epoch => format_seconds(t),
+        TimeFormat::Relative(reference) => {
+            let elapsed = t.since(reference);
+            format!["{}{}", if elapsed.is_negative() { "" } else { "+" }, format_seconds(elapsed)]
+        },
+    }
+}
+
+/// Format a duration in seconds, e.g., "0.000250000 s".
+pub fn format_duration(t: Time) -> String {
+    format!["{} s", format_seconds(t)]
+}
+
+/// Format a time as (signed) seconds, e.g., "-1.500000000".
+pub fn format_seconds(t: Time) -> String {
+    if t.is_negative() && t.nanoseconds > 0 {
+        format!["-{}.{:09}", -(t.seconds + 1), NANOS_PER_SECOND - t.nanoseconds]
+    } else {
+        format!["{}.{:09}", t.seconds, t.nanoseconds]
+    }
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) civil date in
+/// the proleptic Gregorian calendar; see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn convert_times() {
+        // 2015-10-05T00:26:40.5Z
+        let ntp = (1444004800 + NTP_EPOCH_OFFSET as u64) << 32 | 0x8000_0000;
+        assert_eq!(Time::from_ntp(ntp), Time::new(1444004800, 500_000_000));
+        assert_eq!(Time::from_ntp_short(0x0001_4000), Time::new(1, 250_000_000));
+        assert_eq!(Time::from_micros(2_000_001), Time::new(2, 1000));
+        assert_eq!(Time::new(1, 1_500_000_000), Time::new(2, 500_000_000));
+
+        assert_eq!(Time::new(1, 0).since(Time::new(2, 500_000_000)), Time::new(-2, 500_000_000));
+    }
+
+    #[test]
+    fn format_times() {
+        let t = Time::new(951782400, 0);
+        assert_eq!(format_timestamp(t, TimeFormat::Utc), "2000-02-29T00:00:00.000000000Z");
+        assert_eq!(format_timestamp(Time::new(-1, 0), TimeFormat::Utc),
+                   "1969-12-31T23:59:59.000000000Z");
+        assert_eq!(format_timestamp(t, TimeFormat::Relative(Time::new(951782401, 500_000_000))),
+                   "-1.500000000");
+        assert_eq!(format_duration(Time::new(0, 250_000)), "0.000250000 s");
+    }
+}
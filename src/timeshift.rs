@@ -0,0 +1,240 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Correction of capture timestamps, and merging of captures by time.
+//!
+//! Captures taken on different devices are only comparable if their clocks
+//! agree. A `ClockCorrection` shifts timestamps by a constant offset (as
+//! `editcap -t` does) and can also remove a linear drift (skew), e.g., one
+//! measured from two events seen by both devices. Correcting each capture's
+//! source before merging them with `MergeSource` puts their packets in the
+//! order in which they really happened.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::source::{Packet, PacketSource, VecSource};
+//! use rshark::timeshift::{ClockCorrection, MergeSource, ShiftedSource};
+//!
+//! let packet = |s| Packet { timestamp: Duration::new(s, 0), original_length: 0, data: vec![] };
+//! let a = VecSource::new(vec![packet(10), packet(30)], 1);
+//!
+//! // This device's clock is 15 s fast.
+//! let b = VecSource::new(vec![packet(35)], 1);
+//! let b = ShiftedSource::new(b, ClockCorrection::new().shift_secs(-15.0));
+//!
+//! let mut merged = MergeSource::new(vec![Box::new(a), Box::new(b)]).unwrap();
+//! let mut times = Vec::new();
+//! while let Some(packet) = merged.next_packet() {
+//!     times.push(packet.unwrap().timestamp.as_secs());
+//! }
+//! assert_eq!(times, vec![10, 20, 30]);
+//! ```
+
+use std::io;
+use std::time::Duration;
+
+use source::{Packet, PacketSource, SourceStats};
+
+/// A correction of timestamps from one clock: an offset, plus a skew that
+/// grows linearly with time since a reference instant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockCorrection {
+    /// Nanoseconds to add to every timestamp.
+    offset: i64,
+
+    /// Parts per million by which the clock runs fast (positive) or slow.
+    skew_ppm: f64,
+
+    /// The (uncorrected) time at which skew has accumulated to nothing.
+    reference: Duration,
+}
+
+impl ClockCorrection {
+    /// No correction at all.
+    pub fn new() -> ClockCorrection {
+        ClockCorrection { offset: 0, skew_ppm: 0.0, reference: Duration::new(0, 0) }
+    }
+
+    /// Shift all timestamps by `seconds`, which may be negative.
+    pub fn shift_secs(mut self, seconds: f64) -> ClockCorrection {
+        self.offset = (seconds * 1e9) as i64;
+        self
+    }
+
+    /// Shift all timestamps by a number of nanoseconds.
+    pub fn shift_nanos(mut self, nanoseconds: i64) -> ClockCorrection {
+        self.offset = nanoseconds;
+        self
+    }
+
+    /// Remove a drift of `ppm` parts per million (positive if the clock runs
+    /// fast), which had accumulated to nothing at `reference`.
+    pub fn skew_ppm(mut self, ppm: f64, reference: Duration) -> ClockCorrection {
+        self.skew_ppm = ppm;
+        self.reference = reference;
+        self
+    }
+
+    /// The correction that maps two times observed by a clock to the true
+    /// times of the same two events.
+    pub fn from_points(first: (Duration, Duration), second: (Duration, Duration)) -> ClockCorrection {
+        let ((observed1, true1), (observed2, true2)) = (first, second);
+        let observed = nanos(observed2) - nanos(observed1);
+        let actual = nanos(true2) - nanos(true1);
+
+        let ppm = if observed == 0 { 0.0 } else { (observed - actual) as f64 / actual as f64 * 1e6 };
+        ClockCorrection {
+            offset: nanos(true1) - nanos(observed1),
+            skew_ppm: ppm,
+            reference: observed1,
+        }
+    }
+
+    /// The corrected form of `timestamp` (never earlier than the epoch).
+    pub fn apply(&self, timestamp: Duration) -> Duration {
+        let t = nanos(timestamp);
+        let elapsed = (t - nanos(self.reference)) as f64;
+        let drift = elapsed - elapsed / (1.0 + self.skew_ppm / 1e6);
+        let corrected = t + self.offset - drift.round() as i64;
+
+        if corrected <= 0 {
+            Duration::new(0, 0)
+        } else {
+            Duration::new((corrected / 1_000_000_000) as u64, (corrected % 1_000_000_000) as u32)
+        }
+    }
+}
+
+fn nanos(d: Duration) -> i64 {
+    d.as_secs() as i64 * 1_000_000_000 + d.subsec_nanos() as i64
+}
+
+/// A source whose packets' timestamps are corrected.
+pub struct ShiftedSource<S> {
+    source: S,
+    correction: ClockCorrection,
+}
+
+impl<S: PacketSource> ShiftedSource<S> {
+    pub fn new(source: S, correction: ClockCorrection) -> ShiftedSource<S> {
+        ShiftedSource { source: source, correction: correction }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: PacketSource> PacketSource for ShiftedSource<S> {
+    fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+        let correction = self.correction;
+        self.source.next_packet().map(|result| result.map(|mut packet| {
+            packet.timestamp = correction.apply(packet.timestamp);
+            packet
+        }))
+    }
+
+    fn link_type(&self) -> u32 {
+        self.source.link_type()
+    }
+
+    fn stats(&self) -> SourceStats {
+        self.source.stats()
+    }
+
+    fn packet_count(&self) -> Option<u64> {
+        self.source.packet_count()
+    }
+
+    fn seek(&mut self, index: u64) -> io::Result<()> {
+        self.source.seek(index)
+    }
+}
+
+/// Packets from several sources of the same link type, in timestamp order
+/// (assuming that each source is itself in order).
+pub struct MergeSource {
+    sources: Vec<Box<dyn PacketSource>>,
+    heads: Vec<Option<Packet>>,
+    link_type: u32,
+    stats: SourceStats,
+}
+
+impl MergeSource {
+    pub fn new(sources: Vec<Box<dyn PacketSource>>) -> io::Result<MergeSource> {
+        let link_type = sources.first().map(|s| s.link_type()).unwrap_or(0);
+        if let Some(other) = sources.iter().find(|s| s.link_type() != link_type) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!["can't merge link types {} and {}", link_type, other.link_type()]));
+        }
+
+        let heads = sources.iter().map(|_| None).collect();
+        Ok(MergeSource { sources: sources, heads: heads, link_type: link_type, stats: SourceStats::default() })
+    }
+}
+
+impl PacketSource for MergeSource {
+    fn next_packet(&mut self) -> Option<io::Result<Packet>> {
+        for (source, head) in self.sources.iter_mut().zip(self.heads.iter_mut()) {
+            if head.is_none() {
+                match source.next_packet() {
+                    Some(Ok(packet)) => *head = Some(packet),
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => {},
+                }
+            }
+        }
+
+        let earliest = self.heads.iter()
+            .enumerate()
+            .filter_map(|(i, h)| h.as_ref().map(|p| (p.timestamp, i)))
+            .min()
+            .map(|(_, i)| i);
+
+        earliest.and_then(|i| self.heads[i].take()).map(|packet| {
+            self.stats.count(&packet);
+            Ok(packet)
+        })
+    }
+
+    fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    fn stats(&self) -> SourceStats {
+        let dropped = self.sources.iter().filter_map(|s| s.stats().dropped).fold(None, |total, d|
+            Some(total.unwrap_or(0) + d));
+        SourceStats { dropped: dropped, ..self.stats.clone() }
+    }
+
+    fn packet_count(&self) -> Option<u64> {
+        self.sources.iter().map(|s| s.packet_count()).fold(Some(0), |total, n|
+            total.and_then(|t| n.map(|n| t + n)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn correct_clocks() {
+        let shift = ClockCorrection::new().shift_secs(-1.5);
+        assert_eq!(shift.apply(Duration::new(10, 0)), Duration::new(8, 500_000_000));
+        assert_eq!(shift.apply(Duration::new(1, 0)), Duration::new(0, 0));
+
+        // A clock that gains 100 ppm (0.1 ms/s) and was 2 s behind at t=1000
+        let observed = |t: u64| Duration::new(t - 2, 0) + Duration::from_micros((t - 1000) * 100);
+        let correction = ClockCorrection::from_points((observed(1000), Duration::new(1000, 0)),
+                                                      (observed(2000), Duration::new(2000, 0)));
+        assert_eq!(correction.apply(observed(1500)), Duration::new(1500, 0));
+        assert_eq!(correction.apply(observed(3000)), Duration::new(3000, 0));
+    }
+}
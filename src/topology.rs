@@ -0,0 +1,304 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Inference of network topology from the link- and network-layer
+//! addresses in a capture.
+//!
+//! A capture handed over without context still says a lot about where it
+//! was taken. Hosts on the local link send from a MAC address of their own,
+//! while a gateway's MAC address carries traffic for many IP addresses;
+//! local hosts' addresses suggest the local subnets, and an IP address
+//! whose packets start with more than one initial TTL likely hides several
+//! hosts behind a NAT.
+//!
+//! ```
+//! use rshark::tap::Taps;
+//! use rshark::topology::Topology;
+//!
+//! let mut topology = Topology::new();
+//! {
+//!     let mut taps = Taps::new();
+//!     taps.register("Ethernet frame", &mut topology);
+//!     // ... dispatch packets ...
+//! }
+//! print!["{}", topology.summary()];
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::net::IpAddr;
+
+use Val;
+use cidr::Cidr;
+use flow::ip_addr;
+use tap::{PacketInfo, Tap};
+use ttl::initial_ttl;
+
+/// What was seen of one MAC address.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Station {
+    pub packets: u64,
+
+    /// IP source addresses of the packets sent by this station.
+    pub sources: BTreeSet<IpAddr>,
+
+    /// IP destination addresses of the packets sent by this station.
+    pub recipients: BTreeSet<IpAddr>,
+
+    /// IP destination addresses of the packets sent to this station.
+    pub destinations: BTreeSet<IpAddr>,
+}
+
+impl Station {
+    /// The number of distinct IP addresses this station has sent or
+    /// received on behalf of.
+    pub fn addresses(&self) -> usize {
+        self.sources.union(&self.destinations).count()
+    }
+}
+
+/// A subnet inferred from the addresses of local hosts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subnet {
+    pub prefix: Cidr,
+    pub hosts: Vec<IpAddr>,
+
+    /// The MAC addresses of gateways that delivered traffic to the subnet.
+    pub gateways: Vec<String>,
+}
+
+/// An IP address that likely has several hosts behind it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NatPoint {
+    pub address: IpAddr,
+
+    /// The distinct initial TTLs seen from the address.
+    pub initial_ttls: Vec<u8>,
+}
+
+pub struct Topology {
+    stations: BTreeMap<String, Station>,
+    initial_ttls: BTreeMap<IpAddr, BTreeSet<u8>>,
+    gateway_addresses: usize,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+}
+
+impl Topology {
+    pub fn new() -> Topology {
+        Topology {
+            stations: BTreeMap::new(),
+            initial_ttls: BTreeMap::new(),
+            gateway_addresses: 4,
+            ipv4_prefix: 24,
+            ipv6_prefix: 64,
+        }
+    }
+
+    /// How many distinct IP addresses a MAC address must serve before it is
+    /// considered a gateway. The default is 4.
+    pub fn gateway_addresses(mut self, n: usize) -> Topology {
+        self.gateway_addresses = n;
+        self
+    }
+
+    /// The prefix lengths that local hosts are grouped into subnets by. The
+    /// defaults are /24 and /64.
+    pub fn prefixes(mut self, ipv4: u8, ipv6: u8) -> Topology {
+        self.ipv4_prefix = ipv4;
+        self.ipv6_prefix = ipv6;
+        self
+    }
+
+    pub fn station(&self, mac: &str) -> Option<&Station> {
+        self.stations.get(mac)
+    }
+
+    /// MAC addresses that serve many IP addresses, busiest first.
+    pub fn gateways(&self) -> Vec<(&str, &Station)> {
+        let mut gateways = self.stations.iter()
+            .filter(|&(_, s)| s.addresses() >= self.gateway_addresses)
+            .map(|(mac, s)| (mac.as_str(), s))
+            .collect::<Vec<_>>();
+
+        gateways.sort_by(|a, b| b.1.addresses().cmp(&a.1.addresses()).then(a.0.cmp(b.0)));
+        gateways
+    }
+
+    /// Addresses sent from stations that are not gateways, i.e., hosts on
+    /// the local link.
+    pub fn local_hosts(&self) -> BTreeSet<IpAddr> {
+        self.stations.values()
+            .filter(|s| s.addresses() < self.gateway_addresses)
+            .flat_map(|s| s.sources.iter().cloned())
+            .filter(|a| !a.is_unspecified() && !a.is_multicast())
+            .collect()
+    }
+
+    /// Local hosts, grouped by prefix.
+    pub fn subnets(&self) -> Vec<Subnet> {
+        let mut subnets = BTreeMap::new();
+        for host in self.local_hosts() {
+            let length = if host.is_ipv4() { self.ipv4_prefix } else { self.ipv6_prefix };
+            let prefix = Cidr::network(&host, length);
+            subnets.entry(prefix.to_string()).or_insert((prefix, Vec::new())).1.push(host);
+        }
+
+        let gateways = self.gateways();
+        subnets.into_iter().map(|(_, (prefix, hosts))| {
+            let gateways = gateways.iter()
+                .filter(|&&(_, s)| hosts.iter().any(|h| s.recipients.contains(h)))
+                .map(|&(mac, _)| mac.to_string())
+                .collect();
+
+            Subnet { prefix: prefix, hosts: hosts, gateways: gateways }
+        })
+        .collect()
+    }
+
+    /// Addresses whose packets started with more than one initial TTL.
+    pub fn nat_points(&self) -> Vec<NatPoint> {
+        self.initial_ttls.iter()
+            .filter(|&(_, ttls)| ttls.len() > 1)
+            .map(|(&address, ttls)| NatPoint { address: address, initial_ttls: ttls.iter().cloned().collect() })
+            .collect()
+    }
+
+    /// A short topology summary.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "Subnets:").unwrap();
+        for subnet in self.subnets() {
+            writeln!(out, "  {:<24} {:>5} hosts  via {}", subnet.prefix.to_string(), subnet.hosts.len(),
+                     if subnet.gateways.is_empty() { "-".to_string() } else { subnet.gateways.join(", ") })
+                .unwrap();
+        }
+
+        writeln!(out, "Gateways:").unwrap();
+        for (mac, station) in self.gateways() {
+            writeln!(out, "  {}  {:>5} addresses {:>8} packets", mac, station.addresses(), station.packets)
+                .unwrap();
+        }
+
+        writeln!(out, "Likely NAT:").unwrap();
+        for nat in self.nat_points() {
+            writeln!(out, "  {:<40} initial TTLs {}", nat.address.to_string(),
+                     nat.initial_ttls.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", "))
+                .unwrap();
+        }
+
+        out
+    }
+
+    /// Account for a packet from MAC address `source_mac` to `destination_mac`.
+    pub fn observe(&mut self, source_mac: &[u8], destination_mac: &[u8],
+                   source: IpAddr, destination: IpAddr, ttl: Option<u8>) {
+
+        {
+            let station = self.stations.entry(mac(source_mac)).or_insert(Station::default());
+            station.packets += 1;
+            station.sources.insert(source);
+            station.recipients.insert(destination);
+        }
+
+        // Broadcast and multicast frames aren't addressed to a station.
+        if destination_mac.first().map(|b| b & 0x01 == 0).unwrap_or(false) {
+            let station = self.stations.entry(mac(destination_mac)).or_insert(Station::default());
+            station.destinations.insert(destination);
+        }
+
+        if let Some(ttl) = ttl {
+            self.initial_ttls.entry(source).or_insert(BTreeSet::new()).insert(initial_ttl(ttl));
+        }
+    }
+}
+
+fn mac(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!["{:02x}", b]).collect::<Vec<_>>().join(":")
+}
+
+/// Taps Ethernet frames.
+impl Tap for Topology {
+    fn tap(&mut self, _: &PacketInfo, layer: &Val) {
+        let (source_mac, destination_mac) = match (layer.get("Source"), layer.get("Destination")) {
+            (Ok(&Val::Address { bytes: s, .. }), Ok(&Val::Address { bytes: d, .. })) => (s, d),
+            _ => return,
+        };
+
+        let ip = match layer.get("Payload") {
+            Ok(&Val::Payload(_, Ok(ref ip))) => ip,
+            _ => return,
+        };
+
+        let address = |key| ip.get(key).ok().and_then(Val::as_address_bytes).and_then(ip_addr);
+        let ttl = ip.get("TTL").or(ip.get("Hop Limit")).ok().and_then(Val::as_unsigned).map(|t| t as u8);
+
+        if let (Some(source), Some(destination)) = (address("Source"), address("Destination")) {
+            self.observe(source_mac, destination_mac, source, destination, ttl);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use ethernet;
+    use tap::Taps;
+
+    fn frame(source_mac: u8, destination_mac: u8, source: [u8; 4], destination: [u8; 4], ttl: u8) -> Vec<u8> {
+        let mut data = vec![0x02, 0, 0, 0, 0, destination_mac, 0x02, 0, 0, 0, 0, source_mac, 0x08, 0x00,
+                            0x45, 0, 0, 20, 0, 0, 0, 0, ttl, 253, 0, 0];
+        data.extend_from_slice(&source);
+        data.extend_from_slice(&destination);
+        data
+    }
+
+    #[test]
+    fn infer_topology() {
+        // Two local hosts talking through a router (MAC 0xfe) to remote
+        // hosts, one of which is a NAT with Linux and Windows hosts behind it.
+        let packets = vec![
+            frame(1, 0xfe, [192, 168, 1, 10], [8, 8, 8, 8], 64),
+            frame(0xfe, 1, [8, 8, 8, 8], [192, 168, 1, 10], 120),
+            frame(2, 0xfe, [192, 168, 1, 11], [1, 1, 1, 1], 128),
+            frame(0xfe, 2, [1, 1, 1, 1], [192, 168, 1, 11], 57),
+            frame(0xfe, 2, [203, 0, 113, 5], [192, 168, 1, 11], 50),
+            frame(0xfe, 2, [203, 0, 113, 5], [192, 168, 1, 11], 110),
+            frame(1, 0xfe, [192, 168, 1, 10], [198, 51, 100, 7], 64),
+            frame(1, 2, [192, 168, 1, 10], [192, 168, 1, 11], 64),
+        ];
+
+        let mut topology = Topology::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("Ethernet frame", &mut topology);
+
+            for data in &packets {
+                let val = ethernet::dissect(data).unwrap();
+                taps.dispatch(Duration::new(0, 0), data.len(), &val);
+            }
+        }
+
+        let gateways = topology.gateways();
+        assert_eq!(gateways.len(), 1);
+        assert_eq!(gateways[0].0, "02:00:00:00:00:fe");
+
+        let subnets = topology.subnets();
+        assert_eq!(subnets.len(), 1);
+        assert_eq!(subnets[0].prefix.to_string(), "192.168.1.0/24");
+        assert_eq!(subnets[0].hosts.len(), 2);
+        assert_eq!(subnets[0].gateways, vec!["02:00:00:00:00:fe"]);
+
+        let nat = topology.nat_points();
+        assert_eq!(nat, vec![NatPoint { address: "203.0.113.5".parse().unwrap(), initial_ttls: vec![64, 128] }]);
+        assert!(topology.summary().contains("192.168.1.0/24"));
+    }
+}
@@ -0,0 +1,193 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Triage of malformed packets.
+//!
+//! A `Triage` accumulates every dissection error and conformance warning in
+//! a capture, grouped by the protocol layer they occurred in and by error
+//! code, with the numbers of a few example packets for each group. Its
+//! report characterizes fuzz-like traffic or a broken middlebox at a glance.
+//!
+//! ```
+//! use rshark::triage::Triage;
+//!
+//! let mut triage = Triage::new("Ethernet frame");
+//! for (i, data) in [&[0; 14][..], &[0; 3][..], &[0; 3][..]].iter().enumerate() {
+//!     triage.record(i as u64 + 1, &rshark::ethernet::dissect(data));
+//! }
+//!
+//! let issue = &triage.issues()[0];
+//! assert_eq!(issue.layer, "Ethernet frame");
+//! assert_eq!(issue.kind, "truncated");
+//! assert_eq!(issue.packets, vec![2, 3]);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use DissectResult;
+use Val;
+use conformance;
+
+/// The most example packet numbers kept for each issue.
+const EXAMPLES: usize = 5;
+
+/// One kind of problem in one protocol layer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    /// The layer that couldn't be dissected (for errors in a payload, the
+    /// layer that carried it) or that violated its specification.
+    pub layer: &'static str,
+
+    /// The error code's name, or "conformance" for a conformance warning.
+    pub kind: &'static str,
+
+    pub count: u64,
+
+    /// The first message seen, as an example.
+    pub message: String,
+
+    /// The numbers of the first few packets with this issue.
+    pub packets: Vec<u64>,
+}
+
+pub struct Triage {
+    outermost: &'static str,
+    issues: HashMap<(&'static str, &'static str), Issue>,
+    packets: u64,
+    malformed: u64,
+
+    /// Issues of all kinds, counted.
+    total: u64,
+}
+
+impl Triage {
+    /// A triage of packets whose outermost layer is `outermost` (e.g.,
+    /// "Ethernet frame"), which is the layer blamed for packets that can't
+    /// be dissected at all.
+    pub fn new(outermost: &'static str) -> Triage {
+        Triage { outermost: outermost, issues: HashMap::new(), packets: 0, malformed: 0, total: 0 }
+    }
+
+    /// Account for the dissection of a packet.
+    pub fn record(&mut self, number: u64, result: &DissectResult) {
+        self.packets += 1;
+        let before = self.total;
+
+        match result {
+            &Ok(ref val) => self.walk(number, val),
+            &Err(ref e) => self.add(number, self.outermost, e.code().name(), e.to_string()),
+        }
+
+        if self.total > before {
+            self.malformed += 1;
+        }
+    }
+
+    /// Packets recorded.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Packets with at least one issue.
+    pub fn malformed(&self) -> u64 {
+        self.malformed
+    }
+
+    /// All issues, the most common first.
+    pub fn issues(&self) -> Vec<&Issue> {
+        let mut issues = self.issues.values().collect::<Vec<_>>();
+        issues.sort_by(|a, b| b.count.cmp(&a.count)
+                       .then(a.layer.cmp(b.layer))
+                       .then(a.kind.cmp(b.kind)));
+        issues
+    }
+
+    /// A human-readable report.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "Malformed packets: {} of {}", self.malformed, self.packets).unwrap();
+        for issue in self.issues() {
+            writeln!(out, "  {:<24} {:<20} {:>8}  e.g. packets {}", issue.layer, issue.kind, issue.count,
+                     issue.packets.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")).unwrap();
+            writeln!(out, "    {}", issue.message).unwrap();
+        }
+
+        out
+    }
+
+    fn walk(&mut self, number: u64, val: &Val) {
+        if let &Val::Object(name, ref values) = val {
+            for &(key, ref v) in values {
+                match v {
+                    &Val::Payload(_, Ok(ref inner)) => self.walk(number, inner),
+                    &Val::Payload(_, Err(ref e)) => self.add(number, name, e.code().name(), e.to_string()),
+                    &Val::String(ref message) if key == conformance::WARNING =>
+                        self.add(number, name, "conformance", message.clone()),
+                    &Val::Object(..) => self.walk(number, v),
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    fn add(&mut self, number: u64, layer: &'static str, kind: &'static str, message: String) {
+        let issue = self.issues.entry((layer, kind)).or_insert(Issue {
+            layer: layer,
+            kind: kind,
+            count: 0,
+            message: message,
+            packets: Vec::new(),
+        });
+
+        issue.count += 1;
+        self.total += 1;
+        if issue.packets.len() < EXAMPLES && issue.packets.last() != Some(&number) {
+            issue.packets.push(number);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ip;
+
+    #[test]
+    fn triage_report() {
+        // A good UDP packet, a TCP header with a data offset of 4 and a TCP
+        // segment cut short.
+        let udp = [0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                   0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00];
+        let short_offset = [0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                            0xc0, 0x00, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0, 0x40, 0x02, 0xff, 0xff,
+                            0, 0, 0, 0];
+        let truncated = [0x45, 0, 0, 24, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+                         0xc0, 0x00, 0x00, 0x50];
+
+        let mut triage = Triage::new("IPv4");
+        for (i, data) in [&udp[..], &short_offset, &truncated, &short_offset, &[0x45]].iter().enumerate() {
+            triage.record(i as u64 + 1, &ip::dissect(data));
+        }
+
+        assert_eq!(triage.packets(), 5);
+        assert_eq!(triage.malformed(), 4);
+
+        let issues = triage.issues();
+        assert_eq!(issues.iter().map(|i| (i.layer, i.kind, i.count)).collect::<Vec<_>>(), vec![
+            ("IPv4", "truncated", 2),
+            ("TCP", "conformance", 2),
+        ]);
+        assert_eq!(issues[0].packets, vec![3, 5]);
+        assert_eq!(issues[1].packets, vec![2, 4]);
+
+        assert!(triage.report().starts_with("Malformed packets: 4 of 5\n"));
+    }
+}
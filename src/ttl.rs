@@ -0,0 +1,306 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Analysis of IP time-to-live (TTL) and hop limit values.
+//!
+//! Hosts start their packets with one of a few well-known TTLs (64, 128 or
+//! 255), so the TTL seen at the capture point reveals both that initial value
+//! and the number of hops travelled. For a given source both should be stable:
+//! an abrupt change suggests a route change, a middlebox rewriting packets or
+//! a spoofed source address, and a TTL implying an implausible number of hops
+//! is suspicious in itself.
+//!
+//! `TtlAnalysis` is a tap for `"IPv4"` layers that records such anomalies
+//! along with a per-host summary. Hosts are charged to a `MemoryBudget`, so
+//! that a flood of spoofed sources can't exhaust memory: the hosts seen least
+//! recently are forgotten when it is exceeded.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::mem;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use Val;
+use budget::MemoryBudget;
+use flow::ip_addr;
+use tap::{PacketInfo, Tap};
+
+/// Initial TTLs used by common operating systems.
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+
+/// The initial TTL that a packet most likely started with.
+pub fn initial_ttl(ttl: u8) -> u8 {
+    INITIAL_TTLS.iter().cloned().find(|&initial| initial >= ttl).unwrap_or(255)
+}
+
+/// What was observed about the TTLs of one source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostTtl {
+    pub packets: u64,
+    pub min: u8,
+    pub max: u8,
+    pub last: u8,
+
+    /// The inferred initial TTL of the most recent packet.
+    pub initial: u8,
+
+    /// Number of anomalies reported for this host.
+    pub anomalies: u64,
+
+    /// Number of the most recent packet.
+    pub last_seen: u64,
+}
+
+impl HostTtl {
+    /// Hops between the host and the capture point, by the latest packet.
+    pub fn hops(&self) -> u8 {
+        self.initial - self.last
+    }
+}
+
+/// A suspicious TTL observation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnomalyKind {
+    /// The TTL moved by more than the configured tolerance.
+    TtlChange { from: u8, to: u8 },
+
+    /// The inferred initial TTL changed, i.e., a different stack (or a
+    /// different host) is using the address.
+    InitialTtlChange { from: u8, to: u8 },
+
+    /// The TTL implies more hops than any real path should have.
+    ImprobableHops { ttl: u8, hops: u8 },
+}
+
+/// An anomaly, with the packet that revealed it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    /// Packet number (as given by the tap dispatcher).
+    pub number: u64,
+    pub timestamp: Duration,
+    pub source: IpAddr,
+    pub kind: AnomalyKind,
+}
+
+impl Anomaly {
+    /// Severity, in the terms used by Wireshark's expert information.
+    pub fn severity(&self) -> &'static str {
+        match self.kind {
+            AnomalyKind::TtlChange { .. } => "Note",
+            _ => "Warning",
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "packet {}: {} from {}: ", self.number, self.severity(), self.source)?;
+
+        match self.kind {
+            AnomalyKind::TtlChange { from, to } => write!(f, "TTL changed from {} to {}", from, to),
+            AnomalyKind::InitialTtlChange { from, to } =>
+                write!(f, "initial TTL changed from {} to {}", from, to),
+            AnomalyKind::ImprobableHops { ttl, hops } =>
+                write!(f, "TTL {} implies an improbable {} hops", ttl, hops),
+        }
+    }
+}
+
+/// Memory charged to the budget for each host.
+const HOST_SIZE: usize = 2 * mem::size_of::<IpAddr>() + mem::size_of::<HostTtl>()
+    + mem::size_of::<u64>();
+
+/// A tap that tracks TTLs per source address.
+pub struct TtlAnalysis {
+    hosts: HashMap<IpAddr, HostTtl>,
+    anomalies: Vec<Anomaly>,
+    tolerance: u8,
+    max_hops: u8,
+
+    /// Hosts by the number of their latest packet, for LRU eviction.
+    lru: BTreeSet<(u64, IpAddr)>,
+    budget: MemoryBudget,
+    evictions: u64,
+}
+
+impl TtlAnalysis {
+    pub fn new() -> TtlAnalysis {
+        TtlAnalysis::with_budget(MemoryBudget::unlimited())
+    }
+
+    pub fn with_budget(budget: MemoryBudget) -> TtlAnalysis {
+        TtlAnalysis {
+            hosts: HashMap::new(),
+            anomalies: Vec::new(),
+            tolerance: 2,
+            max_hops: 40,
+            lru: BTreeSet::new(),
+            budget: budget,
+            evictions: 0,
+        }
+    }
+
+    /// How far the TTL may move (e.g., with load-balanced paths) before it
+    /// is reported as a change. The default is 2.
+    pub fn tolerance(mut self, tolerance: u8) -> TtlAnalysis {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// The greatest plausible number of hops. The default is 40.
+    pub fn max_hops(mut self, max_hops: u8) -> TtlAnalysis {
+        self.max_hops = max_hops;
+        self
+    }
+
+    pub fn anomalies(&self) -> &[Anomaly] {
+        &self.anomalies
+    }
+
+    pub fn host(&self, address: &IpAddr) -> Option<&HostTtl> {
+        self.hosts.get(address)
+    }
+
+    /// Number of hosts forgotten to stay within the memory budget.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// A per-host summary, most anomalous hosts first.
+    pub fn summary(&self) -> String {
+        let mut hosts = self.hosts.iter().collect::<Vec<_>>();
+        hosts.sort_by(|a, b| b.1.anomalies.cmp(&a.1.anomalies).then_with(|| a.0.cmp(b.0)));
+
+        let mut out = format!["{:<40} {:>8} {:>7} {:>7} {:>5} {:>9}\n",
+                              "Source", "Packets", "TTL", "Initial", "Hops", "Anomalies"];
+        for (address, host) in hosts {
+            out += &format!["{:<40} {:>8} {:>3}-{:<3} {:>7} {:>5} {:>9}\n", address.to_string(),
+                            host.packets, host.min, host.max, host.initial, host.hops(), host.anomalies];
+        }
+
+        out
+    }
+
+    /// Account for a packet's TTL.
+    pub fn observe(&mut self, number: u64, timestamp: Duration, source: IpAddr, ttl: u8) {
+        let initial = initial_ttl(ttl);
+        let mut found = Vec::new();
+
+        if initial - ttl > self.max_hops {
+            found.push(AnomalyKind::ImprobableHops { ttl: ttl, hops: initial - ttl });
+        }
+
+        if !self.hosts.contains_key(&source) {
+            self.budget.charge(HOST_SIZE);
+            while self.budget.is_exceeded() && !self.lru.is_empty() {
+                let oldest = *self.lru.iter().next().unwrap();
+                self.lru.remove(&oldest);
+                self.hosts.remove(&oldest.1);
+                self.budget.release(HOST_SIZE);
+                self.evictions += 1;
+            }
+        }
+
+        let host = self.hosts.entry(source).or_insert(HostTtl {
+            packets: 0, min: ttl, max: ttl, last: ttl, initial: initial, anomalies: 0,
+            last_seen: number,
+        });
+        self.lru.remove(&(host.last_seen, source));
+        self.lru.insert((number, source));
+        host.last_seen = number;
+
+        if host.initial != initial {
+            found.push(AnomalyKind::InitialTtlChange { from: host.initial, to: initial });
+        } else if (host.last as i16 - ttl as i16).abs() > self.tolerance as i16 {
+            found.push(AnomalyKind::TtlChange { from: host.last, to: ttl });
+        }
+
+        host.packets += 1;
+        host.min = ::std::cmp::min(host.min, ttl);
+        host.max = ::std::cmp::max(host.max, ttl);
+        host.last = ttl;
+        host.initial = initial;
+        host.anomalies += found.len() as u64;
+
+        for kind in found {
+            self.anomalies.push(Anomaly { number: number, timestamp: timestamp, source: source, kind: kind });
+        }
+    }
+}
+
+impl Drop for TtlAnalysis {
+    fn drop(&mut self) {
+        self.budget.release(self.hosts.len() * HOST_SIZE);
+    }
+}
+
+impl Tap for TtlAnalysis {
+    fn tap(&mut self, info: &PacketInfo, layer: &Val) {
+        let source = layer.get("Source").ok().and_then(|a| a.as_address_bytes()).and_then(ip_addr);
+        let ttl = layer.get("TTL").ok().and_then(|t| t.as_unsigned());
+
+        if let (Some(source), Some(ttl)) = (source, ttl) {
+            self.observe(info.number, info.timestamp, source, ttl as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+    use ip;
+    use tap::Taps;
+
+    #[test]
+    fn ttl_anomalies() {
+        let mut analysis = TtlAnalysis::new();
+        {
+            let mut taps = Taps::new();
+            taps.register("IPv4", &mut analysis);
+
+            // Stable, jitter, route change, different OS, implausible path
+            for &ttl in &[57, 57, 58, 50, 120, 70] {
+                let data = [0x45, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, ttl, 0xfd, 0x00, 0x00,
+                            192, 0, 2, 1, 192, 0, 2, 2];
+                let val = ip::dissect(&data).unwrap();
+                taps.dispatch(Duration::new(0, 0), data.len(), &val);
+            }
+        }
+
+        let source = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let kinds = analysis.anomalies().iter().map(|a| (a.number, a.kind.clone())).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![
+            (4, AnomalyKind::TtlChange { from: 58, to: 50 }),
+            (5, AnomalyKind::InitialTtlChange { from: 64, to: 128 }),
+            (6, AnomalyKind::ImprobableHops { ttl: 70, hops: 58 }),
+            (6, AnomalyKind::TtlChange { from: 120, to: 70 }),
+        ]);
+
+        let host = analysis.host(&source).unwrap();
+        assert_eq!((host.packets, host.min, host.max, host.anomalies), (6, 50, 120, 4));
+        assert!(analysis.summary().contains("192.0.2.1"));
+        assert_eq!(initial_ttl(1), 32);
+
+        // Only two hosts fit in the budget.
+        let budget = MemoryBudget::new(2 * HOST_SIZE);
+        let mut analysis = TtlAnalysis::with_budget(budget.clone());
+        for (number, last) in (1..4).enumerate() {
+            let source = IpAddr::V4(Ipv4Addr::new(192, 0, 2, last));
+            analysis.observe(number as u64 + 1, Duration::new(0, 0), source, 64);
+        }
+
+        assert_eq!(analysis.evictions(), 1);
+        assert!(analysis.host(&source).is_none());
+        drop(analysis);
+        assert_eq!(budget.used(), 0);
+    }
+}
@@ -0,0 +1,376 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of USB request blocks (URBs) captured by Linux usbmon
+//! (LINKTYPE_USB_LINUX and LINKTYPE_USB_LINUX_MMAPPED), including the setup
+//! packets of control transfers and the standard descriptors they return.
+//!
+//! Each transfer is captured twice: once when the URB is submitted and once
+//! when it completes. The usbmon header is in the byte order of the capturing
+//! host, which is assumed to be little-endian. A completion doesn't repeat
+//! the setup packet of its submission, so the data returned by a control
+//! transfer is recognised as descriptors by its shape.
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use {read_le_u16, read_le_u32, read_le_u64};
+
+enum_map!(pub URB_TYPES {
+    b'S' => "Submit",
+    b'C' => "Complete",
+    b'E' => "Error",
+});
+
+enum_map!(pub TRANSFER_TYPES {
+    0 => "Isochronous",
+    1 => "Interrupt",
+    2 => "Control",
+    3 => "Bulk",
+});
+
+// Transfer types as given in endpoint descriptors, which differ from usbmon's
+enum_map!(pub ENDPOINT_TYPES {
+    0 => "Control",
+    1 => "Isochronous",
+    2 => "Bulk",
+    3 => "Interrupt",
+});
+
+// Negated URB statuses (Linux errno values)
+enum_map!(pub STATUSES {
+    2 => "Unlinked",
+    18 => "Cross-Device Link",
+    32 => "Stall",
+    62 => "Timeout",
+    71 => "Protocol Error",
+    75 => "Overflow",
+    104 => "Reset",
+    108 => "Shutdown",
+    115 => "In Progress",
+    121 => "Short Packet",
+});
+
+enum_map!(pub REQUESTS {
+    0 => "GET_STATUS",
+    1 => "CLEAR_FEATURE",
+    3 => "SET_FEATURE",
+    5 => "SET_ADDRESS",
+    6 => "GET_DESCRIPTOR",
+    7 => "SET_DESCRIPTOR",
+    8 => "GET_CONFIGURATION",
+    9 => "SET_CONFIGURATION",
+    10 => "GET_INTERFACE",
+    11 => "SET_INTERFACE",
+    12 => "SYNCH_FRAME",
+});
+
+enum_map!(pub DESCRIPTOR_TYPES {
+    1 => "Device",
+    2 => "Configuration",
+    3 => "String",
+    4 => "Interface",
+    5 => "Endpoint",
+    6 => "Device Qualifier",
+    11 => "Interface Association",
+    15 => "BOS",
+    33 => "HID",
+    34 => "HID Report",
+});
+
+enum_map!(pub CLASSES {
+    0x00 => "Per Interface",
+    0x01 => "Audio",
+    0x02 => "Communications",
+    0x03 => "HID",
+    0x06 => "Image",
+    0x07 => "Printer",
+    0x08 => "Mass Storage",
+    0x09 => "Hub",
+    0x0a => "CDC Data",
+    0x0b => "Smart Card",
+    0x0e => "Video",
+    0xe0 => "Wireless Controller",
+    0xef => "Miscellaneous",
+    0xfe => "Application Specific",
+    0xff => "Vendor Specific",
+});
+
+const CONTROL: u8 = 2;
+const ISOCHRONOUS: u8 = 0;
+
+/// Dissect a URB with the 64 B header of LINKTYPE_USB_LINUX_MMAPPED.
+pub fn dissect(data : &[u8]) -> DissectResult {
+    dissect_urb(data, 64)
+}
+
+/// Dissect a URB with the 48 B header of LINKTYPE_USB_LINUX.
+pub fn dissect_legacy(data : &[u8]) -> DissectResult {
+    dissect_urb(data, 48)
+}
+
+fn dissect_urb(data: &[u8], header_length: usize) -> DissectResult {
+    if data.len() < header_length {
+        return Err(DissectError::Underflow { expected: Some(header_length), have: data.len(),
+            message: format!["A usbmon header must be {} B", header_length] });
+    }
+
+    let mut values = NamedValues::new();
+    let urb_type = data[8];
+    let transfer_type = data[9];
+    let endpoint = data[10];
+    let status = read_le_u32(data, 28)? as i32;
+
+    values.push(("URB ID", Val::Unsigned(read_le_u64(data, 0)?)));
+    values.push(("URB Type", URB_TYPES.val(urb_type)));
+    values.push(("Transfer Type", TRANSFER_TYPES.val(transfer_type)));
+    values.push(("Endpoint", Val::Unsigned((endpoint & 0x7f) as u64)));
+    values.push(("Direction", Val::Symbol(if endpoint & 0x80 != 0 { "In" } else { "Out" })));
+    values.push(("Device", Val::Unsigned(data[11] as u64)));
+    values.push(("Bus", Val::Unsigned(read_le_u16(data, 12)? as u64)));
+    values.push(("Status", Val::Signed(status as i64)));
+    if status < 0 {
+        values.push(("Status Name", STATUSES.val(-(status as i64) as u64)));
+    }
+    values.push(("URB Length", Val::Unsigned(read_le_u32(data, 32)? as u64)));
+
+    let data_length = read_le_u32(data, 36)? as usize;
+    values.push(("Data Length", Val::Unsigned(data_length as u64)));
+
+    // The setup flag is 0 when a setup packet was captured.
+    if data[14] == 0 {
+        values.push(("Setup", setup(&data[40..48])?));
+    }
+
+    let mut offset = header_length;
+    if header_length == 64 {
+        values.push(("Interval", Val::Signed(read_le_u32(data, 48)? as i32 as i64)));
+        values.push(("Start Frame", Val::Signed(read_le_u32(data, 52)? as i32 as i64)));
+        values.push(("Transfer Flags", Val::Unsigned(read_le_u32(data, 56)? as u64)));
+
+        // Isochronous transfers are followed by 16 B frame descriptors.
+        if transfer_type == ISOCHRONOUS {
+            let descriptors = read_le_u32(data, 60)? as usize;
+            values.push(("ISO Descriptors", Val::Unsigned(descriptors as u64)));
+            offset = offset.saturating_add(descriptors.saturating_mul(16));
+        }
+    }
+
+    if offset > data.len() {
+        return Err(DissectError::Underflow { expected: Some(offset), have: data.len(),
+            message: "usbmon ISO descriptors overrun the capture".to_string() });
+    }
+
+    let payload = &data[offset..offset + data_length.min(data.len() - offset)];
+    if transfer_type == CONTROL && endpoint & 0x80 != 0 && urb_type == b'C'
+        && looks_like_descriptor(payload) {
+
+        values.push(("Payload", Val::Payload(payload, dissect_descriptors(payload))));
+    } else if !payload.is_empty() {
+        values.push(("Data", Val::Bytes(payload)));
+    }
+
+    Ok(Box::new(Val::Object("USB URB", values)))
+}
+
+/// Dissect the 8 B setup packet of a control transfer.
+fn setup(data: &[u8]) -> Result<Val, DissectError> {
+    let request_type = data[0];
+    let mut values = NamedValues::new();
+
+    values.push(("Request Type", Val::Unsigned(request_type as u64)));
+    values.push(("Direction", Val::Symbol(if request_type & 0x80 != 0 { "In" } else { "Out" })));
+    values.push(("Type", Val::Symbol(match (request_type >> 5) & 0x03 {
+        0 => "Standard",
+        1 => "Class",
+        2 => "Vendor",
+        _ => "Reserved",
+    })));
+    values.push(("Recipient", Val::Symbol(match request_type & 0x1f {
+        0 => "Device",
+        1 => "Interface",
+        2 => "Endpoint",
+        _ => "Other",
+    })));
+
+    values.push(("Request", Val::Unsigned(data[1] as u64)));
+    if request_type & 0x60 == 0 {
+        values.push(("Request Name", REQUESTS.val(data[1])));
+    }
+
+    // GET_DESCRIPTOR gives the descriptor type and index in the value.
+    let value = read_le_u16(data, 2)?;
+    values.push(("Value", Val::Unsigned(value as u64)));
+    if request_type & 0x60 == 0 && data[1] == 6 {
+        values.push(("Descriptor Type", DESCRIPTOR_TYPES.val(value >> 8)));
+        values.push(("Descriptor Index", Val::Unsigned((value & 0xff) as u64)));
+    }
+
+    values.push(("Index", Val::Unsigned(read_le_u16(data, 4)? as u64)));
+    values.push(("Length", Val::Unsigned(read_le_u16(data, 6)? as u64)));
+
+    Ok(Val::Object("Setup", values))
+}
+
+fn looks_like_descriptor(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] as usize >= 2 && DESCRIPTOR_TYPES.name(data[1]).is_some()
+}
+
+/// Dissect a sequence of standard USB descriptors, e.g., a configuration
+/// descriptor followed by its interface and endpoint descriptors.
+pub fn dissect_descriptors(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+
+    let mut remaining = data;
+    while remaining.len() >= 2 {
+        let length = remaining[0] as usize;
+        if length < 2 {
+            return Err(DissectError::InvalidData(
+                format!["USB descriptor length {} is invalid", length]));
+        }
+
+        // Hosts often read only the start of a descriptor to learn its length.
+        let available = length.min(remaining.len());
+        values.push(("Descriptor", descriptor(&remaining[..available], length)?));
+        remaining = &remaining[available..];
+    }
+
+    Ok(Box::new(Val::Object("USB Descriptors", values)))
+}
+
+fn descriptor(data: &[u8], length: usize) -> Result<Val, DissectError> {
+    let descriptor_type = data[1];
+    let mut values = NamedValues::new();
+
+    values.push(("Length", Val::Unsigned(length as u64)));
+    values.push(("Type", DESCRIPTOR_TYPES.val(descriptor_type)));
+
+    if data.len() < length {
+        values.push(("Truncated", Val::Undissected("USB descriptor", &data[2..])));
+        return Ok(Val::Object("USB Descriptor", values));
+    }
+
+    let byte = |offset: usize| data.get(offset).map(|&b| Val::Unsigned(b as u64));
+    let word = |offset: usize| read_le_u16(data, offset).ok().map(|w| Val::Unsigned(w as u64));
+    let fields: Vec<(&'static str, Option<Val>)> = match descriptor_type {
+        1 => vec![
+            ("USB Version", word(2)),
+            ("Class", data.get(4).map(|&c| CLASSES.val(c))),
+            ("Subclass", byte(5)),
+            ("Protocol", byte(6)),
+            ("Max Packet Size", byte(7)),
+            ("Vendor ID", word(8)),
+            ("Product ID", word(10)),
+            ("Device Version", word(12)),
+            ("Manufacturer String", byte(14)),
+            ("Product String", byte(15)),
+            ("Serial Number String", byte(16)),
+            ("Configurations", byte(17)),
+        ],
+        2 => vec![
+            ("Total Length", word(2)),
+            ("Interfaces", byte(4)),
+            ("Configuration Value", byte(5)),
+            ("Configuration String", byte(6)),
+            ("Attributes", data.get(7).map(|&a| Val::BitFlags8(a, [
+                None, None, None, None, None, Some("Remote Wakeup"), Some("Self Powered"), None]))),
+            // In units of 2 mA
+            ("Max Power", byte(8)),
+        ],
+        3 => {
+            let units = data[2..].chunks(2).filter(|c| c.len() == 2)
+                .map(|c| c[0] as u16 | (c[1] as u16) << 8)
+                .collect::<Vec<_>>();
+            vec![("String", Some(Val::String(String::from_utf16_lossy(&units))))]
+        },
+        4 => vec![
+            ("Interface Number", byte(2)),
+            ("Alternate Setting", byte(3)),
+            ("Endpoints", byte(4)),
+            ("Class", data.get(5).map(|&c| CLASSES.val(c))),
+            ("Subclass", byte(6)),
+            ("Protocol", byte(7)),
+            ("Interface String", byte(8)),
+        ],
+        5 => vec![
+            ("Endpoint Address", byte(2)),
+            ("Transfer Type", data.get(3).map(|&a| ENDPOINT_TYPES.val(a & 0x03))),
+            ("Max Packet Size", word(4)),
+            ("Interval", byte(6)),
+        ],
+        _ => vec![("Data", Some(Val::Bytes(&data[2..])))],
+    };
+
+    for (name, value) in fields {
+        match value {
+            Some(value) => values.push((name, value)),
+            None => return Err(DissectError::Underflow { expected: None, have: data.len(),
+                message: format!["USB {} descriptor is too short",
+                                 DESCRIPTOR_TYPES.name(descriptor_type).unwrap_or("")] }),
+        }
+    }
+
+    Ok(Val::Object("USB Descriptor", values))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(urb_type: u8, endpoint: u8, setup: Option<[u8; 8]>, data: &[u8]) -> Vec<u8> {
+        let mut urb = vec![0xef, 0xbe, 0xad, 0xde, 0, 0, 0, 0, urb_type, CONTROL, endpoint, 3,
+                           1, 0, if setup.is_some() { 0 } else { b'-' }, 0];
+        urb.extend_from_slice(&[0; 12]);
+        urb.extend_from_slice(&[0, 0, 0, 0, 18, 0, 0, 0, data.len() as u8, 0, 0, 0]);
+        urb.extend_from_slice(&setup.unwrap_or([0; 8]));
+        urb.extend_from_slice(&[0; 16]);
+        urb.extend_from_slice(data);
+        urb
+    }
+
+    #[test]
+    fn dissect_get_descriptor() {
+        let submit = header(b'S', 0x80, Some([0x80, 6, 0, 1, 0, 0, 18, 0]), &[]);
+        let val = *dissect(&submit).unwrap();
+        assert_eq!(val["URB Type"].as_symbol().unwrap(), "Submit");
+        assert_eq!(val["Device"].as_unsigned().unwrap(), 3);
+        assert_eq!(val["Setup"]["Request Name"].as_symbol().unwrap(), "GET_DESCRIPTOR");
+        assert_eq!(val["Setup"]["Descriptor Type"].as_symbol().unwrap(), "Device");
+
+        let device = [18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x6b, 0x1d, 0x04, 0x01, 0x00, 0x01,
+                      1, 2, 3, 1];
+        let complete = header(b'C', 0x80, None, &device);
+        let val = *dissect(&complete).unwrap();
+        let descriptor = &val["Payload"]["Descriptor"];
+        assert_eq!(descriptor["Type"].as_symbol().unwrap(), "Device");
+        assert_eq!(descriptor["Vendor ID"].as_unsigned().unwrap(), 0x1d6b);
+        assert_eq!(descriptor["Product ID"].as_unsigned().unwrap(), 0x0104);
+
+        assert!(dissect(&complete[..63]).is_err());
+    }
+
+    #[test]
+    fn dissect_configuration() {
+        let data = [9, 2, 25, 0, 1, 1, 0, 0xe0, 0,
+                    9, 4, 0, 0, 1, 9, 0, 0, 0,
+                    7, 5, 0x81, 3, 4, 0, 12,
+                    4, 3, b'h', 0];
+
+        let val = *dissect_descriptors(&data).unwrap();
+        let descriptors = val.as_object().unwrap().1;
+        assert_eq!(descriptors.len(), 4);
+        assert!(descriptors[0].1["Attributes"].as_bitflags8_bit_name("Self Powered").unwrap());
+        assert_eq!(descriptors[1].1["Class"].as_symbol().unwrap(), "Hub");
+        assert_eq!(descriptors[2].1["Transfer Type"].as_symbol().unwrap(), "Interrupt");
+        assert_eq!(descriptors[3].1["String"].as_string().unwrap(), "h");
+
+        assert!(dissect_descriptors(&[1, 2]).is_err());
+    }
+}
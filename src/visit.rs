@@ -0,0 +1,154 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Walking dissection trees.
+//!
+//! `Val::walk` visits a value and everything within it, depth first and in
+//! order, calling a `Visitor`'s `enter` before a value's children and
+//! `leave` after them. Each call carries the path to the value from where
+//! the walk started: the field names and list indices that `Val::lookup`
+//! would follow to find it. As with `lookup`, a dissected payload and the
+//! layer inside it share a path.
+//!
+//! ```
+//! use rshark::Val;
+//! use rshark::visit::{Step, Visitor};
+//!
+//! /// Finds the paths of all addresses.
+//! struct Addresses(Vec<String>);
+//!
+//! impl Visitor for Addresses {
+//!     fn enter(&mut self, path: &[Step], val: &Val) -> bool {
+//!         if val.is_address() {
+//!             self.0.push(Step::join(path));
+//!         }
+//!         true
+//!     }
+//! }
+//!
+//! let data = [0; 14];
+//! let packet = rshark::ethernet::dissect(&data).unwrap();
+//!
+//! let mut addresses = Addresses(Vec::new());
+//! packet.walk(&mut addresses);
+//! assert_eq!(addresses.0, vec!["Destination", "Source"]);
+//! ```
+
+use std::fmt;
+
+use Val;
+
+/// One step along the path to a value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// A field of an object.
+    Field(&'static str),
+
+    /// An element of a list.
+    Index(usize),
+}
+
+impl Step {
+    /// A path in the dot-separated form used by `Val::lookup`.
+    pub fn join(path: &[Step]) -> String {
+        path.iter().map(Step::to_string).collect::<Vec<_>>().join(".")
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Step::Field(name) => write![f, "{}", name],
+            Step::Index(i) => write![f, "{}", i],
+        }
+    }
+}
+
+/// Callbacks for a walk over a dissection tree.
+pub trait Visitor {
+    /// Called before visiting a value's children (if it has any). Returning
+    /// false skips the children (and the matching call to `leave`).
+    fn enter(&mut self, path: &[Step], val: &Val) -> bool {
+        let _ = (path, val);
+        true
+    }
+
+    /// Called after visiting a value's children.
+    fn leave(&mut self, path: &[Step], val: &Val) {
+        let _ = (path, val);
+    }
+}
+
+impl<'data> Val<'data> {
+    /// Visit this value and everything within it.
+    pub fn walk<V: Visitor>(&self, visitor: &mut V) {
+        walk(self, &mut Vec::new(), visitor)
+    }
+}
+
+fn walk<V: Visitor>(val: &Val, path: &mut Vec<Step>, visitor: &mut V) {
+    if !visitor.enter(path, val) {
+        return;
+    }
+
+    match *val {
+        Val::Object(_, ref values) => for &(name, ref v) in values {
+            path.push(Step::Field(name));
+            walk(v, path, visitor);
+            path.pop();
+        },
+        Val::List(ref values) => for (i, v) in values.iter().enumerate() {
+            path.push(Step::Index(i));
+            walk(v, path, visitor);
+            path.pop();
+        },
+        Val::Payload(_, Ok(ref inner)) => walk(inner, path, visitor),
+        _ => {},
+    }
+
+    visitor.leave(path, val);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Records enters and leaves, skipping the children of "Skipped".
+    struct Recorder(Vec<String>);
+
+    impl Visitor for Recorder {
+        fn enter(&mut self, path: &[Step], val: &Val) -> bool {
+            self.0.push(format!["> {}", Step::join(path)]);
+            path.last() != Some(&Step::Field("Skipped")) || !val.is_object()
+        }
+
+        fn leave(&mut self, path: &[Step], _: &Val) {
+            self.0.push(format!["< {}", Step::join(path)]);
+        }
+    }
+
+    #[test]
+    fn walk_tree() {
+        let val = Val::Object("Outer", vec![
+            ("Hops", Val::List(vec![Val::Unsigned(3)])),
+            ("Skipped", Val::Object("Skipped", vec![("Hidden", Val::Unsigned(1))])),
+            ("Payload", Val::Payload(&[], Ok(Box::new(Val::Object("Inner", vec![
+                ("Port", Val::Unsigned(53)),
+            ]))))),
+        ]);
+
+        let mut recorder = Recorder(Vec::new());
+        val.walk(&mut recorder);
+        assert_eq!(recorder.0, vec![
+            "> ", "> Hops", "> Hops.0", "< Hops.0", "< Hops", "> Skipped",
+            "> Payload", "> Payload", "> Payload.Port", "< Payload.Port", "< Payload", "< Payload",
+            "< ",
+        ]);
+    }
+}
@@ -0,0 +1,231 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Interval counters for monitoring ("watch") mode.
+//!
+//! Rather than printing each packet's dissection, a `Watch` counts the
+//! packets that match a filter and, at the end of each interval, summarizes
+//! them: how many there were, their rates and the busiest talkers. This is
+//! what `rshark watch` prints.
+//!
+//! Intervals are measured in capture time, so that a capture file can be
+//! "watched" as well as a live interface.
+//!
+//! ```
+//! use std::time::Duration;
+//! use rshark::source::Packet;
+//! use rshark::watch::{parse_interval, Watch};
+//!
+//! let mut watch = Watch::new(parse_interval("1s").unwrap());
+//! let packet = |s| Packet { timestamp: Duration::new(s, 0), original_length: 100, data: vec![0; 14] };
+//!
+//! assert!(watch.observe(&packet(0)).is_none());
+//! assert!(watch.observe(&packet(0)).is_none());
+//! let summary = watch.observe(&packet(1)).unwrap();
+//! assert_eq!((summary.packets, summary.bytes), (2, 200));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use DissectResult;
+use bpf::Program;
+use source::Packet;
+use stats::top;
+
+/// The packets matched during one interval.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interval {
+    pub start: Duration,
+    pub length: Duration,
+    pub packets: u64,
+
+    /// Original (on the wire) bytes.
+    pub bytes: u64,
+
+    /// Busiest source addresses, by packets.
+    pub talkers: Vec<(String, u64)>,
+}
+
+impl Interval {
+    pub fn packets_per_second(&self) -> f64 {
+        self.packets as f64 / self.length.as_secs_f64()
+    }
+
+    pub fn bits_per_second(&self) -> f64 {
+        self.bytes as f64 * 8.0 / self.length.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write![f, "{:>12.3} {:>8} pkts {:>10} B {:>10.1} pkt/s {:>12.0} bit/s",
+               self.start.as_secs_f64(), self.packets, self.bytes,
+               self.packets_per_second(), self.bits_per_second()]?;
+
+        for &(ref talker, count) in &self.talkers {
+            write![f, "  {} ({})", talker, count]?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts filtered packets in fixed intervals.
+pub struct Watch {
+    interval: Duration,
+    filter: Option<Program>,
+    dissector: Option<fn(&[u8]) -> DissectResult>,
+    top: usize,
+
+    current: Option<u64>,
+    packets: u64,
+    bytes: u64,
+    talkers: HashMap<String, u64>,
+}
+
+impl Watch {
+    pub fn new(interval: Duration) -> Watch {
+        assert!(interval > Duration::new(0, 0), "watch interval must be non-zero");
+
+        Watch {
+            interval: interval,
+            filter: None,
+            dissector: None,
+            top: 3,
+            current: None,
+            packets: 0,
+            bytes: 0,
+            talkers: HashMap::new(),
+        }
+    }
+
+    /// Only count packets that match a (compiled) capture filter.
+    pub fn filter(mut self, filter: Program) -> Watch {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Dissect packets with `dissector` to find their source addresses.
+    pub fn talkers(mut self, dissector: fn(&[u8]) -> DissectResult, top: usize) -> Watch {
+        self.dissector = Some(dissector);
+        self.top = top;
+        self
+    }
+
+    /// Count a packet, returning the summary of the previous interval if
+    /// this packet starts a new one.
+    pub fn observe(&mut self, packet: &Packet) -> Option<Interval> {
+        let bucket = (packet.timestamp.as_nanos() / self.interval.as_nanos()) as u64;
+        let finished = match self.current {
+            Some(current) if current != bucket => self.finish(),
+            _ => None,
+        };
+        self.current = Some(bucket);
+
+        if self.filter.as_ref().map(|f| f.matches(&packet.data)).unwrap_or(true) {
+            self.packets += 1;
+            self.bytes += packet.original_length as u64;
+
+            let source = self.dissector
+                .and_then(|dissect| dissect(&packet.data).ok())
+                .and_then(|val| val.layer("IPv4").or(val.layer("IPv6"))
+                                   .and_then(|ip| ip.get("Source").ok())
+                                   .and_then(|a| a.as_address_encoded())
+                                   .map(|a| a.to_string()));
+
+            if let Some(source) = source {
+                *self.talkers.entry(source).or_insert(0) += 1;
+            }
+        }
+
+        finished
+    }
+
+    /// The summary of the current interval, which is then reset.
+    pub fn finish(&mut self) -> Option<Interval> {
+        let bucket = match self.current.take() {
+            Some(bucket) => bucket,
+            None => return None,
+        };
+
+        let interval = Interval {
+            start: self.interval * bucket as u32,
+            length: self.interval,
+            packets: self.packets,
+            bytes: self.bytes,
+            talkers: top(&self.talkers, self.top),
+        };
+
+        self.packets = 0;
+        self.bytes = 0;
+        self.talkers.clear();
+
+        Some(interval)
+    }
+}
+
+/// Parse an interval such as "1s", "500ms" or "2m".
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_digit(10) && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number = number.parse::<f64>().map_err(|_| format!["invalid interval: '{}'", s])?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!["invalid interval unit: '{}'", unit]),
+    };
+
+    if seconds <= 0.0 {
+        return Err(format!["interval must be positive: '{}'", s]);
+    }
+
+    Ok(Duration::from_nanos((seconds * 1e9) as u64))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use bpf;
+    use ethernet;
+
+    fn packet(ms: u64, source: u8, protocol: u8) -> Packet {
+        let data = vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+                        0x45, 0, 0, 20, 0, 0, 0, 0, 64, protocol, 0, 0, 10, 0, 0, source, 10, 0, 0, 254];
+        Packet { timestamp: Duration::from_millis(ms), original_length: 64, data: data }
+    }
+
+    #[test]
+    fn watch_intervals() {
+        let mut watch = Watch::new(parse_interval("500ms").unwrap())
+            .filter(bpf::compile("icmp").unwrap())
+            .talkers(ethernet::dissect, 1);
+
+        let packets = vec![packet(0, 1, 1), packet(100, 2, 1), packet(200, 2, 1), packet(300, 3, 6),
+                           packet(1200, 1, 1)];
+        let summaries = packets.iter().filter_map(|p| watch.observe(p)).collect::<Vec<_>>();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].packets, 3);
+        assert_eq!(summaries[0].packets_per_second(), 6.0);
+        assert_eq!(summaries[0].talkers, vec![("10.0.0.2".to_string(), 2)]);
+
+        let last = watch.finish().unwrap();
+        assert_eq!((last.start, last.packets), (Duration::from_millis(1000), 1));
+        assert!(watch.finish().is_none());
+
+        assert_eq!(parse_interval("2m"), Ok(Duration::new(120, 0)));
+        assert!(parse_interval("1 fortnight").is_err());
+        assert!(parse_interval("0s").is_err());
+    }
+}
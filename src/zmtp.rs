@@ -0,0 +1,215 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! Dissection of the ZeroMQ Message Transport Protocol (ZMTP) 3.x.
+//!
+//! A connection starts with a 64 B greeting from each peer, naming the
+//! protocol version and security mechanism (NULL, PLAIN or CURVE). Then
+//! come frames: a flags byte (MORE, LONG, COMMAND), a size of 1 or 8 B and
+//! a body. Command frames carry the handshake (READY with its metadata
+//! properties, e.g., Socket-Type and Identity), subscriptions and errors;
+//! message frames are application data. ZeroMQ has no well-known port, so
+//! connections are recognized by their greeting (see `looks_like_greeting`)
+//! and later segments can be decoded as ZMTP by calling `dissect` directly.
+//!
+//! See [ZMTP 3.1](https://rfc.zeromq.org/spec/37/).
+
+use DissectError;
+use DissectResult;
+use NamedValues;
+use Val;
+use {read_be_u32, read_be_u64};
+
+/// The size of a complete greeting.
+const GREETING: usize = 64;
+
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+
+/// Whether data starts with a ZMTP greeting's signature: 0xff, eight bytes
+/// of padding and 0x7f. Peers may send the signature on its own, before the
+/// rest of the greeting.
+pub fn looks_like_greeting(data: &[u8]) -> bool {
+    data.len() >= 10 && data[0] == 0xff && data[9] == 0x7f
+}
+
+pub fn dissect(data : &[u8]) -> DissectResult {
+    let mut values = NamedValues::new();
+    let mut offset = 0;
+
+    if looks_like_greeting(data) {
+        offset = greeting(data, &mut values);
+    }
+
+    while offset < data.len() {
+        let (length, frame) = frame(&data[offset..])?;
+        values.push(("Frame", frame));
+        offset += length;
+    }
+
+    Ok(Box::new(Val::Object("ZMTP", values)))
+}
+
+/// Dissect as much of a greeting as there is, returning its length.
+fn greeting<'data>(data: &'data [u8], values: &mut NamedValues<'data>) -> usize {
+    values.push(("Signature", Val::Bytes(&data[..10])));
+
+    if let Some(&major) = data.get(10) {
+        values.push(("Major Version", Val::Unsigned(major as u64)));
+    }
+    if let Some(&minor) = data.get(11) {
+        values.push(("Minor Version", Val::Unsigned(minor as u64)));
+    }
+    if let Some(mechanism) = data.get(12..32) {
+        let name = mechanism.split(|&b| b == 0).next().unwrap_or(&[]);
+        values.push(("Mechanism", Val::String(String::from_utf8_lossy(name).into_owned())));
+    }
+    if let Some(&server) = data.get(32) {
+        values.push(("As Server", Val::Unsigned((server != 0) as u64)));
+    }
+
+    data.len().min(GREETING)
+}
+
+/// Dissect the frame at the start of `data`, returning its length.
+fn frame(data: &[u8]) -> Result<(usize, Val), DissectError> {
+    let flags = data[0];
+    let (size, header) = if flags & LONG != 0 {
+        (read_be_u64(data, 1)?, 9)
+    } else if data.len() >= 2 {
+        (data[1] as u64, 2)
+    } else {
+        return Err(DissectError::Underflow { expected: Some(2), have: data.len(),
+            message: "A ZMTP frame must be at least 2 B".to_string() });
+    };
+
+    if flags & 0xf8 != 0 {
+        return Err(DissectError::InvalidData(format!["ZMTP frame flags 0x{:02x} are invalid", flags]));
+    }
+
+    let mut values = NamedValues::new();
+    values.push(("Flags", Val::BitFlags8(flags, [
+                                         Some("MORE"), Some("LONG"), Some("COMMAND"), None,
+                                         None, None, None, None])));
+    values.push(("Size", Val::Unsigned(size)));
+
+    // The last frame may continue in the next segment.
+    let end = (header as u64).saturating_add(size).min(data.len() as u64) as usize;
+    let body = &data[header..end];
+
+    if flags & COMMAND != 0 {
+        command(body, &mut values);
+    } else {
+        values.push(("More", Val::Unsigned((flags & MORE != 0) as u64)));
+        values.push(("Body", Val::Bytes(body)));
+    }
+
+    Ok((end, Val::Object("ZMTP Frame", values)))
+}
+
+fn command<'data>(body: &'data [u8], values: &mut NamedValues<'data>) {
+    let length = body.first().cloned().unwrap_or(0) as usize;
+    let name = match body.get(1..1 + length) {
+        Some(name) => String::from_utf8_lossy(name).into_owned(),
+        None => {
+            values.push(("Body", Val::Bytes(body)));
+            return;
+        },
+    };
+    let data = &body[1 + length..];
+
+    match name.as_str() {
+        "READY" | "INITIATE" => properties(data, values),
+        "ERROR" => {
+            let length = data.first().cloned().unwrap_or(0) as usize;
+            let reason = data.get(1..1 + length).unwrap_or(&[]);
+            values.push(("Error Reason", Val::String(String::from_utf8_lossy(reason).into_owned())));
+        },
+        "SUBSCRIBE" | "CANCEL" => values.push(("Topic", Val::Bytes(data))),
+        _ if !data.is_empty() => values.push(("Command Data", Val::Bytes(data))),
+        _ => {},
+    }
+    values.insert(2, ("Command", Val::String(name)));
+}
+
+/// Metadata properties: a 1 B name length, a name, a 4 B value length and a
+/// value.
+fn properties<'data>(mut data: &'data [u8], values: &mut NamedValues<'data>) {
+    while !data.is_empty() {
+        let name_length = data[0] as usize;
+        let value_length = match read_be_u32(data, 1 + name_length) {
+            Ok(length) => length as usize,
+            Err(_) => break,
+        };
+        let end = 5 + name_length + value_length;
+        if end > data.len() {
+            break;
+        }
+
+        let name = String::from_utf8_lossy(&data[1..1 + name_length]);
+        let value = &data[5 + name_length..end];
+        let field = match name.to_lowercase().as_str() {
+            "socket-type" => "Socket Type",
+            "identity" | "routing-id" => "Identity",
+            "resource" => "Resource",
+            _ => "Property",
+        };
+
+        values.push((field, if field == "Property" {
+            Val::String(format!["{}: {}", name, String::from_utf8_lossy(value)])
+        } else {
+            Val::String(String::from_utf8_lossy(value).into_owned())
+        }));
+        data = &data[end..];
+    }
+
+    if !data.is_empty() {
+        values.push(("Remainder", Val::Undissected("ZMTP properties", data)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dissect_handshake() {
+        let mut data = vec![0xff, 0, 0, 0, 0, 0, 0, 0, 1, 0x7f, 3, 1];
+        data.extend_from_slice(b"NULL");
+        data.extend_from_slice(&[0; 48]);
+
+        // READY with Socket-Type DEALER
+        data.extend_from_slice(&[0x04, 28, 5]);
+        data.extend_from_slice(b"READY");
+        data.extend_from_slice(&[11]);
+        data.extend_from_slice(b"Socket-Type");
+        data.extend_from_slice(&[0, 0, 0, 6]);
+        data.extend_from_slice(b"DEALER");
+
+        // A two-part message
+        data.extend_from_slice(&[0x01, 0, 0x00, 2, b'h', b'i']);
+
+        assert!(looks_like_greeting(&data));
+        let val = *dissect(&data).unwrap();
+        assert_eq!(val["Mechanism"].as_string().unwrap(), "NULL");
+        assert_eq!(val["Minor Version"].as_unsigned().unwrap(), 1);
+        assert_eq!(val["Frame"]["Command"].as_string().unwrap(), "READY");
+        assert_eq!(val["Frame"]["Socket Type"].as_string().unwrap(), "DEALER");
+
+        let frames = val.as_object().unwrap().1.iter()
+            .filter(|&&(name, _)| name == "Frame")
+            .collect::<Vec<_>>();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].1["More"].as_unsigned().unwrap(), 1);
+        assert_eq!(frames[2].1["Body"].as_bytes().unwrap(), b"hi");
+
+        assert!(dissect(&[0x80, 0]).is_err());
+    }
+}
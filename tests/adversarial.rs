@@ -0,0 +1,162 @@
+/*
+ * Copyright 2015 Jonathan Anderson
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! A corpus of adversarial packets: classic attacks and deliberately
+//! malformed headers. Each must be dissected without panicking, hanging or
+//! reading out of bounds, and with a well-defined outcome: either a
+//! dissection that describes the packet as it really is or an error.
+
+extern crate rshark;
+
+use std::time::Duration;
+
+use rshark::{conformance, ethernet, ip, netbios, DissectError, ErrorCode, Val};
+use rshark::flow::{FlowKey, FlowTable};
+
+/// An IPv4 header from 10.0.0.1 to `destination`, followed by `payload`.
+fn ipv4(protocol: u8, flags_offset: u16, destination: [u8; 4], payload: &[u8]) -> Vec<u8> {
+    let length = 20 + payload.len() as u16;
+    let mut data = vec![0x45, 0, (length >> 8) as u8, length as u8, 0x12, 0x34,
+                        (flags_offset >> 8) as u8, flags_offset as u8, 64, protocol, 0, 0,
+                        10, 0, 0, 1];
+    data.extend_from_slice(&destination);
+    data.extend_from_slice(payload);
+    data
+}
+
+fn udp(source_port: u16, destination_port: u16, length: u16, payload: &[u8]) -> Vec<u8> {
+    let mut data = vec![(source_port >> 8) as u8, source_port as u8,
+                        (destination_port >> 8) as u8, destination_port as u8,
+                        (length >> 8) as u8, length as u8, 0, 0];
+    data.extend_from_slice(payload);
+    data
+}
+
+/// A land attack: a SYN whose source and destination are the same socket.
+#[test]
+fn land_attack() {
+    let syn = [0x00, 0x8b, 0x00, 0x8b, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0x20, 0x00, 0, 0, 0, 0];
+    let data = ipv4(6, 0, [10, 0, 0, 1], &syn);
+
+    let packet = ip::dissect(&data).unwrap();
+    assert_eq!(packet["Payload"]["Source Port"].as_unsigned(), Some(139));
+
+    let key = FlowKey::from_val(&packet).unwrap();
+    assert_eq!(key.source, key.destination);
+    assert_eq!(key.reversed(), key);
+
+    // Both copies count against a single flow, from its originator.
+    let mut flows = FlowTable::new();
+    flows.track(Duration::new(0, 0), &packet);
+    let flow = flows.track(Duration::new(1, 0), &packet).unwrap();
+    assert_eq!((flow.orig_packets, flow.resp_packets), (2, 0));
+    assert_eq!(flows.flows().len(), 1);
+}
+
+/// Teardrop: a second fragment that overlaps the first and claims to end
+/// before the first one does.
+#[test]
+fn teardrop_fragments() {
+    let first = ipv4(17, 0x2000, [10, 0, 0, 2], &udp(1024, 2048, 36, &[0x41; 28]));
+    let second = ipv4(17, 0x0003, [10, 0, 0, 2], &[0x42; 4]);
+
+    let first = ip::dissect(&first).unwrap();
+    assert!(first.layer("UDP").is_some());
+    assert!(first.layer("UDP").unwrap().get("Checksum Status").is_err());
+
+    // A non-initial fragment must not be mistaken for a transport header.
+    let second = ip::dissect(&second).unwrap();
+    assert_eq!(second["Fragment Offset"].as_unsigned(), Some(24));
+    assert!(second.layer("UDP").is_none());
+    match second["Payload"] {
+        Val::Undissected("IPv4 fragment", bytes) => assert_eq!(bytes, &[0x42; 4]),
+        ref other => panic!["unexpected fragment payload: {:?}", other],
+    }
+}
+
+/// TCP data offsets below the minimum and beyond the segment.
+#[test]
+fn absurd_tcp_data_offset() {
+    let mut segment = [0x04, 0x00, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0, 0xf0, 0x10, 0x20, 0x00, 0, 0, 0, 0];
+
+    // 60 B of header in a 20 B segment: the IP layer survives the TCP error.
+    let data = ipv4(6, 0, [10, 0, 0, 2], &segment);
+    let packet = ip::dissect(&data).unwrap();
+    match packet["Payload"] {
+        Val::Payload(_, Err(DissectError::Underflow { expected: Some(60), have: 20, .. })) => {},
+        ref other => panic!["unexpected TCP payload: {:?}", other],
+    }
+
+    // Too small to hold the fixed header: dissected with a warning instead.
+    segment[12] = 0x10;
+    let data = ipv4(6, 0, [10, 0, 0, 2], &segment);
+    let packet = ip::dissect(&data).unwrap();
+    let warnings = conformance::warnings(&packet);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].layer, "TCP");
+}
+
+/// Name compression pointers that point at themselves or at each other.
+#[test]
+fn dns_compression_loops() {
+    let header = [0x13, 0x37, 0x00, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+
+    let mut self_loop = header.to_vec();
+    self_loop.extend_from_slice(&[0xc0, 0x0c, 0, 0x20, 0, 1]);
+
+    let mut mutual = header.to_vec();
+    mutual.extend_from_slice(&[0xc0, 0x0e, 0xc0, 0x0c, 0, 0x20, 0, 1]);
+
+    // Beyond the end of the message
+    let mut dangling = header.to_vec();
+    dangling.extend_from_slice(&[0xc0, 0xff]);
+
+    for message in &[self_loop, mutual, dangling] {
+        assert!(netbios::dissect_name_service(message).is_err());
+
+        // Carried over UDP, the error is confined to the payload.
+        let data = ipv4(17, 0, [10, 0, 0, 2], &udp(137, 137, 8 + message.len() as u16, message));
+        let packet = ip::dissect(&data).unwrap();
+        assert!(packet["Payload"]["Payload"].as_payload().unwrap().is_err());
+
+        // DNS doesn't follow pointers at all.
+        let data = ipv4(17, 0, [10, 0, 0, 2], &udp(53, 53, 8 + message.len() as u16, message));
+        let packet = ip::dissect(&data).unwrap();
+        assert!(packet.layer("UDP").is_some());
+    }
+}
+
+/// UDP datagrams with no payload, or that claim a length of zero.
+#[test]
+fn zero_length_udp() {
+    let data = ipv4(17, 0, [10, 0, 0, 2], &udp(1024, 2048, 8, &[]));
+    let empty = ip::dissect(&data).unwrap();
+    assert_eq!(empty.layer("UDP").unwrap()["Length"].as_unsigned(), Some(8));
+
+    let data = ipv4(17, 0, [10, 0, 0, 2], &udp(1024, 2048, 0, b"data"));
+    let zero = ip::dissect(&data).unwrap();
+    assert_eq!(zero.layer("UDP").unwrap()["Length"].as_unsigned(), Some(0));
+
+    // A datagram too short for its own header
+    let data = ipv4(17, 0, [10, 0, 0, 2], &[0x04, 0x00, 0x08]);
+    let packet = ip::dissect(&data).unwrap();
+    assert_eq!(packet["Payload"].as_payload().unwrap().as_ref().unwrap_err().code(), ErrorCode::Truncated);
+}
+
+/// Every truncation of an attack frame is dissected without panicking.
+#[test]
+fn truncated_frames() {
+    let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x81, 0x00, 0x00, 0x0a, 0x08, 0x00];
+    frame.extend_from_slice(&ipv4(17, 0, [10, 0, 0, 2], &udp(137, 137, 20, &[
+        0x13, 0x37, 0x00, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 0xc0, 0x0c])));
+
+    for end in 0..frame.len() + 1 {
+        let _ = ethernet::dissect(&frame[..end]);
+    }
+}